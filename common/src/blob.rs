@@ -0,0 +1,146 @@
+//! # Blobs
+//!
+//! Types for passing raw block and transaction blobs between the downloader, verifier and database
+//! without copying them.
+//!
+//! Blobs are [`Bytes`], reference-counted slices of the buffer they were read into, so handing a blob
+//! to another component is just a reference count increment. [`BlobPool`] keeps buffers around to be
+//! reused so we are not constantly allocating and freeing large buffers during sync: once every blob
+//! split off a recycled buffer is dropped, the buffer's allocation is used again.
+use std::sync::Mutex;
+
+use bytes::{Bytes, BytesMut};
+
+/// A raw block with its transactions.
+#[derive(Debug, Clone)]
+pub struct RawBlock {
+    /// The block blob.
+    pub block: Bytes,
+    /// The blobs of the block's transactions, not including the miner transaction.
+    pub txs: Vec<Bytes>,
+    /// The prunable hashes of the transactions, in the order of `txs`, if the block was sent pruned.
+    pub prunable_hashes: Option<Vec<[u8; 32]>>,
+    /// The block's weight, only set by peers for pruned blocks.
+    pub block_weight: u64,
+}
+
+impl RawBlock {
+    /// Returns the total size of the blobs in this block.
+    pub fn size(&self) -> usize {
+        self.block.len() + self.txs.iter().map(Bytes::len).sum::<usize>()
+    }
+}
+
+/// A pool of reusable buffers.
+#[derive(Debug)]
+pub struct BlobPool {
+    /// The buffers that are free to be used.
+    free: Mutex<Vec<BytesMut>>,
+    /// The capacity new buffers are allocated with.
+    buffer_capacity: usize,
+    /// The maximum amount of free buffers to keep.
+    max_free_buffers: usize,
+}
+
+impl BlobPool {
+    pub fn new(buffer_capacity: usize, max_free_buffers: usize) -> BlobPool {
+        BlobPool {
+            free: Mutex::new(Vec::with_capacity(max_free_buffers)),
+            buffer_capacity,
+            max_free_buffers,
+        }
+    }
+
+    /// Returns an empty buffer with at least the pool's buffer capacity, reusing a free one if there
+    /// is one.
+    pub fn get(&self) -> BytesMut {
+        let Some(mut buf) = self.free.lock().unwrap().pop() else {
+            return BytesMut::with_capacity(self.buffer_capacity);
+        };
+        // If the parts split off this buffer have been dropped this takes back their space without
+        // allocating.
+        buf.reserve(self.buffer_capacity);
+        buf
+    }
+
+    /// Copies the blobs into a buffer from the pool, returning them as [`Bytes`] that share the
+    /// buffer.
+    pub fn copy_blobs<'a>(&self, blobs: impl IntoIterator<Item = &'a [u8]>) -> Vec<Bytes> {
+        let mut buf = self.get();
+        let blobs = blobs
+            .into_iter()
+            .map(|blob| {
+                buf.extend_from_slice(blob);
+                buf.split().freeze()
+            })
+            .collect();
+        self.recycle(buf);
+        blobs
+    }
+
+    /// Gives a buffer back to the pool.
+    ///
+    /// Buffers that have had parts split off can still be recycled, the space of the split off parts
+    /// is reused once they are dropped.
+    pub fn recycle(&self, mut buf: BytesMut) {
+        buf.clear();
+
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.max_free_buffers {
+            free.push(buf);
+        }
+    }
+
+    /// Returns the amount of free buffers in the pool.
+    pub fn free_buffers(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BufMut;
+
+    use super::BlobPool;
+
+    #[test]
+    fn buffers_are_reused() {
+        let pool = BlobPool::new(1024, 2);
+
+        let mut buf = pool.get();
+        buf.put_slice(&[1; 100]);
+        let ptr = buf.as_ptr();
+        pool.recycle(buf);
+        assert_eq!(pool.free_buffers(), 1);
+
+        let buf = pool.get();
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn pool_is_bounded() {
+        let pool = BlobPool::new(16, 1);
+
+        pool.recycle(pool.get());
+        pool.recycle(pool.get());
+        pool.recycle(bytes::BytesMut::with_capacity(16));
+        assert_eq!(pool.free_buffers(), 1);
+    }
+
+    #[test]
+    fn copied_blobs_share_a_buffer() {
+        let pool = BlobPool::new(1024, 1);
+
+        let blobs = pool.copy_blobs([[1; 100].as_slice(), &[2; 50]]);
+        assert_eq!(blobs[0], [1; 100].as_slice());
+        assert_eq!(blobs[1], [2; 50].as_slice());
+        assert_eq!(blobs[1].as_ptr(), blobs[0][100..].as_ptr());
+        assert_eq!(pool.free_buffers(), 1);
+
+        // The buffer's space is reused once the blobs are dropped.
+        let ptr = blobs[0].as_ptr();
+        drop(blobs);
+        assert_eq!(pool.get().as_ptr(), ptr);
+    }
+}
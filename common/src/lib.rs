@@ -1,3 +1,4 @@
+pub mod blob;
 //pub mod hardforks;
 pub mod network;
 pub mod pruning;
@@ -8,18 +8,21 @@ const STAGENET_NETWORK_ID: [u8; 16] = [
     0x12, 0x30, 0xF1, 0x71, 0x61, 0x04, 0x41, 0x61, 0x17, 0x31, 0x00, 0x82, 0x16, 0xA1, 0xA1, 0x12,
 ];
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum Network {
     #[default]
     Mainnet,
     Testnet,
     Stagenet,
+    /// A private test network, like monerod's `--regtest`. It uses the main-net's network ID and
+    /// genesis block, the hard-fork heights can be set.
+    Regtest,
 }
 
 impl Network {
     pub fn network_id(&self) -> [u8; 16] {
         match self {
-            Network::Mainnet => MAINNET_NETWORK_ID,
+            Network::Mainnet | Network::Regtest => MAINNET_NETWORK_ID,
             Network::Testnet => TESTNET_NETWORK_ID,
             Network::Stagenet => STAGENET_NETWORK_ID,
         }
@@ -50,9 +50,18 @@ pub enum PruningError {
 ///
 // Internally we use an Option<u32> to represent if a pruning seed is 0 (None)which means
 // no pruning will take place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PruningSeed(Option<u32>);
 
 impl PruningSeed {
+    /// The seed of a node that does not prune, it keeps the prunable data of every block.
+    pub const NOT_PRUNED: PruningSeed = PruningSeed(None);
+
+    /// Returns true if this seed prunes blocks.
+    pub fn is_pruned(&self) -> bool {
+        self.0.is_some()
+    }
+
     /// Creates a new pruning seed from a `stripe` and `log_stripes`
     ///
     /// ### What is a `stripe`
@@ -213,10 +222,34 @@ impl PruningSeed {
 
         // We can get the end of our "non-pruning" cycle by getting the next stripe's after us first un-pruned block height
         // so we calculate the next un-pruned block for the next stripe and return it as our next pruned block
-        let next_stripe = (1 + seed_log_stripes) & ((1 << seed_log_stripes) - 1);
+        let next_stripe = 1 + (seed_stripe & ((1 << seed_log_stripes) - 1));
         let seed = PruningSeed::new(next_stripe, seed_log_stripes)?;
         seed.get_next_unpruned_block(block_height, blockchain_height)
     }
+
+    /// Returns true if a node with this seed keeps the prunable data of the block at `block_height`
+    /// when the chain is `blockchain_height` blocks long.
+    ///
+    /// Blocks in our stripe and the last [`CRYPTONOTE_PRUNING_TIP_BLOCKS`] blocks of the chain keep
+    /// their prunable data.
+    ///
+    /// ### Errors
+    ///
+    /// This function will error in the same cases as [`PruningSeed::get_next_unpruned_block`].
+    ///
+    pub fn has_unpruned_data(
+        &self,
+        block_height: u64,
+        blockchain_height: u64,
+    ) -> Result<bool, PruningError> {
+        Ok(self.get_next_unpruned_block(block_height, blockchain_height)? == block_height)
+    }
+}
+
+impl From<PruningSeed> for u32 {
+    fn from(seed: PruningSeed) -> u32 {
+        seed.0.unwrap_or(0)
+    }
 }
 
 impl TryFrom<u32> for PruningSeed {
@@ -402,5 +435,55 @@ mod tests {
         assert_eq!(seed.get_next_unpruned_block(5000, 11000).unwrap(), 5500)
     }
 
-    // TODO: next_pruned_block
+    #[test]
+    fn next_pruned_block() {
+        let all_valid_seeds = make_all_pruning_seeds();
+        let blockchain_height = 76437863;
+
+        for (i, seed) in all_valid_seeds.iter().enumerate() {
+            // Our stripe ends where the next stripe starts.
+            assert_eq!(
+                seed.get_next_pruned_block(i as u64 * 4096, blockchain_height)
+                    .unwrap(),
+                (i as u64 + 1) * 4096
+            )
+        }
+
+        for (i, seed) in all_valid_seeds.iter().enumerate() {
+            // Blocks outside of our stripe are pruned.
+            let block_height = ((i as u64 + 1) % 8) * 4096;
+            assert_eq!(
+                seed.get_next_pruned_block(block_height, blockchain_height)
+                    .unwrap(),
+                block_height
+            )
+        }
+
+        let zero_seed = PruningSeed::NOT_PRUNED;
+
+        assert_eq!(
+            zero_seed.get_next_pruned_block(33443, 5565445).unwrap(),
+            5565445
+        );
+    }
+
+    #[test]
+    fn unpruned_data() {
+        let seed = PruningSeed::new(2, CRYPTONOTE_PRUNING_LOG_STRIPES).unwrap();
+        let blockchain_height = 100_000;
+
+        assert!(!seed.has_unpruned_data(0, blockchain_height).unwrap());
+        assert!(seed.has_unpruned_data(4096, blockchain_height).unwrap());
+        assert!(seed.has_unpruned_data(8191, blockchain_height).unwrap());
+        assert!(!seed.has_unpruned_data(8192, blockchain_height).unwrap());
+        // Tip blocks are never pruned.
+        assert!(seed
+            .has_unpruned_data(blockchain_height - 1, blockchain_height)
+            .unwrap());
+
+        assert!(PruningSeed::NOT_PRUNED
+            .has_unpruned_data(0, blockchain_height)
+            .unwrap());
+        assert_eq!(u32::from(seed), 385);
+    }
 }
@@ -0,0 +1,556 @@
+//! # Alt Chains
+//!
+//! This module contains [`AltChainContextCache`], a set of the contextual caches for a chain that
+//! has split off from the main chain.
+//!
+//! The caches are initialised at the height of the split, then alt blocks are added on top of them
+//! independently of the main chain. If the alt chain overtakes the main chain the caches can be
+//! promoted to become the main chain's caches.
+//!
+//! When several alt chains compete, [`AltChainContextCache::fork_many_from_main_chain`] builds their
+//! caches at once: forks close to each other share the caches of the lowest fork, which are moved up
+//! the main chain to the higher forks instead of reading every window again.
+//!
+use std::collections::{HashMap, VecDeque};
+
+use futures::future::try_join_all;
+use futures::join;
+use monero_serai::block::Block;
+use tower::ServiceExt;
+use tracing::{instrument, Instrument};
+
+use crate::{
+    block::{
+        pow::difficulty::DifficultyCache,
+        reward::calculate_base_reward,
+        weight::{BlockWeightsCache, LONG_TERM_WINDOW},
+    },
+    context::{ChainTip, ContextCacheInit},
+    hardforks::{HardForkConfig, HardForkState},
+    miner_tx::calculate_block_reward,
+    spans::{block_span, record_hf, BLOCK_TARGET},
+    BlockError, ConsensusError, Database, DatabaseRequest,
+};
+
+/// The most blocks a fork's caches are moved up from the caches of a lower fork, forks further
+/// apart have their caches read on their own.
+const MAX_SHARED_FORK_DISTANCE: u64 = 1_000;
+
+/// The contextual caches for an alt chain.
+#[derive(Clone)]
+pub struct AltChainContextCache {
+    pub(crate) block_weight: BlockWeightsCache,
+    /// The long term weights of the blocks added to these caches that are still in the long term
+    /// window, oldest first. The database only has the main chain's weights, so these are used
+    /// when the alt blocks leave the window.
+    pub(crate) alt_long_term_weights: VecDeque<usize>,
+    pub(crate) difficulty: DifficultyCache,
+    pub(crate) hard_fork: HardForkState,
+    /// The height of the first alt block, every block below this height is shared with
+    /// the main chain.
+    pub(crate) fork_height: u64,
+    /// The height of the alt chain, this is one more than the height of the top block.
+    pub(crate) chain_height: u64,
+    /// The hash of the top block of the alt chain.
+    pub(crate) top_hash: [u8; 32],
+    /// The total amount of coins generated up to and including the top block of the alt chain.
+    pub(crate) already_generated_coins: u64,
+}
+
+impl AltChainContextCache {
+    /// Fork the caches off the main chain, the first alt block will be at `fork_height`.
+    ///
+    /// The database must contain the main chain up to at least `fork_height - 1`, an alt chain can
+    /// not fork at the genesis block so `fork_height` must not be 0.
+    #[instrument(
+        target = "cuprate_consensus::context",
+        name = "init_alt_chain_cache",
+        skip(hard_fork_cfg, database)
+    )]
+    pub async fn fork_from_main_chain<D: Database + Clone>(
+        hard_fork_cfg: HardForkConfig,
+        fork_height: u64,
+        database: D,
+    ) -> Result<AltChainContextCache, ConsensusError> {
+        if fork_height == 0 {
+            return Err(BlockError::ForksGenesis.into());
+        }
+
+        let caches =
+            ContextCacheInit::init_from_chain_height(hard_fork_cfg, fork_height, database).await?;
+
+        Ok(AltChainContextCache {
+            block_weight: caches.block_weight,
+            alt_long_term_weights: VecDeque::new(),
+            difficulty: caches.difficulty,
+            hard_fork: caches.hard_fork,
+            fork_height,
+            chain_height: fork_height,
+            top_hash: caches.top_hash,
+            already_generated_coins: caches.already_generated_coins,
+        })
+    }
+
+    /// Fork the caches of several alt chains off the main chain, returning the caches in the order of
+    /// `fork_heights`.
+    ///
+    /// Forks within [`MAX_SHARED_FORK_DISTANCE`] blocks of each other share the caches of the lowest
+    /// one, forks further apart are read concurrently.
+    #[instrument(
+        target = "cuprate_consensus::context",
+        name = "init_alt_chain_caches",
+        skip(hard_fork_cfg, database)
+    )]
+    pub async fn fork_many_from_main_chain<D: Database + Clone>(
+        hard_fork_cfg: HardForkConfig,
+        fork_heights: &[u64],
+        database: D,
+    ) -> Result<Vec<AltChainContextCache>, ConsensusError> {
+        let mut sorted_heights = fork_heights.to_vec();
+        sorted_heights.sort_unstable();
+        sorted_heights.dedup();
+
+        let mut groups: Vec<Vec<u64>> = Vec::new();
+        for fork_height in sorted_heights {
+            match groups.last_mut() {
+                Some(group) if fork_height - group[0] <= MAX_SHARED_FORK_DISTANCE => {
+                    group.push(fork_height)
+                }
+                _ => groups.push(vec![fork_height]),
+            }
+        }
+
+        let caches: HashMap<u64, AltChainContextCache> = try_join_all(
+            groups
+                .into_iter()
+                .map(|group| fork_group(hard_fork_cfg.clone(), group, database.clone())),
+        )
+        .await?
+        .into_iter()
+        .flatten()
+        .map(|cache| (cache.fork_height, cache))
+        .collect();
+
+        Ok(fork_heights
+            .iter()
+            .map(|fork_height| caches[fork_height].clone())
+            .collect())
+    }
+
+    /// Add an alt block to the caches.
+    ///
+    /// The block must build on the top of the alt chain. This function does not verify the block,
+    /// the block should be checked against this context before being added.
+    pub async fn add_block<D: Database>(
+        &mut self,
+        block: &Block,
+        block_weight: usize,
+        database: D,
+    ) -> Result<(), ConsensusError> {
+        self.add_block_with_hash(block, block.hash(), block_weight, database)
+            .await
+    }
+
+    /// Add a block to the caches when its hash is already known, see
+    /// [`AltChainContextCache::add_block`].
+    pub(crate) async fn add_block_with_hash<D: Database>(
+        &mut self,
+        block: &Block,
+        block_hash: [u8; 32],
+        block_weight: usize,
+        database: D,
+    ) -> Result<(), ConsensusError> {
+        let generated_coins = self.next_block_generated_coins(block_weight)?;
+        self.add_block_with_generated_coins(
+            block,
+            block_hash,
+            block_weight,
+            generated_coins,
+            database,
+        )
+        .await
+    }
+
+    /// Add a block to the caches with the coins its miner transaction generated, see
+    /// [`check_miner_tx`](crate::miner_tx::check_miner_tx), instead of the full reward.
+    pub(crate) async fn add_block_with_generated_coins<D: Database>(
+        &mut self,
+        block: &Block,
+        block_hash: [u8; 32],
+        block_weight: usize,
+        generated_coins: u64,
+        mut database: D,
+    ) -> Result<(), ConsensusError> {
+        let span = block_span(self.chain_height, &block_hash);
+        async {
+            if block.header.previous != self.top_hash {
+                return Err(BlockError::DoesNotExtendChain {
+                    previous: block.header.previous,
+                    top_hash: self.top_hash,
+                }
+                .into());
+            }
+
+            let hf = self.hard_fork.current_hardfork();
+            record_hf(&span, hf);
+            let hf_info = self.hard_fork.block_hf_info(&block.header)?;
+
+            let long_term_weight = self
+                .block_weight
+                .next_block_long_term_weight(&hf, block_weight);
+            let cumulative_difficulty =
+                self.difficulty.last_cumulative_difficulty() + self.difficulty.next_difficulty(&hf);
+
+            let height = self.chain_height;
+
+            tracing::debug!(target: BLOCK_TARGET, "Adding alt block");
+
+            // Blocks below the ones added to these caches are on the main chain.
+            let first_added_height = self.chain_height - self.alt_long_term_weights.len() as u64;
+            let removed_long_term_weight = match height.checked_sub(LONG_TERM_WINDOW) {
+                Some(height_to_remove) if height_to_remove >= first_added_height => {
+                    self.alt_long_term_weights.pop_front()
+                }
+                Some(height_to_remove) => Some(
+                    database
+                        .ready()
+                        .await?
+                        .call(DatabaseRequest::BlockWeights(height_to_remove.into()))
+                        .await?
+                        .into_block_weights()?
+                        .long_term_weight,
+                ),
+                None => None,
+            };
+            self.block_weight.add_block_weights(
+                height,
+                block_weight,
+                long_term_weight,
+                removed_long_term_weight,
+            );
+            self.alt_long_term_weights.push_back(long_term_weight);
+            self.difficulty
+                .new_block(height, block.header.timestamp, cumulative_difficulty);
+            self.hard_fork.new_block(hf_info.vote_version(), height);
+
+            self.chain_height += 1;
+            self.top_hash = block_hash;
+            self.already_generated_coins =
+                self.already_generated_coins.saturating_add(generated_coins);
+
+            Ok(())
+        }
+        .instrument(span.clone())
+        .await
+    }
+
+    /// Returns the coins generated by the next block if it has this weight: its reward without the
+    /// fees, like monerod counts them.
+    pub(crate) fn next_block_generated_coins(
+        &self,
+        block_weight: usize,
+    ) -> Result<u64, BlockError> {
+        let hf = self.hard_fork.current_hardfork();
+        let effective_median_weight = self.block_weight.effective_median_block_weight(&hf);
+
+        calculate_block_reward(
+            calculate_base_reward(self.already_generated_coins, &hf),
+            effective_median_weight,
+            block_weight,
+        )
+        .ok_or(BlockError::WeightTooBig {
+            height: self.chain_height,
+            weight: block_weight,
+            limit: 2 * effective_median_weight,
+        })
+    }
+
+    /// Returns the height of the first alt block.
+    pub fn fork_height(&self) -> u64 {
+        self.fork_height
+    }
+
+    /// Returns the height of the alt chain.
+    pub fn chain_height(&self) -> u64 {
+        self.chain_height
+    }
+
+    /// Returns the hash of the top block of the alt chain.
+    pub fn top_hash(&self) -> [u8; 32] {
+        self.top_hash
+    }
+
+    /// Returns the total amount of coins generated up to and including the top block of the alt chain.
+    pub fn already_generated_coins(&self) -> u64 {
+        self.already_generated_coins
+    }
+
+    /// Returns the cumulative difficulty of the top block of the alt chain.
+    pub fn cumulative_difficulty(&self) -> u128 {
+        self.difficulty.last_cumulative_difficulty()
+    }
+
+    /// Returns the top of the alt chain.
+    pub fn tip(&self) -> ChainTip {
+        ChainTip {
+            chain_height: self.chain_height,
+            top_hash: self.top_hash,
+            cumulative_difficulty: self.cumulative_difficulty(),
+        }
+    }
+}
+
+/// Forks the caches at each height of the group, the caches of the first fork are moved up the main
+/// chain to the others.
+async fn fork_group<D: Database + Clone>(
+    hard_fork_cfg: HardForkConfig,
+    fork_heights: Vec<u64>,
+    mut database: D,
+) -> Result<Vec<AltChainContextCache>, ConsensusError> {
+    let base_height = fork_heights[0];
+    let range = base_height..fork_heights[fork_heights.len() - 1];
+
+    let (base, weights, pow_infos, hf_infos, tops) = join!(
+        AltChainContextCache::fork_from_main_chain(hard_fork_cfg, base_height, database.clone()),
+        database
+            .clone()
+            .oneshot(DatabaseRequest::BlockWeightsInRange(range.clone())),
+        database
+            .clone()
+            .oneshot(DatabaseRequest::BlockPOWInfoInRange(range.clone())),
+        database
+            .clone()
+            .oneshot(DatabaseRequest::BlockHfInfoInRange(range)),
+        try_join_all(fork_heights[1..].iter().map(|fork_height| {
+            let database = database.clone();
+            async move {
+                let top_hash = database
+                    .clone()
+                    .oneshot(DatabaseRequest::BlockHash(fork_height - 1))
+                    .await?
+                    .into_block_hash()?;
+                let already_generated_coins = database
+                    .oneshot(DatabaseRequest::GeneratedCoins(fork_height - 1))
+                    .await?
+                    .into_generated_coins()?;
+                Ok::<_, ConsensusError>((top_hash, already_generated_coins))
+            }
+        }))
+    );
+    let mut cache = base?;
+    let weights = weights?.into_block_weights_in_range()?;
+    let pow_infos = pow_infos?.into_block_pow_info_in_range()?;
+    let hf_infos = hf_infos?.into_block_hf_info_in_range()?;
+
+    let mut caches = vec![cache.clone()];
+    for (&fork_height, (top_hash, already_generated_coins)) in fork_heights[1..].iter().zip(tops?) {
+        while cache.chain_height < fork_height {
+            let height = cache.chain_height;
+            let i = (height - base_height) as usize;
+
+            cache
+                .block_weight
+                .new_block_added(
+                    height,
+                    weights[i].block_weight,
+                    weights[i].long_term_weight,
+                    &mut database,
+                )
+                .await?;
+            cache.difficulty.new_block(
+                height,
+                pow_infos[i].timestamp,
+                pow_infos[i].cumulative_difficulty,
+            );
+            cache
+                .hard_fork
+                .new_block(hf_infos[i].vote_version(), height);
+            cache.chain_height += 1;
+        }
+
+        cache.fork_height = fork_height;
+        cache.top_hash = top_hash;
+        cache.already_generated_coins = already_generated_coins;
+        caches.push(cache.clone());
+    }
+
+    Ok(caches)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::hardforks::HardFork;
+    use crate::test_utils::{
+        dummy_block, dummy_miner_tx, dummy_output, DummyBlockExtendedHeader, DummyDatabaseBuilder,
+    };
+
+    #[test]
+    fn alt_chains_can_not_fork_at_genesis() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+
+        assert!(matches!(
+            block_on(AltChainContextCache::fork_from_main_chain(
+                HardForkConfig::main_net(),
+                0,
+                database.clone(),
+            )),
+            Err(ConsensusError::Block(BlockError::ForksGenesis))
+        ));
+        assert!(block_on(AltChainContextCache::fork_many_from_main_chain(
+            HardForkConfig::main_net(),
+            &[5, 0],
+            database,
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn alt_blocks_leave_the_long_term_window() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                10,
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::V1, HardFork::V2)
+                    .with_weight(1_000, 1_000)
+                    .with_pow_info(0, 1),
+            )
+            .finish();
+        let mut cache = block_on(AltChainContextCache::fork_from_main_chain(
+            HardForkConfig::main_net(),
+            10,
+            database.clone(),
+        ))
+        .unwrap();
+
+        // Until the first alt block leaves the window, the database only has the main chain.
+        let mut alt_block = dummy_block(
+            HardFork::V1,
+            HardFork::V2,
+            cache.top_hash(),
+            dummy_miner_tx(1, Some(0), vec![]),
+        );
+        for i in 0..=LONG_TERM_WINDOW {
+            let mut hash = [0xff; 32];
+            hash[..8].copy_from_slice(&i.to_le_bytes());
+            alt_block.header.timestamp = (10 + i) * 120;
+            block_on(cache.add_block_with_hash(&alt_block, hash, 2_000, database.clone())).unwrap();
+            alt_block.header.previous = hash;
+        }
+
+        let summary = cache.block_weight.long_term_summary();
+        assert_eq!(summary.len, LONG_TERM_WINDOW as usize);
+        assert_eq!((summary.min, summary.max), (2_000, 2_000));
+        assert_eq!(cache.alt_long_term_weights.len(), LONG_TERM_WINDOW as usize);
+    }
+
+    #[test]
+    fn generated_coins_do_not_include_fees() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                10,
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::V1, HardFork::V1)
+                    .with_weight(1_000, 1_000)
+                    .with_pow_info(0, 1)
+                    .with_generated_coins(1_000),
+            )
+            .finish();
+        let mut cache = block_on(AltChainContextCache::fork_from_main_chain(
+            HardForkConfig::main_net(),
+            10,
+            database.clone(),
+        ))
+        .unwrap();
+
+        let coins_before = cache.already_generated_coins();
+        let base_reward = calculate_base_reward(coins_before, &HardFork::V1);
+
+        // The miner takes the reward and 1_000_000 in fees.
+        let alt_block = dummy_block(
+            HardFork::V1,
+            HardFork::V2,
+            cache.top_hash(),
+            dummy_miner_tx(
+                1,
+                Some(0),
+                vec![dummy_output(Some(base_reward + 1_000_000))],
+            ),
+        );
+        block_on(cache.add_block(&alt_block, 1_000, database.clone())).unwrap();
+        assert_eq!(cache.already_generated_coins(), coins_before + base_reward);
+
+        // A block over twice the median weight has no reward.
+        let heavy_block = dummy_block(
+            HardFork::V1,
+            HardFork::V2,
+            cache.top_hash(),
+            dummy_miner_tx(1, Some(0), vec![]),
+        );
+        assert!(matches!(
+            block_on(cache.add_block(&heavy_block, 1_000_000, database)),
+            Err(ConsensusError::Block(BlockError::WeightTooBig { .. }))
+        ));
+    }
+
+    #[test]
+    fn forks_share_caches() {
+        let mut builder = DummyDatabaseBuilder::default();
+        for i in 0..1_500_u64 {
+            builder = builder.add_block(
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::V16, HardFork::V16)
+                    .with_weight(300_000 + i as usize, 300_000)
+                    .with_pow_info(i * 120 + i % 7, 1_000 + u128::from(i))
+                    .with_generated_coins(i),
+            );
+        }
+        let database = builder.finish();
+
+        // 200 and 1_300 are too far apart to share caches.
+        let fork_heights = [1_300, 200, 250, 200, 900];
+        let caches = block_on(AltChainContextCache::fork_many_from_main_chain(
+            HardForkConfig::main_net(),
+            &fork_heights,
+            database.clone(),
+        ))
+        .unwrap();
+
+        for (fork_height, cache) in fork_heights.into_iter().zip(caches) {
+            let expected = block_on(AltChainContextCache::fork_from_main_chain(
+                HardForkConfig::main_net(),
+                fork_height,
+                database.clone(),
+            ))
+            .unwrap();
+
+            assert_eq!(cache.fork_height(), fork_height);
+            assert_eq!(cache.tip(), expected.tip());
+            assert_eq!(
+                cache.already_generated_coins(),
+                expected.already_generated_coins()
+            );
+            assert_eq!(
+                cache.difficulty.next_difficulty(&HardFork::V16),
+                expected.difficulty.next_difficulty(&HardFork::V16)
+            );
+            assert_eq!(
+                cache.block_weight.short_term_summary(),
+                expected.block_weight.short_term_summary()
+            );
+            assert_eq!(
+                cache.block_weight.long_term_summary(),
+                expected.block_weight.long_term_summary()
+            );
+            assert_eq!(cache.hard_fork.votes(), expected.hard_fork.votes());
+            assert_eq!(
+                cache.hard_fork.last_height(),
+                expected.hard_fork.last_height()
+            );
+        }
+    }
+}
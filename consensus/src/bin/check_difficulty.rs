@@ -0,0 +1,83 @@
+#![cfg(feature = "binaries")]
+
+//! Recalculates the difficulty of the blocks in a range and compares it to the difficulty stored by
+//! the nodes.
+//!
+//! Usage: `check_difficulty <start height> <end height> [node urls...]`
+
+use tower::ServiceExt;
+use tracing::level_filters::LevelFilter;
+
+use monero_consensus::{
+    block::pow::difficulty::check_difficulties, rpc::init_rpc_load_balancer, DatabaseRequest,
+};
+
+const BATCH_SIZE: u64 = 10_000;
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::INFO)
+        .init();
+
+    let mut args = std::env::args().skip(1);
+    let start: u64 = args
+        .next()
+        .and_then(|start| start.parse().ok())
+        .expect("Usage: check_difficulty <start height> <end height> [node urls...]");
+    let end: Option<u64> = args
+        .next()
+        .map(|end| end.parse().expect("Invalid end height"));
+
+    let mut urls: Vec<String> = args.collect();
+    if urls.is_empty() {
+        urls = vec![
+            "http://xmr-node.cakewallet.com:18081".to_string(),
+            "http://nodex.monerujo.io:18081".to_string(),
+            "http://nodes.hashvault.pro:18081".to_string(),
+        ];
+    }
+
+    let rpc = init_rpc_load_balancer(urls);
+
+    let chain_height = rpc
+        .clone()
+        .oneshot(DatabaseRequest::ChainHeight)
+        .await
+        .unwrap()
+        .into_chain_height()
+        .unwrap();
+
+    let end = end.unwrap_or(chain_height).min(chain_height);
+
+    let mut mismatches = Vec::new();
+    for batch_start in (start..end).step_by(BATCH_SIZE as usize) {
+        let batch_end = (batch_start + BATCH_SIZE).min(end);
+
+        mismatches.extend(
+            check_difficulties(rpc.clone(), batch_start..batch_end)
+                .await
+                .unwrap(),
+        );
+
+        tracing::info!(
+            "Checked blocks up to height: {}, mismatches: {}",
+            batch_end,
+            mismatches.len()
+        );
+    }
+
+    for mismatch in &mismatches {
+        println!(
+            "height: {}, expected: {}, stored: {}",
+            mismatch.height, mismatch.expected, mismatch.stored
+        );
+    }
+
+    println!(
+        "Checked blocks {}..{}, {} mismatches",
+        start,
+        end,
+        mismatches.len()
+    );
+}
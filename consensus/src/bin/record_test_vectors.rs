@@ -0,0 +1,59 @@
+#![cfg(all(feature = "binaries", feature = "test_vectors"))]
+
+//! Records the test vectors of the mainnet heights where the rules change from monerod nodes, see
+//! [`monero_consensus::test_vectors`].
+//!
+//! Usage: `record_test_vectors <out dir> [node urls...]`
+
+use tracing::level_filters::LevelFilter;
+
+use monero_consensus::{
+    rpc::init_rpc_load_balancer,
+    test_vectors::{TestVector, DEFAULT_REPLAYED_BLOCKS, MAINNET_EDGE_CASES},
+};
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::INFO)
+        .init();
+
+    let mut args = std::env::args().skip(1);
+    let out_dir = std::path::PathBuf::from(
+        args.next()
+            .expect("Usage: record_test_vectors <out dir> [node urls...]"),
+    );
+
+    let mut urls: Vec<String> = args.collect();
+    if urls.is_empty() {
+        urls = vec![
+            "http://xmr-node.cakewallet.com:18081".to_string(),
+            "http://nodex.monerujo.io:18081".to_string(),
+            "http://nodes.hashvault.pro:18081".to_string(),
+        ];
+    }
+
+    let rpc = init_rpc_load_balancer(urls);
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    for (name, height) in MAINNET_EDGE_CASES {
+        tracing::info!("Recording vector {} at height {}", name, height);
+
+        let vector = TestVector::record(name, height, DEFAULT_REPLAYED_BLOCKS, rpc.clone())
+            .await
+            .unwrap();
+
+        // Check the vector before writing it, a mismatch is a bug or a bad node.
+        if let Err(e) = vector.replay().await {
+            tracing::error!("Vector {} doesn't replay: {}", name, e);
+        }
+
+        std::fs::write(out_dir.join(format!("{name}.txt")), vector.to_text()).unwrap();
+    }
+
+    println!(
+        "Recorded {} vectors into {}",
+        MAINNET_EDGE_CASES.len(),
+        out_dir.display()
+    );
+}
@@ -13,7 +13,7 @@ use tracing::level_filters::LevelFilter;
 use monero_consensus::rpc::init_rpc_load_balancer;
 use monero_consensus::{
     verifier::{Config, Verifier},
-    ConsensusError, Database, DatabaseRequest, DatabaseResponse,
+    ConsensusError, DatabaseRequest, DatabaseSvc,
 };
 
 const BATCH_SIZE: u64 = 50;
@@ -76,24 +76,19 @@ impl Display for ScanningCache {
 }
 
 #[instrument(skip_all, level = "info")]
-async fn scan_chain<D: Database + Clone + Send + 'static>(
+async fn scan_chain(
     cache: ScanningCache,
     network: Network,
-    mut database: D,
-) -> Result<(), tower::BoxError>
-where
-    D::Future: Send + 'static,
-{
+    mut database: DatabaseSvc,
+) -> Result<(), tower::BoxError> {
     tracing::info!("Beginning chain scan, {}", &cache);
 
-    let DatabaseResponse::ChainHeight(chain_height) = database
+    let chain_height = database
         .ready()
         .await?
         .call(DatabaseRequest::ChainHeight)
         .await?
-    else {
-        panic!("Database sent incorrect response!");
-    };
+        .into_chain_height()?;
 
     tracing::info!("scanning to chain height: {}", chain_height);
 
@@ -130,20 +125,17 @@ where
             ),
         );
 
-        let DatabaseResponse::BlockBatchInRange(blocks) = current_fut.await?? else {
-            panic!("Database sent incorrect response!");
-        };
+        let blocks = current_fut.await??.into_block_batch_in_range()?;
 
         let mut block_data_fut = FuturesOrdered::from_iter(blocks.iter().map(|b| async {
             if !b.txs.is_empty() {
                 let txs = b.txs.clone();
                 let db = database.clone();
                 tokio::spawn(async move {
-                    let DatabaseResponse::Transactions(txs) =
-                        db.oneshot(DatabaseRequest::Transactions(txs)).await?
-                    else {
-                        panic!("Database sent incorrect response!");
-                    };
+                    let txs = db
+                        .oneshot(DatabaseRequest::Transactions(txs))
+                        .await?
+                        .into_transactions()?;
                     Ok(txs)
                 })
                 .await
@@ -197,5 +189,7 @@ async fn main() {
     let network = Network::Mainnet;
     let cache = ScanningCache::default();
 
-    scan_chain(cache, network, rpc).await.unwrap();
+    scan_chain(cache, network, DatabaseSvc::new(rpc))
+        .await
+        .unwrap();
 }
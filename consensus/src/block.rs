@@ -1,2 +1,51 @@
 pub mod pow;
+pub mod reward;
 pub mod weight;
+
+use monero_serai::{block::Block, transaction::Transaction};
+
+use crate::outputs::TxBlob;
+
+/// The transactions of a [`VerifiedBlockInformation`], not including the miner transaction.
+#[derive(Debug, Clone)]
+pub enum VerifiedBlockTxs {
+    Full(Vec<Transaction>),
+    /// The transactions of a block received pruned, see [`Verifier::check_pruned_block`](crate::verifier::Verifier::check_pruned_block).
+    ///
+    /// Version 1 transactions can't be pruned, so their blobs are complete and have no prunable hash.
+    Pruned(Vec<TxBlob>),
+}
+
+impl VerifiedBlockTxs {
+    pub fn len(&self) -> usize {
+        match self {
+            VerifiedBlockTxs::Full(txs) => txs.len(),
+            VerifiedBlockTxs::Pruned(txs) => txs.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A block that has been fully verified, with everything the database needs to add it to the
+/// main chain.
+///
+/// The hard-fork version and vote are in the block's header.
+#[derive(Debug, Clone)]
+pub struct VerifiedBlockInformation {
+    pub block: Block,
+    /// The transactions in the block, in the order of the block's `txs`, not including the miner
+    /// transaction.
+    pub txs: VerifiedBlockTxs,
+    pub block_hash: [u8; 32],
+    pub pow_hash: [u8; 32],
+    pub height: u64,
+    /// The coins generated by this block, the fees are not included.
+    pub generated_coins: u64,
+    pub weight: usize,
+    pub long_term_weight: usize,
+    /// The cumulative difficulty of the chain up to and including this block.
+    pub cumulative_difficulty: u128,
+}
@@ -1,13 +1,49 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
 use crypto_bigint::{CheckedMul, U256};
+use cryptonight_cuprate::{cryptonight_hash, Variant};
+use randomx_rs::{RandomXCache, RandomXError, RandomXFlag, RandomXVM};
+
+use crate::hardforks::HardFork;
 
 pub mod difficulty;
 
+/// The amount of blocks a RandomX seed is used for.
+pub const RX_SEEDHASH_EPOCH_BLOCKS: u64 = 2048;
+/// The amount of blocks after an epoch starts before its seed is used.
+pub const RX_SEEDHASH_EPOCH_LAG: u64 = 64;
+
+/// The amount of RandomX caches kept by [`RandomXCaches`], the seed changes every
+/// [`RX_SEEDHASH_EPOCH_BLOCKS`] so this keeps the current seed's cache when the next is made.
+const RANDOMX_CACHES: usize = 2;
+
+/// The height of the block whose PoW hash is hard-coded, see [`calculate_pow_hash`].
+const BLOCK_202612_HEIGHT: u64 = 202_612;
+/// The PoW hash of block 202612, from monerod's `get_block_longhash`.
+const BLOCK_202612_POW_HASH: [u8; 32] = [
+    0x84, 0xf6, 0x47, 0x66, 0x47, 0x5d, 0x51, 0x83, 0x7a, 0xc9, 0xef, 0xbe, 0xf1, 0x92, 0x64, 0x86,
+    0xe5, 0x85, 0x63, 0xc9, 0x5a, 0x19, 0xfe, 0xf4, 0xae, 0xc3, 0x25, 0x4f, 0x03, 0x00, 0x00, 0x00,
+];
+
 #[derive(Debug)]
 pub struct BlockPOWInfo {
     pub timestamp: u64,
     pub cumulative_difficulty: u128,
 }
 
+/// Returns the height of the block whose hash is the RandomX seed of the block at `height`, the seed
+/// changes every [`RX_SEEDHASH_EPOCH_BLOCKS`] blocks, [`RX_SEEDHASH_EPOCH_LAG`] blocks after the
+/// seed block.
+pub fn randomx_seed_height(height: u64) -> u64 {
+    if height <= RX_SEEDHASH_EPOCH_BLOCKS + RX_SEEDHASH_EPOCH_LAG {
+        0
+    } else {
+        (height - RX_SEEDHASH_EPOCH_LAG - 1) & !(RX_SEEDHASH_EPOCH_BLOCKS - 1)
+    }
+}
+
 /// Returns if the blocks POW hash is valid for the current difficulty.
 ///
 /// See: https://cuprate.github.io/monero-book/consensus_rules/blocks/difficulty.html#checking-a-blocks-proof-of-work
@@ -18,3 +54,116 @@ pub fn check_block_pow(hash: &[u8; 32], difficulty: u128) -> bool {
 
     int_hash.checked_mul(&difficulty).is_some().unwrap_u8() == 1
 }
+
+/// The RandomX caches of the last seeds, shared by the threads PoW is checked on. Clones of this
+/// share the same caches.
+///
+/// Making a cache takes about a second, so a cache is made once for each seed.
+#[derive(Clone, Default)]
+pub struct RandomXCaches(Arc<Mutex<VecDeque<([u8; 32], RandomXCache)>>>);
+
+impl fmt::Debug for RandomXCaches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let caches = self.0.lock().unwrap();
+        f.debug_list()
+            .entries(caches.iter().map(|(seed, _)| hex::encode(seed)))
+            .finish()
+    }
+}
+
+impl RandomXCaches {
+    /// Returns the RandomX hash of `input` with this seed, the seed's cache is made if it isn't kept.
+    pub fn hash(&self, seed: &[u8; 32], input: &[u8]) -> Result<[u8; 32], RandomXError> {
+        let flags = RandomXFlag::get_recommended_flags();
+
+        let cache = {
+            let mut caches = self.0.lock().unwrap();
+            match caches.iter().find(|(cache_seed, _)| cache_seed == seed) {
+                Some((_, cache)) => cache.clone(),
+                None => {
+                    let cache = RandomXCache::new(flags, seed)?;
+                    if caches.len() == RANDOMX_CACHES {
+                        caches.pop_front();
+                    }
+                    caches.push_back((*seed, cache.clone()));
+                    cache
+                }
+            }
+        };
+
+        let vm = RandomXVM::new(flags, Some(cache), None)?;
+        let hash = vm.calculate_hash(input)?;
+        Ok(hash.try_into().expect("RandomX hashes are always 32 bytes"))
+    }
+}
+
+/// Returns the PoW hash of the block at `height` with this hashing blob.
+///
+/// The hash is CryptoNight until [`HardFork::V12`], with the variant changing at [`HardFork::V7`],
+/// [`HardFork::V8`] and [`HardFork::V10`], and RandomX from then. `randomx_seed` is the hash of the
+/// block at [`randomx_seed_height`], it is only used for RandomX.
+///
+/// Like monerod, the hash of block 202612 is hard-coded.
+///
+/// See: https://cuprate.github.io/monero-book/consensus_rules/blocks/difficulty.html#checking-a-blocks-proof-of-work
+pub fn calculate_pow_hash(
+    randomx: &RandomXCaches,
+    hashing_blob: &[u8],
+    height: u64,
+    hf: &HardFork,
+    randomx_seed: &[u8; 32],
+) -> Result<[u8; 32], RandomXError> {
+    if height == BLOCK_202612_HEIGHT {
+        return Ok(BLOCK_202612_POW_HASH);
+    }
+
+    let variant = match hf {
+        HardFork::V1 | HardFork::V2 | HardFork::V3 | HardFork::V4 | HardFork::V5 | HardFork::V6 => {
+            Variant::V0
+        }
+        HardFork::V7 => Variant::V1,
+        HardFork::V8 | HardFork::V9 => Variant::V2,
+        HardFork::V10 | HardFork::V11 => Variant::R { height },
+        _ => return randomx.hash(randomx_seed, hashing_blob),
+    };
+
+    Ok(cryptonight_hash(hashing_blob, &variant))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_heights() {
+        assert_eq!(randomx_seed_height(0), 0);
+        assert_eq!(randomx_seed_height(2112), 0);
+        assert_eq!(randomx_seed_height(2113), 2048);
+        assert_eq!(randomx_seed_height(4160), 2048);
+        assert_eq!(randomx_seed_height(4161), 4096);
+    }
+
+    #[test]
+    fn pow_hash_variants() {
+        let randomx = RandomXCaches::default();
+        let blob = [1; 76];
+        let pow_hash = |height, hf| calculate_pow_hash(&randomx, &blob, height, &hf, &[0; 32]);
+
+        assert_eq!(
+            pow_hash(100, HardFork::V1).unwrap(),
+            cryptonight_hash(&blob, &Variant::V0)
+        );
+        assert_eq!(
+            pow_hash(100, HardFork::V7).unwrap(),
+            cryptonight_hash(&blob, &Variant::V1)
+        );
+        assert_eq!(
+            pow_hash(100, HardFork::V10).unwrap(),
+            cryptonight_hash(&blob, &Variant::R { height: 100 })
+        );
+        assert_eq!(
+            pow_hash(BLOCK_202612_HEIGHT, HardFork::V1).unwrap(),
+            BLOCK_202612_POW_HASH
+        );
+    }
+}
@@ -1,10 +1,13 @@
-
-
+use std::collections::VecDeque;
 use std::ops::Range;
+
 use tower::ServiceExt;
 use tracing::instrument;
 
-use crate::{hardforks::HardFork, ConsensusError, Database, DatabaseRequest, DatabaseResponse};
+use crate::{
+    context::DifficultyWindowSummary, hardforks::HardFork, spans::CONTEXT_TARGET, ConsensusError,
+    Database, DatabaseRequest,
+};
 
 /// The amount of blocks we account for to calculate difficulty
 const DIFFICULTY_WINDOW: usize = 720;
@@ -19,40 +22,53 @@ const DIFFICULTY_LAG: usize = 15;
 const DIFFICULTY_BLOCKS_COUNT: u64 = (DIFFICULTY_WINDOW + DIFFICULTY_LAG) as u64;
 /// The amount of blocks we account for after removing the outliers.
 const DIFFICULTY_ACCOUNTED_WINDOW_LEN: usize = DIFFICULTY_WINDOW - 2 * DIFFICULTY_CUT;
+/// The amount of blocks whose timestamps are used for the adjusted median time.
+pub const BLOCKCHAIN_TIMESTAMP_CHECK_WINDOW: usize = 60;
+/// The block time the adjusted median time is projected forward with, this doesn't change with
+/// the hard-fork.
+const ADJUSTED_TIME_TARGET_SECONDS: u64 = 120;
 
 /// This struct is able to calculate difficulties from blockchain information.
 #[derive(Debug, Clone)]
 pub struct DifficultyCache {
     /// The list of timestamps in the window.
     /// len <= [`DIFFICULTY_BLOCKS_COUNT`]
-    timestamps: Vec<u64>,
-    /// The work done in the [`DIFFICULTY_ACCOUNTED_WINDOW_LEN`] window, this is an optimisation
-    /// so we don't need to keep track of cumulative difficulties as well as timestamps.
-    windowed_work: u128,
+    timestamps: VecDeque<u64>,
+    /// The cumulative difficulties of the blocks in the window.
+    /// len == timestamps.len()
+    ///
+    /// We keep these in memory so blocks not in the database (alt-chain blocks) can be accounted for.
+    cumulative_difficulties: VecDeque<u128>,
     /// The last height we accounted for.
     last_accounted_height: u64,
+    /// The difficulty of every block, if it is fixed, see
+    /// [`ConsensusConstants::fixed_difficulty`](crate::consensus_constants::ConsensusConstants::fixed_difficulty).
+    fixed_difficulty: Option<u128>,
 }
 
 impl DifficultyCache {
     pub async fn init<D: Database + Clone>(mut database: D) -> Result<Self, ConsensusError> {
-        let DatabaseResponse::ChainHeight(chain_height) = database
+        let chain_height = database
             .ready()
             .await?
             .call(DatabaseRequest::ChainHeight)
             .await?
-        else {
-            panic!("Database sent incorrect response")
-        };
+            .into_chain_height()?;
 
         DifficultyCache::init_from_chain_height(chain_height, database).await
     }
 
-    #[instrument(name = "init_difficulty_cache", level = "info", skip(database))]
+    #[instrument(
+        target = "cuprate_consensus::context",
+        name = "init_difficulty_cache",
+        level = "info",
+        skip(database)
+    )]
     pub async fn init_from_chain_height<D: Database + Clone>(
         chain_height: u64,
-        mut database: D,
+        database: D,
     ) -> Result<Self, ConsensusError> {
-        tracing::info!("Initializing difficulty cache this may take a while.");
+        tracing::info!(target: CONTEXT_TARGET, "Initializing difficulty cache this may take a while.");
 
         let mut block_start = chain_height.saturating_sub(DIFFICULTY_BLOCKS_COUNT);
 
@@ -60,18 +76,17 @@ impl DifficultyCache {
             block_start = 1;
         }
 
-        let timestamps =
-            get_blocks_in_range_timestamps(database.clone(), block_start..chain_height).await?;
+        let (timestamps, cumulative_difficulties) =
+            get_blocks_in_range_timestamps(database, block_start..chain_height).await?;
 
-        let mut diff = DifficultyCache {
+        let diff = DifficultyCache {
             timestamps,
-            windowed_work: 0,
+            cumulative_difficulties,
             last_accounted_height: chain_height - 1,
+            fixed_difficulty: None,
         };
 
-        diff.update_windowed_work(&mut database).await?;
-
-        tracing::info!(
+        tracing::info!(target: CONTEXT_TARGET,
             "Current chain height: {}, accounting for {} blocks timestamps",
             chain_height,
             diff.timestamps.len()
@@ -80,18 +95,13 @@ impl DifficultyCache {
         Ok(diff)
     }
 
-    pub async fn resync<D: Database + Clone>(
-        &mut self,
-        mut database: D,
-    ) -> Result<(), ConsensusError> {
-        let DatabaseResponse::ChainHeight(chain_height) = database
+    pub async fn resync<D: Database>(&mut self, mut database: D) -> Result<(), ConsensusError> {
+        let chain_height = database
             .ready()
             .await?
             .call(DatabaseRequest::ChainHeight)
             .await?
-        else {
-            panic!("Database sent incorrect response")
-        };
+            .into_chain_height()?;
 
         // TODO: We need to handle re-orgs
         assert!(chain_height > self.last_accounted_height);
@@ -100,71 +110,142 @@ impl DifficultyCache {
             return Ok(());
         }
 
-        let mut timestamps = get_blocks_in_range_timestamps(
-            database.clone(),
-            self.last_accounted_height + 1..chain_height,
-        )
-        .await?;
+        let (mut timestamps, mut cumulative_difficulties) =
+            get_blocks_in_range_timestamps(database, self.last_accounted_height + 1..chain_height)
+                .await?;
 
         self.timestamps.append(&mut timestamps);
+        self.cumulative_difficulties
+            .append(&mut cumulative_difficulties);
+
+        self.last_accounted_height = chain_height - 1;
+        self.trim_window();
 
-        self.timestamps.drain(
-            0..self
-                .timestamps
-                .len()
-                .saturating_sub(DIFFICULTY_BLOCKS_COUNT as usize),
+        Ok(())
+    }
+
+    /// Add a new block to the cache.
+    ///
+    /// The height **MUST** be one more than the last height the cache has seen.
+    pub fn new_block(&mut self, height: u64, timestamp: u64, cumulative_difficulty: u128) {
+        assert_eq!(self.last_accounted_height + 1, height);
+        self.last_accounted_height += 1;
+
+        tracing::debug!(
+            target: CONTEXT_TARGET,
+            height,
+            timestamp,
+            cumulative_difficulty,
+            "Accounting for new block's difficulty info"
         );
 
-        self.last_accounted_height = chain_height - 1;
+        self.timestamps.push_back(timestamp);
+        self.cumulative_difficulties
+            .push_back(cumulative_difficulty);
 
-        self.update_windowed_work(database).await
+        self.trim_window();
     }
 
-    async fn update_windowed_work<D: Database>(
-        &mut self,
-        mut database: D,
-    ) -> Result<(), ConsensusError> {
-        if self.last_accounted_height == 0 {
-            return Ok(());
-        }
+    /// Removes blocks from the front of the window until there are at most
+    /// [`DIFFICULTY_BLOCKS_COUNT`] blocks.
+    fn trim_window(&mut self) {
+        let amt_to_remove = self
+            .timestamps
+            .len()
+            .saturating_sub(DIFFICULTY_BLOCKS_COUNT as usize);
 
-        let mut block_start =
-            (self.last_accounted_height + 1).saturating_sub(DIFFICULTY_BLOCKS_COUNT);
+        self.timestamps.drain(0..amt_to_remove);
+        self.cumulative_difficulties.drain(0..amt_to_remove);
+    }
 
-        if block_start == 0 {
-            block_start = 1;
-        }
+    /// Returns the cumulative difficulty of the last block accounted for, or 0 if the cache
+    /// is empty.
+    pub fn last_cumulative_difficulty(&self) -> u128 {
+        self.cumulative_difficulties.back().copied().unwrap_or(0)
+    }
 
-        let (start, end) = get_window_start_and_end(self.timestamps.len());
+    /// Returns the height of the last block the cache accounted for.
+    pub fn last_accounted_height(&self) -> u64 {
+        self.last_accounted_height
+    }
 
-        let low_cumulative_difficulty = get_block_cum_diff(
-            &mut database,
-            block_start + TryInto::<u64>::try_into(start).unwrap(),
-        )
-        .await?;
+    /// Returns a summary of the blocks in the difficulty window.
+    pub fn window_summary(&self) -> DifficultyWindowSummary {
+        DifficultyWindowSummary {
+            len: self.timestamps.len(),
+            oldest_timestamp: self.timestamps.front().copied(),
+            newest_timestamp: self.timestamps.back().copied(),
+            last_cumulative_difficulty: self.last_cumulative_difficulty(),
+            last_accounted_height: self.last_accounted_height,
+        }
+    }
 
-        let high_cumulative_difficulty = get_block_cum_diff(
-            &mut database,
-            block_start + TryInto::<u64>::try_into(end).unwrap() - 1,
+    /// Returns the median of the last [`BLOCKCHAIN_TIMESTAMP_CHECK_WINDOW`] timestamps, the next
+    /// block's timestamp can't be below it.
+    ///
+    /// Returns [`None`] if the cache has fewer than [`BLOCKCHAIN_TIMESTAMP_CHECK_WINDOW`] blocks,
+    /// monerod doesn't check the timestamp of those blocks.
+    pub fn median_timestamp(&self) -> Option<u64> {
+        let start = self
+            .timestamps
+            .len()
+            .checked_sub(BLOCKCHAIN_TIMESTAMP_CHECK_WINDOW)?;
+
+        let mut timestamps: Vec<u64> = self.timestamps.range(start..).copied().collect();
+        timestamps.sort_unstable();
+
+        // The window is even so the median is the mean of the two middle timestamps, rounded down.
+        let mid = timestamps.len() / 2;
+        Some(
+            timestamps[mid - 1] / 2
+                + timestamps[mid] / 2
+                + (timestamps[mid - 1] % 2 + timestamps[mid] % 2) / 2,
         )
-        .await?;
+    }
 
-        self.windowed_work = high_cumulative_difficulty - low_cumulative_difficulty;
-        Ok(())
+    /// Returns the adjusted median time of the next block: the median of the last
+    /// [`BLOCKCHAIN_TIMESTAMP_CHECK_WINDOW`] timestamps moved forward to about when the next block
+    /// will be mined, or the last timestamp if that is later.
+    ///
+    /// Returns [`None`] if the cache has fewer than [`BLOCKCHAIN_TIMESTAMP_CHECK_WINDOW`] blocks,
+    /// monerod uses the local clock instead.
+    pub fn adjusted_time(&self) -> Option<u64> {
+        let median = self.median_timestamp()?
+            + (BLOCKCHAIN_TIMESTAMP_CHECK_WINDOW as u64 + 1) * ADJUSTED_TIME_TARGET_SECONDS / 2;
+
+        Some(median.max(*self.timestamps.back().unwrap()))
+    }
+
+    /// Returns the work done in the [`DIFFICULTY_ACCOUNTED_WINDOW_LEN`] window.
+    fn windowed_work(&self) -> u128 {
+        let (start, end) = get_window_start_and_end(self.timestamps.len());
+
+        self.cumulative_difficulties[end - 1] - self.cumulative_difficulties[start]
+    }
+
+    /// Sets the difficulty of every block, instead of calculating it from the chain.
+    pub fn with_fixed_difficulty(mut self, fixed_difficulty: Option<u128>) -> Self {
+        self.fixed_difficulty = fixed_difficulty;
+        self
     }
 
     /// Returns the required difficulty for the next block.
     ///
     /// See: https://cuprate.github.io/monero-book/consensus_rules/blocks/difficulty.html#calculating-difficulty
     pub fn next_difficulty(&self, hf: &HardFork) -> u128 {
+        if let Some(difficulty) = self.fixed_difficulty {
+            return difficulty;
+        }
         if self.timestamps.len() <= 1 {
             return 1;
         }
 
-        let mut sorted_timestamps = self.timestamps.clone();
-        if sorted_timestamps.len() > DIFFICULTY_WINDOW {
-            sorted_timestamps.drain(DIFFICULTY_WINDOW..);
-        };
+        let mut sorted_timestamps: Vec<u64> = self
+            .timestamps
+            .iter()
+            .take(DIFFICULTY_WINDOW)
+            .copied()
+            .collect();
         sorted_timestamps.sort_unstable();
 
         let (window_start, window_end) = get_window_start_and_end(sorted_timestamps.len());
@@ -176,10 +257,98 @@ impl DifficultyCache {
             time_span = 1;
         }
 
-        (self.windowed_work * target_time_for_hf(hf) + time_span - 1) / time_span
+        (self.windowed_work() * target_time_for_hf(hf) + time_span - 1) / time_span
     }
 }
 
+/// A block whose stored difficulty doesn't match the difficulty calculated from the blocks before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DifficultyMismatch {
+    pub height: u64,
+    /// The difficulty calculated from the stored timestamps and cumulative difficulties of the blocks
+    /// before this one.
+    pub expected: u128,
+    /// The difference between the stored cumulative difficulties of this block and the block before,
+    /// 0 if the cumulative difficulty went down.
+    pub stored: u128,
+}
+
+/// Recalculates the difficulty of every block in the range from the stored timestamps and cumulative
+/// difficulties and returns the blocks where it doesn't match the stored difficulty.
+///
+/// This checks our difficulty calculation against the real chain, and the database's timestamps and
+/// cumulative difficulties against each other. The genesis block is skipped.
+#[instrument(
+    target = "cuprate_consensus::context",
+    name = "check_difficulties",
+    skip(database),
+    level = "info"
+)]
+pub async fn check_difficulties<D: Database + Clone>(
+    mut database: D,
+    block_heights: Range<u64>,
+) -> Result<Vec<DifficultyMismatch>, ConsensusError> {
+    let start = block_heights.start.max(1);
+    if start >= block_heights.end {
+        return Ok(vec![]);
+    }
+
+    let mut cache = DifficultyCache::init_from_chain_height(start, database.clone()).await?;
+
+    // Get the block before the range as well, for the first block's difficulty.
+    let pow_infos = database
+        .ready()
+        .await?
+        .call(DatabaseRequest::BlockPOWInfoInRange(
+            start - 1..block_heights.end,
+        ))
+        .await?
+        .into_block_pow_info_in_range()?;
+
+    let hf_infos = database
+        .ready()
+        .await?
+        .call(DatabaseRequest::BlockHfInfoInRange(
+            start..block_heights.end,
+        ))
+        .await?
+        .into_block_hf_info_in_range()?;
+
+    let mut mismatches = Vec::new();
+
+    for ((height, pow_infos), hf_info) in (start..block_heights.end)
+        .zip(pow_infos.windows(2))
+        .zip(hf_infos)
+    {
+        let [previous, block] = pow_infos else {
+            unreachable!()
+        };
+
+        let expected = cache.next_difficulty(&hf_info.version);
+        let stored = block
+            .cumulative_difficulty
+            .saturating_sub(previous.cumulative_difficulty);
+
+        if expected != stored {
+            tracing::warn!(target: CONTEXT_TARGET,
+                "Difficulty mismatch at height: {}, expected: {}, stored: {}",
+                height,
+                expected,
+                stored
+            );
+            mismatches.push(DifficultyMismatch {
+                height,
+                expected,
+                stored,
+            });
+        }
+
+        cache.new_block(height, block.timestamp, block.cumulative_difficulty);
+    }
+
+    Ok(mismatches)
+}
+
 fn get_window_start_and_end(window_len: usize) -> (usize, usize) {
     let window_len = if window_len > DIFFICULTY_WINDOW {
         DIFFICULTY_WINDOW
@@ -195,36 +364,98 @@ fn get_window_start_and_end(window_len: usize) -> (usize, usize) {
     }
 }
 
-#[instrument(name = "get_blocks_timestamps", skip(database), level = "info")]
-async fn get_blocks_in_range_timestamps<D: Database + Clone>(
+#[instrument(
+    target = "cuprate_consensus::context",
+    name = "get_blocks_timestamps",
+    skip(database),
+    level = "info"
+)]
+async fn get_blocks_in_range_timestamps<D: Database>(
     database: D,
     block_heights: Range<u64>,
-) -> Result<Vec<u64>, ConsensusError> {
-    tracing::info!("Getting blocks timestamps");
+) -> Result<(VecDeque<u64>, VecDeque<u128>), ConsensusError> {
+    tracing::info!(target: CONTEXT_TARGET, "Getting blocks timestamps");
 
-    let DatabaseResponse::BlockPOWInfoInRange(pow_infos) = database
+    let pow_infos = database
         .oneshot(DatabaseRequest::BlockPOWInfoInRange(block_heights))
         .await?
-    else {
-        panic!("Database sent incorrect response");
-    };
+        .into_block_pow_info_in_range()?;
 
-    Ok(pow_infos.into_iter().map(|info| info.timestamp).collect())
+    Ok(pow_infos
+        .into_iter()
+        .map(|info| (info.timestamp, info.cumulative_difficulty))
+        .unzip())
 }
 
-async fn get_block_cum_diff<D: Database>(database: D, height: u64) -> Result<u128, ConsensusError> {
-    let DatabaseResponse::BlockPOWInfo(pow) = database
-        .oneshot(DatabaseRequest::BlockPOWInfo(height.into()))
-        .await?
-    else {
-        panic!("Database service sent incorrect response!");
-    };
-    Ok(pow.cumulative_difficulty)
+fn target_time_for_hf(hf: &HardFork) -> u128 {
+    hf.block_time().as_secs().into()
 }
 
-fn target_time_for_hf(hf: &HardFork) -> u128 {
-    match hf {
-        HardFork::V1 => 60,
-        _ => 120,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder};
+
+    fn block(timestamp: u64, difficulty: u128) -> DummyBlockExtendedHeader {
+        DummyBlockExtendedHeader::default()
+            .with_hard_fork_info(HardFork::V16, HardFork::V16)
+            .with_pow_info(timestamp, difficulty)
+    }
+
+    #[test]
+    fn stored_difficulties_are_checked() {
+        // Blocks on target with a difficulty of 1 keep the difficulty at 1.
+        let mut builder = DummyDatabaseBuilder::default();
+        for height in 0..1_000 {
+            builder = builder.add_block(block(height * 120, if height == 900 { 5 } else { 1 }));
+        }
+        let database = builder.finish();
+
+        let mismatches =
+            futures::executor::block_on(check_difficulties(database.clone(), 0..900)).unwrap();
+        assert!(mismatches.is_empty());
+
+        let mismatches =
+            futures::executor::block_on(check_difficulties(database, 800..1_000)).unwrap();
+        assert_eq!(
+            mismatches[0],
+            DifficultyMismatch {
+                height: 900,
+                expected: 1,
+                stored: 5,
+            }
+        );
+        // The extra work is then in the window of the blocks after it.
+        assert!(mismatches[1..].iter().all(|mismatch| mismatch.height > 900));
+    }
+
+    #[test]
+    fn adjusted_time() {
+        let mut builder = DummyDatabaseBuilder::default();
+        for height in 0..100 {
+            builder = builder.add_block(block(height * 120, 1));
+        }
+        let database = builder.finish();
+
+        let mut cache =
+            futures::executor::block_on(DifficultyCache::init_from_chain_height(100, database))
+                .unwrap();
+
+        // The median of blocks 40..100 is 69.5 * 120, moved forward 61 blocks of half the target.
+        assert_eq!(cache.median_timestamp(), Some(8_340));
+        assert_eq!(cache.adjusted_time(), Some(8_340 + 3_660));
+
+        // A timestamp ahead of the adjusted median is used instead.
+        cache.new_block(100, 20_000, 101);
+        assert_eq!(cache.adjusted_time(), Some(20_000));
+
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(50, block(0, 1))
+            .finish();
+        let cache =
+            futures::executor::block_on(DifficultyCache::init_from_chain_height(50, database))
+                .unwrap();
+        assert_eq!(cache.median_timestamp(), None);
+        assert_eq!(cache.adjusted_time(), None);
     }
 }
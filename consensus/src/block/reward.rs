@@ -0,0 +1,42 @@
+//! # Block Reward
+//!
+//! This module contains the calculation of the base block reward, the fees are calculated in
+//! [`fee`](crate::fee).
+//!
+use crate::hardforks::HardFork;
+
+const MONEY_SUPPLY: u64 = u64::MAX;
+/// The tail emission, per minute of block time.
+const MINIMUM_REWARD_PER_MIN: u64 = 3 * 10_u64.pow(11);
+/// Fees are rounded up to a multiple of this.
+pub const FEE_QUANTIZATION_MASK: u64 = 10_u64.pow(4);
+
+/// Returns the base reward of the next block, before any penalty for the block being over the median
+/// weight.
+///
+/// https://cuprate.github.io/monero-book/consensus_rules/blocks/reward.html
+pub fn calculate_base_reward(already_generated_coins: u64, hf: &HardFork) -> u64 {
+    let target_minutes = hf.block_time().as_secs() / 60;
+    let emission_speed_factor = 20 - (target_minutes - 1);
+
+    ((MONEY_SUPPLY - already_generated_coins) >> emission_speed_factor)
+        .max(MINIMUM_REWARD_PER_MIN * target_minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_reward() {
+        // The reward of the first block after genesis, 17.592186044415 XMR.
+        assert_eq!(calculate_base_reward(0, &HardFork::V1), 17_592_186_044_415);
+        // Two minute blocks halve the emission speed and double the reward.
+        assert_eq!(calculate_base_reward(0, &HardFork::V2), 35_184_372_088_831);
+        // Tail emission.
+        assert_eq!(
+            calculate_base_reward(MONEY_SUPPLY - 1000, &HardFork::V16),
+            6 * 10_u64.pow(11)
+        );
+    }
+}
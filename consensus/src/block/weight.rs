@@ -9,21 +9,30 @@
 use std::cmp::{max, min};
 use std::collections::VecDeque;
 use std::ops::Range;
+use std::pin::pin;
 
+use futures::TryStreamExt;
 use monero_serai::{block::Block, transaction::Transaction};
 use tower::ServiceExt;
 use tracing::instrument;
 
-use crate::{hardforks::HardFork, ConsensusError, Database, DatabaseRequest, DatabaseResponse};
+use crate::{
+    consensus_constants::{PENALTY_FREE_ZONE_1, PENALTY_FREE_ZONE_2, PENALTY_FREE_ZONE_5},
+    context::WeightWindowSummary,
+    hardforks::HardFork,
+    range_chunk_responses,
+    spans::CONTEXT_TARGET,
+    ConsensusError, Database, DatabaseRequest,
+};
 
-const PENALTY_FREE_ZONE_1: usize = 20000;
-const PENALTY_FREE_ZONE_2: usize = 60000;
-const PENALTY_FREE_ZONE_5: usize = 300000;
+mod median;
+
+pub(crate) use median::{BucketedMedian, RollingMedian};
 
 const SHORT_TERM_WINDOW: u64 = 100;
-const LONG_TERM_WINDOW: u64 = 100000;
+pub(crate) const LONG_TERM_WINDOW: u64 = 100000;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BlockWeightInfo {
     pub block_weight: usize,
     pub long_term_weight: usize,
@@ -59,10 +68,12 @@ pub fn penalty_free_zone(hf: &HardFork) -> usize {
 /// this data it reduces the load on the database.
 #[derive(Clone)]
 pub struct BlockWeightsCache {
-    /// This list is not sorted.
+    /// The short term block weights, in the order the blocks are in the chain.
     short_term_block_weights: VecDeque<usize>,
-    /// This list is sorted.
-    long_term_weights: Vec<usize>,
+    /// The short term block weights, kept for their median.
+    short_term_median: RollingMedian,
+    /// The long term weights, kept for their median.
+    long_term_median: BucketedMedian,
     /// The height of the top block.
     tip_height: u64,
 }
@@ -70,50 +81,51 @@ pub struct BlockWeightsCache {
 impl BlockWeightsCache {
     /// Initialize the [`BlockWeightsCache`] at the the height of the database.
     pub async fn init<D: Database + Clone>(mut database: D) -> Result<Self, ConsensusError> {
-        let DatabaseResponse::ChainHeight(chain_height) = database
+        let chain_height = database
             .ready()
             .await?
             .call(DatabaseRequest::ChainHeight)
             .await?
-        else {
-            panic!("Database sent incorrect response!");
-        };
+            .into_chain_height()?;
 
         Self::init_from_chain_height(chain_height, database).await
     }
 
     /// Initialize the [`BlockWeightsCache`] at the the given chain height.
-    #[instrument(name = "init_weight_cache", level = "info", skip(database))]
+    #[instrument(
+        target = "cuprate_consensus::context",
+        name = "init_weight_cache",
+        level = "info",
+        skip(database)
+    )]
     pub async fn init_from_chain_height<D: Database + Clone>(
         chain_height: u64,
         database: D,
     ) -> Result<Self, ConsensusError> {
-        tracing::info!("Initializing weight cache this may take a while.");
+        tracing::info!(target: CONTEXT_TARGET, "Initializing weight cache this may take a while.");
 
-        let mut long_term_weights = get_long_term_weight_in_range(
+        // The short term window is the end of the long term window, so both are read in one pass.
+        let mut long_term_median = BucketedMedian::default();
+        let mut short_term_block_weights = VecDeque::with_capacity(SHORT_TERM_WINDOW as usize);
+        for_each_weight_in_range(
             chain_height.saturating_sub(LONG_TERM_WINDOW)..chain_height,
-            database.clone(),
-        )
-        .await?;
-
-        long_term_weights.sort_unstable();
-        tracing::debug!(
-            "Sorted long term weights with length: {}",
-            long_term_weights.len()
-        );
-
-        let short_term_block_weights: VecDeque<usize> = get_blocks_weight_in_range(
-            chain_height.saturating_sub(SHORT_TERM_WINDOW)..chain_height,
             database,
+            |info| {
+                long_term_median.insert(info.long_term_weight);
+                if short_term_block_weights.len() == SHORT_TERM_WINDOW as usize {
+                    short_term_block_weights.pop_front();
+                }
+                short_term_block_weights.push_back(info.block_weight);
+            },
         )
-        .await?
-        .into();
+        .await?;
 
-        tracing::info!("Initialized block weight cache, chain-height: {:?}, long term weights length: {:?}, short term weights length: {:?}", chain_height, long_term_weights.len(), short_term_block_weights.len());
+        tracing::info!(target: CONTEXT_TARGET, "Initialized block weight cache, chain-height: {:?}, long term weights length: {:?}, short term weights length: {:?}", chain_height, long_term_median.len(), short_term_block_weights.len());
 
         Ok(BlockWeightsCache {
+            short_term_median: short_term_block_weights.iter().copied().collect(),
             short_term_block_weights,
-            long_term_weights,
+            long_term_median,
             tip_height: chain_height - 1,
         })
     }
@@ -129,50 +141,81 @@ impl BlockWeightsCache {
         long_term_weight: usize,
         database: &mut D,
     ) -> Result<(), ConsensusError> {
-        tracing::debug!(
-            "Adding new block's {} weights to block cache, weight: {}, long term weight: {}",
+        let removed_long_term_weight = match block_height.checked_sub(LONG_TERM_WINDOW) {
+            Some(height_to_remove) => Some(
+                database
+                    .oneshot(DatabaseRequest::BlockWeights(height_to_remove.into()))
+                    .await?
+                    .into_block_weights()?
+                    .long_term_weight,
+            ),
+            None => None,
+        };
+
+        self.add_block_weights(
+            block_height,
             block_weight,
+            long_term_weight,
+            removed_long_term_weight,
+        );
+        Ok(())
+    }
+
+    /// Add a new block to the cache when the long term weight of the block leaving the long term
+    /// window, the block at `block_height - LONG_TERM_WINDOW`, is already known.
+    ///
+    /// The block_height **MUST** be one more than the last height the cache has
+    /// seen.
+    pub(crate) fn add_block_weights(
+        &mut self,
+        block_height: u64,
+        block_weight: usize,
+        long_term_weight: usize,
+        removed_long_term_weight: Option<usize>,
+    ) {
+        tracing::debug!(
+            target: CONTEXT_TARGET,
+            height = block_height,
             block_weight,
-            long_term_weight
+            long_term_weight,
+            "Adding new block's weights to block cache"
         );
         assert_eq!(self.tip_height + 1, block_height);
+        assert_eq!(
+            removed_long_term_weight.is_some(),
+            block_height >= LONG_TERM_WINDOW
+        );
         self.tip_height += 1;
 
-        match self.long_term_weights.binary_search(&long_term_weight) {
-            Ok(idx) | Err(idx) => self.long_term_weights.insert(idx, long_term_weight),
-        };
+        self.long_term_median.insert(long_term_weight);
 
-        if let Some(height_to_remove) = block_height.checked_sub(LONG_TERM_WINDOW) {
-            tracing::debug!(
+        if let Some(removed_long_term_weight) = removed_long_term_weight {
+            tracing::debug!(target: CONTEXT_TARGET,
                 "Block {} is out of the long term weight window, removing it",
-                height_to_remove
+                block_height - LONG_TERM_WINDOW
             );
-            let DatabaseResponse::BlockWeights(weights) = database
-                .oneshot(DatabaseRequest::BlockWeights(height_to_remove.into()))
-                .await?
-            else {
-                panic!("Database sent incorrect response!");
-            };
-            let idx = self
-                .long_term_weights
-                .binary_search(&weights.long_term_weight)
-                .expect("Weight must be in list if in the window");
-            self.long_term_weights.remove(idx);
+            self.long_term_median.remove(removed_long_term_weight);
         }
 
         self.short_term_block_weights.push_back(block_weight);
+        self.short_term_median.insert(block_weight);
         if self.short_term_block_weights.len() > SHORT_TERM_WINDOW.try_into().unwrap() {
-            self.short_term_block_weights.pop_front();
+            let removed = self.short_term_block_weights.pop_front().unwrap();
+            self.short_term_median.remove(removed);
         }
 
-        Ok(())
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_weight_cache_len(
+            self.short_term_block_weights.len(),
+            self.long_term_median.len(),
+        );
     }
 
     /// Returns the next blocks long term weight.
     ///
     /// See: https://cuprate.github.io/monero-book/consensus_rules/blocks/weight_limit.html#calculating-a-blocks-long-term-weight
     pub fn next_block_long_term_weight(&self, hf: &HardFork, block_weight: usize) -> usize {
-        calculate_block_long_term_weight(hf, block_weight, &self.long_term_weights)
+        calculate_block_long_term_weight(hf, block_weight, self.long_term_median.median())
     }
 
     /// Returns the effective median weight, used for block reward calculations and to calculate
@@ -180,33 +223,66 @@ impl BlockWeightsCache {
     ///
     /// See: https://cuprate.github.io/monero-book/consensus_rules/blocks/weight_limit.html#calculating-effective-median-weight
     pub fn effective_median_block_weight(&self, hf: &HardFork) -> usize {
-        let mut sorted_short_term_weights: Vec<usize> =
-            self.short_term_block_weights.clone().into();
-        sorted_short_term_weights.sort_unstable();
         calculate_effective_median_block_weight(
             hf,
-            &sorted_short_term_weights,
-            &self.long_term_weights,
+            self.short_term_median.median(),
+            self.long_term_median.median(),
         )
     }
 
+    /// Returns the median of the short term block weights.
+    pub fn short_term_median(&self) -> usize {
+        self.short_term_median.median()
+    }
+
+    /// Returns the median of the long term weights.
+    pub fn long_term_median(&self) -> usize {
+        self.long_term_median.median()
+    }
+
     /// Returns the block weight limit.
     pub fn next_block_weight_limit(&self, hf: &HardFork) -> usize {
         2 * self.effective_median_block_weight(hf)
     }
+
+    /// Returns a summary of the short term block weights.
+    pub fn short_term_summary(&self) -> WeightWindowSummary {
+        let median = &self.short_term_median;
+        WeightWindowSummary {
+            len: median.len(),
+            min: median.min(),
+            median: median.median(),
+            max: median.max(),
+        }
+    }
+
+    /// Returns a summary of the long term block weights.
+    pub fn long_term_summary(&self) -> WeightWindowSummary {
+        let median = &self.long_term_median;
+        WeightWindowSummary {
+            len: median.len(),
+            min: median.min(),
+            median: median.median(),
+            max: median.max(),
+        }
+    }
+
+    /// Returns the height of the top block in the cache.
+    pub fn tip_height(&self) -> u64 {
+        self.tip_height
+    }
 }
 
-fn calculate_effective_median_block_weight(
+pub(crate) fn calculate_effective_median_block_weight(
     hf: &HardFork,
-    sorted_short_term_window: &[usize],
-    sorted_long_term_window: &[usize],
+    short_term_median: usize,
+    long_term_median: usize,
 ) -> usize {
     if hf.in_range(&HardFork::V1, &HardFork::V10) {
-        return median(sorted_short_term_window);
+        return short_term_median.max(penalty_free_zone(hf));
     }
 
-    let long_term_median = median(sorted_long_term_window).max(PENALTY_FREE_ZONE_5);
-    let short_term_median = median(sorted_short_term_window);
+    let long_term_median = long_term_median.max(PENALTY_FREE_ZONE_5);
     let effective_median = if hf.in_range(&HardFork::V10, &HardFork::V15) {
         min(
             max(PENALTY_FREE_ZONE_5, short_term_median),
@@ -222,16 +298,16 @@ fn calculate_effective_median_block_weight(
     effective_median.max(penalty_free_zone(hf))
 }
 
-fn calculate_block_long_term_weight(
+pub(crate) fn calculate_block_long_term_weight(
     hf: &HardFork,
     block_weight: usize,
-    sorted_long_term_window: &[usize],
+    long_term_median: usize,
 ) -> usize {
     if hf.in_range(&HardFork::V1, &HardFork::V10) {
         return block_weight;
     }
 
-    let long_term_median = max(penalty_free_zone(hf), median(sorted_long_term_window));
+    let long_term_median = max(penalty_free_zone(hf), long_term_median);
 
     let (short_term_constraint, adjusted_block_weight) =
         if hf.in_range(&HardFork::V10, &HardFork::V15) {
@@ -245,58 +321,139 @@ fn calculate_block_long_term_weight(
     min(short_term_constraint, adjusted_block_weight)
 }
 
-fn get_mid(a: usize, b: usize) -> usize {
-    // https://github.com/monero-project/monero/blob/90294f09ae34ef96f3dea5fea544816786df87c8/contrib/epee/include/misc_language.h#L43
-    (a / 2) + (b / 2) + ((a - 2 * (a / 2)) + (b - 2 * (b / 2))) / 2
-}
-
-fn median(array: &[usize]) -> usize {
-    let mid = array.len() / 2;
-
-    if array.len() == 1 {
-        return array[0];
+/// Gets the weights of the blocks in the range from the database, in chunks of
+/// [`RANGE_REQUEST_CHUNK_SIZE`](crate::RANGE_REQUEST_CHUNK_SIZE) blocks, passing each to `f`.
+#[instrument(
+    target = "cuprate_consensus::context",
+    name = "get_block_weights",
+    skip(database, f)
+)]
+async fn for_each_weight_in_range<D: Database + Clone>(
+    range: Range<u64>,
+    database: D,
+    mut f: impl FnMut(BlockWeightInfo),
+) -> Result<(), ConsensusError> {
+    tracing::info!(target: CONTEXT_TARGET, "getting block weights.");
+
+    let mut responses = pin!(range_chunk_responses(
+        database,
+        range,
+        DatabaseRequest::BlockWeightsInRange
+    ));
+    while let Some(weights) = responses.try_next().await? {
+        weights
+            .into_block_weights_in_range()?
+            .into_iter()
+            .for_each(&mut f);
     }
 
-    if array.len() % 2 == 0 {
-        get_mid(array[mid - 1], array[mid])
-    } else {
-        array[mid]
-    }
+    Ok(())
 }
 
-#[instrument(name = "get_block_weights", skip(database))]
-async fn get_blocks_weight_in_range<D: Database + Clone>(
-    range: Range<u64>,
-    database: D,
-) -> Result<Vec<usize>, ConsensusError> {
-    tracing::info!("getting block weights.");
-
-    let DatabaseResponse::BlockWeightsInRange(weights) = database
-        .oneshot(DatabaseRequest::BlockWeightsInRange(range))
-        .await?
-    else {
-        panic!("Database sent incorrect response!")
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+    use tower::ServiceExt;
+
+    use super::BlockWeightsCache;
+    use crate::consensus_constants::{PENALTY_FREE_ZONE_1, PENALTY_FREE_ZONE_5};
+    use crate::hardforks::HardFork;
+    use crate::test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder};
+    use crate::{
+        ConsensusError, DatabaseProtocolError, DatabaseRequest, DatabaseResponse, InternalError,
+        RANGE_REQUEST_CHUNK_SIZE,
     };
 
-    Ok(weights.into_iter().map(|info| info.block_weight).collect())
-}
+    #[test]
+    fn effective_median_of_constant_chain() {
+        let mut database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                200,
+                DummyBlockExtendedHeader::default().with_weight(1_000_000, 1_000_000),
+            )
+            .finish();
+
+        let mut cache = block_on(BlockWeightsCache::init_from_chain_height(
+            200,
+            database.clone(),
+        ))
+        .unwrap();
 
-#[instrument(name = "get_long_term_weights", skip(database), level = "info")]
-async fn get_long_term_weight_in_range<D: Database + Clone>(
-    range: Range<u64>,
-    database: D,
-) -> Result<Vec<usize>, ConsensusError> {
-    tracing::info!("getting block long term weights.");
-
-    let DatabaseResponse::BlockWeightsInRange(weights) = database
-        .oneshot(DatabaseRequest::BlockWeightsInRange(range))
-        .await?
-    else {
-        panic!("Database sent incorrect response!")
-    };
+        assert_eq!(
+            cache.effective_median_block_weight(&HardFork::V1),
+            1_000_000
+        );
+        assert_eq!(cache.next_block_weight_limit(&HardFork::V1), 2_000_000);
+
+        for height in 200..300 {
+            block_on(cache.new_block_added(height, 10, 10, &mut database)).unwrap();
+        }
 
-    Ok(weights
-        .into_iter()
-        .map(|info| info.long_term_weight)
-        .collect())
+        // Before V10 only the short term median is used, raised to the penalty free zone.
+        assert_eq!(
+            cache.effective_median_block_weight(&HardFork::V1),
+            PENALTY_FREE_ZONE_1
+        );
+        // After V10 the long term median, which is still 1,000,000, is taken into account.
+        assert_eq!(
+            cache.effective_median_block_weight(&HardFork::V16),
+            1_000_000
+        );
+        assert_eq!(
+            cache.next_block_long_term_weight(&HardFork::V16, 10),
+            PENALTY_FREE_ZONE_5.max(1_000_000) * 10 / 17
+        );
+    }
+
+    #[test]
+    fn weights_are_requested_in_chunks() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                25_000,
+                DummyBlockExtendedHeader::default().with_weight(100, 100),
+            )
+            .finish();
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let chunk_requests = requests.clone();
+        let chunked_database = tower::service_fn(move |req: DatabaseRequest| {
+            if let DatabaseRequest::BlockWeightsInRange(range) = &req {
+                assert!(range.end - range.start <= RANGE_REQUEST_CHUNK_SIZE);
+                chunk_requests.fetch_add(1, Ordering::Relaxed);
+            }
+            database.clone().oneshot(req)
+        });
+
+        let cache = block_on(BlockWeightsCache::init_from_chain_height(
+            25_000,
+            chunked_database,
+        ))
+        .unwrap();
+
+        assert_eq!(cache.long_term_median.len(), 25_000);
+        assert_eq!(cache.short_term_block_weights.len(), 100);
+        // The short term weights are taken from the long term window, not read again.
+        assert_eq!(requests.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn wrong_database_response_is_an_error() {
+        let database = tower::service_fn(|_: DatabaseRequest| async {
+            Ok::<_, tower::BoxError>(DatabaseResponse::WriteBlock)
+        });
+
+        let res = block_on(BlockWeightsCache::init(database));
+        assert!(matches!(
+            res,
+            Err(ConsensusError::Internal(InternalError::DatabaseProtocol(
+                DatabaseProtocolError {
+                    expected: "ChainHeight",
+                    got: "WriteBlock",
+                }
+            )))
+        ));
+    }
 }
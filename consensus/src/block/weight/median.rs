@@ -0,0 +1,396 @@
+//! # Rolling Median
+//!
+//! This module contains [`RollingMedian`], a multiset that keeps track of its median as values are
+//! added and removed, so the weight windows don't need to be sorted every time a median is needed.
+//!
+//! The long term window holds 100,000 weights, for it [`BucketedMedian`] keeps the values as runs
+//! of `u32`s in shared buckets, which takes less memory and makes a clone, for an alt chain, copy
+//! only the buckets that are changed.
+//!
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The most runs of values in a [`BucketedMedian`] bucket, a bucket with more is split in two.
+const MAX_BUCKET_RUNS: usize = 256;
+
+/// A multiset of values split into a lower and upper half, so the median can be read from the
+/// boundary between the two halves.
+///
+/// Adding and removing values is O(log n), getting the median is O(log n).
+#[derive(Debug, Default, Clone)]
+pub struct RollingMedian {
+    /// The lower half of the values, this holds the extra value when there is an odd amount.
+    lower: BTreeMap<usize, usize>,
+    lower_len: usize,
+    /// The upper half of the values.
+    upper: BTreeMap<usize, usize>,
+    upper_len: usize,
+}
+
+impl RollingMedian {
+    /// Returns the amount of values in the set.
+    pub fn len(&self) -> usize {
+        self.lower_len + self.upper_len
+    }
+
+    /// Adds a value to the set.
+    pub fn insert(&mut self, value: usize) {
+        match max_key(&self.lower) {
+            Some(lower_max) if value > lower_max => {
+                add(&mut self.upper, value);
+                self.upper_len += 1;
+            }
+            _ => {
+                add(&mut self.lower, value);
+                self.lower_len += 1;
+            }
+        }
+
+        self.rebalance();
+    }
+
+    /// Removes a value from the set.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the value is not in the set.
+    pub fn remove(&mut self, value: usize) {
+        if max_key(&self.lower).is_some_and(|lower_max| value <= lower_max) {
+            take(&mut self.lower, value);
+            self.lower_len -= 1;
+        } else {
+            take(&mut self.upper, value);
+            self.upper_len -= 1;
+        }
+
+        self.rebalance();
+    }
+
+    /// Returns the smallest value in the set, or 0 if the set is empty.
+    pub fn min(&self) -> usize {
+        self.lower.keys().next().copied().unwrap_or(0)
+    }
+
+    /// Returns the largest value in the set, or 0 if the set is empty.
+    pub fn max(&self) -> usize {
+        max_key(&self.upper)
+            .or_else(|| max_key(&self.lower))
+            .unwrap_or(0)
+    }
+
+    /// Returns the median of the set, the mean of the two middle values is rounded down.
+    ///
+    /// Returns 0 if the set is empty.
+    pub fn median(&self) -> usize {
+        let Some(lower_max) = max_key(&self.lower) else {
+            return 0;
+        };
+
+        if self.lower_len == self.upper_len {
+            let upper_min = *self.upper.keys().next().expect(
+                "Upper half can't be empty if it has the same amount of values as the lower half",
+            );
+            get_mid(lower_max, upper_min)
+        } else {
+            lower_max
+        }
+    }
+
+    /// Moves values between the halves so the lower half has the same amount of values as the upper
+    /// half, or one more.
+    fn rebalance(&mut self) {
+        if self.lower_len > self.upper_len + 1 {
+            let value = max_key(&self.lower).unwrap();
+            take(&mut self.lower, value);
+            self.lower_len -= 1;
+            add(&mut self.upper, value);
+            self.upper_len += 1;
+        } else if self.upper_len > self.lower_len {
+            let value = *self.upper.keys().next().unwrap();
+            take(&mut self.upper, value);
+            self.upper_len -= 1;
+            add(&mut self.lower, value);
+            self.lower_len += 1;
+        }
+    }
+}
+
+impl FromIterator<usize> for RollingMedian {
+    fn from_iter<T: IntoIterator<Item = usize>>(iter: T) -> Self {
+        let mut median = RollingMedian::default();
+        for value in iter {
+            median.insert(value);
+        }
+        median
+    }
+}
+
+/// A run of the same value in a [`BucketedMedian`].
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    value: u32,
+    count: u32,
+}
+
+/// A sorted list of runs, every value is lower than the values of the next bucket.
+#[derive(Debug, Clone)]
+struct Bucket {
+    runs: Vec<Run>,
+    /// The amount of values in the bucket.
+    len: usize,
+}
+
+impl Bucket {
+    fn max(&self) -> u32 {
+        self.runs.last().expect("Buckets are never empty").value
+    }
+}
+
+/// A multiset of values stored as sorted runs of the same value, in buckets of at most
+/// [`MAX_BUCKET_RUNS`] runs shared between clones.
+///
+/// Adding and removing values is O(log n + [`MAX_BUCKET_RUNS`]), getting the median is
+/// O(n / [`MAX_BUCKET_RUNS`] + [`MAX_BUCKET_RUNS`]). Weights around the penalty free zone repeat a
+/// lot, so there are usually far less runs than values.
+///
+/// Values must fit in a `u32`, which block weights always do: a block is far smaller than the
+/// largest p2p message.
+#[derive(Debug, Default, Clone)]
+pub struct BucketedMedian {
+    buckets: Vec<Arc<Bucket>>,
+    len: usize,
+}
+
+impl BucketedMedian {
+    /// Returns the amount of values in the set.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Adds a value to the set.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the value doesn't fit in a `u32`.
+    pub fn insert(&mut self, value: usize) {
+        let value = u32::try_from(value).expect("Values must fit in a u32");
+        self.len += 1;
+
+        if self.buckets.is_empty() {
+            self.buckets.push(Arc::new(Bucket {
+                runs: vec![Run { value, count: 1 }],
+                len: 1,
+            }));
+            return;
+        }
+
+        let i = self.bucket_index(value).unwrap_or(self.buckets.len() - 1);
+        let bucket = Arc::make_mut(&mut self.buckets[i]);
+        bucket.len += 1;
+        match bucket.runs.binary_search_by_key(&value, |run| run.value) {
+            Ok(j) => bucket.runs[j].count += 1,
+            Err(j) => bucket.runs.insert(j, Run { value, count: 1 }),
+        }
+
+        if bucket.runs.len() > MAX_BUCKET_RUNS {
+            let runs = bucket.runs.split_off(bucket.runs.len() / 2);
+            let len = runs.iter().map(|run| run.count as usize).sum();
+            bucket.len -= len;
+            self.buckets.insert(i + 1, Arc::new(Bucket { runs, len }));
+        }
+    }
+
+    /// Removes a value from the set.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the value is not in the set.
+    pub fn remove(&mut self, value: usize) {
+        let i = u32::try_from(value)
+            .ok()
+            .and_then(|value| Some((value, self.bucket_index(value)?)));
+        let Some((value, i)) = i else {
+            panic!("Value must be in the set to be removed");
+        };
+
+        let bucket = Arc::make_mut(&mut self.buckets[i]);
+        let j = bucket
+            .runs
+            .binary_search_by_key(&value, |run| run.value)
+            .expect("Value must be in the set to be removed");
+        bucket.runs[j].count -= 1;
+        if bucket.runs[j].count == 0 {
+            bucket.runs.remove(j);
+        }
+        bucket.len -= 1;
+        self.len -= 1;
+
+        // Merge small buckets, so removes don't leave many nearly empty buckets.
+        if bucket.runs.is_empty() {
+            self.buckets.remove(i);
+        } else if let Some(next) = self.buckets.get(i + 1) {
+            if self.buckets[i].runs.len() + next.runs.len() <= MAX_BUCKET_RUNS / 2 {
+                let next = self.buckets.remove(i + 1);
+                let bucket = Arc::make_mut(&mut self.buckets[i]);
+                bucket.runs.extend_from_slice(&next.runs);
+                bucket.len += next.len;
+            }
+        }
+    }
+
+    /// Returns the smallest value in the set, or 0 if the set is empty.
+    pub fn min(&self) -> usize {
+        self.buckets
+            .first()
+            .map_or(0, |bucket| bucket.runs[0].value as usize)
+    }
+
+    /// Returns the largest value in the set, or 0 if the set is empty.
+    pub fn max(&self) -> usize {
+        self.buckets
+            .last()
+            .map_or(0, |bucket| bucket.max() as usize)
+    }
+
+    /// Returns the median of the set, the mean of the two middle values is rounded down.
+    ///
+    /// Returns 0 if the set is empty.
+    pub fn median(&self) -> usize {
+        if self.len == 0 {
+            return 0;
+        }
+
+        let mid = self.nth(self.len / 2);
+        if self.len % 2 == 1 {
+            mid
+        } else {
+            get_mid(self.nth(self.len / 2 - 1), mid)
+        }
+    }
+
+    /// Returns the index of the first bucket that could hold the value.
+    fn bucket_index(&self, value: u32) -> Option<usize> {
+        let i = self.buckets.partition_point(|bucket| bucket.max() < value);
+        (i < self.buckets.len()).then_some(i)
+    }
+
+    /// Returns the value at this index of the sorted set.
+    fn nth(&self, mut index: usize) -> usize {
+        for bucket in &self.buckets {
+            if index >= bucket.len {
+                index -= bucket.len;
+                continue;
+            }
+
+            for run in &bucket.runs {
+                if index < run.count as usize {
+                    return run.value as usize;
+                }
+                index -= run.count as usize;
+            }
+        }
+        unreachable!("The index is in the set")
+    }
+}
+
+impl FromIterator<usize> for BucketedMedian {
+    fn from_iter<T: IntoIterator<Item = usize>>(iter: T) -> Self {
+        let mut median = BucketedMedian::default();
+        for value in iter {
+            median.insert(value);
+        }
+        median
+    }
+}
+
+fn max_key(half: &BTreeMap<usize, usize>) -> Option<usize> {
+    half.keys().next_back().copied()
+}
+
+fn add(half: &mut BTreeMap<usize, usize>, value: usize) {
+    *half.entry(value).or_default() += 1;
+}
+
+fn take(half: &mut BTreeMap<usize, usize>, value: usize) {
+    let count = half
+        .get_mut(&value)
+        .expect("Value must be in the set to be removed");
+    *count -= 1;
+    if *count == 0 {
+        half.remove(&value);
+    }
+}
+
+fn get_mid(a: usize, b: usize) -> usize {
+    // https://github.com/monero-project/monero/blob/90294f09ae34ef96f3dea5fea544816786df87c8/contrib/epee/include/misc_language.h#L43
+    (a / 2) + (b / 2) + ((a - 2 * (a / 2)) + (b - 2 * (b / 2))) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BucketedMedian, RollingMedian, MAX_BUCKET_RUNS};
+
+    #[test]
+    fn median_after_inserts_and_removes() {
+        let mut median: RollingMedian = [5, 1, 3].into_iter().collect();
+        assert_eq!(median.median(), 3);
+
+        median.insert(4);
+        assert_eq!(median.median(), 3);
+
+        median.remove(1);
+        assert_eq!(median.median(), 4);
+
+        median.insert(usize::MAX);
+        median.insert(usize::MAX);
+        median.remove(3);
+        assert_eq!(median.len(), 4);
+        assert_eq!(median.median(), usize::MAX / 2 + 3);
+        assert_eq!(median.min(), 4);
+        assert_eq!(median.max(), usize::MAX);
+
+        for value in [4, 5, usize::MAX, usize::MAX] {
+            median.remove(value);
+        }
+        assert_eq!(median.len(), 0);
+        assert_eq!(median.median(), 0);
+        assert_eq!(median.max(), 0);
+    }
+
+    #[test]
+    fn bucketed_median_matches_rolling_median() {
+        let mut bucketed = BucketedMedian::default();
+        let mut rolling = RollingMedian::default();
+
+        // Enough distinct values to split buckets, with runs of the same value.
+        let values: Vec<usize> = (0..10 * MAX_BUCKET_RUNS)
+            .map(|i| (i * 7919) % (4 * MAX_BUCKET_RUNS) + 300_000)
+            .collect();
+        for (i, value) in values.iter().enumerate() {
+            bucketed.insert(*value);
+            rolling.insert(*value);
+            if i >= 1_000 {
+                bucketed.remove(values[i - 1_000]);
+                rolling.remove(values[i - 1_000]);
+            }
+            assert_eq!(bucketed.median(), rolling.median());
+        }
+        assert!(bucketed.buckets.len() > 1);
+        assert_eq!(bucketed.len(), rolling.len());
+        assert_eq!(bucketed.min(), rolling.min());
+        assert_eq!(bucketed.max(), rolling.max());
+
+        // Clones share their buckets until they are changed.
+        let mut clone = bucketed.clone();
+        clone.insert(0);
+        assert_eq!(clone.min(), 0);
+        assert_eq!(bucketed.min(), rolling.min());
+
+        for value in &values[values.len() - 1_000..] {
+            bucketed.remove(*value);
+        }
+        assert_eq!(bucketed.len(), 0);
+        assert!(bucketed.buckets.is_empty());
+        assert_eq!(bucketed.median(), 0);
+    }
+}
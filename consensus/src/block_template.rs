@@ -0,0 +1,392 @@
+//! # Block Templates
+//!
+//! This module contains [`build_block_template`], which builds a candidate block on top of the main
+//! chain for the `get_block_template` RPC method and solo mining.
+//!
+//! Transactions are picked from the [`TxPool`] by fee per byte, under the block weight limit minus
+//! [`MINER_TX_RESERVED_WEIGHT`] for the miner transaction. Past the median weight a block's reward is
+//! penalized, so a transaction is only added if its fee covers the extra penalty it causes.
+//!
+//! The miner transaction is built with [`construct_miner_tx`], which pays the reward to one output,
+//! so templates are only built from [`HardFork::V4`], before it outputs had to be split into
+//! denominations. The miner transaction has the requested extra nonce, a
+//! pool reserves space for it and miners change it (and the header's nonce) to get new hashing blobs
+//! without asking for a new template. [`BlockTemplate::check_blob`] checks a block mined from a
+//! template only changed those bytes, a miner writing past its reserved space corrupts the block.
+//!
+//! On regtest networks, which don't check PoW, [`generate_block`] builds blocks that can be added
+//! to the chain as they are, like monerod's `generateblocks`.
+//!
+use monero_serai::block::{Block, BlockHeader};
+
+use crate::{
+    context::BlockChainContext,
+    hardforks::HardFork,
+    miner_tx::{
+        calculate_block_reward, construct_miner_tx, varint_len, MinerTxKeys, MAX_EXTRA_NONCE_SIZE,
+    },
+    txpool::{PoolTx, TxPool},
+};
+
+/// The weight kept free for the miner transaction when picking transactions, the same as monerod's.
+pub const MINER_TX_RESERVED_WEIGHT: usize = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum BlockTemplateError {
+    #[error("The extra nonce can be at most {MAX_EXTRA_NONCE_SIZE} bytes")]
+    ExtraNonceTooBig,
+    #[error("The block is over the weight limit")]
+    BlockTooBig,
+    #[error("The block changes its template outside the nonce and the reserved space")]
+    ReservedSpaceOverrun,
+    #[error("Block templates can't be built before hard fork 4")]
+    HardForkTooOld,
+}
+
+/// A candidate block for miners.
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    pub block: Block,
+    /// The height the block will be at.
+    pub height: u64,
+    pub difficulty: u128,
+    /// The reward of the block, the base reward after any penalty plus the fees.
+    pub reward: u64,
+    /// The total fees of the block's transactions.
+    pub fees: u64,
+    /// The weight of the block, including the miner transaction.
+    pub weight: usize,
+    /// The serialized block.
+    pub blob: Vec<u8>,
+    /// The blob that is hashed for the block's PoW.
+    pub hashing_blob: Vec<u8>,
+    /// The offset of the extra nonce in [`BlockTemplate::blob`].
+    pub reserved_offset: usize,
+    /// The size of the extra nonce, the space reserved for the miner.
+    pub reserve_size: usize,
+}
+
+impl BlockTemplate {
+    /// Returns the offset of the miner transaction's public key in [`BlockTemplate::blob`].
+    fn tx_pub_key_offset(&self) -> usize {
+        let nonce_header = if self.reserve_size == 0 {
+            0
+        } else {
+            1 + varint_len(self.reserve_size)
+        };
+
+        self.reserved_offset - nonce_header - 32
+    }
+
+    /// Returns the offset of the header's nonce in [`BlockTemplate::blob`].
+    fn nonce_offset(&self) -> usize {
+        let header = &self.block.header;
+
+        varint_len(header.major_version.into())
+            + varint_len(header.minor_version.into())
+            + varint_len(usize::try_from(header.timestamp).unwrap())
+            + 32
+    }
+
+    /// Returns true if the blob is a block mined from this template, its miner transaction has the
+    /// template's public key, which is new for every template.
+    pub fn is_template_of(&self, blob: &[u8]) -> bool {
+        let offset = self.tx_pub_key_offset();
+
+        blob.get(offset..offset + 32) == self.blob.get(offset..offset + 32)
+    }
+
+    /// Checks a block mined from this template only changed the header's nonce and the reserved
+    /// space.
+    pub fn check_blob(&self, blob: &[u8]) -> Result<(), BlockTemplateError> {
+        let nonce = self.nonce_offset();
+        let reserved = self.reserved_offset;
+
+        if blob.len() != self.blob.len()
+            || blob[..nonce] != self.blob[..nonce]
+            || blob[nonce + 4..reserved] != self.blob[nonce + 4..reserved]
+            || blob[reserved + self.reserve_size..] != self.blob[reserved + self.reserve_size..]
+        {
+            return Err(BlockTemplateError::ReservedSpaceOverrun);
+        }
+
+        Ok(())
+    }
+}
+
+/// Picks the transactions of a block template, highest fee per byte first.
+///
+/// Once the block is over the median weight each transaction must pay more in fees than the penalty
+/// it adds to the block reward.
+fn select_txs<'a>(
+    context: &BlockChainContext,
+    candidates: impl IntoIterator<Item = &'a PoolTx>,
+    base_reward: u64,
+) -> Vec<&'a PoolTx> {
+    let median = context.effective_median_weight;
+
+    let mut weight = MINER_TX_RESERVED_WEIGHT;
+    let mut txs = Vec::new();
+
+    for tx in candidates {
+        let new_weight = weight + tx.weight;
+
+        if new_weight > median {
+            let (Some(reward), Some(new_reward)) = (
+                calculate_block_reward(base_reward, median, weight),
+                calculate_block_reward(base_reward, median, new_weight),
+            ) else {
+                continue;
+            };
+
+            if new_reward + tx.fee <= reward {
+                continue;
+            }
+        }
+
+        weight = new_weight;
+        txs.push(tx);
+    }
+
+    txs
+}
+
+/// Builds a block template on top of the chain in the context.
+///
+/// The extra nonce is put in the miner transaction's extra, at [`BlockTemplate::reserved_offset`]
+/// in the blob. To reserve space pass zeros of the size needed.
+///
+/// Returns [`BlockTemplateError::HardForkTooOld`] before [`HardFork::V4`], see the
+/// [module docs](self).
+pub fn build_block_template(
+    context: &BlockChainContext,
+    pool: &TxPool,
+    miner_keys: &MinerTxKeys,
+    extra_nonce: &[u8],
+    timestamp: u64,
+) -> Result<BlockTemplate, BlockTemplateError> {
+    if extra_nonce.len() > MAX_EXTRA_NONCE_SIZE {
+        return Err(BlockTemplateError::ExtraNonceTooBig);
+    }
+    if context.current_hf < HardFork::V4 {
+        return Err(BlockTemplateError::HardForkTooOld);
+    }
+
+    let height = context.chain_height;
+    let hf = &context.current_hf;
+    let base_reward = context.next_block_base_reward();
+    let max_txs_weight = context
+        .next_block_weight_limit
+        .saturating_sub(MINER_TX_RESERVED_WEIGHT);
+
+    let txs = select_txs(
+        context,
+        pool.block_template_txs(max_txs_weight),
+        base_reward,
+    );
+    let txs_weight: usize = txs.iter().map(|tx| tx.weight).sum();
+    let fees: u64 = txs.iter().map(|tx| tx.fee).sum();
+
+    // The miner transaction's weight depends on its reward, so first build it with the highest reward
+    // it could have. A smaller reward never makes it heavier so the penalty from this weight is at
+    // least the real penalty.
+    let miner_tx = construct_miner_tx(height, base_reward + fees, hf, miner_keys, extra_nonce);
+    let weight = txs_weight + miner_tx.weight();
+
+    let reward = calculate_block_reward(base_reward, context.effective_median_weight, weight)
+        .ok_or(BlockTemplateError::BlockTooBig)?
+        + fees;
+
+    let miner_tx = construct_miner_tx(height, reward, hf, miner_keys, extra_nonce);
+    let weight = txs_weight + miner_tx.weight();
+
+    let block = Block {
+        header: BlockHeader {
+            major_version: *hf as u8,
+            minor_version: HardFork::LATEST.max(*hf) as u8,
+            timestamp,
+            previous: context.top_hash,
+            nonce: 0,
+        },
+        miner_tx,
+        txs: txs.iter().map(|tx| tx.hash).collect(),
+    };
+
+    let blob = block.serialize();
+    let hashing_blob = block.serialize_hashable();
+
+    // The extra nonce comes after the transaction's public key, its tag and its length.
+    let reserved_offset = blob
+        .windows(32)
+        .position(|window| window == miner_keys.tx_pub_key)
+        .unwrap()
+        + 32
+        + if extra_nonce.is_empty() {
+            0
+        } else {
+            1 + varint_len(extra_nonce.len())
+        };
+
+    Ok(BlockTemplate {
+        block,
+        height,
+        difficulty: context.next_difficulty,
+        reward,
+        fees,
+        weight,
+        blob,
+        hashing_blob,
+        reserved_offset,
+        reserve_size: extra_nonce.len(),
+    })
+}
+
+/// Builds the next block of a chain that doesn't check PoW, like monerod's `generateblocks`.
+///
+/// The block has no extra nonce, a nonce of 0 and is timestamped one block time after
+/// `previous_timestamp`, the timestamp of the top block, so the same chain and pool always give
+/// the same block.
+pub fn generate_block(
+    context: &BlockChainContext,
+    pool: &TxPool,
+    miner_keys: &MinerTxKeys,
+    previous_timestamp: u64,
+) -> Result<BlockTemplate, BlockTemplateError> {
+    let timestamp = previous_timestamp + context.current_hf.block_time().as_secs();
+
+    build_block_template(context, pool, miner_keys, &[], timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use cuprate_common::Network;
+
+    use super::*;
+    use crate::genesis::generate_genesis_block;
+    use crate::test_utils::dummy_context;
+    use crate::tx_extra::{TX_EXTRA_NONCE, TX_EXTRA_TAG_PUBKEY};
+    use crate::txpool::TxPoolConfig;
+
+    fn context(effective_median_weight: usize) -> BlockChainContext {
+        BlockChainContext {
+            adjusted_time: None,
+            effective_median_weight,
+            short_term_median_weight: effective_median_weight,
+            next_block_weight_limit: 2 * effective_median_weight,
+            ..dummy_context(HardFork::V16)
+        }
+    }
+
+    fn pool_tx(hash: u8, weight: usize, fee: u64) -> PoolTx {
+        PoolTx {
+            tx: generate_genesis_block(&Network::Mainnet).miner_tx,
+            hash: [hash; 32],
+            weight,
+            fee,
+            key_images: vec![[hash; 32]],
+            received_at: 0,
+        }
+    }
+
+    /// Returns a template with an 8 byte reserved space whose blob is the header, the miner
+    /// transaction's public key and the reserved space.
+    fn template() -> BlockTemplate {
+        let mut block = generate_genesis_block(&Network::Mainnet);
+        block.header.major_version = 16;
+        block.header.minor_version = 16;
+        block.header.timestamp = 1_000;
+
+        // The header: 2 versions, a 2 byte timestamp, the previous hash and the nonce.
+        let mut blob = [vec![16, 16, 0xe8, 0x07], vec![0; 32], vec![0; 4]].concat();
+        blob.extend([1; 20]);
+        blob.push(TX_EXTRA_TAG_PUBKEY);
+        blob.extend([9; 32]);
+        blob.extend([TX_EXTRA_NONCE, 8]);
+        blob.extend([0; 8]);
+        blob.extend([2; 10]);
+
+        BlockTemplate {
+            block,
+            height: 100,
+            difficulty: 1,
+            reward: 0,
+            fees: 0,
+            weight: 0,
+            reserved_offset: blob.len() - 18,
+            reserve_size: 8,
+            hashing_blob: vec![],
+            blob,
+        }
+    }
+
+    #[test]
+    fn mined_blobs_only_change_the_reserved_space() {
+        let template = template();
+        assert!(template.is_template_of(&template.blob));
+
+        let mut mined = template.blob.clone();
+        mined[36..40].copy_from_slice(&[1, 2, 3, 4]);
+        mined[template.reserved_offset..template.reserved_offset + 8].copy_from_slice(&[5; 8]);
+        assert_eq!(template.check_blob(&mined), Ok(()));
+
+        // Writing a byte past the reserved space.
+        mined[template.reserved_offset + 8] = 5;
+        assert_eq!(
+            template.check_blob(&mined),
+            Err(BlockTemplateError::ReservedSpaceOverrun)
+        );
+        assert_eq!(
+            template.check_blob(&template.blob[1..]),
+            Err(BlockTemplateError::ReservedSpaceOverrun)
+        );
+
+        // A block mined from another template.
+        let mut other = template.blob.clone();
+        other[template.reserved_offset - 20] = 0;
+        assert!(!template.is_template_of(&other));
+    }
+
+    #[test]
+    fn txs_must_pay_for_the_penalty() {
+        let context = context(300_000);
+        let base_reward = context.next_block_base_reward();
+
+        let txs = [
+            pool_tx(1, 200_000, 10_u64.pow(12)),
+            pool_tx(2, 250_000, 10_u64.pow(10)),
+            pool_tx(3, 1_000, 10_u64.pow(9)),
+        ];
+
+        // The second transaction pushes the block over the median and pays less than the penalty.
+        let selected = select_txs(&context, &txs, base_reward);
+        assert_eq!(
+            selected.iter().map(|tx| tx.hash[0]).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+
+        // Under the median every transaction is added.
+        let selected = select_txs(&context, &txs[1..], base_reward);
+        assert_eq!(selected.len(), 2);
+    }
+
+    #[test]
+    fn no_templates_before_v4() {
+        let keys = MinerTxKeys {
+            tx_pub_key: [1; 32],
+            output_key: [2; 32],
+            view_tag: 3,
+        };
+        let pool = TxPool::new(TxPoolConfig::default());
+
+        let mut context = context(300_000);
+        context.current_hf = HardFork::V3;
+        assert_eq!(
+            build_block_template(&context, &pool, &keys, &[], 0).err(),
+            Some(BlockTemplateError::HardForkTooOld)
+        );
+
+        context.current_hf = HardFork::V4;
+        let template = build_block_template(&context, &pool, &keys, &[], 0).unwrap();
+        assert_eq!(template.block.miner_tx.prefix.outputs.len(), 1);
+    }
+}
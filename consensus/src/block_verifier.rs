@@ -0,0 +1,317 @@
+//! # Block Verifier
+//!
+//! This module contains [`verify_block`], the checks of a block before it is added to the main
+//! chain. They are shared by the initial sync, see [`sync`](crate::sync), bootstrap imports, see
+//! [`bootstrap`](crate::bootstrap), and the blocks queued for the verifier, which are added like
+//! synced blocks.
+//!
+//! A block is checked against the checkpoints and the hard-fork rules, its timestamp against the
+//! blocks before it and its miner transaction against its reward and fees. Its PoW is checked on
+//! the [`VerificationPool`](crate::verification_pool::VerificationPool) against the difficulty of
+//! the chain it is added to, unless the [`VerificationProfile`](crate::verifier::VerificationProfile)
+//! trusts the block's height. The inputs of the block's transactions must be unlocked and their key
+//! images unspent, in the chain and in the block. Unless the profile trusts the block's height the
+//! signatures of its transactions, ring signatures or RingCT proofs, are verified on the pool too. The
+//! transactions of a pruned block are checked from their prefixes and the fees in their RingCT
+//! bases, the rest is pruned.
+//!
+//! The checks read earlier blocks from the database: from [`HardFork::V12`] the RandomX seed of a
+//! block is the hash of an earlier block, and transactions can spend the outputs of the blocks just
+//! before them. Callers holding verified blocks to write them in batches must write them first when
+//! [`needs_written_blocks`] says so.
+//!
+use std::collections::HashSet;
+use std::io;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use monero_serai::{
+    block::Block,
+    ringct::{RctBase, RctPrunable, RctSignatures},
+    transaction::{Transaction, TransactionPrefix},
+};
+use tower::ServiceExt;
+
+use crate::{
+    alt_chain::AltChainContextCache,
+    block::{
+        pow::{calculate_pow_hash, difficulty::DifficultyCache, randomx_seed_height},
+        VerifiedBlockInformation, VerifiedBlockTxs,
+    },
+    bootstrap::{read_byte, read_varint},
+    consensus_constants::BLOCK_FUTURE_TIME_LIMIT,
+    context::BlockChainContext,
+    hardforks::HardFork,
+    miner_tx::check_miner_tx,
+    outputs::TxBlob,
+    timings::{BlockTimings, VerificationStage},
+    transactions::{
+        check_ring_members_unlocked, check_tx_version, tx_fee, tx_key_images, verify_tx_signatures,
+    },
+    verifier::Verifier,
+    BlockError, ConsensusError, Database, DatabaseRequest, InternalError, TransactionError,
+};
+
+/// Returns true if the block at `height` can only be verified once the blocks from
+/// `written_height` up are written: it has transactions, which can spend the outputs of those
+/// blocks, or its PoW is checked and from [`HardFork::V12`] its RandomX seed is one of them.
+pub(crate) fn needs_written_blocks(
+    verifier: &Verifier,
+    height: u64,
+    hf: &HardFork,
+    has_txs: bool,
+    written_height: u64,
+) -> bool {
+    let needs_seed = verifier.should_check_pow(height)
+        && *hf >= HardFork::V12
+        && randomx_seed_height(height) >= written_height;
+
+    written_height < height && (has_txs || needs_seed)
+}
+
+/// A block to verify, its transactions have been checked to be the block's.
+pub(crate) struct UnverifiedBlock {
+    pub block: Block,
+    pub block_hash: [u8; 32],
+    /// The block's transactions, not including the miner transaction.
+    pub txs: VerifiedBlockTxs,
+    pub weight: usize,
+}
+
+/// Checks a block against the caches, which it must build on, and adds it to them, returning it
+/// for the database.
+///
+/// The time spent waiting for the PoW is recorded as the block's [`VerificationStage::Pow`].
+pub(crate) async fn verify_block<D: Database + Clone>(
+    verifier: &Verifier,
+    caches: &mut AltChainContextCache,
+    unverified: UnverifiedBlock,
+    mut database: D,
+    timings: &mut BlockTimings,
+) -> Result<VerifiedBlockInformation, ConsensusError> {
+    let UnverifiedBlock {
+        block,
+        block_hash,
+        txs,
+        weight,
+    } = unverified;
+    let height = caches.chain_height;
+    let hf = caches.hard_fork.current_hardfork();
+
+    // Checked again when the block is added to the caches, but the PoW of a stale block isn't
+    // worth calculating.
+    if block.header.previous != caches.top_hash {
+        return Err(BlockError::DoesNotExtendChain {
+            previous: block.header.previous,
+            top_hash: caches.top_hash,
+        }
+        .into());
+    }
+    verifier.check_checkpoint(height, &block_hash)?;
+    let hf_info = caches.hard_fork.block_hf_info(&block.header)?;
+    caches.hard_fork.check_block_version_vote(&hf_info)?;
+    check_timestamp(block.header.timestamp, &caches.difficulty, height)?;
+
+    let pow = if verifier.should_check_pow(height) {
+        let randomx_seed = if hf >= HardFork::V12 {
+            database
+                .ready()
+                .await?
+                .call(DatabaseRequest::BlockHash(randomx_seed_height(height)))
+                .await?
+                .into_block_hash()?
+        } else {
+            [0; 32]
+        };
+
+        let randomx = verifier.randomx_caches().clone();
+        let hashing_blob = block.serialize_hashable();
+        Some(verifier.verification_pool().check_pow(
+            move || calculate_pow_hash(&randomx, &hashing_blob, height, &hf, &randomx_seed),
+            caches.difficulty.next_difficulty(&hf),
+        ))
+    } else {
+        None
+    };
+
+    // The transactions are checked while the PoW is calculated.
+    let pruned_txs;
+    let checked_txs = match &txs {
+        VerifiedBlockTxs::Full(txs) => txs,
+        VerifiedBlockTxs::Pruned(blobs) => {
+            pruned_txs = blobs
+                .iter()
+                .map(pruned_tx)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| BlockError::InvalidBlob { height })?;
+            &pruned_txs
+        }
+    };
+    let context = BlockChainContext::from_caches(
+        verifier.network(),
+        height,
+        caches.top_hash,
+        caches.already_generated_coins,
+        &caches.block_weight,
+        &caches.difficulty,
+        &caches.hard_fork,
+    );
+    let fees = check_block_txs(checked_txs, height, &context, database.clone()).await?;
+    // Pruned blocks are only accepted for heights where signatures aren't checked.
+    if verifier.should_check_signatures(height) {
+        verify_tx_signatures(
+            checked_txs,
+            &hf,
+            database.clone(),
+            verifier.verification_pool(),
+            timings,
+        )
+        .await?;
+    }
+
+    let reward = caches.next_block_generated_coins(weight)?;
+    let generated_coins = check_miner_tx(&block.miner_tx, height, &hf, reward, fees)
+        .map_err(|source| BlockError::InvalidMinerTx { height, source })?;
+
+    let pow_hash = match pow {
+        Some(pow) => {
+            let started = Instant::now();
+            let pow_hash = pow.await;
+            timings.record(VerificationStage::Pow, started.elapsed());
+
+            pow_hash
+                .map_err(InternalError::RandomX)?
+                .ok_or(BlockError::InvalidPow { height })?
+        }
+        // The PoW hash of a trusted block is not calculated.
+        None => [0; 32],
+    };
+
+    let long_term_weight = caches.block_weight.next_block_long_term_weight(&hf, weight);
+    let coins_before = caches.already_generated_coins;
+    let started = Instant::now();
+    caches
+        .add_block_with_generated_coins(&block, block_hash, weight, generated_coins, database)
+        .await?;
+    timings.record(VerificationStage::ContextUpdate, started.elapsed());
+
+    Ok(VerifiedBlockInformation {
+        block,
+        txs,
+        block_hash,
+        pow_hash,
+        height,
+        generated_coins: caches.already_generated_coins - coins_before,
+        weight,
+        long_term_weight,
+        cumulative_difficulty: caches.cumulative_difficulty(),
+    })
+}
+
+/// Checks the block's timestamp is not below the median of the blocks before it, see
+/// [`DifficultyCache::median_timestamp`], or more than [`BLOCK_FUTURE_TIME_LIMIT`] ahead of our
+/// clock.
+fn check_timestamp(
+    timestamp: u64,
+    difficulty: &DifficultyCache,
+    height: u64,
+) -> Result<(), BlockError> {
+    let limit = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + BLOCK_FUTURE_TIME_LIMIT;
+    if timestamp > limit {
+        return Err(BlockError::TimestampInFuture {
+            height,
+            timestamp,
+            limit,
+        });
+    }
+
+    match difficulty.median_timestamp() {
+        Some(median) if timestamp < median => Err(BlockError::TimestampTooEarly {
+            height,
+            timestamp,
+            median,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the transaction of a pruned block with what is checked of it: its prefix and, for a
+/// version 2 transaction, the fee from its RingCT base. The signatures are left empty.
+fn pruned_tx(tx: &TxBlob) -> io::Result<Transaction> {
+    let mut blob = tx.blob.as_ref();
+    if tx.prunable_hash.is_none() {
+        // Version 1 transactions are sent complete.
+        return Transaction::read(&mut blob);
+    }
+
+    let prefix = TransactionPrefix::read(&mut blob)?;
+    // The fee follows the RingCT type, transactions without RingCT signatures have no fee.
+    let fee = match read_byte(&mut blob)? {
+        0 => 0,
+        _ => read_varint(&mut blob)?,
+    };
+
+    Ok(Transaction {
+        prefix,
+        signatures: vec![],
+        rct_signatures: RctSignatures {
+            base: RctBase {
+                fee,
+                pseudo_outs: vec![],
+                encrypted_amounts: vec![],
+                commitments: vec![],
+            },
+            prunable: RctPrunable::Null,
+        },
+    })
+}
+
+/// Checks the inputs of a block's transactions against the chain, returning the transactions'
+/// fees.
+///
+/// Every transaction must be allowed at the hard-fork and spend key images unspent in the chain and
+/// in the block, with ring members that can be spent in the block.
+async fn check_block_txs<D: Database + Clone>(
+    txs: &[Transaction],
+    height: u64,
+    context: &BlockChainContext,
+    database: D,
+) -> Result<u64, ConsensusError> {
+    if txs.is_empty() {
+        return Ok(0);
+    }
+
+    let mut key_images = HashSet::new();
+    let mut fees = 0_u64;
+    for tx in txs {
+        check_tx_version(tx, &context.current_hf)?;
+        for key_image in tx_key_images(tx)? {
+            if !key_images.insert(key_image) {
+                return Err(BlockError::DoubleSpend { height }.into());
+            }
+        }
+        fees = fees
+            .checked_add(tx_fee(tx)?)
+            .ok_or(TransactionError::AmountOverflow)?;
+    }
+
+    let spent = database
+        .clone()
+        .oneshot(DatabaseRequest::KeyImagesSpent(
+            key_images.into_iter().collect(),
+        ))
+        .await?
+        .into_key_images_spent()?;
+    if spent {
+        return Err(BlockError::DoubleSpend { height }.into());
+    }
+
+    for tx in txs {
+        check_ring_members_unlocked(tx, context, database.clone()).await?;
+    }
+
+    Ok(fees)
+}
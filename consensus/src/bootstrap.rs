@@ -0,0 +1,801 @@
+//! # Bootstrap Files
+//!
+//! This module reads and writes monerod's `blockchain.raw` bootstrap files, the files made by
+//! `monero-blockchain-export` and read by `monero-blockchain-import`, so a node can be bootstrapped
+//! from a monerod data dump and our chain can be exported for monerod.
+//!
+//! A file is the magic number [`BLOCKCHAIN_RAW_MAGIC`], a [`HEADER_SIZE`] byte header and then a
+//! chunk for every block from the genesis block up. The header holds monerod's `file_info` and
+//! `blocks_info`, each after its size, and is padded with zeros. A chunk is its size followed by a
+//! [`BlockPackage`]. Every size is 4 little endian bytes.
+//!
+//! [`import_blocks`] adds the blocks of a file on top of a [`Verifier`]'s chain and writes them with
+//! a [`BlockWriter`], how much is checked is set by the [`ImportMode`]. [`export_blocks`] and
+//! [`export_chain`] write the blocks of a database to a file.
+//!
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::time::Instant;
+
+use monero_serai::{block::Block, transaction::Transaction};
+use tower::ServiceExt;
+
+use crate::{
+    alt_chain::AltChainContextCache,
+    block::{weight::block_weight, VerifiedBlockInformation, VerifiedBlockTxs},
+    block_verifier::{needs_written_blocks, verify_block, UnverifiedBlock},
+    genesis::genesis_hash,
+    misbehaviour::BlockSource,
+    spans::BLOCK_TARGET,
+    timings::{BatchTimings, BlockTimings, VerificationStage},
+    verifier::Verifier,
+    write_batch::{BlockWriter, WriteBatchConfig},
+    ConsensusError, Database, DatabaseRequest, DatabaseResponse,
+};
+
+/// The magic number at the start of a `blockchain.raw` file.
+pub const BLOCKCHAIN_RAW_MAGIC: u32 = 0x2872_1586;
+/// The size of the header after the magic number, monerod always writes a 1024 byte header.
+///
+/// The header size in the file's `file_info` counts from the end of the magic number.
+pub const HEADER_SIZE: u32 = 1024;
+/// The biggest chunk monerod reads, a chunk holds a single block.
+pub const MAX_CHUNK_SIZE: u32 = 1_000_000;
+
+/// The version of the files monerod writes.
+const MAJOR_VERSION: u8 = 0;
+const MINOR_VERSION: u8 = 1;
+
+/// The amount of blocks read from the database at a time by [`export_blocks`].
+const EXPORT_CHUNK_SIZE: u64 = 100;
+/// The amount of blocks between the progress logs of [`import_blocks`].
+const IMPORT_PROGRESS_INTERVAL: u64 = 1_000;
+
+/// An error reading, writing or importing a bootstrap file.
+#[derive(Debug, thiserror::Error)]
+pub enum BootstrapError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("The file starts with {0:#010x}, not the magic number of a blockchain.raw file")]
+    BadMagic(u32),
+    #[error("Version {major}.{minor} bootstrap files are not supported")]
+    UnsupportedVersion { major: u8, minor: u8 },
+    #[error("The header size {0} is too small")]
+    InvalidHeader(u64),
+    #[error("The chunk of block {height} is {size} bytes, over the {MAX_CHUNK_SIZE} byte limit")]
+    ChunkTooBig { height: u64, size: u64 },
+    #[error("The chunk of block {height} can't be parsed: {source}")]
+    InvalidPackage { height: u64, source: io::Error },
+    /// The file is of another network.
+    #[error(
+        "The file's genesis block is {}, not the genesis block of the verifier's network",
+        hex::encode(.0)
+    )]
+    GenesisMismatch([u8; 32]),
+    /// A field of a block's package doesn't match the chain, the file is corrupt.
+    #[error("The {field} of block {height} in the file does not match the chain")]
+    PackageMismatch { height: u64, field: &'static str },
+    #[error("Block {height} is invalid: {source}")]
+    InvalidBlock { height: u64, source: ConsensusError },
+    #[error("{0}")]
+    Consensus(#[from] ConsensusError),
+}
+
+impl From<tower::BoxError> for BootstrapError {
+    fn from(e: tower::BoxError) -> Self {
+        ConsensusError::from(e).into()
+    }
+}
+
+/// A block in a bootstrap file, with the data monerod needs to add it without recomputing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockPackage {
+    pub block: Block,
+    /// The block's transactions, in the order of the block's `txs`.
+    pub txs: Vec<Transaction>,
+    pub block_weight: usize,
+    /// The cumulative difficulty of the chain up to and including this block.
+    pub cumulative_difficulty: u128,
+    /// The total amount of coins generated up to and including this block.
+    pub coins_generated: u64,
+}
+
+impl BlockPackage {
+    /// Writes the package as monerod's `block_package`.
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.block.write(w)?;
+        write_varint(w, self.txs.len() as u64)?;
+        for tx in &self.txs {
+            tx.write(w)?;
+        }
+        write_varint(w, self.block_weight as u64)?;
+        // A difficulty is written as its high then its low 64 bits.
+        write_varint(w, (self.cumulative_difficulty >> 64) as u64)?;
+        write_varint(w, self.cumulative_difficulty as u64)?;
+        write_varint(w, self.coins_generated)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut blob = Vec::new();
+        self.write(&mut blob).unwrap();
+        blob
+    }
+
+    /// Reads a package written by [`BlockPackage::write`].
+    pub fn read<R: Read>(r: &mut R) -> io::Result<BlockPackage> {
+        let block = Block::read(r)?;
+        let tx_count = read_varint(r)?;
+        let txs = (0..tx_count)
+            .map(|_| Transaction::read(r))
+            .collect::<io::Result<_>>()?;
+        let block_weight = usize::try_from(read_varint(r)?).map_err(invalid_data)?;
+        let high = read_varint(r)?;
+        let low = read_varint(r)?;
+        let coins_generated = read_varint(r)?;
+
+        Ok(BlockPackage {
+            block,
+            txs,
+            block_weight,
+            cumulative_difficulty: (u128::from(high) << 64) | u128::from(low),
+            coins_generated,
+        })
+    }
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+pub(crate) fn read_byte<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    r.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+pub(crate) fn read_varint<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut number = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_byte(r)?;
+        number |= u64::from(byte & 0x7f) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(number);
+        }
+    }
+    Err(invalid_data("varint over 64 bits"))
+}
+
+fn write_varint<W: Write>(w: &mut W, mut number: u64) -> io::Result<()> {
+    while number >= 0x80 {
+        w.write_all(&[(number as u8 & 0x7f) | 0x80])?;
+        number >>= 7;
+    }
+    w.write_all(&[number as u8])
+}
+
+/// Reads a blob after its size, the header's `file_info` and `blocks_info` are written like this.
+fn read_sized<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut size = [0; 4];
+    r.read_exact(&mut size)?;
+    let size = u32::from_le_bytes(size);
+    if size > HEADER_SIZE {
+        return Err(invalid_data("header field bigger than the header"));
+    }
+
+    let mut blob = vec![0; size as usize];
+    r.read_exact(&mut blob)?;
+    Ok(blob)
+}
+
+fn write_sized<W: Write>(w: &mut W, blob: &[u8]) -> io::Result<()> {
+    w.write_all(&(blob.len() as u32).to_le_bytes())?;
+    w.write_all(blob)
+}
+
+/// Reads the blocks of a bootstrap file, in order from the genesis block.
+pub struct RawReader<R> {
+    reader: R,
+    /// The height of the next block.
+    height: u64,
+}
+
+impl<R: Read> RawReader<R> {
+    /// Reads the file's header, returning a reader of its blocks.
+    pub fn new(mut reader: R) -> Result<RawReader<R>, BootstrapError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        let magic = u32::from_le_bytes(magic);
+        if magic != BLOCKCHAIN_RAW_MAGIC {
+            return Err(BootstrapError::BadMagic(magic));
+        }
+
+        // monerod's `file_info`: the versions then the header size.
+        let file_info = read_sized(&mut reader)?;
+        let mut file_info_reader = file_info.as_slice();
+        let major = read_byte(&mut file_info_reader)?;
+        let minor = read_byte(&mut file_info_reader)?;
+        if major != MAJOR_VERSION {
+            return Err(BootstrapError::UnsupportedVersion { major, minor });
+        }
+        let header_size = read_varint(&mut file_info_reader)?;
+
+        // The `blocks_info` and the padding are skipped, monerod doesn't read them either.
+        let read = 4 + file_info.len() as u64;
+        let rest = header_size
+            .checked_sub(read)
+            .ok_or(BootstrapError::InvalidHeader(header_size))?;
+        let skipped = io::copy(&mut (&mut reader).take(rest), &mut io::sink())?;
+        if skipped != rest {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(RawReader { reader, height: 0 })
+    }
+
+    /// Returns the height of the next block.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Reads the next chunk, [`None`] at the end of the file.
+    fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, BootstrapError> {
+        let mut size = [0; 4];
+        let mut read = 0;
+        while read < size.len() {
+            match self.reader.read(&mut size[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let size = u32::from_le_bytes(size);
+        if size > MAX_CHUNK_SIZE {
+            return Err(BootstrapError::ChunkTooBig {
+                height: self.height,
+                size: size.into(),
+            });
+        }
+
+        let mut chunk = vec![0; size as usize];
+        self.reader.read_exact(&mut chunk)?;
+        Ok(Some(chunk))
+    }
+
+    /// Reads the next block, [`None`] at the end of the file.
+    pub fn next_package(&mut self) -> Result<Option<BlockPackage>, BootstrapError> {
+        let Some(chunk) = self.next_chunk()? else {
+            return Ok(None);
+        };
+
+        let height = self.height;
+        let mut reader = chunk.as_slice();
+        let package = BlockPackage::read(&mut reader)
+            .and_then(|package| match reader.is_empty() {
+                true => Ok(package),
+                false => Err(invalid_data("bytes after the block package")),
+            })
+            .map_err(|source| BootstrapError::InvalidPackage { height, source })?;
+
+        self.height += 1;
+        Ok(Some(package))
+    }
+
+    /// Skips the next block without parsing it, returns false at the end of the file.
+    pub fn skip_package(&mut self) -> Result<bool, BootstrapError> {
+        let skipped = self.next_chunk()?.is_some();
+        if skipped {
+            self.height += 1;
+        }
+        Ok(skipped)
+    }
+}
+
+/// Writes the blocks of a bootstrap file, the first block written must be the genesis block.
+pub struct RawWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> RawWriter<W> {
+    /// Writes the file's header, returning a writer of its blocks.
+    pub fn new(mut writer: W) -> io::Result<RawWriter<W>> {
+        writer.write_all(&BLOCKCHAIN_RAW_MAGIC.to_le_bytes())?;
+
+        let mut file_info = vec![MAJOR_VERSION, MINOR_VERSION];
+        write_varint(&mut file_info, HEADER_SIZE.into())?;
+        // The `blocks_info`: the first block, last block and position of the last block, monerod
+        // writes these as 0 and doesn't read them.
+        let blocks_info = [0; 3];
+
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        write_sized(&mut header, &file_info)?;
+        write_sized(&mut header, &blocks_info)?;
+        header.resize(HEADER_SIZE as usize, 0);
+        writer.write_all(&header)?;
+
+        Ok(RawWriter { writer })
+    }
+
+    /// Writes the next block.
+    pub fn write_package(&mut self, package: &BlockPackage) -> Result<(), BootstrapError> {
+        let chunk = package.serialize();
+        let size = u32::try_from(chunk.len())
+            .ok()
+            .filter(|size| *size <= MAX_CHUNK_SIZE)
+            .ok_or(BootstrapError::ChunkTooBig {
+                height: package.block.number() as u64,
+                size: chunk.len() as u64,
+            })?;
+
+        self.writer.write_all(&size.to_le_bytes())?;
+        self.writer.write_all(&chunk)?;
+        Ok(())
+    }
+
+    /// Flushes the file, returning the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// How much of each imported block is checked, see [`import_blocks`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Verify each block like a synced block: its PoW, timestamp, miner transaction and the inputs
+    /// and signatures of its transactions, unless the verifier's profile trusts its height.
+    #[default]
+    Verify,
+    /// Trust the file, only check the blocks build on each other, like monerod's
+    /// `--dangerous-unverified-import`. Only use this for a file from a trusted source.
+    Trusted,
+}
+
+/// The blocks handled by [`import_blocks`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// The blocks of the file already in the chain.
+    pub skipped: u64,
+    /// The blocks added to the chain.
+    pub imported: u64,
+}
+
+/// Adds the blocks of a bootstrap file on top of the verifier's chain and writes them to the
+/// database.
+///
+/// The blocks of the file already in the chain are skipped, so an import can be resumed. With
+/// [`ImportMode::Verify`] each block is verified like a synced block, see
+/// [`add_synced_blocks`](crate::sync::add_synced_blocks), and the transactions, weight, cumulative
+/// difficulty and generated coins the file gives it are checked against the chain.
+///
+/// An invalid block is reported with [`Verifier::report_failure`] as from [`BlockSource::Import`].
+/// On an error the blocks before the failing block are written but the verifier is left as it was,
+/// it should be initialized from the database again.
+pub async fn import_blocks<R: Read, D: Database + Clone>(
+    reader: &mut RawReader<R>,
+    verifier: &mut Verifier,
+    mode: ImportMode,
+    mut database: D,
+    config: WriteBatchConfig,
+) -> Result<ImportReport, BootstrapError> {
+    let chain_height = verifier.context().chain_height;
+    let mut report = ImportReport::default();
+
+    // The genesis block is checked so the blocks of another network are not added on top of ours.
+    if reader.height() == 0 {
+        let Some(genesis) = reader.next_package()? else {
+            return Ok(report);
+        };
+        let hash = genesis.block.hash();
+        if hash != genesis_hash(&verifier.network()) {
+            return Err(BootstrapError::GenesisMismatch(hash));
+        }
+        report.skipped += 1;
+    }
+    while reader.height() < chain_height {
+        if !reader.skip_package()? {
+            return Ok(report);
+        }
+        report.skipped += 1;
+    }
+
+    tracing::info!(
+        target: BLOCK_TARGET,
+        height = chain_height,
+        mode = ?mode,
+        "Importing blocks from a bootstrap file"
+    );
+
+    let mut caches = verifier.main_chain_caches();
+    let mut writer = BlockWriter::new(database.clone(), config);
+    // The blocks held by the writer, they are announced once they are written.
+    let mut unannounced = Vec::new();
+    let mut first_unannounced = chain_height;
+    let mut batch_timings = BatchTimings::new(chain_height);
+
+    let res = async {
+        while let Some(package) = reader.next_package()? {
+            let height = caches.chain_height;
+            let written_height = height - writer.held_blocks() as u64;
+            if mode == ImportMode::Verify
+                && needs_written_blocks(
+                    verifier,
+                    height,
+                    &caches.hard_fork.current_hardfork(),
+                    !package.txs.is_empty(),
+                    written_height,
+                )
+                && writer.flush().await? != 0
+            {
+                verifier.announce_blocks(first_unannounced, &unannounced);
+                first_unannounced += unannounced.len() as u64;
+                unannounced.clear();
+            }
+
+            let mut timings = BlockTimings::default();
+            let block = import_block(
+                verifier,
+                &mut caches,
+                package,
+                mode,
+                database.clone(),
+                &mut timings,
+            )
+            .await
+            .inspect_err(|e| {
+                if let BootstrapError::InvalidBlock { source, .. } = e {
+                    verifier.report_failure(BlockSource::Import, source);
+                }
+            })?;
+            unannounced.push(block.block.clone());
+            // The end of the file isn't known, so the blocks are written in batches until the end.
+            let started = Instant::now();
+            let written = writer.write(block, u64::MAX).await;
+            timings.record(VerificationStage::DatabaseWrite, started.elapsed());
+            if written? != 0 {
+                verifier.announce_blocks(first_unannounced, &unannounced);
+                first_unannounced += unannounced.len() as u64;
+                unannounced.clear();
+            }
+
+            verifier.record_block_timings(&timings);
+            batch_timings.add_block(height, &timings);
+
+            report.imported += 1;
+            if report.imported % IMPORT_PROGRESS_INTERVAL == 0 {
+                tracing::info!(target: BLOCK_TARGET, height, "Imported {} blocks", report.imported);
+                std::mem::replace(&mut batch_timings, BatchTimings::new(height + 1)).finish();
+            }
+        }
+        Ok::<_, BootstrapError>(())
+    }
+    .await;
+    let flushed = writer.flush().await;
+    if report.imported % IMPORT_PROGRESS_INTERVAL != 0 {
+        batch_timings.finish();
+    }
+    if matches!(flushed, Ok(written) if written != 0) {
+        verifier.announce_blocks(first_unannounced, &unannounced);
+    }
+
+    res?;
+    flushed?;
+    verifier
+        .cross_check_database(&caches, &mut database)
+        .await?;
+    verifier.extend_main_chain(caches);
+
+    Ok(report)
+}
+
+/// Checks a block as set by the mode and adds it to the caches, returning it for the database.
+async fn import_block<D: Database + Clone>(
+    verifier: &Verifier,
+    caches: &mut AltChainContextCache,
+    package: BlockPackage,
+    mode: ImportMode,
+    database: D,
+    timings: &mut BlockTimings,
+) -> Result<VerifiedBlockInformation, BootstrapError> {
+    let height = caches.chain_height;
+    let invalid = |source: ConsensusError| BootstrapError::InvalidBlock { height, source };
+    let mismatch = |field| BootstrapError::PackageMismatch { height, field };
+
+    let block_hash = package.block.hash();
+    let hf = caches.hard_fork.current_hardfork();
+
+    if mode == ImportMode::Trusted {
+        let weight = package.block_weight;
+        let long_term_weight = caches.block_weight.next_block_long_term_weight(&hf, weight);
+        let coins_before = caches.already_generated_coins;
+        let started = Instant::now();
+        caches
+            .add_block_with_hash(&package.block, block_hash, weight, database)
+            .await
+            .map_err(invalid)?;
+        timings.record(VerificationStage::ContextUpdate, started.elapsed());
+
+        return Ok(VerifiedBlockInformation {
+            block: package.block,
+            txs: VerifiedBlockTxs::Full(package.txs),
+            block_hash,
+            // The PoW is not checked in a trusted import.
+            pow_hash: [0; 32],
+            height,
+            generated_coins: caches.already_generated_coins - coins_before,
+            weight,
+            long_term_weight,
+            cumulative_difficulty: caches.cumulative_difficulty(),
+        });
+    }
+
+    if package.txs.len() != package.block.txs.len()
+        || package
+            .txs
+            .iter()
+            .zip(&package.block.txs)
+            .any(|(tx, hash)| tx.hash() != *hash)
+    {
+        return Err(mismatch("transactions"));
+    }
+
+    let weight = block_weight(&package.block, &package.txs);
+    if weight != package.block_weight {
+        return Err(mismatch("weight"));
+    }
+
+    let block = verify_block(
+        verifier,
+        caches,
+        UnverifiedBlock {
+            block: package.block,
+            block_hash,
+            txs: VerifiedBlockTxs::Full(package.txs),
+            weight,
+        },
+        database,
+        timings,
+    )
+    .await
+    .map_err(invalid)?;
+
+    if block.cumulative_difficulty != package.cumulative_difficulty {
+        return Err(mismatch("cumulative difficulty"));
+    }
+    if caches.already_generated_coins != package.coins_generated {
+        return Err(mismatch("generated coins"));
+    }
+
+    Ok(block)
+}
+
+/// Writes the blocks in `range` of the database to a bootstrap file, returning the amount written.
+///
+/// monerod can only import a file that starts at the genesis block.
+pub async fn export_blocks<W: Write, D: Database + Clone>(
+    writer: &mut RawWriter<W>,
+    mut database: D,
+    range: Range<u64>,
+) -> Result<u64, BootstrapError> {
+    let mut exported = 0;
+
+    let end = range.end;
+    for chunk in range
+        .step_by(EXPORT_CHUNK_SIZE as usize)
+        .map(|start| start..(start + EXPORT_CHUNK_SIZE).min(end))
+    {
+        let blocks = request(
+            &mut database,
+            DatabaseRequest::BlockBatchInRange(chunk.clone()),
+        )
+        .await?
+        .into_block_batch_in_range()
+        .map_err(ConsensusError::from)?;
+        let weights = request(
+            &mut database,
+            DatabaseRequest::BlockWeightsInRange(chunk.clone()),
+        )
+        .await?
+        .into_block_weights_in_range()
+        .map_err(ConsensusError::from)?;
+        let pow_infos = request(
+            &mut database,
+            DatabaseRequest::BlockPOWInfoInRange(chunk.clone()),
+        )
+        .await?
+        .into_block_pow_info_in_range()
+        .map_err(ConsensusError::from)?;
+
+        for (height, ((block, weights), pow_info)) in
+            chunk.zip(blocks.into_iter().zip(weights).zip(pow_infos))
+        {
+            let txs = match block.txs.is_empty() {
+                true => vec![],
+                false => request(
+                    &mut database,
+                    DatabaseRequest::Transactions(block.txs.clone()),
+                )
+                .await?
+                .into_transactions()
+                .map_err(ConsensusError::from)?,
+            };
+            let coins_generated = request(&mut database, DatabaseRequest::GeneratedCoins(height))
+                .await?
+                .into_generated_coins()
+                .map_err(ConsensusError::from)?;
+
+            writer.write_package(&BlockPackage {
+                block,
+                txs,
+                block_weight: weights.block_weight,
+                cumulative_difficulty: pow_info.cumulative_difficulty,
+                coins_generated,
+            })?;
+            exported += 1;
+        }
+    }
+
+    Ok(exported)
+}
+
+/// Writes the whole chain of the database to a bootstrap file, returning the amount of blocks
+/// written, see [`export_blocks`].
+pub async fn export_chain<W: Write, D: Database + Clone>(
+    writer: &mut RawWriter<W>,
+    mut database: D,
+) -> Result<u64, BootstrapError> {
+    let chain_height = request(&mut database, DatabaseRequest::ChainHeight)
+        .await?
+        .into_chain_height()
+        .map_err(ConsensusError::from)?;
+
+    export_blocks(writer, database, 0..chain_height).await
+}
+
+async fn request<D: Database>(
+    database: &mut D,
+    req: DatabaseRequest,
+) -> Result<DatabaseResponse, BootstrapError> {
+    Ok(database.ready().await?.call(req).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use cuprate_common::Network;
+
+    use super::*;
+    use crate::genesis::generate_genesis_block;
+
+    /// The start of a file written by `monero-blockchain-export`: the magic number, the size of the
+    /// `file_info` and the `file_info`, then the size of the `blocks_info` and the `blocks_info`.
+    /// The rest of the 1024 byte header is zeros.
+    const MONEROD_HEADER_START: &str = "86157228040000000001800803000000000000";
+
+    fn header() -> Vec<u8> {
+        RawWriter::new(Vec::new()).unwrap().finish().unwrap()
+    }
+
+    fn monerod_header() -> Vec<u8> {
+        let mut file = hex::decode(MONEROD_HEADER_START).unwrap();
+        file.resize(4 + HEADER_SIZE as usize, 0);
+        file
+    }
+
+    #[test]
+    fn monerod_header_is_read() {
+        let mut file = monerod_header();
+        // The mainnet genesis block, the first chunk of a mainnet export.
+        let package = BlockPackage {
+            block: generate_genesis_block(&Network::Mainnet),
+            txs: vec![],
+            block_weight: 80,
+            cumulative_difficulty: 1,
+            coins_generated: 17_592_186_044_415,
+        };
+        let chunk = package.serialize();
+        file.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        file.extend_from_slice(&chunk);
+
+        let mut reader = RawReader::new(file.as_slice()).unwrap();
+        assert_eq!(reader.next_package().unwrap(), Some(package));
+        assert!(reader.next_package().unwrap().is_none());
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let file = header();
+        assert_eq!(file, monerod_header());
+
+        let mut reader = RawReader::new(file.as_slice()).unwrap();
+        assert_eq!(reader.height(), 0);
+        assert!(reader.next_package().unwrap().is_none());
+        assert!(!reader.skip_package().unwrap());
+    }
+
+    #[test]
+    fn invalid_files() {
+        let mut file = header();
+        file[0] = 0;
+        assert!(matches!(
+            RawReader::new(file.as_slice()),
+            Err(BootstrapError::BadMagic(0x2872_1500))
+        ));
+
+        let mut file = header();
+        file[8] = 1;
+        assert!(matches!(
+            RawReader::new(file.as_slice()),
+            Err(BootstrapError::UnsupportedVersion { major: 1, minor: 1 })
+        ));
+
+        let mut file = header();
+        file.extend_from_slice(&(MAX_CHUNK_SIZE + 1).to_le_bytes());
+        let mut reader = RawReader::new(file.as_slice()).unwrap();
+        assert!(matches!(
+            reader.next_package(),
+            Err(BootstrapError::ChunkTooBig { height: 0, .. })
+        ));
+
+        let mut file = header();
+        file.extend_from_slice(&10_u32.to_le_bytes());
+        file.extend_from_slice(&[1, 2, 3]);
+        let mut reader = RawReader::new(file.as_slice()).unwrap();
+        assert!(matches!(
+            reader.next_package(),
+            Err(BootstrapError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+
+        let mut file = header();
+        file.extend_from_slice(&[1, 0]);
+        let mut reader = RawReader::new(file.as_slice()).unwrap();
+        assert!(reader.next_package().is_err());
+    }
+
+    #[test]
+    fn chunks_are_skipped() {
+        let mut file = header();
+        for chunk in [&[1, 2, 3][..], &[4]] {
+            file.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            file.extend_from_slice(chunk);
+        }
+
+        let mut reader = RawReader::new(file.as_slice()).unwrap();
+        assert!(reader.skip_package().unwrap());
+        assert!(reader.skip_package().unwrap());
+        assert!(!reader.skip_package().unwrap());
+        assert_eq!(reader.height(), 2);
+    }
+
+    #[test]
+    fn varints() {
+        for number in [0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX] {
+            let mut blob = Vec::new();
+            write_varint(&mut blob, number).unwrap();
+            assert_eq!(read_varint(&mut blob.as_slice()).unwrap(), number);
+        }
+        assert!(read_varint(&mut [0xff; 10].as_slice()).is_err());
+    }
+
+    #[test]
+    fn package_round_trip() {
+        let package = BlockPackage {
+            block: generate_genesis_block(&Network::Mainnet),
+            txs: vec![],
+            block_weight: 80,
+            cumulative_difficulty: (3 << 64) | 1,
+            coins_generated: 17_592_186_044_415,
+        };
+
+        let mut file = RawWriter::new(Vec::new()).unwrap();
+        file.write_package(&package).unwrap();
+        let file = file.finish().unwrap();
+
+        let mut reader = RawReader::new(file.as_slice()).unwrap();
+        assert_eq!(reader.next_package().unwrap(), Some(package));
+        assert_eq!(reader.height(), 1);
+        assert!(reader.next_package().unwrap().is_none());
+    }
+}
@@ -0,0 +1,166 @@
+//! # Checkpoints
+//!
+//! This module contains the hard-coded block hash checkpoints for each network. Blocks at a checkpoint
+//! height must have the checkpoint's hash.
+//!
+//! As every block below the highest checkpoint is fixed, the verifier can skip the expensive checks
+//! (PoW and signatures) for them, like monerod's `--fast-block-sync`.
+//!
+//! The checkpoints are taken from monerod's `src/checkpoints/checkpoints.cpp`, the lists in
+//! `checkpoints/` keep its `ADD_CHECKPOINT` lines so they can be copied from it as they are.
+//!
+//! `checkpoints/mainnet.txt` only has monerod's checkpoints up to height 232150, the rest of
+//! monerod's list has not been copied in. Until it is, the mainnet checkpoint zone, and so the blocks
+//! fast sync trusts, ends at that height.
+//!
+use std::collections::BTreeMap;
+
+use cuprate_common::Network;
+
+use crate::{spans::BLOCK_TARGET, BlockError, ConsensusError};
+
+const MAINNET_CHECKPOINTS: &str = include_str!("checkpoints/mainnet.txt");
+const TESTNET_CHECKPOINTS: &str = include_str!("checkpoints/testnet.txt");
+const STAGENET_CHECKPOINTS: &str = include_str!("checkpoints/stagenet.txt");
+
+/// Parses a list of checkpoints in monerod's format, one `ADD_CHECKPOINT(height, "hash");` or
+/// `ADD_CHECKPOINT2(height, "hash", "difficulty");` per line. Empty lines and `//` comments are
+/// skipped, the difficulties are not used.
+///
+/// # Panics
+/// Panics if a line is not a checkpoint, the lists are hard-coded.
+fn parse_checkpoint_list(list: &str) -> BTreeMap<u64, [u8; 32]> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("//"))
+        .map(|line| {
+            let mut args = line
+                .strip_prefix("ADD_CHECKPOINT2(")
+                .or_else(|| line.strip_prefix("ADD_CHECKPOINT("))
+                .and_then(|args| args.strip_suffix(");"))
+                .unwrap_or_else(|| panic!("Invalid checkpoint line: {line}"))
+                .split(',')
+                .map(|arg| arg.trim().trim_matches('"'));
+
+            let height = args
+                .next()
+                .and_then(|height| height.parse().ok())
+                .unwrap_or_else(|| panic!("Invalid checkpoint height: {line}"));
+            let hash = args
+                .next()
+                .and_then(|hash| hex::decode(hash).ok())
+                .and_then(|hash| hash.try_into().ok())
+                .unwrap_or_else(|| panic!("Invalid checkpoint hash: {line}"));
+            (height, hash)
+        })
+        .collect()
+}
+
+/// A set of block hash checkpoints.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checkpoints {
+    checkpoints: BTreeMap<u64, [u8; 32]>,
+}
+
+impl Checkpoints {
+    /// Returns the hard-coded checkpoints for this network.
+    pub fn for_network(network: &Network) -> Checkpoints {
+        let list = match network {
+            Network::Mainnet => MAINNET_CHECKPOINTS,
+            Network::Testnet => TESTNET_CHECKPOINTS,
+            Network::Stagenet => STAGENET_CHECKPOINTS,
+            Network::Regtest => "",
+        };
+
+        Checkpoints {
+            checkpoints: parse_checkpoint_list(list),
+        }
+    }
+
+    /// Adds a checkpoint, replacing any checkpoint already at this height.
+    pub fn add_checkpoint(&mut self, height: u64, hash: [u8; 32]) {
+        self.checkpoints.insert(height, hash);
+    }
+
+    /// Returns the checkpointed hash at this height, if there is one.
+    pub fn checkpoint_at(&self, height: u64) -> Option<&[u8; 32]> {
+        self.checkpoints.get(&height)
+    }
+
+    /// Returns the height of the highest checkpoint, or 0 if there are none.
+    pub fn top_checkpoint_height(&self) -> u64 {
+        self.checkpoints.keys().next_back().copied().unwrap_or(0)
+    }
+
+    /// Returns true if the block at this height is at or below the highest checkpoint.
+    pub fn is_in_checkpoint_zone(&self, height: u64) -> bool {
+        !self.checkpoints.is_empty() && height <= self.top_checkpoint_height()
+    }
+
+    /// Checks a block's hash against the checkpoint at its height, if there is one.
+    pub fn check_block(&self, height: u64, hash: &[u8; 32]) -> Result<(), ConsensusError> {
+        match self.checkpoints.get(&height) {
+            Some(checkpoint) if checkpoint != hash => {
+                tracing::warn!(
+                    target: BLOCK_TARGET,
+                    height,
+                    hash = %hex::encode(hash),
+                    "Block does not match the checkpoint: {}",
+                    hex::encode(checkpoint)
+                );
+                Err(BlockError::CheckpointMismatch {
+                    height,
+                    expected: *checkpoint,
+                    got: *hash,
+                }
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cuprate_common::Network;
+
+    use super::{parse_checkpoint_list, Checkpoints};
+
+    #[test]
+    fn checkpoint_tables_parse() {
+        for network in [Network::Mainnet, Network::Testnet, Network::Stagenet] {
+            let checkpoints = Checkpoints::for_network(&network);
+            assert!(checkpoints.checkpoint_at(0).is_some());
+            assert!(checkpoints.top_checkpoint_height() > 0);
+        }
+    }
+
+    #[test]
+    fn monerod_checkpoint_lines_parse() {
+        let hash = "771fbcd656ec1464d3a02ead5e18644030007a0fc664c0a964d30922821a8148";
+        let list = format!(
+            "// A comment.\n\n  ADD_CHECKPOINT(1, \"{hash}\");\n\
+             ADD_CHECKPOINT2(2,  \"{hash}\", \"0x10\");\n"
+        );
+
+        let checkpoints = parse_checkpoint_list(&list);
+        assert_eq!(checkpoints.len(), 2);
+        assert_eq!(hex::encode(checkpoints[&1]), hash);
+        assert_eq!(hex::encode(checkpoints[&2]), hash);
+    }
+
+    #[test]
+    fn check_block_against_checkpoints() {
+        let mut checkpoints = Checkpoints::default();
+        assert!(!checkpoints.is_in_checkpoint_zone(0));
+
+        checkpoints.add_checkpoint(10, [1; 32]);
+
+        assert!(checkpoints.check_block(10, &[1; 32]).is_ok());
+        assert!(checkpoints.check_block(10, &[2; 32]).is_err());
+        assert!(checkpoints.check_block(11, &[2; 32]).is_ok());
+
+        assert!(checkpoints.is_in_checkpoint_zone(10));
+        assert!(!checkpoints.is_in_checkpoint_zone(11));
+    }
+}
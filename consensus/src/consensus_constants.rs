@@ -0,0 +1,189 @@
+//! # Consensus Constants
+//!
+//! This module collects the constants of Monero's consensus rules: the hard-fork heights of each
+//! network, the difficulty targets, the lock windows and the penalty free zones.
+//!
+//! Most of them are the same on every network, the ones that change are kept in
+//! [`ConsensusConstants`], which can be overridden for [`Network::Regtest`] to run a private test
+//! network with custom fork heights and a fixed difficulty, like monerod's
+//! `--regtest --fixed-difficulty`. The fork heights of any network can be overridden with
+//! [`ConsensusConstants::with_fork_height_overrides`], to test a fork before its height is released.
+//!
+use cuprate_common::Network;
+
+use crate::hardforks::{HardFork, HARD_FORKS};
+
+/// The amount of hard-forks Monero has had.
+pub const NUMB_OF_HARD_FORKS: usize = HARD_FORKS.len();
+
+/// The target time between blocks at [`HardFork::V1`], in seconds.
+pub const DIFFICULTY_TARGET_V1: u64 = 60;
+/// The target time between blocks from [`HardFork::V2`], in seconds.
+pub const DIFFICULTY_TARGET_V2: u64 = 120;
+
+/// The amount of blocks the output of a miner transaction is locked for.
+pub const MINED_MONEY_UNLOCK_WINDOW: u64 = 60;
+/// The amount of blocks a time-lock can be ahead of the chain and still be unlocked.
+pub const LOCKED_TX_ALLOWED_DELTA_BLOCKS: u64 = 1;
+/// How far a block's timestamp can be ahead of our clock, in seconds.
+pub const BLOCK_FUTURE_TIME_LIMIT: u64 = 60 * 60 * 2;
+
+/// The penalty free zone at [`HardFork::V1`].
+pub const PENALTY_FREE_ZONE_1: usize = 20000;
+/// The penalty free zone from [`HardFork::V2`] to [`HardFork::V4`].
+pub const PENALTY_FREE_ZONE_2: usize = 60000;
+/// The penalty free zone from [`HardFork::V5`].
+pub const PENALTY_FREE_ZONE_5: usize = 300000;
+
+/// The difficulty of every block of a regtest network made with [`ConsensusConstants::regtest`].
+pub const REGTEST_FIXED_DIFFICULTY: u128 = 1;
+
+/// Returns the height each hard-fork activates at on this network, from [`HARD_FORKS`].
+///
+/// On [`Network::Regtest`] the latest hard-fork activates at height 1, like monerod's `--regtest`.
+const fn network_fork_heights(network: Network) -> [u64; NUMB_OF_HARD_FORKS] {
+    let mut heights = [0; NUMB_OF_HARD_FORKS];
+    let mut i = 1;
+    while i < NUMB_OF_HARD_FORKS {
+        heights[i] = match network {
+            Network::Mainnet => HARD_FORKS[i].mainnet_height,
+            Network::Testnet => HARD_FORKS[i].testnet_height,
+            Network::Stagenet => HARD_FORKS[i].stagenet_height,
+            Network::Regtest => 1,
+        };
+        i += 1;
+    }
+    heights
+}
+
+/// The consensus constants that change between networks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsensusConstants {
+    network: Network,
+    /// The height each hard-fork activates at, index 0 is [`HardFork::V1`].
+    fork_heights: [u64; NUMB_OF_HARD_FORKS],
+    /// The difficulty of every block, instead of the difficulty calculated from the chain.
+    fixed_difficulty: Option<u128>,
+}
+
+impl ConsensusConstants {
+    /// Returns the constants of this network.
+    pub fn for_network(network: &Network) -> ConsensusConstants {
+        ConsensusConstants {
+            network: *network,
+            fork_heights: network_fork_heights(*network),
+            fixed_difficulty: None,
+        }
+    }
+
+    /// Returns the constants of a [`Network::Regtest`] network: every hard-fork after
+    /// [`HardFork::V1`] activates at height 1 and every block has a difficulty of
+    /// [`REGTEST_FIXED_DIFFICULTY`].
+    pub fn regtest() -> ConsensusConstants {
+        ConsensusConstants::for_network(&Network::Regtest)
+            .with_fixed_difficulty(REGTEST_FIXED_DIFFICULTY)
+    }
+
+    /// Sets the height each hard-fork activates at, index 0 is [`HardFork::V1`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is not [`Network::Regtest`], [`HardFork::V1`] does not activate at 0 or the
+    /// heights are not in order.
+    pub fn with_fork_heights(
+        mut self,
+        fork_heights: [u64; NUMB_OF_HARD_FORKS],
+    ) -> ConsensusConstants {
+        assert_eq!(
+            self.network,
+            Network::Regtest,
+            "Only regtest forks can be moved"
+        );
+        check_fork_heights(&fork_heights);
+
+        self.fork_heights = fork_heights;
+        self
+    }
+
+    /// Moves the height some hard-forks activate at, on any network. This is for testing and for
+    /// configuring a future fork before its height is released.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`HardFork::V1`] is moved from 0 or the heights are not in order after the
+    /// overrides.
+    pub fn with_fork_height_overrides(
+        mut self,
+        overrides: &[(HardFork, u64)],
+    ) -> ConsensusConstants {
+        for (hf, height) in overrides {
+            self.fork_heights[*hf as usize - 1] = *height;
+        }
+        check_fork_heights(&self.fork_heights);
+
+        self
+    }
+
+    /// Sets the difficulty of every block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is not [`Network::Regtest`] or the difficulty is 0.
+    pub fn with_fixed_difficulty(mut self, difficulty: u128) -> ConsensusConstants {
+        assert_eq!(
+            self.network,
+            Network::Regtest,
+            "Only regtest can fix the difficulty"
+        );
+        assert_ne!(difficulty, 0, "The difficulty can't be 0");
+
+        self.fixed_difficulty = Some(difficulty);
+        self
+    }
+
+    /// Returns the network these constants are for.
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Returns the minimum height the hard-fork will activate at.
+    pub fn fork_height(&self, hf: &HardFork) -> u64 {
+        self.fork_heights[*hf as usize - 1]
+    }
+
+    /// Returns the difficulty of every block, if it is fixed.
+    pub fn fixed_difficulty(&self) -> Option<u128> {
+        self.fixed_difficulty
+    }
+}
+
+fn check_fork_heights(fork_heights: &[u64; NUMB_OF_HARD_FORKS]) {
+    assert_eq!(fork_heights[0], 0, "V1 must activate at the genesis block");
+    assert!(
+        fork_heights
+            .windows(2)
+            .all(|heights| heights[0] <= heights[1]),
+        "Hard-forks must activate in order"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fork_heights() {
+        let main_net = ConsensusConstants::for_network(&Network::Mainnet);
+        assert_eq!(main_net.fork_height(&HardFork::V1), 0);
+        assert_eq!(main_net.fork_height(&HardFork::V16), 2689608);
+
+        let mut heights = [10; NUMB_OF_HARD_FORKS];
+        heights[0] = 0;
+        let regtest = ConsensusConstants::for_network(&Network::Regtest)
+            .with_fork_heights(heights)
+            .with_fixed_difficulty(1);
+        assert_eq!(regtest.fork_height(&HardFork::V2), 10);
+        assert_eq!(regtest.fixed_difficulty(), Some(1));
+        assert_eq!(main_net.fixed_difficulty(), None);
+    }
+}
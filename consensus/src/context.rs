@@ -0,0 +1,455 @@
+//! # Context
+//!
+//! This module contains [`BlockChainContext`], a snapshot of the state of the main chain taken from
+//! the [`Verifier`], and [`ContextService`], a [`tower::Service`] other components (like the RPC
+//! server) can use to get the latest snapshot without needing access to the verifier.
+//!
+//! The verifier's owner should call [`ContextService::update`] after every change to the chain.
+//!
+//! [`compare_chains`] is Monero's fork-choice rule, it picks between the main chain and an alt chain
+//! by the cumulative difficulty of their [`ChainTip`]s.
+//!
+//! [`ContextCacheInit`] reads the caches the verifier needs for a chain, sending every read at once.
+//!
+//! For debugging, [`ContextDump`] holds a summary of the verifier's caches: the hard-fork votes, the
+//! block weight windows and the difficulty window. The service also returns the verifier's
+//! [`AltChainStats`], for monitoring.
+//!
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+
+use futures::{
+    future::{ready, Ready},
+    join,
+};
+use tower::ServiceExt;
+use tracing::instrument;
+
+use cuprate_common::Network;
+
+use crate::{
+    block::{
+        pow::difficulty::DifficultyCache, reward::calculate_base_reward, weight::BlockWeightsCache,
+    },
+    consensus_constants::NUMB_OF_HARD_FORKS,
+    fee::{get_fee_estimate, FeeEstimate},
+    fork_metrics::AltChainStats,
+    hardforks::{HardFork, HardForkConfig, HardForkState},
+    verifier::Verifier,
+    ConsensusError, ContextProtocolError, Database, DatabaseRequest,
+};
+
+/// A snapshot of the state of the main chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockChainContext {
+    pub network: Network,
+    /// The height of the chain, this is one more than the height of the top block.
+    pub chain_height: u64,
+    pub top_hash: [u8; 32],
+    /// The cumulative difficulty of the top block.
+    pub cumulative_difficulty: u128,
+    /// The difficulty of the next block.
+    pub next_difficulty: u128,
+    /// The adjusted median time of the next block, see
+    /// [`DifficultyCache::adjusted_time`](crate::block::pow::difficulty::DifficultyCache::adjusted_time).
+    pub adjusted_time: Option<u64>,
+    /// The hard-fork of the next block.
+    pub current_hf: HardFork,
+    /// The total amount of coins generated up to and including the top block.
+    pub already_generated_coins: u64,
+    pub effective_median_weight: usize,
+    /// The median weight of the last 100 blocks, used for fees.
+    pub short_term_median_weight: usize,
+    /// The median long term weight of the last 100,000 blocks, used for fees.
+    pub long_term_median_weight: usize,
+    pub next_block_weight_limit: usize,
+}
+
+impl BlockChainContext {
+    /// Returns the base reward of the next block.
+    pub fn next_block_base_reward(&self) -> u64 {
+        calculate_base_reward(self.already_generated_coins, &self.current_hf)
+    }
+
+    /// Returns the fees a transaction should pay to be mined with each priority.
+    pub fn fee_estimate(&self) -> FeeEstimate {
+        get_fee_estimate(
+            self.short_term_median_weight,
+            self.long_term_median_weight,
+            self.next_block_base_reward(),
+            &self.current_hf,
+        )
+    }
+
+    /// Builds the context of a chain from its caches.
+    pub(crate) fn from_caches(
+        network: Network,
+        chain_height: u64,
+        top_hash: [u8; 32],
+        already_generated_coins: u64,
+        block_weight: &BlockWeightsCache,
+        difficulty: &DifficultyCache,
+        hard_fork: &HardForkState,
+    ) -> BlockChainContext {
+        let current_hf = hard_fork.current_hardfork();
+
+        BlockChainContext {
+            network,
+            chain_height,
+            top_hash,
+            cumulative_difficulty: difficulty.last_cumulative_difficulty(),
+            next_difficulty: difficulty.next_difficulty(&current_hf),
+            adjusted_time: difficulty.adjusted_time(),
+            current_hf,
+            already_generated_coins,
+            effective_median_weight: block_weight.effective_median_block_weight(&current_hf),
+            short_term_median_weight: block_weight.short_term_median(),
+            long_term_median_weight: block_weight.long_term_median(),
+            next_block_weight_limit: block_weight.next_block_weight_limit(&current_hf),
+        }
+    }
+
+    /// Returns the top of the main chain.
+    pub fn tip(&self) -> ChainTip {
+        ChainTip {
+            chain_height: self.chain_height,
+            top_hash: self.top_hash,
+            cumulative_difficulty: self.cumulative_difficulty,
+        }
+    }
+}
+
+/// The top of a chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainTip {
+    /// The height of the chain, this is one more than the height of the top block.
+    pub chain_height: u64,
+    pub top_hash: [u8; 32],
+    /// The cumulative difficulty of the top block.
+    pub cumulative_difficulty: u128,
+}
+
+/// Returns the top of the main chain when the block at `height` was the top block.
+pub async fn chain_tip_at<D: Database>(
+    mut database: D,
+    height: u64,
+) -> Result<ChainTip, ConsensusError> {
+    let top_hash = database
+        .ready()
+        .await?
+        .call(DatabaseRequest::BlockHash(height))
+        .await?
+        .into_block_hash()?;
+
+    let cumulative_difficulty = database
+        .oneshot(DatabaseRequest::CumulativeDifficulty(height))
+        .await?
+        .into_cumulative_difficulty()?;
+
+    Ok(ChainTip {
+        chain_height: height + 1,
+        top_hash,
+        cumulative_difficulty,
+    })
+}
+
+/// The chain picked by [`compare_chains`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainChoice {
+    Main,
+    Alt,
+}
+
+/// Picks between the main chain and an alt chain, like monerod the chain with the most cumulative
+/// difficulty wins.
+///
+/// The alt chain must have strictly more cumulative difficulty, on a tie we stay on the chain we
+/// saw first, the main chain. The height of the chains does not matter, a shorter chain with more
+/// work wins.
+pub fn compare_chains(main_tip: &ChainTip, alt_tip: &ChainTip) -> ChainChoice {
+    if alt_tip.cumulative_difficulty > main_tip.cumulative_difficulty {
+        ChainChoice::Alt
+    } else {
+        ChainChoice::Main
+    }
+}
+
+/// The caches of a chain, read from the database for the main chain at startup and for alt chains
+/// when they split off.
+///
+/// Each cache reads its own window, these reads are sent at once so the database can answer them
+/// in parallel instead of one cache waiting for another.
+pub struct ContextCacheInit {
+    pub(crate) block_weight: BlockWeightsCache,
+    pub(crate) difficulty: DifficultyCache,
+    pub(crate) hard_fork: HardForkState,
+    pub(crate) top_hash: [u8; 32],
+    /// The total amount of coins generated up to and including the top block.
+    pub(crate) already_generated_coins: u64,
+}
+
+impl ContextCacheInit {
+    /// Reads the caches for the blocks below `chain_height`.
+    #[instrument(
+        target = "cuprate_consensus::context",
+        name = "init_context_caches",
+        skip(hard_fork_cfg, database)
+    )]
+    pub async fn init_from_chain_height<D: Database + Clone>(
+        hard_fork_cfg: HardForkConfig,
+        chain_height: u64,
+        database: D,
+    ) -> Result<ContextCacheInit, ConsensusError> {
+        let fixed_difficulty = hard_fork_cfg.constants().fixed_difficulty();
+        let (top_hash, already_generated_coins, block_weight, difficulty, hard_fork) = join!(
+            database
+                .clone()
+                .oneshot(DatabaseRequest::BlockHash(chain_height - 1)),
+            database
+                .clone()
+                .oneshot(DatabaseRequest::GeneratedCoins(chain_height - 1)),
+            BlockWeightsCache::init_from_chain_height(chain_height, database.clone()),
+            DifficultyCache::init_from_chain_height(chain_height, database.clone()),
+            HardForkState::init_from_chain_height(hard_fork_cfg, chain_height, database)
+        );
+
+        Ok(ContextCacheInit {
+            block_weight: block_weight?,
+            difficulty: difficulty?.with_fixed_difficulty(fixed_difficulty),
+            hard_fork: hard_fork?,
+            top_hash: top_hash?.into_block_hash()?,
+            already_generated_coins: already_generated_coins?.into_generated_coins()?,
+        })
+    }
+}
+
+/// A summary of a window of block weights.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WeightWindowSummary {
+    /// The amount of weights in the window.
+    pub len: usize,
+    pub min: usize,
+    pub median: usize,
+    pub max: usize,
+}
+
+/// A summary of the difficulty window.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DifficultyWindowSummary {
+    /// The amount of blocks in the window.
+    pub len: usize,
+    pub oldest_timestamp: Option<u64>,
+    pub newest_timestamp: Option<u64>,
+    pub last_cumulative_difficulty: u128,
+    /// The height of the last block in the window.
+    pub last_accounted_height: u64,
+}
+
+/// A summary of the verifier's caches, for debugging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextDump {
+    /// The height of the chain, this is one more than the height of the top block.
+    pub chain_height: u64,
+    pub current_hf: HardFork,
+    pub next_hf: Option<HardFork>,
+    /// The votes for each hard-fork in the voting window, index 0 is [`HardFork::V1`].
+    pub hf_votes: [u64; NUMB_OF_HARD_FORKS],
+    pub hf_total_votes: u64,
+    /// The height of the last block accounted for in the hard-fork votes.
+    pub hf_last_height: u64,
+    pub short_term_weights: WeightWindowSummary,
+    pub long_term_weights: WeightWindowSummary,
+    /// The height of the last block accounted for in the weight windows.
+    pub weights_tip_height: u64,
+    pub difficulty: DifficultyWindowSummary,
+}
+
+#[derive(Debug, Clone)]
+pub enum ContextRequest {
+    BlockChainContext,
+    DumpContext,
+    AltChainStats,
+}
+
+#[derive(Debug)]
+pub enum ContextResponse {
+    BlockChainContext(BlockChainContext),
+    DumpContext(ContextDump),
+    AltChainStats(AltChainStats),
+}
+
+impl ContextResponse {
+    /// Returns the name of this response's variant.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContextResponse::BlockChainContext(_) => "BlockChainContext",
+            ContextResponse::DumpContext(_) => "DumpContext",
+            ContextResponse::AltChainStats(_) => "AltChainStats",
+        }
+    }
+
+    pub fn into_block_chain_context(self) -> Result<BlockChainContext, ContextProtocolError> {
+        match self {
+            ContextResponse::BlockChainContext(context) => Ok(context),
+            res => Err(ContextProtocolError {
+                expected: "BlockChainContext",
+                got: res.name(),
+            }),
+        }
+    }
+
+    pub fn into_dump_context(self) -> Result<ContextDump, ContextProtocolError> {
+        match self {
+            ContextResponse::DumpContext(dump) => Ok(dump),
+            res => Err(ContextProtocolError {
+                expected: "DumpContext",
+                got: res.name(),
+            }),
+        }
+    }
+
+    pub fn into_alt_chain_stats(self) -> Result<AltChainStats, ContextProtocolError> {
+        match self {
+            ContextResponse::AltChainStats(stats) => Ok(stats),
+            res => Err(ContextProtocolError {
+                expected: "AltChainStats",
+                got: res.name(),
+            }),
+        }
+    }
+}
+
+/// A [`tower::Service`] returning the latest [`BlockChainContext`], clones of this service share the
+/// same context.
+#[derive(Debug, Clone)]
+pub struct ContextService {
+    context: Arc<RwLock<(BlockChainContext, ContextDump, AltChainStats)>>,
+}
+
+impl ContextService {
+    pub fn new(verifier: &Verifier) -> ContextService {
+        ContextService {
+            context: Arc::new(RwLock::new((
+                verifier.context(),
+                verifier.dump_context(),
+                verifier.alt_chain_stats(),
+            ))),
+        }
+    }
+
+    /// Updates the context from the verifier.
+    pub fn update(&self, verifier: &Verifier) {
+        *self.context.write().unwrap() = (
+            verifier.context(),
+            verifier.dump_context(),
+            verifier.alt_chain_stats(),
+        );
+    }
+}
+
+impl tower::Service<ContextRequest> for ContextService {
+    type Response = ContextResponse;
+    type Error = ConsensusError;
+    type Future = Ready<Result<ContextResponse, ConsensusError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: ContextRequest) -> Self::Future {
+        let context = self.context.read().unwrap();
+        match req {
+            ContextRequest::BlockChainContext => {
+                ready(Ok(ContextResponse::BlockChainContext(context.0.clone())))
+            }
+            ContextRequest::DumpContext => {
+                ready(Ok(ContextResponse::DumpContext(context.1.clone())))
+            }
+            ContextRequest::AltChainStats => {
+                ready(Ok(ContextResponse::AltChainStats(context.2.clone())))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder};
+
+    fn tip(chain_height: u64, cumulative_difficulty: u128) -> ChainTip {
+        ChainTip {
+            chain_height,
+            top_hash: [chain_height as u8; 32],
+            cumulative_difficulty,
+        }
+    }
+
+    #[test]
+    fn most_work_wins() {
+        assert_eq!(
+            compare_chains(&tip(10, 100), &tip(11, 101)),
+            ChainChoice::Alt
+        );
+        // A shorter chain with more work wins.
+        assert_eq!(
+            compare_chains(&tip(10, 100), &tip(9, 101)),
+            ChainChoice::Alt
+        );
+        assert_eq!(
+            compare_chains(&tip(10, 100), &tip(12, 99)),
+            ChainChoice::Main
+        );
+        // Ties stay on the main chain.
+        assert_eq!(
+            compare_chains(&tip(10, 100), &tip(11, 100)),
+            ChainChoice::Main
+        );
+    }
+
+    #[test]
+    fn tip_from_database() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                10,
+                DummyBlockExtendedHeader {
+                    difficulty: 5,
+                    ..Default::default()
+                },
+            )
+            .finish();
+
+        let tip = block_on(chain_tip_at(database.clone(), 3)).unwrap();
+        assert_eq!(tip.chain_height, 4);
+        assert_eq!(tip.cumulative_difficulty, 20);
+        assert_eq!(Some(tip.top_hash), database.block_hash(3));
+    }
+
+    #[test]
+    fn wrong_responses_are_errors() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+        let verifier = block_on(Verifier::init(
+            crate::verifier::Config::main_net(),
+            database,
+        ))
+        .unwrap();
+        let mut context_svc = ContextService::new(&verifier);
+
+        let res = block_on(tower::Service::call(
+            &mut context_svc,
+            ContextRequest::DumpContext,
+        ))
+        .unwrap();
+        assert_eq!(
+            res.into_block_chain_context().unwrap_err(),
+            ContextProtocolError {
+                expected: "BlockChainContext",
+                got: "DumpContext"
+            }
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! # Decoy Analysis
+//!
+//! This module contains [`DecoyAnalyzer`], a relay policy check that flags transactions with ring
+//! members that don't look like they were picked by a wallet's normal decoy selection. Flagged
+//! transactions are only logged and counted in [`DecoyStats`], they are never rejected, and nothing
+//! in here is used to verify blocks.
+//!
+//! Two patterns are flagged:
+//! - All ancient rings: every member of a ring is older than [`DecoyAnalyzerConfig::ancient_age`].
+//!   Wallets pick most decoys from recent outputs, so rings made only of old outputs stand out.
+//! - Duplicate ages: more than [`DecoyAnalyzerConfig::max_members_per_block`] members of a ring come
+//!   from the same block, or two rings of a transaction have members of exactly the same ages.
+//!
+//! Rings with a single member have no decoys so they are not analyzed.
+//!
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use monero_serai::transaction::{Input, Transaction};
+use tower::ServiceExt;
+
+use crate::{ConsensusError, Database, DatabaseRequest};
+
+/// The default age, in blocks, after which an output is ancient: about a year of 2 minute blocks.
+pub const DEFAULT_ANCIENT_AGE: u64 = 262_800;
+/// The default maximum amount of members of a ring that can come from the same block.
+pub const DEFAULT_MAX_MEMBERS_PER_BLOCK: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecoyAnalyzerConfig {
+    /// The age, in blocks, after which an output is ancient.
+    pub ancient_age: u64,
+    /// The maximum amount of members of a ring that can come from the same block.
+    pub max_members_per_block: usize,
+}
+
+impl Default for DecoyAnalyzerConfig {
+    fn default() -> Self {
+        DecoyAnalyzerConfig {
+            ancient_age: DEFAULT_ANCIENT_AGE,
+            max_members_per_block: DEFAULT_MAX_MEMBERS_PER_BLOCK,
+        }
+    }
+}
+
+/// A pattern in a transaction's rings that normal decoy selection is unlikely to produce.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecoyAnomaly {
+    /// Every member of the ring of this input is ancient.
+    AllAncientRing { input: usize },
+    /// `count` members of the ring of this input were created in the block at `height`.
+    MembersFromSameBlock {
+        input: usize,
+        height: u64,
+        count: usize,
+    },
+    /// The members of the rings of these inputs have the same ages.
+    DuplicateRingAges { input: usize, other_input: usize },
+}
+
+/// Returns the anomalies in a transaction's rings, given the heights of the members of each ring.
+pub fn find_anomalies(
+    ring_member_heights: &[Vec<u64>],
+    chain_height: u64,
+    config: &DecoyAnalyzerConfig,
+) -> Vec<DecoyAnomaly> {
+    let mut anomalies = Vec::new();
+    let mut seen_rings: HashMap<Vec<u64>, usize> = HashMap::new();
+
+    for (input, heights) in ring_member_heights.iter().enumerate() {
+        if heights.len() < 2 {
+            continue;
+        }
+
+        if heights
+            .iter()
+            .all(|height| chain_height.saturating_sub(*height) > config.ancient_age)
+        {
+            anomalies.push(DecoyAnomaly::AllAncientRing { input });
+        }
+
+        let mut per_block: HashMap<u64, usize> = HashMap::new();
+        for height in heights {
+            *per_block.entry(*height).or_default() += 1;
+        }
+        let mut crowded_blocks: Vec<_> = per_block
+            .into_iter()
+            .filter(|(_, count)| *count > config.max_members_per_block)
+            .collect();
+        crowded_blocks.sort_unstable();
+        anomalies.extend(crowded_blocks.into_iter().map(|(height, count)| {
+            DecoyAnomaly::MembersFromSameBlock {
+                input,
+                height,
+                count,
+            }
+        }));
+
+        let mut sorted_heights = heights.clone();
+        sorted_heights.sort_unstable();
+        if let Some(other_input) = seen_rings.insert(sorted_heights, input) {
+            anomalies.push(DecoyAnomaly::DuplicateRingAges { input, other_input });
+        }
+    }
+
+    anomalies
+}
+
+/// Returns the (amount, amount index) of the members of each ring of a transaction, RingCT members
+/// have an amount of 0.
+pub fn ring_members(tx: &Transaction) -> Vec<Vec<(u64, u64)>> {
+    tx.prefix
+        .inputs
+        .iter()
+        .filter_map(|input| match input {
+            Input::Gen(_) => None,
+            Input::ToKey {
+                amount,
+                key_offsets,
+                ..
+            } => {
+                let amount = amount.unwrap_or(0);
+                // The offsets are relative to the previous member.
+                let mut index = 0;
+                Some(
+                    key_offsets
+                        .iter()
+                        .map(|offset| {
+                            index += offset;
+                            (amount, index)
+                        })
+                        .collect(),
+                )
+            }
+        })
+        .collect()
+}
+
+/// Counts of the transactions analyzed and the anomalies found.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DecoyStats {
+    pub txs_analyzed: u64,
+    /// The amount of transactions with at least one anomaly.
+    pub txs_flagged: u64,
+    pub all_ancient_rings: u64,
+    pub members_from_same_block: u64,
+    pub duplicate_ring_ages: u64,
+}
+
+impl DecoyStats {
+    fn record(&mut self, anomalies: &[DecoyAnomaly]) {
+        self.txs_analyzed += 1;
+        if !anomalies.is_empty() {
+            self.txs_flagged += 1;
+        }
+
+        for anomaly in anomalies {
+            match anomaly {
+                DecoyAnomaly::AllAncientRing { .. } => self.all_ancient_rings += 1,
+                DecoyAnomaly::MembersFromSameBlock { .. } => self.members_from_same_block += 1,
+                DecoyAnomaly::DuplicateRingAges { .. } => self.duplicate_ring_ages += 1,
+            }
+        }
+    }
+
+    /// Writes the stats in the Prometheus text format.
+    pub fn write_prometheus(&self, w: &mut impl Write) -> std::fmt::Result {
+        writeln!(
+            w,
+            "# HELP cuprate_decoy_txs_analyzed_total Transactions with their rings analyzed."
+        )?;
+        writeln!(w, "# TYPE cuprate_decoy_txs_analyzed_total counter")?;
+        writeln!(w, "cuprate_decoy_txs_analyzed_total {}", self.txs_analyzed)?;
+
+        writeln!(
+            w,
+            "# HELP cuprate_decoy_txs_flagged_total Transactions with anomalous rings."
+        )?;
+        writeln!(w, "# TYPE cuprate_decoy_txs_flagged_total counter")?;
+        writeln!(w, "cuprate_decoy_txs_flagged_total {}", self.txs_flagged)?;
+
+        writeln!(
+            w,
+            "# HELP cuprate_decoy_anomalies_total Anomalies found in rings, by kind."
+        )?;
+        writeln!(w, "# TYPE cuprate_decoy_anomalies_total counter")?;
+        writeln!(
+            w,
+            "cuprate_decoy_anomalies_total{{kind=\"all_ancient_ring\"}} {}",
+            self.all_ancient_rings
+        )?;
+        writeln!(
+            w,
+            "cuprate_decoy_anomalies_total{{kind=\"members_from_same_block\"}} {}",
+            self.members_from_same_block
+        )?;
+        writeln!(
+            w,
+            "cuprate_decoy_anomalies_total{{kind=\"duplicate_ring_ages\"}} {}",
+            self.duplicate_ring_ages
+        )
+    }
+}
+
+/// Analyzes the rings of transactions, clones of this analyzer share the same stats.
+#[derive(Debug, Clone, Default)]
+pub struct DecoyAnalyzer {
+    config: DecoyAnalyzerConfig,
+    stats: Arc<Mutex<DecoyStats>>,
+}
+
+impl DecoyAnalyzer {
+    pub fn new(config: DecoyAnalyzerConfig) -> DecoyAnalyzer {
+        DecoyAnalyzer {
+            config,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Returns a snapshot of the stats.
+    pub fn stats(&self) -> DecoyStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Analyzes the rings of a transaction, the anomalies found are added to the stats.
+    pub async fn analyze<D: Database>(
+        &self,
+        tx: &Transaction,
+        database: D,
+    ) -> Result<Vec<DecoyAnomaly>, ConsensusError> {
+        self.analyze_rings(ring_members(tx), database).await
+    }
+
+    /// Analyzes rings given as the (amount, amount index) of their members.
+    pub async fn analyze_rings<D: Database>(
+        &self,
+        rings: Vec<Vec<(u64, u64)>>,
+        mut database: D,
+    ) -> Result<Vec<DecoyAnomaly>, ConsensusError> {
+        let chain_height = database
+            .ready()
+            .await?
+            .call(DatabaseRequest::ChainHeight)
+            .await?
+            .into_chain_height()?;
+
+        let outputs = database
+            .oneshot(DatabaseRequest::Outputs(rings.concat()))
+            .await?
+            .into_outputs()?;
+
+        let mut outputs = outputs.into_iter();
+        let ring_member_heights: Vec<Vec<u64>> = rings
+            .iter()
+            .map(|ring| {
+                outputs
+                    .by_ref()
+                    .take(ring.len())
+                    .map(|output| output.height)
+                    .collect()
+            })
+            .collect();
+
+        let anomalies = find_anomalies(&ring_member_heights, chain_height, &self.config);
+        self.stats.lock().unwrap().record(&anomalies);
+
+        Ok(anomalies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use monero_serai::transaction::Timelock;
+
+    use super::*;
+    use crate::{
+        outputs::OutputOnChain,
+        test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder},
+    };
+
+    #[test]
+    fn anomalies() {
+        let config = DecoyAnalyzerConfig {
+            ancient_age: 100,
+            max_members_per_block: 2,
+        };
+
+        // A normal ring, and a ring with a single member which isn't analyzed.
+        assert!(find_anomalies(&[vec![990, 995, 999], vec![1]], 1000, &config).is_empty());
+
+        assert_eq!(
+            find_anomalies(&[vec![1, 5, 899], vec![899, 5, 1]], 1000, &config),
+            vec![
+                DecoyAnomaly::AllAncientRing { input: 0 },
+                DecoyAnomaly::AllAncientRing { input: 1 },
+                DecoyAnomaly::DuplicateRingAges {
+                    input: 1,
+                    other_input: 0
+                },
+            ]
+        );
+
+        assert_eq!(
+            find_anomalies(&[vec![990, 990, 990, 999]], 1000, &config),
+            vec![DecoyAnomaly::MembersFromSameBlock {
+                input: 0,
+                height: 990,
+                count: 3
+            }]
+        );
+    }
+
+    #[test]
+    fn analyze_rings_uses_output_heights() {
+        let output = |height| OutputOnChain {
+            height,
+            time_lock: Timelock::None,
+            key: [0; 32],
+            mask: [0; 32],
+            txid: [0; 32],
+        };
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .add_output(0, 1, output(1))
+            .add_output(0, 2, output(2))
+            .add_output(0, 3, output(9))
+            .finish();
+
+        let analyzer = DecoyAnalyzer::new(DecoyAnalyzerConfig {
+            ancient_age: 5,
+            ..Default::default()
+        });
+
+        let anomalies = block_on(
+            analyzer.analyze_rings(vec![vec![(0, 1), (0, 2)], vec![(0, 2), (0, 3)]], database),
+        )
+        .unwrap();
+        assert_eq!(anomalies, vec![DecoyAnomaly::AllAncientRing { input: 0 }]);
+
+        let stats = analyzer.stats();
+        assert_eq!(stats.txs_analyzed, 1);
+        assert_eq!(stats.txs_flagged, 1);
+        assert_eq!(stats.all_ancient_rings, 1);
+    }
+}
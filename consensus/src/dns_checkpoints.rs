@@ -0,0 +1,377 @@
+//! # DNS Checkpoints
+//!
+//! This module fetches checkpoints from DNS TXT records, like monerod's `--enforce-dns-checkpointing`.
+//! The Monero developers publish a `<height>:<hash>` TXT record for each checkpoint on a few
+//! `moneropulse` domains, the records are signed with DNSSEC.
+//!
+//! The records are fetched from every domain with a [`TxtRecordSource`] and, like monerod, a set
+//! of records is only trusted if it was DNSSEC validated and a majority of the domains returned the
+//! same set. So one compromised domain can't add a checkpoint.
+//!
+//! The DNS checkpoints are given to the verifier with [`Verifier::set_dns_checkpoints`], by default a
+//! block that doesn't match a DNS checkpoint is only logged, with
+//! [`Config::with_enforced_dns_checkpoints`] it is rejected and we refuse to reorg past a DNS
+//! checkpoint.
+//!
+//! With the `dns_checkpoints` feature `DnsResolver` is a [`TxtRecordSource`] that queries the
+//! system's DNS servers.
+//!
+//! [`Verifier::set_dns_checkpoints`]: crate::verifier::Verifier::set_dns_checkpoints
+//! [`Config::with_enforced_dns_checkpoints`]: crate::verifier::Config::with_enforced_dns_checkpoints
+//!
+use std::collections::HashMap;
+
+use futures::future::join_all;
+use tower::ServiceExt;
+
+use cuprate_common::Network;
+
+use crate::{checkpoints::Checkpoints, spans::BLOCK_TARGET};
+
+/// The domains monerod fetches main-net checkpoints from.
+pub const MAINNET_DNS_CHECKPOINT_DOMAINS: &[&str] = &[
+    "checkpoints.moneropulse.se",
+    "checkpoints.moneropulse.org",
+    "checkpoints.moneropulse.net",
+    "checkpoints.moneropulse.co",
+];
+
+/// The domains monerod fetches test-net checkpoints from.
+pub const TESTNET_DNS_CHECKPOINT_DOMAINS: &[&str] = &[
+    "testpoints.moneropulse.se",
+    "testpoints.moneropulse.org",
+    "testpoints.moneropulse.net",
+    "testpoints.moneropulse.co",
+];
+
+/// The domains monerod fetches stage-net checkpoints from.
+pub const STAGENET_DNS_CHECKPOINT_DOMAINS: &[&str] = &[
+    "stagenetpoints.moneropulse.se",
+    "stagenetpoints.moneropulse.org",
+    "stagenetpoints.moneropulse.net",
+    "stagenetpoints.moneropulse.co",
+];
+
+/// Returns the domains the checkpoints of this network are published on.
+pub fn dns_checkpoint_domains(network: &Network) -> &'static [&'static str] {
+    match network {
+        Network::Mainnet => MAINNET_DNS_CHECKPOINT_DOMAINS,
+        Network::Testnet => TESTNET_DNS_CHECKPOINT_DOMAINS,
+        Network::Stagenet => STAGENET_DNS_CHECKPOINT_DOMAINS,
+        Network::Regtest => &[],
+    }
+}
+
+/// The TXT records of a domain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxtRecords {
+    /// The records, one string per record.
+    pub records: Vec<String>,
+    /// If the records were validated with DNSSEC, unvalidated records are never trusted.
+    pub dnssec_valid: bool,
+}
+
+/// A source of TXT records, called with the domain to look up.
+///
+/// This is a trait so the DNS can be mocked in tests, see `DnsResolver` for the real one.
+pub trait TxtRecordSource:
+    tower::Service<String, Response = TxtRecords, Error = tower::BoxError>
+{
+}
+
+impl<T: tower::Service<String, Response = TxtRecords, Error = tower::BoxError>> TxtRecordSource
+    for T
+{
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DnsCheckpointError {
+    #[error("No majority of the {domains} domains returned the same DNSSEC valid checkpoints")]
+    NoMajority { domains: usize },
+    #[error("The DNS checkpoint at height {height} conflicts with a hard-coded checkpoint")]
+    ConflictsWithHardCoded { height: u64 },
+}
+
+/// Parses a `<height>:<hash>` checkpoint record, [`None`] if the record is invalid.
+fn parse_record(record: &str) -> Option<(u64, [u8; 32])> {
+    let (height, hash) = record.trim().split_once(':')?;
+    let height = height.parse().ok()?;
+    let hash = hex::decode(hash).ok()?.try_into().ok()?;
+    Some((height, hash))
+}
+
+/// Fetches the checkpoints published on `domains`.
+///
+/// Every domain is looked up at once, a domain that fails or isn't DNSSEC validated counts as a
+/// domain that didn't agree. The checkpoints are checked against `hard_coded`, a DNS checkpoint
+/// can add checkpoints but never change one we ship.
+pub async fn fetch_dns_checkpoints<S: TxtRecordSource + Clone>(
+    source: S,
+    domains: &[&str],
+    hard_coded: &Checkpoints,
+) -> Result<Checkpoints, DnsCheckpointError> {
+    let lookups = join_all(
+        domains
+            .iter()
+            .map(|domain| source.clone().oneshot(domain.to_string())),
+    )
+    .await;
+
+    // The amount of domains that returned each set of records.
+    let mut agreeing_domains: HashMap<Vec<String>, usize> = HashMap::new();
+    for (domain, lookup) in domains.iter().zip(lookups) {
+        match lookup {
+            Ok(txt) if txt.dnssec_valid => {
+                let mut records = txt.records;
+                records.sort_unstable();
+                records.dedup();
+                *agreeing_domains.entry(records).or_default() += 1;
+            }
+            Ok(_) => {
+                tracing::warn!(
+                    target: BLOCK_TARGET,
+                    domain,
+                    "DNS checkpoints failed DNSSEC validation"
+                )
+            }
+            Err(e) => {
+                tracing::warn!(
+                    target: BLOCK_TARGET,
+                    domain,
+                    "Failed to fetch DNS checkpoints: {e}"
+                )
+            }
+        }
+    }
+
+    let records = agreeing_domains
+        .into_iter()
+        .find(|(_, agreeing)| *agreeing > domains.len() / 2)
+        .map(|(records, _)| records)
+        .ok_or(DnsCheckpointError::NoMajority {
+            domains: domains.len(),
+        })?;
+
+    let mut checkpoints = Checkpoints::default();
+    for record in records {
+        let Some((height, hash)) = parse_record(&record) else {
+            tracing::warn!(target: BLOCK_TARGET, record, "Ignoring invalid DNS checkpoint");
+            continue;
+        };
+
+        if hard_coded
+            .checkpoint_at(height)
+            .is_some_and(|known| known != &hash)
+        {
+            return Err(DnsCheckpointError::ConflictsWithHardCoded { height });
+        }
+        checkpoints.add_checkpoint(height, hash);
+    }
+
+    tracing::info!(
+        target: BLOCK_TARGET,
+        height = checkpoints.top_checkpoint_height(),
+        "Fetched DNS checkpoints"
+    );
+
+    Ok(checkpoints)
+}
+
+#[cfg(feature = "dns_checkpoints")]
+pub use resolver::DnsResolver;
+
+#[cfg(feature = "dns_checkpoints")]
+mod resolver {
+    use std::{
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use hickory_resolver::{
+        config::{ResolverConfig, ResolverOpts},
+        TokioAsyncResolver,
+    };
+
+    use super::TxtRecords;
+
+    /// A [`TxtRecordSource`](super::TxtRecordSource) that looks records up with the system's DNS
+    /// servers, validating them with DNSSEC.
+    ///
+    /// Lookups of records that can't be validated fail, so every returned record is DNSSEC valid.
+    #[derive(Clone)]
+    pub struct DnsResolver {
+        resolver: TokioAsyncResolver,
+    }
+
+    impl DnsResolver {
+        /// Returns a resolver using the system's DNS config, or Cloudflare's servers if it can't
+        /// be read.
+        pub fn new() -> DnsResolver {
+            let (config, mut opts) = hickory_resolver::system_conf::read_system_conf()
+                .unwrap_or_else(|_| (ResolverConfig::cloudflare(), ResolverOpts::default()));
+            opts.validate = true;
+
+            DnsResolver {
+                resolver: TokioAsyncResolver::tokio(config, opts),
+            }
+        }
+    }
+
+    impl Default for DnsResolver {
+        fn default() -> Self {
+            DnsResolver::new()
+        }
+    }
+
+    impl tower::Service<String> for DnsResolver {
+        type Response = TxtRecords;
+        type Error = tower::BoxError;
+        type Future =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, domain: String) -> Self::Future {
+            let resolver = self.resolver.clone();
+            Box::pin(async move {
+                let lookup = resolver.txt_lookup(domain).await?;
+                let records = lookup
+                    .iter()
+                    .map(|txt| {
+                        txt.txt_data()
+                            .iter()
+                            .map(|data| String::from_utf8_lossy(data))
+                            .collect()
+                    })
+                    .collect();
+
+                Ok(TxtRecords {
+                    records,
+                    dnssec_valid: true,
+                })
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use futures::{
+        executor::block_on,
+        future::{ready, Ready},
+    };
+
+    use super::*;
+
+    const HASH: &str = "c758b7c81f928be3295d45e230646de8b852ec96a821eac3fea4daf3fcac0ca2";
+
+    /// A mock DNS, domains not in the map fail to resolve.
+    #[derive(Clone)]
+    struct MockDns(HashMap<String, TxtRecords>);
+
+    impl tower::Service<String> for MockDns {
+        type Response = TxtRecords;
+        type Error = tower::BoxError;
+        type Future = Ready<Result<TxtRecords, tower::BoxError>>;
+
+        fn poll_ready(
+            &mut self,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, domain: String) -> Self::Future {
+            ready(self.0.get(&domain).cloned().ok_or("NXDOMAIN".into()))
+        }
+    }
+
+    fn mock_dns(records: &[(&str, &[&str], bool)]) -> MockDns {
+        MockDns(
+            records
+                .iter()
+                .map(|(domain, records, dnssec_valid)| {
+                    (
+                        domain.to_string(),
+                        TxtRecords {
+                            records: records.iter().map(|r| r.to_string()).collect(),
+                            dnssec_valid: *dnssec_valid,
+                        },
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn records_are_parsed() {
+        assert_eq!(
+            parse_record(&format!("10000:{HASH}")),
+            Some((10000, hex::decode(HASH).unwrap().try_into().unwrap()))
+        );
+        assert_eq!(parse_record(HASH), None);
+        assert_eq!(parse_record("10000:00"), None);
+        assert_eq!(parse_record(&format!("ten:{HASH}")), None);
+    }
+
+    #[test]
+    fn a_majority_of_valid_domains_must_agree() {
+        let record = format!("10000:{HASH}");
+        let other = format!("20000:{HASH}");
+        let domains = MAINNET_DNS_CHECKPOINT_DOMAINS;
+
+        // 3 of 4 domains agree, one has a different record.
+        let dns = mock_dns(&[
+            (domains[0], &[&record], true),
+            (domains[1], &[&record], true),
+            (domains[2], &[&record], true),
+            (domains[3], &[&other], true),
+        ]);
+        let checkpoints =
+            block_on(fetch_dns_checkpoints(dns, domains, &Checkpoints::default())).unwrap();
+        assert_eq!(checkpoints.top_checkpoint_height(), 10000);
+
+        // Half the domains isn't a majority.
+        let dns = mock_dns(&[
+            (domains[0], &[&record], true),
+            (domains[1], &[&record], true),
+            (domains[2], &[&other], true),
+        ]);
+        assert_eq!(
+            block_on(fetch_dns_checkpoints(dns, domains, &Checkpoints::default())),
+            Err(DnsCheckpointError::NoMajority { domains: 4 })
+        );
+
+        // Records that weren't validated don't count.
+        let dns = mock_dns(&[
+            (domains[0], &[&record], true),
+            (domains[1], &[&record], true),
+            (domains[2], &[&record], false),
+        ]);
+        assert!(block_on(fetch_dns_checkpoints(dns, domains, &Checkpoints::default())).is_err());
+    }
+
+    #[test]
+    fn dns_checkpoints_cant_change_hard_coded_ones() {
+        let domains = &["a", "b", "c"];
+        let dns = mock_dns(&[
+            ("a", &["1:00"], true),
+            ("b", &[&format!("1:{HASH}")], true),
+            ("c", &[&format!("1:{HASH}")], true),
+        ]);
+
+        assert_eq!(
+            block_on(fetch_dns_checkpoints(
+                dns,
+                domains,
+                &Checkpoints::for_network(&Network::Mainnet)
+            )),
+            Err(DnsCheckpointError::ConflictsWithHardCoded { height: 1 })
+        );
+    }
+}
@@ -0,0 +1,385 @@
+//! # Consensus Errors
+//!
+//! This module contains [`ConsensusError`], the error of everything in this crate, split by where the
+//! error came from so callers can tell an invalid block or transaction, which a peer can be punished
+//! for, from a failure on our side, which can be retried.
+//!
+//! [`ConsensusError::is_peer_fault`] says which errors mean the data we were given was invalid.
+//!
+use cuprate_common::Network;
+
+use crate::{
+    consensus_constants::MINED_MONEY_UNLOCK_WINDOW, hardforks::HardFork, tx_extra::TxExtraError,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConsensusError {
+    #[error("Invalid block: {0}")]
+    Block(#[from] BlockError),
+    #[error("Invalid transaction: {0}")]
+    Transaction(#[from] TransactionError),
+    #[error("Invalid hard fork: {0}")]
+    HardFork(#[from] HardForkError),
+    #[error("Internal error: {0}")]
+    Internal(#[from] InternalError),
+}
+
+impl ConsensusError {
+    /// Returns if the error means the block or transaction is invalid, so the peer that sent it
+    /// should be punished, otherwise the error is on our side and the request can be retried.
+    pub fn is_peer_fault(&self) -> bool {
+        match self {
+            ConsensusError::Block(e) => e.is_peer_fault(),
+            ConsensusError::Transaction(e) => e.is_peer_fault(),
+            ConsensusError::HardFork(e) => e.is_peer_fault(),
+            ConsensusError::Internal(_) => false,
+        }
+    }
+
+    /// Returns if the error could come from a bug in our rules or database instead of an invalid
+    /// block, so a block rejected with it is worth keeping, see [`quarantine`](crate::quarantine).
+    ///
+    /// Checkpoints and our own policies are trusted, as are failures with a known cause, like a
+    /// database that can't be read.
+    pub fn may_be_local_bug(&self) -> bool {
+        match self {
+            // A key image spent in the chain may be our database being wrong, a low fee is only
+            // our pool's policy.
+            ConsensusError::Block(e) => matches!(e, BlockError::DoubleSpend { .. }),
+            ConsensusError::Transaction(e) => !matches!(e, TransactionError::FeeTooLow { .. }),
+            // An unknown version may be a hard-fork this version doesn't know about yet.
+            ConsensusError::HardFork(_) => true,
+            ConsensusError::Internal(e) => matches!(e, InternalError::DatabaseProtocol(_)),
+        }
+    }
+}
+
+impl From<tower::BoxError> for ConsensusError {
+    fn from(e: tower::BoxError) -> Self {
+        InternalError::Database(e).into()
+    }
+}
+
+impl From<DatabaseProtocolError> for ConsensusError {
+    fn from(e: DatabaseProtocolError) -> Self {
+        InternalError::DatabaseProtocol(e).into()
+    }
+}
+
+impl From<ContextProtocolError> for ConsensusError {
+    fn from(e: ContextProtocolError) -> Self {
+        InternalError::ContextProtocol(e).into()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BlockError {
+    /// The block was given to a chain it does not build on, the caller picked the wrong chain.
+    #[error(
+        "The block's previous block {} is not the top of the chain {}",
+        hex::encode(previous),
+        hex::encode(top_hash)
+    )]
+    DoesNotExtendChain {
+        previous: [u8; 32],
+        top_hash: [u8; 32],
+    },
+    #[error(
+        "The block at height {height} has hash {}, the checkpoint is {}",
+        hex::encode(got),
+        hex::encode(expected)
+    )]
+    CheckpointMismatch {
+        height: u64,
+        expected: [u8; 32],
+        got: [u8; 32],
+    },
+    #[error("The block at height {height} can not be accepted pruned")]
+    PrunedBlockNotAllowed { height: u64 },
+    /// The transactions sent with a block are not the block's transactions.
+    #[error("The transactions sent with the block at height {height} are not the block's")]
+    TransactionsMismatch { height: u64 },
+    /// A peer sent a block or transaction blob that is not valid.
+    #[error("The block at height {height} or one of its transactions could not be parsed")]
+    InvalidBlob { height: u64 },
+    #[error("The block at height {height} does not have enough PoW for its difficulty")]
+    InvalidPow { height: u64 },
+    /// An alt chain that would replace the genesis block, it is not a chain of our network.
+    #[error("An alt chain can not fork off the main chain at the genesis block")]
+    ForksGenesis,
+    #[error("The block at height {height} has weight {weight}, over the limit {limit}")]
+    WeightTooBig {
+        height: u64,
+        weight: usize,
+        limit: usize,
+    },
+    #[error("The block at height {height} has timestamp {timestamp}, below the median {median} of the blocks before it")]
+    TimestampTooEarly {
+        height: u64,
+        timestamp: u64,
+        median: u64,
+    },
+    /// The block's timestamp is too far ahead of our clock, which may be wrong.
+    #[error("The block at height {height} has timestamp {timestamp}, after the limit {limit}")]
+    TimestampInFuture {
+        height: u64,
+        timestamp: u64,
+        limit: u64,
+    },
+    #[error("The miner transaction of the block at height {height} is invalid: {source}")]
+    InvalidMinerTx { height: u64, source: MinerTxError },
+    /// A key image is spent twice in the block or was already spent in the chain.
+    #[error("The block at height {height} spends a key image that is already spent")]
+    DoubleSpend { height: u64 },
+}
+
+impl BlockError {
+    /// Returns if the peer that sent the block should be punished, see [`ConsensusError::is_peer_fault`].
+    pub fn is_peer_fault(&self) -> bool {
+        match self {
+            BlockError::DoesNotExtendChain { .. } => false,
+            BlockError::CheckpointMismatch { .. }
+            | BlockError::PrunedBlockNotAllowed { .. }
+            | BlockError::TransactionsMismatch { .. }
+            | BlockError::InvalidBlob { .. }
+            | BlockError::InvalidPow { .. }
+            | BlockError::ForksGenesis
+            | BlockError::WeightTooBig { .. }
+            | BlockError::TimestampTooEarly { .. }
+            | BlockError::InvalidMinerTx { .. }
+            | BlockError::DoubleSpend { .. } => true,
+            BlockError::TimestampInFuture { .. } => false,
+        }
+    }
+}
+
+/// A broken rule of a block's miner transaction, see
+/// [`check_miner_tx`](crate::miner_tx::check_miner_tx).
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum MinerTxError {
+    #[error("Version {version} is not allowed at hard fork {hf:?}")]
+    VersionNotAllowed { version: u64, hf: HardFork },
+    #[error("The transaction does not have a single miner input for the block's height")]
+    InvalidInput,
+    #[error(
+        "The transaction is not locked until {MINED_MONEY_UNLOCK_WINDOW} blocks after the block"
+    )]
+    InvalidTimelock,
+    #[error("The transaction has RingCT signatures")]
+    HasSignatures,
+    #[error("The outputs' view tags are not allowed at hard fork {0:?}")]
+    InvalidViewTags(HardFork),
+    #[error("The amount of the outputs overflows")]
+    AmountOverflow,
+    #[error("The outputs pay {outputs}, the block's reward and fees are {reward}")]
+    WrongReward { outputs: u64, reward: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TransactionError {
+    #[error("Transaction version {version} is not allowed at hard fork {hf:?}")]
+    VersionNotAllowed { version: u64, hf: HardFork },
+    #[error("The transaction's weight {weight} is over the limit {limit}")]
+    TooBig { weight: usize, limit: usize },
+    /// The fee is too low for the pool, the transaction is not invalid.
+    #[error("The transaction's fee {fee} is below the minimum {minimum}")]
+    FeeTooLow { fee: u64, minimum: u64 },
+    /// The key image is already spent, the peer may not have seen the spend yet.
+    #[error("The key image {} is already spent", hex::encode(.0))]
+    KeyImageSpent([u8; 32]),
+    #[error("Ring member {index} of amount {amount} is a miner output from height {height} which has not matured")]
+    CoinbaseNotMature {
+        amount: u64,
+        index: u64,
+        height: u64,
+    },
+    #[error("Ring member {index} of amount {amount} is time-locked")]
+    OutputLocked { amount: u64, index: u64 },
+    #[error("Ring member {index} of amount {amount} does not have a valid key")]
+    InvalidRingMember { amount: u64, index: u64 },
+    #[error("The amount of the transaction's inputs or outputs overflows")]
+    AmountOverflow,
+    #[error("The transaction's outputs {outputs} are more than its inputs {inputs}")]
+    OutputsMoreThanInputs { inputs: u64, outputs: u64 },
+    #[error("The key image of input {0} is not valid")]
+    InvalidKeyImage(usize),
+    #[error("The ring signature of input {0} is not valid")]
+    InvalidRingSignature(usize),
+    #[error("The transaction's RingCT type is not allowed")]
+    RctTypeNotAllowed,
+    #[error("The RingCT signature of input {0} is not valid")]
+    InvalidRctSignature(usize),
+    #[error("The transaction's range proofs are not valid")]
+    InvalidRangeProof,
+    #[error("The commitments of the transaction's inputs don't balance its outputs and fee")]
+    AmountsDontBalance,
+    #[error("The transaction has no inputs")]
+    NoInputs,
+    #[error("The transaction has a miner input")]
+    MinerInput,
+    #[error("The key image {} is spent twice by the transaction", hex::encode(.0))]
+    DuplicateKeyImage([u8; 32]),
+    /// The extra is too big to relay, the transaction is not invalid.
+    #[error("The extra is {size} bytes, the most is {limit}")]
+    ExtraTooBig { size: usize, limit: usize },
+    /// The extra can't be parsed for relay, the transaction is not invalid.
+    #[error("The extra is malformed: {0}")]
+    InvalidExtra(TxExtraError),
+}
+
+impl TransactionError {
+    /// Returns if the peer that sent the transaction should be punished, see
+    /// [`ConsensusError::is_peer_fault`].
+    pub fn is_peer_fault(&self) -> bool {
+        match self {
+            TransactionError::VersionNotAllowed { .. }
+            | TransactionError::TooBig { .. }
+            | TransactionError::CoinbaseNotMature { .. }
+            | TransactionError::OutputLocked { .. }
+            | TransactionError::InvalidRingMember { .. }
+            | TransactionError::AmountOverflow
+            | TransactionError::OutputsMoreThanInputs { .. }
+            | TransactionError::InvalidKeyImage(_)
+            | TransactionError::InvalidRingSignature(_)
+            | TransactionError::RctTypeNotAllowed
+            | TransactionError::InvalidRctSignature(_)
+            | TransactionError::InvalidRangeProof
+            | TransactionError::AmountsDontBalance
+            | TransactionError::NoInputs
+            | TransactionError::MinerInput
+            | TransactionError::DuplicateKeyImage(_) => true,
+            TransactionError::FeeTooLow { .. }
+            | TransactionError::KeyImageSpent(_)
+            | TransactionError::ExtraTooBig { .. }
+            | TransactionError::InvalidExtra(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum HardForkError {
+    #[error("Version {0} is not a known hard fork")]
+    UnknownVersion(u8),
+    /// The block is for a hard-fork we don't know about and the
+    /// [`UnknownForkPolicy`](crate::hardforks::UnknownForkPolicy) is to halt, the node needs updating.
+    #[error(
+        "Version {0} is a hard fork this version does not know about, the node needs updating"
+    )]
+    UnknownFork(u8),
+    #[error("The block's version is {got:?}, expected {expected:?}")]
+    VersionMismatch { expected: HardFork, got: HardFork },
+    #[error("The block votes for {vote:?}, which is before the current hard fork {current:?}")]
+    VoteTooLow { vote: HardFork, current: HardFork },
+}
+
+impl HardForkError {
+    /// Returns if the peer that sent the block should be punished, see [`ConsensusError::is_peer_fault`].
+    pub fn is_peer_fault(&self) -> bool {
+        !matches!(self, HardForkError::UnknownFork(_))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InternalError {
+    #[error("Database error: {0}")]
+    Database(tower::BoxError),
+    #[error("Database protocol error: {0}")]
+    DatabaseProtocol(DatabaseProtocolError),
+    #[error("Context service protocol error: {0}")]
+    ContextProtocol(ContextProtocolError),
+    /// A RandomX VM could not be made to check a block's PoW, usually from a lack of memory.
+    #[error("RandomX error: {0}")]
+    RandomX(randomx_rs::RandomXError),
+    /// The database disagrees with itself, probably from a crash in the middle of a write.
+    #[error(
+        "The block at height {height} has version {stored:?} but the fork heights and votes give \
+         {expected:?}, the database is probably corrupt"
+    )]
+    CorruptDatabase {
+        height: u64,
+        stored: HardFork,
+        expected: HardFork,
+    },
+    /// A value stored in the database is not the one we calculated, see
+    /// [`VerificationOptions::cross_check_database`](crate::verifier::VerificationOptions::cross_check_database).
+    #[error(
+        "The database has {stored} for the {value} of the block at height {height}, we calculated \
+         {calculated}, the database is probably corrupt"
+    )]
+    DatabaseMismatch {
+        height: u64,
+        value: &'static str,
+        stored: u128,
+        calculated: u128,
+    },
+    /// The database's genesis block is not the network's, the database is of another network.
+    #[error(
+        "The database's genesis block is {}, the {network:?} genesis block is {}",
+        hex::encode(got),
+        hex::encode(expected)
+    )]
+    GenesisMismatch {
+        network: Network,
+        expected: [u8; 32],
+        got: [u8; 32],
+    },
+}
+
+/// The database answered a request with the wrong [`DatabaseResponse`](crate::DatabaseResponse), the
+/// database is buggy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("expected a {expected} response, got a {got} response")]
+pub struct DatabaseProtocolError {
+    pub expected: &'static str,
+    pub got: &'static str,
+}
+
+/// The context service answered a request with the wrong
+/// [`ContextResponse`](crate::context::ContextResponse).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("expected a {expected} response, got a {got} response")]
+pub struct ContextProtocolError {
+    pub expected: &'static str,
+    pub got: &'static str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_fault() {
+        let invalid: ConsensusError = BlockError::CheckpointMismatch {
+            height: 10,
+            expected: [1; 32],
+            got: [2; 32],
+        }
+        .into();
+        assert!(invalid.is_peer_fault());
+
+        let invalid: ConsensusError = HardForkError::UnknownVersion(200).into();
+        assert!(invalid.is_peer_fault());
+        assert!(invalid.may_be_local_bug());
+
+        let outdated: ConsensusError = HardForkError::UnknownFork(200).into();
+        assert!(!outdated.is_peer_fault());
+
+        let not_invalid: ConsensusError = TransactionError::FeeTooLow { fee: 1, minimum: 2 }.into();
+        assert!(!not_invalid.is_peer_fault());
+        assert!(!not_invalid.may_be_local_bug());
+
+        let internal: ConsensusError = tower::BoxError::from("database closed").into();
+        assert!(matches!(
+            internal,
+            ConsensusError::Internal(InternalError::Database(_))
+        ));
+        assert!(!internal.is_peer_fault());
+
+        let internal: ConsensusError = DatabaseProtocolError {
+            expected: "ChainHeight",
+            got: "WriteBlock",
+        }
+        .into();
+        assert!(!internal.is_peer_fault());
+    }
+}
@@ -0,0 +1,384 @@
+//! # Fee Estimation
+//!
+//! This module contains [`estimate_fee`], the fee a transaction should pay to get mined with a
+//! priority, calculated from the short and long term median block weights (from the
+//! [`BlockWeightsCache`](crate::block::weight::BlockWeightsCache)) and the base reward of the next
+//! block, and [`get_fee_estimate`], which returns the fees of every priority for the RPC.
+//!
+//! The algorithm has changed with hard-forks:
+//! - Before [`HardFork::V8`] fees are per kB, the base fee is scaled by the penalty free zone over
+//!   the effective median and by the base reward. [`HardFork::V5`] lowered the base fee.
+//! - From [`HardFork::V8`] fees are per byte: `base_reward * 3000 / median² / 5`, with the
+//!   effective median.
+//! - From [`HardFork::V15`] each priority has its own fee, calculated like monerod's
+//!   `get_dynamic_base_fee_estimate_2021_scaling` from the smaller of the two medians. The fee of
+//!   [`FeePriority::High`] is derived from the fee of [`FeePriority::Elevated`] and grows with the
+//!   short term median, so high priority transactions can compete for space when blocks grow.
+//!
+//! Before [`HardFork::V15`] the higher priorities are the base fee times a multiplier, the
+//! multipliers have changed at [`HardFork::V3`], [`HardFork::V5`] and [`HardFork::V8`].
+//!
+//! [`minimum_fee`] is the lowest fee a transaction is relayed with, the fee of [`FeePriority::Low`]
+//! for its weight, accepted 2% below like monerod so wallets rounding differently aren't rejected.
+//!
+//! [`estimate_backlog`] estimates how many blocks of the pool's transactions will be mined before a
+//! transaction paying a fee, the same estimate monero-wallet-cli shows before sending.
+//!
+use crate::{
+    block::{
+        reward::FEE_QUANTIZATION_MASK,
+        weight::{calculate_effective_median_block_weight, penalty_free_zone},
+    },
+    hardforks::HardFork,
+};
+
+const DYNAMIC_FEE_REFERENCE_TRANSACTION_WEIGHT: u128 = 3000;
+/// The base fee per kB before [`HardFork::V5`].
+const DYNAMIC_FEE_PER_KB_BASE_FEE: u128 = 2_000_000_000;
+/// The base fee per kB from [`HardFork::V5`].
+const DYNAMIC_FEE_PER_KB_BASE_FEE_V5: u128 = 400_000_000;
+/// The block reward the per kB base fee was set for.
+const DYNAMIC_FEE_PER_KB_BASE_BLOCK_REWARD: u128 = 10_000_000_000_000;
+/// From [`HardFork::V15`] fees are rounded up to this many significant digits.
+const FEE_ROUNDING_PLACES: u32 = 2;
+
+/// The priority a transaction is sent with, the names in the wallets are unimportant, normal,
+/// elevated and priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FeePriority {
+    Low,
+    Normal,
+    Elevated,
+    High,
+}
+
+impl FeePriority {
+    pub const ALL: [FeePriority; 4] = [
+        FeePriority::Low,
+        FeePriority::Normal,
+        FeePriority::Elevated,
+        FeePriority::High,
+    ];
+}
+
+/// Returns the multipliers of the base fee for each priority before [`HardFork::V15`], forks with
+/// only 3 multipliers use the last one for [`FeePriority::High`].
+fn fee_multipliers(hf: &HardFork) -> &'static [u64] {
+    if hf < &HardFork::V3 {
+        &[1, 2, 3]
+    } else if hf < &HardFork::V5 {
+        &[1, 20, 166]
+    } else if hf < &HardFork::V8 {
+        &[1, 4, 20, 166]
+    } else {
+        &[1, 5, 25, 1000]
+    }
+}
+
+/// Rounds the amount up to `places` significant digits.
+fn round_up_significant(amount: u128, places: u32) -> u128 {
+    let digits = amount.checked_ilog10().map_or(1, |log| log + 1);
+    if digits <= places {
+        return amount;
+    }
+
+    let order = 10_u128.pow(digits - places);
+    amount.div_ceil(order) * order
+}
+
+/// Returns the base fee before [`HardFork::V15`], per kB before [`HardFork::V8`] and per byte after.
+fn base_fee(effective_median_weight: usize, base_reward: u64, hf: &HardFork) -> u128 {
+    let min_block_weight = penalty_free_zone(hf) as u128;
+    let median = (effective_median_weight as u128).max(min_block_weight);
+    let base_reward = u128::from(base_reward);
+
+    if hf >= &HardFork::V8 {
+        return base_reward * DYNAMIC_FEE_REFERENCE_TRANSACTION_WEIGHT / median / median / 5;
+    }
+
+    let fee_base = if hf >= &HardFork::V5 {
+        DYNAMIC_FEE_PER_KB_BASE_FEE_V5
+    } else {
+        DYNAMIC_FEE_PER_KB_BASE_FEE
+    };
+
+    let fee =
+        fee_base * min_block_weight / median * base_reward / DYNAMIC_FEE_PER_KB_BASE_BLOCK_REWARD;
+    fee.div_ceil(u128::from(FEE_QUANTIZATION_MASK)) * u128::from(FEE_QUANTIZATION_MASK)
+}
+
+/// Returns the fee of each priority from [`HardFork::V15`].
+///
+/// The names are the paper's: `Zm` the penalty free zone, `Mnw` the short term median, `Mlw` the
+/// long term median and `Mfw` the smaller of the two, the medians are never less than `Zm`.
+///
+/// https://github.com/ArticMine/Monero-Documents/blob/master/MoneroScaling2021-02.pdf
+fn fees_2021_scaling(
+    short_term_median: usize,
+    long_term_median: usize,
+    base_reward: u64,
+) -> [u128; 4] {
+    let zm = penalty_free_zone(&HardFork::V15) as u128;
+    let mnw = (short_term_median as u128).max(zm);
+    let mlw = (long_term_median as u128).max(zm);
+    let mfw = mnw.min(mlw);
+    let base_reward = u128::from(base_reward);
+
+    let low = base_reward * DYNAMIC_FEE_REFERENCE_TRANSACTION_WEIGHT / (mfw * mfw);
+    let normal = 4 * low;
+    let elevated = 16 * base_reward * DYNAMIC_FEE_REFERENCE_TRANSACTION_WEIGHT / (zm * mfw);
+    let high = (4 * elevated)
+        .max(4 * elevated * mfw / (32 * DYNAMIC_FEE_REFERENCE_TRANSACTION_WEIGHT * mnw / zm));
+
+    [low, normal, elevated, high].map(|fee| round_up_significant(fee, FEE_ROUNDING_PLACES))
+}
+
+/// Returns the fee a transaction should pay to be mined with this priority: per byte from
+/// [`HardFork::V8`] and per kB before.
+///
+/// Before [`HardFork::V15`] the fee is calculated from the effective median of the two medians.
+pub fn estimate_fee(
+    priority: FeePriority,
+    short_term_median: usize,
+    long_term_median: usize,
+    base_reward: u64,
+    hf: &HardFork,
+) -> u64 {
+    let fee = if hf >= &HardFork::V15 {
+        fees_2021_scaling(short_term_median, long_term_median, base_reward)[priority as usize]
+    } else {
+        let effective_median_weight =
+            calculate_effective_median_block_weight(hf, short_term_median, long_term_median);
+        let multipliers = fee_multipliers(hf);
+        let multiplier = multipliers[(priority as usize).min(multipliers.len() - 1)];
+        base_fee(effective_median_weight, base_reward, hf) * u128::from(multiplier)
+    };
+
+    u64::try_from(fee).unwrap_or(u64::MAX)
+}
+
+/// Returns the lowest fee a transaction of this weight is relayed with.
+pub fn minimum_fee(
+    tx_weight: usize,
+    short_term_median: usize,
+    long_term_median: usize,
+    base_reward: u64,
+    hf: &HardFork,
+) -> u64 {
+    let fee = u128::from(estimate_fee(
+        FeePriority::Low,
+        short_term_median,
+        long_term_median,
+        base_reward,
+        hf,
+    ));
+
+    let needed_fee = if hf >= &HardFork::V8 {
+        let mask = u128::from(FEE_QUANTIZATION_MASK);
+        (fee * tx_weight as u128).div_ceil(mask) * mask
+    } else {
+        fee * tx_weight.div_ceil(1024) as u128
+    };
+
+    u64::try_from(needed_fee - needed_fee / 50).unwrap_or(u64::MAX)
+}
+
+/// The fee estimate returned by the `get_fee_estimate` RPC method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeEstimate {
+    /// The fee of [`FeePriority::Low`].
+    pub fee: u64,
+    /// The fee of each priority, in the order of [`FeePriority::ALL`].
+    pub fees: [u64; 4],
+    /// The fee of a transaction, the fee per byte times its weight, should be rounded up to a
+    /// multiple of this.
+    pub quantization_mask: u64,
+}
+
+/// Returns the fees of every priority.
+pub fn get_fee_estimate(
+    short_term_median: usize,
+    long_term_median: usize,
+    base_reward: u64,
+    hf: &HardFork,
+) -> FeeEstimate {
+    let fees = FeePriority::ALL.map(|priority| {
+        estimate_fee(
+            priority,
+            short_term_median,
+            long_term_median,
+            base_reward,
+            hf,
+        )
+    });
+
+    FeeEstimate {
+        fee: fees[0],
+        fees,
+        quantization_mask: FEE_QUANTIZATION_MASK,
+    }
+}
+
+/// The backlog of pool transactions ahead of a transaction paying a fee per byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backlog {
+    pub fee_per_byte: u64,
+    /// The total weight of the pool transactions paying the same fee per byte or more.
+    pub weight_ahead: u64,
+    /// The amount of full blocks of transactions ahead, the transaction is expected to be mined in
+    /// the block after these.
+    pub blocks_ahead: u64,
+}
+
+/// Estimates the backlog ahead of a transaction paying each fee per byte, like monero-wallet-cli.
+///
+/// `pool` is the weight and fee of every transaction in the pool. Miners fill blocks up to the
+/// effective median weight, past it they lose reward, so each block is counted as that much of the
+/// pool's transactions, highest fee per byte first.
+pub fn estimate_backlog(
+    pool: &[(usize, u64)],
+    fees_per_byte: &[u64],
+    effective_median_weight: usize,
+) -> Vec<Backlog> {
+    let block_weight = effective_median_weight.max(1) as u64;
+
+    fees_per_byte
+        .iter()
+        .map(|&fee_per_byte| {
+            let weight_ahead = pool
+                .iter()
+                .filter(|(weight, fee)| {
+                    u128::from(*fee) >= u128::from(fee_per_byte) * *weight as u128
+                })
+                .map(|(weight, _)| *weight as u64)
+                .sum();
+
+            Backlog {
+                fee_per_byte,
+                weight_ahead,
+                blocks_ahead: weight_ahead / block_weight,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tail emission reward.
+    const TAIL_REWARD: u64 = 6 * 10_u64.pow(11);
+
+    #[test]
+    fn fees_2021_scaling() {
+        // The fees monerod returns with the tail emission and medians at the penalty free zone.
+        assert_eq!(
+            get_fee_estimate(0, 0, TAIL_REWARD, &HardFork::V16).fees,
+            [20_000, 80_000, 320_000, 4_000_000]
+        );
+
+        // Both medians doubled: the low and normal fees are a quarter, the elevated fee half, and
+        // the high fee only falls by half as it grows with the short term median.
+        let fees = get_fee_estimate(600_000, 600_000, TAIL_REWARD, &HardFork::V16).fees;
+        assert_eq!(fees, [5_000, 20_000, 160_000, 2_000_000]);
+
+        // The fees are calculated from the smaller median, only the high fee uses the short term
+        // median.
+        let fees = get_fee_estimate(600_000, 300_000, TAIL_REWARD, &HardFork::V16).fees;
+        assert_eq!(fees, [20_000, 80_000, 320_000, 2_000_000]);
+        assert_eq!(
+            get_fee_estimate(300_000, 600_000, TAIL_REWARD, &HardFork::V16).fees,
+            [20_000, 80_000, 320_000, 4_000_000]
+        );
+
+        // Fees are rounded up to 2 significant digits.
+        assert_eq!(
+            estimate_fee(
+                FeePriority::Low,
+                700_000,
+                700_000,
+                TAIL_REWARD,
+                &HardFork::V15
+            ),
+            3_700
+        );
+    }
+
+    #[test]
+    fn per_byte_fees() {
+        let estimate = get_fee_estimate(0, 0, TAIL_REWARD, &HardFork::V14);
+        assert_eq!(estimate.fee, 4_000);
+        assert_eq!(estimate.fees, [4_000, 20_000, 100_000, 4_000_000]);
+    }
+
+    #[test]
+    fn per_kb_fees() {
+        // With the reward the base fee was set for and a median at the penalty free zone the fee
+        // is the base fee.
+        let reward = DYNAMIC_FEE_PER_KB_BASE_BLOCK_REWARD as u64;
+        assert_eq!(
+            estimate_fee(FeePriority::Low, 0, 0, reward, &HardFork::V5),
+            DYNAMIC_FEE_PER_KB_BASE_FEE_V5 as u64
+        );
+        assert_eq!(
+            estimate_fee(FeePriority::Low, 0, 0, reward, &HardFork::V4),
+            DYNAMIC_FEE_PER_KB_BASE_FEE as u64
+        );
+
+        // Forks with 3 multipliers use the last one for high priority.
+        assert_eq!(
+            estimate_fee(FeePriority::High, 0, 0, reward, &HardFork::V4),
+            166 * DYNAMIC_FEE_PER_KB_BASE_FEE as u64
+        );
+
+        // Per kB fees are quantized.
+        assert_eq!(
+            estimate_fee(FeePriority::Low, 0, 0, 12_345_678_901_234, &HardFork::V7)
+                % FEE_QUANTIZATION_MASK,
+            0
+        );
+    }
+
+    #[test]
+    fn minimum_fees() {
+        // 20,000 per byte, 2% off.
+        assert_eq!(
+            minimum_fee(1_500, 0, 0, TAIL_REWARD, &HardFork::V16),
+            29_400_000
+        );
+        // 4,000 per byte, quantized up before the 2% is taken off.
+        assert_eq!(
+            minimum_fee(1_501, 0, 0, TAIL_REWARD, &HardFork::V14),
+            5_889_800
+        );
+
+        // Per kB fees are paid for every started kB.
+        let reward = DYNAMIC_FEE_PER_KB_BASE_BLOCK_REWARD as u64;
+        assert_eq!(minimum_fee(1_025, 0, 0, reward, &HardFork::V5), 784_000_000);
+    }
+
+    #[test]
+    fn backlog() {
+        // (weight, fee): 1,000 and 100 per byte, and a 10 per byte transaction.
+        let pool = [
+            (300_000, 300_000_000),
+            (300_000, 30_000_000),
+            (150_000, 1_500_000),
+        ];
+
+        let backlog = estimate_backlog(&pool, &[1_000, 100, 50, 1], 300_000);
+        let blocks: Vec<u64> = backlog.iter().map(|backlog| backlog.blocks_ahead).collect();
+        assert_eq!(blocks, [1, 2, 2, 2]);
+        // Transactions paying the same fee are ahead.
+        assert_eq!(backlog[1].weight_ahead, 600_000);
+        assert_eq!(backlog[3].weight_ahead, 750_000);
+
+        // Nothing is ahead of a fee higher than the pool's.
+        assert_eq!(
+            estimate_backlog(&pool, &[2_000], 300_000)[0].weight_ahead,
+            0
+        );
+        // A bigger median fits more in each block.
+        assert_eq!(estimate_backlog(&pool, &[1], 1_000_000)[0].blocks_ahead, 0);
+    }
+}
@@ -0,0 +1,303 @@
+//! # Fluffy Blocks
+//!
+//! This module contains [`FluffyBlock`], a block announced with `NEW_FLUFFY_BLOCK`. Fluffy blocks
+//! are sent with only the transactions the announcing peer thinks we don't have, which is normally
+//! none, so a new block propagates with a fraction of the bandwidth of the full block.
+//!
+//! The rest of the block's transactions are taken from the tx pool, which should already have most
+//! of them, and the transactions still missing are requested from the announcing peer by their index
+//! in the block, see [`reconstruct_fluffy_block`]. The returned [`FullBlock`] is then queued for the
+//! verifier with [`QueueSender::push_tip`](crate::verification_queue::QueueSender::push_tip).
+//!
+//! Transactions are matched to the block by hash, a peer sending a transaction that isn't in the
+//! block is an error.
+//!
+use std::collections::HashMap;
+use std::future::Future;
+
+use monero_serai::{block::Block, transaction::Transaction};
+use tower::ServiceExt;
+
+use crate::{
+    spans::BLOCK_TARGET,
+    txpool::{TxPoolError, TxPoolRequest, TxPoolResponse},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum FluffyBlockError {
+    #[error("A transaction sent with the block is not in the block")]
+    TxNotInBlock,
+    #[error("The peer did not send every missing transaction")]
+    MissingTxs,
+    #[error("Tx pool error: {0}")]
+    TxPool(#[from] TxPoolError),
+    /// The tx pool answered a request with the wrong [`TxPoolResponse`], the pool is buggy.
+    #[error("The tx pool sent an unexpected response")]
+    UnexpectedPoolResponse,
+    #[error("Requesting the missing transactions failed: {0}")]
+    Request(tower::BoxError),
+}
+
+/// A block with all of its transactions.
+#[derive(Debug, Clone)]
+pub struct FullBlock {
+    pub block: Block,
+    /// The block's transactions, in the order of the block's transaction hashes.
+    pub txs: Vec<Transaction>,
+}
+
+/// A block we have some of the transactions of.
+#[derive(Debug, Clone)]
+pub struct FluffyBlock {
+    block: Block,
+    /// The block's transactions, in the order of the block's transaction hashes.
+    txs: Vec<Option<Transaction>>,
+}
+
+impl FluffyBlock {
+    /// Returns a fluffy block with the transactions sent with it.
+    pub fn new(block: Block, txs: Vec<Transaction>) -> Result<Self, FluffyBlockError> {
+        let mut fluffy_block = FluffyBlock {
+            txs: vec![None; block.txs.len()],
+            block,
+        };
+        fluffy_block.add_txs(txs)?;
+        Ok(fluffy_block)
+    }
+
+    pub fn block(&self) -> &Block {
+        &self.block
+    }
+
+    /// Adds transactions to the block, in any order.
+    pub fn add_txs(
+        &mut self,
+        txs: impl IntoIterator<Item = Transaction>,
+    ) -> Result<(), FluffyBlockError> {
+        let indexes: HashMap<[u8; 32], usize> = self
+            .block
+            .txs
+            .iter()
+            .enumerate()
+            .map(|(i, hash)| (*hash, i))
+            .collect();
+
+        for tx in txs {
+            let i = indexes
+                .get(&tx.hash())
+                .ok_or(FluffyBlockError::TxNotInBlock)?;
+            self.txs[*i] = Some(tx);
+        }
+        Ok(())
+    }
+
+    /// Returns the indexes of the transactions we don't have, for a `FluffyMissingTransactionsRequest`.
+    pub fn missing_tx_indices(&self) -> Vec<u64> {
+        self.txs
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(i, _)| i as u64)
+            .collect()
+    }
+
+    /// Adds the block's transactions that are in the pool.
+    pub async fn add_pool_txs<P>(&mut self, pool: P) -> Result<(), FluffyBlockError>
+    where
+        P: tower::Service<TxPoolRequest, Response = TxPoolResponse, Error = TxPoolError>,
+    {
+        let missing = self.missing_tx_indices();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let tx_hashes = missing
+            .iter()
+            .map(|&i| self.block.txs[i as usize])
+            .collect();
+        let TxPoolResponse::FluffyBlockTxs(txs) = pool
+            .oneshot(TxPoolRequest::FluffyBlockTxs(tx_hashes))
+            .await?
+        else {
+            return Err(FluffyBlockError::UnexpectedPoolResponse);
+        };
+
+        for (i, tx) in missing.into_iter().zip(txs) {
+            self.txs[i as usize] = tx.map(|tx| tx.tx);
+        }
+        Ok(())
+    }
+
+    /// Returns the full block, or [`FluffyBlockError::MissingTxs`] if we don't have every transaction.
+    pub fn complete(self) -> Result<FullBlock, FluffyBlockError> {
+        Ok(FullBlock {
+            txs: self
+                .txs
+                .into_iter()
+                .collect::<Option<_>>()
+                .ok_or(FluffyBlockError::MissingTxs)?,
+            block: self.block,
+        })
+    }
+}
+
+/// Rebuilds a fluffy block from the transactions sent with it, the pool and the announcing peer.
+///
+/// `request_missing` is only called if transactions are missing after checking the pool, with their
+/// indexes in the block. It should send the peer a `FluffyMissingTransactionsRequest` and return the
+/// transactions the peer sent back.
+pub async fn reconstruct_fluffy_block<P, F, Fut, E>(
+    block: Block,
+    txs: Vec<Transaction>,
+    pool: P,
+    request_missing: F,
+) -> Result<FullBlock, FluffyBlockError>
+where
+    P: tower::Service<TxPoolRequest, Response = TxPoolResponse, Error = TxPoolError>,
+    F: FnOnce(Vec<u64>) -> Fut,
+    Fut: Future<Output = Result<Vec<Transaction>, E>>,
+    E: Into<tower::BoxError>,
+{
+    let mut fluffy_block = FluffyBlock::new(block, txs)?;
+    fluffy_block.add_pool_txs(pool).await?;
+
+    let missing = fluffy_block.missing_tx_indices();
+    if !missing.is_empty() {
+        tracing::debug!(
+            target: BLOCK_TARGET,
+            missing = missing.len(),
+            txs = fluffy_block.txs.len(),
+            "Requesting the missing txs of a fluffy block"
+        );
+
+        let txs = request_missing(missing)
+            .await
+            .map_err(|e| FluffyBlockError::Request(e.into()))?;
+        fluffy_block.add_txs(txs)?;
+    }
+
+    fluffy_block.complete()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::ready;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::hardforks::HardFork;
+    use crate::test_utils::{dummy_block, dummy_miner_tx, dummy_output};
+    use crate::txpool::PoolTx;
+
+    /// Returns a transaction, transactions with different fees have different hashes.
+    fn tx(fee: u64) -> Transaction {
+        let mut tx = dummy_miner_tx(2, None, vec![dummy_output(None)]);
+        tx.rct_signatures.base.fee = fee;
+        tx
+    }
+
+    /// Returns a block holding these transactions.
+    fn fluffy_block(txs: &[Transaction]) -> Block {
+        let mut block = dummy_block(HardFork::V16, HardFork::V16, [0; 32], tx(0));
+        block.txs = txs.iter().map(Transaction::hash).collect();
+        block
+    }
+
+    /// Returns a pool holding these transactions.
+    fn pool(
+        txs: Vec<Transaction>,
+    ) -> impl tower::Service<
+        TxPoolRequest,
+        Response = TxPoolResponse,
+        Error = TxPoolError,
+        Future = std::future::Ready<Result<TxPoolResponse, TxPoolError>>,
+    > {
+        tower::service_fn(move |req| {
+            let TxPoolRequest::FluffyBlockTxs(tx_hashes) = req else {
+                panic!("Fluffy blocks only request their txs");
+            };
+            let txs = tx_hashes
+                .iter()
+                .map(|hash| {
+                    txs.iter().find(|tx| tx.hash() == *hash).map(|tx| PoolTx {
+                        tx: tx.clone(),
+                        hash: *hash,
+                        weight: 0,
+                        fee: 0,
+                        key_images: vec![],
+                        received_at: 0,
+                    })
+                })
+                .collect();
+            ready(Ok(TxPoolResponse::FluffyBlockTxs(txs)))
+        })
+    }
+
+    #[test]
+    fn blocks_are_rebuilt_from_the_pool_and_the_peer() {
+        let txs: Vec<_> = (1..=4).map(tx).collect();
+
+        // The peer sent tx 4 with the block, the pool has tx 1 and 3.
+        let full_block = block_on(reconstruct_fluffy_block(
+            fluffy_block(&txs),
+            vec![txs[3].clone()],
+            pool(vec![txs[2].clone(), txs[0].clone()]),
+            |missing| {
+                assert_eq!(missing, vec![1]);
+                ready(Ok::<_, tower::BoxError>(vec![txs[1].clone()]))
+            },
+        ))
+        .unwrap();
+        assert_eq!(full_block.txs, txs);
+
+        // The peer isn't asked for anything when the pool has every tx.
+        let full_block = block_on(reconstruct_fluffy_block(
+            fluffy_block(&txs),
+            vec![],
+            pool(txs.clone()),
+            |_| -> std::future::Ready<Result<_, tower::BoxError>> { panic!("No txs are missing") },
+        ))
+        .unwrap();
+        assert_eq!(full_block.txs, txs);
+    }
+
+    #[test]
+    fn peers_must_send_the_missing_txs() {
+        let txs: Vec<_> = (1..=2).map(tx).collect();
+
+        let requested = AtomicBool::new(false);
+        let err = block_on(reconstruct_fluffy_block(
+            fluffy_block(&txs),
+            vec![],
+            pool(vec![]),
+            |missing| {
+                requested.store(true, Ordering::Relaxed);
+                assert_eq!(missing, vec![0, 1]);
+                ready(Ok::<_, tower::BoxError>(vec![txs[0].clone()]))
+            },
+        ))
+        .unwrap_err();
+        assert!(requested.load(Ordering::Relaxed));
+        assert!(matches!(err, FluffyBlockError::MissingTxs));
+
+        // Transactions that aren't in the block are rejected.
+        assert!(matches!(
+            FluffyBlock::new(fluffy_block(&txs), vec![tx(5)]),
+            Err(FluffyBlockError::TxNotInBlock)
+        ));
+    }
+
+    #[test]
+    fn wrong_pool_responses_are_errors() {
+        let txs: Vec<_> = (1..=2).map(tx).collect();
+        let mut fluffy_block = FluffyBlock::new(fluffy_block(&txs), vec![]).unwrap();
+
+        let pool = tower::service_fn(|_| ready(Ok::<_, TxPoolError>(TxPoolResponse::Ok)));
+        assert!(matches!(
+            block_on(fluffy_block.add_pool_txs(pool)),
+            Err(FluffyBlockError::UnexpectedPoolResponse)
+        ));
+    }
+}
@@ -0,0 +1,236 @@
+//! # Fork Metrics
+//!
+//! This module contains [`ForkMetrics`], counters for the alt blocks we see and the reorgs we do, so
+//! operators can keep an eye on the health of the network.
+//!
+//! Along with the counts we keep a histogram of reorg depths and the total time an alt chain had more
+//! work than our main chain without being promoted. [`AltChainStats`] is a snapshot of the metrics,
+//! which can be written in the Prometheus text format.
+//!
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// The upper bounds of the reorg depth buckets, the last bucket holds everything above the last bound.
+const REORG_DEPTH_BUCKETS: [u64; 8] = [1, 2, 3, 5, 10, 20, 50, 100];
+
+/// A histogram of reorg depths.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReorgDepthHistogram {
+    counts: [u64; REORG_DEPTH_BUCKETS.len() + 1],
+    sum: u64,
+}
+
+impl ReorgDepthHistogram {
+    pub fn record(&mut self, depth: u64) {
+        let bucket = REORG_DEPTH_BUCKETS
+            .iter()
+            .position(|bound| depth <= *bound)
+            .unwrap_or(REORG_DEPTH_BUCKETS.len());
+
+        self.counts[bucket] += 1;
+        self.sum += depth;
+    }
+
+    /// Returns the amount of depths recorded.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the sum of all the depths recorded.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Returns the buckets as (upper bound, count) pairs, the last bucket has no upper bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<u64>, u64)> + '_ {
+        REORG_DEPTH_BUCKETS
+            .iter()
+            .copied()
+            .map(Some)
+            .chain([None])
+            .zip(self.counts.iter().copied())
+    }
+}
+
+/// Metrics on the alt blocks and reorgs seen by a verifier.
+#[derive(Debug, Default, Clone)]
+pub struct ForkMetrics {
+    alt_blocks: u64,
+    reorgs: u64,
+    max_reorg_depth: u64,
+    reorg_depths: ReorgDepthHistogram,
+    /// The total time an alt chain had more work than the main chain, not counting the current period.
+    time_below_alt_tip: Duration,
+    /// When an alt chain overtook the main chain, if it still has more work.
+    below_alt_tip_since: Option<Instant>,
+}
+
+impl ForkMetrics {
+    /// Records an alt block being added to an alt chain, with the cumulative difficulties of the
+    /// alt chain and the main chain after the block was added.
+    pub fn record_alt_block(
+        &mut self,
+        alt_cumulative_difficulty: u128,
+        main_cumulative_difficulty: u128,
+        now: Instant,
+    ) {
+        self.alt_blocks += 1;
+
+        if alt_cumulative_difficulty > main_cumulative_difficulty {
+            self.below_alt_tip_since.get_or_insert(now);
+        } else {
+            self.stop_below_alt_tip(now);
+        }
+    }
+
+    /// Records a reorg which removed `depth` blocks from the main chain.
+    pub fn record_reorg(&mut self, depth: u64, now: Instant) {
+        self.reorgs += 1;
+        self.max_reorg_depth = self.max_reorg_depth.max(depth);
+        self.reorg_depths.record(depth);
+        self.stop_below_alt_tip(now);
+    }
+
+    fn stop_below_alt_tip(&mut self, now: Instant) {
+        if let Some(since) = self.below_alt_tip_since.take() {
+            self.time_below_alt_tip += now.saturating_duration_since(since);
+        }
+    }
+
+    /// Returns a snapshot of the metrics.
+    pub fn stats(&self, now: Instant) -> AltChainStats {
+        let current_period = self
+            .below_alt_tip_since
+            .map(|since| now.saturating_duration_since(since))
+            .unwrap_or_default();
+
+        AltChainStats {
+            alt_blocks: self.alt_blocks,
+            reorgs: self.reorgs,
+            max_reorg_depth: self.max_reorg_depth,
+            reorg_depths: self.reorg_depths.clone(),
+            time_below_alt_tip: self.time_below_alt_tip + current_period,
+            below_alt_tip: self.below_alt_tip_since.is_some(),
+        }
+    }
+}
+
+/// A snapshot of the [`ForkMetrics`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AltChainStats {
+    /// The amount of alt blocks added to alt chains.
+    pub alt_blocks: u64,
+    /// The amount of times an alt chain was promoted to the main chain.
+    pub reorgs: u64,
+    /// The most blocks removed from the main chain in a single reorg.
+    pub max_reorg_depth: u64,
+    pub reorg_depths: ReorgDepthHistogram,
+    /// The total time an alt chain has had more work than the main chain.
+    pub time_below_alt_tip: Duration,
+    /// True if an alt chain currently has more work than the main chain.
+    pub below_alt_tip: bool,
+}
+
+impl AltChainStats {
+    /// Writes the stats in the Prometheus text format.
+    pub fn write_prometheus(&self, w: &mut impl Write) -> std::fmt::Result {
+        writeln!(
+            w,
+            "# HELP cuprate_alt_blocks_total Alt blocks added to alt chains."
+        )?;
+        writeln!(w, "# TYPE cuprate_alt_blocks_total counter")?;
+        writeln!(w, "cuprate_alt_blocks_total {}", self.alt_blocks)?;
+
+        writeln!(
+            w,
+            "# HELP cuprate_reorgs_total Alt chains promoted to the main chain."
+        )?;
+        writeln!(w, "# TYPE cuprate_reorgs_total counter")?;
+        writeln!(w, "cuprate_reorgs_total {}", self.reorgs)?;
+
+        writeln!(
+            w,
+            "# HELP cuprate_reorg_depth Blocks removed from the main chain by reorgs."
+        )?;
+        writeln!(w, "# TYPE cuprate_reorg_depth histogram")?;
+        let mut cumulative = 0;
+        for (bound, count) in self.reorg_depths.buckets() {
+            cumulative += count;
+            match bound {
+                Some(bound) => writeln!(
+                    w,
+                    "cuprate_reorg_depth_bucket{{le=\"{}\"}} {}",
+                    bound, cumulative
+                )?,
+                None => writeln!(
+                    w,
+                    "cuprate_reorg_depth_bucket{{le=\"+Inf\"}} {}",
+                    cumulative
+                )?,
+            }
+        }
+        writeln!(w, "cuprate_reorg_depth_sum {}", self.reorg_depths.sum())?;
+        writeln!(w, "cuprate_reorg_depth_count {}", self.reorg_depths.count())?;
+
+        writeln!(
+            w,
+            "# HELP cuprate_below_alt_tip_seconds_total Time an alt chain had more work than the main chain."
+        )?;
+        writeln!(w, "# TYPE cuprate_below_alt_tip_seconds_total counter")?;
+        writeln!(
+            w,
+            "cuprate_below_alt_tip_seconds_total {}",
+            self.time_below_alt_tip.as_secs_f64()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::ForkMetrics;
+
+    #[test]
+    fn time_below_alt_tip() {
+        let start = Instant::now();
+        let mut metrics = ForkMetrics::default();
+
+        metrics.record_alt_block(10, 20, start);
+        metrics.record_alt_block(30, 20, start + Duration::from_secs(1));
+        metrics.record_alt_block(40, 20, start + Duration::from_secs(2));
+
+        let stats = metrics.stats(start + Duration::from_secs(4));
+        assert!(stats.below_alt_tip);
+        assert_eq!(stats.time_below_alt_tip, Duration::from_secs(3));
+
+        metrics.record_reorg(3, start + Duration::from_secs(5));
+        metrics.record_reorg(30, start + Duration::from_secs(10));
+
+        let stats = metrics.stats(start + Duration::from_secs(20));
+        assert!(!stats.below_alt_tip);
+        assert_eq!(stats.time_below_alt_tip, Duration::from_secs(4));
+        assert_eq!(stats.alt_blocks, 3);
+        assert_eq!(stats.reorgs, 2);
+        assert_eq!(stats.max_reorg_depth, 30);
+    }
+
+    #[test]
+    fn prometheus_reorg_depth_buckets_are_cumulative() {
+        let now = Instant::now();
+        let mut metrics = ForkMetrics::default();
+        metrics.record_reorg(1, now);
+        metrics.record_reorg(4, now);
+        metrics.record_reorg(1000, now);
+
+        let mut out = String::new();
+        metrics.stats(now).write_prometheus(&mut out).unwrap();
+
+        assert!(out.contains("cuprate_reorg_depth_bucket{le=\"1\"} 1\n"));
+        assert!(out.contains("cuprate_reorg_depth_bucket{le=\"5\"} 2\n"));
+        assert!(out.contains("cuprate_reorg_depth_bucket{le=\"100\"} 2\n"));
+        assert!(out.contains("cuprate_reorg_depth_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("cuprate_reorg_depth_sum 1005\n"));
+        assert!(out.contains("cuprate_reorgs_total 3\n"));
+    }
+}
@@ -1,26 +1,54 @@
-/// This module contains the code to generate Monero's genesis blocks.
+/// This module contains the code to generate Monero's genesis blocks, and to write the genesis block
+/// to an empty database so a node can sync from height 0, see [`seed_genesis_block`].
 ///
 /// ref: consensus-doc#Genesis
 use monero_serai::{
     block::{Block, BlockHeader},
     transaction::Transaction,
 };
+use tower::ServiceExt;
 
 use cuprate_common::Network;
 
-fn genesis_nonce(network: &Network) -> u32 {
+use crate::{
+    block::{VerifiedBlockInformation, VerifiedBlockTxs},
+    spans::CONTEXT_TARGET,
+    ConsensusError, Database, DatabaseRequest, InternalError,
+};
+
+/// Returns the nonce of the network's genesis block.
+pub fn genesis_nonce(network: &Network) -> u32 {
     match network {
-        Network::Mainnet => 10000,
+        Network::Mainnet | Network::Regtest => 10000,
         Network::Testnet => 10001,
         Network::Stagenet => 10002,
     }
 }
 
-fn genesis_miner_tx(network: &Network) -> Transaction {
-    Transaction::read(&mut hex::decode(match network {
-        Network::Mainnet | Network::Testnet  => "013c01ff0001ffffffffffff03029b2e4c0281c0b02e7c53291a94d1d0cbff8883f8024f5142ee494ffbbd08807121017767aafcde9be00dcfd098715ebcf7f410daebc582fda69d24a28e9d0bc890d1",
+/// Returns the blob of the network's genesis miner transaction.
+pub fn genesis_miner_tx_blob(network: &Network) -> Vec<u8> {
+    hex::decode(match network {
+        Network::Mainnet | Network::Testnet | Network::Regtest => "013c01ff0001ffffffffffff03029b2e4c0281c0b02e7c53291a94d1d0cbff8883f8024f5142ee494ffbbd08807121017767aafcde9be00dcfd098715ebcf7f410daebc582fda69d24a28e9d0bc890d1",
         Network::Stagenet => "013c01ff0001ffffffffffff0302df5d56da0c7d643ddd1ce61901c7bdc5fb1738bfe39fbe69c28a3a7032729c0f2101168d0c4ca86fb55a4cf6a36d31431be1c53a3bd7411bb24e8832410289fa6f3b"
-    }).unwrap().as_slice()).unwrap()
+    }).unwrap()
+}
+
+fn genesis_miner_tx(network: &Network) -> Transaction {
+    Transaction::read(&mut genesis_miner_tx_blob(network).as_slice()).unwrap()
+}
+
+/// Returns the hash of the network's genesis block.
+pub fn genesis_hash(network: &Network) -> [u8; 32] {
+    hex::decode(match network {
+        Network::Mainnet | Network::Regtest => {
+            "418015bb9ae982a1975da7d79277c2705727a56894ba0fb246adaabb1f4632e3"
+        }
+        Network::Testnet => "48ca7cd3c8de5b6a4d53d2861fbdaedca141553559f9be9520068053cda8430b",
+        Network::Stagenet => "76ee3cc98646292206cd3e86f74d88b4dcc1d937088645e9b0cbca84b7ce74eb",
+    })
+    .unwrap()
+    .try_into()
+    .unwrap()
 }
 
 /// Generates the Monero genesis block.
@@ -40,11 +68,100 @@ pub fn generate_genesis_block(network: &Network) -> Block {
     }
 }
 
+/// Returns the network's genesis block with everything the database needs to add it, see
+/// [`DatabaseRequest::WriteBlock`].
+///
+/// The genesis block is not verified, its PoW is never checked so its PoW hash is left empty.
+pub fn genesis_block_information(network: &Network) -> VerifiedBlockInformation {
+    let block = generate_genesis_block(network);
+    // The miner transaction is version 1, so its weight is its size.
+    let weight = genesis_miner_tx_blob(network).len();
+    let generated_coins = block
+        .miner_tx
+        .prefix
+        .outputs
+        .iter()
+        .map(|output| output.amount.unwrap_or(0))
+        .sum();
+
+    VerifiedBlockInformation {
+        block,
+        txs: VerifiedBlockTxs::Full(vec![]),
+        block_hash: genesis_hash(network),
+        pow_hash: [0; 32],
+        height: 0,
+        generated_coins,
+        weight,
+        long_term_weight: weight,
+        cumulative_difficulty: 1,
+    }
+}
+
+/// Writes the network's genesis block to the database if it is empty, returning the chain height.
+///
+/// If the database isn't empty its genesis block is checked to be the network's, so a database of
+/// another network is not built on.
+pub async fn seed_genesis_block<D: Database>(
+    network: &Network,
+    mut database: D,
+) -> Result<u64, ConsensusError> {
+    let chain_height = database
+        .ready()
+        .await?
+        .call(DatabaseRequest::ChainHeight)
+        .await?
+        .into_chain_height()?;
+
+    let expected = genesis_hash(network);
+    if chain_height > 0 {
+        let got = database
+            .ready()
+            .await?
+            .call(DatabaseRequest::BlockHash(0))
+            .await?
+            .into_block_hash()?;
+
+        if got != expected {
+            return Err(InternalError::GenesisMismatch {
+                network: *network,
+                expected,
+                got,
+            }
+            .into());
+        }
+        return Ok(chain_height);
+    }
+
+    tracing::info!(
+        target: CONTEXT_TARGET,
+        network = ?network,
+        hash = hex::encode(expected),
+        "The database is empty, writing the genesis block"
+    );
+    database
+        .ready()
+        .await?
+        .call(DatabaseRequest::WriteBlock(Box::new(
+            genesis_block_information(network),
+        )))
+        .await?;
+
+    Ok(1)
+}
+
 #[cfg(test)]
 mod tests {
+    use futures::{executor::block_on, future::ready};
+    use tower::Service;
+
     use cuprate_common::Network;
 
-    use super::generate_genesis_block;
+    use super::*;
+    use crate::{
+        test_utils::{DummyBlockExtendedHeader, DummyDatabase, DummyDatabaseBuilder},
+        verifier::{Config, Verifier},
+        DatabaseResponse,
+    };
 
     #[test]
     fn generate_genesis_blocks() {
@@ -66,5 +183,70 @@ mod tests {
                 .unwrap()
                 .as_slice()
         );
+
+        for network in [Network::Mainnet, Network::Testnet, Network::Stagenet] {
+            assert_eq!(
+                generate_genesis_block(&network).hash(),
+                genesis_hash(&network)
+            );
+        }
+    }
+
+    /// A [`DummyDatabase`] that adds the blocks written to it.
+    fn writable(
+        database: DummyDatabase,
+    ) -> impl Database<Future = futures::future::Ready<Result<DatabaseResponse, tower::BoxError>>> + Clone
+    {
+        tower::service_fn(move |req| match req {
+            DatabaseRequest::WriteBlock(block) => {
+                database.add_block(
+                    DummyBlockExtendedHeader::default()
+                        .with_weight(block.weight, block.long_term_weight)
+                        .with_generated_coins(block.generated_coins),
+                );
+                ready(Ok(DatabaseResponse::WriteBlock))
+            }
+            req => database.clone().call(req),
+        })
+    }
+
+    #[test]
+    fn genesis_block_information() {
+        let info = super::genesis_block_information(&Network::Stagenet);
+        assert_eq!(info.height, 0);
+        assert_eq!(info.block_hash, genesis_hash(&Network::Stagenet));
+        assert_eq!(info.block.header.nonce, 10002);
+        assert_eq!(info.weight, 80);
+        assert_eq!(info.cumulative_difficulty, 1);
+    }
+
+    #[test]
+    fn empty_database_is_seeded() {
+        let database = DummyDatabaseBuilder::default().finish();
+
+        let verifier = block_on(Verifier::init_with_genesis(
+            Config::main_net(),
+            writable(database.clone()),
+        ))
+        .unwrap();
+        assert_eq!(verifier.context().chain_height, 1);
+        assert!(database.block_hash(0).is_some());
+        assert!(database.block_hash(1).is_none());
+    }
+
+    #[test]
+    fn database_of_another_network_is_rejected() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+
+        let err = block_on(seed_genesis_block(&Network::Mainnet, database)).unwrap_err();
+        assert!(matches!(
+            err,
+            ConsensusError::Internal(InternalError::GenesisMismatch {
+                network: Network::Mainnet,
+                ..
+            })
+        ));
     }
 }
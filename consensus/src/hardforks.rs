@@ -1,40 +1,116 @@
+use std::collections::VecDeque;
 use std::fmt::{Display, Formatter};
 use std::ops::Range;
+use std::pin::pin;
+use std::time::Duration;
 
+use futures::TryStreamExt;
 use monero_serai::block::BlockHeader;
 use tower::ServiceExt;
 use tracing::instrument;
 
 use cuprate_common::Network;
 
-use crate::{ConsensusError, Database, DatabaseRequest, DatabaseResponse};
+use crate::{
+    consensus_constants::{
+        ConsensusConstants, DIFFICULTY_TARGET_V1, DIFFICULTY_TARGET_V2, NUMB_OF_HARD_FORKS,
+    },
+    range_chunk_responses,
+    spans::CONTEXT_TARGET,
+    ConsensusError, Database, DatabaseRequest, HardForkError, InternalError,
+};
 
 // https://cuprate.github.io/monero-docs/consensus_rules/hardforks.html#accepting-a-fork
 const DEFAULT_WINDOW_SIZE: u64 = 10080; // supermajority window check length - a week
 
-#[derive(Debug, Clone, Copy)]
+/// The default percentage of the window that has to vote for a hard-fork we don't know about before
+/// we warn that the node needs updating, monerod's default fork threshold.
+pub const DEFAULT_UNKNOWN_FORK_THRESHOLD: u64 = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BlockHFInfo {
-    version: HardFork,
-    vote: HardFork,
+    pub version: HardFork,
+    pub vote: HardFork,
+    /// The block's `minor_version` field, [`BlockHFInfo::vote`] is the latest hard-fork we know
+    /// about if this is a later one.
+    pub raw_vote: u8,
 }
 
 impl BlockHFInfo {
-    pub fn from_block_header(block_header: &BlockHeader) -> Result<BlockHFInfo, ConsensusError> {
+    pub fn from_block_header(block_header: &BlockHeader) -> Result<BlockHFInfo, HardForkError> {
         BlockHFInfo::from_major_minor(block_header.major_version, block_header.minor_version)
     }
 
     pub fn from_major_minor(
         major_version: u8,
         minor_version: u8,
-    ) -> Result<BlockHFInfo, ConsensusError> {
+    ) -> Result<BlockHFInfo, HardForkError> {
         Ok(BlockHFInfo {
             version: HardFork::from_version(&major_version)?,
             vote: HardFork::from_vote(&minor_version),
+            raw_vote: minor_version,
         })
     }
+
+    /// Returns the block's vote, keeping votes for hard-forks we don't know about.
+    pub fn vote_version(&self) -> HardForkVersion {
+        match HardForkVersion::from_version(self.raw_vote) {
+            // A vote of 0 is interpreted as 1, see [`HardFork::from_vote`].
+            HardForkVersion::UnknownFork(0) => HardForkVersion::Known(HardFork::V1),
+            version => version,
+        }
+    }
+}
+
+/// A block version or vote, which may be for a hard-fork after [`HardFork::LATEST`].
+///
+/// A version we don't know about means the network may have upgraded past this node, see
+/// [`UnknownForkPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HardForkVersion {
+    Known(HardFork),
+    UnknownFork(u8),
+}
+
+impl HardForkVersion {
+    /// Returns the version of a blocks `major_version` or `minor_version` field.
+    pub fn from_version(version: u8) -> HardForkVersion {
+        match HardFork::from_version(&version) {
+            Ok(hf) => HardForkVersion::Known(hf),
+            Err(_) => HardForkVersion::UnknownFork(version),
+        }
+    }
+
+    /// Returns the hard-fork a vote counts for, votes for a hard-fork we don't know about count for
+    /// [`HardFork::LATEST`].
+    pub fn as_vote(&self) -> HardFork {
+        match self {
+            HardForkVersion::Known(hf) => *hf,
+            HardForkVersion::UnknownFork(_) => HardFork::LATEST,
+        }
+    }
+}
+
+impl From<HardFork> for HardForkVersion {
+    fn from(hf: HardFork) -> Self {
+        HardForkVersion::Known(hf)
+    }
+}
+
+/// What to do with a block whose version is a hard-fork we don't know about.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownForkPolicy {
+    /// Reject the block as invalid with [`HardForkError::UnknownVersion`], like monerod.
+    #[default]
+    RejectBlock,
+    /// Warn that the node needs updating and return [`HardForkError::UnknownFork`], which is not the
+    /// peer's fault, so the caller can stop syncing instead of banning the peers that are ahead.
+    HaltAndWarn,
 }
 
 /// An identifier for every hard-fork Monero has had.
+///
+/// A new hard-fork is a variant here and a row in [`HARD_FORKS`].
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
 #[repr(u8)]
 pub enum HardFork {
@@ -53,38 +129,101 @@ pub enum HardFork {
     V13,
     V14,
     V15,
-    // remember to update from_vote!
     V16,
 }
 
+/// The heights a hard-fork activates at on each network, see [`HARD_FORKS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForkInfo {
+    pub hard_fork: HardFork,
+    pub mainnet_height: u64,
+    pub testnet_height: u64,
+    pub stagenet_height: u64,
+}
+
+const fn fork(
+    hard_fork: HardFork,
+    mainnet_height: u64,
+    testnet_height: u64,
+    stagenet_height: u64,
+) -> ForkInfo {
+    ForkInfo {
+        hard_fork,
+        mainnet_height,
+        testnet_height,
+        stagenet_height,
+    }
+}
+
+/// Every hard-fork, in order, with the heights it activates at on main-net, test-net and stage-net.
+///
+/// https://cuprate.github.io/monero-docs/consensus_rules/hardforks.html#Mainnet-Hard-Forks
+pub const HARD_FORKS: &[ForkInfo] = &[
+    // Monero core has V1 at 1 on main-net, which is strange.
+    fork(HardFork::V1, 0, 0, 0),
+    fork(HardFork::V2, 1009827, 624634, 32000),
+    fork(HardFork::V3, 1141317, 624635, 33000),
+    fork(HardFork::V4, 1220516, 624636, 34000),
+    fork(HardFork::V5, 1288616, 800500, 35000),
+    fork(HardFork::V6, 1400000, 801219, 36000),
+    fork(HardFork::V7, 1546000, 802660, 37000),
+    fork(HardFork::V8, 1685555, 971400, 176456),
+    fork(HardFork::V9, 1686275, 971500, 177176),
+    fork(HardFork::V10, 1788000, 1057027, 269000),
+    fork(HardFork::V11, 1788720, 1057058, 269720),
+    fork(HardFork::V12, 1978433, 1154318, 454721),
+    fork(HardFork::V13, 2210000, 1546000, 675405),
+    fork(HardFork::V14, 2210720, 1546120, 676125),
+    fork(HardFork::V15, 2688888, 1982800, 1151000),
+    fork(HardFork::V16, 2689608, 1983520, 1151720),
+];
+
+/// Fails the build if a row of [`HARD_FORKS`] is out of order.
+const _: () = {
+    let mut i = 0;
+    while i < HARD_FORKS.len() {
+        assert!(
+            HARD_FORKS[i].hard_fork as usize == i + 1,
+            "HARD_FORKS must have a row for every hard-fork, in order"
+        );
+        i += 1;
+    }
+};
+
 impl HardFork {
+    /// The latest hard-fork we know about, new blocks vote for it.
+    pub const LATEST: HardFork = HARD_FORKS[HARD_FORKS.len() - 1].hard_fork;
+
+    /// Every hard-fork, in order.
+    pub const ALL: [HardFork; NUMB_OF_HARD_FORKS] = {
+        let mut all = [HardFork::V1; NUMB_OF_HARD_FORKS];
+        let mut i = 0;
+        while i < NUMB_OF_HARD_FORKS {
+            all[i] = HARD_FORKS[i].hard_fork;
+            i += 1;
+        }
+        all
+    };
+
+    /// Returns the latest hard-fork we know about, see [`HardFork::LATEST`].
+    pub const fn latest() -> HardFork {
+        HardFork::LATEST
+    }
+
+    /// Returns the hard-fork's row of [`HARD_FORKS`].
+    pub fn info(&self) -> &'static ForkInfo {
+        &HARD_FORKS[*self as usize - 1]
+    }
+
     /// Returns the hard-fork for a blocks `major_version` field.
     ///
     /// https://cuprate.github.io/monero-docs/consensus_rules/hardforks.html#blocks-version-and-vote
-    pub fn from_version(version: &u8) -> Result<HardFork, ConsensusError> {
-        Ok(match version {
-            1 => HardFork::V1,
-            2 => HardFork::V2,
-            3 => HardFork::V3,
-            4 => HardFork::V4,
-            5 => HardFork::V5,
-            6 => HardFork::V6,
-            7 => HardFork::V7,
-            8 => HardFork::V8,
-            9 => HardFork::V9,
-            10 => HardFork::V10,
-            11 => HardFork::V11,
-            12 => HardFork::V12,
-            13 => HardFork::V13,
-            14 => HardFork::V14,
-            15 => HardFork::V15,
-            16 => HardFork::V16,
-            _ => {
-                return Err(ConsensusError::InvalidHardForkVersion(
-                    "Version is not a known hard fork",
-                ))
-            }
-        })
+    pub fn from_version(version: &u8) -> Result<HardFork, HardForkError> {
+        usize::from(*version)
+            .checked_sub(1)
+            .and_then(|i| HardFork::ALL.get(i))
+            .copied()
+            .ok_or(HardForkError::UnknownVersion(*version))
     }
 
     /// Returns the hard-fork for a blocks `minor_version` (vote) field.
@@ -96,12 +235,21 @@ impl HardFork {
             return HardFork::V1;
         }
         // This must default to the latest hard-fork!
-        Self::from_version(vote).unwrap_or(HardFork::V16)
+        Self::from_version(vote).unwrap_or(HardFork::LATEST)
     }
 
     /// Returns the next hard-fork.
     pub fn next_fork(&self) -> Option<HardFork> {
-        HardFork::from_version(&(*self as u8 + 1)).ok()
+        HardFork::ALL.get(*self as usize).copied()
+    }
+
+    /// Returns the target time between blocks.
+    pub fn block_time(&self) -> Duration {
+        if self == &HardFork::V1 {
+            Duration::from_secs(DIFFICULTY_TARGET_V1)
+        } else {
+            Duration::from_secs(DIFFICULTY_TARGET_V2)
+        }
     }
 
     /// Returns the threshold of this fork.
@@ -119,43 +267,7 @@ impl HardFork {
 
     /// Returns the minimum height this fork will activate at
     pub fn fork_height(&self, network: &Network) -> u64 {
-        match network {
-            Network::Mainnet => self.mainnet_fork_height(),
-            Network::Stagenet => self.stagenet_fork_height(),
-            Network::Testnet => self.testnet_fork_height(),
-        }
-    }
-
-    /// https://cuprate.github.io/monero-docs/consensus_rules/hardforks.html#Stagenet-Hard-Forks
-    fn stagenet_fork_height(&self) -> u64 {
-        todo!()
-    }
-
-    /// https://cuprate.github.io/monero-docs/consensus_rules/hardforks.html#Testnet-Hard-Forks
-    fn testnet_fork_height(&self) -> u64 {
-        todo!()
-    }
-
-    /// https://cuprate.github.io/monero-docs/consensus_rules/hardforks.html#Mainnet-Hard-Forks
-    fn mainnet_fork_height(&self) -> u64 {
-        match self {
-            HardFork::V1 => 0, // Monero core has this as 1, which is strange
-            HardFork::V2 => 1009827,
-            HardFork::V3 => 1141317,
-            HardFork::V4 => 1220516,
-            HardFork::V5 => 1288616,
-            HardFork::V6 => 1400000,
-            HardFork::V7 => 1546000,
-            HardFork::V8 => 1685555,
-            HardFork::V9 => 1686275,
-            HardFork::V10 => 1788000,
-            HardFork::V11 => 1788720,
-            HardFork::V12 => 1978433,
-            HardFork::V13 => 2210000,
-            HardFork::V14 => 2210720,
-            HardFork::V15 => 2688888,
-            HardFork::V16 => 2689608,
-        }
+        ConsensusConstants::for_network(network).fork_height(self)
     }
 
     /// Returns if the hard-fork is in range:
@@ -167,49 +279,88 @@ impl HardFork {
 }
 
 /// A struct holding the current voting state of the blockchain.
+///
+/// The votes in the window are kept, so votes leaving the window can be removed without asking the
+/// database. Consecutive blocks usually vote the same way, so they are kept as runs of the same vote.
 #[derive(Debug, Default, Clone)]
-struct HFVotes {
-    votes: [u64; 16],
+pub(crate) struct HFVotes {
+    votes: [u64; NUMB_OF_HARD_FORKS],
+    /// The votes for hard-forks we don't know about, these are also counted for [`HardFork::LATEST`].
+    unknown_votes: u64,
+    /// The votes in the window as (vote, amount of blocks) runs, oldest first.
+    vote_list: VecDeque<(HardForkVersion, u64)>,
 }
 
 impl Display for HFVotes {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("HFVotes")
-            .field("total", &self.total_votes())
-            .field("V1", &self.votes_for_hf(&HardFork::V1))
-            .field("V2", &self.votes_for_hf(&HardFork::V2))
-            .field("V3", &self.votes_for_hf(&HardFork::V3))
-            .field("V4", &self.votes_for_hf(&HardFork::V4))
-            .field("V5", &self.votes_for_hf(&HardFork::V5))
-            .field("V6", &self.votes_for_hf(&HardFork::V6))
-            .field("V7", &self.votes_for_hf(&HardFork::V7))
-            .field("V8", &self.votes_for_hf(&HardFork::V8))
-            .field("V9", &self.votes_for_hf(&HardFork::V9))
-            .field("V10", &self.votes_for_hf(&HardFork::V10))
-            .field("V11", &self.votes_for_hf(&HardFork::V11))
-            .field("V12", &self.votes_for_hf(&HardFork::V12))
-            .field("V13", &self.votes_for_hf(&HardFork::V13))
-            .field("V14", &self.votes_for_hf(&HardFork::V14))
-            .field("V15", &self.votes_for_hf(&HardFork::V15))
-            .field("V16", &self.votes_for_hf(&HardFork::V16))
-            .finish()
+        let mut debug = f.debug_struct("HFVotes");
+        debug.field("total", &self.total_votes());
+        for hf in HardFork::ALL {
+            debug.field(&format!("{hf:?}"), &self.votes_for_hf(&hf));
+        }
+        debug.field("unknown", &self.unknown_votes);
+        debug.finish()
     }
 }
 
 impl HFVotes {
-    /// Add votes for a hard-fork
-    pub fn add_votes_for_hf(&mut self, hf: &HardFork, votes: u64) {
-        self.votes[*hf as usize - 1] += votes;
+    /// Add a vote for a hard-fork, this should be the newest block's vote.
+    pub fn push_back(&mut self, vote: impl Into<HardForkVersion>) {
+        self.push_back_n(vote, 1);
     }
 
-    /// Add a vote for a hard-fork.
-    pub fn add_vote_for_hf(&mut self, hf: &HardFork) {
-        self.add_votes_for_hf(hf, 1)
+    /// Add `count` votes for a hard-fork, these should be the votes of the newest blocks.
+    pub fn push_back_n(&mut self, vote: impl Into<HardForkVersion>, count: u64) {
+        if count == 0 {
+            return;
+        }
+
+        let vote = vote.into();
+        self.add_votes(vote, count);
+        match self.vote_list.back_mut() {
+            Some((last_vote, last_count)) if *last_vote == vote => *last_count += count,
+            _ => self.vote_list.push_back((vote, count)),
+        }
+    }
+
+    fn add_votes(&mut self, vote: HardForkVersion, count: u64) {
+        self.votes[vote.as_vote() as usize - 1] += count;
+        if let HardForkVersion::UnknownFork(_) = vote {
+            self.unknown_votes += count;
+        }
+    }
+
+    fn remove_votes(&mut self, vote: HardForkVersion, count: u64) {
+        self.votes[vote.as_vote() as usize - 1] -= count;
+        if let HardForkVersion::UnknownFork(_) = vote {
+            self.unknown_votes -= count;
+        }
+    }
+
+    /// Remove the oldest vote, returning it.
+    pub fn pop_front(&mut self) -> Option<HardForkVersion> {
+        let (hf, _) = *self.vote_list.front()?;
+        self.pop_front_n(1);
+        Some(hf)
     }
 
-    /// Remove a vote for a hard-fork.
-    pub fn remove_vote_for_hf(&mut self, hf: &HardFork) {
-        self.votes[*hf as usize - 1] -= 1;
+    /// Remove the `count` oldest votes, or every vote if there are less than `count`.
+    pub fn pop_front_n(&mut self, mut count: u64) {
+        while count > 0 {
+            let Some((vote, run_count)) = self.vote_list.front_mut() else {
+                return;
+            };
+
+            let vote = *vote;
+            let removed = count.min(*run_count);
+            *run_count -= removed;
+            count -= removed;
+            if *run_count == 0 {
+                self.vote_list.pop_front();
+            }
+
+            self.remove_votes(vote, removed);
+        }
     }
 
     /// Returns the total votes for a hard-fork.
@@ -223,23 +374,270 @@ impl HFVotes {
     pub fn total_votes(&self) -> u64 {
         self.votes.iter().sum()
     }
+
+    /// Returns the amount of votes for each hard-fork, index 0 is [`HardFork::V1`].
+    ///
+    /// Unlike [`HFVotes::votes_for_hf`] these don't include votes for later forks.
+    pub fn raw_votes(&self) -> [u64; NUMB_OF_HARD_FORKS] {
+        self.votes
+    }
+
+    /// Returns the amount of votes for hard-forks we don't know about.
+    pub fn unknown_votes(&self) -> u64 {
+        self.unknown_votes
+    }
+
+    /// Returns the highest hard-fork we don't know about that is voted for, if any.
+    pub fn highest_unknown_vote(&self) -> Option<u8> {
+        self.vote_list
+            .iter()
+            .filter_map(|(vote, _)| match vote {
+                HardForkVersion::UnknownFork(version) => Some(*version),
+                HardForkVersion::Known(_) => None,
+            })
+            .max()
+    }
+}
+
+/// The votes of a batch of consecutive blocks, pre-aggregated into runs of the same vote so they can
+/// be added to the [`HardForkState`] in one go, see [`HardForkState::new_blocks`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HFVoteBatch {
+    /// The height of the first block in the batch.
+    start_height: u64,
+    /// The votes as (vote, amount of blocks) runs, oldest first.
+    runs: Vec<(HardForkVersion, u64)>,
+}
+
+impl HFVoteBatch {
+    /// Creates an empty batch, starting at the block at `start_height`.
+    pub fn new(start_height: u64) -> HFVoteBatch {
+        HFVoteBatch {
+            start_height,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Adds the vote of the next block in the batch.
+    pub fn push(&mut self, vote: impl Into<HardForkVersion>) {
+        self.push_n(vote, 1);
+    }
+
+    /// Adds the votes of the next `count` blocks in the batch, which all vote for `vote`.
+    pub fn push_n(&mut self, vote: impl Into<HardForkVersion>, count: u64) {
+        if count == 0 {
+            return;
+        }
+
+        let vote = vote.into();
+        match self.runs.last_mut() {
+            Some((last_vote, last_count)) if *last_vote == vote => *last_count += count,
+            _ => self.runs.push((vote, count)),
+        }
+    }
+
+    /// Returns the amount of blocks in the batch.
+    pub fn len(&self) -> u64 {
+        self.runs.iter().map(|(_, count)| count).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+}
+
+/// How the hard-fork state is checked against the database when it is initialized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConsistencyCheck {
+    /// Trust the version stored in the top block.
+    #[default]
+    None,
+    /// Recompute the version of the top block from the fork heights and the vote window and return
+    /// [`InternalError::CorruptDatabase`] if it does not match the stored version.
+    Verify,
+    /// Like [`ConsistencyCheck::Verify`] but on a mismatch the state is rebuilt by trusting the block
+    /// before `trusted_height` and re-scanning the votes of the blocks after it.
+    Repair { trusted_height: u64 },
 }
 
 /// Configuration for hard-forks.
 ///
 #[derive(Debug, Clone)]
 pub struct HardForkConfig {
-    /// The network we are on.
-    network: Network,
+    /// The constants of the network we are on.
+    constants: ConsensusConstants,
     /// The amount of votes we are taking into account to decide on a fork activation.
     window: u64,
+    /// How the state is checked against the database on init.
+    consistency_check: ConsistencyCheck,
+    /// What to do with blocks whose version we don't know about.
+    unknown_fork_policy: UnknownForkPolicy,
+    /// The percentage of the window that has to vote for a hard-fork we don't know about before we
+    /// warn that the node needs updating.
+    unknown_fork_threshold: u64,
 }
 
 impl HardForkConfig {
+    /// Returns a [`HardForkConfigBuilder`], for configs with a different window or fork heights.
+    pub fn builder() -> HardForkConfigBuilder {
+        HardForkConfigBuilder::default()
+    }
+
     pub fn main_net() -> HardForkConfig {
+        Self::for_network(Network::Mainnet)
+    }
+
+    pub fn for_network(network: Network) -> HardForkConfig {
+        Self::from_constants(ConsensusConstants::for_network(&network))
+    }
+
+    /// Returns the config for a network with these constants, see [`ConsensusConstants`].
+    pub fn from_constants(constants: ConsensusConstants) -> HardForkConfig {
         Self {
-            network: Network::Mainnet,
+            constants,
             window: DEFAULT_WINDOW_SIZE,
+            consistency_check: ConsistencyCheck::None,
+            unknown_fork_policy: UnknownForkPolicy::default(),
+            unknown_fork_threshold: DEFAULT_UNKNOWN_FORK_THRESHOLD,
+        }
+    }
+
+    /// Sets the [`ConsistencyCheck`] done on init.
+    pub fn with_consistency_check(mut self, consistency_check: ConsistencyCheck) -> HardForkConfig {
+        self.consistency_check = consistency_check;
+        self
+    }
+
+    /// Sets the [`UnknownForkPolicy`] for blocks with a version we don't know about.
+    pub fn with_unknown_fork_policy(mut self, policy: UnknownForkPolicy) -> HardForkConfig {
+        self.unknown_fork_policy = policy;
+        self
+    }
+
+    /// Returns the [`UnknownForkPolicy`] for blocks with a version we don't know about.
+    pub fn unknown_fork_policy(&self) -> UnknownForkPolicy {
+        self.unknown_fork_policy
+    }
+
+    /// Returns the amount of votes in the window for hard-forks we don't know about needed before we
+    /// warn that the node needs updating.
+    pub fn unknown_fork_votes_needed(&self) -> u64 {
+        (self.unknown_fork_threshold * self.window).div_ceil(100)
+    }
+
+    /// Returns the network we are on.
+    pub fn network(&self) -> Network {
+        self.constants.network()
+    }
+
+    /// Returns the constants of the network we are on.
+    pub fn constants(&self) -> &ConsensusConstants {
+        &self.constants
+    }
+
+    /// Returns the amount of blocks whose votes are counted.
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+}
+
+/// A builder for a [`HardForkConfig`], so tests and private networks can shrink the voting window
+/// and move fork heights.
+///
+/// ```ignore
+/// let config = HardForkConfig::builder()
+///     .network(Network::Testnet)
+///     .window(100)
+///     .fork_height_override(HardFork::V16, 2_000_000)
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct HardForkConfigBuilder {
+    constants: ConsensusConstants,
+    window: u64,
+    fork_height_overrides: Vec<(HardFork, u64)>,
+    consistency_check: ConsistencyCheck,
+    unknown_fork_policy: UnknownForkPolicy,
+    unknown_fork_threshold: u64,
+}
+
+impl Default for HardForkConfigBuilder {
+    fn default() -> Self {
+        HardForkConfigBuilder {
+            constants: ConsensusConstants::for_network(&Network::Mainnet),
+            window: DEFAULT_WINDOW_SIZE,
+            fork_height_overrides: vec![],
+            consistency_check: ConsistencyCheck::None,
+            unknown_fork_policy: UnknownForkPolicy::default(),
+            unknown_fork_threshold: DEFAULT_UNKNOWN_FORK_THRESHOLD,
+        }
+    }
+}
+
+impl HardForkConfigBuilder {
+    /// Sets the network, defaults to [`Network::Mainnet`].
+    pub fn network(self, network: Network) -> Self {
+        self.constants(ConsensusConstants::for_network(&network))
+    }
+
+    /// Sets the network's constants, see [`ConsensusConstants`].
+    pub fn constants(mut self, constants: ConsensusConstants) -> Self {
+        self.constants = constants;
+        self
+    }
+
+    /// Sets the amount of blocks whose votes are counted, defaults to a week of blocks.
+    pub fn window(mut self, window: u64) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Moves the height a hard-fork activates at.
+    pub fn fork_height_override(mut self, hf: HardFork, height: u64) -> Self {
+        self.fork_height_overrides.push((hf, height));
+        self
+    }
+
+    /// Sets the [`ConsistencyCheck`] done on init.
+    pub fn consistency_check(mut self, consistency_check: ConsistencyCheck) -> Self {
+        self.consistency_check = consistency_check;
+        self
+    }
+
+    /// Sets the [`UnknownForkPolicy`], defaults to [`UnknownForkPolicy::RejectBlock`].
+    pub fn unknown_fork_policy(mut self, policy: UnknownForkPolicy) -> Self {
+        self.unknown_fork_policy = policy;
+        self
+    }
+
+    /// Sets the percentage of the window that has to vote for a hard-fork we don't know about before
+    /// we warn that the node needs updating, defaults to [`DEFAULT_UNKNOWN_FORK_THRESHOLD`].
+    pub fn unknown_fork_threshold(mut self, threshold: u64) -> Self {
+        self.unknown_fork_threshold = threshold;
+        self
+    }
+
+    /// Builds the config.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the window is 0, the unknown fork threshold is over 100, [`HardFork::V1`] is moved
+    /// from 0 or the fork heights are not in order after the overrides.
+    pub fn build(self) -> HardForkConfig {
+        assert_ne!(self.window, 0, "The voting window can't be empty");
+        assert!(
+            self.unknown_fork_threshold <= 100,
+            "The unknown fork threshold is a percentage"
+        );
+
+        HardForkConfig {
+            constants: self
+                .constants
+                .with_fork_height_overrides(&self.fork_height_overrides),
+            window: self.window,
+            consistency_check: self.consistency_check,
+            unknown_fork_policy: self.unknown_fork_policy,
+            unknown_fork_threshold: self.unknown_fork_threshold,
         }
     }
 }
@@ -252,6 +650,9 @@ pub struct HardForkState {
 
     config: HardForkConfig,
     votes: HFVotes,
+    /// The highest hard-fork we don't know about that enough of the window votes for, see
+    /// [`HardForkState::fork_ahead`].
+    fork_ahead: Option<u8>,
 
     last_height: u64,
 }
@@ -261,28 +662,116 @@ impl HardForkState {
         config: HardForkConfig,
         mut database: D,
     ) -> Result<Self, ConsensusError> {
-        let DatabaseResponse::ChainHeight(chain_height) = database
+        let chain_height = database
             .ready()
             .await?
             .call(DatabaseRequest::ChainHeight)
             .await?
-        else {
-            panic!("Database sent incorrect response")
-        };
+            .into_chain_height()?;
 
         let hfs = HardForkState::init_from_chain_height(config, chain_height, database).await?;
 
         Ok(hfs)
     }
 
-    #[instrument(name = "init_hardfork_state", skip(config, database), level = "info")]
+    /// Initializes the state from the blocks below `chain_height`, checking it against the database
+    /// as set by the config's [`ConsistencyCheck`].
+    #[instrument(
+        target = "cuprate_consensus::context",
+        name = "init_hardfork_state",
+        skip(config, database),
+        level = "info"
+    )]
     pub async fn init_from_chain_height<D: Database + Clone>(
         config: HardForkConfig,
         chain_height: u64,
-        mut database: D,
+        database: D,
     ) -> Result<Self, ConsensusError> {
-        tracing::info!("Initializing hard-fork state this may take a while.");
+        tracing::info!(target: CONTEXT_TARGET, "Initializing hard-fork state this may take a while.");
+
+        let consistency_check = config.consistency_check;
+        let (hfs, stored) =
+            HardForkState::init_trusting_top_block(config, chain_height, database.clone()).await?;
+
+        if consistency_check == ConsistencyCheck::None {
+            return Ok(hfs);
+        }
+
+        let height = chain_height - 1;
+        let expected = hfs.expected_hardfork_at(height);
+        if expected == stored {
+            return Ok(hfs);
+        }
+
+        match consistency_check {
+            ConsistencyCheck::Repair { trusted_height }
+                if trusted_height > 0 && trusted_height < chain_height =>
+            {
+                tracing::warn!(target: CONTEXT_TARGET,
+                    "Block {} has version {:?}, expected {:?}, re-scanning votes from height {}",
+                    height,
+                    stored,
+                    expected,
+                    trusted_height
+                );
+                let config = hfs.config;
+                HardForkState::repair(config, trusted_height, chain_height, database).await
+            }
+            _ => Err(InternalError::CorruptDatabase {
+                height,
+                stored,
+                expected,
+            }
+            .into()),
+        }
+    }
+
+    /// Rebuilds the state by trusting the block before `trusted_height` and adding the votes of the
+    /// blocks from `trusted_height` up to `chain_height`.
+    async fn repair<D: Database + Clone>(
+        config: HardForkConfig,
+        trusted_height: u64,
+        chain_height: u64,
+        database: D,
+    ) -> Result<Self, ConsensusError> {
+        let (mut hfs, _) =
+            HardForkState::init_trusting_top_block(config, trusted_height, database.clone())
+                .await?;
+
+        let mut responses = pin!(range_chunk_responses(
+            database,
+            trusted_height..chain_height,
+            DatabaseRequest::BlockHfInfoInRange
+        ));
+        let mut chunk_start = trusted_height;
+        while let Some(hf_infos) = responses.try_next().await? {
+            let hf_infos = hf_infos.into_block_hf_info_in_range()?;
+
+            let mut batch = HFVoteBatch::new(chunk_start);
+            for hf_info in &hf_infos {
+                batch.push(hf_info.vote_version());
+            }
+            hfs.new_blocks(&batch);
+            chunk_start += hf_infos.len() as u64;
+        }
+
+        tracing::info!(
+            target: CONTEXT_TARGET,
+            hf = ?hfs.current_hardfork,
+            "Repaired Hfs, {}",
+            hfs.votes
+        );
 
+        Ok(hfs)
+    }
+
+    /// Initializes the state from the version stored in the block below `chain_height`, returning
+    /// the state and the stored version.
+    async fn init_trusting_top_block<D: Database + Clone>(
+        config: HardForkConfig,
+        chain_height: u64,
+        mut database: D,
+    ) -> Result<(Self, HardFork), ConsensusError> {
         let block_start = chain_height.saturating_sub(config.window);
 
         let votes = get_votes_in_range(database.clone(), block_start..chain_height).await?;
@@ -291,14 +780,12 @@ impl HardForkState {
             debug_assert_eq!(votes.total_votes(), config.window)
         }
 
-        let DatabaseResponse::BlockHFInfo(hf_info) = database
+        let hf_info = database
             .ready()
             .await?
             .call(DatabaseRequest::BlockHFInfo((chain_height - 1).into()))
             .await?
-        else {
-            panic!("Database sent incorrect response!");
-        };
+            .into_block_hf_info()?;
 
         let current_hardfork = hf_info.version;
 
@@ -309,61 +796,133 @@ impl HardForkState {
             current_hardfork,
             next_hardfork,
             votes,
+            fork_ahead: None,
             last_height: chain_height - 1,
         };
 
         hfs.check_set_new_hf();
+        hfs.check_fork_ahead();
 
         tracing::info!(
-            "Initialized Hfs, current fork: {:?}, {}",
-            hfs.current_hardfork,
+            target: CONTEXT_TARGET,
+            hf = ?hfs.current_hardfork,
+            "Initialized Hfs, {}",
             hfs.votes
         );
 
-        Ok(hfs)
+        Ok((hfs, hf_info.version))
     }
 
-    pub fn check_block_version_vote(&self, block_hf_info: &BlockHFInfo) -> bool {
-        self.current_hardfork == block_hf_info.version
-            && block_hf_info.vote >= self.current_hardfork
+    /// Returns the hard-fork the block at `height` should have, computed from the fork heights and
+    /// the votes in the window.
+    ///
+    /// The current window's votes are used for every fork, which is exact as no Monero hard-fork needs
+    /// votes, see [`HardFork::fork_threshold`].
+    fn expected_hardfork_at(&self, height: u64) -> HardFork {
+        let mut hf = HardFork::V1;
+        while let Some(next_hf) = hf.next_fork() {
+            if height < self.config.constants.fork_height(&next_hf)
+                || self.votes.votes_for_hf(&next_hf)
+                    < next_hf.votes_needed(&self.config.network(), self.config.window)
+            {
+                break;
+            }
+            hf = next_hf;
+        }
+        hf
     }
 
-    pub async fn new_block<D: Database>(
-        &mut self,
-        vote: HardFork,
-        height: u64,
-        mut database: D,
-    ) -> Result<(), ConsensusError> {
+    /// Returns the current hard-fork.
+    pub fn current_hardfork(&self) -> HardFork {
+        self.current_hardfork
+    }
+
+    /// Returns the next hard-fork, if there is one.
+    pub fn next_hardfork(&self) -> Option<HardFork> {
+        self.next_hardfork
+    }
+
+    /// Returns the amount of votes for each hard-fork in the voting window, index 0 is
+    /// [`HardFork::V1`].
+    pub fn votes(&self) -> [u64; NUMB_OF_HARD_FORKS] {
+        self.votes.raw_votes()
+    }
+
+    /// Returns the amount of votes in the voting window.
+    pub fn total_votes(&self) -> u64 {
+        self.votes.total_votes()
+    }
+
+    /// Returns the height of the last block accounted for.
+    pub fn last_height(&self) -> u64 {
+        self.last_height
+    }
+
+    /// Returns the highest hard-fork we don't know about, if enough of the window votes for hard-forks
+    /// we don't know about that the network is probably about to fork without us, see
+    /// [`HardForkConfigBuilder::unknown_fork_threshold`].
+    pub fn fork_ahead(&self) -> Option<u8> {
+        self.fork_ahead
+    }
+
+    /// Returns a block's version and vote, applying the config's [`UnknownForkPolicy`] if its version
+    /// is a hard-fork we don't know about.
+    pub fn block_hf_info(&self, block_header: &BlockHeader) -> Result<BlockHFInfo, HardForkError> {
+        match BlockHFInfo::from_block_header(block_header) {
+            Err(HardForkError::UnknownVersion(version))
+                if self.config.unknown_fork_policy == UnknownForkPolicy::HaltAndWarn =>
+            {
+                tracing::warn!(
+                    target: CONTEXT_TARGET,
+                    version,
+                    height = self.last_height + 1,
+                    "Got a block for hard-fork {version}, which this version does not know about, \
+                     update your node"
+                );
+                Err(HardForkError::UnknownFork(version))
+            }
+            res => res,
+        }
+    }
+
+    /// Checks a block's version is the current hard-fork and it votes for the current hard-fork or
+    /// a later one.
+    pub fn check_block_version_vote(
+        &self,
+        block_hf_info: &BlockHFInfo,
+    ) -> Result<(), HardForkError> {
+        if self.current_hardfork != block_hf_info.version {
+            return Err(HardForkError::VersionMismatch {
+                expected: self.current_hardfork,
+                got: block_hf_info.version,
+            });
+        }
+        if block_hf_info.vote < self.current_hardfork {
+            return Err(HardForkError::VoteTooLow {
+                vote: block_hf_info.vote,
+                current: self.current_hardfork,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn new_block(&mut self, vote: impl Into<HardForkVersion>, height: u64) {
+        let vote = vote.into();
         assert_eq!(self.last_height + 1, height);
         self.last_height += 1;
 
         tracing::debug!(
-            "Accounting for new blocks vote, height: {}, vote: {:?}",
-            self.last_height,
-            vote
+            target: CONTEXT_TARGET,
+            height,
+            vote = ?vote,
+            "Accounting for new block's vote"
         );
 
-        self.votes.add_vote_for_hf(&vote);
-
-        for height_to_remove in
-            (self.config.window..self.votes.total_votes()).map(|offset| height - offset)
-        {
-            let DatabaseResponse::BlockHFInfo(hf_info) = database
-                .ready()
-                .await?
-                .call(DatabaseRequest::BlockHFInfo(height_to_remove.into()))
-                .await?
-            else {
-                panic!("Database sent incorrect response!");
-            };
+        self.votes.push_back(vote);
 
-            tracing::debug!(
-                "Removing block {} vote ({:?}) as they have left the window",
-                height_to_remove,
-                hf_info.vote
-            );
-
-            self.votes.remove_vote_for_hf(&hf_info.vote);
+        while self.votes.total_votes() > self.config.window {
+            let removed = self.votes.pop_front();
+            tracing::debug!(target: CONTEXT_TARGET, "Removing vote {:?} as it has left the window", removed);
         }
 
         if height > self.config.window {
@@ -371,7 +930,64 @@ impl HardForkState {
         }
 
         self.check_set_new_hf();
-        Ok(())
+        self.check_fork_ahead();
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_hard_fork_votes_len(self.votes.total_votes());
+    }
+
+    /// Adds the votes of a batch of blocks, the first block in the batch must be the block after the
+    /// last block accounted for.
+    ///
+    /// This gives the same state as calling [`HardForkState::new_block`] for every block in the batch
+    /// but votes are added and removed a run at a time. A run is split at the height the next
+    /// hard-fork can activate at, so forks activate at the same block they would have if the blocks
+    /// were added one at a time.
+    pub fn new_blocks(&mut self, batch: &HFVoteBatch) {
+        assert_eq!(self.last_height + 1, batch.start_height);
+
+        tracing::debug!(
+            target: CONTEXT_TARGET,
+            height = batch.start_height,
+            batch_size = batch.len(),
+            "Accounting for a batch of blocks' votes"
+        );
+
+        for &(vote, count) in &batch.runs {
+            let mut remaining = count;
+            while remaining > 0 {
+                // The next fork can activate once the block before its fork height is accounted for.
+                let blocks_until_fork = self
+                    .next_hardfork
+                    .map(|hf| {
+                        self.config
+                            .constants
+                            .fork_height(&hf)
+                            .saturating_sub(self.last_height + 1)
+                    })
+                    .filter(|blocks| *blocks > 0)
+                    .unwrap_or(remaining);
+                let added = remaining.min(blocks_until_fork);
+
+                self.votes.push_back_n(vote, added);
+                self.votes
+                    .pop_front_n(self.votes.total_votes().saturating_sub(self.config.window));
+                self.last_height += added;
+                remaining -= added;
+
+                self.check_set_new_hf();
+            }
+        }
+
+        self.check_fork_ahead();
+
+        debug_assert!(self.votes.total_votes() <= self.config.window);
+        if self.last_height >= self.config.window {
+            debug_assert_eq!(self.votes.total_votes(), self.config.window);
+        }
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_hard_fork_votes_len(self.votes.total_votes());
     }
 
     /// Checks if the next hard-fork should be activated and activates it if it should.
@@ -379,9 +995,9 @@ impl HardForkState {
     /// https://cuprate.github.io/monero-docs/consensus_rules/hardforks.html#accepting-a-fork
     fn check_set_new_hf(&mut self) {
         while let Some(new_hf) = self.next_hardfork {
-            if self.last_height + 1 >= new_hf.fork_height(&self.config.network)
+            if self.last_height + 1 >= self.config.constants.fork_height(&new_hf)
                 && self.votes.votes_for_hf(&new_hf)
-                    >= new_hf.votes_needed(&self.config.network, self.config.window)
+                    >= new_hf.votes_needed(&self.config.network(), self.config.window)
             {
                 self.set_hf(new_hf);
             } else {
@@ -390,6 +1006,28 @@ impl HardForkState {
         }
     }
 
+    /// Checks if enough of the window votes for hard-forks we don't know about that the network is
+    /// probably forking without us, warning once when it starts, like monerod's "update your node"
+    /// warning.
+    fn check_fork_ahead(&mut self) {
+        let fork_ahead = (self.votes.unknown_votes() > 0
+            && self.votes.unknown_votes() >= self.config.unknown_fork_votes_needed())
+        .then(|| self.votes.highest_unknown_vote())
+        .flatten();
+
+        if fork_ahead.is_some() && fork_ahead != self.fork_ahead {
+            tracing::warn!(
+                target: CONTEXT_TARGET,
+                votes = self.votes.unknown_votes(),
+                window = self.config.window,
+                "The network is voting for hard-fork {}, which this version does not know about, \
+                 update your node",
+                fork_ahead.unwrap_or_default()
+            );
+        }
+        self.fork_ahead = fork_ahead;
+    }
+
     /// Sets a new hard-fork.
     fn set_hf(&mut self, new_hf: HardFork) {
         self.next_hardfork = new_hf.next_fork();
@@ -397,23 +1035,310 @@ impl HardForkState {
     }
 }
 
-#[instrument(name = "get_votes", skip(database))]
-async fn get_votes_in_range<D: Database>(
+#[instrument(
+    target = "cuprate_consensus::context",
+    name = "get_votes",
+    skip(database)
+)]
+async fn get_votes_in_range<D: Database + Clone>(
     database: D,
     block_heights: Range<u64>,
 ) -> Result<HFVotes, ConsensusError> {
     let mut votes = HFVotes::default();
 
-    let DatabaseResponse::BlockHfInfoInRange(vote_list) = database
-        .oneshot(DatabaseRequest::BlockHfInfoInRange(block_heights))
-        .await?
-    else {
-        panic!("Database sent incorrect response!");
+    let mut responses = pin!(range_chunk_responses(
+        database,
+        block_heights,
+        DatabaseRequest::BlockHfInfoInRange
+    ));
+    while let Some(vote_list) = responses.try_next().await? {
+        for hf_info in vote_list.into_block_hf_info_in_range()? {
+            votes.push_back(hf_info.vote_version());
+        }
+    }
+
+    Ok(votes)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use monero_serai::block::BlockHeader;
+
+    use super::{
+        BlockHFInfo, ConsistencyCheck, HFVoteBatch, HFVotes, HardFork, HardForkConfig,
+        HardForkState, HardForkVersion, UnknownForkPolicy, DEFAULT_WINDOW_SIZE, HARD_FORKS,
     };
+    use crate::test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder};
+    use crate::{ConsensusError, HardForkError, InternalError};
+    use cuprate_common::Network;
+
+    #[test]
+    fn fork_table() {
+        assert_eq!(HardFork::latest(), HardFork::V16);
+        assert_eq!(HardFork::ALL.len(), HARD_FORKS.len());
+        assert_eq!(HardFork::V8.info().mainnet_height, 1685555);
 
-    for hf_info in vote_list.into_iter() {
-        votes.add_vote_for_hf(&hf_info.vote);
+        for (i, hf) in HardFork::ALL.into_iter().enumerate() {
+            assert_eq!(HardFork::from_version(&(i as u8 + 1)), Ok(hf));
+            assert_eq!(hf.next_fork(), HardFork::ALL.get(i + 1).copied());
+        }
+
+        let unknown = HardFork::LATEST as u8 + 1;
+        assert!(HardFork::from_version(&unknown).is_err());
+        assert!(HardFork::from_version(&0).is_err());
+        // Unknown votes are votes for the latest fork.
+        assert_eq!(HardFork::from_vote(&unknown), HardFork::LATEST);
+        assert_eq!(HardFork::from_vote(&0), HardFork::V1);
     }
 
-    Ok(votes)
+    #[test]
+    fn config_builder() {
+        let config = HardForkConfig::builder()
+            .network(Network::Testnet)
+            .window(10)
+            .fork_height_override(HardFork::V2, 5)
+            .build();
+
+        assert_eq!(config.network(), Network::Testnet);
+        assert_eq!(config.window(), 10);
+        assert_eq!(config.constants().fork_height(&HardFork::V2), 5);
+        assert_eq!(config.constants().fork_height(&HardFork::V3), 624635);
+
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                5,
+                DummyBlockExtendedHeader::default().with_hard_fork_info(HardFork::V1, HardFork::V2),
+            )
+            .add_blocks(
+                20,
+                DummyBlockExtendedHeader::default().with_hard_fork_info(HardFork::V2, HardFork::V2),
+            )
+            .finish();
+
+        let hfs = block_on(HardForkState::init(config, database)).unwrap();
+        assert_eq!(hfs.current_hardfork(), HardFork::V2);
+        assert_eq!(hfs.votes.total_votes(), 10);
+    }
+
+    #[test]
+    fn votes_leave_the_window() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                DEFAULT_WINDOW_SIZE as usize,
+                DummyBlockExtendedHeader::default().with_hard_fork_info(HardFork::V1, HardFork::V2),
+            )
+            .finish();
+
+        let mut hfs = block_on(HardForkState::init_from_chain_height(
+            HardForkConfig::main_net(),
+            DEFAULT_WINDOW_SIZE,
+            database.clone(),
+        ))
+        .unwrap();
+
+        assert_eq!(hfs.current_hardfork(), HardFork::V1);
+        assert_eq!(hfs.votes.total_votes(), DEFAULT_WINDOW_SIZE);
+        assert_eq!(hfs.votes.votes_for_hf(&HardFork::V3), 0);
+
+        for height in DEFAULT_WINDOW_SIZE..DEFAULT_WINDOW_SIZE + 10 {
+            hfs.new_block(HardFork::V3, height);
+            database.add_block(
+                DummyBlockExtendedHeader::default().with_hard_fork_info(HardFork::V1, HardFork::V3),
+            );
+        }
+
+        assert_eq!(hfs.votes.total_votes(), DEFAULT_WINDOW_SIZE);
+        assert_eq!(hfs.votes.votes_for_hf(&HardFork::V3), 10);
+        assert_eq!(hfs.votes.votes_for_hf(&HardFork::V2), DEFAULT_WINDOW_SIZE);
+    }
+
+    #[test]
+    fn vote_runs() {
+        let mut votes = HFVotes::default();
+        votes.push_back_n(HardFork::V1, 3);
+        votes.push_back(HardFork::V1);
+        votes.push_back_n(HardFork::V2, 2);
+        assert_eq!(votes.vote_list.len(), 2);
+        assert_eq!(votes.total_votes(), 6);
+
+        votes.pop_front_n(5);
+        assert_eq!(votes.total_votes(), 1);
+        assert_eq!(votes.votes_for_hf(&HardFork::V1), 1);
+        assert_eq!(votes.votes_for_hf(&HardFork::V2), 1);
+        assert_eq!(votes.pop_front(), Some(HardFork::V2.into()));
+        assert_eq!(votes.pop_front(), None);
+    }
+
+    #[test]
+    fn unknown_votes() {
+        let unknown = HardFork::LATEST as u8 + 1;
+        let info = BlockHFInfo::from_major_minor(HardFork::LATEST as u8, unknown).unwrap();
+        assert_eq!(info.vote, HardFork::LATEST);
+        assert_eq!(info.vote_version(), HardForkVersion::UnknownFork(unknown));
+        let info = BlockHFInfo::from_major_minor(1, 0).unwrap();
+        assert_eq!(info.vote_version(), HardForkVersion::Known(HardFork::V1));
+
+        let mut votes = HFVotes::default();
+        votes.push_back_n(HardFork::LATEST, 2);
+        votes.push_back_n(HardForkVersion::UnknownFork(unknown), 3);
+        votes.push_back(HardForkVersion::UnknownFork(unknown + 1));
+        // Votes for forks we don't know about count for the latest fork.
+        assert_eq!(votes.votes_for_hf(&HardFork::LATEST), 6);
+        assert_eq!(votes.unknown_votes(), 4);
+        assert_eq!(votes.highest_unknown_vote(), Some(unknown + 1));
+
+        votes.pop_front_n(4);
+        assert_eq!(votes.unknown_votes(), 2);
+        assert_eq!(votes.votes_for_hf(&HardFork::LATEST), 2);
+    }
+
+    #[test]
+    fn fork_ahead_of_us() {
+        let unknown = HardForkVersion::UnknownFork(HardFork::LATEST as u8 + 1);
+        let config = HardForkConfig::builder()
+            .network(Network::Testnet)
+            .window(10)
+            .build();
+        assert_eq!(config.unknown_fork_votes_needed(), 8);
+
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                10,
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::LATEST, HardFork::LATEST),
+            )
+            .finish();
+        let mut hfs = block_on(HardForkState::init(config, database)).unwrap();
+
+        for height in 10..17 {
+            hfs.new_block(unknown, height);
+        }
+        assert_eq!(hfs.fork_ahead(), None);
+
+        hfs.new_block(unknown, 17);
+        assert_eq!(hfs.fork_ahead(), Some(HardFork::LATEST as u8 + 1));
+        assert_eq!(hfs.current_hardfork(), HardFork::LATEST);
+
+        let mut batch = HFVoteBatch::new(18);
+        batch.push_n(HardFork::LATEST, 3);
+        hfs.new_blocks(&batch);
+        assert_eq!(hfs.fork_ahead(), None);
+    }
+
+    #[test]
+    fn unknown_fork_policy() {
+        let header = BlockHeader {
+            major_version: HardFork::LATEST as u8 + 1,
+            minor_version: HardFork::LATEST as u8 + 1,
+            timestamp: 0,
+            previous: [0; 32],
+            nonce: 0,
+        };
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+
+        let hfs = block_on(HardForkState::init(
+            HardForkConfig::main_net(),
+            database.clone(),
+        ))
+        .unwrap();
+        assert_eq!(
+            hfs.block_hf_info(&header),
+            Err(HardForkError::UnknownVersion(HardFork::LATEST as u8 + 1))
+        );
+
+        let hfs = block_on(HardForkState::init(
+            HardForkConfig::main_net().with_unknown_fork_policy(UnknownForkPolicy::HaltAndWarn),
+            database,
+        ))
+        .unwrap();
+        assert_eq!(
+            hfs.block_hf_info(&header),
+            Err(HardForkError::UnknownFork(HardFork::LATEST as u8 + 1))
+        );
+    }
+
+    #[test]
+    fn batched_votes_match_single_votes() {
+        let fork_height = HardFork::V2.fork_height(&Network::Mainnet);
+
+        let mut votes = HFVotes::default();
+        votes.push_back_n(HardFork::V1, DEFAULT_WINDOW_SIZE);
+        let mut single = HardForkState {
+            current_hardfork: HardFork::V1,
+            next_hardfork: Some(HardFork::V2),
+            config: HardForkConfig::main_net(),
+            votes,
+            fork_ahead: None,
+            last_height: fork_height - 100,
+        };
+        let mut batched = single.clone();
+
+        let mut batch = HFVoteBatch::new(fork_height - 99);
+        for (vote, count) in [(HardFork::V1, 50), (HardFork::V2, 100), (HardFork::V3, 20)] {
+            batch.push_n(vote, count);
+            for _ in 0..count {
+                single.new_block(vote, single.last_height + 1);
+            }
+        }
+        assert_eq!(batch.len(), 170);
+
+        batched.new_blocks(&batch);
+
+        assert_eq!(batched.current_hardfork, HardFork::V2);
+        assert_eq!(batched.current_hardfork, single.current_hardfork);
+        assert_eq!(batched.next_hardfork, single.next_hardfork);
+        assert_eq!(batched.last_height, single.last_height);
+        assert_eq!(batched.votes(), single.votes());
+        assert_eq!(batched.total_votes(), DEFAULT_WINDOW_SIZE);
+    }
+
+    #[test]
+    fn consistency_check() {
+        // The top block was written with the wrong version.
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                100,
+                DummyBlockExtendedHeader::default().with_hard_fork_info(HardFork::V1, HardFork::V1),
+            )
+            .add_blocks(
+                1,
+                DummyBlockExtendedHeader::default().with_hard_fork_info(HardFork::V2, HardFork::V2),
+            )
+            .finish();
+        let init = |consistency_check| {
+            block_on(HardForkState::init_from_chain_height(
+                HardForkConfig::main_net().with_consistency_check(consistency_check),
+                101,
+                database.clone(),
+            ))
+        };
+
+        let hfs = init(ConsistencyCheck::None).unwrap();
+        assert_eq!(hfs.current_hardfork(), HardFork::V2);
+
+        assert!(matches!(
+            init(ConsistencyCheck::Verify),
+            Err(ConsensusError::Internal(InternalError::CorruptDatabase {
+                height: 100,
+                stored: HardFork::V2,
+                expected: HardFork::V1,
+            }))
+        ));
+
+        let hfs = init(ConsistencyCheck::Repair { trusted_height: 50 }).unwrap();
+        assert_eq!(hfs.current_hardfork(), HardFork::V1);
+        assert_eq!(hfs.last_height(), 100);
+        assert_eq!(hfs.total_votes(), 101);
+        assert_eq!(hfs.votes()[1], 1);
+
+        // A trusted height that is not in the chain can't be used to repair.
+        assert!(init(ConsistencyCheck::Repair {
+            trusted_height: 101
+        })
+        .is_err());
+    }
 }
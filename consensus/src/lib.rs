@@ -1,17 +1,108 @@
+//! # Monero Consensus
+//!
+//! This crate contains Monero's consensus rules and the services that verify blocks and
+//! transactions against a [`Database`].
+//!
+//! Without its default features the crate doesn't use the network or an async runtime, so it can be
+//! embedded, for example by a wallet daemon that only verifies, with any [`Database`]. The features
+//! are:
+//! - `binaries` (default), the chain scanning and difficulty checking binaries.
+//! - `rpc`, a [`Database`] backed by monerod's RPC, see [`rpc`].
+//! - `retry`, the [`retry`] middleware.
+//! - `tokio`, the tasks that need a timer, like [`txpool::expire_transactions_task`].
+//! - `metrics`, see [`metrics`].
+//! - `dns_checkpoints`, a resolver to fetch checkpoints from DNS, see [`dns_checkpoints`].
+//! - `test_utils` and `proptest`, a dummy database and property test strategies for tests.
+//! - `test_vectors`, the replay of recorded mainnet blocks, see [`test_vectors`].
+//!
+//! Logs are split between the `tracing` targets in [`spans`].
+//!
+pub mod alt_chain;
 pub mod block;
+pub mod block_template;
+mod block_verifier;
+pub mod bootstrap;
+pub mod checkpoints;
+pub mod consensus_constants;
+pub mod context;
+pub mod decoys;
+pub mod dns_checkpoints;
+mod error;
+pub mod fee;
+pub mod fluffy_block;
+pub mod fork_metrics;
 pub mod genesis;
 pub mod hardforks;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod miner_tx;
-#[cfg(feature = "binaries")]
+pub mod misbehaviour;
+pub mod output_analytics;
+pub mod output_distribution;
+pub mod outputs;
+#[cfg(feature = "proptest")]
+pub mod proptest;
+pub mod quarantine;
+pub mod read_scheduler;
+#[cfg(feature = "retry")]
+pub mod retry;
+#[cfg(feature = "rpc")]
 pub mod rpc;
+pub mod rule_flags;
+pub mod spans;
+pub mod speculative;
+pub mod sync;
+#[cfg(any(test, feature = "test_utils"))]
+pub mod test_utils;
+#[cfg(any(test, feature = "test_vectors"))]
+pub mod test_vectors;
+pub mod timings;
+pub mod transactions;
+pub mod tx_extra;
+pub mod tx_report;
+pub mod tx_verifier;
+pub mod txpool;
+pub mod verification_pool;
+pub mod verification_queue;
 pub mod verifier;
+pub mod write_batch;
 
-#[derive(Debug, thiserror::Error)]
-pub enum ConsensusError {
-    #[error("Invalid hard fork version: {0}")]
-    InvalidHardForkVersion(&'static str),
-    #[error("Database error: {0}")]
-    Database(#[from] tower::BoxError),
+pub use error::{
+    BlockError, ConsensusError, ContextProtocolError, DatabaseProtocolError, HardForkError,
+    InternalError, MinerTxError, TransactionError,
+};
+
+/// The most blocks asked for in one range request when initializing the caches, so a big window (like
+/// the 100,000 block long term weight window) is never held in memory by both us and the database.
+pub(crate) const RANGE_REQUEST_CHUNK_SIZE: u64 = 10_000;
+
+/// The most range requests sent at once when initializing the caches, see [`range_chunk_responses`].
+pub(crate) const CONCURRENT_RANGE_REQUESTS: usize = 4;
+
+/// Splits a range of block heights into ranges of at most [`RANGE_REQUEST_CHUNK_SIZE`] blocks.
+pub(crate) fn range_chunks(
+    range: std::ops::Range<u64>,
+) -> impl Iterator<Item = std::ops::Range<u64>> {
+    let end = range.end;
+    range
+        .step_by(RANGE_REQUEST_CHUNK_SIZE as usize)
+        .map(move |start| start..(start + RANGE_REQUEST_CHUNK_SIZE).min(end))
+}
+
+/// Sends `request` for each chunk of the range, [`CONCURRENT_RANGE_REQUESTS`] at a time, so the
+/// database reads the next chunks while the last is being processed. The responses are returned in
+/// the order of the range.
+pub(crate) fn range_chunk_responses<D: Database + Clone>(
+    database: D,
+    range: std::ops::Range<u64>,
+    request: fn(std::ops::Range<u64>) -> DatabaseRequest,
+) -> impl futures::Stream<Item = Result<DatabaseResponse, tower::BoxError>> {
+    use futures::StreamExt;
+    use tower::ServiceExt;
+
+    futures::stream::iter(range_chunks(range))
+        .map(move |chunk| database.clone().oneshot(request(chunk)))
+        .buffered(CONCURRENT_RANGE_REQUESTS)
 }
 
 pub trait Database:
@@ -24,11 +115,68 @@ impl<T: tower::Service<DatabaseRequest, Response = DatabaseResponse, Error = tow
 {
 }
 
+/// A boxed [`Database`], so code can hold a database, or pass one around, without being generic over
+/// it.
+///
+/// ```ignore
+/// async fn chain_height(mut database: DatabaseSvc) -> Result<u64, ConsensusError> {
+///     Ok(database
+///         .ready()
+///         .await?
+///         .call(DatabaseRequest::ChainHeight)
+///         .await?
+///         .into_chain_height()?)
+/// }
+/// ```
+#[derive(Clone)]
+pub struct DatabaseSvc(
+    tower::util::BoxCloneService<DatabaseRequest, DatabaseResponse, tower::BoxError>,
+);
+
+impl DatabaseSvc {
+    /// Boxes the database.
+    pub fn new<D>(database: D) -> DatabaseSvc
+    where
+        D: Database + Clone + Send + 'static,
+        D::Future: Send + 'static,
+    {
+        DatabaseSvc(tower::util::BoxCloneService::new(database))
+    }
+}
+
+impl std::fmt::Debug for DatabaseSvc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DatabaseSvc").finish_non_exhaustive()
+    }
+}
+
+impl tower::Service<DatabaseRequest> for DatabaseSvc {
+    type Response = DatabaseResponse;
+    type Error = tower::BoxError;
+    type Future = futures::future::BoxFuture<'static, Result<DatabaseResponse, tower::BoxError>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: DatabaseRequest) -> Self::Future {
+        self.0.call(req)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum DatabaseRequest {
     BlockHFInfo(cuprate_common::BlockID),
     BlockPOWInfo(cuprate_common::BlockID),
     BlockWeights(cuprate_common::BlockID),
+    BlockHash(u64),
+    /// The total amount of coins generated up to and including the block at this height.
+    GeneratedCoins(u64),
+    /// The cumulative difficulty of the chain up to and including the block at this height.
+    CumulativeDifficulty(u64),
 
     BlockHfInfoInRange(std::ops::Range<u64>),
     BlockWeightsInRange(std::ops::Range<u64>),
@@ -36,17 +184,99 @@ pub enum DatabaseRequest {
 
     ChainHeight,
 
-    #[cfg(feature = "binaries")]
+    /// Returns true if any of these key images have been spent in the chain.
+    KeyImagesSpent(Vec<[u8; 32]>),
+
+    Block(cuprate_common::BlockID),
+    /// The height of the block with this hash, if it's in the main chain.
+    BlockHeight([u8; 32]),
+
+    /// The blobs of the blocks in the range, with the transactions pruned if `pruned` is set.
+    BlockBlobsInRange {
+        range: std::ops::Range<u64>,
+        pruned: bool,
+    },
+    /// The output indices of a transaction in the chain.
+    TxOutputIndices([u8; 32]),
+    /// The outputs with these (amount, amount index) pairs, RingCT outputs have an amount of 0.
+    Outputs(Vec<(u64, u64)>),
+    /// The time-locks of the outputs with these (amount, amount index) pairs, RingCT outputs have an
+    /// amount of 0.
+    OutputTimeLocks(Vec<(u64, u64)>),
+    /// The amount of outputs with this amount created in each block of the range, RingCT outputs have
+    /// an amount of 0.
+    NumOutputsInRange {
+        amount: u64,
+        range: std::ops::Range<u64>,
+    },
+    /// The amount of outputs with this amount created up to and including each block from
+    /// `from_height` to `to_height`, both inclusive, RingCT outputs have an amount of 0.
+    OutputDistribution {
+        amount: u64,
+        from_height: u64,
+        to_height: u64,
+    },
+    /// The amount of outputs with each of these amounts in the chain, RingCT outputs have an amount
+    /// of 0.
+    NumberOutputsWithAmount(Vec<u64>),
+
+    /// Adds a verified block to the top of the main chain. The block, its transactions, outputs and
+    /// key images are written in one storage transaction, so a failed write leaves the chain as it was.
+    WriteBlock(Box<block::VerifiedBlockInformation>),
+    /// Adds verified blocks, in order, to the top of the main chain in one storage transaction, see
+    /// [`write_batch`]. Answered with [`DatabaseResponse::WriteBlock`].
+    WriteBlocks(Vec<block::VerifiedBlockInformation>),
+    /// Removes the top block of the main chain, with its transactions, outputs and key images, for
+    /// reorgs.
+    PopBlock,
+
+    /// The blocks in the range, for scanning the chain.
     BlockBatchInRange(std::ops::Range<u64>),
-    #[cfg(feature = "binaries")]
+    /// The transactions with these hashes, for scanning the chain.
     Transactions(Vec<[u8; 32]>),
 }
 
+impl DatabaseRequest {
+    /// Returns the name of this request's variant.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DatabaseRequest::BlockHFInfo(_) => "BlockHFInfo",
+            DatabaseRequest::BlockPOWInfo(_) => "BlockPOWInfo",
+            DatabaseRequest::BlockWeights(_) => "BlockWeights",
+            DatabaseRequest::BlockHash(_) => "BlockHash",
+            DatabaseRequest::GeneratedCoins(_) => "GeneratedCoins",
+            DatabaseRequest::CumulativeDifficulty(_) => "CumulativeDifficulty",
+            DatabaseRequest::BlockHfInfoInRange(_) => "BlockHfInfoInRange",
+            DatabaseRequest::BlockWeightsInRange(_) => "BlockWeightsInRange",
+            DatabaseRequest::BlockPOWInfoInRange(_) => "BlockPOWInfoInRange",
+            DatabaseRequest::ChainHeight => "ChainHeight",
+            DatabaseRequest::KeyImagesSpent(_) => "KeyImagesSpent",
+            DatabaseRequest::Block(_) => "Block",
+            DatabaseRequest::BlockHeight(_) => "BlockHeight",
+            DatabaseRequest::BlockBlobsInRange { .. } => "BlockBlobsInRange",
+            DatabaseRequest::TxOutputIndices(_) => "TxOutputIndices",
+            DatabaseRequest::Outputs(_) => "Outputs",
+            DatabaseRequest::OutputTimeLocks(_) => "OutputTimeLocks",
+            DatabaseRequest::NumOutputsInRange { .. } => "NumOutputsInRange",
+            DatabaseRequest::OutputDistribution { .. } => "OutputDistribution",
+            DatabaseRequest::NumberOutputsWithAmount(_) => "NumberOutputsWithAmount",
+            DatabaseRequest::WriteBlock(_) => "WriteBlock",
+            DatabaseRequest::WriteBlocks(_) => "WriteBlocks",
+            DatabaseRequest::PopBlock => "PopBlock",
+            DatabaseRequest::BlockBatchInRange(_) => "BlockBatchInRange",
+            DatabaseRequest::Transactions(_) => "Transactions",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum DatabaseResponse {
     BlockHFInfo(hardforks::BlockHFInfo),
     BlockPOWInfo(block::pow::BlockPOWInfo),
     BlockWeights(block::weight::BlockWeightInfo),
+    BlockHash([u8; 32]),
+    GeneratedCoins(u64),
+    CumulativeDifficulty(u128),
 
     BlockHfInfoInRange(Vec<hardforks::BlockHFInfo>),
     BlockWeightsInRange(Vec<block::weight::BlockWeightInfo>),
@@ -54,8 +284,153 @@ pub enum DatabaseResponse {
 
     ChainHeight(u64),
 
-    #[cfg(feature = "binaries")]
+    KeyImagesSpent(bool),
+
+    Block(Box<monero_serai::block::Block>),
+    BlockHeight(Option<u64>),
+
+    BlockBlobsInRange(Vec<outputs::BlockBlobs>),
+    TxOutputIndices(Vec<u64>),
+    Outputs(Vec<outputs::OutputOnChain>),
+    OutputTimeLocks(Vec<outputs::OutputTimeLock>),
+    NumOutputsInRange(Vec<u64>),
+    OutputDistribution(Vec<u64>),
+    NumberOutputsWithAmount(std::collections::HashMap<u64, usize>),
+
+    WriteBlock,
+    /// The block that was removed and its transactions, not including the miner transaction.
+    PopBlock(
+        Box<monero_serai::block::Block>,
+        Vec<monero_serai::transaction::Transaction>,
+    ),
+
     BlockBatchInRange(Vec<monero_serai::block::Block>),
-    #[cfg(feature = "binaries")]
     Transactions(Vec<monero_serai::transaction::Transaction>),
 }
+
+/// Implements a method for each [`DatabaseResponse`] variant that returns the variant's data, or a
+/// [`DatabaseProtocolError`] if the response is another variant. The method for a request's response
+/// is named after the request, so callers don't need to match on the response.
+macro_rules! database_response_accessors {
+    ($($(#[$meta:meta])* $variant:ident($data:ty) => $accessor:ident,)*) => {
+        impl DatabaseResponse {
+            /// Returns the name of this response's variant.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $($(#[$meta])* DatabaseResponse::$variant(_) => stringify!($variant),)*
+                    DatabaseResponse::WriteBlock => "WriteBlock",
+                    DatabaseResponse::PopBlock(..) => "PopBlock",
+                }
+            }
+
+            $(
+                $(#[$meta])*
+                pub fn $accessor(self) -> Result<$data, DatabaseProtocolError> {
+                    match self {
+                        DatabaseResponse::$variant(data) => Ok(data),
+                        res => Err(DatabaseProtocolError {
+                            expected: stringify!($variant),
+                            got: res.name(),
+                        }),
+                    }
+                }
+            )*
+
+            pub fn into_write_block(self) -> Result<(), DatabaseProtocolError> {
+                match self {
+                    DatabaseResponse::WriteBlock => Ok(()),
+                    res => Err(DatabaseProtocolError {
+                        expected: "WriteBlock",
+                        got: res.name(),
+                    }),
+                }
+            }
+
+            #[allow(clippy::type_complexity)]
+            pub fn into_pop_block(
+                self,
+            ) -> Result<
+                (
+                    Box<monero_serai::block::Block>,
+                    Vec<monero_serai::transaction::Transaction>,
+                ),
+                DatabaseProtocolError,
+            > {
+                match self {
+                    DatabaseResponse::PopBlock(block, txs) => Ok((block, txs)),
+                    res => Err(DatabaseProtocolError {
+                        expected: "PopBlock",
+                        got: res.name(),
+                    }),
+                }
+            }
+        }
+    };
+}
+
+database_response_accessors! {
+    BlockHFInfo(hardforks::BlockHFInfo) => into_block_hf_info,
+    BlockPOWInfo(block::pow::BlockPOWInfo) => into_block_pow_info,
+    BlockWeights(block::weight::BlockWeightInfo) => into_block_weights,
+    BlockHash([u8; 32]) => into_block_hash,
+    GeneratedCoins(u64) => into_generated_coins,
+    CumulativeDifficulty(u128) => into_cumulative_difficulty,
+    BlockHfInfoInRange(Vec<hardforks::BlockHFInfo>) => into_block_hf_info_in_range,
+    BlockWeightsInRange(Vec<block::weight::BlockWeightInfo>) => into_block_weights_in_range,
+    BlockPOWInfoInRange(Vec<block::pow::BlockPOWInfo>) => into_block_pow_info_in_range,
+    ChainHeight(u64) => into_chain_height,
+    KeyImagesSpent(bool) => into_key_images_spent,
+    Block(Box<monero_serai::block::Block>) => into_block,
+    BlockHeight(Option<u64>) => into_block_height,
+    BlockBlobsInRange(Vec<outputs::BlockBlobs>) => into_block_blobs_in_range,
+    TxOutputIndices(Vec<u64>) => into_tx_output_indices,
+    Outputs(Vec<outputs::OutputOnChain>) => into_outputs,
+    OutputTimeLocks(Vec<outputs::OutputTimeLock>) => into_output_time_locks,
+    NumOutputsInRange(Vec<u64>) => into_num_outputs_in_range,
+    OutputDistribution(Vec<u64>) => into_output_distribution,
+    NumberOutputsWithAmount(std::collections::HashMap<u64, usize>) => into_number_outputs_with_amount,
+    BlockBatchInRange(Vec<monero_serai::block::Block>) => into_block_batch_in_range,
+    Transactions(Vec<monero_serai::transaction::Transaction>) => into_transactions,
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder};
+
+    #[test]
+    fn boxed_database() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(5, DummyBlockExtendedHeader::default())
+            .finish();
+        let database = DatabaseSvc::new(database);
+
+        let chain_height = block_on(database.clone().oneshot(DatabaseRequest::ChainHeight))
+            .unwrap()
+            .into_chain_height()
+            .unwrap();
+        assert_eq!(chain_height, 5);
+
+        let verifier = block_on(verifier::Verifier::init(
+            verifier::Config::for_network(cuprate_common::Network::Mainnet),
+            database,
+        ));
+        assert!(verifier.is_ok());
+    }
+
+    #[test]
+    fn request_names() {
+        assert_eq!(DatabaseRequest::ChainHeight.name(), "ChainHeight");
+        assert_eq!(
+            DatabaseRequest::NumOutputsInRange {
+                amount: 0,
+                range: 0..1
+            }
+            .name(),
+            "NumOutputsInRange"
+        );
+    }
+}
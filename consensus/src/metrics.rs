@@ -0,0 +1,155 @@
+//! # Metrics
+//!
+//! This module records metrics with the [`metrics`](::metrics) crate facade, so operators can export
+//! them with any recorder, like `metrics-exporter-prometheus`. It is behind the `metrics` feature.
+//!
+//! The metrics are:
+//! - [`BLOCKS_VERIFIED`], a counter of verified blocks, its rate is the blocks verified per second.
+//! - [`STAGE_DURATION`], a histogram of the time blocks spend in each
+//!   [`VerificationStage`], labeled with `stage`.
+//! - [`DATABASE_REQUEST_DURATION`], a histogram of database request latencies, labeled with the
+//!   `request` variant. Wrap the database in [`MeteredDatabase`] to record this.
+//! - [`WEIGHT_CACHE_LEN`], a gauge of the amount of block weights held by the
+//!   [`BlockWeightsCache`](crate::block::weight::BlockWeightsCache), labeled with the `window`.
+//! - [`HARD_FORK_VOTES_LEN`], a gauge of the amount of votes held by the
+//!   [`HardForkState`](crate::hardforks::HardForkState).
+//! - [`WRITE_BATCH_SIZE`], a histogram of the amount of blocks in each batch written by the
+//!   [`BlockWriter`](crate::write_batch::BlockWriter).
+//!
+//! Call [`describe_metrics`] once, after installing the recorder, to give the metrics their units and
+//! descriptions.
+//!
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use ::metrics::{
+    counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit,
+};
+use futures::FutureExt;
+
+use crate::{
+    timings::{BlockTimings, VerificationStage},
+    DatabaseRequest,
+};
+
+pub const BLOCKS_VERIFIED: &str = "consensus_blocks_verified_total";
+pub const STAGE_DURATION: &str = "consensus_verification_stage_duration_seconds";
+pub const DATABASE_REQUEST_DURATION: &str = "consensus_database_request_duration_seconds";
+pub const WEIGHT_CACHE_LEN: &str = "consensus_weight_cache_len";
+pub const HARD_FORK_VOTES_LEN: &str = "consensus_hard_fork_votes_len";
+pub const WRITE_BATCH_SIZE: &str = "consensus_write_batch_size";
+
+/// Gives the metrics their units and descriptions.
+pub fn describe_metrics() {
+    describe_counter!(
+        BLOCKS_VERIFIED,
+        Unit::Count,
+        "The amount of blocks verified."
+    );
+    describe_histogram!(
+        STAGE_DURATION,
+        Unit::Seconds,
+        "The time a block spent in each verification stage."
+    );
+    describe_histogram!(
+        DATABASE_REQUEST_DURATION,
+        Unit::Seconds,
+        "The time the database took to answer each request."
+    );
+    describe_gauge!(
+        WEIGHT_CACHE_LEN,
+        Unit::Count,
+        "The amount of block weights in the weight cache's windows."
+    );
+    describe_gauge!(
+        HARD_FORK_VOTES_LEN,
+        Unit::Count,
+        "The amount of hard-fork votes in the voting window."
+    );
+    describe_histogram!(
+        WRITE_BATCH_SIZE,
+        Unit::Count,
+        "The amount of blocks written to the database in each batch."
+    );
+}
+
+/// Records a verified block and the time it spent in each stage.
+pub(crate) fn record_block_timings(timings: &BlockTimings) {
+    counter!(BLOCKS_VERIFIED).increment(1);
+
+    for stage in VerificationStage::ALL {
+        histogram!(STAGE_DURATION, "stage" => stage.name())
+            .record(timings.stage(stage).as_secs_f64());
+    }
+}
+
+/// Records the amount of block weights in the weight cache's windows.
+pub(crate) fn record_weight_cache_len(short_term: usize, long_term: usize) {
+    gauge!(WEIGHT_CACHE_LEN, "window" => "short_term").set(short_term as f64);
+    gauge!(WEIGHT_CACHE_LEN, "window" => "long_term").set(long_term as f64);
+}
+
+/// Records the amount of votes in the hard-fork voting window.
+pub(crate) fn record_hard_fork_votes_len(votes: u64) {
+    gauge!(HARD_FORK_VOTES_LEN).set(votes as f64);
+}
+
+/// Records the amount of blocks in a written batch.
+pub(crate) fn record_write_batch(blocks: usize) {
+    histogram!(WRITE_BATCH_SIZE).record(blocks as f64);
+}
+
+/// A [`tower::Layer`] that wraps databases in [`MeteredDatabase`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MeteredDatabaseLayer;
+
+impl<D> tower::Layer<D> for MeteredDatabaseLayer {
+    type Service = MeteredDatabase<D>;
+
+    fn layer(&self, inner: D) -> Self::Service {
+        MeteredDatabase::new(inner)
+    }
+}
+
+/// A middleware that records the latency of every database request in
+/// [`DATABASE_REQUEST_DURATION`].
+#[derive(Debug, Clone)]
+pub struct MeteredDatabase<D> {
+    inner: D,
+}
+
+impl<D> MeteredDatabase<D> {
+    pub fn new(inner: D) -> MeteredDatabase<D> {
+        MeteredDatabase { inner }
+    }
+}
+
+impl<D> tower::Service<DatabaseRequest> for MeteredDatabase<D>
+where
+    D: tower::Service<DatabaseRequest>,
+    D::Future: Send + 'static,
+{
+    type Response = D::Response;
+    type Error = D::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: DatabaseRequest) -> Self::Future {
+        let request = req.name();
+        let started = Instant::now();
+
+        self.inner
+            .call(req)
+            .inspect(move |_| {
+                histogram!(DATABASE_REQUEST_DURATION, "request" => request)
+                    .record(started.elapsed().as_secs_f64());
+            })
+            .boxed()
+    }
+}
@@ -1 +1,356 @@
+//! # Miner Transaction
+//!
+//! This module contains [`calculate_block_reward`], the reward of a block after the penalty for
+//! being over the median weight, [`construct_miner_tx`], which builds the miner transaction of a
+//! new block, and [`check_miner_tx`], which checks the miner transaction of a block.
+//!
+//! Building the miner transaction needs the one-time key of its output and the transaction's public
+//! key, [`MinerTxKeys::derive`] derives these from the miner's address and a random transaction key.
+//!
+use curve25519_dalek::{
+    constants::ED25519_BASEPOINT_TABLE, edwards::CompressedEdwardsY, EdwardsPoint, Scalar,
+};
+use monero_serai::{
+    hash, hash_to_scalar,
+    ringct::{RctBase, RctPrunable, RctSignatures},
+    transaction::{Input, Output, Timelock, Transaction, TransactionPrefix},
+};
 
+use crate::{
+    consensus_constants::MINED_MONEY_UNLOCK_WINDOW,
+    hardforks::HardFork,
+    tx_extra::{TX_EXTRA_NONCE, TX_EXTRA_TAG_PUBKEY},
+    MinerTxError,
+};
+
+/// The maximum size of the extra nonce, the space in the miner transaction's extra that miners can
+/// fill however they like.
+pub const MAX_EXTRA_NONCE_SIZE: usize = 255;
+
+/// The keys needed for the output of a miner transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinerTxKeys {
+    /// The transaction's public key, `rG`.
+    pub tx_pub_key: [u8; 32],
+    /// The one-time key of the output to the miner.
+    pub output_key: [u8; 32],
+    /// The view tag of the output, used from [`HardFork::V15`].
+    pub view_tag: u8,
+}
+
+impl MinerTxKeys {
+    /// Derives the keys of a miner transaction paying the address with these public spend and view
+    /// keys. `tx_key` is the transaction's secret key, `r`, and must be random.
+    pub fn derive(
+        spend_key: &EdwardsPoint,
+        view_key: &EdwardsPoint,
+        tx_key: &Scalar,
+    ) -> MinerTxKeys {
+        let derivation = (tx_key * view_key).mul_by_cofactor().compress().to_bytes();
+        // The output's index, 0, as a varint.
+        let output_index = [0];
+
+        let shared_key = hash_to_scalar(&[derivation.as_slice(), &output_index].concat());
+        let view_tag = hash(&[b"view_tag".as_slice(), &derivation, &output_index].concat())[0];
+
+        MinerTxKeys {
+            tx_pub_key: (tx_key * ED25519_BASEPOINT_TABLE).compress().to_bytes(),
+            output_key: (&shared_key * ED25519_BASEPOINT_TABLE + spend_key)
+                .compress()
+                .to_bytes(),
+            view_tag,
+        }
+    }
+}
+
+/// Returns the reward of a block with this weight: the base reward with the penalty for being over
+/// the median weight.
+///
+/// The penalty is `base_reward * ((block_weight / median_weight) - 1)²`. Returns [`None`] if the block
+/// is over twice the median weight, the most a block can weigh.
+///
+/// https://cuprate.github.io/monero-book/consensus_rules/blocks/reward.html#calculating-block-reward
+pub fn calculate_block_reward(
+    base_reward: u64,
+    effective_median_weight: usize,
+    block_weight: usize,
+) -> Option<u64> {
+    if block_weight <= effective_median_weight {
+        return Some(base_reward);
+    }
+
+    if block_weight > 2 * effective_median_weight {
+        return None;
+    }
+
+    let median = effective_median_weight as u128;
+    let weight = block_weight as u128;
+
+    let reward = u128::from(base_reward) * ((2 * median - weight) * weight) / median / median;
+    Some(u64::try_from(reward).unwrap())
+}
+
+/// Returns the amount of bytes `number` takes as a varint.
+pub(crate) fn varint_len(mut number: usize) -> usize {
+    let mut len = 1;
+    while number >= 0x80 {
+        number >>= 7;
+        len += 1;
+    }
+    len
+}
+
+/// Returns the extra of a miner transaction: its public key and the extra nonce, if it isn't empty.
+fn miner_tx_extra(tx_pub_key: &[u8; 32], extra_nonce: &[u8]) -> Vec<u8> {
+    let mut extra = Vec::with_capacity(35 + extra_nonce.len());
+
+    extra.push(TX_EXTRA_TAG_PUBKEY);
+    extra.extend_from_slice(tx_pub_key);
+
+    if !extra_nonce.is_empty() {
+        extra.push(TX_EXTRA_NONCE);
+        let mut size = extra_nonce.len();
+        while size >= 0x80 {
+            extra.push((size & 0x7f) as u8 | 0x80);
+            size >>= 7;
+        }
+        extra.push(size as u8);
+        extra.extend_from_slice(extra_nonce);
+    }
+
+    extra
+}
+
+/// Builds the miner transaction of the block at `height`, paying `reward` to the miner.
+///
+/// The extra holds the transaction's public key followed by the extra nonce, which is at most
+/// [`MAX_EXTRA_NONCE_SIZE`] bytes.
+///
+/// The reward is paid to a single output, so the transaction is only valid from [`HardFork::V4`]
+/// where outputs don't need to be split into denominations, [`build_block_template`] doesn't build
+/// templates for earlier hard forks.
+///
+/// [`build_block_template`]: crate::block_template::build_block_template
+pub fn construct_miner_tx(
+    height: u64,
+    reward: u64,
+    hf: &HardFork,
+    keys: &MinerTxKeys,
+    extra_nonce: &[u8],
+) -> Transaction {
+    assert!(extra_nonce.len() <= MAX_EXTRA_NONCE_SIZE);
+
+    let version = if hf >= &HardFork::V4 { 2 } else { 1 };
+
+    Transaction {
+        prefix: TransactionPrefix {
+            version,
+            timelock: Timelock::Block(usize::try_from(height + MINED_MONEY_UNLOCK_WINDOW).unwrap()),
+            inputs: vec![Input::Gen(height)],
+            outputs: vec![Output {
+                amount: Some(reward),
+                key: CompressedEdwardsY(keys.output_key),
+                view_tag: (hf >= &HardFork::V15).then_some(keys.view_tag),
+            }],
+            extra: miner_tx_extra(&keys.tx_pub_key, extra_nonce),
+        },
+        signatures: vec![],
+        rct_signatures: RctSignatures {
+            base: RctBase {
+                fee: 0,
+                pseudo_outs: vec![],
+                encrypted_amounts: vec![],
+                commitments: vec![],
+            },
+            prunable: RctPrunable::Null,
+        },
+    }
+}
+
+/// Checks the miner transaction of the block at `height`, returning the coins it generated: the
+/// amount of its outputs minus the fees of the block's transactions.
+///
+/// `reward` is the block's reward from [`calculate_block_reward`]. The outputs must pay exactly the
+/// reward and the fees at [`HardFork::V1`] and from [`HardFork::V12`], in between a miner could
+/// claim less.
+pub fn check_miner_tx(
+    tx: &Transaction,
+    height: u64,
+    hf: &HardFork,
+    reward: u64,
+    fees: u64,
+) -> Result<u64, MinerTxError> {
+    let version = tx.prefix.version;
+    let version_allowed = match version {
+        1 => *hf < HardFork::V12,
+        2 => *hf >= HardFork::V4,
+        _ => false,
+    };
+    if !version_allowed {
+        return Err(MinerTxError::VersionNotAllowed { version, hf: *hf });
+    }
+
+    if tx.prefix.inputs != [Input::Gen(height)] {
+        return Err(MinerTxError::InvalidInput);
+    }
+    if tx.prefix.timelock
+        != Timelock::Block(usize::try_from(height + MINED_MONEY_UNLOCK_WINDOW).unwrap())
+    {
+        return Err(MinerTxError::InvalidTimelock);
+    }
+    if *hf >= HardFork::V12 && !matches!(tx.rct_signatures.prunable, RctPrunable::Null) {
+        return Err(MinerTxError::HasSignatures);
+    }
+
+    // View tags are optional in the hard-fork that added them.
+    let view_tags_allowed = |has_view_tag: bool| {
+        if *hf < HardFork::V15 {
+            !has_view_tag
+        } else {
+            *hf == HardFork::V15 || has_view_tag
+        }
+    };
+    if !tx
+        .prefix
+        .outputs
+        .iter()
+        .all(|output| view_tags_allowed(output.view_tag.is_some()))
+    {
+        return Err(MinerTxError::InvalidViewTags(*hf));
+    }
+
+    let outputs = tx
+        .prefix
+        .outputs
+        .iter()
+        .map(|output| output.amount.unwrap_or(0))
+        .try_fold(0_u64, u64::checked_add)
+        .ok_or(MinerTxError::AmountOverflow)?;
+    let total = reward
+        .checked_add(fees)
+        .ok_or(MinerTxError::AmountOverflow)?;
+
+    let exact = *hf == HardFork::V1 || *hf >= HardFork::V12;
+    if outputs > total || (exact && outputs != total) {
+        return Err(MinerTxError::WrongReward {
+            outputs,
+            reward: total,
+        });
+    }
+
+    Ok(outputs.saturating_sub(fees))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx_extra::TxExtra;
+
+    const KEYS: MinerTxKeys = MinerTxKeys {
+        tx_pub_key: [1; 32],
+        output_key: [2; 32],
+        view_tag: 3,
+    };
+
+    #[test]
+    fn block_reward_penalty() {
+        let base_reward = 6 * 10_u64.pow(11);
+
+        assert_eq!(
+            calculate_block_reward(base_reward, 300_000, 300_000),
+            Some(base_reward)
+        );
+        // 50% over the median loses a quarter of the reward.
+        assert_eq!(
+            calculate_block_reward(base_reward, 300_000, 450_000),
+            Some(base_reward / 4 * 3)
+        );
+        // At twice the median there is no reward.
+        assert_eq!(
+            calculate_block_reward(base_reward, 300_000, 600_000),
+            Some(0)
+        );
+        assert_eq!(calculate_block_reward(base_reward, 300_000, 600_001), None);
+    }
+
+    #[test]
+    fn miner_tx_layout() {
+        let tx = construct_miner_tx(100, 1_000, &HardFork::V16, &KEYS, &[0; 8]);
+
+        assert_eq!(tx.prefix.version, 2);
+        assert_eq!(tx.prefix.inputs, vec![Input::Gen(100)]);
+        assert_eq!(tx.prefix.timelock, Timelock::Block(160));
+        assert_eq!(tx.prefix.outputs[0].amount, Some(1_000));
+        assert_eq!(tx.prefix.outputs[0].view_tag, Some(3));
+
+        let extra = &tx.prefix.extra;
+        assert_eq!(extra.len(), 1 + 32 + 2 + 8);
+        assert_eq!(&extra[..33], &[[1].as_slice(), &[1; 32]].concat());
+        assert_eq!(&extra[33..35], &[TX_EXTRA_NONCE, 8]);
+
+        let parsed = TxExtra::parse_strict(extra).unwrap();
+        assert_eq!(parsed.pub_key(), Some(KEYS.tx_pub_key));
+        assert_eq!(parsed.nonce(), Some([0; 8].as_slice()));
+
+        // No view tags before V15 and no extra nonce if there is no space reserved.
+        let tx = construct_miner_tx(100, 1_000, &HardFork::V14, &KEYS, &[]);
+        assert_eq!(tx.prefix.outputs[0].view_tag, None);
+        assert_eq!(tx.prefix.extra.len(), 33);
+
+        // Extra nonces of 128 bytes or more have a 2 byte length.
+        let tx = construct_miner_tx(100, 1_000, &HardFork::V16, &KEYS, &[7; 200]);
+        assert_eq!(&tx.prefix.extra[33..36], &[TX_EXTRA_NONCE, 0xc8, 0x01]);
+        assert_eq!(&tx.prefix.extra[36..], &[7; 200]);
+        assert_eq!(varint_len(200), 2);
+    }
+    #[test]
+    fn miner_tx_checks() {
+        let tx = construct_miner_tx(100, 1_000, &HardFork::V16, &KEYS, &[]);
+        assert_eq!(check_miner_tx(&tx, 100, &HardFork::V16, 900, 100), Ok(900));
+        assert_eq!(
+            check_miner_tx(&tx, 100, &HardFork::V16, 1_000, 100),
+            Err(MinerTxError::WrongReward {
+                outputs: 1_000,
+                reward: 1_100
+            })
+        );
+        assert_eq!(
+            check_miner_tx(&tx, 101, &HardFork::V16, 900, 100),
+            Err(MinerTxError::InvalidInput)
+        );
+        // The output has a view tag.
+        assert_eq!(
+            check_miner_tx(&tx, 100, &HardFork::V14, 900, 100),
+            Err(MinerTxError::InvalidViewTags(HardFork::V14))
+        );
+
+        // Between V2 and V12 a miner can claim less than the reward.
+        let tx = construct_miner_tx(100, 1_000, &HardFork::V10, &KEYS, &[]);
+        assert_eq!(
+            check_miner_tx(&tx, 100, &HardFork::V10, 2_000, 100),
+            Ok(900)
+        );
+        assert_eq!(
+            check_miner_tx(&tx, 100, &HardFork::V10, 800, 100),
+            Err(MinerTxError::WrongReward {
+                outputs: 1_000,
+                reward: 900
+            })
+        );
+
+        let mut tx = construct_miner_tx(100, 1_000, &HardFork::V16, &KEYS, &[]);
+        tx.prefix.timelock = Timelock::Block(159);
+        assert_eq!(
+            check_miner_tx(&tx, 100, &HardFork::V16, 1_000, 0),
+            Err(MinerTxError::InvalidTimelock)
+        );
+        tx.prefix.version = 1;
+        assert_eq!(
+            check_miner_tx(&tx, 100, &HardFork::V16, 1_000, 0),
+            Err(MinerTxError::VersionNotAllowed {
+                version: 1,
+                hf: HardFork::V16
+            })
+        );
+    }
+}
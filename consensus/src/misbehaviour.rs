@@ -0,0 +1,228 @@
+//! # Misbehaviour
+//!
+//! This module contains the types that feed verification failures back to whatever sent the data,
+//! so the P2P layer can punish peers that send invalid blocks.
+//!
+//! Every block queued for the verifier is tagged with its [`BlockSource`], see [`QueuedBlock`]. When
+//! a block fails verification the verifier's owner calls
+//! [`Verifier::report_failure`](crate::verifier::Verifier::report_failure) with the block's source
+//! and the error, which classifies the failure with a [`Severity`] and gives a
+//! [`MisbehaviourReport`] to the [`MisbehaviourListener`] set with
+//! [`Config::with_misbehaviour_listener`](crate::verifier::Config::with_misbehaviour_listener).
+//!
+//! Failures that are not the source's fault, like a stale block or a database error, have
+//! [`Severity::None`] and are not reported.
+//!
+use std::fmt::{self, Display};
+
+use monero_serai::block::Block;
+
+use crate::{BlockError, ConsensusError, HardForkError, TransactionError};
+
+/// The id the P2P layer gave a connection, the P2P layer maps it back to the peer's address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId(pub u64);
+
+/// Where a block being verified came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockSource {
+    /// A block relayed or downloaded from a peer.
+    Peer(PeerId),
+    /// A block submitted over RPC, like a mined block from `submit_block`.
+    Rpc,
+    /// A block from a bootstrap file, see [`bootstrap`](crate::bootstrap).
+    Import,
+}
+
+impl Display for BlockSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockSource::Peer(PeerId(id)) => write!(f, "peer {id:016x}"),
+            BlockSource::Rpc => f.write_str("rpc"),
+            BlockSource::Import => f.write_str("import"),
+        }
+    }
+}
+
+/// A block queued for the verifier, see
+/// [`verification_queue`](crate::verification_queue::verification_queue).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedBlock {
+    pub source: BlockSource,
+    pub block: Block,
+}
+
+/// How badly a verification failure reflects on the source of the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Not the source's fault, like a stale block that doesn't build on our chain, a transaction
+    /// below our pool's fee or a failure on our side.
+    None,
+    /// Invalid, but an honest node with another view of the chain, or on an older version, could
+    /// have sent it. The peer's score should be lowered.
+    Minor,
+    /// Only a broken or malicious node sends this, like a block with invalid PoW. The peer should
+    /// be banned.
+    Ban,
+}
+
+impl Severity {
+    /// Returns the severity of a verification failure.
+    pub fn of(error: &ConsensusError) -> Severity {
+        if !error.is_peer_fault() {
+            return Severity::None;
+        }
+
+        match error {
+            ConsensusError::Block(e) => match e {
+                BlockError::PrunedBlockNotAllowed { .. } => Severity::Minor,
+                _ => Severity::Ban,
+            },
+            ConsensusError::Transaction(e) => match e {
+                // These depend on the outputs in the chain, which a reorg can change.
+                TransactionError::CoinbaseNotMature { .. }
+                | TransactionError::OutputLocked { .. }
+                | TransactionError::InvalidRingMember { .. } => Severity::Minor,
+                _ => Severity::Ban,
+            },
+            ConsensusError::HardFork(e) => match e {
+                // The peer may be on a hard-fork we don't know about yet.
+                HardForkError::UnknownVersion(_) => Severity::Minor,
+                _ => Severity::Ban,
+            },
+            ConsensusError::Internal(_) => Severity::None,
+        }
+    }
+}
+
+/// A verification failure blamed on the source of the data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MisbehaviourReport {
+    pub source: BlockSource,
+    pub severity: Severity,
+    /// The error the data failed verification with.
+    pub reason: String,
+}
+
+impl MisbehaviourReport {
+    /// Returns the report of a failure of data from `source`.
+    pub fn new(source: BlockSource, error: &ConsensusError) -> MisbehaviourReport {
+        MisbehaviourReport {
+            source,
+            severity: Severity::of(error),
+            reason: error.to_string(),
+        }
+    }
+}
+
+/// Told about verification failures blamed on the source of the data, see
+/// [`Verifier::report_failure`](crate::verifier::Verifier::report_failure).
+///
+/// This is called by the task verifying blocks so it should not block.
+pub trait MisbehaviourListener: fmt::Debug + Send + Sync {
+    /// Called after data failed verification with a [`Severity`] other than [`Severity::None`].
+    fn misbehaviour(&self, report: &MisbehaviourReport);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::executor::block_on;
+
+    use cuprate_common::Network;
+
+    use super::*;
+    use crate::{
+        test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder},
+        verifier::{Config, Verifier},
+    };
+
+    #[test]
+    fn severities() {
+        let severity = |error: ConsensusError| Severity::of(&error);
+
+        assert_eq!(
+            severity(BlockError::InvalidPow { height: 10 }.into()),
+            Severity::Ban
+        );
+        let stale = BlockError::DoesNotExtendChain {
+            previous: [1; 32],
+            top_hash: [2; 32],
+        };
+        assert_eq!(severity(stale.into()), Severity::None);
+        assert_eq!(
+            severity(BlockError::PrunedBlockNotAllowed { height: 10 }.into()),
+            Severity::Minor
+        );
+
+        assert_eq!(
+            severity(TransactionError::InvalidRingSignature(0).into()),
+            Severity::Ban
+        );
+        assert_eq!(
+            severity(
+                TransactionError::OutputLocked {
+                    amount: 0,
+                    index: 5
+                }
+                .into()
+            ),
+            Severity::Minor
+        );
+        assert_eq!(
+            severity(TransactionError::FeeTooLow { fee: 1, minimum: 2 }.into()),
+            Severity::None
+        );
+
+        assert_eq!(
+            severity(HardForkError::UnknownVersion(200).into()),
+            Severity::Minor
+        );
+        assert_eq!(
+            severity(HardForkError::UnknownFork(200).into()),
+            Severity::None
+        );
+        assert_eq!(
+            severity(tower::BoxError::from("database closed").into()),
+            Severity::None
+        );
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingListener(Mutex<Vec<MisbehaviourReport>>);
+
+    impl MisbehaviourListener for RecordingListener {
+        fn misbehaviour(&self, report: &MisbehaviourReport) {
+            self.0.lock().unwrap().push(report.clone());
+        }
+    }
+
+    #[test]
+    fn failures_are_reported_to_the_listener() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+        let listener = Arc::new(RecordingListener::default());
+        let verifier = block_on(Verifier::init(
+            Config::for_network(Network::Mainnet).with_misbehaviour_listener(listener.clone()),
+            database,
+        ))
+        .unwrap();
+
+        let source = BlockSource::Peer(PeerId(7));
+        let invalid_pow: ConsensusError = BlockError::InvalidPow { height: 10 }.into();
+        let report = verifier.report_failure(source, &invalid_pow).unwrap();
+        assert_eq!(report.source, source);
+        assert_eq!(report.severity, Severity::Ban);
+
+        let stale: ConsensusError = BlockError::DoesNotExtendChain {
+            previous: [1; 32],
+            top_hash: [2; 32],
+        }
+        .into();
+        assert!(verifier.report_failure(source, &stale).is_none());
+
+        assert_eq!(*listener.0.lock().unwrap(), vec![report]);
+    }
+}
@@ -0,0 +1,271 @@
+//! # Output Analytics
+//!
+//! This module contains [`OutputAnalytics`], an optional research mode that keeps, for every output
+//! used as a ring member, the height it was first referenced at and the amount of rings it is in. It
+//! lets researchers study output ages and decoy usage on the node, without an external indexer.
+//!
+//! Outputs are identified by their (amount, amount index), RingCT outputs have an amount of 0.
+//!
+//! The analytics are updated as blocks are added to and popped from the main chain, nothing in here
+//! is used to verify blocks. Pruned blocks have no rings to read, so a node in research mode should
+//! not accept pruned blocks, see [`VerificationProfile::Full`](crate::verifier::VerificationProfile::Full).
+//!
+//! Every ring member ever referenced is kept in memory, on mainnet this is a lot of data.
+//!
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
+
+use monero_serai::transaction::Transaction;
+
+use crate::{
+    block::{VerifiedBlockInformation, VerifiedBlockTxs},
+    decoys::ring_members,
+    spans::TX_TARGET,
+};
+
+/// The usage of an output as a ring member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputUsage {
+    /// The height of the first block with a ring this output is in.
+    pub first_reference_height: u64,
+    /// The amount of rings this output is in.
+    pub ring_memberships: u64,
+}
+
+#[derive(Debug, Default)]
+struct AnalyticsTables {
+    /// (amount, amount index) => usage.
+    outputs: HashMap<(u64, u64), OutputUsage>,
+    /// first reference height => the outputs first referenced in that block.
+    first_references: BTreeMap<u64, Vec<(u64, u64)>>,
+    /// The height of the next block to add, [`None`] until the first block is added.
+    next_height: Option<u64>,
+}
+
+/// Keeps per-output first reference heights and ring membership counts, clones of this share the
+/// same tables.
+#[derive(Debug, Clone, Default)]
+pub struct OutputAnalytics {
+    tables: Arc<RwLock<AnalyticsTables>>,
+}
+
+impl OutputAnalytics {
+    pub fn new() -> OutputAnalytics {
+        OutputAnalytics::default()
+    }
+
+    /// Adds the rings of a block added to the main chain.
+    ///
+    /// Returns false, without changing the analytics, if the block was received pruned.
+    pub fn add_verified_block(&self, block: &VerifiedBlockInformation) -> bool {
+        let VerifiedBlockTxs::Full(txs) = &block.txs else {
+            tracing::warn!(
+                target: TX_TARGET,
+                height = block.height,
+                "Block was received pruned, its rings are not in the output analytics"
+            );
+            return false;
+        };
+
+        self.add_block(block.height, txs);
+        true
+    }
+
+    /// Adds the rings of the transactions of a block added to the main chain.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this isn't the block after the last block added.
+    pub fn add_block(&self, height: u64, txs: &[Transaction]) {
+        let mut tables = self.tables.write().unwrap();
+        let tables = &mut *tables;
+
+        assert_eq!(*tables.next_height.get_or_insert(height), height);
+        tables.next_height = Some(height + 1);
+
+        for member in txs.iter().flat_map(ring_members).flatten() {
+            let usage = tables.outputs.entry(member).or_insert_with(|| {
+                tables
+                    .first_references
+                    .entry(height)
+                    .or_default()
+                    .push(member);
+
+                OutputUsage {
+                    first_reference_height: height,
+                    ring_memberships: 0,
+                }
+            });
+            usage.ring_memberships += 1;
+        }
+    }
+
+    /// Removes the rings of the transactions of the top block, for reorgs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this isn't the last block added.
+    pub fn pop_block(&self, height: u64, txs: &[Transaction]) {
+        let mut tables = self.tables.write().unwrap();
+        let tables = &mut *tables;
+
+        assert_eq!(tables.next_height, Some(height + 1));
+        tables.next_height = Some(height);
+
+        for member in txs.iter().flat_map(ring_members).flatten() {
+            let usage = tables
+                .outputs
+                .get_mut(&member)
+                .expect("Ring members of added blocks are in the analytics");
+            usage.ring_memberships -= 1;
+        }
+
+        // Outputs first referenced in the top block can't be referenced by any other block.
+        for member in tables.first_references.remove(&height).unwrap_or_default() {
+            let usage = tables.outputs.remove(&member);
+            debug_assert_eq!(usage.map(|usage| usage.ring_memberships), Some(0));
+        }
+    }
+
+    /// Returns the usage of an output, [`None`] if it has never been a ring member.
+    pub fn output(&self, amount: u64, index: u64) -> Option<OutputUsage> {
+        self.tables
+            .read()
+            .unwrap()
+            .outputs
+            .get(&(amount, index))
+            .copied()
+    }
+
+    /// Returns the outputs first referenced in the blocks in the range, ordered by the height they
+    /// were first referenced at.
+    pub fn first_referenced_in(&self, range: Range<u64>) -> Vec<((u64, u64), OutputUsage)> {
+        let tables = self.tables.read().unwrap();
+
+        tables
+            .first_references
+            .range(range)
+            .flat_map(|(_, members)| members)
+            .map(|member| (*member, tables.outputs[member]))
+            .collect()
+    }
+
+    /// Returns the amount of outputs with each amount of ring memberships.
+    pub fn ring_membership_histogram(&self) -> BTreeMap<u64, u64> {
+        let mut histogram = BTreeMap::new();
+        for usage in self.tables.read().unwrap().outputs.values() {
+            *histogram.entry(usage.ring_memberships).or_default() += 1;
+        }
+        histogram
+    }
+
+    /// Returns the amount of outputs that have been ring members.
+    pub fn referenced_outputs(&self) -> usize {
+        self.tables.read().unwrap().outputs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use monero_serai::{
+        ringct::{RctBase, RctPrunable, RctSignatures},
+        transaction::{Input, Timelock, TransactionPrefix},
+    };
+
+    use super::*;
+
+    /// Returns a transaction with a RingCT input for each ring, rings are given as absolute indices.
+    fn tx(rings: &[&[u64]]) -> Transaction {
+        Transaction {
+            prefix: TransactionPrefix {
+                version: 2,
+                timelock: Timelock::None,
+                inputs: rings
+                    .iter()
+                    .map(|ring| Input::ToKey {
+                        amount: None,
+                        key_offsets: ring
+                            .iter()
+                            .scan(0, |last, index| {
+                                let offset = index - *last;
+                                *last = *index;
+                                Some(offset)
+                            })
+                            .collect(),
+                        key_image: curve25519_dalek::edwards::EdwardsPoint::default(),
+                    })
+                    .collect(),
+                outputs: vec![],
+                extra: vec![],
+            },
+            signatures: vec![],
+            rct_signatures: RctSignatures {
+                base: RctBase {
+                    fee: 0,
+                    pseudo_outs: vec![],
+                    encrypted_amounts: vec![],
+                    commitments: vec![],
+                },
+                prunable: RctPrunable::Null,
+            },
+        }
+    }
+
+    #[test]
+    fn first_references_and_memberships() {
+        let analytics = OutputAnalytics::new();
+
+        analytics.add_block(100, &[tx(&[&[1, 5, 9]]), tx(&[&[5, 10]])]);
+        analytics.add_block(101, &[tx(&[&[1, 2], &[9, 11]])]);
+
+        assert_eq!(
+            analytics.output(0, 5),
+            Some(OutputUsage {
+                first_reference_height: 100,
+                ring_memberships: 2
+            })
+        );
+        assert_eq!(
+            analytics.output(0, 2),
+            Some(OutputUsage {
+                first_reference_height: 101,
+                ring_memberships: 1
+            })
+        );
+        assert_eq!(analytics.output(0, 3), None);
+        assert_eq!(analytics.output(5, 5), None);
+
+        let first_referenced: Vec<_> = analytics
+            .first_referenced_in(101..102)
+            .into_iter()
+            .map(|(member, _)| member)
+            .collect();
+        assert_eq!(first_referenced, vec![(0, 2), (0, 11)]);
+
+        // 1, 5 and 9 are in 2 rings, 2, 10 and 11 in 1.
+        assert_eq!(
+            analytics.ring_membership_histogram(),
+            BTreeMap::from([(1, 3), (2, 3)])
+        );
+    }
+
+    #[test]
+    fn pop_block() {
+        let analytics = OutputAnalytics::new();
+        let top_block = [tx(&[&[1, 2]])];
+
+        analytics.add_block(100, &[tx(&[&[1, 5]])]);
+        analytics.add_block(101, &top_block);
+        assert_eq!(analytics.referenced_outputs(), 3);
+
+        analytics.pop_block(101, &top_block);
+        assert_eq!(analytics.referenced_outputs(), 2);
+        assert_eq!(analytics.output(0, 1).unwrap().ring_memberships, 1);
+        assert_eq!(analytics.output(0, 2), None);
+        assert!(analytics.first_referenced_in(101..102).is_empty());
+
+        // The chain can grow again from the popped height.
+        analytics.add_block(101, &[]);
+    }
+}
@@ -0,0 +1,428 @@
+//! # Output Distribution
+//!
+//! This module contains [`OutputDistributionCache`], a database wrapper that keeps the cumulative
+//! distribution of RingCT outputs in memory. Wallets ask for it with `get_output_distribution` to
+//! pick decoys, normally from the genesis block, and reading it from the database reads the
+//! metadata of every block in the chain.
+//!
+//! The distribution is read from the database the first time [`DatabaseRequest::OutputDistribution`]
+//! is requested for RingCT outputs, then it is kept up to date by the blocks written and popped
+//! through the cache, so each request only copies the heights it asked for. Blocks must be written
+//! through the cache, or a clone of it, for it to see them.
+//!
+//! Pre-RingCT amounts are rarely requested and are not cached, their requests and every other
+//! request are passed to the database.
+//!
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::FutureExt;
+use tower::ServiceExt;
+
+use crate::{DatabaseRequest, DatabaseResponse};
+
+#[derive(Debug, Default)]
+struct CachedDistribution {
+    /// The amount of RingCT outputs created up to and including each block, empty until it is read
+    /// from the database.
+    cumulative: Vec<u64>,
+    /// The amount of writes made through the cache, a distribution read from the database before
+    /// a write finished is not cached.
+    writes: u64,
+}
+
+/// A database that answers RingCT output distribution requests from memory, clones of this share
+/// the same distribution.
+#[derive(Debug, Clone)]
+pub struct OutputDistributionCache<D> {
+    inner: D,
+    cached: Arc<Mutex<CachedDistribution>>,
+}
+
+impl<D> OutputDistributionCache<D> {
+    pub fn new(inner: D) -> Self {
+        OutputDistributionCache {
+            inner,
+            cached: Arc::default(),
+        }
+    }
+
+    /// Returns the amount of blocks in the cached distribution, 0 if it hasn't been read yet.
+    pub fn cached_blocks(&self) -> usize {
+        self.cached.lock().unwrap().cumulative.len()
+    }
+}
+
+impl<D> tower::Service<DatabaseRequest> for OutputDistributionCache<D>
+where
+    D: tower::Service<DatabaseRequest, Response = DatabaseResponse, Error = tower::BoxError>
+        + Clone
+        + Send
+        + 'static,
+    D::Future: Send + 'static,
+{
+    type Response = DatabaseResponse;
+    type Error = tower::BoxError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Requests answered from memory don't need the inner service, it is waited for in the
+        // returned future.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: DatabaseRequest) -> Self::Future {
+        let inner = self.inner.clone();
+        let cached = self.cached.clone();
+
+        match req {
+            DatabaseRequest::OutputDistribution {
+                amount: 0,
+                from_height,
+                to_height,
+            } => async move {
+                if let Some(distribution) = cached_range(&cached, from_height, to_height) {
+                    return Ok(DatabaseResponse::OutputDistribution(distribution));
+                }
+
+                fill(inner.clone(), &cached).await?;
+                if let Some(distribution) = cached_range(&cached, from_height, to_height) {
+                    return Ok(DatabaseResponse::OutputDistribution(distribution));
+                }
+
+                // Heights past the top of the chain, the database gives the error.
+                inner
+                    .oneshot(DatabaseRequest::OutputDistribution {
+                        amount: 0,
+                        from_height,
+                        to_height,
+                    })
+                    .await
+            }
+            .boxed(),
+            DatabaseRequest::WriteBlock(block) => {
+                let height = block.height;
+                write(inner, cached, DatabaseRequest::WriteBlock(block), height, 1).boxed()
+            }
+            DatabaseRequest::WriteBlocks(blocks) => {
+                let height = blocks.first().map_or(0, |block| block.height);
+                let count = blocks.len() as u64;
+                write(
+                    inner,
+                    cached,
+                    DatabaseRequest::WriteBlocks(blocks),
+                    height,
+                    count,
+                )
+                .boxed()
+            }
+            DatabaseRequest::PopBlock => async move {
+                let res = inner.oneshot(DatabaseRequest::PopBlock).await?;
+
+                let mut cached = cached.lock().unwrap();
+                cached.writes += 1;
+                cached.cumulative.pop();
+                Ok(res)
+            }
+            .boxed(),
+            req => inner.oneshot(req).boxed(),
+        }
+    }
+}
+
+/// Writes `count` blocks from `height` and adds their outputs to the cached distribution.
+async fn write<D>(
+    inner: D,
+    cached: Arc<Mutex<CachedDistribution>>,
+    req: DatabaseRequest,
+    height: u64,
+    count: u64,
+) -> Result<DatabaseResponse, tower::BoxError>
+where
+    D: tower::Service<DatabaseRequest, Response = DatabaseResponse, Error = tower::BoxError>
+        + Clone,
+{
+    let res = inner.clone().oneshot(req).await?;
+    if count == 0 {
+        return Ok(res);
+    }
+
+    let writes = {
+        let mut cached = cached.lock().unwrap();
+        cached.writes += 1;
+        if cached.cumulative.len() as u64 != height {
+            // The cache missed a block, it is read again on the next request.
+            cached.cumulative.clear();
+            return Ok(res);
+        }
+        cached.writes
+    };
+
+    let new_cumulative = inner
+        .oneshot(DatabaseRequest::OutputDistribution {
+            amount: 0,
+            from_height: height,
+            to_height: height + count - 1,
+        })
+        .await
+        .and_then(|res| Ok(res.into_output_distribution()?));
+
+    let mut cached = cached.lock().unwrap();
+    match new_cumulative {
+        Ok(new_cumulative) if cached.writes == writes && new_cumulative.len() as u64 == count => {
+            cached.cumulative.extend(new_cumulative)
+        }
+        _ => cached.cumulative.clear(),
+    }
+    Ok(res)
+}
+
+/// Returns the cached distribution from `from_height` to `to_height`, if it is cached.
+fn cached_range(
+    cached: &Mutex<CachedDistribution>,
+    from_height: u64,
+    to_height: u64,
+) -> Option<Vec<u64>> {
+    let cached = cached.lock().unwrap();
+    let from = usize::try_from(from_height).ok()?;
+    let to = usize::try_from(to_height).ok()?;
+
+    cached.cumulative.get(from..=to).map(<[u64]>::to_vec)
+}
+
+/// Reads the whole distribution from the database into the cache.
+async fn fill<D>(inner: D, cached: &Mutex<CachedDistribution>) -> Result<(), tower::BoxError>
+where
+    D: tower::Service<DatabaseRequest, Response = DatabaseResponse, Error = tower::BoxError>
+        + Clone,
+{
+    let writes = cached.lock().unwrap().writes;
+
+    let chain_height = inner
+        .clone()
+        .oneshot(DatabaseRequest::ChainHeight)
+        .await?
+        .into_chain_height()?;
+    if chain_height == 0 {
+        return Ok(());
+    }
+
+    let cumulative = inner
+        .oneshot(DatabaseRequest::OutputDistribution {
+            amount: 0,
+            from_height: 0,
+            to_height: chain_height - 1,
+        })
+        .await?
+        .into_output_distribution()?;
+
+    let mut cached = cached.lock().unwrap();
+    if cached.writes == writes {
+        cached.cumulative = cumulative;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::block::VerifiedBlockInformation;
+    use crate::hardforks::HardFork;
+    use crate::test_utils::{dummy_block, dummy_miner_tx, dummy_output, dummy_verified_block};
+
+    /// A database holding the amount of RingCT outputs of each block, it counts the distribution
+    /// reads.
+    #[derive(Clone, Default)]
+    struct CountingDatabase {
+        rct_outputs: Arc<Mutex<Vec<u64>>>,
+        distribution_reads: Arc<AtomicUsize>,
+    }
+
+    impl CountingDatabase {
+        fn service(
+            &self,
+        ) -> impl tower::Service<
+            DatabaseRequest,
+            Response = DatabaseResponse,
+            Error = tower::BoxError,
+            Future = std::future::Ready<Result<DatabaseResponse, tower::BoxError>>,
+        > + Clone
+               + Send
+               + 'static {
+            let database = self.clone();
+            tower::service_fn(move |req| {
+                let mut rct_outputs = database.rct_outputs.lock().unwrap();
+                std::future::ready(Ok(match req {
+                    DatabaseRequest::ChainHeight => {
+                        DatabaseResponse::ChainHeight(rct_outputs.len() as u64)
+                    }
+                    DatabaseRequest::OutputDistribution {
+                        from_height,
+                        to_height,
+                        ..
+                    } => {
+                        database.distribution_reads.fetch_add(1, Ordering::Relaxed);
+                        let cumulative: Vec<u64> = rct_outputs
+                            .iter()
+                            .scan(0, |total, count| {
+                                *total += count;
+                                Some(*total)
+                            })
+                            .collect();
+                        let Some(distribution) =
+                            cumulative.get(from_height as usize..=to_height as usize)
+                        else {
+                            return std::future::ready(Err("Heights not in the database".into()));
+                        };
+                        DatabaseResponse::OutputDistribution(distribution.to_vec())
+                    }
+                    DatabaseRequest::WriteBlock(block) => {
+                        rct_outputs.push(block.block.txs.len() as u64 + 1);
+                        DatabaseResponse::WriteBlock
+                    }
+                    DatabaseRequest::WriteBlocks(blocks) => {
+                        rct_outputs.extend(blocks.iter().map(|b| b.block.txs.len() as u64 + 1));
+                        DatabaseResponse::WriteBlock
+                    }
+                    DatabaseRequest::PopBlock => {
+                        rct_outputs.pop();
+                        DatabaseResponse::PopBlock(Box::new(block_with_txs(0).block), vec![])
+                    }
+                    _ => panic!("The cache only reads the distribution"),
+                }))
+            })
+        }
+
+        fn reads(&self) -> usize {
+            self.distribution_reads.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Returns a block at this height with a miner transaction and `height` other transactions.
+    fn block_with_txs(height: u64) -> VerifiedBlockInformation {
+        let mut block = dummy_block(
+            HardFork::V16,
+            HardFork::V16,
+            [0; 32],
+            dummy_miner_tx(2, None, vec![dummy_output(None)]),
+        );
+        block.txs = vec![[0; 32]; height as usize];
+        dummy_verified_block(block, height)
+    }
+
+    fn distribution<D>(
+        cache: &OutputDistributionCache<D>,
+        from_height: u64,
+        to_height: u64,
+    ) -> Vec<u64>
+    where
+        D: tower::Service<DatabaseRequest, Response = DatabaseResponse, Error = tower::BoxError>
+            + Clone
+            + Send
+            + 'static,
+        D::Future: Send + 'static,
+    {
+        block_on(cache.clone().oneshot(DatabaseRequest::OutputDistribution {
+            amount: 0,
+            from_height,
+            to_height,
+        }))
+        .unwrap()
+        .into_output_distribution()
+        .unwrap()
+    }
+
+    #[test]
+    fn distribution_is_read_once() {
+        let database = CountingDatabase::default();
+        *database.rct_outputs.lock().unwrap() = vec![1, 2, 3];
+        let cache = OutputDistributionCache::new(database.service());
+
+        assert_eq!(distribution(&cache, 0, 2), vec![1, 3, 6]);
+        assert_eq!(distribution(&cache, 1, 2), vec![3, 6]);
+        assert_eq!(cache.cached_blocks(), 3);
+        // One read for the whole chain, the second request was answered from memory.
+        assert_eq!(database.reads(), 1);
+
+        // Heights past the top of the chain are not in the cache.
+        assert!(
+            block_on(cache.clone().oneshot(DatabaseRequest::OutputDistribution {
+                amount: 0,
+                from_height: 0,
+                to_height: 3,
+            }))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn writes_and_pops_update_the_distribution() {
+        let database = CountingDatabase::default();
+        let cache = OutputDistributionCache::new(database.service());
+
+        for height in 0..3 {
+            block_on(
+                cache
+                    .clone()
+                    .oneshot(DatabaseRequest::WriteBlock(Box::new(block_with_txs(
+                        height,
+                    )))),
+            )
+            .unwrap();
+        }
+        // A cache made for an empty chain follows it from the genesis block.
+        assert_eq!(cache.cached_blocks(), 3);
+        assert_eq!(distribution(&cache, 0, 2), vec![1, 3, 6]);
+
+        block_on(cache.clone().oneshot(DatabaseRequest::PopBlock)).unwrap();
+        block_on(
+            cache
+                .clone()
+                .oneshot(DatabaseRequest::WriteBlock(Box::new(block_with_txs(2)))),
+        )
+        .unwrap();
+        block_on(
+            cache
+                .clone()
+                .oneshot(DatabaseRequest::WriteBlock(Box::new(block_with_txs(3)))),
+        )
+        .unwrap();
+
+        let reads = database.reads();
+        assert_eq!(distribution(&cache, 0, 3), vec![1, 3, 6, 10]);
+        assert_eq!(database.reads(), reads);
+    }
+
+    #[test]
+    fn batched_writes_update_the_distribution() {
+        let database = CountingDatabase::default();
+        let cache = OutputDistributionCache::new(database.service());
+
+        block_on(
+            cache
+                .clone()
+                .oneshot(DatabaseRequest::WriteBlocks((0..3).map(block).collect())),
+        )
+        .unwrap();
+        block_on(cache.clone().oneshot(DatabaseRequest::WriteBlocks(vec![]))).unwrap();
+        block_on(
+            cache
+                .clone()
+                .oneshot(DatabaseRequest::WriteBlocks((3..5).map(block).collect())),
+        )
+        .unwrap();
+
+        assert_eq!(cache.cached_blocks(), 5);
+        // One read for each batch.
+        assert_eq!(database.reads(), 2);
+        assert_eq!(distribution(&cache, 0, 4), vec![1, 3, 6, 10, 15]);
+        assert_eq!(database.reads(), 2);
+    }
+}
@@ -0,0 +1,123 @@
+//! # Outputs
+//!
+//! This module contains the types the database returns when serving the chain to wallets: the blobs
+//! of blocks and their transactions ([`BlockBlobs`]) and the outputs used as ring members
+//! ([`OutputOnChain`], or just their time-locks: [`OutputTimeLock`]).
+//!
+//! It also contains [`is_output_unlocked`], the time-lock check monerod uses when telling wallets if
+//! an output can be spent.
+//!
+use bytes::Bytes;
+use monero_serai::transaction::Timelock;
+
+use crate::{consensus_constants::LOCKED_TX_ALLOWED_DELTA_BLOCKS, hardforks::HardFork};
+
+/// The blob of a transaction in a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxBlob {
+    /// The transaction, without the prunable data if the transaction was pruned.
+    pub blob: Bytes,
+    /// The hash of the prunable data, only set if the transaction was pruned.
+    pub prunable_hash: Option<[u8; 32]>,
+}
+
+/// The blobs of a block and its transactions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockBlobs {
+    pub block: Vec<u8>,
+    pub block_weight: usize,
+    /// The transactions in the block, not including the miner transaction.
+    pub txs: Vec<TxBlob>,
+    /// The output indices of every transaction in the block, the miner transaction is first.
+    pub output_indices: Vec<Vec<u64>>,
+}
+
+/// An output in the chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputOnChain {
+    /// The height of the block the output was created in.
+    pub height: u64,
+    pub time_lock: Timelock,
+    pub key: [u8; 32],
+    /// The output's commitment, for pre-RingCT outputs this is a commitment to the amount with a
+    /// mask of 1.
+    pub mask: [u8; 32],
+    /// The hash of the transaction that created the output.
+    pub txid: [u8; 32],
+}
+
+/// The time-lock of an output in the chain, what's needed to check if a ring member can be spent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputTimeLock {
+    /// The height of the block the output was created in.
+    pub height: u64,
+    pub time_lock: Timelock,
+    /// If the output was created by a miner transaction.
+    pub is_coinbase: bool,
+}
+
+/// Returns true if an output with this time-lock can be spent in the next block.
+///
+/// `current_time` is the UNIX timestamp to check time based locks against.
+pub fn is_output_unlocked(
+    time_lock: &Timelock,
+    chain_height: u64,
+    current_time: u64,
+    hf: &HardFork,
+) -> bool {
+    match time_lock {
+        Timelock::None => true,
+        Timelock::Block(unlock_height) => {
+            chain_height - 1 + LOCKED_TX_ALLOWED_DELTA_BLOCKS >= *unlock_height as u64
+        }
+        Timelock::Time(unlock_time) => {
+            current_time + hf.block_time().as_secs() * LOCKED_TX_ALLOWED_DELTA_BLOCKS
+                >= *unlock_time
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use monero_serai::transaction::Timelock;
+
+    use super::is_output_unlocked;
+    use crate::hardforks::HardFork;
+
+    #[test]
+    fn time_locks() {
+        assert!(is_output_unlocked(&Timelock::None, 1, 0, &HardFork::V16));
+
+        assert!(is_output_unlocked(
+            &Timelock::Block(100),
+            100,
+            0,
+            &HardFork::V16
+        ));
+        assert!(!is_output_unlocked(
+            &Timelock::Block(101),
+            100,
+            0,
+            &HardFork::V16
+        ));
+
+        assert!(is_output_unlocked(
+            &Timelock::Time(1_120),
+            1,
+            1_000,
+            &HardFork::V16
+        ));
+        assert!(!is_output_unlocked(
+            &Timelock::Time(1_121),
+            1,
+            1_000,
+            &HardFork::V16
+        ));
+        assert!(!is_output_unlocked(
+            &Timelock::Time(1_061),
+            1,
+            1_000,
+            &HardFork::V1
+        ));
+    }
+}
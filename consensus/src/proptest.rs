@@ -0,0 +1,509 @@
+//! # Proptest
+//!
+//! Property tests for the consensus rules with sharp edge cases (medians, penalty free zones, vote
+//! windows and difficulty cuts). Each property cross-checks our implementation against a straightforward
+//! reference implementation written directly from Monero's rules.
+//!
+//! The weight rules are also checked differentially against a line by line transcription of
+//! monerod's code (`epee::misc_utils::median` and `Blockchain`'s weight limit and long term weight
+//! functions), so a difference in rounding like `get_mid` shows up as a failing case instead of a
+//! chain split.
+//!
+//! The strategies used to generate the chains are public so they can be reused.
+//!
+use ::proptest::{collection::vec, prelude::*};
+
+use crate::hardforks::HardFork;
+
+/// A strategy for a hard-fork.
+pub fn arb_hard_fork() -> impl Strategy<Value = HardFork> {
+    prop::sample::select(HardFork::ALL.to_vec())
+}
+
+/// A strategy for block weights, weighted towards values around the penalty free zones where the
+/// interesting edge cases are.
+pub fn arb_block_weight() -> impl Strategy<Value = usize> {
+    prop_oneof![
+        0_usize..1_000,
+        19_000_usize..21_000,
+        59_000_usize..61_000,
+        299_000_usize..301_000,
+        0_usize..20_000_000,
+    ]
+}
+
+/// A strategy for a list of block weights between `min` and `max` long.
+pub fn arb_block_weights(min: usize, max: usize) -> impl Strategy<Value = Vec<usize>> {
+    vec(arb_block_weight(), min..max)
+}
+
+/// A strategy for a list of hard-fork votes between `min` and `max` long.
+pub fn arb_votes(min: usize, max: usize) -> impl Strategy<Value = Vec<HardFork>> {
+    vec(arb_hard_fork(), min..max)
+}
+
+/// A strategy for a list of (timestamp, difficulty) pairs between `min` and `max` long.
+///
+/// Timestamps are not always increasing, like on the real chain.
+pub fn arb_pow_info(min: usize, max: usize) -> impl Strategy<Value = Vec<(u64, u128)>> {
+    vec((0_u64..10_000_000, 1_u128..1_000_000_000), min..max)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::proptest::prelude::*;
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{
+        block::{
+            pow::difficulty::DifficultyCache,
+            weight::{
+                calculate_block_long_term_weight, calculate_effective_median_block_weight,
+                BlockWeightsCache, BucketedMedian, RollingMedian,
+            },
+        },
+        hardforks::HFVotes,
+        test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder},
+    };
+
+    /// The reference median, the mean of the two middle values is rounded down.
+    fn reference_median(values: &[usize]) -> usize {
+        let mut values = values.to_vec();
+        values.sort_unstable();
+
+        let mid = values.len() / 2;
+        if values.is_empty() {
+            0
+        } else if values.len().is_multiple_of(2) {
+            ((values[mid - 1] as u128 + values[mid] as u128) / 2) as usize
+        } else {
+            values[mid]
+        }
+    }
+
+    fn reference_penalty_free_zone(hf: HardFork) -> usize {
+        match hf as u8 {
+            1 => 20_000,
+            2..=4 => 60_000,
+            _ => 300_000,
+        }
+    }
+
+    fn reference_effective_median(
+        hf: HardFork,
+        short_term: &[usize],
+        long_term: &[usize],
+    ) -> usize {
+        let version = hf as u8;
+        if version < 10 {
+            return reference_median(short_term).max(reference_penalty_free_zone(hf));
+        }
+
+        let long_term_median = reference_median(long_term).max(300_000);
+        let short_term_median = reference_median(short_term);
+
+        let effective_median = if version < 15 {
+            short_term_median.max(300_000).min(50 * long_term_median)
+        } else {
+            short_term_median
+                .max(long_term_median)
+                .min(50 * long_term_median)
+        };
+
+        effective_median.max(reference_penalty_free_zone(hf))
+    }
+
+    fn reference_long_term_weight(hf: HardFork, block_weight: usize, long_term: &[usize]) -> usize {
+        let version = hf as u8;
+        if version < 10 {
+            return block_weight;
+        }
+
+        let long_term_median = reference_median(long_term).max(reference_penalty_free_zone(hf));
+
+        if version < 15 {
+            block_weight.min(long_term_median + long_term_median * 2 / 5)
+        } else {
+            block_weight
+                .max(long_term_median * 10 / 17)
+                .min(long_term_median + long_term_median * 7 / 10)
+        }
+    }
+
+    /// Monero's `next_difficulty` over the blocks `chain[..chain_height]`.
+    fn reference_next_difficulty(chain: &[(u64, u128)], hf: HardFork) -> u128 {
+        const WINDOW: usize = 720;
+        const CUT: usize = 60;
+        const LAG: usize = 15;
+
+        let start = chain.len().saturating_sub(WINDOW + LAG).max(1);
+        let window = &chain[start.min(chain.len())..];
+        let window = &window[..window.len().min(WINDOW)];
+
+        if window.len() <= 1 {
+            return 1;
+        }
+
+        let mut timestamps: Vec<u64> = window.iter().map(|(timestamp, _)| *timestamp).collect();
+        timestamps.sort_unstable();
+
+        let cumulative_difficulties: Vec<u128> = chain
+            .iter()
+            .scan(0, |cum, (_, diff)| {
+                *cum += diff;
+                Some(*cum)
+            })
+            .skip(start)
+            .take(window.len())
+            .collect();
+
+        let (cut_begin, cut_end) = if window.len() <= WINDOW - 2 * CUT {
+            (0, window.len())
+        } else {
+            let cut_begin = (window.len() - (WINDOW - 2 * CUT)).div_ceil(2);
+            (cut_begin, cut_begin + WINDOW - 2 * CUT)
+        };
+
+        let time_span = u128::from(timestamps[cut_end - 1] - timestamps[cut_begin]).max(1);
+        let total_work = cumulative_difficulties[cut_end - 1] - cumulative_difficulties[cut_begin];
+        let target = if hf == HardFork::V1 { 60 } else { 120 };
+
+        (total_work * target).div_ceil(time_span)
+    }
+
+    /// A transcription of monerod's weight code, kept as close to the C++ as Rust allows.
+    mod monerod {
+        use crate::hardforks::HardFork;
+
+        const CRYPTONOTE_BLOCK_GRANTED_FULL_REWARD_ZONE_V1: u64 = 20000;
+        const CRYPTONOTE_BLOCK_GRANTED_FULL_REWARD_ZONE_V2: u64 = 60000;
+        const CRYPTONOTE_BLOCK_GRANTED_FULL_REWARD_ZONE_V5: u64 = 300000;
+        const CRYPTONOTE_SHORT_TERM_BLOCK_WEIGHT_SURGE_FACTOR: u64 = 50;
+        const HF_VERSION_LONG_TERM_BLOCK_WEIGHT: u8 = 10;
+        const HF_VERSION_EFFECTIVE_SHORT_TERM_MEDIAN_IN_PENALTY: u8 = 15;
+        const HF_VERSION_2021_SCALING: u8 = 15;
+
+        /// `epee::misc_utils::get_mid`
+        fn get_mid(a: u64, b: u64) -> u64 {
+            (a / 2) + (b / 2) + ((a - 2 * (a / 2)) + (b - 2 * (b / 2))) / 2
+        }
+
+        /// `epee::misc_utils::median`
+        pub fn median(v: &[u64]) -> u64 {
+            let mut v = v.to_vec();
+            if v.is_empty() {
+                return 0;
+            }
+            if v.len() == 1 {
+                return v[0];
+            }
+
+            let n = v.len() / 2;
+            v.sort();
+
+            if !v.len().is_multiple_of(2) {
+                v[n]
+            } else {
+                get_mid(v[n - 1], v[n])
+            }
+        }
+
+        /// `cryptonote::get_min_block_weight`
+        fn get_min_block_weight(version: u8) -> u64 {
+            if version < 2 {
+                return CRYPTONOTE_BLOCK_GRANTED_FULL_REWARD_ZONE_V1;
+            }
+            if version < 5 {
+                return CRYPTONOTE_BLOCK_GRANTED_FULL_REWARD_ZONE_V2;
+            }
+            CRYPTONOTE_BLOCK_GRANTED_FULL_REWARD_ZONE_V5
+        }
+
+        /// `Blockchain::update_next_cumulative_weight_limit`, returns
+        /// `(m_current_block_cumul_weight_median, m_current_block_cumul_weight_limit)`.
+        ///
+        /// `weights` are the last 100 block weights and `long_term_weights` the long term weights
+        /// in the long term window.
+        pub fn update_next_cumulative_weight_limit(
+            hf: HardFork,
+            weights: &[u64],
+            long_term_weights: &[u64],
+        ) -> (u64, u64) {
+            let version = hf as u8;
+            let full_reward_zone = get_min_block_weight(version);
+
+            let mut current_block_cumul_weight_median;
+            if version < HF_VERSION_LONG_TERM_BLOCK_WEIGHT {
+                current_block_cumul_weight_median = median(weights);
+            } else {
+                let long_term_median = median(long_term_weights);
+                let long_term_effective_median_block_weight =
+                    CRYPTONOTE_BLOCK_GRANTED_FULL_REWARD_ZONE_V5.max(long_term_median);
+
+                let short_term_median = median(weights);
+                let effective_median_block_weight =
+                    if version >= HF_VERSION_EFFECTIVE_SHORT_TERM_MEDIAN_IN_PENALTY {
+                        long_term_effective_median_block_weight
+                            .max(short_term_median)
+                            .min(
+                                CRYPTONOTE_SHORT_TERM_BLOCK_WEIGHT_SURGE_FACTOR
+                                    * long_term_effective_median_block_weight,
+                            )
+                    } else {
+                        CRYPTONOTE_BLOCK_GRANTED_FULL_REWARD_ZONE_V5
+                            .max(short_term_median)
+                            .min(
+                                CRYPTONOTE_SHORT_TERM_BLOCK_WEIGHT_SURGE_FACTOR
+                                    * long_term_effective_median_block_weight,
+                            )
+                    };
+
+                current_block_cumul_weight_median = effective_median_block_weight;
+            }
+
+            if current_block_cumul_weight_median <= full_reward_zone {
+                current_block_cumul_weight_median = full_reward_zone;
+            }
+
+            (
+                current_block_cumul_weight_median,
+                current_block_cumul_weight_median * 2,
+            )
+        }
+
+        /// `Blockchain::get_next_long_term_block_weight`
+        pub fn get_next_long_term_block_weight(
+            hf: HardFork,
+            block_weight: u64,
+            long_term_weights: &[u64],
+        ) -> u64 {
+            let hf_version = hf as u8;
+            if hf_version < HF_VERSION_LONG_TERM_BLOCK_WEIGHT {
+                return block_weight;
+            }
+
+            let long_term_median = median(long_term_weights);
+            let long_term_effective_median_block_weight =
+                CRYPTONOTE_BLOCK_GRANTED_FULL_REWARD_ZONE_V5.max(long_term_median);
+
+            let short_term_constraint;
+            let mut long_term_block_weight = block_weight;
+            if hf_version >= HF_VERSION_2021_SCALING {
+                long_term_block_weight =
+                    block_weight.max(long_term_effective_median_block_weight * 10 / 17);
+                short_term_constraint = long_term_effective_median_block_weight
+                    + long_term_effective_median_block_weight * 7 / 10;
+            } else {
+                short_term_constraint = long_term_effective_median_block_weight
+                    + long_term_effective_median_block_weight * 2 / 5;
+            }
+
+            long_term_block_weight.min(short_term_constraint)
+        }
+    }
+
+    fn to_u64(values: &[usize]) -> Vec<u64> {
+        values.iter().map(|value| *value as u64).collect()
+    }
+
+    /// A strategy for a chain of (block weight, long term weight) pairs between `min` and `max` long.
+    fn arb_weight_chain(min: usize, max: usize) -> impl Strategy<Value = Vec<(usize, usize)>> {
+        vec((arb_block_weight(), arb_block_weight()), min..max)
+    }
+
+    /// A strategy for values with odd and even values next to each other and values near the top of
+    /// the range, where the rounding and overflow of the median's mean differ.
+    fn arb_median_values() -> impl Strategy<Value = Vec<usize>> {
+        vec(
+            prop_oneof![
+                0_usize..10,
+                arb_block_weight(),
+                (u64::MAX as usize - 10)..=u64::MAX as usize,
+            ],
+            1..300,
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn effective_median_matches_reference(
+            hf in arb_hard_fork(),
+            short_term in arb_block_weights(1, 100),
+            long_term in arb_block_weights(1, 500),
+        ) {
+            prop_assert_eq!(
+                calculate_effective_median_block_weight(&hf, reference_median(&short_term), reference_median(&long_term)),
+                reference_effective_median(hf, &short_term, &long_term)
+            );
+        }
+
+        #[test]
+        fn long_term_weight_matches_reference(
+            hf in arb_hard_fork(),
+            block_weight in arb_block_weight(),
+            long_term in arb_block_weights(1, 500),
+        ) {
+            prop_assert_eq!(
+                calculate_block_long_term_weight(&hf, block_weight, reference_median(&long_term)),
+                reference_long_term_weight(hf, block_weight, &long_term)
+            );
+        }
+
+        #[test]
+        fn bucketed_median_matches_reference(
+            weights in arb_block_weights(1, 2_000),
+            window in 1_usize..1_000,
+        ) {
+            let mut median = BucketedMedian::default();
+
+            for (i, weight) in weights.iter().enumerate() {
+                median.insert(*weight);
+                if i >= window {
+                    median.remove(weights[i - window]);
+                }
+
+                let window_weights = &weights[(i + 1).saturating_sub(window)..=i];
+                prop_assert_eq!(median.len(), window_weights.len());
+                prop_assert_eq!(median.median(), reference_median(window_weights));
+            }
+        }
+
+        #[test]
+        fn rolling_median_matches_reference(
+            weights in arb_block_weights(1, 1_000),
+            window in 1_usize..200,
+        ) {
+            let mut median = RollingMedian::default();
+
+            for (i, weight) in weights.iter().enumerate() {
+                median.insert(*weight);
+                if i >= window {
+                    median.remove(weights[i - window]);
+                }
+
+                let window_weights = &weights[(i + 1).saturating_sub(window)..=i];
+                prop_assert_eq!(median.len(), window_weights.len());
+                prop_assert_eq!(median.median(), reference_median(window_weights));
+            }
+        }
+
+        #[test]
+        fn weight_cache_window_matches_reference(
+            hf in arb_hard_fork(),
+            weights in arb_block_weights(2, 300),
+            split in 1_usize..300,
+        ) {
+            let split = split.min(weights.len() - 1).max(1);
+
+            let mut builder = DummyDatabaseBuilder::default();
+            for weight in &weights[..split] {
+                builder = builder.add_block(DummyBlockExtendedHeader::default().with_weight(*weight, *weight));
+            }
+            let mut database = builder.finish();
+
+            let mut cache = block_on(BlockWeightsCache::init_from_chain_height(split as u64, database.clone())).unwrap();
+
+            for (height, weight) in weights.iter().enumerate().skip(split) {
+                block_on(cache.new_block_added(height as u64, *weight, *weight, &mut database)).unwrap();
+                database.add_block(DummyBlockExtendedHeader::default().with_weight(*weight, *weight));
+            }
+
+            let short_term = &weights[weights.len().saturating_sub(100)..];
+
+            prop_assert_eq!(
+                cache.effective_median_block_weight(&hf),
+                reference_effective_median(hf, short_term, &weights)
+            );
+        }
+
+        #[test]
+        fn hf_votes_match_window(
+            votes in arb_votes(1, 2_000),
+            window in 1_usize..500,
+        ) {
+            let mut hf_votes = HFVotes::default();
+
+            for (i, vote) in votes.iter().enumerate() {
+                hf_votes.push_back(*vote);
+                if i >= window {
+                    prop_assert_eq!(hf_votes.pop_front(), Some(votes[i - window].into()));
+                }
+            }
+
+            let window_votes = &votes[votes.len().saturating_sub(window)..];
+            prop_assert_eq!(hf_votes.total_votes(), window_votes.len() as u64);
+
+            for hf in HardFork::ALL {
+                // A vote for a fork is also a vote for all the forks before it.
+                let expected = window_votes.iter().filter(|vote| **vote >= hf).count() as u64;
+                prop_assert_eq!(hf_votes.votes_for_hf(&hf), expected);
+            }
+        }
+
+        #[test]
+        fn difficulty_matches_reference(
+            hf in arb_hard_fork(),
+            chain in arb_pow_info(2, 1_000),
+        ) {
+            let mut builder = DummyDatabaseBuilder::default();
+            for (timestamp, difficulty) in &chain {
+                builder = builder.add_block(DummyBlockExtendedHeader::default().with_pow_info(*timestamp, *difficulty));
+            }
+            let database = builder.finish();
+
+            let cache = block_on(DifficultyCache::init_from_chain_height(chain.len() as u64, database)).unwrap();
+
+            prop_assert_eq!(cache.next_difficulty(&hf), reference_next_difficulty(&chain, hf));
+        }
+
+        #[test]
+        fn rolling_median_matches_monerod(values in arb_median_values()) {
+            let median: RollingMedian = values.iter().copied().collect();
+            prop_assert_eq!(median.median() as u64, monerod::median(&to_u64(&values)));
+        }
+
+        #[test]
+        fn weight_limit_matches_monerod(
+            hf in arb_hard_fork(),
+            chain in arb_weight_chain(1, 600),
+        ) {
+            let mut builder = DummyDatabaseBuilder::default();
+            for (weight, long_term_weight) in &chain {
+                builder = builder.add_block(DummyBlockExtendedHeader::default().with_weight(*weight, *long_term_weight));
+            }
+            let database = builder.finish();
+
+            let cache = block_on(BlockWeightsCache::init_from_chain_height(chain.len() as u64, database)).unwrap();
+
+            let weights: Vec<u64> = chain[chain.len().saturating_sub(100)..].iter().map(|(weight, _)| *weight as u64).collect();
+            let long_term_weights: Vec<u64> = chain.iter().map(|(_, long_term_weight)| *long_term_weight as u64).collect();
+            let (median, limit) = monerod::update_next_cumulative_weight_limit(hf, &weights, &long_term_weights);
+
+            prop_assert_eq!(cache.effective_median_block_weight(&hf) as u64, median);
+            prop_assert_eq!(cache.next_block_weight_limit(&hf) as u64, limit);
+        }
+
+        #[test]
+        fn long_term_weight_matches_monerod(
+            hf in arb_hard_fork(),
+            block_weight in arb_block_weight(),
+            chain in arb_weight_chain(1, 600),
+        ) {
+            let mut builder = DummyDatabaseBuilder::default();
+            for (weight, long_term_weight) in &chain {
+                builder = builder.add_block(DummyBlockExtendedHeader::default().with_weight(*weight, *long_term_weight));
+            }
+            let database = builder.finish();
+
+            let cache = block_on(BlockWeightsCache::init_from_chain_height(chain.len() as u64, database)).unwrap();
+
+            let long_term_weights: Vec<u64> = chain.iter().map(|(_, long_term_weight)| *long_term_weight as u64).collect();
+
+            prop_assert_eq!(
+                cache.next_block_long_term_weight(&hf, block_weight) as u64,
+                monerod::get_next_long_term_block_weight(hf, block_weight as u64, &long_term_weights)
+            );
+        }
+    }
+}
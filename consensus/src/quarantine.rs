@@ -0,0 +1,481 @@
+//! # Quarantine
+//!
+//! This module contains [`QuarantineStore`], a directory of blocks that failed verification with an
+//! error that could be a bug on our side, see [`ConsensusError::may_be_local_bug`]. A block mined
+//! on a rule edge case we get wrong would otherwise be thrown away with the peer that sent it, the
+//! quarantine keeps the block, its transactions and the error so the failure can be looked at and
+//! the block verified again after an upgrade.
+//!
+//! Each block is stored in its own directory, named after the block's hash:
+//! - `block.bin`, the block's blob.
+//! - `tx_<index>.bin`, the blobs of its transactions, in the order of the block.
+//! - `report.txt`, the height, the error, when it was quarantined and the version that rejected it.
+//!
+//! The store holds at most [`DEFAULT_MAX_QUARANTINED_BLOCKS`] blocks, so peers sending invalid
+//! blocks can't fill the disk. Blocks are written to a temporary directory first, a crash leaves no
+//! partial entries.
+//!
+//! On startup the node calls [`QuarantineStore::reverify`], blocks quarantined by an older version
+//! are verified again and added to the chain if the upgrade fixed the bug they hit, so a node heals
+//! without a resync.
+//!
+use std::fs;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::Instrument;
+
+use crate::{
+    spans::{block_span, BLOCK_TARGET},
+    ConsensusError,
+};
+
+/// The default most blocks kept in quarantine.
+pub const DEFAULT_MAX_QUARANTINED_BLOCKS: usize = 100;
+
+const BLOCK_FILE: &str = "block.bin";
+const REPORT_FILE: &str = "report.txt";
+/// The suffix of an entry still being written.
+const TEMP_SUFFIX: &str = ".tmp";
+
+#[derive(Debug, thiserror::Error)]
+pub enum QuarantineError {
+    #[error("The quarantine already holds {0} blocks")]
+    Full(usize),
+    #[error("The quarantine report is invalid: {0}")]
+    InvalidReport(String),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A block kept in quarantine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantinedBlock {
+    pub block_hash: [u8; 32],
+    pub height: u64,
+    pub block_blob: Vec<u8>,
+    /// The blobs of the block's transactions, not including the miner transaction.
+    pub tx_blobs: Vec<Vec<u8>>,
+    /// The error the block failed verification with.
+    pub error: String,
+    /// The UNIX timestamp of when the block was quarantined.
+    pub quarantined_at: u64,
+    /// The version of this crate that rejected the block.
+    pub version: String,
+}
+
+impl QuarantinedBlock {
+    /// Returns a block rejected now with this error, by this version.
+    pub fn new(
+        block_hash: [u8; 32],
+        height: u64,
+        block_blob: Vec<u8>,
+        tx_blobs: Vec<Vec<u8>>,
+        error: &ConsensusError,
+    ) -> QuarantinedBlock {
+        QuarantinedBlock {
+            block_hash,
+            height,
+            block_blob,
+            tx_blobs,
+            // The report has one field per line.
+            error: error.to_string().replace('\n', " "),
+            quarantined_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    fn report(&self) -> String {
+        format!(
+            "height: {}\nerror: {}\nquarantined_at: {}\nversion: {}\n",
+            self.height, self.error, self.quarantined_at, self.version
+        )
+    }
+}
+
+/// What [`QuarantineStore::reverify`] did with the quarantined blocks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReverifyReport {
+    /// Blocks valid with this version, they were added to the chain and removed from quarantine.
+    pub accepted: Vec<[u8; 32]>,
+    /// Blocks still failing with an error that may be a bug, their report is updated.
+    pub still_quarantined: Vec<[u8; 32]>,
+    /// Blocks this version finds invalid, they were removed from quarantine.
+    pub rejected: Vec<[u8; 32]>,
+    /// Blocks that failed with an internal error, they are verified again on the next start.
+    pub retry: Vec<[u8; 32]>,
+    /// The amount of blocks quarantined by this version, which weren't verified again.
+    pub skipped: usize,
+}
+
+/// A directory of quarantined blocks.
+#[derive(Debug, Clone)]
+pub struct QuarantineStore {
+    dir: PathBuf,
+    max_blocks: usize,
+}
+
+impl QuarantineStore {
+    pub fn new(dir: impl Into<PathBuf>) -> QuarantineStore {
+        QuarantineStore {
+            dir: dir.into(),
+            max_blocks: DEFAULT_MAX_QUARANTINED_BLOCKS,
+        }
+    }
+
+    pub fn with_max_blocks(mut self, max_blocks: usize) -> QuarantineStore {
+        self.max_blocks = max_blocks;
+        self
+    }
+
+    /// Stores the block if `error` could be a bug on our side, returns the directory the block was
+    /// stored in or [`None`] if the block is just invalid.
+    pub fn quarantine(
+        &self,
+        error: &ConsensusError,
+        block_hash: [u8; 32],
+        height: u64,
+        block_blob: Vec<u8>,
+        tx_blobs: Vec<Vec<u8>>,
+    ) -> Result<Option<PathBuf>, QuarantineError> {
+        if !error.may_be_local_bug() {
+            return Ok(None);
+        }
+
+        let block = QuarantinedBlock::new(block_hash, height, block_blob, tx_blobs, error);
+        let path = self.store(&block)?;
+        tracing::warn!(
+            target: BLOCK_TARGET,
+            height,
+            hash = %hex::encode(block_hash),
+            "Block failed with an error that may be a bug, it was quarantined in {}: {}",
+            path.display(),
+            block.error
+        );
+        Ok(Some(path))
+    }
+
+    /// Stores the block, replacing it if it is already quarantined.
+    pub fn store(&self, block: &QuarantinedBlock) -> Result<PathBuf, QuarantineError> {
+        let path = self.entry_path(&block.block_hash);
+
+        let stored = self.list()?;
+        if stored.len() >= self.max_blocks && !stored.contains(&block.block_hash) {
+            return Err(QuarantineError::Full(stored.len()));
+        }
+
+        let temp_path = self
+            .dir
+            .join(format!("{}{TEMP_SUFFIX}", hex::encode(block.block_hash)));
+        remove_if_exists(&temp_path)?;
+        fs::create_dir_all(&temp_path)?;
+
+        fs::write(temp_path.join(BLOCK_FILE), &block.block_blob)?;
+        for (i, tx_blob) in block.tx_blobs.iter().enumerate() {
+            fs::write(temp_path.join(tx_file(i)), tx_blob)?;
+        }
+        fs::write(temp_path.join(REPORT_FILE), block.report())?;
+
+        remove_if_exists(&path)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(path)
+    }
+
+    /// Returns the hashes of the quarantined blocks.
+    pub fn list(&self) -> Result<Vec<[u8; 32]>, QuarantineError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut hashes = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            // Entries still being written have a suffix and aren't valid hex.
+            let mut hash = [0; 32];
+            if hex::decode_to_slice(name.to_string_lossy().as_bytes(), &mut hash).is_ok() {
+                hashes.push(hash);
+            }
+        }
+        hashes.sort_unstable();
+        Ok(hashes)
+    }
+
+    /// Reads a quarantined block, to verify it again.
+    pub fn load(&self, block_hash: &[u8; 32]) -> Result<QuarantinedBlock, QuarantineError> {
+        let path = self.entry_path(block_hash);
+
+        let block_blob = fs::read(path.join(BLOCK_FILE))?;
+        let mut tx_blobs = Vec::new();
+        loop {
+            match fs::read(path.join(tx_file(tx_blobs.len()))) {
+                Ok(tx_blob) => tx_blobs.push(tx_blob),
+                Err(e) if e.kind() == ErrorKind::NotFound => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let report = fs::read_to_string(path.join(REPORT_FILE))?;
+        let field = |name: &str| {
+            report
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+                .ok_or_else(|| QuarantineError::InvalidReport(format!("{name} is missing")))
+        };
+        let number = |name: &str| {
+            field(name)?
+                .parse()
+                .map_err(|_| QuarantineError::InvalidReport(format!("{name} is not a number")))
+        };
+
+        Ok(QuarantinedBlock {
+            block_hash: *block_hash,
+            height: number("height")?,
+            block_blob,
+            tx_blobs,
+            error: field("error")?.to_string(),
+            quarantined_at: number("quarantined_at")?,
+            version: field("version")?.to_string(),
+        })
+    }
+
+    /// Verifies the blocks quarantined by an older version again, lowest height first so a block's
+    /// parent is added before it.
+    ///
+    /// `verify_and_add` should verify the block with this version's rules and add it to the chain
+    /// if it is valid. Blocks that still fail with an error that may be a bug are kept, with the
+    /// report updated to this version so they aren't verified again until the next upgrade.
+    pub async fn reverify<F, Fut>(
+        &self,
+        mut verify_and_add: F,
+    ) -> Result<ReverifyReport, QuarantineError>
+    where
+        F: FnMut(QuarantinedBlock) -> Fut,
+        Fut: Future<Output = Result<(), ConsensusError>>,
+    {
+        let mut report = ReverifyReport::default();
+
+        let mut blocks = Vec::new();
+        for block_hash in self.list()? {
+            let block = self.load(&block_hash)?;
+            if block.version == env!("CARGO_PKG_VERSION") {
+                report.skipped += 1;
+            } else {
+                blocks.push(block);
+            }
+        }
+        blocks.sort_by_key(|block| block.height);
+
+        for block in blocks {
+            let QuarantinedBlock {
+                block_hash, height, ..
+            } = block;
+
+            let span = block_span(height, &block_hash);
+            let result = verify_and_add(block.clone()).instrument(span.clone()).await;
+            // Nothing below awaits, so the span can be entered for the events.
+            let _guard = span.enter();
+
+            match result {
+                Ok(()) => {
+                    tracing::info!(
+                        target: BLOCK_TARGET,
+                        "Quarantined block is valid with this version, it was added to the chain"
+                    );
+                    self.remove(&block_hash)?;
+                    report.accepted.push(block_hash);
+                }
+                Err(e) if e.may_be_local_bug() => {
+                    tracing::warn!(target: BLOCK_TARGET, "Quarantined block still fails: {}", e);
+                    self.store(&QuarantinedBlock::new(
+                        block_hash,
+                        height,
+                        block.block_blob,
+                        block.tx_blobs,
+                        &e,
+                    ))?;
+                    report.still_quarantined.push(block_hash);
+                }
+                Err(e) if e.is_peer_fault() => {
+                    tracing::info!(target: BLOCK_TARGET, "Quarantined block is invalid: {}", e);
+                    self.remove(&block_hash)?;
+                    report.rejected.push(block_hash);
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        target: BLOCK_TARGET,
+                        "Could not verify quarantined block again: {}",
+                        e
+                    );
+                    report.retry.push(block_hash);
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Removes a block from quarantine, once it has been verified again or looked at.
+    pub fn remove(&self, block_hash: &[u8; 32]) -> Result<(), QuarantineError> {
+        remove_if_exists(&self.entry_path(block_hash))?;
+        Ok(())
+    }
+
+    fn entry_path(&self, block_hash: &[u8; 32]) -> PathBuf {
+        self.dir.join(hex::encode(block_hash))
+    }
+}
+
+fn tx_file(index: usize) -> String {
+    format!("tx_{index}.bin")
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), std::io::Error> {
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlockError, HardForkError, TransactionError};
+
+    /// Returns a store in an empty temporary directory.
+    fn store(name: &str) -> QuarantineStore {
+        let dir =
+            std::env::temp_dir().join(format!("cuprate-quarantine-{name}-{}", std::process::id()));
+        remove_if_exists(&dir).unwrap();
+        QuarantineStore::new(dir)
+    }
+
+    fn quarantine(
+        store: &QuarantineStore,
+        error: ConsensusError,
+        hash: u8,
+    ) -> Result<Option<PathBuf>, QuarantineError> {
+        store.quarantine(
+            &error,
+            [hash; 32],
+            10,
+            vec![hash],
+            vec![vec![1], vec![2, 3]],
+        )
+    }
+
+    #[test]
+    fn blocks_round_trip() {
+        let store = store("round-trip");
+
+        let path = quarantine(&store, HardForkError::UnknownVersion(200).into(), 1)
+            .unwrap()
+            .unwrap();
+        assert!(path.ends_with(hex::encode([1; 32])));
+        assert_eq!(store.list().unwrap(), vec![[1; 32]]);
+
+        let block = store.load(&[1; 32]).unwrap();
+        assert_eq!(block.height, 10);
+        assert_eq!(block.block_blob, vec![1]);
+        assert_eq!(block.tx_blobs, vec![vec![1], vec![2, 3]]);
+        assert_eq!(
+            block.error,
+            "Invalid hard fork: Version 200 is not a known hard fork"
+        );
+        assert_eq!(block.version, env!("CARGO_PKG_VERSION"));
+
+        store.remove(&[1; 32]).unwrap();
+        assert!(store.list().unwrap().is_empty());
+        remove_if_exists(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn only_possible_bugs_are_quarantined() {
+        let store = store("possible-bugs").with_max_blocks(2);
+
+        // Checkpoints are trusted and a low fee is only our policy.
+        let checkpoint = BlockError::CheckpointMismatch {
+            height: 10,
+            expected: [1; 32],
+            got: [2; 32],
+        };
+        assert!(quarantine(&store, checkpoint.into(), 1).unwrap().is_none());
+        let fee = TransactionError::FeeTooLow { fee: 1, minimum: 2 };
+        assert!(quarantine(&store, fee.into(), 2).unwrap().is_none());
+
+        let signature = || TransactionError::InvalidRingSignature(0).into();
+        assert!(quarantine(&store, signature(), 3).unwrap().is_some());
+        assert!(quarantine(&store, signature(), 4).unwrap().is_some());
+        assert!(matches!(
+            quarantine(&store, signature(), 5),
+            Err(QuarantineError::Full(2))
+        ));
+        // A block already in quarantine can still be replaced.
+        assert!(quarantine(&store, signature(), 4).unwrap().is_some());
+
+        assert_eq!(store.list().unwrap(), vec![[3; 32], [4; 32]]);
+        remove_if_exists(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn blocks_are_verified_again_after_an_upgrade() {
+        let store = store("reverify");
+
+        // Blocks 1-4 were quarantined by an older version, at decreasing heights.
+        for hash in 1..=4 {
+            let mut block = QuarantinedBlock::new(
+                [hash; 32],
+                100 - u64::from(hash),
+                vec![hash],
+                vec![],
+                &HardForkError::UnknownVersion(200).into(),
+            );
+            block.version = "0.0.1".to_string();
+            store.store(&block).unwrap();
+        }
+        quarantine(&store, HardForkError::UnknownVersion(200).into(), 5).unwrap();
+
+        let mut heights = Vec::new();
+        let report = futures::executor::block_on(store.reverify(|block| {
+            heights.push(block.height);
+            futures::future::ready(match block.block_hash[0] {
+                1 => Ok(()),
+                2 => Err(HardForkError::UnknownVersion(201).into()),
+                3 => Err(BlockError::CheckpointMismatch {
+                    height: 97,
+                    expected: [1; 32],
+                    got: [3; 32],
+                }
+                .into()),
+                _ => Err(tower::BoxError::from("database closed").into()),
+            })
+        }))
+        .unwrap();
+
+        assert_eq!(heights, vec![96, 97, 98, 99]);
+        assert_eq!(
+            report,
+            ReverifyReport {
+                accepted: vec![[1; 32]],
+                still_quarantined: vec![[2; 32]],
+                rejected: vec![[3; 32]],
+                retry: vec![[4; 32]],
+                skipped: 1,
+            }
+        );
+
+        assert_eq!(store.list().unwrap(), vec![[2; 32], [4; 32], [5; 32]]);
+        let block = store.load(&[2; 32]).unwrap();
+        assert_eq!(block.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            block.error,
+            "Invalid hard fork: Version 201 is not a known hard fork"
+        );
+        remove_if_exists(&store.dir).unwrap();
+    }
+}
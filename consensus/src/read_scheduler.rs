@@ -0,0 +1,323 @@
+//! # Read Scheduler
+//!
+//! This module contains [`ReadScheduler`], which limits the amount of database reads running at once
+//! and decides which waiting read runs next. Without it a public RPC server flooded with bulk reads,
+//! like `get_blocks.bin` or `get_outs.bin`, can take every database reader and stall block
+//! verification at the tip.
+//!
+//! Each user of the database is given its own [`Scheduled`] database with a [`ReadPriority`]:
+//! - [`ReadPriority::Critical`] for block verification and serving sync to peers, these reads always
+//!   queue and are started before any waiting bulk read.
+//! - [`ReadPriority::Bulk`] for the RPC server, once [`ReadSchedulerConfig::max_queued_bulk_reads`]
+//!   bulk reads are waiting new ones are shed with [`Overloaded`].
+//!
+//! Reads that have started are not interrupted. Writes, [`DatabaseRequest::WriteBlock`],
+//! [`DatabaseRequest::WriteBlocks`] and [`DatabaseRequest::PopBlock`], are not scheduled.
+//!
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::{channel::oneshot, FutureExt};
+use tower::ServiceExt;
+
+use crate::DatabaseRequest;
+
+/// The default amount of reads that can run at once.
+pub const DEFAULT_MAX_CONCURRENT_READS: usize = 16;
+/// The default amount of bulk reads that can wait for a reader.
+pub const DEFAULT_MAX_QUEUED_BULK_READS: usize = 256;
+
+/// The priority of the reads of a [`Scheduled`] database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadPriority {
+    /// Reads needed to follow the chain, started before any [`ReadPriority::Bulk`] read.
+    Critical,
+    /// Reads for the RPC server, shed when too many are waiting.
+    Bulk,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("The database is overloaded, too many bulk reads are waiting")]
+pub struct Overloaded;
+
+/// Configuration for the [`ReadScheduler`].
+#[derive(Debug, Clone)]
+pub struct ReadSchedulerConfig {
+    /// The maximum amount of reads running at once, this should not be more than the database's
+    /// amount of readers.
+    pub max_concurrent_reads: usize,
+    /// The maximum amount of bulk reads waiting to start, more are shed.
+    pub max_queued_bulk_reads: usize,
+}
+
+impl Default for ReadSchedulerConfig {
+    fn default() -> Self {
+        ReadSchedulerConfig {
+            max_concurrent_reads: DEFAULT_MAX_CONCURRENT_READS,
+            max_queued_bulk_reads: DEFAULT_MAX_QUEUED_BULK_READS,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SchedulerState {
+    running: usize,
+    critical: VecDeque<oneshot::Sender<ReadPermit>>,
+    bulk: VecDeque<oneshot::Sender<ReadPermit>>,
+}
+
+/// Schedules the reads of every [`Scheduled`] database made from it, clones of this share the same
+/// readers.
+#[derive(Debug, Clone)]
+pub struct ReadScheduler {
+    config: Arc<ReadSchedulerConfig>,
+    state: Arc<Mutex<SchedulerState>>,
+}
+
+/// Either a permit to read now or the receiver of a permit once a reader is free.
+enum Acquire {
+    Ready(ReadPermit),
+    Queued(oneshot::Receiver<ReadPermit>),
+}
+
+impl ReadScheduler {
+    /// # Panics
+    ///
+    /// Panics if `max_concurrent_reads` is 0.
+    pub fn new(config: ReadSchedulerConfig) -> ReadScheduler {
+        assert_ne!(config.max_concurrent_reads, 0, "Reads must be able to run");
+
+        ReadScheduler {
+            config: Arc::new(config),
+            state: Arc::default(),
+        }
+    }
+
+    /// Returns a database that schedules its reads with this priority.
+    pub fn schedule<D>(&self, inner: D, priority: ReadPriority) -> Scheduled<D> {
+        Scheduled {
+            inner,
+            priority,
+            scheduler: self.clone(),
+        }
+    }
+
+    /// Returns the amount of reads running.
+    pub fn running_reads(&self) -> usize {
+        self.state.lock().unwrap().running
+    }
+
+    /// Returns the amount of reads waiting to start, critical then bulk.
+    pub fn queued_reads(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.critical.len(), state.bulk.len())
+    }
+
+    fn acquire(&self, priority: ReadPriority) -> Result<Acquire, Overloaded> {
+        let mut state = self.state.lock().unwrap();
+
+        // Readers are only free when nothing is waiting, so a bulk read can't jump a critical one.
+        if state.running < self.config.max_concurrent_reads {
+            state.running += 1;
+            return Ok(Acquire::Ready(ReadPermit {
+                scheduler: Some(self.clone()),
+            }));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        match priority {
+            ReadPriority::Critical => state.critical.push_back(tx),
+            ReadPriority::Bulk => {
+                if state.bulk.len() >= self.config.max_queued_bulk_reads {
+                    return Err(Overloaded);
+                }
+                state.bulk.push_back(tx)
+            }
+        }
+
+        Ok(Acquire::Queued(rx))
+    }
+
+    /// Gives a finished read's reader to the next waiting read, critical reads first.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        while let Some(tx) = state
+            .critical
+            .pop_front()
+            .or_else(|| state.bulk.pop_front())
+        {
+            match tx.send(ReadPermit {
+                scheduler: Some(self.clone()),
+            }) {
+                Ok(()) => return,
+                // The waiting read was dropped, the reader is still ours to give away.
+                Err(mut permit) => permit.scheduler = None,
+            }
+        }
+
+        state.running -= 1;
+    }
+}
+
+/// A reader held by a running read, it is given to the next read when dropped.
+#[derive(Debug)]
+struct ReadPermit {
+    /// [`None`] if the permit was never given to a read.
+    scheduler: Option<ReadScheduler>,
+}
+
+impl Drop for ReadPermit {
+    fn drop(&mut self) {
+        if let Some(scheduler) = self.scheduler.take() {
+            scheduler.release();
+        }
+    }
+}
+
+/// A database whose reads wait for a reader from a [`ReadScheduler`].
+#[derive(Debug, Clone)]
+pub struct Scheduled<D> {
+    inner: D,
+    priority: ReadPriority,
+    scheduler: ReadScheduler,
+}
+
+impl<D> tower::Service<DatabaseRequest> for Scheduled<D>
+where
+    D: tower::Service<DatabaseRequest, Error = tower::BoxError> + Clone + Send + 'static,
+    D::Future: Send + 'static,
+    D::Response: Send + 'static,
+{
+    type Response = D::Response;
+    type Error = tower::BoxError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Like `Retry` we wait for the inner service in the returned future, after the read has a
+        // reader.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: DatabaseRequest) -> Self::Future {
+        let inner = self.inner.clone();
+
+        if matches!(
+            req,
+            DatabaseRequest::WriteBlock(_)
+                | DatabaseRequest::WriteBlocks(_)
+                | DatabaseRequest::PopBlock
+        ) {
+            return inner.oneshot(req).boxed();
+        }
+
+        let acquire = self.scheduler.acquire(self.priority);
+
+        async move {
+            let _permit = match acquire? {
+                Acquire::Ready(permit) => permit,
+                Acquire::Queued(rx) => rx
+                    .await
+                    .expect("Queued reads are given a reader before the scheduler is dropped"),
+            };
+
+            inner.oneshot(req).await
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder};
+
+    fn scheduler(max_concurrent_reads: usize, max_queued_bulk_reads: usize) -> ReadScheduler {
+        ReadScheduler::new(ReadSchedulerConfig {
+            max_concurrent_reads,
+            max_queued_bulk_reads,
+        })
+    }
+
+    fn permit(acquire: Acquire) -> Option<ReadPermit> {
+        match acquire {
+            Acquire::Ready(permit) => Some(permit),
+            Acquire::Queued(_) => None,
+        }
+    }
+
+    #[test]
+    fn critical_reads_start_first() {
+        let scheduler = scheduler(1, 1);
+
+        let running = permit(scheduler.acquire(ReadPriority::Bulk).unwrap()).unwrap();
+        let Acquire::Queued(mut bulk) = scheduler.acquire(ReadPriority::Bulk).unwrap() else {
+            panic!("The only reader is taken");
+        };
+        let Acquire::Queued(mut critical) = scheduler.acquire(ReadPriority::Critical).unwrap()
+        else {
+            panic!("The only reader is taken");
+        };
+        assert_eq!(scheduler.queued_reads(), (1, 1));
+
+        // The bulk queue is full.
+        assert!(scheduler.acquire(ReadPriority::Bulk).is_err());
+
+        drop(running);
+        assert!(bulk.try_recv().unwrap().is_none());
+        let running = critical.try_recv().unwrap().unwrap();
+        assert_eq!(scheduler.running_reads(), 1);
+
+        drop(running);
+        let running = bulk.try_recv().unwrap().unwrap();
+        drop(running);
+        assert_eq!(scheduler.running_reads(), 0);
+        assert_eq!(scheduler.queued_reads(), (0, 0));
+    }
+
+    #[test]
+    fn dropped_reads_give_up_their_place() {
+        let scheduler = scheduler(1, 1);
+
+        let running = permit(scheduler.acquire(ReadPriority::Bulk).unwrap()).unwrap();
+        let critical = scheduler.acquire(ReadPriority::Critical).unwrap();
+        drop(critical);
+
+        drop(running);
+        assert_eq!(scheduler.running_reads(), 0);
+        assert!(permit(scheduler.acquire(ReadPriority::Bulk).unwrap()).is_some());
+    }
+
+    #[test]
+    fn scheduled_database() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(5, DummyBlockExtendedHeader::default())
+            .finish();
+        let scheduler = scheduler(1, 0);
+        let rpc = scheduler.schedule(database.clone(), ReadPriority::Bulk);
+        let verifier = scheduler.schedule(database, ReadPriority::Critical);
+
+        let chain_height = block_on(rpc.clone().oneshot(DatabaseRequest::ChainHeight))
+            .unwrap()
+            .into_chain_height()
+            .unwrap();
+        assert_eq!(chain_height, 5);
+        assert_eq!(scheduler.running_reads(), 0);
+
+        // With every reader taken bulk reads are shed, critical reads wait.
+        let running = permit(scheduler.acquire(ReadPriority::Critical).unwrap()).unwrap();
+        let err = block_on(rpc.oneshot(DatabaseRequest::ChainHeight)).unwrap_err();
+        assert!(err.is::<Overloaded>());
+
+        let mut critical = verifier.oneshot(DatabaseRequest::ChainHeight);
+        assert!((&mut critical).now_or_never().is_none());
+        drop(running);
+        assert!(block_on(critical).is_ok());
+    }
+}
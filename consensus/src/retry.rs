@@ -0,0 +1,509 @@
+//! # Retry
+//!
+//! This module contains [`Retry`], a tower middleware that retries failed requests with an
+//! exponential backoff and stops sending requests to an inner service that keeps failing (circuit
+//! breaking).
+//!
+//! Only requests that are safe to send again are retried, see [`RetryableRequest`].
+//!
+//! The circuit breaker is closed until the inner service fails [`RetryConfig::failure_threshold`]
+//! times in a row, then it opens and [`Retry`] is not ready for [`RetryConfig::cool_down`]. After the
+//! cool down the breaker is half-open: requests are sent again, the first success closes the
+//! breaker and the first failure opens it again.
+//!
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use futures::FutureExt;
+use rand::Rng;
+use tokio::time::{Instant, Sleep};
+use tower::ServiceExt;
+
+use crate::DatabaseRequest;
+
+/// A request that knows if it is safe to send again after a failure.
+pub trait RetryableRequest {
+    /// Returns true if sending this request more than once has the same effect as sending it once.
+    fn is_idempotent(&self) -> bool;
+}
+
+impl RetryableRequest for DatabaseRequest {
+    fn is_idempotent(&self) -> bool {
+        match self {
+            // A write that failed may still have been committed, sending it again could add the
+            // block twice or pop two blocks.
+            DatabaseRequest::WriteBlock(_)
+            | DatabaseRequest::WriteBlocks(_)
+            | DatabaseRequest::PopBlock => false,
+            DatabaseRequest::BlockHFInfo(_)
+            | DatabaseRequest::BlockPOWInfo(_)
+            | DatabaseRequest::BlockWeights(_)
+            | DatabaseRequest::BlockHash(_)
+            | DatabaseRequest::GeneratedCoins(_)
+            | DatabaseRequest::CumulativeDifficulty(_)
+            | DatabaseRequest::BlockHfInfoInRange(_)
+            | DatabaseRequest::BlockWeightsInRange(_)
+            | DatabaseRequest::BlockPOWInfoInRange(_)
+            | DatabaseRequest::ChainHeight
+            | DatabaseRequest::KeyImagesSpent(_)
+            | DatabaseRequest::Block(_)
+            | DatabaseRequest::BlockHeight(_)
+            | DatabaseRequest::BlockBlobsInRange { .. }
+            | DatabaseRequest::TxOutputIndices(_)
+            | DatabaseRequest::Outputs(_)
+            | DatabaseRequest::OutputTimeLocks(_)
+            | DatabaseRequest::NumOutputsInRange { .. }
+            | DatabaseRequest::OutputDistribution { .. }
+            | DatabaseRequest::NumberOutputsWithAmount(_)
+            | DatabaseRequest::BlockBatchInRange(_)
+            | DatabaseRequest::Transactions(_) => true,
+        }
+    }
+}
+
+/// Configuration for the [`Retry`] middleware.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// The maximum amount of times a request will be retried.
+    pub max_retries: u32,
+    /// The delay before the first retry, this doubles after each failed attempt.
+    pub base_delay: Duration,
+    /// The maximum delay between attempts.
+    pub max_delay: Duration,
+    /// Whether to randomise the delays, this stops lots of requests retrying at the same time.
+    pub jitter: bool,
+    /// The amount of consecutive failures before the circuit breaker opens.
+    pub failure_threshold: u32,
+    /// The amount of time the circuit breaker stays open for.
+    pub cool_down: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+            failure_threshold: 20,
+            cool_down: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Returns the delay to wait before retrying after the given amount of failed attempts.
+    fn delay(&self, failed_attempts: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(failed_attempts.saturating_sub(1)))
+            .min(self.max_delay);
+
+        if self.jitter {
+            // "Equal jitter": wait somewhere between half and all of the delay.
+            let half = delay / 2;
+            half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+        } else {
+            delay
+        }
+    }
+}
+
+/// The state of a [`CircuitBreaker`], see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum BreakerState {
+    /// Requests are sent to the inner service.
+    #[default]
+    Closed,
+    /// No requests are sent until this instant.
+    Open(Instant),
+    /// The cool down has passed, the next failure opens the breaker again.
+    HalfOpen,
+}
+
+/// The state of the circuit breaker, shared between clones of [`Retry`].
+#[derive(Debug, Default)]
+struct CircuitBreaker {
+    consecutive_failures: u32,
+    state: BreakerState,
+}
+
+impl CircuitBreaker {
+    /// Returns the instant the breaker stops being open, [`None`] if it isn't open.
+    fn open_until(&mut self) -> Option<Instant> {
+        match self.state {
+            BreakerState::Open(open_until) if Instant::now() < open_until => Some(open_until),
+            BreakerState::Open(_) => {
+                self.state = BreakerState::HalfOpen;
+                None
+            }
+            BreakerState::Closed | BreakerState::HalfOpen => None,
+        }
+    }
+
+    fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.state = BreakerState::Closed;
+    }
+
+    fn record_failure(&mut self, config: &RetryConfig) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+
+        if self.state == BreakerState::HalfOpen
+            || self.consecutive_failures >= config.failure_threshold
+        {
+            tracing::warn!(
+                "Service failed {} times in a row, stopping requests for {:?}",
+                self.consecutive_failures,
+                config.cool_down
+            );
+            self.state = BreakerState::Open(Instant::now() + config.cool_down);
+        }
+    }
+}
+
+/// A [`tower::Layer`] that wraps services in [`Retry`].
+#[derive(Debug, Clone)]
+pub struct RetryLayer {
+    config: RetryConfig,
+}
+
+impl RetryLayer {
+    pub fn new(config: RetryConfig) -> RetryLayer {
+        RetryLayer { config }
+    }
+}
+
+impl<S> tower::Layer<S> for RetryLayer {
+    type Service = Retry<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Retry::new(inner, self.config.clone())
+    }
+}
+
+/// A middleware that retries idempotent requests with an exponential backoff.
+///
+/// Clones of this service share the same circuit breaker. While the breaker is open the service is
+/// not ready, it wakes the task polling it when the breaker becomes half-open.
+#[derive(Debug)]
+pub struct Retry<S> {
+    inner: S,
+    config: Arc<RetryConfig>,
+    breaker: Arc<Mutex<CircuitBreaker>>,
+    /// The timer waking us when the breaker becomes half-open.
+    cool_down: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S: Clone> Clone for Retry<S> {
+    fn clone(&self) -> Self {
+        Retry {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+            breaker: self.breaker.clone(),
+            cool_down: None,
+        }
+    }
+}
+
+impl<S> Retry<S> {
+    pub fn new(inner: S, config: RetryConfig) -> Retry<S> {
+        Retry {
+            inner,
+            config: Arc::new(config),
+            breaker: Arc::new(Mutex::new(CircuitBreaker::default())),
+            cool_down: None,
+        }
+    }
+}
+
+impl<S, Req> tower::Service<Req> for Retry<S>
+where
+    S: tower::Service<Req, Error = tower::BoxError> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Response: Send + 'static,
+    Req: RetryableRequest + Clone + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = tower::BoxError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        loop {
+            let open_until = self.breaker.lock().unwrap().open_until();
+            let Some(open_until) = open_until else {
+                self.cool_down = None;
+                break;
+            };
+
+            match &mut self.cool_down {
+                Some(cool_down) => cool_down.as_mut().reset(open_until),
+                None => self.cool_down = Some(Box::pin(tokio::time::sleep_until(open_until))),
+            }
+            ready!(self.cool_down.as_mut().unwrap().as_mut().poll(cx));
+        }
+
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        // Take the inner service that was polled ready, leaving a clone in its place.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+        let breaker = self.breaker.clone();
+
+        async move {
+            let mut failed_attempts = 0;
+            loop {
+                let res = match inner.ready().await {
+                    Ok(svc) => svc.call(req.clone()).await,
+                    Err(e) => Err(e),
+                };
+
+                let err = match res {
+                    Ok(res) => {
+                        breaker.lock().unwrap().record_success();
+                        return Ok(res);
+                    }
+                    Err(e) => e,
+                };
+
+                failed_attempts += 1;
+
+                let breaker_open = {
+                    let mut breaker = breaker.lock().unwrap();
+                    breaker.record_failure(&config);
+                    breaker.open_until().is_some()
+                };
+
+                if !req.is_idempotent() || failed_attempts > config.max_retries || breaker_open {
+                    return Err(err);
+                }
+
+                let delay = config.delay(failed_attempts);
+                tracing::debug!(
+                    "Request failed: {}, retrying in {:?}, attempt: {}",
+                    err,
+                    delay,
+                    failed_attempts
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::task::Context;
+    use std::time::Duration;
+
+    use futures::task::noop_waker_ref;
+    use tower::{service_fn, util::BoxCloneService, Service, ServiceExt};
+
+    use super::{BreakerState, Retry, RetryConfig};
+    use crate::{DatabaseRequest, DatabaseResponse};
+
+    /// A database that fails every request, counting the requests in `calls`.
+    fn failing_database(
+        calls: Arc<AtomicU32>,
+    ) -> BoxCloneService<DatabaseRequest, DatabaseResponse, tower::BoxError> {
+        BoxCloneService::new(service_fn(move |_: DatabaseRequest| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            futures::future::ready(Err("database unavailable".into()))
+        }))
+    }
+
+    fn no_delay_config() -> RetryConfig {
+        RetryConfig {
+            max_retries: 3,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: false,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_pop_block_is_not_retried() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let mut retry = Retry::new(failing_database(calls.clone()), no_delay_config());
+
+        let res = retry
+            .ready()
+            .await
+            .unwrap()
+            .call(DatabaseRequest::PopBlock)
+            .await;
+        assert!(res.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_read_is_retried() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let mut retry = Retry::new(failing_database(calls.clone()), no_delay_config());
+
+        let res = retry
+            .ready()
+            .await
+            .unwrap()
+            .call(DatabaseRequest::ChainHeight)
+            .await;
+        assert!(res.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    fn breaker_state<S>(retry: &Retry<S>) -> BreakerState {
+        retry.breaker.lock().unwrap().state
+    }
+
+    /// Returns a retry middleware around a database that fails while `failing` is set, with a
+    /// breaker opening after 2 failures for 10 seconds.
+    fn breaker_test_service(
+        failing: Arc<AtomicBool>,
+    ) -> Retry<BoxCloneService<DatabaseRequest, DatabaseResponse, tower::BoxError>> {
+        let database = BoxCloneService::new(service_fn(move |_: DatabaseRequest| {
+            futures::future::ready(if failing.load(Ordering::SeqCst) {
+                Err("database unavailable".into())
+            } else {
+                Ok(DatabaseResponse::ChainHeight(10))
+            })
+        }));
+
+        Retry::new(
+            database,
+            RetryConfig {
+                max_retries: 0,
+                failure_threshold: 2,
+                cool_down: Duration::from_secs(10),
+                ..no_delay_config()
+            },
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn open_breaker_is_not_ready_until_half_open() {
+        let failing = Arc::new(AtomicBool::new(true));
+        let mut retry = breaker_test_service(failing.clone());
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        for _ in 0..2 {
+            assert!(retry.poll_ready(&mut cx).is_ready());
+            assert!(retry.call(DatabaseRequest::ChainHeight).await.is_err());
+        }
+        assert!(matches!(breaker_state(&retry), BreakerState::Open(_)));
+
+        // Open: the service is pending instead of failing.
+        assert!(retry.poll_ready(&mut cx).is_pending());
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert!(retry.poll_ready(&mut cx).is_pending());
+
+        // The wake up at the end of the cool down makes the service ready again.
+        tokio::time::timeout(Duration::from_secs(2), retry.ready())
+            .await
+            .expect("Service was not woken at the end of the cool down")
+            .unwrap();
+        assert_eq!(breaker_state(&retry), BreakerState::HalfOpen);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn half_open_breaker_opens_on_failure() {
+        let failing = Arc::new(AtomicBool::new(true));
+        let mut retry = breaker_test_service(failing.clone());
+
+        for _ in 0..2 {
+            let _ = retry
+                .ready()
+                .await
+                .unwrap()
+                .call(DatabaseRequest::ChainHeight)
+                .await;
+        }
+        tokio::time::advance(Duration::from_secs(10)).await;
+        retry.ready().await.unwrap();
+        assert_eq!(breaker_state(&retry), BreakerState::HalfOpen);
+
+        // A single failure while half-open opens the breaker again.
+        assert!(retry.call(DatabaseRequest::ChainHeight).await.is_err());
+        assert!(matches!(breaker_state(&retry), BreakerState::Open(_)));
+        assert!(retry
+            .poll_ready(&mut Context::from_waker(noop_waker_ref()))
+            .is_pending());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn half_open_breaker_closes_on_success() {
+        let failing = Arc::new(AtomicBool::new(true));
+        let mut retry = breaker_test_service(failing.clone());
+
+        for _ in 0..2 {
+            let _ = retry
+                .ready()
+                .await
+                .unwrap()
+                .call(DatabaseRequest::ChainHeight)
+                .await;
+        }
+        tokio::time::advance(Duration::from_secs(10)).await;
+        failing.store(false, Ordering::SeqCst);
+
+        let res = retry
+            .ready()
+            .await
+            .unwrap()
+            .call(DatabaseRequest::ChainHeight)
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(breaker_state(&retry), BreakerState::Closed);
+
+        // Closed: a single failure doesn't open the breaker again.
+        failing.store(true, Ordering::SeqCst);
+        let _ = retry
+            .ready()
+            .await
+            .unwrap()
+            .call(DatabaseRequest::ChainHeight)
+            .await;
+        assert_eq!(breaker_state(&retry), BreakerState::Closed);
+    }
+
+    #[test]
+    fn delay_doubles_and_is_capped() {
+        let config = RetryConfig {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: false,
+            ..Default::default()
+        };
+
+        assert_eq!(config.delay(1), Duration::from_millis(100));
+        assert_eq!(config.delay(2), Duration::from_millis(200));
+        assert_eq!(config.delay(4), Duration::from_millis(800));
+        assert_eq!(config.delay(5), Duration::from_millis(1000));
+        assert_eq!(config.delay(u32::MAX), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn jittered_delay_in_range() {
+        let config = RetryConfig {
+            base_delay: Duration::from_millis(100),
+            jitter: true,
+            ..Default::default()
+        };
+
+        for _ in 0..100 {
+            let delay = config.delay(2);
+            assert!(delay >= Duration::from_millis(100));
+            assert!(delay <= Duration::from_millis(200));
+        }
+    }
+}
@@ -19,33 +19,12 @@ use cuprate_common::BlockID;
 use crate::block::pow::BlockPOWInfo;
 use crate::block::weight::BlockWeightInfo;
 use crate::hardforks::BlockHFInfo;
-use crate::{DatabaseRequest, DatabaseResponse};
+use crate::retry::{Retry, RetryConfig};
+use crate::{DatabaseProtocolError, DatabaseRequest, DatabaseResponse};
 
 pub const MAX_BLOCKS_IN_RANGE: u64 = 10;
 pub const MAX_BLOCKS_HEADERS_IN_RANGE: u64 = 50;
 
-#[derive(Clone)]
-pub struct Attempts(u64);
-
-impl<Req: Clone, Res, E> tower::retry::Policy<Req, Res, E> for Attempts {
-    type Future = futures::future::Ready<Self>;
-    fn retry(&self, _: &Req, result: Result<&Res, &E>) -> Option<Self::Future> {
-        if result.is_err() {
-            if self.0 == 0 {
-                None
-            } else {
-                Some(futures::future::ready(Attempts(self.0 - 1)))
-            }
-        } else {
-            None
-        }
-    }
-
-    fn clone_request(&self, req: &Req) -> Option<Req> {
-        Some(req.clone())
-    }
-}
-
 pub fn init_rpc_load_balancer(
     addresses: Vec<String>,
 ) -> impl tower::Service<
@@ -63,7 +42,13 @@ pub fn init_rpc_load_balancer(
     );
     let rpc_balance = Balance::new(rpc_discoverer);
     let rpc_buffer = tower::buffer::Buffer::new(BoxService::new(rpc_balance), 3);
-    let rpcs = tower::retry::Retry::new(Attempts(2), rpc_buffer);
+    let rpcs = Retry::new(
+        rpc_buffer,
+        RetryConfig {
+            max_retries: 2,
+            ..Default::default()
+        },
+    );
 
     RpcBalancer { rpcs }
 }
@@ -95,71 +80,39 @@ where
         let this = self.rpcs.clone();
 
         match req {
-            DatabaseRequest::BlockBatchInRange(range) => {
-                let resp_to_ret = |resp: DatabaseResponse| {
-                    let DatabaseResponse::BlockBatchInRange(pow_info) = resp else {
-                        panic!("Database sent incorrect response");
-                    };
-                    pow_info
-                };
-                split_range_request(
-                    this,
-                    range,
-                    DatabaseRequest::BlockBatchInRange,
-                    DatabaseResponse::BlockBatchInRange,
-                    resp_to_ret,
-                    MAX_BLOCKS_IN_RANGE,
-                )
-            }
-            DatabaseRequest::BlockPOWInfoInRange(range) => {
-                let resp_to_ret = |resp: DatabaseResponse| {
-                    let DatabaseResponse::BlockPOWInfoInRange(pow_info) = resp else {
-                        panic!("Database sent incorrect response");
-                    };
-                    pow_info
-                };
-                split_range_request(
-                    this,
-                    range,
-                    DatabaseRequest::BlockPOWInfoInRange,
-                    DatabaseResponse::BlockPOWInfoInRange,
-                    resp_to_ret,
-                    MAX_BLOCKS_HEADERS_IN_RANGE,
-                )
-            }
-
-            DatabaseRequest::BlockWeightsInRange(range) => {
-                let resp_to_ret = |resp: DatabaseResponse| {
-                    let DatabaseResponse::BlockWeightsInRange(weights) = resp else {
-                        panic!("Database sent incorrect response");
-                    };
-                    weights
-                };
-                split_range_request(
-                    this,
-                    range,
-                    DatabaseRequest::BlockWeightsInRange,
-                    DatabaseResponse::BlockWeightsInRange,
-                    resp_to_ret,
-                    MAX_BLOCKS_HEADERS_IN_RANGE,
-                )
-            }
-            DatabaseRequest::BlockHfInfoInRange(range) => {
-                let resp_to_ret = |resp: DatabaseResponse| {
-                    let DatabaseResponse::BlockHfInfoInRange(hf_info) = resp else {
-                        panic!("Database sent incorrect response");
-                    };
-                    hf_info
-                };
-                split_range_request(
-                    this,
-                    range,
-                    DatabaseRequest::BlockHfInfoInRange,
-                    DatabaseResponse::BlockHfInfoInRange,
-                    resp_to_ret,
-                    MAX_BLOCKS_HEADERS_IN_RANGE,
-                )
-            }
+            DatabaseRequest::BlockBatchInRange(range) => split_range_request(
+                this,
+                range,
+                DatabaseRequest::BlockBatchInRange,
+                DatabaseResponse::BlockBatchInRange,
+                DatabaseResponse::into_block_batch_in_range,
+                MAX_BLOCKS_IN_RANGE,
+            ),
+            DatabaseRequest::BlockPOWInfoInRange(range) => split_range_request(
+                this,
+                range,
+                DatabaseRequest::BlockPOWInfoInRange,
+                DatabaseResponse::BlockPOWInfoInRange,
+                DatabaseResponse::into_block_pow_info_in_range,
+                MAX_BLOCKS_HEADERS_IN_RANGE,
+            ),
+
+            DatabaseRequest::BlockWeightsInRange(range) => split_range_request(
+                this,
+                range,
+                DatabaseRequest::BlockWeightsInRange,
+                DatabaseResponse::BlockWeightsInRange,
+                DatabaseResponse::into_block_weights_in_range,
+                MAX_BLOCKS_HEADERS_IN_RANGE,
+            ),
+            DatabaseRequest::BlockHfInfoInRange(range) => split_range_request(
+                this,
+                range,
+                DatabaseRequest::BlockHfInfoInRange,
+                DatabaseResponse::BlockHfInfoInRange,
+                DatabaseResponse::into_block_hf_info_in_range,
+                MAX_BLOCKS_HEADERS_IN_RANGE,
+            ),
             req => this.oneshot(req).boxed(),
         }
     }
@@ -170,7 +123,10 @@ fn split_range_request<T, Ret>(
     range: Range<u64>,
     req: impl FnOnce(Range<u64>) -> DatabaseRequest + Clone + Send + 'static,
     resp: impl FnOnce(Vec<Ret>) -> DatabaseResponse + Send + 'static,
-    resp_to_ret: impl Fn(DatabaseResponse) -> Vec<Ret> + Copy + Send + 'static,
+    resp_to_ret: impl Fn(DatabaseResponse) -> Result<Vec<Ret>, DatabaseProtocolError>
+        + Copy
+        + Send
+        + 'static,
     max_request_per_rpc: u64,
 ) -> Pin<Box<dyn Future<Output = Result<DatabaseResponse, tower::BoxError>> + Send + 'static>>
 where
@@ -196,8 +152,8 @@ where
     let mut res = Vec::with_capacity(range.count());
 
     async move {
-        for mut rpc_res in fut.try_collect::<Vec<Vec<_>>>().await?.into_iter() {
-            res.append(&mut rpc_res)
+        for rpc_res in fut.try_collect::<Vec<_>>().await?.into_iter() {
+            res.append(&mut rpc_res?)
         }
 
         Ok(resp(res))
@@ -284,6 +240,21 @@ impl<R: RpcConnection + Send + Sync + 'static> tower::Service<DatabaseRequest> f
             DatabaseRequest::BlockPOWInfo(id) => get_blocks_pow_info(id, rpc).boxed(),
             DatabaseRequest::BlockWeights(id) => get_blocks_weight_info(id, rpc).boxed(),
             DatabaseRequest::BlockHFInfo(id) => get_blocks_hf_info(id, rpc).boxed(),
+            DatabaseRequest::BlockHash(height) => async move {
+                let res: Result<_, RpcError> = rpc
+                    .get_block_hash(height.try_into().unwrap())
+                    .map_ok(DatabaseResponse::BlockHash)
+                    .await;
+                if let Err(e) = &res {
+                    *err_slot.lock().unwrap() = Some(e.clone());
+                }
+                res.map_err(Into::into)
+            }
+            .boxed(),
+            DatabaseRequest::GeneratedCoins(height) => get_generated_coins(height, rpc).boxed(),
+            DatabaseRequest::CumulativeDifficulty(height) => {
+                get_cumulative_difficulty(height, rpc).boxed()
+            }
             DatabaseRequest::BlockHfInfoInRange(range) => {
                 get_blocks_hf_info_in_range(range, rpc).boxed()
             }
@@ -293,8 +264,25 @@ impl<R: RpcConnection + Send + Sync + 'static> tower::Service<DatabaseRequest> f
             DatabaseRequest::BlockPOWInfoInRange(range) => {
                 get_blocks_pow_info_in_range(range, rpc).boxed()
             }
+            DatabaseRequest::KeyImagesSpent(key_images) => {
+                get_key_images_spent(key_images, rpc).boxed()
+            }
+            DatabaseRequest::Block(id) => get_block(id, rpc).boxed(),
             DatabaseRequest::BlockBatchInRange(range) => get_blocks_in_range(range, rpc).boxed(),
             DatabaseRequest::Transactions(txs) => get_transactions(txs, rpc).boxed(),
+            DatabaseRequest::BlockHeight(_)
+            | DatabaseRequest::BlockBlobsInRange { .. }
+            | DatabaseRequest::TxOutputIndices(_)
+            | DatabaseRequest::Outputs(_)
+            | DatabaseRequest::OutputTimeLocks(_)
+            | DatabaseRequest::NumOutputsInRange { .. }
+            | DatabaseRequest::OutputDistribution { .. }
+            | DatabaseRequest::NumberOutputsWithAmount(_)
+            | DatabaseRequest::WriteBlock(_)
+            | DatabaseRequest::WriteBlocks(_)
+            | DatabaseRequest::PopBlock => {
+                async { Err("Request not supported by the RPC database".into()) }.boxed()
+            }
         }
     }
 }
@@ -313,6 +301,47 @@ async fn get_transactions<R: RpcConnection>(
     Ok(DatabaseResponse::Transactions(txs))
 }
 
+async fn get_key_images_spent<R: RpcConnection>(
+    key_images: Vec<[u8; 32]>,
+    rpc: OwnedMutexGuard<monero_serai::rpc::Rpc<R>>,
+) -> Result<DatabaseResponse, tower::BoxError> {
+    #[derive(Deserialize, Debug)]
+    struct Response {
+        #[serde(default)]
+        spent_status: Vec<u8>,
+    }
+
+    if key_images.is_empty() {
+        return Ok(DatabaseResponse::KeyImagesSpent(false));
+    }
+
+    let res = rpc
+        .rpc_call::<_, Response>(
+            "is_key_image_spent",
+            Some(json!({"key_images": key_images.iter().map(hex::encode).collect::<Vec<_>>()})),
+        )
+        .await?;
+
+    // 1 means the key image is spent in the chain, 2 means it is only spent in the node's pool.
+    Ok(DatabaseResponse::KeyImagesSpent(
+        res.spent_status.contains(&1),
+    ))
+}
+
+async fn get_block<R: RpcConnection>(
+    id: BlockID,
+    rpc: OwnedMutexGuard<monero_serai::rpc::Rpc<R>>,
+) -> Result<DatabaseResponse, tower::BoxError> {
+    tracing::info!("Getting block: {}", id);
+
+    let block = match id {
+        BlockID::Height(height) => rpc.get_block_by_number(height.try_into()?).await?,
+        BlockID::Hash(hash) => rpc.get_block(hash).await?,
+    };
+
+    Ok(DatabaseResponse::Block(Box::new(block)))
+}
+
 async fn get_blocks_in_range<R: RpcConnection>(
     range: Range<u64>,
     rpc: OwnedMutexGuard<monero_serai::rpc::Rpc<R>>,
@@ -330,6 +359,27 @@ async fn get_blocks_in_range<R: RpcConnection>(
     ))
 }
 
+async fn get_generated_coins<R: RpcConnection>(
+    height: u64,
+    rpc: OwnedMutexGuard<monero_serai::rpc::Rpc<R>>,
+) -> Result<DatabaseResponse, tower::BoxError> {
+    #[derive(Deserialize, Debug)]
+    struct Response {
+        emission_amount: u64,
+    }
+
+    tracing::info!("Getting generated coins at height: {}", height);
+
+    let res = rpc
+        .json_rpc_call::<Response>(
+            "get_coinbase_tx_sum",
+            Some(json!({"height": 0, "count": height + 1})),
+        )
+        .await?;
+
+    Ok(DatabaseResponse::GeneratedCoins(res.emission_amount))
+}
+
 #[derive(Deserialize, Debug)]
 struct BlockInfo {
     cumulative_difficulty: u64,
@@ -455,6 +505,18 @@ async fn get_blocks_pow_info<R: RpcConnection>(
     }))
 }
 
+async fn get_cumulative_difficulty<R: RpcConnection>(
+    height: u64,
+    rpc: OwnedMutexGuard<monero_serai::rpc::Rpc<R>>,
+) -> Result<DatabaseResponse, tower::BoxError> {
+    let info = get_block_info(height.into(), rpc).await?;
+
+    Ok(DatabaseResponse::CumulativeDifficulty(u128_from_low_high(
+        info.cumulative_difficulty,
+        info.cumulative_difficulty_top64,
+    )))
+}
+
 fn u128_from_low_high(low: u64, high: u64) -> u128 {
     let res: u128 = high as u128;
     res << 64 | low as u128
@@ -0,0 +1,114 @@
+//! # Rule Flags
+//!
+//! This module contains [`RuleFlags`], which gates rules that don't correspond to a hard-fork on an
+//! activation height, for example emergency patches or relay policy changes.
+//!
+//! Each [`RuleFlag`] has a default activation height for each network, which can be overridden, so a
+//! node can activate a patched rule early or disable a relay rule.
+//!
+use std::collections::HashMap;
+
+use cuprate_common::Network;
+
+/// Where a rule is enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    /// The rule applies to blocks, a block breaking it is invalid.
+    Consensus,
+    /// The rule only applies to transactions being relayed, blocks can still contain transactions
+    /// breaking it.
+    Relay,
+}
+
+/// A rule that is not tied to a hard-fork.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RuleFlag {
+    /// Don't relay transactions with a `tx_extra` bigger than [`MAX_TX_EXTRA_SIZE`].
+    TxExtraSizeLimit,
+}
+
+/// The maximum size of a relayed transaction's `tx_extra`.
+///
+/// https://github.com/monero-project/monero/blob/90294f09ae34ef96f3dea5fea544816786df87c8/src/cryptonote_config.h#L210
+pub const MAX_TX_EXTRA_SIZE: usize = 1060;
+
+impl RuleFlag {
+    /// Every rule flag.
+    pub const ALL: [RuleFlag; 1] = [RuleFlag::TxExtraSizeLimit];
+
+    /// Returns where this rule is enforced.
+    pub fn kind(&self) -> RuleKind {
+        match self {
+            RuleFlag::TxExtraSizeLimit => RuleKind::Relay,
+        }
+    }
+
+    /// Returns the height this rule activates at on this network, or [`None`] if it is not
+    /// active by default.
+    pub fn default_activation_height(&self, network: &Network) -> Option<u64> {
+        match (self, network) {
+            (RuleFlag::TxExtraSizeLimit, _) => Some(0),
+        }
+    }
+}
+
+/// The activation heights of every [`RuleFlag`] on a network.
+#[derive(Debug, Clone)]
+pub struct RuleFlags {
+    activation_heights: HashMap<RuleFlag, u64>,
+}
+
+impl RuleFlags {
+    /// Returns the default rule flags for this network.
+    pub fn for_network(network: &Network) -> RuleFlags {
+        RuleFlags {
+            activation_heights: RuleFlag::ALL
+                .iter()
+                .filter_map(|flag| Some((*flag, flag.default_activation_height(network)?)))
+                .collect(),
+        }
+    }
+
+    /// Sets the height a rule activates at.
+    pub fn set_activation_height(&mut self, flag: RuleFlag, height: u64) {
+        self.activation_heights.insert(flag, height);
+    }
+
+    /// Disables a rule.
+    pub fn disable(&mut self, flag: RuleFlag) {
+        self.activation_heights.remove(&flag);
+    }
+
+    /// Returns the height a rule activates at, or [`None`] if it is disabled.
+    pub fn activation_height(&self, flag: RuleFlag) -> Option<u64> {
+        self.activation_heights.get(&flag).copied()
+    }
+
+    /// Returns true if the rule is active for a block at this height.
+    pub fn is_active(&self, flag: RuleFlag, height: u64) -> bool {
+        self.activation_height(flag)
+            .is_some_and(|activation_height| height >= activation_height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cuprate_common::Network;
+
+    use super::{RuleFlag, RuleFlags};
+
+    #[test]
+    fn overridden_activation_heights() {
+        let mut flags = RuleFlags::for_network(&Network::Mainnet);
+        assert!(flags.is_active(RuleFlag::TxExtraSizeLimit, 0));
+
+        flags.set_activation_height(RuleFlag::TxExtraSizeLimit, 100);
+        assert!(!flags.is_active(RuleFlag::TxExtraSizeLimit, 99));
+        assert!(flags.is_active(RuleFlag::TxExtraSizeLimit, 100));
+
+        flags.disable(RuleFlag::TxExtraSizeLimit);
+        assert_eq!(flags.activation_height(RuleFlag::TxExtraSizeLimit), None);
+        assert!(!flags.is_active(RuleFlag::TxExtraSizeLimit, u64::MAX));
+    }
+}
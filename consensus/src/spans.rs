@@ -0,0 +1,68 @@
+//! # Spans
+//!
+//! This module contains the `tracing` targets and spans used across the crate, so operators can
+//! filter verbose sync logs and follow a single block through the verifier.
+//!
+//! Events and spans are split between three targets:
+//! - [`BLOCK_TARGET`], `cuprate_consensus::block`: block checks, alt blocks, fluffy blocks,
+//!   quarantined blocks and the per-stage timings of verified blocks.
+//! - [`TX_TARGET`], `cuprate_consensus::tx`: transaction checks and the tx pool.
+//! - [`CONTEXT_TARGET`], `cuprate_consensus::context`: the hard-fork, weight and difficulty caches.
+//!
+//! For example `RUST_LOG=info,cuprate_consensus::block=debug` logs every verified block's timings
+//! without the caches' debug logs.
+//!
+//! Spans use the same field names everywhere: `height`, `hash` (hex encoded), `hf`, `source` and
+//! `batch_size`. A block's events happen inside its [`block_span`], so filtering on its hash, like
+//! `RUST_LOG='[block{hash=...}]=trace'`, shows everything done to one stuck block, and filtering on
+//! its source, like `RUST_LOG='[block{source=peer 000000000000002a}]=debug'`, everything done to the
+//! blocks of one peer.
+//!
+use tracing::Span;
+
+use crate::{hardforks::HardFork, misbehaviour::BlockSource};
+
+/// The target of block verification events.
+pub const BLOCK_TARGET: &str = "cuprate_consensus::block";
+/// The target of transaction verification events.
+pub const TX_TARGET: &str = "cuprate_consensus::tx";
+/// The target of the contextual caches' events.
+pub const CONTEXT_TARGET: &str = "cuprate_consensus::context";
+
+/// Returns the span a block is verified in, the block's hard-fork is recorded with [`record_hf`] once
+/// it is known and its source with [`record_source`].
+pub fn block_span(height: u64, hash: &[u8; 32]) -> Span {
+    tracing::info_span!(
+        target: BLOCK_TARGET,
+        "block",
+        height,
+        hash = %hex::encode(hash),
+        hf = tracing::field::Empty,
+        source = tracing::field::Empty
+    )
+}
+
+/// Records the hard-fork of the block in a [`block_span`].
+pub fn record_hf(span: &Span, hf: HardFork) {
+    span.record("hf", tracing::field::debug(hf));
+}
+
+/// Records where the block in a [`block_span`] came from.
+pub fn record_source(span: &Span, source: BlockSource) {
+    span.record("source", tracing::field::display(source));
+}
+
+/// Returns the span a batch of blocks is verified in.
+pub fn batch_span(start_height: u64, batch_size: usize) -> Span {
+    tracing::info_span!(
+        target: BLOCK_TARGET,
+        "batch",
+        height = start_height,
+        batch_size
+    )
+}
+
+/// Returns the span a transaction is verified in.
+pub fn tx_span(hash: &[u8; 32]) -> Span {
+    tracing::info_span!(target: TX_TARGET, "tx", hash = %hex::encode(hash))
+}
@@ -0,0 +1,139 @@
+//! # Speculative Contexts
+//!
+//! This module contains [`SpeculativeContext`], a view of the main chain's caches that hypothetical
+//! blocks can be added to. The tx pool and block templates use it to ask what the context would be
+//! with a block on top of the chain, without touching the [`Verifier`]'s caches.
+//!
+//! Views are taken with [`Verifier::speculative`] and are copy-on-write: the long term weights are
+//! kept in buckets shared with the verifier's cache and a block only copies the buckets it changes,
+//! the other windows hold at most a few hundred values. So taking a view and adding a block to it
+//! doesn't copy the 100,000 blocks of the long term window.
+//!
+//! [`Verifier`]: crate::verifier::Verifier
+//! [`Verifier::speculative`]: crate::verifier::Verifier::speculative
+//!
+use monero_serai::block::Block;
+
+use cuprate_common::Network;
+
+use crate::{
+    alt_chain::AltChainContextCache, context::BlockChainContext, ConsensusError, Database,
+};
+
+/// A copy-on-write view of the main chain's caches with hypothetical blocks added on top.
+#[derive(Clone)]
+pub struct SpeculativeContext {
+    network: Network,
+    /// The caches, forked from the main chain at the height of the view.
+    caches: AltChainContextCache,
+}
+
+impl SpeculativeContext {
+    pub(crate) fn new(network: Network, caches: AltChainContextCache) -> SpeculativeContext {
+        SpeculativeContext { network, caches }
+    }
+
+    /// Returns the context with the hypothetical blocks on top of the main chain.
+    pub fn context(&self) -> BlockChainContext {
+        BlockChainContext::from_caches(
+            self.network,
+            self.caches.chain_height,
+            self.caches.top_hash,
+            self.caches.already_generated_coins,
+            &self.caches.block_weight,
+            &self.caches.difficulty,
+            &self.caches.hard_fork,
+        )
+    }
+
+    /// Returns the amount of hypothetical blocks added to the view.
+    pub fn blocks_added(&self) -> u64 {
+        self.caches.chain_height - self.caches.fork_height()
+    }
+
+    /// Adds a hypothetical block to the view.
+    ///
+    /// The block must build on the top of the view. This function does not verify the block, it
+    /// should be checked against [`SpeculativeContext::context`] before being added.
+    pub async fn add_block<D: Database>(
+        &mut self,
+        block: &Block,
+        block_weight: usize,
+        database: D,
+    ) -> Result<(), ConsensusError> {
+        self.caches.add_block(block, block_weight, database).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::block::reward::calculate_base_reward;
+    use crate::hardforks::HardFork;
+    use crate::test_utils::{
+        dummy_block, dummy_miner_tx, dummy_output, DummyBlockExtendedHeader, DummyDatabaseBuilder,
+    };
+    use crate::verifier::{Config, Verifier};
+
+    #[test]
+    fn hypothetical_blocks_leave_the_verifier_alone() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                100,
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::V1, HardFork::V2)
+                    .with_weight(1_000, 1_000)
+                    .with_pow_info(50, 10),
+            )
+            .finish();
+        let verifier = block_on(Verifier::init(Config::main_net(), database.clone())).unwrap();
+        let context = verifier.context();
+
+        let mut speculative = verifier.speculative();
+        assert_eq!(speculative.context(), context);
+        assert_eq!(speculative.blocks_added(), 0);
+
+        let mut block = dummy_block(
+            HardFork::V1,
+            HardFork::V2,
+            context.top_hash,
+            dummy_miner_tx(1, Some(100), vec![dummy_output(Some(1_000))]),
+        );
+        block.header.timestamp = 60;
+
+        let hash = [1; 32];
+        block_on(
+            speculative
+                .caches
+                .add_block_with_hash(&block, hash, 1_000, database.clone()),
+        )
+        .unwrap();
+        // Blocks must build on the top of the view.
+        block.header.timestamp = 70;
+        assert!(block_on(
+            speculative
+                .caches
+                .add_block_with_hash(&block, [2; 32], 1_000, database,)
+        )
+        .is_err());
+
+        let next = speculative.context();
+        assert_eq!(speculative.blocks_added(), 1);
+        assert_eq!(next.chain_height, context.chain_height + 1);
+        assert_eq!(next.top_hash, hash);
+        assert_eq!(
+            next.cumulative_difficulty,
+            context.cumulative_difficulty + context.next_difficulty
+        );
+        // Only the emission is counted, not the miner transaction's 1_000.
+        assert_eq!(
+            next.already_generated_coins,
+            context.already_generated_coins
+                + calculate_base_reward(context.already_generated_coins, &HardFork::V1)
+        );
+
+        assert_eq!(verifier.context(), context);
+    }
+}
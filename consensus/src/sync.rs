@@ -0,0 +1,691 @@
+//! # Syncing
+//!
+//! This module adds the blocks downloaded from peers while syncing to the main chain, see
+//! [`add_synced_blocks`]. Blocks are passed as [`RawBlock`]s, so the blobs of pruned transactions
+//! are handed to the database in the buffers they were downloaded into.
+//!
+//! A block can be downloaded in full or pruned, without the prunable data of its transactions. A
+//! pruned block's transactions are checked against the block's transaction hashes with their
+//! prunable hashes, and the block is only accepted if [`Verifier::check_pruned_block`] allows it,
+//! so a pruned node only downloads the prunable data it keeps.
+//!
+use cuprate_common::blob::RawBlock;
+use monero_serai::{
+    block::Block,
+    transaction::{Transaction, TransactionPrefix},
+};
+use sha3::{Digest, Keccak256};
+use tower::ServiceExt;
+
+use crate::{
+    alt_chain::AltChainContextCache,
+    block::{weight::block_weight, VerifiedBlockInformation, VerifiedBlockTxs},
+    block_verifier::{needs_written_blocks, verify_block, UnverifiedBlock},
+    misbehaviour::BlockSource,
+    outputs::TxBlob,
+    timings::BlockTimings,
+    verifier::Verifier,
+    BlockError, ConsensusError, Database, DatabaseRequest,
+};
+
+/// A block downloaded from a peer, before it is verified.
+struct SyncedBlock {
+    block: Block,
+    /// The block's transactions, not including the miner transaction.
+    txs: VerifiedBlockTxs,
+    /// The block's weight given by the peer, only used for pruned blocks as their weight can't be
+    /// calculated without the prunable data, see [`Verifier::check_pruned_block`].
+    block_weight: usize,
+}
+
+impl SyncedBlock {
+    /// Parses a block sent by a peer for this height.
+    fn from_raw(raw: RawBlock, height: u64) -> Result<SyncedBlock, BlockError> {
+        let invalid = || BlockError::InvalidBlob { height };
+
+        let block = Block::read(&mut raw.block.as_ref()).map_err(|_| invalid())?;
+        let txs = match raw.prunable_hashes {
+            None => VerifiedBlockTxs::Full(
+                raw.txs
+                    .iter()
+                    .map(|tx| Transaction::read(&mut tx.as_ref()))
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid())?,
+            ),
+            Some(prunable_hashes) => {
+                if prunable_hashes.len() != raw.txs.len() {
+                    return Err(invalid());
+                }
+                VerifiedBlockTxs::Pruned(
+                    raw.txs
+                        .into_iter()
+                        .zip(prunable_hashes)
+                        .map(|(blob, prunable_hash)| {
+                            let prefix = TransactionPrefix::read(&mut blob.as_ref())
+                                .map_err(|_| invalid())?;
+                            // Version 1 transactions can't be pruned, they are sent complete with a
+                            // zero prunable hash.
+                            Ok(TxBlob {
+                                prunable_hash: (prefix.version != 1).then_some(prunable_hash),
+                                blob,
+                            })
+                        })
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+        };
+
+        Ok(SyncedBlock {
+            block,
+            txs,
+            block_weight: raw.block_weight as usize,
+        })
+    }
+}
+
+/// Verifies blocks downloaded from `source` that build, in order, on the verifier's chain and writes
+/// them to the database with [`DatabaseRequest::WriteBlocks`].
+///
+/// `target_height` is the height of the chain being synced, it decides which blocks can be accepted
+/// pruned. Each block is checked against the checkpoints, the hard-fork rules, the timestamps of
+/// the blocks before it and its reward, and its PoW is checked unless the verifier's profile trusts
+/// its height. The key images of the transactions must be unspent and their ring members unlocked,
+/// their signatures, ring signatures or RingCT proofs, are verified unless the profile trusts the
+/// block's height.
+///
+/// The verified blocks are written in one request, unless a block needs the blocks before it in the
+/// database to be verified, like a block with transactions, then those are written first. The
+/// verifier is extended with the blocks once they are written. An invalid block is reported with [`Verifier::report_failure`], the
+/// verified blocks before it that are not written yet are dropped.
+pub async fn add_synced_blocks<D: Database + Clone>(
+    verifier: &mut Verifier,
+    blocks: Vec<RawBlock>,
+    source: BlockSource,
+    target_height: u64,
+    mut database: D,
+) -> Result<(), ConsensusError> {
+    let mut caches = verifier.main_chain_caches();
+
+    let mut verified = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let height = caches.chain_height;
+        let written_height = height - verified.len() as u64;
+        if needs_written_blocks(
+            verifier,
+            height,
+            &caches.hard_fork.current_hardfork(),
+            !block.txs.is_empty(),
+            written_height,
+        ) {
+            caches = write_blocks(
+                verifier,
+                caches,
+                std::mem::take(&mut verified),
+                &mut database,
+            )
+            .await?;
+        }
+
+        let mut timings = BlockTimings::default();
+        let block = verify_synced_block(
+            verifier,
+            &mut caches,
+            block,
+            target_height,
+            database.clone(),
+            &mut timings,
+        )
+        .await
+        .inspect_err(|e| {
+            verifier.report_failure(source, e);
+        })?;
+        verifier.record_block_timings(&timings);
+        verified.push(block);
+    }
+
+    write_blocks(verifier, caches, verified, &mut database).await?;
+    Ok(())
+}
+
+/// Writes verified blocks to the database and extends the verifier's chain with the caches they
+/// were added to, returning the caches of the new main chain.
+async fn write_blocks<D: Database>(
+    verifier: &mut Verifier,
+    caches: AltChainContextCache,
+    verified: Vec<VerifiedBlockInformation>,
+    database: &mut D,
+) -> Result<AltChainContextCache, ConsensusError> {
+    if verified.is_empty() {
+        return Ok(caches);
+    }
+
+    let first_height = caches.fork_height();
+    let blocks: Vec<Block> = verified.iter().map(|block| block.block.clone()).collect();
+    database
+        .ready()
+        .await?
+        .call(DatabaseRequest::WriteBlocks(verified))
+        .await?
+        .into_write_block()?;
+    verifier.cross_check_database(&caches, database).await?;
+
+    verifier.announce_blocks(first_height, &blocks);
+    verifier.extend_main_chain(caches);
+    Ok(verifier.main_chain_caches())
+}
+
+/// Checks a block against the caches and adds it to them, returning it for the database.
+async fn verify_synced_block<D: Database + Clone>(
+    verifier: &Verifier,
+    caches: &mut AltChainContextCache,
+    raw: RawBlock,
+    target_height: u64,
+    database: D,
+    timings: &mut BlockTimings,
+) -> Result<VerifiedBlockInformation, ConsensusError> {
+    let height = caches.chain_height;
+    let synced = SyncedBlock::from_raw(raw, height)?;
+
+    let mismatch = BlockError::TransactionsMismatch { height };
+    if synced.txs.len() != synced.block.txs.len() {
+        return Err(mismatch.into());
+    }
+    let weight = match &synced.txs {
+        VerifiedBlockTxs::Full(txs) => {
+            if txs
+                .iter()
+                .zip(&synced.block.txs)
+                .any(|(tx, hash)| tx.hash() != *hash)
+            {
+                return Err(mismatch.into());
+            }
+            block_weight(&synced.block, txs)
+        }
+        VerifiedBlockTxs::Pruned(txs) => {
+            verifier.check_pruned_block(height, target_height)?;
+            if txs
+                .iter()
+                .zip(&synced.block.txs)
+                .any(|(tx, hash)| pruned_tx_hash(tx).as_ref() != Some(hash))
+            {
+                return Err(mismatch.into());
+            }
+            synced.block_weight
+        }
+    };
+
+    verify_block(
+        verifier,
+        caches,
+        UnverifiedBlock {
+            block_hash: synced.block.hash(),
+            block: synced.block,
+            txs: synced.txs,
+            weight,
+        },
+        database,
+        timings,
+    )
+    .await
+}
+
+fn keccak(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+/// Returns the hash of a transaction of a pruned block, [`None`] if the blob is not a transaction.
+///
+/// Like monerod's `get_pruned_transaction_hash`, the hash of a version 2 transaction is the hash of
+/// the hashes of its prefix, its RingCT base and its prunable data, the pruned blob is the prefix
+/// followed by the base. Version 1 transactions can't be pruned, their blob is complete.
+fn pruned_tx_hash(tx: &TxBlob) -> Option<[u8; 32]> {
+    let Some(prunable_hash) = tx.prunable_hash else {
+        let full_tx = Transaction::read(&mut tx.blob.as_ref()).ok()?;
+        return (full_tx.prefix.version == 1).then(|| full_tx.hash());
+    };
+
+    let mut rct_base: &[u8] = &tx.blob;
+    let prefix = TransactionPrefix::read(&mut rct_base).ok()?;
+    if prefix.version == 1 {
+        return None;
+    }
+    let prefix_blob = &tx.blob[..tx.blob.len() - rct_base.len()];
+
+    let mut hashes = Vec::with_capacity(96);
+    hashes.extend(keccak(prefix_blob));
+    hashes.extend(keccak(rct_base));
+    hashes.extend(prunable_hash);
+    Some(keccak(&hashes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use curve25519_dalek::{constants::ED25519_BASEPOINT_POINT, Scalar};
+    use futures::executor::block_on;
+    use monero_serai::transaction::{Input, RingSignature, Timelock};
+    use tower::Service;
+
+    use cuprate_common::{Network, PruningSeed, CRYPTONOTE_PRUNING_LOG_STRIPES};
+
+    use super::*;
+    use crate::{
+        block::reward::calculate_base_reward,
+        consensus_constants::{ConsensusConstants, NUMB_OF_HARD_FORKS},
+        hardforks::HardFork,
+        misbehaviour::PeerId,
+        outputs::OutputOnChain,
+        test_utils::{
+            dummy_block, dummy_miner_tx, dummy_output, DummyBlockExtendedHeader, DummyDatabase,
+            DummyDatabaseBuilder,
+        },
+        transactions::tx_fee,
+        verifier::{Config, VerificationProfile},
+        DatabaseResponse, MinerTxError, TransactionError,
+    };
+
+    const SOURCE: BlockSource = BlockSource::Peer(PeerId(1));
+
+    /// Returns a database that answers reads from `database` and records the heights of the
+    /// blocks written.
+    fn recording_database(
+        database: DummyDatabase,
+        written: Arc<Mutex<Vec<u64>>>,
+    ) -> impl tower::Service<
+        DatabaseRequest,
+        Response = DatabaseResponse,
+        Error = tower::BoxError,
+        Future = futures::future::Ready<Result<DatabaseResponse, tower::BoxError>>,
+    > + Clone {
+        tower::service_fn(move |req| match req {
+            DatabaseRequest::WriteBlocks(blocks) => {
+                let mut written = written.lock().unwrap();
+                written.extend(blocks.iter().map(|block| block.height));
+                futures::future::ready(Ok(DatabaseResponse::WriteBlock))
+            }
+            req => database.clone().call(req),
+        })
+    }
+
+    /// Returns a verifier at height 100 that keeps the second pruning stripe and trusts the blocks
+    /// below height 50,000.
+    fn pruned_verifier(database: DummyDatabase) -> Verifier {
+        let seed = PruningSeed::new(2, CRYPTONOTE_PRUNING_LOG_STRIPES).unwrap();
+        block_on(Verifier::init(
+            Config::for_network(Network::Testnet)
+                .with_profile(VerificationProfile::Fast {
+                    trusted_height: 50_000,
+                })
+                .with_pruning_seed(seed),
+            database,
+        ))
+        .unwrap()
+    }
+
+    /// A version 2 transaction without inputs, it has no prunable data so its prunable hash is zero.
+    fn v2_tx(extra: u8) -> Transaction {
+        let mut tx = dummy_miner_tx(2, None, vec![]);
+        tx.prefix.extra = vec![extra];
+        tx
+    }
+
+    /// A version 1 transaction spending output `index` of amount 10, with a fee of 5.
+    fn v1_tx(index: u64) -> Transaction {
+        let mut tx = dummy_miner_tx(1, None, vec![dummy_output(Some(5))]);
+        tx.prefix.inputs = vec![Input::ToKey {
+            amount: Some(10),
+            key_offsets: vec![index],
+            key_image: ED25519_BASEPOINT_POINT * Scalar::from(index + 1),
+        }];
+        tx.signatures = vec![RingSignature {
+            sigs: vec![(Scalar::ONE, Scalar::ONE)],
+        }];
+        tx
+    }
+
+    /// Returns block 100 with these transactions, paying its reward and the fees.
+    fn block(verifier: &Verifier, txs: &[Transaction]) -> Block {
+        let reward = verifier
+            .main_chain_caches()
+            .next_block_generated_coins(0)
+            .unwrap();
+        let fees: u64 = txs.iter().map(|tx| tx_fee(tx).unwrap()).sum();
+
+        let mut block = dummy_block(
+            HardFork::V1,
+            HardFork::V1,
+            verifier.main_chain_caches().top_hash,
+            dummy_miner_tx(1, Some(100), vec![dummy_output(Some(reward + fees))]),
+        );
+        // The timestamp of every block in the database.
+        block.header.timestamp = 50;
+        block.txs = txs.iter().map(Transaction::hash).collect();
+        block
+    }
+
+    /// Returns the block as a peer would send it, pruned if `pruned`.
+    fn to_raw(block: &Block, txs: &[Transaction], pruned: bool) -> RawBlock {
+        RawBlock {
+            block: block.serialize().into(),
+            txs: txs.iter().map(|tx| tx.serialize().into()).collect(),
+            prunable_hashes: pruned.then(|| vec![[0; 32]; txs.len()]),
+            block_weight: 1_000,
+        }
+    }
+
+    /// Returns block 100 with these transactions as a peer would send it, pruned if `pruned`.
+    fn raw_block(verifier: &Verifier, txs: &[Transaction], pruned: bool) -> RawBlock {
+        to_raw(&block(verifier, txs), txs, pruned)
+    }
+
+    fn database() -> DummyDatabase {
+        DummyDatabaseBuilder::default()
+            .add_blocks(
+                100,
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::V1, HardFork::V1)
+                    .with_weight(1_000, 1_000)
+                    .with_pow_info(50, 10),
+            )
+            .add_output(10, 0, output())
+            .add_output(10, 1, output())
+            .add_output(10, 2, output())
+            // The key image of `v1_tx(2)`.
+            .add_spent_key_image((ED25519_BASEPOINT_POINT * Scalar::from(3_u64)).compress().0)
+            .finish()
+    }
+
+    fn output() -> OutputOnChain {
+        OutputOnChain {
+            height: 10,
+            time_lock: Timelock::None,
+            key: [0; 32],
+            mask: [0; 32],
+            txid: [0; 32],
+        }
+    }
+
+    #[test]
+    fn pruned_tx_hashes() {
+        let tx = v2_tx(10);
+        let pruned = TxBlob {
+            blob: tx.serialize().into(),
+            prunable_hash: Some([0; 32]),
+        };
+        assert_eq!(pruned_tx_hash(&pruned), Some(tx.hash()));
+
+        let wrong_prunable_hash = TxBlob {
+            prunable_hash: Some([1; 32]),
+            ..pruned.clone()
+        };
+        assert_ne!(pruned_tx_hash(&wrong_prunable_hash), Some(tx.hash()));
+
+        // Only version 1 transactions are sent without a prunable hash.
+        let unpruned = TxBlob {
+            prunable_hash: None,
+            ..pruned
+        };
+        assert_eq!(pruned_tx_hash(&unpruned), None);
+        let v1_tx = dummy_miner_tx(1, Some(5), vec![]);
+        let v1_blob = TxBlob {
+            blob: v1_tx.serialize().into(),
+            prunable_hash: None,
+        };
+        assert_eq!(pruned_tx_hash(&v1_blob), Some(v1_tx.hash()));
+    }
+
+    #[test]
+    fn pruned_blocks_are_written_pruned() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let database = database();
+        let mut verifier = pruned_verifier(database.clone());
+        let database = recording_database(database, written.clone());
+
+        // Block 100 is in the first stripe, which we don't keep.
+        let block = raw_block(&verifier, &[v1_tx(0), v1_tx(1)], true);
+        block_on(add_synced_blocks(
+            &mut verifier,
+            vec![block],
+            SOURCE,
+            10_000,
+            database,
+        ))
+        .unwrap();
+
+        assert_eq!(*written.lock().unwrap(), [100]);
+        assert_eq!(verifier.context().chain_height, 101);
+    }
+
+    #[test]
+    fn pruned_blocks_in_the_tip_are_rejected() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let database = database();
+        let mut verifier = pruned_verifier(database.clone());
+        let database = recording_database(database, written.clone());
+
+        let block = raw_block(&verifier, &[v1_tx(0)], true);
+        let err = block_on(add_synced_blocks(
+            &mut verifier,
+            vec![block.clone()],
+            SOURCE,
+            1_000,
+            database.clone(),
+        ))
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ConsensusError::Block(BlockError::PrunedBlockNotAllowed { height: 100 })
+        ));
+
+        // The full block is accepted.
+        let block = raw_block(&verifier, &[v1_tx(0)], false);
+        block_on(add_synced_blocks(
+            &mut verifier,
+            vec![block],
+            SOURCE,
+            1_000,
+            database,
+        ))
+        .unwrap();
+        assert_eq!(*written.lock().unwrap(), [100]);
+    }
+
+    #[test]
+    fn transactions_must_match_the_block() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let database = database();
+        let mut verifier = pruned_verifier(database.clone());
+        let database = recording_database(database, written.clone());
+
+        for pruned in [true, false] {
+            let mut block = raw_block(&verifier, &[v2_tx(10)], pruned);
+            block.txs = raw_block(&verifier, &[v2_tx(20)], pruned).txs;
+
+            let err = block_on(add_synced_blocks(
+                &mut verifier,
+                vec![block.clone()],
+                SOURCE,
+                10_000,
+                database.clone(),
+            ))
+            .unwrap_err();
+            assert!(matches!(
+                err,
+                ConsensusError::Block(BlockError::TransactionsMismatch { height: 100 })
+            ));
+
+            block.txs[0] = vec![2; 10].into();
+            let err = block_on(add_synced_blocks(
+                &mut verifier,
+                vec![block],
+                SOURCE,
+                10_000,
+                database.clone(),
+            ))
+            .unwrap_err();
+            assert!(matches!(
+                err,
+                ConsensusError::Block(BlockError::InvalidBlob { height: 100 })
+            ));
+        }
+        assert!(written.lock().unwrap().is_empty());
+        assert_eq!(verifier.context().chain_height, 100);
+    }
+
+    #[test]
+    fn pow_is_only_skipped_on_regtest() {
+        // Every block has the same timestamp, so the next difficulty is far above what a block
+        // without real PoW meets.
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                101,
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::V1, HardFork::V1)
+                    .with_weight(1_000, 1_000)
+                    .with_pow_info(50, u64::MAX.into()),
+            )
+            .finish();
+        // No coins were generated by the blocks in the database.
+        let reward = calculate_base_reward(0, &HardFork::V1);
+        let mut block = dummy_block(
+            HardFork::V1,
+            HardFork::V1,
+            database.block_hash(100).unwrap(),
+            dummy_miner_tx(1, Some(101), vec![dummy_output(Some(reward))]),
+        );
+        block.header.timestamp = 50;
+        let raw = RawBlock {
+            block: block.serialize().into(),
+            txs: vec![],
+            prunable_hashes: None,
+            block_weight: 0,
+        };
+
+        let mut fork_heights = [1_000_000; NUMB_OF_HARD_FORKS];
+        fork_heights[0] = 0;
+        let regtest = Config::from_constants(
+            ConsensusConstants::for_network(&Network::Regtest).with_fork_heights(fork_heights),
+        )
+        .with_profile(VerificationProfile::Regtest);
+
+        for (config, valid) in [
+            (Config::for_network(Network::Mainnet), false),
+            (regtest, true),
+        ] {
+            let written = Arc::new(Mutex::new(vec![]));
+            let mut verifier = block_on(Verifier::init(config, database.clone())).unwrap();
+
+            let res = block_on(add_synced_blocks(
+                &mut verifier,
+                vec![raw.clone()],
+                SOURCE,
+                102,
+                recording_database(database.clone(), written.clone()),
+            ));
+            if valid {
+                res.unwrap();
+                assert_eq!(*written.lock().unwrap(), [101]);
+            } else {
+                assert!(matches!(
+                    res,
+                    Err(ConsensusError::Block(BlockError::InvalidPow {
+                        height: 101
+                    }))
+                ));
+                assert!(written.lock().unwrap().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn synced_blocks_are_checked_against_the_chain() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let database = database();
+        let mut verifier = pruned_verifier(database.clone());
+        let database = recording_database(database, written.clone());
+
+        // Pruned blocks are only accepted out of the tip, they get the same checks.
+        for (pruned, target_height) in [(false, 1_000), (true, 10_000)] {
+            let check = |verifier: &mut Verifier, raw: RawBlock| {
+                block_on(add_synced_blocks(
+                    verifier,
+                    vec![raw],
+                    SOURCE,
+                    target_height,
+                    database.clone(),
+                ))
+                .unwrap_err()
+            };
+
+            // A key image spent in the chain or twice in the block.
+            for txs in [vec![v1_tx(2)], vec![v1_tx(0), v1_tx(0)]] {
+                let raw = raw_block(&verifier, &txs, pruned);
+                let err = check(&mut verifier, raw);
+                assert!(matches!(
+                    err,
+                    ConsensusError::Block(BlockError::DoubleSpend { height: 100 })
+                ));
+            }
+
+            let txs = [v1_tx(0), v1_tx(1)];
+            let mut block = block(&verifier, &txs);
+            block.header.timestamp = 49;
+            let err = check(&mut verifier, to_raw(&block, &txs, pruned));
+            assert!(matches!(
+                err,
+                ConsensusError::Block(BlockError::TimestampTooEarly {
+                    height: 100,
+                    timestamp: 49,
+                    median: 50
+                })
+            ));
+
+            // The miner doesn't claim the fees, which isn't allowed at V1.
+            block.header.timestamp = 50;
+            block.miner_tx.prefix.outputs[0].amount = block.miner_tx.prefix.outputs[0]
+                .amount
+                .map(|amount| amount - 10);
+            let err = check(&mut verifier, to_raw(&block, &txs, pruned));
+            assert!(matches!(
+                err,
+                ConsensusError::Block(BlockError::InvalidMinerTx {
+                    height: 100,
+                    source: MinerTxError::WrongReward { .. }
+                })
+            ));
+        }
+
+        assert!(written.lock().unwrap().is_empty());
+        assert_eq!(verifier.context().chain_height, 100);
+    }
+
+    #[test]
+    fn signatures_are_checked_above_the_trusted_height() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let database = database();
+        // Regtest checks the signatures of every block, but not their PoW.
+        let mut verifier = block_on(Verifier::init(
+            Config::for_network(Network::Testnet).with_profile(VerificationProfile::Regtest),
+            database.clone(),
+        ))
+        .unwrap();
+        let database = recording_database(database, written.clone());
+
+        // The signatures of `v1_tx` are only accepted below the trusted height of `pruned_verifier`.
+        let raw = raw_block(&verifier, &[v1_tx(0)], false);
+        let err = block_on(add_synced_blocks(
+            &mut verifier,
+            vec![raw],
+            SOURCE,
+            1_000,
+            database,
+        ))
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ConsensusError::Transaction(TransactionError::InvalidRingSignature(0))
+        ));
+        assert!(written.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,597 @@
+//! # Test Utils
+//!
+//! This module contains [`DummyDatabase`], an in-memory database that can be used to test the
+//! components of this crate, and [`DummyDatabaseBuilder`] to build synthetic chains to fill it
+//! with. [`dummy_block`], [`dummy_miner_tx`] and [`dummy_verified_block`] build the blocks that
+//! tests give to the verifier, the caches and the database, and [`dummy_context`] the context
+//! transactions and templates are checked against. [`sign_v1_tx`] signs version 1 transactions. [`MemoryDatabase`] stores the blocks written to
+//! it, for running nodes without a database on disk.
+//!
+//! ```ignore
+//! // 1000 blocks of weight 300,000 voting for V16.
+//! let database = DummyDatabaseBuilder::default()
+//!     .add_blocks(
+//!         1000,
+//!         DummyBlockExtendedHeader::default()
+//!             .with_weight(300_000, 300_000)
+//!             .with_hard_fork_info(HardFork::V16, HardFork::V16),
+//!     )
+//!     .finish();
+//! ```
+mod memory;
+
+pub use memory::MemoryDatabase;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+
+use curve25519_dalek::{
+    constants::ED25519_BASEPOINT_POINT, edwards::CompressedEdwardsY, EdwardsPoint, Scalar,
+};
+use futures::future::{ready, Ready};
+use monero_serai::{
+    block::{Block, BlockHeader},
+    hash, hash_to_scalar,
+    ringct::{hash_to_point, RctBase, RctPrunable, RctSignatures},
+    transaction::{Input, Output, Timelock, Transaction, TransactionPrefix},
+};
+
+use cuprate_common::{BlockID, Network};
+
+use crate::{
+    block::{
+        pow::BlockPOWInfo, weight::BlockWeightInfo, VerifiedBlockInformation, VerifiedBlockTxs,
+    },
+    consensus_constants::MINED_MONEY_UNLOCK_WINDOW,
+    context::BlockChainContext,
+    hardforks::{BlockHFInfo, HardFork},
+    outputs::{BlockBlobs, OutputOnChain, OutputTimeLock},
+    DatabaseRequest, DatabaseResponse,
+};
+
+/// The information the [`DummyDatabase`] holds for each block.
+#[derive(Debug, Clone, Copy)]
+pub struct DummyBlockExtendedHeader {
+    pub version: HardFork,
+    pub vote: HardFork,
+
+    pub timestamp: u64,
+    /// The difficulty of this block, the cumulative difficulty is calculated by the builder.
+    pub difficulty: u128,
+
+    pub block_weight: usize,
+    pub long_term_weight: usize,
+
+    /// The coins generated by this block, the total is calculated by the builder.
+    pub generated_coins: u64,
+}
+
+impl Default for DummyBlockExtendedHeader {
+    fn default() -> Self {
+        DummyBlockExtendedHeader {
+            version: HardFork::V1,
+            vote: HardFork::V1,
+            timestamp: 0,
+            difficulty: 1,
+            block_weight: 0,
+            long_term_weight: 0,
+            generated_coins: 0,
+        }
+    }
+}
+
+impl DummyBlockExtendedHeader {
+    /// Sets the block's version and vote.
+    pub fn with_hard_fork_info(mut self, version: HardFork, vote: HardFork) -> Self {
+        self.version = version;
+        self.vote = vote;
+        self
+    }
+
+    /// Sets the block's weight and long term weight.
+    pub fn with_weight(mut self, block_weight: usize, long_term_weight: usize) -> Self {
+        self.block_weight = block_weight;
+        self.long_term_weight = long_term_weight;
+        self
+    }
+
+    /// Sets the block's timestamp and difficulty.
+    pub fn with_pow_info(mut self, timestamp: u64, difficulty: u128) -> Self {
+        self.timestamp = timestamp;
+        self.difficulty = difficulty;
+        self
+    }
+
+    /// Sets the coins generated by the block.
+    pub fn with_generated_coins(mut self, generated_coins: u64) -> Self {
+        self.generated_coins = generated_coins;
+        self
+    }
+}
+
+/// A block stored in the [`DummyDatabase`].
+#[derive(Debug, Clone, Copy)]
+struct DummyBlock {
+    header: DummyBlockExtendedHeader,
+    cumulative_difficulty: u128,
+    already_generated_coins: u64,
+    hash: [u8; 32],
+}
+
+/// A builder for a [`DummyDatabase`].
+#[derive(Debug, Default)]
+pub struct DummyDatabaseBuilder {
+    blocks: Vec<DummyBlock>,
+    spent_key_images: HashSet<[u8; 32]>,
+    tx_output_indices: HashMap<[u8; 32], Vec<u64>>,
+    outputs: HashMap<(u64, u64), OutputOnChain>,
+    coinbase_outputs: HashSet<(u64, u64)>,
+}
+
+impl DummyDatabaseBuilder {
+    /// Adds a block to the top of the chain.
+    pub fn add_block(mut self, header: DummyBlockExtendedHeader) -> Self {
+        self.blocks.push(next_block(&self.blocks, header));
+        self
+    }
+
+    /// Adds `count` copies of a block to the top of the chain.
+    pub fn add_blocks(mut self, count: usize, header: DummyBlockExtendedHeader) -> Self {
+        for _ in 0..count {
+            self.blocks.push(next_block(&self.blocks, header));
+        }
+        self
+    }
+
+    /// Marks a key image as spent in the chain.
+    pub fn add_spent_key_image(mut self, key_image: [u8; 32]) -> Self {
+        self.spent_key_images.insert(key_image);
+        self
+    }
+
+    /// Sets the output indices of a transaction in the chain.
+    pub fn add_tx_output_indices(mut self, txid: [u8; 32], indices: Vec<u64>) -> Self {
+        self.tx_output_indices.insert(txid, indices);
+        self
+    }
+
+    /// Adds an output with this amount and amount index.
+    pub fn add_output(mut self, amount: u64, index: u64, output: OutputOnChain) -> Self {
+        self.outputs.insert((amount, index), output);
+        self
+    }
+
+    /// Adds an output created by a miner transaction with this amount and amount index.
+    pub fn add_coinbase_output(mut self, amount: u64, index: u64, output: OutputOnChain) -> Self {
+        self.coinbase_outputs.insert((amount, index));
+        self.add_output(amount, index, output)
+    }
+
+    pub fn finish(self) -> DummyDatabase {
+        DummyDatabase {
+            blocks: Arc::new(RwLock::new(self.blocks)),
+            spent_key_images: Arc::new(self.spent_key_images),
+            tx_output_indices: Arc::new(self.tx_output_indices),
+            outputs: Arc::new(self.outputs),
+            coinbase_outputs: Arc::new(self.coinbase_outputs),
+        }
+    }
+}
+
+/// Creates the next block to go on top of the chain.
+fn next_block(chain: &[DummyBlock], header: DummyBlockExtendedHeader) -> DummyBlock {
+    let cumulative_difficulty = chain
+        .last()
+        .map(|block| block.cumulative_difficulty)
+        .unwrap_or(0)
+        + header.difficulty;
+    let already_generated_coins = chain
+        .last()
+        .map(|block| block.already_generated_coins)
+        .unwrap_or(0)
+        + header.generated_coins;
+
+    let mut hash = [0; 32];
+    hash[0..8].copy_from_slice(&(chain.len() as u64).to_le_bytes());
+
+    DummyBlock {
+        header,
+        cumulative_difficulty,
+        already_generated_coins,
+        hash,
+    }
+}
+
+/// An in-memory database, clones of this database share the same chain.
+///
+/// The blocks have no transactions, their blobs are their hashes and their miner transactions have
+/// a single output with an index of the block's height.
+#[derive(Debug, Clone)]
+pub struct DummyDatabase {
+    blocks: Arc<RwLock<Vec<DummyBlock>>>,
+    spent_key_images: Arc<HashSet<[u8; 32]>>,
+    tx_output_indices: Arc<HashMap<[u8; 32], Vec<u64>>>,
+    outputs: Arc<HashMap<(u64, u64), OutputOnChain>>,
+    coinbase_outputs: Arc<HashSet<(u64, u64)>>,
+}
+
+impl DummyDatabase {
+    /// Adds a block to the top of the chain.
+    pub fn add_block(&self, header: DummyBlockExtendedHeader) {
+        let mut blocks = self.blocks.write().unwrap();
+        let block = next_block(&blocks, header);
+        blocks.push(block);
+    }
+
+    /// Removes the top block of the chain.
+    pub fn pop_block(&self) {
+        self.blocks.write().unwrap().pop();
+    }
+
+    /// Returns the hash the database gives the block at this height.
+    pub fn block_hash(&self, height: u64) -> Option<[u8; 32]> {
+        self.blocks
+            .read()
+            .unwrap()
+            .get(usize::try_from(height).ok()?)
+            .map(|block| block.hash)
+    }
+}
+
+fn find_block(blocks: &[DummyBlock], id: &BlockID) -> Result<DummyBlock, tower::BoxError> {
+    match id {
+        BlockID::Height(height) => usize::try_from(*height)
+            .ok()
+            .and_then(|height| blocks.get(height)),
+        BlockID::Hash(hash) => blocks.iter().find(|block| &block.hash == hash),
+    }
+    .copied()
+    .ok_or_else(|| format!("Block not found: {}", id).into())
+}
+
+fn get_range(
+    blocks: &[DummyBlock],
+    range: std::ops::Range<u64>,
+) -> Result<&[DummyBlock], tower::BoxError> {
+    let start = usize::try_from(range.start)?;
+    let end = usize::try_from(range.end)?;
+
+    blocks
+        .get(start..end)
+        .ok_or_else(|| format!("Range not in database: {:?}", range).into())
+}
+
+fn block_blobs(height: u64, block: &DummyBlock) -> BlockBlobs {
+    BlockBlobs {
+        block: block.hash.to_vec(),
+        block_weight: block.header.block_weight,
+        txs: vec![],
+        output_indices: vec![vec![height]],
+    }
+}
+
+fn hf_info(block: &DummyBlock) -> BlockHFInfo {
+    BlockHFInfo {
+        version: block.header.version,
+        vote: block.header.vote,
+        raw_vote: block.header.vote as u8,
+    }
+}
+
+fn pow_info(block: &DummyBlock) -> BlockPOWInfo {
+    BlockPOWInfo {
+        timestamp: block.header.timestamp,
+        cumulative_difficulty: block.cumulative_difficulty,
+    }
+}
+
+fn weight_info(block: &DummyBlock) -> BlockWeightInfo {
+    BlockWeightInfo {
+        block_weight: block.header.block_weight,
+        long_term_weight: block.header.long_term_weight,
+    }
+}
+
+impl tower::Service<DatabaseRequest> for DummyDatabase {
+    type Response = DatabaseResponse;
+    type Error = tower::BoxError;
+    type Future = Ready<Result<DatabaseResponse, tower::BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: DatabaseRequest) -> Self::Future {
+        let blocks = self.blocks.read().unwrap();
+
+        let res = (|| {
+            Ok(match req {
+                DatabaseRequest::ChainHeight => DatabaseResponse::ChainHeight(blocks.len() as u64),
+                DatabaseRequest::BlockHFInfo(id) => {
+                    DatabaseResponse::BlockHFInfo(hf_info(&find_block(&blocks, &id)?))
+                }
+                DatabaseRequest::BlockPOWInfo(id) => {
+                    DatabaseResponse::BlockPOWInfo(pow_info(&find_block(&blocks, &id)?))
+                }
+                DatabaseRequest::BlockWeights(id) => {
+                    DatabaseResponse::BlockWeights(weight_info(&find_block(&blocks, &id)?))
+                }
+                DatabaseRequest::BlockHash(height) => {
+                    DatabaseResponse::BlockHash(find_block(&blocks, &height.into())?.hash)
+                }
+                DatabaseRequest::GeneratedCoins(height) => DatabaseResponse::GeneratedCoins(
+                    find_block(&blocks, &height.into())?.already_generated_coins,
+                ),
+                DatabaseRequest::CumulativeDifficulty(height) => {
+                    DatabaseResponse::CumulativeDifficulty(
+                        find_block(&blocks, &height.into())?.cumulative_difficulty,
+                    )
+                }
+                DatabaseRequest::BlockHfInfoInRange(range) => DatabaseResponse::BlockHfInfoInRange(
+                    get_range(&blocks, range)?.iter().map(hf_info).collect(),
+                ),
+                DatabaseRequest::BlockWeightsInRange(range) => {
+                    DatabaseResponse::BlockWeightsInRange(
+                        get_range(&blocks, range)?.iter().map(weight_info).collect(),
+                    )
+                }
+                DatabaseRequest::BlockPOWInfoInRange(range) => {
+                    DatabaseResponse::BlockPOWInfoInRange(
+                        get_range(&blocks, range)?.iter().map(pow_info).collect(),
+                    )
+                }
+                DatabaseRequest::KeyImagesSpent(key_images) => DatabaseResponse::KeyImagesSpent(
+                    key_images
+                        .iter()
+                        .any(|key_image| self.spent_key_images.contains(key_image)),
+                ),
+                // Blocks are made up from the headers, with a miner transaction paying the generated
+                // coins to one output.
+                DatabaseRequest::Block(cuprate_common::BlockID::Height(height)) => {
+                    let block = get_range(&blocks, height..height + 1)?[0];
+                    let previous = match height {
+                        0 => [0; 32],
+                        _ => blocks[height as usize - 1].hash,
+                    };
+                    DatabaseResponse::Block(Box::new(dummy_block(
+                        block.header.version,
+                        block.header.vote,
+                        previous,
+                        dummy_miner_tx(
+                            2,
+                            Some(height),
+                            vec![dummy_output(Some(block.header.generated_coins))],
+                        ),
+                    )))
+                }
+                DatabaseRequest::Block(cuprate_common::BlockID::Hash(_)) => {
+                    return Err("The dummy database only gets blocks by height".into())
+                }
+                DatabaseRequest::BlockHeight(hash) => DatabaseResponse::BlockHeight(
+                    blocks
+                        .iter()
+                        .position(|block| block.hash == hash)
+                        .map(|height| height as u64),
+                ),
+                DatabaseRequest::BlockBlobsInRange { range, .. } => {
+                    DatabaseResponse::BlockBlobsInRange(
+                        get_range(&blocks, range.clone())?
+                            .iter()
+                            .zip(range)
+                            .map(|(block, height)| block_blobs(height, block))
+                            .collect(),
+                    )
+                }
+                DatabaseRequest::TxOutputIndices(txid) => DatabaseResponse::TxOutputIndices(
+                    self.tx_output_indices
+                        .get(&txid)
+                        .cloned()
+                        .ok_or("Transaction not found")?,
+                ),
+                DatabaseRequest::Outputs(outputs) => DatabaseResponse::Outputs(
+                    outputs
+                        .iter()
+                        .map(|id| self.outputs.get(id).cloned().ok_or("Output not found"))
+                        .collect::<Result<_, _>>()?,
+                ),
+                DatabaseRequest::OutputTimeLocks(outputs) => DatabaseResponse::OutputTimeLocks(
+                    outputs
+                        .iter()
+                        .map(|id| {
+                            let output = self.outputs.get(id).ok_or("Output not found")?;
+                            Ok::<_, tower::BoxError>(OutputTimeLock {
+                                height: output.height,
+                                time_lock: output.time_lock,
+                                is_coinbase: self.coinbase_outputs.contains(id),
+                            })
+                        })
+                        .collect::<Result<_, _>>()?,
+                ),
+                DatabaseRequest::NumOutputsInRange { amount, range } => {
+                    // Every block has a single RingCT output: its miner transaction's.
+                    let count = u64::from(amount == 0);
+                    DatabaseResponse::NumOutputsInRange(vec![
+                        count;
+                        get_range(&blocks, range)?.len()
+                    ])
+                }
+                DatabaseRequest::OutputDistribution {
+                    amount,
+                    from_height,
+                    to_height,
+                } => {
+                    // The running total of the counts of `NumOutputsInRange`.
+                    get_range(&blocks, from_height..to_height + 1)?;
+                    let per_block = u64::from(amount == 0);
+                    DatabaseResponse::OutputDistribution(
+                        (from_height..=to_height)
+                            .map(|height| (height + 1) * per_block)
+                            .collect(),
+                    )
+                }
+                DatabaseRequest::NumberOutputsWithAmount(amounts) => {
+                    DatabaseResponse::NumberOutputsWithAmount(
+                        amounts
+                            .into_iter()
+                            .map(|amount| (amount, blocks.len() * usize::from(amount == 0)))
+                            .collect(),
+                    )
+                }
+                DatabaseRequest::WriteBlock(_)
+                | DatabaseRequest::WriteBlocks(_)
+                | DatabaseRequest::PopBlock => return Err("The dummy database is read-only".into()),
+                DatabaseRequest::BlockBatchInRange(_) | DatabaseRequest::Transactions(_) => {
+                    return Err("The dummy database does not hold blocks or transactions".into())
+                }
+            })
+        })();
+
+        ready(res)
+    }
+}
+
+/// Returns an output of this amount to the identity key, RingCT outputs have no amount.
+pub fn dummy_output(amount: Option<u64>) -> Output {
+    Output {
+        amount,
+        key: CompressedEdwardsY([0; 32]),
+        view_tag: None,
+    }
+}
+
+/// Returns a miner transaction of this version paying these outputs.
+///
+/// With a `height` the transaction has the [`Input::Gen`] input of that height and is locked for
+/// [`MINED_MONEY_UNLOCK_WINDOW`] blocks, without one it has no inputs and isn't locked.
+pub fn dummy_miner_tx(version: u64, height: Option<u64>, outputs: Vec<Output>) -> Transaction {
+    let (inputs, timelock) = match height {
+        Some(height) => (
+            vec![Input::Gen(height)],
+            Timelock::Block((height + MINED_MONEY_UNLOCK_WINDOW) as usize),
+        ),
+        None => (vec![], Timelock::None),
+    };
+
+    Transaction {
+        prefix: TransactionPrefix {
+            version,
+            timelock,
+            inputs,
+            outputs,
+            extra: vec![],
+        },
+        signatures: vec![],
+        rct_signatures: RctSignatures {
+            base: RctBase {
+                fee: 0,
+                pseudo_outs: vec![],
+                encrypted_amounts: vec![],
+                commitments: vec![],
+            },
+            prunable: RctPrunable::Null,
+        },
+    }
+}
+
+/// Signs every input of a version 1 transaction, the real spend of each ring is its first member,
+/// the public key of the input's secret key.
+pub fn sign_v1_tx(tx: &mut Transaction, secrets: &[Scalar], rings: &[Vec<EdwardsPoint>]) {
+    for ((input, secret), ring) in tx.prefix.inputs.iter_mut().zip(secrets).zip(rings) {
+        let Input::ToKey { key_image, .. } = input else {
+            unreachable!()
+        };
+        *key_image = secret * hash_to_point(ring[0]);
+    }
+
+    let mut prefix = Vec::new();
+    tx.prefix.write(&mut prefix).unwrap();
+    let prefix_hash = hash(&prefix);
+
+    for (i, (signature, (secret, ring))) in tx
+        .signatures
+        .iter_mut()
+        .zip(secrets.iter().zip(rings))
+        .enumerate()
+    {
+        let key_image = secret * hash_to_point(ring[0]);
+        let k = Scalar::from(100 + i as u64);
+
+        let mut buf = prefix_hash.to_vec();
+        let mut sum = Scalar::ZERO;
+        signature.sigs = vec![(Scalar::ZERO, Scalar::ZERO)];
+        buf.extend_from_slice((ED25519_BASEPOINT_POINT * k).compress().as_bytes());
+        buf.extend_from_slice((k * hash_to_point(ring[0])).compress().as_bytes());
+        for (j, member) in ring.iter().enumerate().skip(1) {
+            let (c, r) = (Scalar::from(j as u64), Scalar::from(10 + j as u64));
+            let l = EdwardsPoint::vartime_double_scalar_mul_basepoint(&c, member, &r);
+            buf.extend_from_slice(l.compress().as_bytes());
+            buf.extend_from_slice(
+                (r * hash_to_point(*member) + c * key_image)
+                    .compress()
+                    .as_bytes(),
+            );
+            sum += c;
+            signature.sigs.push((c, r));
+        }
+
+        let c = hash_to_scalar(&buf) - sum;
+        signature.sigs[0] = (c, k - c * secret);
+    }
+}
+
+/// Returns a block of this version and vote on top of `previous`, with this miner transaction and
+/// no other transactions.
+pub fn dummy_block(
+    version: HardFork,
+    vote: HardFork,
+    previous: [u8; 32],
+    miner_tx: Transaction,
+) -> Block {
+    Block {
+        header: BlockHeader {
+            major_version: version as u8,
+            minor_version: vote as u8,
+            timestamp: 0,
+            previous,
+            nonce: 0,
+        },
+        miner_tx,
+        txs: vec![],
+    }
+}
+
+/// Returns the context of a mainnet chain 100 blocks long at this hard-fork, with median weights of
+/// 300,000 and the tail emission, change fields with struct update syntax.
+pub fn dummy_context(current_hf: HardFork) -> BlockChainContext {
+    BlockChainContext {
+        network: Network::Mainnet,
+        chain_height: 100,
+        top_hash: [0; 32],
+        cumulative_difficulty: 100,
+        next_difficulty: 1,
+        adjusted_time: Some(1_000),
+        current_hf,
+        already_generated_coins: u64::MAX,
+        effective_median_weight: 300_000,
+        short_term_median_weight: 300_000,
+        long_term_median_weight: 300_000,
+        next_block_weight_limit: 600_000,
+    }
+}
+
+/// Returns the information of `block` as if it was verified at this height, with no transactions
+/// and everything else zeroed.
+pub fn dummy_verified_block(block: Block, height: u64) -> VerifiedBlockInformation {
+    VerifiedBlockInformation {
+        block,
+        txs: VerifiedBlockTxs::Full(vec![]),
+        block_hash: [0; 32],
+        pow_hash: [0; 32],
+        height,
+        generated_coins: 0,
+        weight: 0,
+        long_term_weight: 0,
+        cumulative_difficulty: 0,
+    }
+}
@@ -0,0 +1,630 @@
+//! # Memory Database
+//!
+//! This module contains [`MemoryDatabase`], an in-memory database that stores the blocks written to
+//! it, so a node can be run without a database on disk, for example to run several nodes in one
+//! process in tests.
+//!
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+
+use curve25519_dalek::Scalar;
+use futures::future::{ready, Ready};
+use monero_serai::{
+    block::Block,
+    transaction::{Input, Transaction, TransactionPrefix},
+    Commitment,
+};
+
+use cuprate_common::BlockID;
+
+use crate::{
+    block::{
+        pow::BlockPOWInfo, weight::BlockWeightInfo, VerifiedBlockInformation, VerifiedBlockTxs,
+    },
+    hardforks::BlockHFInfo,
+    outputs::{BlockBlobs, OutputOnChain, OutputTimeLock, TxBlob},
+    DatabaseRequest, DatabaseResponse,
+};
+
+/// A block in the [`MemoryDatabase`].
+#[derive(Debug)]
+struct MemoryBlock {
+    info: VerifiedBlockInformation,
+    /// The coins generated up to and including this block.
+    already_generated_coins: u64,
+    /// The output indices of every transaction in the block, the miner transaction is first.
+    output_indices: Vec<Vec<u64>>,
+}
+
+/// An output in the [`MemoryDatabase`].
+#[derive(Debug)]
+struct MemoryOutput {
+    output: OutputOnChain,
+    is_coinbase: bool,
+}
+
+#[derive(Debug, Default)]
+struct MemoryChain {
+    blocks: Vec<MemoryBlock>,
+    key_images: HashSet<[u8; 32]>,
+    /// The outputs by (amount, amount index), RingCT outputs have an amount of 0.
+    outputs: HashMap<(u64, u64), MemoryOutput>,
+    /// The amount of outputs with each amount.
+    output_counts: HashMap<u64, u64>,
+}
+
+/// An in-memory database that stores the blocks written to it, clones of this database share the
+/// same chain.
+///
+/// Pruned blocks can be written but not read back unpruned, and blocks are only returned pruned if
+/// their transactions are all version 1.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDatabase {
+    chain: Arc<RwLock<MemoryChain>>,
+}
+
+impl MemoryDatabase {
+    /// Returns the amount of blocks in the chain.
+    pub fn chain_height(&self) -> u64 {
+        self.chain.read().unwrap().blocks.len() as u64
+    }
+
+    /// Returns the hash of the block at this height.
+    pub fn block_hash(&self, height: u64) -> Option<[u8; 32]> {
+        self.chain
+            .read()
+            .unwrap()
+            .blocks
+            .get(usize::try_from(height).ok()?)
+            .map(|block| block.info.block_hash)
+    }
+}
+
+/// Returns the prefixes of the block's transactions, not including the miner transaction.
+fn tx_prefixes(
+    txs: &VerifiedBlockTxs,
+) -> Result<Vec<(TransactionPrefix, Option<Transaction>)>, tower::BoxError> {
+    Ok(match txs {
+        VerifiedBlockTxs::Full(txs) => txs
+            .iter()
+            .map(|tx| (tx.prefix.clone(), Some(tx.clone())))
+            .collect(),
+        VerifiedBlockTxs::Pruned(txs) => txs
+            .iter()
+            .map(|tx| Ok((TransactionPrefix::read(&mut tx.blob.as_ref())?, None)))
+            .collect::<Result<_, std::io::Error>>()?,
+    })
+}
+
+/// Returns a commitment to the amount with a mask of 1, the commitment of miner and pre-RingCT
+/// outputs.
+fn zero_commitment(amount: u64) -> [u8; 32] {
+    Commitment::new(Scalar::ONE, amount)
+        .calculate()
+        .compress()
+        .to_bytes()
+}
+
+impl MemoryChain {
+    fn block(&self, id: &BlockID) -> Result<&MemoryBlock, tower::BoxError> {
+        match id {
+            BlockID::Height(height) => usize::try_from(*height)
+                .ok()
+                .and_then(|height| self.blocks.get(height)),
+            BlockID::Hash(hash) => self
+                .blocks
+                .iter()
+                .find(|block| &block.info.block_hash == hash),
+        }
+        .ok_or_else(|| format!("Block not found: {}", id).into())
+    }
+
+    fn range(&self, range: std::ops::Range<u64>) -> Result<&[MemoryBlock], tower::BoxError> {
+        let start = usize::try_from(range.start)?;
+        let end = usize::try_from(range.end)?;
+
+        self.blocks
+            .get(start..end)
+            .ok_or_else(|| format!("Range not in database: {:?}", range).into())
+    }
+
+    fn output(&self, id: &(u64, u64)) -> Result<&MemoryOutput, tower::BoxError> {
+        self.outputs
+            .get(id)
+            .ok_or_else(|| format!("Output not found: {:?}", id).into())
+    }
+
+    fn write_block(&mut self, info: VerifiedBlockInformation) -> Result<(), tower::BoxError> {
+        if info.height != self.blocks.len() as u64 {
+            return Err(format!(
+                "Block at height {} written to a chain of height {}",
+                info.height,
+                self.blocks.len()
+            )
+            .into());
+        }
+
+        let txs = tx_prefixes(&info.txs)?;
+        let key_images: Vec<[u8; 32]> = txs
+            .iter()
+            .flat_map(|(prefix, _)| &prefix.inputs)
+            .filter_map(|input| match input {
+                Input::ToKey { key_image, .. } => Some(key_image.compress().to_bytes()),
+                Input::Gen(_) => None,
+            })
+            .collect();
+        if key_images
+            .iter()
+            .any(|key_image| self.key_images.contains(key_image))
+        {
+            return Err("Block spends a spent key image".into());
+        }
+
+        // The outputs of each transaction with their amounts, checked before anything is written so
+        // a failed write leaves the chain as it was.
+        let miner_tx = (
+            info.block.miner_tx.prefix.clone(),
+            Some(info.block.miner_tx.clone()),
+        );
+        let mut tx_outputs = Vec::with_capacity(txs.len() + 1);
+        for (i, (prefix, tx)) in std::iter::once(miner_tx).chain(txs).enumerate() {
+            let is_coinbase = i == 0;
+            let txid = match i {
+                0 => info.block.miner_tx.hash(),
+                _ => info.block.txs[i - 1],
+            };
+
+            let mut outputs = Vec::with_capacity(prefix.outputs.len());
+            for (j, output) in prefix.outputs.iter().enumerate() {
+                let amount = output.amount.unwrap_or(0);
+                let (indexed_amount, mask) = match (prefix.version, &tx) {
+                    (1, _) => (amount, zero_commitment(amount)),
+                    (_, _) if is_coinbase => (0, zero_commitment(amount)),
+                    (_, Some(tx)) => (
+                        0,
+                        tx.rct_signatures
+                            .base
+                            .commitments
+                            .get(j)
+                            .ok_or("RingCT output without a commitment")?
+                            .compress()
+                            .to_bytes(),
+                    ),
+                    // The commitments of pruned transactions are in their blobs, which are not
+                    // parsed past the prefix.
+                    (_, None) => (0, [0; 32]),
+                };
+
+                outputs.push((
+                    indexed_amount,
+                    MemoryOutput {
+                        output: OutputOnChain {
+                            height: info.height,
+                            time_lock: prefix.timelock,
+                            key: output.key.to_bytes(),
+                            mask,
+                            txid,
+                        },
+                        is_coinbase,
+                    },
+                ));
+            }
+            tx_outputs.push(outputs);
+        }
+
+        self.key_images.extend(key_images);
+        let output_indices = tx_outputs
+            .into_iter()
+            .map(|outputs| {
+                outputs
+                    .into_iter()
+                    .map(|(amount, output)| {
+                        let count = self.output_counts.entry(amount).or_default();
+                        let index = *count;
+                        *count += 1;
+                        self.outputs.insert((amount, index), output);
+                        index
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let already_generated_coins = self
+            .blocks
+            .last()
+            .map_or(0, |block| block.already_generated_coins)
+            .saturating_add(info.generated_coins);
+        self.blocks.push(MemoryBlock {
+            info,
+            already_generated_coins,
+            output_indices,
+        });
+        Ok(())
+    }
+
+    fn pop_block(&mut self) -> Result<(Block, Vec<Transaction>), tower::BoxError> {
+        let block = self.blocks.pop().ok_or("The chain is empty")?;
+
+        let txs = tx_prefixes(&block.info.txs)?;
+        for (prefix, _) in &txs {
+            for input in &prefix.inputs {
+                if let Input::ToKey { key_image, .. } = input {
+                    self.key_images.remove(&key_image.compress().to_bytes());
+                }
+            }
+        }
+        // Outputs are indexed in order, so the block's outputs are the last of each amount.
+        self.outputs
+            .retain(|_, output| output.output.height != block.info.height);
+        for count in self.output_counts.values_mut() {
+            *count = 0;
+        }
+        for (amount, index) in self.outputs.keys() {
+            let count = self.output_counts.entry(*amount).or_default();
+            *count = (*count).max(index + 1);
+        }
+
+        let txs = txs
+            .into_iter()
+            .map(|(_, tx)| tx.ok_or("The block's transactions were pruned"))
+            .collect::<Result<_, _>>()?;
+        Ok((block.info.block, txs))
+    }
+
+    fn block_blobs(
+        &self,
+        block: &MemoryBlock,
+        pruned: bool,
+    ) -> Result<BlockBlobs, tower::BoxError> {
+        let txs = match &block.info.txs {
+            VerifiedBlockTxs::Full(txs) => txs
+                .iter()
+                .map(|tx| {
+                    if pruned && tx.prefix.version != 1 {
+                        return Err("The memory database does not prune transactions".into());
+                    }
+                    Ok(TxBlob {
+                        blob: tx.serialize().into(),
+                        prunable_hash: None,
+                    })
+                })
+                .collect::<Result<_, tower::BoxError>>()?,
+            VerifiedBlockTxs::Pruned(_) if !pruned => {
+                return Err("The block's transactions were pruned".into())
+            }
+            VerifiedBlockTxs::Pruned(txs) => txs.clone(),
+        };
+
+        Ok(BlockBlobs {
+            block: block.info.block.serialize(),
+            block_weight: block.info.weight,
+            txs,
+            output_indices: block.output_indices.clone(),
+        })
+    }
+}
+
+fn hf_info(block: &MemoryBlock) -> Result<BlockHFInfo, tower::BoxError> {
+    Ok(BlockHFInfo::from_block_header(&block.info.block.header)?)
+}
+
+fn pow_info(block: &MemoryBlock) -> BlockPOWInfo {
+    BlockPOWInfo {
+        timestamp: block.info.block.header.timestamp,
+        cumulative_difficulty: block.info.cumulative_difficulty,
+    }
+}
+
+fn weight_info(block: &MemoryBlock) -> BlockWeightInfo {
+    BlockWeightInfo {
+        block_weight: block.info.weight,
+        long_term_weight: block.info.long_term_weight,
+    }
+}
+
+impl tower::Service<DatabaseRequest> for MemoryDatabase {
+    type Response = DatabaseResponse;
+    type Error = tower::BoxError;
+    type Future = Ready<Result<DatabaseResponse, tower::BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: DatabaseRequest) -> Self::Future {
+        let res = match req {
+            DatabaseRequest::WriteBlock(block) => self
+                .chain
+                .write()
+                .unwrap()
+                .write_block(*block)
+                .map(|()| DatabaseResponse::WriteBlock),
+            DatabaseRequest::WriteBlocks(blocks) => {
+                let mut chain = self.chain.write().unwrap();
+                let height = chain.blocks.len();
+                // Like a storage transaction, a failed write leaves the chain as it was.
+                blocks
+                    .into_iter()
+                    .try_for_each(|block| chain.write_block(block))
+                    .map(|()| DatabaseResponse::WriteBlock)
+                    .inspect_err(|_| {
+                        while chain.blocks.len() > height {
+                            let _ = chain.pop_block();
+                        }
+                    })
+            }
+            DatabaseRequest::PopBlock => self
+                .chain
+                .write()
+                .unwrap()
+                .pop_block()
+                .map(|(block, txs)| DatabaseResponse::PopBlock(Box::new(block), txs)),
+            req => read(&self.chain.read().unwrap(), req),
+        };
+
+        ready(res)
+    }
+}
+
+fn read(chain: &MemoryChain, req: DatabaseRequest) -> Result<DatabaseResponse, tower::BoxError> {
+    Ok(match req {
+        DatabaseRequest::ChainHeight => DatabaseResponse::ChainHeight(chain.blocks.len() as u64),
+        DatabaseRequest::BlockHFInfo(id) => {
+            DatabaseResponse::BlockHFInfo(hf_info(chain.block(&id)?)?)
+        }
+        DatabaseRequest::BlockPOWInfo(id) => {
+            DatabaseResponse::BlockPOWInfo(pow_info(chain.block(&id)?))
+        }
+        DatabaseRequest::BlockWeights(id) => {
+            DatabaseResponse::BlockWeights(weight_info(chain.block(&id)?))
+        }
+        DatabaseRequest::BlockHash(height) => {
+            DatabaseResponse::BlockHash(chain.block(&height.into())?.info.block_hash)
+        }
+        DatabaseRequest::GeneratedCoins(height) => {
+            DatabaseResponse::GeneratedCoins(chain.block(&height.into())?.already_generated_coins)
+        }
+        DatabaseRequest::CumulativeDifficulty(height) => DatabaseResponse::CumulativeDifficulty(
+            chain.block(&height.into())?.info.cumulative_difficulty,
+        ),
+        DatabaseRequest::BlockHfInfoInRange(range) => DatabaseResponse::BlockHfInfoInRange(
+            chain
+                .range(range)?
+                .iter()
+                .map(hf_info)
+                .collect::<Result<_, _>>()?,
+        ),
+        DatabaseRequest::BlockWeightsInRange(range) => DatabaseResponse::BlockWeightsInRange(
+            chain.range(range)?.iter().map(weight_info).collect(),
+        ),
+        DatabaseRequest::BlockPOWInfoInRange(range) => DatabaseResponse::BlockPOWInfoInRange(
+            chain.range(range)?.iter().map(pow_info).collect(),
+        ),
+        DatabaseRequest::KeyImagesSpent(key_images) => DatabaseResponse::KeyImagesSpent(
+            key_images
+                .iter()
+                .any(|key_image| chain.key_images.contains(key_image)),
+        ),
+        DatabaseRequest::Block(id) => {
+            DatabaseResponse::Block(Box::new(chain.block(&id)?.info.block.clone()))
+        }
+        DatabaseRequest::BlockHeight(hash) => DatabaseResponse::BlockHeight(
+            chain
+                .blocks
+                .iter()
+                .position(|block| block.info.block_hash == hash)
+                .map(|height| height as u64),
+        ),
+        DatabaseRequest::BlockBlobsInRange { range, pruned } => {
+            DatabaseResponse::BlockBlobsInRange(
+                chain
+                    .range(range)?
+                    .iter()
+                    .map(|block| chain.block_blobs(block, pruned))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        DatabaseRequest::TxOutputIndices(txid) => DatabaseResponse::TxOutputIndices(
+            chain
+                .blocks
+                .iter()
+                .find_map(|block| {
+                    std::iter::once(block.info.block.miner_tx.hash())
+                        .chain(block.info.block.txs.iter().copied())
+                        .position(|hash| hash == txid)
+                        .map(|i| block.output_indices[i].clone())
+                })
+                .ok_or("Transaction not found")?,
+        ),
+        DatabaseRequest::Outputs(outputs) => DatabaseResponse::Outputs(
+            outputs
+                .iter()
+                .map(|id| Ok(chain.output(id)?.output.clone()))
+                .collect::<Result<_, tower::BoxError>>()?,
+        ),
+        DatabaseRequest::OutputTimeLocks(outputs) => DatabaseResponse::OutputTimeLocks(
+            outputs
+                .iter()
+                .map(|id| {
+                    let output = chain.output(id)?;
+                    Ok(OutputTimeLock {
+                        height: output.output.height,
+                        time_lock: output.output.time_lock,
+                        is_coinbase: output.is_coinbase,
+                    })
+                })
+                .collect::<Result<_, tower::BoxError>>()?,
+        ),
+        DatabaseRequest::NumOutputsInRange { amount, range } => {
+            let blocks = chain.range(range.clone())?;
+            DatabaseResponse::NumOutputsInRange(
+                range
+                    .take(blocks.len())
+                    .map(|height| {
+                        chain
+                            .outputs
+                            .iter()
+                            .filter(|((a, _), output)| {
+                                *a == amount && output.output.height == height
+                            })
+                            .count() as u64
+                    })
+                    .collect(),
+            )
+        }
+        DatabaseRequest::OutputDistribution {
+            amount,
+            from_height,
+            to_height,
+        } => {
+            chain.range(from_height..to_height + 1)?;
+            DatabaseResponse::OutputDistribution(
+                (from_height..=to_height)
+                    .map(|height| {
+                        chain
+                            .outputs
+                            .iter()
+                            .filter(|((a, _), output)| {
+                                *a == amount && output.output.height <= height
+                            })
+                            .count() as u64
+                    })
+                    .collect(),
+            )
+        }
+        DatabaseRequest::NumberOutputsWithAmount(amounts) => {
+            DatabaseResponse::NumberOutputsWithAmount(
+                amounts
+                    .into_iter()
+                    .map(|amount| {
+                        let count = chain.output_counts.get(&amount).copied().unwrap_or(0);
+                        (amount, count as usize)
+                    })
+                    .collect(),
+            )
+        }
+        DatabaseRequest::BlockBatchInRange(range) => DatabaseResponse::BlockBatchInRange(
+            chain
+                .range(range)?
+                .iter()
+                .map(|block| block.info.block.clone())
+                .collect(),
+        ),
+        DatabaseRequest::Transactions(hashes) => DatabaseResponse::Transactions(
+            hashes
+                .iter()
+                .map(|hash| {
+                    chain
+                        .blocks
+                        .iter()
+                        .find_map(|block| {
+                            let i = block.info.block.txs.iter().position(|tx| tx == hash)?;
+                            match &block.info.txs {
+                                VerifiedBlockTxs::Full(txs) => Some(txs[i].clone()),
+                                VerifiedBlockTxs::Pruned(_) => None,
+                            }
+                        })
+                        .ok_or_else(|| "Transaction not found".into())
+                })
+                .collect::<Result<_, tower::BoxError>>()?,
+        ),
+        DatabaseRequest::WriteBlock(_)
+        | DatabaseRequest::WriteBlocks(_)
+        | DatabaseRequest::PopBlock => unreachable!("Writes are handled before reads"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        hardforks::HardFork,
+        test_utils::{dummy_block, dummy_miner_tx, dummy_output, dummy_verified_block},
+    };
+
+    /// Returns the verified information of a block at this height with a miner output.
+    fn block(height: u64, previous: [u8; 32]) -> VerifiedBlockInformation {
+        let block = dummy_block(
+            HardFork::V1,
+            HardFork::V1,
+            previous,
+            dummy_miner_tx(1, Some(height), vec![dummy_output(Some(10))]),
+        );
+        let mut info = dummy_verified_block(block, height);
+        info.block_hash = info.block.hash();
+        info.generated_coins = 10;
+        info
+    }
+
+    fn call(database: &MemoryDatabase, req: DatabaseRequest) -> DatabaseResponse {
+        block_on(database.clone().oneshot(req)).unwrap()
+    }
+
+    #[test]
+    fn written_blocks_are_read_back_and_popped() {
+        let database = MemoryDatabase::default();
+        let first = block(0, [0; 32]);
+        let second = block(1, first.block_hash);
+        let second_hash = second.block_hash;
+
+        call(&database, DatabaseRequest::WriteBlocks(vec![first, second]));
+        assert_eq!(database.chain_height(), 2);
+        assert_eq!(
+            call(&database, DatabaseRequest::BlockHeight(second_hash))
+                .into_block_height()
+                .unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            call(&database, DatabaseRequest::GeneratedCoins(1))
+                .into_generated_coins()
+                .unwrap(),
+            20
+        );
+        assert_eq!(
+            call(
+                &database,
+                DatabaseRequest::NumberOutputsWithAmount(vec![10])
+            )
+            .into_number_outputs_with_amount()
+            .unwrap()[&10],
+            2
+        );
+
+        let DatabaseResponse::PopBlock(popped, _) = call(&database, DatabaseRequest::PopBlock)
+        else {
+            panic!("Database sent incorrect response!");
+        };
+        assert_eq!(popped.hash(), second_hash);
+        assert_eq!(database.chain_height(), 1);
+        assert_eq!(
+            call(
+                &database,
+                DatabaseRequest::NumberOutputsWithAmount(vec![10])
+            )
+            .into_number_outputs_with_amount()
+            .unwrap()[&10],
+            1
+        );
+    }
+
+    #[test]
+    fn failed_batches_are_not_written() {
+        let database = MemoryDatabase::default();
+        let first = block(0, [0; 32]);
+        // The second block is for the wrong height.
+        let second = block(5, first.block_hash);
+
+        let res = block_on(
+            database
+                .clone()
+                .oneshot(DatabaseRequest::WriteBlocks(vec![first, second])),
+        );
+        assert!(res.is_err());
+        assert_eq!(database.chain_height(), 0);
+    }
+}
@@ -0,0 +1,604 @@
+//! # Test Vectors
+//!
+//! This module contains [`TestVector`], the data of the mainnet blocks around a height where the
+//! consensus rules change, and [`TestVector::replay`], which checks the blocks against
+//! [`HardForkState`] and [`BlockWeightsCache`] so a refactor can be checked against the real chain.
+//!
+//! Vectors are recorded from a node with [`TestVector::record`], the `record_test_vectors` binary
+//! records every height in [`MAINNET_EDGE_CASES`] into `test_vectors/`. A vector holds the blocks of
+//! the long term weight window before its height, so the caches can be initialized like they would
+//! be on the real chain, and the replayed blocks after it.
+//!
+//! Vectors are stored as text, a header of `key: value` lines and then a line for each block:
+//! `<version> <vote> <block weight> <long term weight>`, replayed blocks also have the coins they
+//! generated and their blob in hex. A replayed block's blob is checked against the other fields of
+//! its line, so the numbers replayed are the ones of the real block.
+//!
+use std::fmt::Write;
+use std::pin::pin;
+use std::task::{Context, Poll};
+
+use futures::future::{ready, Ready};
+use futures::TryStreamExt;
+use monero_serai::{block::Block, transaction::Input};
+use tower::ServiceExt;
+
+use cuprate_common::BlockID;
+
+use crate::{
+    block::{
+        reward::calculate_base_reward,
+        weight::{BlockWeightInfo, BlockWeightsCache, LONG_TERM_WINDOW},
+    },
+    hardforks::{BlockHFInfo, HardForkConfig, HardForkState},
+    miner_tx::calculate_block_reward,
+    range_chunk_responses, ConsensusError, Database, DatabaseRequest, DatabaseResponse,
+};
+
+/// The mainnet heights where the rules change, with the name of their vector.
+pub const MAINNET_EDGE_CASES: [(&str, u64); 6] = [
+    ("v2_fork", 1009827),
+    ("v8_fork", 1685555),
+    ("v9_fork", 1686275),
+    // Long term weights are used from v10.
+    ("v10_fork", 1788000),
+    ("v15_fork", 2688888),
+    ("v16_fork", 2689608),
+];
+
+/// The default amount of blocks replayed from a vector's height.
+pub const DEFAULT_REPLAYED_BLOCKS: u64 = 20;
+
+/// The blocks replayed before the vector's height, so the last blocks of the old rules are checked.
+const BLOCKS_BEFORE_HEIGHT: u64 = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TestVectorError {
+    #[error("Line {line} of the vector is invalid: {reason}")]
+    Parse { line: usize, reason: String },
+    #[error("Block {height}'s {check} is {got}, expected {expected}")]
+    Mismatch {
+        height: u64,
+        check: &'static str,
+        expected: String,
+        got: String,
+    },
+    #[error("{0}")]
+    Consensus(#[from] ConsensusError),
+}
+
+/// A block in a [`TestVector`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VectorBlock {
+    pub hf_info: BlockHFInfo,
+    pub weights: BlockWeightInfo,
+    /// The coins generated by the block, only set for replayed blocks.
+    pub generated_coins: Option<u64>,
+    /// The serialized block, only set for replayed blocks.
+    pub blob: Option<Vec<u8>>,
+}
+
+/// The blocks around a height of the chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestVector {
+    pub name: String,
+    /// The height of the first block.
+    pub start_height: u64,
+    /// The height of the first replayed block, the blocks before it initialize the caches.
+    pub replay_height: u64,
+    /// The coins generated up to and including the block before `replay_height`.
+    pub already_generated_coins: u64,
+    pub blocks: Vec<VectorBlock>,
+}
+
+impl TestVector {
+    /// Records the vector of the blocks around `height` from the database, replaying
+    /// `replayed_blocks` blocks.
+    pub async fn record<D: Database + Clone>(
+        name: &str,
+        height: u64,
+        replayed_blocks: u64,
+        database: D,
+    ) -> Result<TestVector, ConsensusError> {
+        let replay_height = height.saturating_sub(BLOCKS_BEFORE_HEIGHT).max(1);
+        let start_height = replay_height.saturating_sub(LONG_TERM_WINDOW);
+        let end_height = height + replayed_blocks;
+
+        let mut hf_infos = Vec::new();
+        let mut responses = pin!(range_chunk_responses(
+            database.clone(),
+            start_height..end_height,
+            DatabaseRequest::BlockHfInfoInRange
+        ));
+        while let Some(response) = responses.try_next().await? {
+            hf_infos.extend(response.into_block_hf_info_in_range()?);
+        }
+
+        let mut weights = Vec::new();
+        let mut responses = pin!(range_chunk_responses(
+            database.clone(),
+            start_height..end_height,
+            DatabaseRequest::BlockWeightsInRange
+        ));
+        while let Some(response) = responses.try_next().await? {
+            weights.extend(response.into_block_weights_in_range()?);
+        }
+
+        let mut total_generated_coins = Vec::new();
+        for height in replay_height - 1..end_height {
+            total_generated_coins.push(
+                database
+                    .clone()
+                    .oneshot(DatabaseRequest::GeneratedCoins(height))
+                    .await?
+                    .into_generated_coins()?,
+            );
+        }
+
+        let mut blobs = Vec::new();
+        for height in replay_height..end_height {
+            blobs.push(
+                database
+                    .clone()
+                    .oneshot(DatabaseRequest::Block(BlockID::Height(height)))
+                    .await?
+                    .into_block()?
+                    .serialize(),
+            );
+        }
+
+        let blocks = hf_infos
+            .into_iter()
+            .zip(weights)
+            .enumerate()
+            .map(|(i, (hf_info, weights))| {
+                let height = start_height + i as u64;
+                VectorBlock {
+                    hf_info,
+                    weights,
+                    generated_coins: height.checked_sub(replay_height).map(|j| {
+                        total_generated_coins[j as usize + 1] - total_generated_coins[j as usize]
+                    }),
+                    blob: height
+                        .checked_sub(replay_height)
+                        .map(|j| blobs[j as usize].clone()),
+                }
+            })
+            .collect();
+
+        Ok(TestVector {
+            name: name.to_string(),
+            start_height,
+            replay_height,
+            already_generated_coins: total_generated_coins[0],
+            blocks,
+        })
+    }
+
+    /// Replays the vector's blocks, returning the first block whose version, vote, long term weight
+    /// or reward doesn't match what the rules give, or whose blob doesn't match its other fields.
+    pub async fn replay(&self) -> Result<(), TestVectorError> {
+        let database = VectorDatabase {
+            start_height: self.start_height,
+            blocks: self.blocks[..(self.replay_height - self.start_height) as usize].to_vec(),
+        };
+
+        let mut hard_fork = HardForkState::init_from_chain_height(
+            HardForkConfig::main_net(),
+            self.replay_height,
+            database.clone(),
+        )
+        .await?;
+        let mut weights =
+            BlockWeightsCache::init_from_chain_height(self.replay_height, database.clone()).await?;
+        let mut already_generated_coins = self.already_generated_coins;
+
+        let mut database = VectorDatabase {
+            blocks: self.blocks.clone(),
+            ..database
+        };
+        for (height, block) in (self.start_height..).zip(&self.blocks) {
+            if height < self.replay_height {
+                continue;
+            }
+            check_blob(height, block)?;
+
+            hard_fork
+                .check_block_version_vote(&block.hf_info)
+                .map_err(ConsensusError::from)?;
+            let hf = hard_fork.current_hardfork();
+
+            check(
+                height,
+                "long term weight",
+                weights.next_block_long_term_weight(&hf, block.weights.block_weight),
+                block.weights.long_term_weight,
+            )?;
+
+            let reward = calculate_block_reward(
+                calculate_base_reward(already_generated_coins, &hf),
+                weights.effective_median_block_weight(&hf),
+                block.weights.block_weight,
+            );
+            let generated_coins = block.generated_coins.unwrap_or_default();
+            check(height, "reward", reward, Some(generated_coins))?;
+            already_generated_coins += generated_coins;
+
+            hard_fork.new_block(block.hf_info.vote_version(), height);
+            weights
+                .new_block_added(
+                    height,
+                    block.weights.block_weight,
+                    block.weights.long_term_weight,
+                    &mut database,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Parses a vector from its text form, see the [module docs](self).
+    pub fn parse(text: &str) -> Result<TestVector, TestVectorError> {
+        let mut lines = text.lines().enumerate();
+        let mut header = |key: &str| {
+            // A missing line is reported as the line after the last.
+            let (line, text) = lines.next().unwrap_or((text.lines().count(), ""));
+            text.strip_prefix(key)
+                .and_then(|value| value.strip_prefix(": "))
+                .ok_or_else(|| parse_error(line, format!("expected {key}")))
+                .map(|value| (line, value))
+        };
+
+        let name = header("name")?.1.to_string();
+        let (line, start_height) = header("start_height")?;
+        let start_height = parse_number(line, start_height)?;
+        let (line, replay_height) = header("replay_height")?;
+        let replay_height = parse_number(line, replay_height)?;
+        let (line, already_generated_coins) = header("already_generated_coins")?;
+        let already_generated_coins = parse_number(line, already_generated_coins)?;
+
+        if replay_height <= start_height {
+            return Err(parse_error(
+                line,
+                "the replay height is not above the start height".to_string(),
+            ));
+        }
+
+        let mut blocks = Vec::new();
+        for (line, text) in lines {
+            let replayed = start_height + blocks.len() as u64 >= replay_height;
+            let mut fields: Vec<&str> = text.split(' ').collect();
+            let blob = match replayed {
+                true => Some(
+                    fields
+                        .pop()
+                        .and_then(|blob| hex::decode(blob).ok())
+                        .ok_or_else(|| parse_error(line, "invalid blob".to_string()))?,
+                ),
+                false => None,
+            };
+            let fields = fields
+                .into_iter()
+                .map(|field| parse_number(line, field))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let (&[version, vote, block_weight, long_term_weight], generated_coins) =
+                fields.split_at(4.min(fields.len()))
+            else {
+                return Err(parse_error(line, "missing fields".to_string()));
+            };
+            let generated_coins = match generated_coins {
+                [] if !replayed => None,
+                [generated_coins] if replayed => Some(*generated_coins),
+                _ => return Err(parse_error(line, "wrong amount of fields".to_string())),
+            };
+
+            blocks.push(VectorBlock {
+                hf_info: BlockHFInfo::from_major_minor(
+                    u8::try_from(version).unwrap_or(u8::MAX),
+                    u8::try_from(vote).unwrap_or(u8::MAX),
+                )
+                .map_err(|e| parse_error(line, e.to_string()))?,
+                weights: BlockWeightInfo {
+                    block_weight: block_weight as usize,
+                    long_term_weight: long_term_weight as usize,
+                },
+                generated_coins,
+                blob,
+            });
+        }
+
+        if start_height + (blocks.len() as u64) <= replay_height {
+            return Err(parse_error(
+                text.lines().count(),
+                "no blocks are replayed".to_string(),
+            ));
+        }
+
+        Ok(TestVector {
+            name,
+            start_height,
+            replay_height,
+            already_generated_coins,
+            blocks,
+        })
+    }
+
+    /// Returns the vector's text form, see the [module docs](self).
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "name: {}\nstart_height: {}\nreplay_height: {}\nalready_generated_coins: {}\n",
+            self.name, self.start_height, self.replay_height, self.already_generated_coins
+        );
+
+        for block in &self.blocks {
+            write!(
+                text,
+                "{} {} {} {}",
+                block.hf_info.version as u8,
+                block.hf_info.vote as u8,
+                block.weights.block_weight,
+                block.weights.long_term_weight
+            )
+            .unwrap();
+            if let Some(generated_coins) = block.generated_coins {
+                write!(text, " {generated_coins}").unwrap();
+            }
+            if let Some(blob) = &block.blob {
+                write!(text, " {}", hex::encode(blob)).unwrap();
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Checks a replayed block's blob is the block of its line: a block with its version and vote whose
+/// miner transaction is for its height and pays at least the coins it generated, the rest being
+/// the fees.
+fn check_blob(height: u64, block: &VectorBlock) -> Result<(), TestVectorError> {
+    let parsed = block
+        .blob
+        .as_deref()
+        .and_then(|mut blob| Block::read(&mut blob).ok())
+        .ok_or_else(|| TestVectorError::Mismatch {
+            height,
+            check: "blob",
+            expected: "a block".to_string(),
+            got: "an invalid blob".to_string(),
+        })?;
+
+    let hf_info =
+        BlockHFInfo::from_major_minor(parsed.header.major_version, parsed.header.minor_version)
+            .map_err(ConsensusError::from)?;
+    check(
+        height,
+        "blob's version",
+        hf_info.version,
+        block.hf_info.version,
+    )?;
+    check(height, "blob's vote", hf_info.vote, block.hf_info.vote)?;
+    check(
+        height,
+        "miner transaction's input",
+        parsed.miner_tx.prefix.inputs.as_slice(),
+        [Input::Gen(height)].as_slice(),
+    )?;
+
+    let paid: u128 = parsed
+        .miner_tx
+        .prefix
+        .outputs
+        .iter()
+        .map(|output| u128::from(output.amount.unwrap_or(0)))
+        .sum();
+    let generated_coins = u128::from(block.generated_coins.unwrap_or_default());
+    if paid < generated_coins {
+        return Err(TestVectorError::Mismatch {
+            height,
+            check: "miner transaction's outputs",
+            expected: format!("at least {generated_coins}"),
+            got: paid.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn check<T: PartialEq + std::fmt::Debug>(
+    height: u64,
+    check: &'static str,
+    got: T,
+    expected: T,
+) -> Result<(), TestVectorError> {
+    if got == expected {
+        return Ok(());
+    }
+
+    Err(TestVectorError::Mismatch {
+        height,
+        check,
+        expected: format!("{expected:?}"),
+        got: format!("{got:?}"),
+    })
+}
+
+fn parse_number(line: usize, number: &str) -> Result<u64, TestVectorError> {
+    number
+        .parse()
+        .map_err(|_| parse_error(line, format!("{number} is not a number")))
+}
+
+fn parse_error(line: usize, reason: String) -> TestVectorError {
+    TestVectorError::Parse {
+        line: line + 1,
+        reason,
+    }
+}
+
+/// A database of a vector's blocks, which answers the requests the caches make on init and when a
+/// block is added.
+#[derive(Debug, Clone)]
+struct VectorDatabase {
+    start_height: u64,
+    blocks: Vec<VectorBlock>,
+}
+
+impl VectorDatabase {
+    fn range(&self, range: std::ops::Range<u64>) -> Result<&[VectorBlock], tower::BoxError> {
+        range
+            .start
+            .checked_sub(self.start_height)
+            .zip(range.end.checked_sub(self.start_height))
+            .and_then(|(start, end)| self.blocks.get(start as usize..end as usize))
+            .ok_or_else(|| format!("Range not in the vector: {:?}", range).into())
+    }
+}
+
+impl tower::Service<DatabaseRequest> for VectorDatabase {
+    type Response = DatabaseResponse;
+    type Error = tower::BoxError;
+    type Future = Ready<Result<DatabaseResponse, tower::BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: DatabaseRequest) -> Self::Future {
+        let height = |id: &cuprate_common::BlockID| match id {
+            cuprate_common::BlockID::Height(height) => Ok(*height),
+            cuprate_common::BlockID::Hash(_) => Err("Vectors don't have block hashes"),
+        };
+
+        ready((|| {
+            Ok(match req {
+                DatabaseRequest::ChainHeight => {
+                    DatabaseResponse::ChainHeight(self.start_height + self.blocks.len() as u64)
+                }
+                DatabaseRequest::BlockHFInfo(id) => {
+                    let height = height(&id)?;
+                    DatabaseResponse::BlockHFInfo(self.range(height..height + 1)?[0].hf_info)
+                }
+                DatabaseRequest::BlockWeights(id) => {
+                    let height = height(&id)?;
+                    DatabaseResponse::BlockWeights(self.range(height..height + 1)?[0].weights)
+                }
+                DatabaseRequest::BlockHfInfoInRange(range) => DatabaseResponse::BlockHfInfoInRange(
+                    self.range(range)?
+                        .iter()
+                        .map(|block| block.hf_info)
+                        .collect(),
+                ),
+                DatabaseRequest::BlockWeightsInRange(range) => {
+                    DatabaseResponse::BlockWeightsInRange(
+                        self.range(range)?
+                            .iter()
+                            .map(|block| block.weights)
+                            .collect(),
+                    )
+                }
+                req => return Err(format!("{req:?} is not in test vectors").into()),
+            })
+        })())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::hardforks::HardFork;
+    use crate::test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder};
+
+    const WEIGHT: usize = 300_000;
+
+    /// Returns a vector of v16 blocks that generated the reward the rules give.
+    fn vector() -> TestVector {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                (LONG_TERM_WINDOW + 30) as usize,
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::V16, HardFork::V16)
+                    .with_weight(WEIGHT, WEIGHT)
+                    // Tail emission.
+                    .with_generated_coins(6 * 10_u64.pow(11)),
+            )
+            .finish();
+
+        let mut vector = futures::executor::block_on(TestVector::record(
+            "test",
+            LONG_TERM_WINDOW + 20,
+            DEFAULT_REPLAYED_BLOCKS / 2,
+            database,
+        ))
+        .unwrap();
+        // The dummy chain is far from the tail emission, start at it.
+        vector.already_generated_coins = u64::MAX - 10_u64.pow(15);
+        vector
+    }
+
+    #[test]
+    fn vectors_round_trip() {
+        let vector = vector();
+        assert_eq!(vector.start_height, 10);
+        assert_eq!(vector.replay_height, LONG_TERM_WINDOW + 10);
+        assert_eq!(vector.blocks.len() as u64, LONG_TERM_WINDOW + 20);
+
+        assert_eq!(TestVector::parse(&vector.to_text()).unwrap(), vector);
+        assert!(matches!(
+            TestVector::parse("name: test\nstart_height: 1"),
+            Err(TestVectorError::Parse { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn replay_finds_mismatches() {
+        let mut vector = vector();
+        futures::executor::block_on(vector.replay()).unwrap();
+
+        let height = vector.replay_height + 5;
+        vector.blocks[(height - vector.start_height) as usize]
+            .weights
+            .long_term_weight = WEIGHT + 1;
+        match futures::executor::block_on(vector.replay()) {
+            Err(TestVectorError::Mismatch {
+                height: got_height,
+                check: "long term weight",
+                ..
+            }) => assert_eq!(got_height, height),
+            res => panic!("Replay didn't find the mismatch: {res:?}"),
+        }
+    }
+
+    #[test]
+    fn replay_checks_the_blobs() {
+        let mut vector = vector();
+        let index = (vector.replay_height - vector.start_height) as usize + 3;
+
+        let mut block = Block::read(&mut vector.blocks[index].blob.as_deref().unwrap()).unwrap();
+        block.miner_tx.prefix.outputs[0].amount = Some(1);
+        vector.blocks[index].blob = Some(block.serialize());
+
+        assert!(matches!(
+            futures::executor::block_on(vector.replay()),
+            Err(TestVectorError::Mismatch {
+                check: "miner transaction's outputs",
+                ..
+            })
+        ));
+    }
+
+    /// Replays the vectors recorded from mainnet with the `record_test_vectors` binary.
+    #[test]
+    #[ignore = "needs the vectors recorded with the record_test_vectors binary"]
+    fn replay_mainnet_vectors() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test_vectors");
+        for (name, _) in MAINNET_EDGE_CASES {
+            let text = std::fs::read_to_string(dir.join(format!("{name}.txt"))).unwrap();
+            let vector = TestVector::parse(&text).unwrap();
+            if let Err(e) = futures::executor::block_on(vector.replay()) {
+                panic!("Vector {name} failed: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! # Timings
+//!
+//! This module contains types to time each stage of block verification, so slow stages can be found
+//! when users report slow syncs.
+//!
+//! Each block's stage timings are recorded in a [`BlockTimings`], these are added to a [`BatchTimings`]
+//! which emits a single tracing event for the whole batch when finished. [`StageHistograms`] keeps
+//! histograms of the per-block timings across all batches.
+//!
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+use crate::spans::BLOCK_TARGET;
+
+/// A stage of block verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum VerificationStage {
+    Pow,
+    Signatures,
+    RangeProofs,
+    ContextUpdate,
+    DatabaseWrite,
+}
+
+impl VerificationStage {
+    /// Every stage, in the order they happen.
+    pub const ALL: [VerificationStage; 5] = [
+        VerificationStage::Pow,
+        VerificationStage::Signatures,
+        VerificationStage::RangeProofs,
+        VerificationStage::ContextUpdate,
+        VerificationStage::DatabaseWrite,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VerificationStage::Pow => "pow",
+            VerificationStage::Signatures => "signatures",
+            VerificationStage::RangeProofs => "range_proofs",
+            VerificationStage::ContextUpdate => "context_update",
+            VerificationStage::DatabaseWrite => "database_write",
+        }
+    }
+}
+
+/// The time spent in each stage verifying a single block.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BlockTimings {
+    durations: [Duration; VerificationStage::ALL.len()],
+}
+
+impl BlockTimings {
+    /// Adds time spent in a stage.
+    pub fn record(&mut self, stage: VerificationStage, duration: Duration) {
+        self.durations[stage as usize] += duration;
+    }
+
+    /// Runs `f`, recording the time it took to the stage.
+    pub fn time<T>(&mut self, stage: VerificationStage, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let ret = f();
+        self.record(stage, start.elapsed());
+        ret
+    }
+
+    /// Returns the time spent in a stage.
+    pub fn stage(&self, stage: VerificationStage) -> Duration {
+        self.durations[stage as usize]
+    }
+
+    /// Returns the total time spent verifying the block.
+    pub fn total(&self) -> Duration {
+        self.durations.iter().sum()
+    }
+}
+
+impl Display for BlockTimings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("Timings");
+        for stage in VerificationStage::ALL {
+            debug.field(stage.name(), &self.stage(stage));
+        }
+        debug.field("total", &self.total()).finish()
+    }
+}
+
+/// The timings of a batch of blocks.
+#[derive(Debug)]
+pub struct BatchTimings {
+    /// The height of the first block in the batch.
+    start_height: u64,
+    /// The amount of blocks in the batch.
+    blocks: u64,
+    /// The total time spent in each stage.
+    totals: BlockTimings,
+    /// When the batch was started.
+    started: Instant,
+}
+
+impl BatchTimings {
+    pub fn new(start_height: u64) -> BatchTimings {
+        BatchTimings {
+            start_height,
+            blocks: 0,
+            totals: BlockTimings::default(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Adds a block's timings to the batch.
+    pub fn add_block(&mut self, height: u64, timings: &BlockTimings) {
+        tracing::debug!(
+            target: BLOCK_TARGET,
+            height,
+            pow = ?timings.stage(VerificationStage::Pow),
+            signatures = ?timings.stage(VerificationStage::Signatures),
+            range_proofs = ?timings.stage(VerificationStage::RangeProofs),
+            context_update = ?timings.stage(VerificationStage::ContextUpdate),
+            database_write = ?timings.stage(VerificationStage::DatabaseWrite),
+            total = ?timings.total(),
+            "Verified block"
+        );
+
+        self.blocks += 1;
+        for stage in VerificationStage::ALL {
+            self.totals.record(stage, timings.stage(stage));
+        }
+    }
+
+    /// Returns the total time spent in each stage for this batch.
+    pub fn totals(&self) -> &BlockTimings {
+        &self.totals
+    }
+
+    /// Emits the batch's timings as a tracing event.
+    pub fn finish(self) {
+        let elapsed = self.started.elapsed();
+        let blocks_per_sec = self.blocks as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+
+        tracing::info!(target: BLOCK_TARGET,
+            height = self.start_height,
+            batch_size = self.blocks,
+            pow = ?self.totals.stage(VerificationStage::Pow),
+            signatures = ?self.totals.stage(VerificationStage::Signatures),
+            range_proofs = ?self.totals.stage(VerificationStage::RangeProofs),
+            context_update = ?self.totals.stage(VerificationStage::ContextUpdate),
+            database_write = ?self.totals.stage(VerificationStage::DatabaseWrite),
+            "Verified batch in {:?}, {:.2} blocks/s",
+            elapsed,
+            blocks_per_sec
+        );
+    }
+}
+
+/// The upper bounds of the histogram buckets, the last bucket holds everything above the last bound.
+const HISTOGRAM_BUCKETS: [Duration; 7] = [
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+];
+
+/// A histogram of durations.
+#[derive(Debug, Default, Clone)]
+pub struct Histogram {
+    counts: [u64; HISTOGRAM_BUCKETS.len() + 1],
+    sum: Duration,
+}
+
+impl Histogram {
+    pub fn record(&mut self, duration: Duration) {
+        let bucket = HISTOGRAM_BUCKETS
+            .iter()
+            .position(|bound| duration <= *bound)
+            .unwrap_or(HISTOGRAM_BUCKETS.len());
+
+        self.counts[bucket] += 1;
+        self.sum += duration;
+    }
+
+    /// Returns the amount of durations recorded.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the sum of all the durations recorded.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Returns the buckets as (upper bound, count) pairs, the last bucket has no upper bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        HISTOGRAM_BUCKETS
+            .iter()
+            .copied()
+            .map(Some)
+            .chain([None])
+            .zip(self.counts.iter().copied())
+    }
+}
+
+/// Histograms of the time blocks spend in each stage.
+#[derive(Debug, Default, Clone)]
+pub struct StageHistograms {
+    stages: [Histogram; VerificationStage::ALL.len()],
+}
+
+impl StageHistograms {
+    /// Adds a block's timings to the histograms.
+    pub fn record_block(&mut self, timings: &BlockTimings) {
+        for stage in VerificationStage::ALL {
+            self.stages[stage as usize].record(timings.stage(stage));
+        }
+    }
+
+    /// Returns the histogram for a stage.
+    pub fn stage(&self, stage: VerificationStage) -> &Histogram {
+        &self.stages[stage as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{BlockTimings, Histogram, VerificationStage};
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = Histogram::default();
+        histogram.record(Duration::from_micros(5));
+        histogram.record(Duration::from_millis(5));
+        histogram.record(Duration::from_secs(100));
+
+        let buckets: Vec<_> = histogram.buckets().collect();
+        assert_eq!(buckets[0], (Some(Duration::from_micros(10)), 1));
+        assert_eq!(buckets[3], (Some(Duration::from_millis(10)), 1));
+        assert_eq!(buckets[7], (None, 1));
+        assert_eq!(histogram.count(), 3);
+    }
+
+    #[test]
+    fn block_timings_total() {
+        let mut timings = BlockTimings::default();
+        timings.record(VerificationStage::Pow, Duration::from_millis(2));
+        timings.record(VerificationStage::Pow, Duration::from_millis(3));
+        timings.record(VerificationStage::DatabaseWrite, Duration::from_millis(1));
+
+        assert_eq!(
+            timings.stage(VerificationStage::Pow),
+            Duration::from_millis(5)
+        );
+        assert_eq!(timings.total(), Duration::from_millis(6));
+    }
+}
@@ -0,0 +1,806 @@
+//! # Transactions
+//!
+//! This module contains the checks of a transaction's inputs against the chain.
+//!
+//! [`check_ring_members_unlocked`] checks every ring member of a transaction can be spent in the next
+//! block: outputs of miner transactions must have matured for [`MINED_MONEY_UNLOCK_WINDOW`] blocks
+//! and the time-lock of every output must have passed.
+//!
+//! Time based locks are checked against the adjusted median time of the chain from
+//! [`HardFork::V13`], so every node agrees on them, before that monerod used its local clock.
+//!
+//! Syncing from genesis means verifying the pre-RingCT (version 1) transactions of the first ~1.2M
+//! blocks, [`check_v1_ring_signatures`] checks their ring signatures and [`v1_fee`] that their
+//! inputs cover their outputs, the difference being the fee. The RingCT proofs of version 2
+//! transactions are checked with [`check_rct_signatures`]. [`verify_tx_signatures`] reads the ring
+//! members of every transaction of a block from the database in one request. The signatures
+//! themselves can't be batched, the challenge of each ring is a hash of its own points, so each
+//! transaction is checked on the [`VerificationPool`] and the transactions of a block are checked
+//! on every core at once.
+//!
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use curve25519_dalek::{edwards::CompressedEdwardsY, traits::IsIdentity, EdwardsPoint, Scalar};
+use monero_serai::{
+    hash, hash_to_scalar,
+    ringct::{
+        hash_to_point,
+        mlsag::{AggregateRingMatrixBuilder, RingMatrix},
+        RctPrunable,
+    },
+    transaction::{Input, Transaction},
+    H,
+};
+use rand::rngs::OsRng;
+use tower::ServiceExt;
+use tracing::instrument;
+
+use crate::{
+    block::weight::penalty_free_zone,
+    block_template::MINER_TX_RESERVED_WEIGHT,
+    consensus_constants::MINED_MONEY_UNLOCK_WINDOW,
+    context::BlockChainContext,
+    decoys::ring_members,
+    fee::minimum_fee,
+    hardforks::HardFork,
+    outputs::{is_output_unlocked, OutputTimeLock},
+    rule_flags::MAX_TX_EXTRA_SIZE,
+    timings::{BlockTimings, VerificationStage},
+    tx_extra::TxExtra,
+    verification_pool::VerificationPool,
+    ConsensusError, Database, DatabaseRequest, TransactionError,
+};
+
+/// Checks the transaction's version is allowed at this hard-fork: version 1 transactions are allowed
+/// before [`HardFork::V6`] and RingCT (version 2) transactions from [`HardFork::V4`].
+pub fn check_tx_version(tx: &Transaction, hf: &HardFork) -> Result<(), TransactionError> {
+    let allowed = match tx.prefix.version {
+        1 => *hf < HardFork::V6,
+        2 => *hf >= HardFork::V4,
+        _ => false,
+    };
+
+    if !allowed {
+        return Err(TransactionError::VersionNotAllowed {
+            version: tx.prefix.version,
+            hf: *hf,
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns the maximum weight of a transaction, the penalty free zone minus the weight reserved for
+/// the miner transaction. From [`HardFork::V8`] a transaction can only take half the penalty free
+/// zone.
+pub fn tx_weight_limit(hf: &HardFork) -> usize {
+    if hf >= &HardFork::V8 {
+        penalty_free_zone(hf) / 2 - MINER_TX_RESERVED_WEIGHT
+    } else {
+        penalty_free_zone(hf) - MINER_TX_RESERVED_WEIGHT
+    }
+}
+
+/// Checks the transaction's weight is not over [`tx_weight_limit`].
+pub fn check_tx_weight(weight: usize, hf: &HardFork) -> Result<(), TransactionError> {
+    let limit = tx_weight_limit(hf);
+    if weight > limit {
+        return Err(TransactionError::TooBig { weight, limit });
+    }
+
+    Ok(())
+}
+
+/// Returns the fee of a version 1 transaction, the amount of its inputs minus the amount of its
+/// outputs.
+pub fn v1_fee(tx: &Transaction) -> Result<u64, TransactionError> {
+    let inputs = tx
+        .prefix
+        .inputs
+        .iter()
+        .filter_map(|input| match input {
+            Input::Gen(_) => None,
+            Input::ToKey { amount, .. } => Some(amount.unwrap_or(0)),
+        })
+        .try_fold(0_u64, u64::checked_add)
+        .ok_or(TransactionError::AmountOverflow)?;
+    let outputs = tx
+        .prefix
+        .outputs
+        .iter()
+        .map(|output| output.amount.unwrap_or(0))
+        .try_fold(0_u64, u64::checked_add)
+        .ok_or(TransactionError::AmountOverflow)?;
+
+    inputs
+        .checked_sub(outputs)
+        .ok_or(TransactionError::OutputsMoreThanInputs { inputs, outputs })
+}
+
+/// Returns the fee of a transaction, see [`v1_fee`] for version 1 transactions.
+pub fn tx_fee(tx: &Transaction) -> Result<u64, TransactionError> {
+    if tx.prefix.version == 1 {
+        v1_fee(tx)
+    } else {
+        Ok(tx.rct_signatures.base.fee)
+    }
+}
+
+/// Returns the key images of the transaction's inputs, checking there is at least one input, none
+/// are miner inputs and no key image is spent twice.
+pub fn tx_key_images(tx: &Transaction) -> Result<Vec<[u8; 32]>, TransactionError> {
+    if tx.prefix.inputs.is_empty() {
+        return Err(TransactionError::NoInputs);
+    }
+
+    let mut key_images = Vec::with_capacity(tx.prefix.inputs.len());
+    for input in &tx.prefix.inputs {
+        let Input::ToKey { key_image, .. } = input else {
+            return Err(TransactionError::MinerInput);
+        };
+
+        let key_image = key_image.compress().to_bytes();
+        if key_images.contains(&key_image) {
+            return Err(TransactionError::DuplicateKeyImage(key_image));
+        }
+        key_images.push(key_image);
+    }
+
+    Ok(key_images)
+}
+
+/// Checks the fee is not below the [`minimum_fee`] of a transaction of this weight in the next block,
+/// returning the minimum.
+pub fn check_tx_fee(
+    fee: u64,
+    weight: usize,
+    context: &BlockChainContext,
+) -> Result<u64, TransactionError> {
+    let minimum = minimum_fee(
+        weight,
+        context.short_term_median_weight,
+        context.long_term_median_weight,
+        context.next_block_base_reward(),
+        &context.current_hf,
+    );
+    if fee < minimum {
+        return Err(TransactionError::FeeTooLow { fee, minimum });
+    }
+
+    Ok(minimum)
+}
+
+/// Checks the extra is not bigger than [`MAX_TX_EXTRA_SIZE`].
+pub fn check_tx_extra_size(extra: &[u8]) -> Result<(), TransactionError> {
+    if extra.len() > MAX_TX_EXTRA_SIZE {
+        return Err(TransactionError::ExtraTooBig {
+            size: extra.len(),
+            limit: MAX_TX_EXTRA_SIZE,
+        });
+    }
+
+    Ok(())
+}
+
+/// Parses the extra with [`TxExtra::parse_strict`].
+pub fn parse_tx_extra(extra: &[u8]) -> Result<TxExtra, TransactionError> {
+    TxExtra::parse_strict(extra).map_err(TransactionError::InvalidExtra)
+}
+
+/// Checks a CryptoNote ring signature, the signature of pre-RingCT inputs.
+///
+/// https://github.com/monero-project/monero/blob/90294f09ae34ef96f3dea5fea544816786df87c8/src/crypto/crypto.cpp
+fn check_ring_signature(
+    message: &[u8; 32],
+    ring: &[EdwardsPoint],
+    key_image: &EdwardsPoint,
+    sigs: &[(Scalar, Scalar)],
+) -> bool {
+    let mut buf = Vec::with_capacity(32 + ring.len() * 64);
+    buf.extend_from_slice(message);
+
+    let mut sum = Scalar::ZERO;
+    for (member, (c, r)) in ring.iter().zip(sigs) {
+        // L = rG + cP, R = rHp(P) + cI
+        let l = EdwardsPoint::vartime_double_scalar_mul_basepoint(c, member, r);
+        let r = r * hash_to_point(*member) + c * key_image;
+
+        sum += c;
+        buf.extend_from_slice(l.compress().as_bytes());
+        buf.extend_from_slice(r.compress().as_bytes());
+    }
+
+    hash_to_scalar(&buf) == sum
+}
+
+/// Checks the ring signatures of a version 1 transaction, `rings` are the keys of the ring members
+/// of each input.
+pub fn check_v1_ring_signatures(
+    tx: &Transaction,
+    rings: &[Vec<EdwardsPoint>],
+) -> Result<(), TransactionError> {
+    let key_images = input_key_images(tx);
+
+    if tx.signatures.len() != key_images.len() || rings.len() != key_images.len() {
+        // The first input without a signature or a ring.
+        let input = tx.signatures.len().min(rings.len()).min(key_images.len());
+        return Err(TransactionError::InvalidRingSignature(input));
+    }
+
+    // The cheap checks of every input are done before hashing anything.
+    for (i, ((key_image, ring), signature)) in
+        key_images.iter().zip(rings).zip(&tx.signatures).enumerate()
+    {
+        if ring.len() != signature.sigs.len() {
+            return Err(TransactionError::InvalidRingSignature(i));
+        }
+
+        check_key_image(key_image, i)?;
+    }
+
+    let mut prefix = Vec::new();
+    tx.prefix
+        .write(&mut prefix)
+        .expect("Writing to a Vec can't fail");
+    let prefix_hash = hash(&prefix);
+
+    for (i, ((key_image, ring), signature)) in
+        key_images.iter().zip(rings).zip(&tx.signatures).enumerate()
+    {
+        if !check_ring_signature(&prefix_hash, ring, key_image, &signature.sigs) {
+            return Err(TransactionError::InvalidRingSignature(i));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the RingCT proofs of a version 2 transaction, `rings` are the key and commitment of the
+/// ring members of each input.
+///
+/// The inputs are signed with an MLSAG for each input, or one for all of them in the first RingCT
+/// transactions, and from [`HardFork::V13`] with a CLSAG for each input. The amounts of the outputs
+/// are proven in range with Borromean signatures, then with Bulletproofs. The commitments of the
+/// inputs must balance the commitments of the outputs and the fee, for a single MLSAG this is part
+/// of the signature.
+pub fn check_rct_signatures(
+    tx: &Transaction,
+    rings: &[Vec<[EdwardsPoint; 2]>],
+) -> Result<(), TransactionError> {
+    let key_images = input_key_images(tx);
+    if rings.len() != key_images.len() {
+        return Err(TransactionError::InvalidRctSignature(
+            rings.len().min(key_images.len()),
+        ));
+    }
+    for (i, key_image) in key_images.iter().enumerate() {
+        check_key_image(key_image, i)?;
+    }
+
+    let rct = &tx.rct_signatures;
+    let commitments = &rct.base.commitments;
+    if commitments.len() != tx.prefix.outputs.len() {
+        return Err(TransactionError::InvalidRangeProof);
+    }
+
+    let ranges_valid = match &rct.prunable {
+        RctPrunable::Null => return Err(TransactionError::RctTypeNotAllowed),
+        RctPrunable::AggregateMlsagBorromean { borromean, .. }
+        | RctPrunable::MlsagBorromean { borromean, .. } => {
+            borromean.len() == commitments.len()
+                && borromean
+                    .iter()
+                    .zip(commitments)
+                    .all(|(range, commitment)| range.verify(commitment))
+        }
+        RctPrunable::MlsagBulletproofs { bulletproofs, .. }
+        | RctPrunable::Clsag { bulletproofs, .. } => bulletproofs.verify(&mut OsRng, commitments),
+    };
+    if !ranges_valid {
+        return Err(TransactionError::InvalidRangeProof);
+    }
+
+    let msg = tx.signature_hash();
+    let pseudo_outs = match &rct.prunable {
+        RctPrunable::AggregateMlsagBorromean { mlsag, .. } => {
+            // The signature is of the inputs minus the outputs and fee, so it checks they balance.
+            let mut matrix = AggregateRingMatrixBuilder::new(commitments, rct.base.fee);
+            for (i, ring) in rings.iter().enumerate() {
+                matrix
+                    .push_ring(ring)
+                    .map_err(|_| TransactionError::InvalidRctSignature(i))?;
+            }
+            let key_images: Vec<EdwardsPoint> = key_images.into_iter().copied().collect();
+            return matrix
+                .build()
+                .and_then(|matrix| mlsag.verify(&msg, &matrix, &key_images))
+                .map_err(|_| TransactionError::InvalidRctSignature(0));
+        }
+        RctPrunable::MlsagBorromean { mlsags, .. } => {
+            check_rct_inputs(mlsags.len(), &rct.base.pseudo_outs, key_images.len())?;
+            for (i, (((mlsag, pseudo_out), key_image), ring)) in mlsags
+                .iter()
+                .zip(&rct.base.pseudo_outs)
+                .zip(&key_images)
+                .zip(rings)
+                .enumerate()
+            {
+                RingMatrix::individual(ring, *pseudo_out)
+                    .and_then(|matrix| mlsag.verify(&msg, &matrix, &[**key_image]))
+                    .map_err(|_| TransactionError::InvalidRctSignature(i))?;
+            }
+            &rct.base.pseudo_outs
+        }
+        RctPrunable::MlsagBulletproofs {
+            mlsags,
+            pseudo_outs,
+            ..
+        } => {
+            check_rct_inputs(mlsags.len(), pseudo_outs, key_images.len())?;
+            for (i, (((mlsag, pseudo_out), key_image), ring)) in mlsags
+                .iter()
+                .zip(pseudo_outs)
+                .zip(&key_images)
+                .zip(rings)
+                .enumerate()
+            {
+                RingMatrix::individual(ring, *pseudo_out)
+                    .and_then(|matrix| mlsag.verify(&msg, &matrix, &[**key_image]))
+                    .map_err(|_| TransactionError::InvalidRctSignature(i))?;
+            }
+            pseudo_outs
+        }
+        RctPrunable::Clsag {
+            clsags,
+            pseudo_outs,
+            ..
+        } => {
+            check_rct_inputs(clsags.len(), pseudo_outs, key_images.len())?;
+            for (i, (((clsag, pseudo_out), key_image), ring)) in clsags
+                .iter()
+                .zip(pseudo_outs)
+                .zip(&key_images)
+                .zip(rings)
+                .enumerate()
+            {
+                clsag
+                    .verify(ring, key_image, pseudo_out, &msg)
+                    .map_err(|_| TransactionError::InvalidRctSignature(i))?;
+            }
+            pseudo_outs
+        }
+        RctPrunable::Null => unreachable!("Checked with the range proofs"),
+    };
+
+    let inputs: EdwardsPoint = pseudo_outs.iter().sum();
+    let outputs = commitments.iter().sum::<EdwardsPoint>() + Scalar::from(rct.base.fee) * H();
+    if inputs != outputs {
+        return Err(TransactionError::AmountsDontBalance);
+    }
+
+    Ok(())
+}
+
+/// Checks there is a signature and a pseudo output, the commitment the signature is of, for each of
+/// the `inputs`.
+fn check_rct_inputs(
+    signatures: usize,
+    pseudo_outs: &[EdwardsPoint],
+    inputs: usize,
+) -> Result<(), TransactionError> {
+    if signatures != inputs || pseudo_outs.len() != inputs {
+        // The first input without a signature or a pseudo output.
+        let input = signatures.min(pseudo_outs.len());
+        return Err(TransactionError::InvalidRctSignature(input));
+    }
+
+    Ok(())
+}
+
+/// Returns the key images of the transaction's inputs.
+fn input_key_images(tx: &Transaction) -> Vec<&EdwardsPoint> {
+    tx.prefix
+        .inputs
+        .iter()
+        .filter_map(|input| match input {
+            Input::Gen(_) => None,
+            Input::ToKey { key_image, .. } => Some(key_image),
+        })
+        .collect()
+}
+
+/// Checks the key image of input `i` is in the prime order subgroup.
+fn check_key_image(key_image: &EdwardsPoint, i: usize) -> Result<(), TransactionError> {
+    if key_image.is_identity() || !key_image.is_torsion_free() {
+        return Err(TransactionError::InvalidKeyImage(i));
+    }
+
+    Ok(())
+}
+
+/// Checks the version of every transaction of a block and verifies their signatures, the ring
+/// signatures and amounts of version 1 transactions, see [`check_v1_ring_signatures`], and the
+/// RingCT proofs of version 2 transactions, see [`check_rct_signatures`]. The signatures are
+/// checked on the pool.
+///
+/// The time spent waiting for the pool's checks is recorded as the block's
+/// [`VerificationStage::Signatures`].
+#[instrument(
+    target = "cuprate_consensus::tx",
+    name = "verify_tx_signatures",
+    skip_all,
+    fields(batch_size = txs.len(), hf = ?hf)
+)]
+pub async fn verify_tx_signatures<D: Database>(
+    txs: &[Transaction],
+    hf: &HardFork,
+    database: D,
+    pool: &VerificationPool,
+    timings: &mut BlockTimings,
+) -> Result<(), ConsensusError> {
+    for tx in txs {
+        check_tx_version(tx, hf)?;
+        if tx.prefix.version == 1 {
+            v1_fee(tx)?;
+        }
+    }
+
+    let rings: Vec<Vec<Vec<(u64, u64)>>> = txs.iter().map(ring_members).collect();
+    let members: Vec<(u64, u64)> = rings.iter().flatten().flatten().copied().collect();
+    if members.is_empty() {
+        return Ok(());
+    }
+
+    let outputs = database
+        .oneshot(DatabaseRequest::Outputs(members))
+        .await?
+        .into_outputs()?;
+    let mut outputs = outputs.into_iter();
+
+    let started = Instant::now();
+    let mut checks = Vec::with_capacity(txs.len());
+    for (tx, tx_rings) in txs.iter().zip(rings) {
+        let ring_keys = tx_rings
+            .into_iter()
+            .map(|ring| {
+                ring.into_iter()
+                    .zip(outputs.by_ref())
+                    .map(|((amount, index), output)| {
+                        let invalid = || TransactionError::InvalidRingMember { amount, index };
+                        let key = CompressedEdwardsY(output.key)
+                            .decompress()
+                            .ok_or_else(invalid)?;
+                        let mask = CompressedEdwardsY(output.mask)
+                            .decompress()
+                            .ok_or_else(invalid)?;
+                        Ok([key, mask])
+                    })
+                    .collect::<Result<Vec<_>, TransactionError>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tx = tx.clone();
+        checks.push(pool.spawn(move || {
+            if tx.prefix.version == 1 {
+                let rings: Vec<Vec<EdwardsPoint>> = ring_keys
+                    .iter()
+                    .map(|ring| ring.iter().map(|[key, _]| *key).collect())
+                    .collect();
+                check_v1_ring_signatures(&tx, &rings)
+            } else {
+                check_rct_signatures(&tx, &ring_keys)
+            }
+        }));
+    }
+
+    let res = futures::future::try_join_all(checks).await;
+    timings.record(VerificationStage::Signatures, started.elapsed());
+    res?;
+    Ok(())
+}
+
+/// Returns the UNIX timestamp time based locks are checked against for the next block.
+///
+/// This is the adjusted median time from [`HardFork::V13`], or the local clock before that or if the
+/// chain is too short to have an adjusted median time.
+pub fn time_lock_check_time(context: &BlockChainContext) -> u64 {
+    match context.adjusted_time {
+        Some(adjusted_time) if context.current_hf >= HardFork::V13 => adjusted_time,
+        _ => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    }
+}
+
+/// Checks a ring member can be spent in the next block.
+///
+/// `current_time` is the UNIX timestamp to check time based locks against, see
+/// [`time_lock_check_time`].
+pub fn check_output_unlocked(
+    (amount, index): (u64, u64),
+    output: &OutputTimeLock,
+    chain_height: u64,
+    current_time: u64,
+    hf: &HardFork,
+) -> Result<(), TransactionError> {
+    if output.is_coinbase && output.height + MINED_MONEY_UNLOCK_WINDOW > chain_height {
+        return Err(TransactionError::CoinbaseNotMature {
+            amount,
+            index,
+            height: output.height,
+        });
+    }
+
+    if !is_output_unlocked(&output.time_lock, chain_height, current_time, hf) {
+        return Err(TransactionError::OutputLocked { amount, index });
+    }
+
+    Ok(())
+}
+
+/// Checks every ring member of the transaction can be spent in the next block.
+pub async fn check_ring_members_unlocked<D: Database>(
+    tx: &Transaction,
+    context: &BlockChainContext,
+    database: D,
+) -> Result<(), ConsensusError> {
+    let ring_members = ring_members(tx).concat();
+    if ring_members.is_empty() {
+        return Ok(());
+    }
+
+    let time_locks = database
+        .oneshot(DatabaseRequest::OutputTimeLocks(ring_members.clone()))
+        .await?
+        .into_output_time_locks()?;
+
+    let current_time = time_lock_check_time(context);
+
+    for (member, output) in ring_members.into_iter().zip(&time_locks) {
+        check_output_unlocked(
+            member,
+            output,
+            context.chain_height,
+            current_time,
+            &context.current_hf,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use curve25519_dalek::constants::{ED25519_BASEPOINT_POINT, EIGHT_TORSION};
+    use monero_serai::{
+        ringct::{RctBase, RctPrunable, RctSignatures},
+        transaction::{Output, RingSignature, Timelock, TransactionPrefix},
+    };
+
+    use super::*;
+    use crate::test_utils::{dummy_context, sign_v1_tx};
+
+    /// Returns a version 1 transaction spending these amounts with rings of `ring_size` and creating
+    /// outputs of these amounts.
+    fn v1_tx(inputs: &[u64], outputs: &[u64], ring_size: usize) -> Transaction {
+        Transaction {
+            prefix: TransactionPrefix {
+                version: 1,
+                timelock: Timelock::None,
+                inputs: inputs
+                    .iter()
+                    .map(|amount| Input::ToKey {
+                        amount: Some(*amount),
+                        key_offsets: vec![1; ring_size],
+                        key_image: ED25519_BASEPOINT_POINT,
+                    })
+                    .collect(),
+                outputs: outputs
+                    .iter()
+                    .map(|amount| Output {
+                        amount: Some(*amount),
+                        key: CompressedEdwardsY([0; 32]),
+                        view_tag: None,
+                    })
+                    .collect(),
+                extra: vec![],
+            },
+            signatures: inputs
+                .iter()
+                .map(|_| RingSignature {
+                    sigs: vec![(Scalar::ONE, Scalar::ONE); ring_size],
+                })
+                .collect(),
+            rct_signatures: RctSignatures {
+                base: RctBase {
+                    fee: 0,
+                    pseudo_outs: vec![],
+                    encrypted_amounts: vec![],
+                    commitments: vec![],
+                },
+                prunable: RctPrunable::Null,
+            },
+        }
+    }
+
+    fn output(height: u64, time_lock: Timelock, is_coinbase: bool) -> OutputTimeLock {
+        OutputTimeLock {
+            height,
+            time_lock,
+            is_coinbase,
+        }
+    }
+
+    #[test]
+    fn coinbase_outputs_mature() {
+        let coinbase = output(100, Timelock::None, true);
+
+        assert_eq!(
+            check_output_unlocked((0, 5), &coinbase, 159, 0, &HardFork::V16),
+            Err(TransactionError::CoinbaseNotMature {
+                amount: 0,
+                index: 5,
+                height: 100
+            })
+        );
+        assert_eq!(
+            check_output_unlocked((0, 5), &coinbase, 160, 0, &HardFork::V16),
+            Ok(())
+        );
+
+        // The same output from a normal transaction is spendable straight away.
+        let not_coinbase = output(100, Timelock::None, false);
+        assert_eq!(
+            check_output_unlocked((0, 5), &not_coinbase, 101, 0, &HardFork::V16),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn time_locked_outputs() {
+        let block_locked = output(10, Timelock::Block(200), false);
+        assert_eq!(
+            check_output_unlocked((0, 1), &block_locked, 199, 0, &HardFork::V16),
+            Err(TransactionError::OutputLocked {
+                amount: 0,
+                index: 1
+            })
+        );
+        assert_eq!(
+            check_output_unlocked((0, 1), &block_locked, 200, 0, &HardFork::V16),
+            Ok(())
+        );
+
+        let time_locked = output(10, Timelock::Time(1_000_000), false);
+        assert!(check_output_unlocked((0, 1), &time_locked, 200, 999_000, &HardFork::V16).is_err());
+        assert_eq!(
+            check_output_unlocked((0, 1), &time_locked, 200, 999_880, &HardFork::V16),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn tx_versions() {
+        let mut tx = v1_tx(&[10], &[10], 1);
+        assert_eq!(check_tx_version(&tx, &HardFork::V5), Ok(()));
+        assert_eq!(
+            check_tx_version(&tx, &HardFork::V6),
+            Err(TransactionError::VersionNotAllowed {
+                version: 1,
+                hf: HardFork::V6
+            })
+        );
+
+        tx.prefix.version = 2;
+        assert!(check_tx_version(&tx, &HardFork::V3).is_err());
+        assert_eq!(check_tx_version(&tx, &HardFork::V4), Ok(()));
+    }
+
+    #[test]
+    fn tx_weights() {
+        assert_eq!(tx_weight_limit(&HardFork::V1), 19_400);
+        assert_eq!(tx_weight_limit(&HardFork::V7), 299_400);
+        assert_eq!(tx_weight_limit(&HardFork::V8), 149_400);
+
+        assert_eq!(check_tx_weight(149_400, &HardFork::V16), Ok(()));
+        assert_eq!(
+            check_tx_weight(149_401, &HardFork::V16),
+            Err(TransactionError::TooBig {
+                weight: 149_401,
+                limit: 149_400
+            })
+        );
+    }
+
+    #[test]
+    fn v1_amounts() {
+        assert_eq!(v1_fee(&v1_tx(&[10, 5], &[12], 1)), Ok(3));
+        assert_eq!(v1_fee(&v1_tx(&[10, 5], &[10, 5], 1)), Ok(0));
+        assert_eq!(
+            v1_fee(&v1_tx(&[10, 5], &[16], 1)),
+            Err(TransactionError::OutputsMoreThanInputs {
+                inputs: 15,
+                outputs: 16
+            })
+        );
+        assert_eq!(
+            v1_fee(&v1_tx(&[u64::MAX, 1], &[1], 1)),
+            Err(TransactionError::AmountOverflow)
+        );
+    }
+
+    #[test]
+    fn v1_ring_signature_structure() {
+        let ring = vec![ED25519_BASEPOINT_POINT; 3];
+
+        // A ring member without a signature.
+        let tx = v1_tx(&[10], &[10], 2);
+        assert_eq!(
+            check_v1_ring_signatures(&tx, std::slice::from_ref(&ring)),
+            Err(TransactionError::InvalidRingSignature(0))
+        );
+
+        // A ring missing.
+        let tx = v1_tx(&[10, 10], &[20], 3);
+        assert_eq!(
+            check_v1_ring_signatures(&tx, std::slice::from_ref(&ring)),
+            Err(TransactionError::InvalidRingSignature(1))
+        );
+
+        // Key images must be in the prime order subgroup.
+        let mut tx = v1_tx(&[10, 10], &[20], 3);
+        let Input::ToKey { key_image, .. } = &mut tx.prefix.inputs[1] else {
+            unreachable!()
+        };
+        *key_image += EIGHT_TORSION[1];
+        assert_eq!(
+            check_v1_ring_signatures(&tx, &[ring.clone(), ring]),
+            Err(TransactionError::InvalidKeyImage(1))
+        );
+    }
+
+    /// No mainnet version 1 transaction is checked here, this tree has no recorded vector of one, so
+    /// the signatures are made with [`sign_v1_tx`](crate::test_utils::sign_v1_tx).
+    #[test]
+    fn v1_ring_signatures() {
+        let secrets = [Scalar::from(3_u64), Scalar::from(4_u64)];
+        let rings: Vec<Vec<EdwardsPoint>> = secrets
+            .iter()
+            .map(|secret| {
+                [*secret, Scalar::from(5_u64), Scalar::from(6_u64)]
+                    .iter()
+                    .map(|key| ED25519_BASEPOINT_POINT * key)
+                    .collect()
+            })
+            .collect();
+        let mut tx = v1_tx(&[10, 10], &[20], 3);
+        sign_v1_tx(&mut tx, &secrets, &rings);
+        assert_eq!(check_v1_ring_signatures(&tx, &rings), Ok(()));
+
+        // The signatures are of the prefix.
+        let mut changed = tx.clone();
+        changed.prefix.outputs[0].amount = Some(19);
+        assert_eq!(
+            check_v1_ring_signatures(&changed, &rings),
+            Err(TransactionError::InvalidRingSignature(0))
+        );
+
+        // The ring must be the one signed.
+        let mut wrong_rings = rings.clone();
+        wrong_rings[1].swap(1, 2);
+        assert_eq!(
+            check_v1_ring_signatures(&tx, &wrong_rings),
+            Err(TransactionError::InvalidRingSignature(1))
+        );
+    }
+
+    #[test]
+    fn adjusted_time_is_used_from_v13() {
+        let mut context = dummy_context(HardFork::V13);
+        assert_eq!(time_lock_check_time(&context), 1_000);
+
+        context.current_hf = HardFork::V12;
+        assert!(time_lock_check_time(&context) > 1_000);
+    }
+}
@@ -0,0 +1,367 @@
+//! # Tx Extra
+//!
+//! This module contains [`TxExtra`], the parsed `extra` field of a transaction: its public key, the
+//! additional public keys of subaddress outputs, the extra nonce (which can hold a payment ID) and the
+//! merge mining tag of miner transactions.
+//!
+//! The extra is not checked by consensus, a block can contain transactions with an extra that does not
+//! parse. So there are two modes:
+//! - [`TxExtra::parse_lenient`] matches monerod when reading the chain, the fields before the first
+//!   malformed one are kept and the rest of the extra is ignored.
+//! - [`TxExtra::parse_strict`] is for relay policy, the whole extra must parse, no tag may be unknown
+//!   and the public key, additional public keys and nonce may each appear once.
+//!
+
+/// The tag of padding, zeros until the end of the extra.
+pub const TX_EXTRA_TAG_PADDING: u8 = 0x00;
+/// The tag of the transaction's public key.
+pub const TX_EXTRA_TAG_PUBKEY: u8 = 0x01;
+/// The tag of the extra nonce.
+pub const TX_EXTRA_NONCE: u8 = 0x02;
+/// The tag of the merge mining tag.
+pub const TX_EXTRA_MERGE_MINING_TAG: u8 = 0x03;
+/// The tag of the additional public keys.
+pub const TX_EXTRA_TAG_ADDITIONAL_PUBKEYS: u8 = 0x04;
+/// The tag of a field added by the MinerGate pool.
+pub const TX_EXTRA_MYSTERIOUS_MINERGATE_TAG: u8 = 0xde;
+
+/// The most bytes of padding, including its tag.
+pub const TX_EXTRA_PADDING_MAX_COUNT: usize = 255;
+/// The most bytes in an extra nonce.
+pub const TX_EXTRA_NONCE_MAX_COUNT: usize = 255;
+
+/// The first byte of an extra nonce holding an unencrypted payment ID.
+const TX_EXTRA_NONCE_PAYMENT_ID: u8 = 0x00;
+/// The first byte of an extra nonce holding an encrypted payment ID.
+const TX_EXTRA_NONCE_ENCRYPTED_PAYMENT_ID: u8 = 0x01;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TxExtraError {
+    #[error("The field at byte {0} ends after the extra")]
+    Truncated(usize),
+    #[error("The padding at byte {0} is not all zeros or is too long")]
+    InvalidPadding(usize),
+    #[error("The extra nonce is {0} bytes, the most is {TX_EXTRA_NONCE_MAX_COUNT}")]
+    NonceTooBig(usize),
+    #[error("The merge mining tag at byte {0} is malformed")]
+    InvalidMergeMiningTag(usize),
+    #[error("Unknown tag {tag:#04x} at byte {offset}")]
+    UnknownTag { tag: u8, offset: usize },
+    #[error("The extra has more than one {0}")]
+    DuplicateField(&'static str),
+}
+
+/// A payment ID held in the extra nonce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentId {
+    /// A long payment ID, these are deprecated.
+    Unencrypted([u8; 32]),
+    /// A short payment ID, encrypted to the receiver.
+    Encrypted([u8; 8]),
+}
+
+/// A field of the extra.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtraField {
+    /// Zeros until the end of the extra, the amount includes the tag.
+    Padding(usize),
+    PubKey([u8; 32]),
+    Nonce(Vec<u8>),
+    MergeMining {
+        depth: u64,
+        merkle_root: [u8; 32],
+    },
+    AdditionalPubKeys(Vec<[u8; 32]>),
+    MysteriousMinergate(Vec<u8>),
+}
+
+/// The parsed extra of a transaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxExtra {
+    /// The fields, in the order they are in the extra.
+    pub fields: Vec<ExtraField>,
+    /// The error that stopped [`TxExtra::parse_lenient`] parsing the rest of the extra.
+    pub malformed: Option<TxExtraError>,
+}
+
+impl TxExtra {
+    /// Parses the extra like monerod does for transactions in the chain, keeping the fields before
+    /// the first malformed one.
+    pub fn parse_lenient(extra: &[u8]) -> TxExtra {
+        let mut reader = Reader { extra, offset: 0 };
+        let mut tx_extra = TxExtra::default();
+
+        while reader.offset < extra.len() {
+            match reader.read_field() {
+                Ok(field) => tx_extra.fields.push(field),
+                Err(e) => {
+                    tx_extra.malformed = Some(e);
+                    break;
+                }
+            }
+        }
+
+        tx_extra
+    }
+
+    /// Parses the extra for relay policy, every field must parse, tags must be known and the public
+    /// key, additional public keys and nonce may appear at most once.
+    pub fn parse_strict(extra: &[u8]) -> Result<TxExtra, TxExtraError> {
+        let tx_extra = TxExtra::parse_lenient(extra);
+        if let Some(e) = tx_extra.malformed {
+            return Err(e);
+        }
+
+        for (name, count) in [
+            (
+                "public key",
+                tx_extra.count(|field| matches!(field, ExtraField::PubKey(_))),
+            ),
+            (
+                "additional public keys field",
+                tx_extra.count(|field| matches!(field, ExtraField::AdditionalPubKeys(_))),
+            ),
+            (
+                "extra nonce",
+                tx_extra.count(|field| matches!(field, ExtraField::Nonce(_))),
+            ),
+        ] {
+            if count > 1 {
+                return Err(TxExtraError::DuplicateField(name));
+            }
+        }
+
+        Ok(tx_extra)
+    }
+
+    fn count(&self, f: impl Fn(&ExtraField) -> bool) -> usize {
+        self.fields.iter().filter(|field| f(field)).count()
+    }
+
+    /// Returns the transaction's public key, the first one if there are more.
+    pub fn pub_key(&self) -> Option<[u8; 32]> {
+        self.fields.iter().find_map(|field| match field {
+            ExtraField::PubKey(key) => Some(*key),
+            _ => None,
+        })
+    }
+
+    /// Returns the additional public keys, one for each output, used when sending to subaddresses.
+    pub fn additional_pub_keys(&self) -> Option<&[[u8; 32]]> {
+        self.fields.iter().find_map(|field| match field {
+            ExtraField::AdditionalPubKeys(keys) => Some(keys.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Returns the extra nonce.
+    pub fn nonce(&self) -> Option<&[u8]> {
+        self.fields.iter().find_map(|field| match field {
+            ExtraField::Nonce(nonce) => Some(nonce.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Returns the payment ID in the extra nonce.
+    pub fn payment_id(&self) -> Option<PaymentId> {
+        match self.nonce()? {
+            [TX_EXTRA_NONCE_PAYMENT_ID, id @ ..] => {
+                Some(PaymentId::Unencrypted(id.try_into().ok()?))
+            }
+            [TX_EXTRA_NONCE_ENCRYPTED_PAYMENT_ID, id @ ..] => {
+                Some(PaymentId::Encrypted(id.try_into().ok()?))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the merge mining tag of a miner transaction: the depth of the merkle tree and its root.
+    pub fn merge_mining_tag(&self) -> Option<(u64, [u8; 32])> {
+        self.fields.iter().find_map(|field| match field {
+            ExtraField::MergeMining { depth, merkle_root } => Some((*depth, *merkle_root)),
+            _ => None,
+        })
+    }
+}
+
+struct Reader<'a> {
+    extra: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, len: usize, field_start: usize) -> Result<&'a [u8], TxExtraError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.extra.len())
+            .ok_or(TxExtraError::Truncated(field_start))?;
+
+        let bytes = &self.extra[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn read_key(&mut self, field_start: usize) -> Result<[u8; 32], TxExtraError> {
+        Ok(self.read_bytes(32, field_start)?.try_into().unwrap())
+    }
+
+    fn read_varint(&mut self, field_start: usize) -> Result<u64, TxExtraError> {
+        let mut number = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_bytes(1, field_start)?[0];
+            number |= u64::from(byte & 0x7f) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(number);
+            }
+        }
+        // A varint over 64 bits can't be read, the extra after it has no meaning.
+        Err(TxExtraError::Truncated(field_start))
+    }
+
+    /// Reads a varint length and that many bytes.
+    fn read_blob(&mut self, field_start: usize) -> Result<&'a [u8], TxExtraError> {
+        let len = self.read_varint(field_start)?;
+        self.read_bytes(
+            usize::try_from(len).map_err(|_| TxExtraError::Truncated(field_start))?,
+            field_start,
+        )
+    }
+
+    fn read_field(&mut self) -> Result<ExtraField, TxExtraError> {
+        let field_start = self.offset;
+        let tag = self.read_bytes(1, field_start)?[0];
+
+        match tag {
+            TX_EXTRA_TAG_PADDING => {
+                let padding = &self.extra[field_start..];
+                if padding.len() > TX_EXTRA_PADDING_MAX_COUNT || padding.iter().any(|b| *b != 0) {
+                    return Err(TxExtraError::InvalidPadding(field_start));
+                }
+                self.offset = self.extra.len();
+                Ok(ExtraField::Padding(padding.len()))
+            }
+            TX_EXTRA_TAG_PUBKEY => Ok(ExtraField::PubKey(self.read_key(field_start)?)),
+            TX_EXTRA_NONCE => {
+                let nonce = self.read_blob(field_start)?;
+                if nonce.len() > TX_EXTRA_NONCE_MAX_COUNT {
+                    return Err(TxExtraError::NonceTooBig(nonce.len()));
+                }
+                Ok(ExtraField::Nonce(nonce.to_vec()))
+            }
+            TX_EXTRA_MERGE_MINING_TAG => {
+                let mut tag = Reader {
+                    extra: self.read_blob(field_start)?,
+                    offset: 0,
+                };
+                let invalid = |_| TxExtraError::InvalidMergeMiningTag(field_start);
+
+                let depth = tag.read_varint(0).map_err(invalid)?;
+                let merkle_root = tag.read_key(0).map_err(invalid)?;
+                if tag.offset != tag.extra.len() {
+                    return Err(TxExtraError::InvalidMergeMiningTag(field_start));
+                }
+
+                Ok(ExtraField::MergeMining { depth, merkle_root })
+            }
+            TX_EXTRA_TAG_ADDITIONAL_PUBKEYS => {
+                let count = self.read_varint(field_start)?;
+                // Each key is at least 32 bytes, so this stops a huge count allocating.
+                if count > (self.extra.len() / 32) as u64 {
+                    return Err(TxExtraError::Truncated(field_start));
+                }
+
+                let keys = (0..count)
+                    .map(|_| self.read_key(field_start))
+                    .collect::<Result<_, _>>()?;
+                Ok(ExtraField::AdditionalPubKeys(keys))
+            }
+            TX_EXTRA_MYSTERIOUS_MINERGATE_TAG => Ok(ExtraField::MysteriousMinergate(
+                self.read_blob(field_start)?.to_vec(),
+            )),
+            tag => Err(TxExtraError::UnknownTag {
+                tag,
+                offset: field_start,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fields() {
+        let mut extra = vec![TX_EXTRA_TAG_PUBKEY];
+        extra.extend([1; 32]);
+        extra.extend([TX_EXTRA_NONCE, 9, TX_EXTRA_NONCE_ENCRYPTED_PAYMENT_ID]);
+        extra.extend([2; 8]);
+        extra.extend([TX_EXTRA_TAG_ADDITIONAL_PUBKEYS, 2]);
+        extra.extend([3; 64]);
+        extra.extend([TX_EXTRA_MERGE_MINING_TAG, 33, 5]);
+        extra.extend([4; 32]);
+        extra.extend([TX_EXTRA_TAG_PADDING, 0, 0]);
+
+        let tx_extra = TxExtra::parse_strict(&extra).unwrap();
+        assert_eq!(tx_extra.pub_key(), Some([1; 32]));
+        assert_eq!(tx_extra.payment_id(), Some(PaymentId::Encrypted([2; 8])));
+        assert_eq!(
+            tx_extra.additional_pub_keys(),
+            Some([[3; 32], [3; 32]].as_slice())
+        );
+        assert_eq!(tx_extra.merge_mining_tag(), Some((5, [4; 32])));
+        assert_eq!(tx_extra.fields.last(), Some(&ExtraField::Padding(3)));
+        assert_eq!(tx_extra, TxExtra::parse_lenient(&extra));
+    }
+
+    #[test]
+    fn lenient_keeps_fields_before_malformed_one() {
+        let mut extra = vec![TX_EXTRA_TAG_PUBKEY];
+        extra.extend([1; 32]);
+        extra.extend([0x99, TX_EXTRA_TAG_PUBKEY]);
+        extra.extend([2; 32]);
+
+        let tx_extra = TxExtra::parse_lenient(&extra);
+        assert_eq!(tx_extra.fields, vec![ExtraField::PubKey([1; 32])]);
+        assert_eq!(
+            tx_extra.malformed,
+            Some(TxExtraError::UnknownTag {
+                tag: 0x99,
+                offset: 33
+            })
+        );
+        assert_eq!(
+            TxExtra::parse_strict(&extra),
+            Err(tx_extra.malformed.unwrap())
+        );
+
+        // A truncated key.
+        let tx_extra = TxExtra::parse_lenient(&extra[..20]);
+        assert!(tx_extra.fields.is_empty());
+        assert_eq!(tx_extra.malformed, Some(TxExtraError::Truncated(0)));
+
+        // Padding followed by anything else.
+        assert_eq!(
+            TxExtra::parse_strict(&[TX_EXTRA_TAG_PADDING, 0, 1]),
+            Err(TxExtraError::InvalidPadding(0))
+        );
+    }
+
+    #[test]
+    fn strict_rejects_duplicate_fields() {
+        let mut extra = vec![TX_EXTRA_TAG_PUBKEY];
+        extra.extend([1; 32]);
+        extra.push(TX_EXTRA_TAG_PUBKEY);
+        extra.extend([2; 32]);
+
+        let tx_extra = TxExtra::parse_lenient(&extra);
+        assert_eq!(tx_extra.pub_key(), Some([1; 32]));
+        assert_eq!(tx_extra.malformed, None);
+        assert_eq!(
+            TxExtra::parse_strict(&extra),
+            Err(TxExtraError::DuplicateField("public key"))
+        );
+    }
+}
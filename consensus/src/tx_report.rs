@@ -0,0 +1,363 @@
+//! # Transaction Reports
+//!
+//! This module contains [`tx_report`], which evaluates the rules a transaction sent to the node is
+//! checked against and returns a [`TxReport`] of each one with the values it was checked with, so
+//! wallet developers can see why a transaction was rejected. Unlike verification a failed rule
+//! doesn't stop the report, every rule is evaluated.
+//!
+//! The rules are:
+//! - `version`, the version is allowed at the next block's hard-fork, see [`check_tx_version`].
+//! - `weight`, the weight is not over [`tx_weight_limit`].
+//! - `inputs`, there is at least one input and none are miner inputs.
+//! - `amounts`, the inputs of a version 1 transaction cover its outputs, see [`v1_fee`].
+//! - `fee`, the fee is not below the minimum fee, see [`check_tx_fee`].
+//! - `extra_size`, the extra is not bigger than [`MAX_TX_EXTRA_SIZE`].
+//! - `extra`, the extra parses, see [`parse_tx_extra`].
+//! - `key_images_unspent`, no key image is spent in the chain.
+//! - `ring_members_unlocked`, every ring member can be spent in the next block, see
+//!   [`check_output_unlocked`]. Ring members that can't be read, like members past the last output,
+//!   fail this rule instead of the report.
+//!
+//! The report also has the age in blocks of every ring member. Signatures and range proofs are not
+//! checked, a transaction passing every rule can still be invalid.
+//!
+use std::fmt::Display;
+
+use monero_serai::transaction::{Input, Transaction};
+use tower::ServiceExt;
+
+use crate::{
+    context::BlockChainContext,
+    decoys::ring_members,
+    hardforks::HardFork,
+    rule_flags::MAX_TX_EXTRA_SIZE,
+    transactions::{
+        check_output_unlocked, check_tx_extra_size, check_tx_fee, check_tx_version,
+        check_tx_weight, parse_tx_extra, time_lock_check_time, tx_weight_limit, v1_fee,
+    },
+    ConsensusError, Database, DatabaseRequest, TransactionError,
+};
+
+/// A rule evaluated for a [`TxReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleCheck {
+    /// The name of the rule, like `fee`.
+    pub rule: &'static str,
+    pub passed: bool,
+    /// The values the rule was checked with, or why it failed.
+    pub details: String,
+}
+
+impl RuleCheck {
+    fn new(rule: &'static str, result: Result<String, impl Display>) -> RuleCheck {
+        match result {
+            Ok(details) => RuleCheck {
+                rule,
+                passed: true,
+                details,
+            },
+            Err(e) => RuleCheck {
+                rule,
+                passed: false,
+                details: e.to_string(),
+            },
+        }
+    }
+}
+
+/// The rules a transaction was checked against, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxReport {
+    pub version: u64,
+    pub weight: usize,
+    /// The fee, [`None`] if the amounts of a version 1 transaction are invalid.
+    pub fee: Option<u64>,
+    /// The hard-fork of the next block, the transaction was checked for this hard-fork.
+    pub hf: HardFork,
+    /// The rules, in the order they were evaluated.
+    pub checks: Vec<RuleCheck>,
+    /// The age in blocks of each ring's members, empty if the ring members couldn't be read.
+    pub ring_ages: Vec<Vec<u64>>,
+}
+
+impl TxReport {
+    /// Returns true if every rule passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    /// Returns the rules that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &RuleCheck> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+}
+
+/// Evaluates every rule against the transaction for the next block and returns the report.
+///
+/// `weight` is the transaction's weight. An error is only returned if the database fails to answer
+/// a request for the key images.
+pub async fn tx_report<D: Database + Clone>(
+    tx: &Transaction,
+    weight: usize,
+    context: &BlockChainContext,
+    database: D,
+) -> Result<TxReport, ConsensusError> {
+    let hf = context.current_hf;
+    let mut checks = Vec::new();
+
+    checks.push(RuleCheck::new(
+        "version",
+        check_tx_version(tx, &hf)
+            .map(|()| format!("version {} at hard-fork {}", tx.prefix.version, hf as u8)),
+    ));
+
+    checks.push(RuleCheck::new(
+        "weight",
+        check_tx_weight(weight, &hf)
+            .map(|()| format!("weight {weight}, limit {}", tx_weight_limit(&hf))),
+    ));
+
+    let inputs = tx.prefix.inputs.len();
+    checks.push(RuleCheck::new(
+        "inputs",
+        if inputs == 0 {
+            Err("The transaction has no inputs")
+        } else if tx
+            .prefix
+            .inputs
+            .iter()
+            .any(|input| matches!(input, Input::Gen(_)))
+        {
+            Err("The transaction has a miner input")
+        } else {
+            Ok(format!("{inputs} inputs"))
+        },
+    ));
+
+    let fee = if tx.prefix.version == 1 {
+        let fee = v1_fee(tx);
+        checks.push(RuleCheck::new(
+            "amounts",
+            fee.as_ref().map(|fee| format!("fee {fee}")),
+        ));
+        fee.ok()
+    } else {
+        Some(tx.rct_signatures.base.fee)
+    };
+
+    checks.push(RuleCheck::new(
+        "fee",
+        match fee {
+            Some(fee) => check_tx_fee(fee, weight, context)
+                .map(|minimum| format!("fee {fee}, minimum {minimum}"))
+                .map_err(|e| e.to_string()),
+            None => Err("The fee can't be calculated, the amounts are invalid".to_string()),
+        },
+    ));
+
+    let extra_size = tx.prefix.extra.len();
+    checks.push(RuleCheck::new(
+        "extra_size",
+        check_tx_extra_size(&tx.prefix.extra)
+            .map(|()| format!("{extra_size} bytes, limit {MAX_TX_EXTRA_SIZE}")),
+    ));
+
+    checks.push(RuleCheck::new(
+        "extra",
+        parse_tx_extra(&tx.prefix.extra).map(|extra| format!("{} fields", extra.fields.len())),
+    ));
+
+    // One request per key image, so the report can name the first spent one.
+    let mut spent = None;
+    for input in &tx.prefix.inputs {
+        let Input::ToKey { key_image, .. } = input else {
+            continue;
+        };
+        let key_image = key_image.compress().to_bytes();
+
+        let is_spent = database
+            .clone()
+            .oneshot(DatabaseRequest::KeyImagesSpent(vec![key_image]))
+            .await?
+            .into_key_images_spent()?;
+        if is_spent {
+            spent = Some(key_image);
+            break;
+        }
+    }
+    checks.push(RuleCheck::new(
+        "key_images_unspent",
+        match spent {
+            Some(key_image) => Err(TransactionError::KeyImageSpent(key_image)),
+            None => Ok(format!("{inputs} key images")),
+        },
+    ));
+
+    let rings = ring_members(tx);
+    let (unlocked, ring_ages) = check_rings_unlocked(&rings, context, database).await;
+    checks.push(RuleCheck::new("ring_members_unlocked", unlocked));
+
+    Ok(TxReport {
+        version: tx.prefix.version,
+        weight,
+        fee,
+        hf,
+        checks,
+        ring_ages,
+    })
+}
+
+/// Checks every ring member can be spent in the next block, returning the result of the
+/// `ring_members_unlocked` rule and the ages of the ring members.
+async fn check_rings_unlocked<D: Database>(
+    rings: &[Vec<(u64, u64)>],
+    context: &BlockChainContext,
+    database: D,
+) -> (Result<String, String>, Vec<Vec<u64>>) {
+    let members = rings.concat();
+    if members.is_empty() {
+        return (Ok("0 ring members".to_string()), vec![]);
+    }
+
+    let time_locks = match database
+        .oneshot(DatabaseRequest::OutputTimeLocks(members.clone()))
+        .await
+        .map_err(ConsensusError::from)
+        .and_then(|res| Ok(res.into_output_time_locks()?))
+    {
+        Ok(time_locks) => time_locks,
+        Err(e) => {
+            return (
+                Err(format!("The ring members couldn't be read: {e}")),
+                vec![],
+            )
+        }
+    };
+
+    let current_time = time_lock_check_time(context);
+    let unlocked = members
+        .iter()
+        .zip(&time_locks)
+        .try_for_each(|(member, output)| {
+            check_output_unlocked(
+                *member,
+                output,
+                context.chain_height,
+                current_time,
+                &context.current_hf,
+            )
+        })
+        .map(|()| format!("{} ring members", members.len()))
+        .map_err(|e| e.to_string());
+
+    let mut ages = time_locks
+        .iter()
+        .map(|output| context.chain_height.saturating_sub(output.height));
+    let ring_ages = rings
+        .iter()
+        .map(|ring| ages.by_ref().take(ring.len()).collect())
+        .collect();
+
+    (unlocked, ring_ages)
+}
+
+#[cfg(test)]
+mod tests {
+    use curve25519_dalek::constants::ED25519_BASEPOINT_POINT;
+    use futures::executor::block_on;
+    use monero_serai::transaction::Timelock;
+
+    use super::*;
+    use crate::outputs::OutputOnChain;
+    use crate::test_utils::{
+        dummy_context, dummy_miner_tx, dummy_output, DummyBlockExtendedHeader, DummyDatabaseBuilder,
+    };
+
+    fn context() -> BlockChainContext {
+        dummy_context(HardFork::V16)
+    }
+
+    /// Returns a version 2 transaction with no inputs paying this fee.
+    fn v2_tx(fee: u64) -> Transaction {
+        let mut tx = dummy_miner_tx(2, None, vec![dummy_output(None)]);
+        tx.rct_signatures.base.fee = fee;
+        tx
+    }
+
+    fn check<'a>(report: &'a TxReport, rule: &str) -> &'a RuleCheck {
+        report
+            .checks
+            .iter()
+            .find(|check| check.rule == rule)
+            .unwrap()
+    }
+
+    #[test]
+    fn every_rule_is_evaluated() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(100, DummyBlockExtendedHeader::default())
+            .finish();
+
+        // At the tail emission the minimum fee is 20,000 per byte less 2%.
+        let mut tx = v2_tx(29_400_000);
+        tx.prefix.extra = vec![0xff; MAX_TX_EXTRA_SIZE + 1];
+        let report = block_on(tx_report(&tx, 1_500, &context(), database.clone())).unwrap();
+
+        assert!(!report.passed());
+        let failures: Vec<_> = report.failures().map(|check| check.rule).collect();
+        assert_eq!(failures, ["inputs", "extra_size", "extra"]);
+        assert_eq!(
+            check(&report, "fee").details,
+            "fee 29400000, minimum 29400000"
+        );
+
+        let report = block_on(tx_report(&v2_tx(29_399_999), 1_500, &context(), database)).unwrap();
+        let failures: Vec<_> = report.failures().map(|check| check.rule).collect();
+        assert_eq!(failures, ["inputs", "fee"]);
+        assert_eq!(report.fee, Some(29_399_999));
+    }
+
+    #[test]
+    fn ring_member_ages() {
+        let output = |height, time_lock| OutputOnChain {
+            height,
+            time_lock,
+            key: [0; 32],
+            mask: [0; 32],
+            txid: [0; 32],
+        };
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(100, DummyBlockExtendedHeader::default())
+            .add_output(0, 0, output(10, Timelock::None))
+            .add_output(0, 1, output(95, Timelock::Block(101)))
+            .add_spent_key_image(ED25519_BASEPOINT_POINT.compress().to_bytes())
+            .finish();
+
+        let mut tx = v2_tx(u64::MAX);
+        tx.prefix.inputs = vec![Input::ToKey {
+            amount: None,
+            key_offsets: vec![0, 1],
+            key_image: ED25519_BASEPOINT_POINT,
+        }];
+        let report = block_on(tx_report(&tx, 1_500, &context(), database)).unwrap();
+
+        assert!(!check(&report, "key_images_unspent").passed);
+        assert_eq!(report.ring_ages, [vec![90, 5]]);
+        let unlocked = check(&report, "ring_members_unlocked");
+        assert!(!unlocked.passed);
+        assert_eq!(
+            unlocked.details,
+            TransactionError::OutputLocked {
+                amount: 0,
+                index: 1
+            }
+            .to_string()
+        );
+
+        // Members past the last output fail the rule, not the report.
+        let database = DummyDatabaseBuilder::default().finish();
+        let report = block_on(tx_report(&tx, 1_500, &context(), database)).unwrap();
+        assert!(!check(&report, "ring_members_unlocked").passed);
+        assert!(report.ring_ages.is_empty());
+    }
+}
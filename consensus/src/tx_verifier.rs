@@ -0,0 +1,406 @@
+//! # Tx Verifier
+//!
+//! This module contains [`TxVerifier`], the
+//! [`TxVerifierService`](crate::txpool::TxVerifierService) the
+//! [`TxPoolService`](crate::txpool::TxPoolService) verifies relayed transactions with. Each
+//! transaction is verified for the next block, with the latest [`BlockChainContext`] from a context
+//! service, see [`verify_tx`].
+//!
+//! The rules are the ones [`tx_report`](crate::tx_report) reports on, checked with the same functions
+//! from [`transactions`](crate::transactions), apart from the key images being unspent, which the
+//! pool checks against the chain and its own transactions. Unlike the report,
+//! verification stops at the first broken rule. The relay rules, the size and format of the extra
+//! and the minimum fee, are not peer faults: a transaction breaking them can still be mined.
+//!
+//! The ring signatures of version 1 transactions and the RingCT proofs of version 2 transactions
+//! are checked on the [`VerificationPool`] before the transaction is returned as a [`VerifiedTx`],
+//! like the transactions of blocks, see
+//! [`verify_tx_signatures`](crate::transactions::verify_tx_signatures).
+//!
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::FutureExt;
+use monero_serai::transaction::Transaction;
+use tower::ServiceExt;
+
+use crate::{
+    context::{BlockChainContext, ContextRequest, ContextResponse},
+    rule_flags::{RuleFlag, RuleFlags},
+    timings::BlockTimings,
+    transactions::{
+        check_ring_members_unlocked, check_tx_extra_size, check_tx_fee, check_tx_version,
+        check_tx_weight, parse_tx_extra, tx_fee, tx_key_images, verify_tx_signatures,
+    },
+    txpool::VerifiedTx,
+    verification_pool::VerificationPool,
+    ConsensusError, Database,
+};
+
+/// A [`tower::Service`] verifying transactions for the tx pool against the latest context.
+#[derive(Debug, Clone)]
+pub struct TxVerifier<C, D> {
+    context_svc: C,
+    database: D,
+    verification_pool: VerificationPool,
+    /// The relay rules are only checked once they are active, see [`RuleFlag`].
+    rule_flags: RuleFlags,
+}
+
+impl<C, D> TxVerifier<C, D> {
+    /// Returns a verifier getting the context from `context_svc`, checking signatures on
+    /// `verification_pool` and the relay rules active in `rule_flags`, which should be the
+    /// [`Verifier`](crate::verifier::Verifier)'s.
+    pub fn new(
+        context_svc: C,
+        database: D,
+        verification_pool: VerificationPool,
+        rule_flags: RuleFlags,
+    ) -> Self {
+        TxVerifier {
+            context_svc,
+            database,
+            verification_pool,
+            rule_flags,
+        }
+    }
+}
+
+impl<C, D> tower::Service<Transaction> for TxVerifier<C, D>
+where
+    C: tower::Service<ContextRequest, Response = ContextResponse, Error = ConsensusError>
+        + Clone
+        + Send
+        + 'static,
+    C::Future: Send + 'static,
+    D: Database + Clone + Send + 'static,
+    D::Future: Send + 'static,
+{
+    type Response = VerifiedTx;
+    type Error = ConsensusError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The context service and database are cloned for every request and we wait for them to be
+        // ready in the returned future.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, tx: Transaction) -> Self::Future {
+        let context_svc = self.context_svc.clone();
+        let database = self.database.clone();
+        let verification_pool = self.verification_pool.clone();
+        let rule_flags = self.rule_flags.clone();
+
+        async move {
+            let context = context_svc
+                .oneshot(ContextRequest::BlockChainContext)
+                .await?
+                .into_block_chain_context()?;
+
+            verify_tx(&tx, &context, &rule_flags, database, &verification_pool).await
+        }
+        .boxed()
+    }
+}
+
+/// Verifies the transaction can be added to the tx pool for the next block, apart from its key
+/// images being unspent, see the [module docs](self).
+///
+/// The extra's size is only checked if [`RuleFlag::TxExtraSizeLimit`] is active for the next block.
+pub async fn verify_tx<D: Database + Clone>(
+    tx: &Transaction,
+    context: &BlockChainContext,
+    rule_flags: &RuleFlags,
+    database: D,
+    verification_pool: &VerificationPool,
+) -> Result<VerifiedTx, ConsensusError> {
+    let hf = context.current_hf;
+    let weight = tx.weight();
+
+    check_tx_version(tx, &hf)?;
+    check_tx_weight(weight, &hf)?;
+    let key_images = tx_key_images(tx)?;
+    if rule_flags.is_active(RuleFlag::TxExtraSizeLimit, context.chain_height) {
+        check_tx_extra_size(&tx.prefix.extra)?;
+    }
+    parse_tx_extra(&tx.prefix.extra)?;
+
+    let fee = tx_fee(tx)?;
+    check_tx_fee(fee, weight, context)?;
+
+    check_ring_members_unlocked(tx, context, database.clone()).await?;
+
+    verify_tx_signatures(
+        std::slice::from_ref(tx),
+        &hf,
+        database,
+        verification_pool,
+        &mut BlockTimings::default(),
+    )
+    .await?;
+
+    Ok(VerifiedTx {
+        weight,
+        fee,
+        key_images,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cuprate_common::Network;
+    use curve25519_dalek::{constants::ED25519_BASEPOINT_POINT, Scalar};
+    use futures::{executor::block_on, future::ready};
+    use monero_serai::{
+        ringct::hash_to_point,
+        transaction::{Input, RingSignature, Timelock},
+    };
+
+    use super::*;
+    use crate::{
+        fork_metrics::ForkMetrics,
+        hardforks::HardFork,
+        outputs::OutputOnChain,
+        rule_flags::MAX_TX_EXTRA_SIZE,
+        test_utils::{
+            dummy_context, dummy_miner_tx, dummy_output, sign_v1_tx, DummyBlockExtendedHeader,
+            DummyDatabase, DummyDatabaseBuilder,
+        },
+        InternalError, TransactionError,
+    };
+
+    /// The amount of the output `v1_tx` spends.
+    const V1_AMOUNT: u64 = 10_000_000_000_000;
+
+    fn context() -> BlockChainContext {
+        dummy_context(HardFork::V16)
+    }
+
+    /// The secret key of the output `v1_tx` spends.
+    fn v1_secret() -> Scalar {
+        Scalar::from(7_u64)
+    }
+
+    fn database() -> DummyDatabase {
+        let output = |time_lock| OutputOnChain {
+            height: 10,
+            time_lock,
+            key: [0; 32],
+            mask: [0; 32],
+            txid: [0; 32],
+        };
+        DummyDatabaseBuilder::default()
+            .add_blocks(100, DummyBlockExtendedHeader::default())
+            .add_output(0, 0, output(Timelock::None))
+            .add_output(0, 1, output(Timelock::Block(101)))
+            .add_output(
+                V1_AMOUNT,
+                0,
+                OutputOnChain {
+                    key: (ED25519_BASEPOINT_POINT * v1_secret()).compress().0,
+                    ..output(Timelock::None)
+                },
+            )
+            .finish()
+    }
+
+    /// Returns a version 2 transaction spending one key image with a ring of the outputs at these
+    /// indices, paying more than the minimum fee.
+    fn v2_tx(ring: Vec<u64>) -> Transaction {
+        let mut tx = dummy_miner_tx(2, None, vec![dummy_output(None)]);
+        tx.prefix.inputs = vec![Input::ToKey {
+            amount: None,
+            key_offsets: ring,
+            key_image: ED25519_BASEPOINT_POINT,
+        }];
+        tx.rct_signatures.base.fee = 1_000_000_000;
+        tx
+    }
+
+    /// Returns a version 1 transaction spending the output of [`V1_AMOUNT`] with a fee of
+    /// 9,000,000,000,000, signed by its key.
+    fn v1_tx() -> Transaction {
+        let mut tx = dummy_miner_tx(1, None, vec![dummy_output(Some(1_000_000_000_000))]);
+        tx.prefix.inputs = vec![Input::ToKey {
+            amount: Some(V1_AMOUNT),
+            key_offsets: vec![0],
+            key_image: ED25519_BASEPOINT_POINT,
+        }];
+        tx.signatures = vec![RingSignature { sigs: vec![] }];
+        let ring = vec![ED25519_BASEPOINT_POINT * v1_secret()];
+        sign_v1_tx(&mut tx, &[v1_secret()], &[ring]);
+        tx
+    }
+
+    fn rule_flags() -> RuleFlags {
+        RuleFlags::for_network(&Network::Mainnet)
+    }
+
+    fn verify(tx: Transaction) -> Result<VerifiedTx, ConsensusError> {
+        verify_with(tx, context(), rule_flags())
+    }
+
+    fn verify_with(
+        tx: Transaction,
+        context: BlockChainContext,
+        rule_flags: RuleFlags,
+    ) -> Result<VerifiedTx, ConsensusError> {
+        let context_svc = tower::service_fn(move |_| {
+            ready(Ok::<_, ConsensusError>(ContextResponse::BlockChainContext(
+                context.clone(),
+            )))
+        });
+        let tx_verifier = TxVerifier::new(
+            context_svc,
+            database(),
+            VerificationPool::new(1),
+            rule_flags,
+        );
+        block_on(tx_verifier.oneshot(tx))
+    }
+
+    /// Returns the transaction error the transaction fails verification with.
+    fn tx_error(tx: Transaction) -> TransactionError {
+        match verify(tx).unwrap_err() {
+            ConsensusError::Transaction(e) => e,
+            e => panic!("Unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn valid_txs_return_their_key_images() {
+        let tx = v1_tx();
+        let verified = verify_with(tx.clone(), dummy_context(HardFork::V5), rule_flags()).unwrap();
+
+        let key_image = v1_secret() * hash_to_point(ED25519_BASEPOINT_POINT * v1_secret());
+        assert_eq!(verified.weight, tx.weight());
+        assert_eq!(verified.fee, 9_000_000_000_000);
+        assert_eq!(verified.key_images, [key_image.compress().to_bytes()]);
+
+        // The signature is of the prefix.
+        let mut tx = v1_tx();
+        tx.prefix.outputs[0].amount = Some(2_000_000_000_000);
+        assert!(matches!(
+            verify_with(tx, dummy_context(HardFork::V5), rule_flags()),
+            Err(ConsensusError::Transaction(
+                TransactionError::InvalidRingSignature(0)
+            ))
+        ));
+    }
+
+    /// No valid RingCT transaction is checked here, this tree has no recorded vector of one, so only
+    /// invalid proofs are.
+    #[test]
+    fn rct_proofs_are_checked() {
+        // `v2_tx` has no commitment for its output.
+        assert_eq!(
+            tx_error(v2_tx(vec![0])),
+            TransactionError::InvalidRangeProof
+        );
+
+        let mut tx = v2_tx(vec![0]);
+        tx.rct_signatures.base.commitments = vec![ED25519_BASEPOINT_POINT];
+        assert_eq!(tx_error(tx), TransactionError::RctTypeNotAllowed);
+    }
+
+    #[test]
+    fn wrong_context_responses_are_errors() {
+        let context_svc = tower::service_fn(|_| {
+            ready(Ok::<_, ConsensusError>(ContextResponse::AltChainStats(
+                ForkMetrics::default().stats(std::time::Instant::now()),
+            )))
+        });
+        let tx_verifier = TxVerifier::new(
+            context_svc,
+            database(),
+            VerificationPool::new(1),
+            rule_flags(),
+        );
+        assert!(matches!(
+            block_on(tx_verifier.oneshot(v2_tx(vec![0]))),
+            Err(ConsensusError::Internal(InternalError::ContextProtocol(_)))
+        ));
+    }
+
+    #[test]
+    fn inputs_are_checked() {
+        let mut tx = v2_tx(vec![0]);
+        tx.prefix.inputs.clear();
+        assert_eq!(tx_error(tx), TransactionError::NoInputs);
+
+        let miner_tx = dummy_miner_tx(2, Some(10), vec![dummy_output(None)]);
+        assert_eq!(tx_error(miner_tx), TransactionError::MinerInput);
+
+        let mut tx = v2_tx(vec![0]);
+        tx.prefix.inputs.push(tx.prefix.inputs[0].clone());
+        assert_eq!(
+            tx_error(tx),
+            TransactionError::DuplicateKeyImage(ED25519_BASEPOINT_POINT.compress().to_bytes())
+        );
+    }
+
+    #[test]
+    fn relay_rules_are_checked() {
+        let mut tx = v2_tx(vec![0]);
+        tx.prefix.extra = vec![0; MAX_TX_EXTRA_SIZE + 1];
+        let err = tx_error(tx);
+        assert_eq!(
+            err,
+            TransactionError::ExtraTooBig {
+                size: MAX_TX_EXTRA_SIZE + 1,
+                limit: MAX_TX_EXTRA_SIZE,
+            }
+        );
+        assert!(!err.is_peer_fault());
+
+        // The size limit is a rule flag, which can be disabled.
+        let mut flags = rule_flags();
+        flags.disable(RuleFlag::TxExtraSizeLimit);
+        let mut tx = v2_tx(vec![0]);
+        tx.prefix.extra = vec![0; MAX_TX_EXTRA_SIZE + 1];
+        assert!(!matches!(
+            verify_with(tx, context(), flags),
+            Err(ConsensusError::Transaction(
+                TransactionError::ExtraTooBig { .. }
+            ))
+        ));
+
+        let mut tx = v2_tx(vec![0]);
+        tx.prefix.extra = vec![0xff];
+        assert!(matches!(tx_error(tx), TransactionError::InvalidExtra(_)));
+
+        let mut tx = v2_tx(vec![0]);
+        tx.rct_signatures.base.fee = 1;
+        assert!(matches!(
+            tx_error(tx),
+            TransactionError::FeeTooLow { fee: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn ring_members_must_be_unlocked() {
+        assert_eq!(
+            tx_error(v2_tx(vec![0, 1])),
+            TransactionError::OutputLocked {
+                amount: 0,
+                index: 1
+            }
+        );
+    }
+
+    #[test]
+    fn version_1_txs_are_not_allowed_after_v6() {
+        let mut tx = v2_tx(vec![0]);
+        tx.prefix.version = 1;
+        assert_eq!(
+            tx_error(tx),
+            TransactionError::VersionNotAllowed {
+                version: 1,
+                hf: HardFork::V16
+            }
+        );
+    }
+}
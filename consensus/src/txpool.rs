@@ -0,0 +1,1057 @@
+//! # Tx Pool
+//!
+//! This module contains [`TxPool`], the pool of transactions waiting to be mined.
+//!
+//! Along with the transactions the pool keeps an index from the first 8 bytes of each transaction's hash
+//! to its entry. Transaction hashes are uniformly distributed so the prefix can be used directly as the
+//! hash map's hash, this makes looking up the transactions of a fluffy block cheap, and the transactions
+//! missing from the pool can be found without scanning the whole pool.
+//!
+//! The pool also keeps its transactions ordered by fee per byte, which is used to pick transactions
+//! for block templates and to decide what to evict when the pool is full, and an index of the key
+//! images spent by the pool so double spends between pool transactions are rejected.
+//!
+//! Transactions that stay in the pool for longer than [`TxPoolConfig::max_tx_age`] expire and are
+//! removed, see [`expire_transactions_task`].
+//!
+//! The pool remembers the transactions it removed for [`TxPoolConfig::removed_txs_age`], so wallets
+//! can be sent the changes to the pool since they last asked, see [`TxPool::changes_since`].
+//!
+//! [`TxPoolService`] wraps a shared [`TxPool`] in a [`tower::Service`], new transactions are run
+//! through a [`TxVerifierService`] and checked against the chain's spent key images before being
+//! added. If the service has a [`DecoyAnalyzer`] the rings of added transactions are analyzed, this is
+//! only logged and never stops a transaction being added.
+//!
+//! A [`TxPoolListener`] can be given to the service to be told about every transaction added to the
+//! pool, this is how subscribers outside of the pool, like ZMQ, are notified.
+//!
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::future::Future;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::FutureExt;
+use monero_serai::transaction::Transaction;
+use tower::ServiceExt;
+use tracing::Instrument;
+
+use crate::{
+    decoys::DecoyAnalyzer,
+    spans::{tx_span, TX_TARGET},
+    ConsensusError, Database, DatabaseRequest,
+};
+
+/// The default maximum weight of the pool, the same as monerod's.
+pub const DEFAULT_MAX_POOL_WEIGHT: usize = 648_000_000;
+/// The default age transactions are expired at, the same as monerod's.
+pub const DEFAULT_MAX_TX_AGE: Duration = Duration::from_secs(60 * 60 * 24 * 3);
+/// The default time removed transactions are remembered for.
+pub const DEFAULT_REMOVED_TXS_AGE: Duration = Duration::from_secs(60 * 30);
+/// The longest the expiry task will sleep for between checks.
+#[cfg(feature = "tokio")]
+const MAX_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum TxPoolError {
+    #[error("The transaction failed verification: {0}")]
+    Verification(#[from] ConsensusError),
+    #[error("The transaction spends a key image that is spent in the chain")]
+    KeyImageSpentInChain,
+    #[error("The transaction spends a key image that is spent by a transaction in the pool")]
+    DoubleSpendInPool,
+    #[error("The pool is full and the transaction's fee is too low to replace other transactions")]
+    PoolFull,
+    #[error("Database error: {0}")]
+    Database(tower::BoxError),
+}
+
+/// A transaction in the [`TxPool`].
+#[derive(Debug, Clone)]
+pub struct PoolTx {
+    pub tx: Transaction,
+    pub hash: [u8; 32],
+    pub weight: usize,
+    pub fee: u64,
+    /// The key images spent by this transaction.
+    pub key_images: Vec<[u8; 32]>,
+    /// The UNIX timestamp of when this transaction was added to the pool.
+    pub received_at: u64,
+}
+
+impl PoolTx {
+    fn fee_rate(&self) -> FeeRate {
+        FeeRate {
+            fee: self.fee,
+            weight: self.weight,
+        }
+    }
+}
+
+/// A transaction's fee per byte, kept as a fraction so comparisons are exact.
+#[derive(Debug, Clone, Copy)]
+struct FeeRate {
+    fee: u64,
+    weight: usize,
+}
+
+impl Ord for FeeRate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // fee_a / weight_a vs fee_b / weight_b, without the divisions.
+        (u128::from(self.fee) * other.weight as u128)
+            .cmp(&(u128::from(other.fee) * self.weight as u128))
+    }
+}
+
+impl PartialOrd for FeeRate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for FeeRate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FeeRate {}
+
+/// A [`Hasher`] for tx hash prefixes, the prefix is already uniformly distributed so it is used as is.
+#[derive(Debug, Default)]
+struct PrefixHasher(u64);
+
+impl Hasher for PrefixHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        // Only `write_u64` should be called, but fold any other bytes in just in case.
+        for byte in bytes {
+            self.0 = self.0.rotate_left(8) ^ u64::from(*byte);
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0 = i;
+    }
+}
+
+type PrefixIndex = HashMap<u64, Vec<usize>, BuildHasherDefault<PrefixHasher>>;
+
+fn hash_prefix(hash: &[u8; 32]) -> u64 {
+    u64::from_le_bytes(hash[0..8].try_into().unwrap())
+}
+
+/// The config for the [`TxPool`].
+#[derive(Debug, Clone, Copy)]
+pub struct TxPoolConfig {
+    /// The maximum total weight of the transactions in the pool, the transactions with the lowest
+    /// fee per byte are evicted to keep the pool under this.
+    pub max_weight: usize,
+    /// How long a transaction can stay in the pool before it expires.
+    pub max_tx_age: Duration,
+    /// How long removed transactions are remembered for, wallets that last asked for the pool's
+    /// changes longer ago than this are sent the whole pool.
+    pub removed_txs_age: Duration,
+}
+
+impl Default for TxPoolConfig {
+    fn default() -> Self {
+        TxPoolConfig {
+            max_weight: DEFAULT_MAX_POOL_WEIGHT,
+            max_tx_age: DEFAULT_MAX_TX_AGE,
+            removed_txs_age: DEFAULT_REMOVED_TXS_AGE,
+        }
+    }
+}
+
+/// Statistics about the [`TxPool`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxPoolStats {
+    pub txs: usize,
+    pub total_weight: usize,
+    /// The UNIX timestamp of when the oldest transaction in the pool was received.
+    pub oldest_received_at: Option<u64>,
+    /// The UNIX timestamp of when the next transaction expires.
+    pub next_expiry: Option<u64>,
+}
+
+/// The changes to the [`TxPool`] since a point in time, see [`TxPool::changes_since`].
+#[derive(Debug, Clone)]
+pub struct PoolChanges {
+    /// The transactions in the pool that were added since then, oldest first.
+    pub added: Vec<PoolTx>,
+    /// The hashes of the transactions removed since then.
+    pub removed: Vec<[u8; 32]>,
+}
+
+/// Returns the current UNIX timestamp.
+fn current_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// The transaction pool.
+#[derive(Debug, Default)]
+pub struct TxPool {
+    config: TxPoolConfig,
+    /// The pool's entries, removed entries are left as [`None`] to be reused.
+    entries: Vec<Option<PoolTx>>,
+    /// The indexes of the [`None`] entries.
+    free_entries: Vec<usize>,
+    /// A map of tx hash prefixes to the indexes of the entries with that prefix.
+    index: PrefixIndex,
+    /// The total weight of the transactions in the pool.
+    total_weight: usize,
+    /// The pool's transactions ordered by fee per byte, lowest first.
+    by_fee: BTreeSet<(FeeRate, [u8; 32])>,
+    /// The pool's transactions ordered by when they were received, oldest first.
+    by_age: BTreeSet<(u64, [u8; 32])>,
+    /// A map of the key images spent in the pool to the hash of the transaction spending them.
+    key_images: HashMap<[u8; 32], [u8; 32]>,
+    /// The transactions removed from the pool and when they were removed, oldest first.
+    removed: VecDeque<(u64, [u8; 32])>,
+    /// The UNIX timestamp from which every removed transaction is in `removed`.
+    removed_since: u64,
+}
+
+impl TxPool {
+    pub fn new(config: TxPoolConfig) -> TxPool {
+        TxPool {
+            config,
+            // Anything removed before we started is unknown.
+            removed_since: current_time(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the amount of transactions in the pool.
+    pub fn len(&self) -> usize {
+        self.entries.len() - self.free_entries.len()
+    }
+
+    /// Returns true if the pool is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the total weight of the transactions in the pool.
+    pub fn total_weight(&self) -> usize {
+        self.total_weight
+    }
+
+    /// Returns the index of the entry with this hash.
+    fn find(&self, hash: &[u8; 32]) -> Option<usize> {
+        self.index
+            .get(&hash_prefix(hash))?
+            .iter()
+            .copied()
+            .find(|idx| {
+                self.entries[*idx]
+                    .as_ref()
+                    .is_some_and(|tx| &tx.hash == hash)
+            })
+    }
+
+    /// Returns true if the transaction is in the pool.
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.find(hash).is_some()
+    }
+
+    /// Returns the transaction with this hash.
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&PoolTx> {
+        self.find(hash).and_then(|idx| self.entries[idx].as_ref())
+    }
+
+    /// Returns true if any of these key images are spent by a transaction in the pool.
+    pub fn key_images_spent(&self, key_images: &[[u8; 32]]) -> bool {
+        key_images
+            .iter()
+            .any(|key_image| self.key_images.contains_key(key_image))
+    }
+
+    /// Returns an iterator over the transactions in the pool, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &PoolTx> {
+        self.entries.iter().flatten()
+    }
+
+    /// Adds a transaction to the pool, returns false if the transaction was already in the pool.
+    ///
+    /// This does not check for double spends or the pool's weight limit, see [`TxPool::add_transaction`].
+    pub fn insert(&mut self, tx: PoolTx) -> bool {
+        if self.contains(&tx.hash) {
+            return false;
+        }
+
+        let prefix = hash_prefix(&tx.hash);
+        self.total_weight += tx.weight;
+        self.by_fee.insert((tx.fee_rate(), tx.hash));
+        self.by_age.insert((tx.received_at, tx.hash));
+        for key_image in &tx.key_images {
+            self.key_images.insert(*key_image, tx.hash);
+        }
+
+        let idx = match self.free_entries.pop() {
+            Some(idx) => {
+                self.entries[idx] = Some(tx);
+                idx
+            }
+            None => {
+                self.entries.push(Some(tx));
+                self.entries.len() - 1
+            }
+        };
+
+        self.index.entry(prefix).or_default().push(idx);
+        true
+    }
+
+    /// Removes the transaction with this hash from the pool.
+    pub fn remove(&mut self, hash: &[u8; 32]) -> Option<PoolTx> {
+        self.remove_at(hash, current_time())
+    }
+
+    /// Removes the transaction with this hash from the pool, it is remembered as removed at the
+    /// UNIX timestamp `now`.
+    fn remove_at(&mut self, hash: &[u8; 32], now: u64) -> Option<PoolTx> {
+        let idx = self.find(hash)?;
+        let prefix = hash_prefix(hash);
+
+        let idxs = self.index.get_mut(&prefix).unwrap();
+        idxs.retain(|entry| *entry != idx);
+        if idxs.is_empty() {
+            self.index.remove(&prefix);
+        }
+
+        let tx = self.entries[idx].take().unwrap();
+        self.free_entries.push(idx);
+        self.total_weight -= tx.weight;
+        self.by_fee.remove(&(tx.fee_rate(), tx.hash));
+        self.by_age.remove(&(tx.received_at, tx.hash));
+        for key_image in &tx.key_images {
+            self.key_images.remove(key_image);
+        }
+
+        self.removed.push_back((now, tx.hash));
+        let oldest_kept = now.saturating_sub(self.config.removed_txs_age.as_secs());
+        while let Some((removed_at, _)) = self
+            .removed
+            .front()
+            .copied()
+            .filter(|(removed_at, _)| *removed_at < oldest_kept)
+        {
+            self.removed_since = self.removed_since.max(removed_at + 1);
+            self.removed.pop_front();
+        }
+
+        Some(tx)
+    }
+
+    /// Adds a transaction to the pool, evicting the transactions with the lowest fee per byte if
+    /// the pool would go over its maximum weight.
+    ///
+    /// Returns false if the transaction was already in the pool.
+    pub fn add_transaction(&mut self, tx: PoolTx) -> Result<bool, TxPoolError> {
+        if self.contains(&tx.hash) {
+            return Ok(false);
+        }
+
+        if self.key_images_spent(&tx.key_images) {
+            return Err(TxPoolError::DoubleSpendInPool);
+        }
+
+        if tx.weight > self.config.max_weight {
+            return Err(TxPoolError::PoolFull);
+        }
+
+        let fee_rate = tx.fee_rate();
+        let mut to_evict = Vec::new();
+        let mut freed_weight = 0;
+
+        for (evict_fee_rate, hash) in self.by_fee.iter() {
+            if self.total_weight - freed_weight + tx.weight <= self.config.max_weight {
+                break;
+            }
+            // Only transactions paying less than the new one are evicted for it.
+            if *evict_fee_rate >= fee_rate {
+                return Err(TxPoolError::PoolFull);
+            }
+
+            to_evict.push(*hash);
+            freed_weight += self.get(hash).unwrap().weight;
+        }
+
+        for hash in to_evict {
+            tracing::debug!(target: TX_TARGET, hash = %hex::encode(hash), "Evicting tx from full pool");
+            self.remove(&hash);
+        }
+
+        Ok(self.insert(tx))
+    }
+
+    /// Removes the transactions spending any of these key images, returns the removed transactions.
+    pub fn remove_double_spends(&mut self, key_images: &[[u8; 32]]) -> Vec<PoolTx> {
+        let hashes = key_images
+            .iter()
+            .filter_map(|key_image| self.key_images.get(key_image).copied())
+            .collect::<Vec<_>>();
+
+        // A transaction spending more than one of the key images is only removed once.
+        hashes.iter().filter_map(|hash| self.remove(hash)).collect()
+    }
+
+    /// Returns the UNIX timestamp of when the next transaction expires.
+    pub fn next_expiry(&self) -> Option<u64> {
+        let (received_at, _) = self.by_age.first()?;
+        Some(received_at.saturating_add(self.config.max_tx_age.as_secs()))
+    }
+
+    /// Removes the transactions that have expired at the UNIX timestamp `now`, returns the removed
+    /// transactions.
+    pub fn remove_expired(&mut self, now: u64) -> Vec<PoolTx> {
+        let oldest_allowed = now.saturating_sub(self.config.max_tx_age.as_secs());
+
+        let expired = self
+            .by_age
+            .iter()
+            .take_while(|(received_at, _)| *received_at < oldest_allowed)
+            .map(|(_, hash)| *hash)
+            .collect::<Vec<_>>();
+
+        expired
+            .iter()
+            .filter_map(|hash| self.remove_at(hash, now))
+            .collect()
+    }
+
+    /// Returns the changes to the pool at or after the UNIX timestamp `since`, or [`None`] if
+    /// transactions removed that long ago have been forgotten.
+    pub fn changes_since(&self, since: u64) -> Option<PoolChanges> {
+        if since < self.removed_since {
+            return None;
+        }
+
+        Some(PoolChanges {
+            added: self
+                .by_age
+                .range((since, [0; 32])..)
+                .map(|(_, hash)| self.get(hash).unwrap().clone())
+                .collect(),
+            removed: self
+                .removed
+                .iter()
+                .filter(|(removed_at, _)| *removed_at >= since)
+                .map(|(_, hash)| *hash)
+                .collect(),
+        })
+    }
+
+    /// Returns the pool's statistics.
+    pub fn stats(&self) -> TxPoolStats {
+        TxPoolStats {
+            txs: self.len(),
+            total_weight: self.total_weight,
+            oldest_received_at: self.by_age.first().map(|(received_at, _)| *received_at),
+            next_expiry: self.next_expiry(),
+        }
+    }
+
+    /// Returns the transactions to put in a block template, highest fee per byte first, with a
+    /// total weight of at most `max_weight`.
+    pub fn block_template_txs(&self, max_weight: usize) -> Vec<&PoolTx> {
+        let mut weight = 0;
+        let mut txs = Vec::new();
+
+        for (_, hash) in self.by_fee.iter().rev() {
+            let tx = self.get(hash).unwrap();
+            // A smaller transaction might still fit, so keep looking.
+            if weight + tx.weight > max_weight {
+                continue;
+            }
+
+            weight += tx.weight;
+            txs.push(tx);
+        }
+
+        txs
+    }
+
+    /// Finds the transactions of a fluffy block in the pool, in the order of `tx_hashes`.
+    ///
+    /// If any transactions are not in the pool this returns the indexes of the missing transactions in
+    /// `tx_hashes`, which is what we need to request them with a `FluffyMissingTransactionsRequest`.
+    pub fn reconstruct_fluffy_block<'a>(
+        &'a self,
+        tx_hashes: &[[u8; 32]],
+    ) -> Result<Vec<&'a PoolTx>, Vec<u64>> {
+        let mut txs = Vec::with_capacity(tx_hashes.len());
+        let mut missing = Vec::new();
+
+        for (i, hash) in tx_hashes.iter().enumerate() {
+            match self.get(hash) {
+                Some(tx) => txs.push(tx),
+                None => missing.push(i as u64),
+            }
+        }
+
+        if missing.is_empty() {
+            Ok(txs)
+        } else {
+            Err(missing)
+        }
+    }
+}
+
+/// The result of verifying a transaction for the pool.
+#[derive(Debug, Clone)]
+pub struct VerifiedTx {
+    pub weight: usize,
+    pub fee: u64,
+    /// The key images spent by the transaction.
+    pub key_images: Vec<[u8; 32]>,
+}
+
+/// A service that fully verifies transactions, apart from checking their key images are unspent,
+/// like [`TxVerifier`](crate::tx_verifier::TxVerifier).
+pub trait TxVerifierService:
+    tower::Service<Transaction, Response = VerifiedTx, Error = ConsensusError>
+{
+}
+
+impl<T: tower::Service<Transaction, Response = VerifiedTx, Error = ConsensusError>>
+    TxVerifierService for T
+{
+}
+
+/// Told about transactions added to the pool by [`TxPoolService`].
+///
+/// This is called while handling the request so it should not block.
+pub trait TxPoolListener: std::fmt::Debug + Send + Sync {
+    /// Called after a new transaction was added to the pool.
+    fn tx_added(&self, tx: &PoolTx);
+}
+
+#[derive(Debug, Clone)]
+pub enum TxPoolRequest {
+    /// A transaction relayed to us, this is verified before being added to the pool.
+    NewTransaction(Transaction),
+    /// The transactions to put in a block template with a total weight of at most `max_weight`,
+    /// highest fee per byte first.
+    BlockTemplateTransactions { max_weight: usize },
+    /// Every transaction in the pool, for the `get_transaction_pool` RPC.
+    GetTransactionPool,
+    /// The transactions of a fluffy block, [`None`] for the transactions not in the pool.
+    FluffyBlockTxs(Vec<[u8; 32]>),
+    /// The pool's statistics.
+    Stats,
+    /// Remove the expired transactions from the pool, the removed transactions are returned.
+    RemoveExpired,
+    /// A block was added to the chain, the block's transactions and any transactions double
+    /// spending the block's key images are removed from the pool.
+    BlockAdded {
+        tx_hashes: Vec<[u8; 32]>,
+        key_images: Vec<[u8; 32]>,
+    },
+}
+
+#[derive(Debug)]
+pub enum TxPoolResponse {
+    /// The transaction was added to the pool or was already in it.
+    Ok,
+    Transactions(Vec<PoolTx>),
+    FluffyBlockTxs(Vec<Option<PoolTx>>),
+    Stats(TxPoolStats),
+}
+
+/// A [`tower::Service`] over a shared [`TxPool`], clones of this service share the same pool.
+#[derive(Debug, Clone)]
+pub struct TxPoolService<Tv, D> {
+    pool: Arc<Mutex<TxPool>>,
+    tx_verifier: Tv,
+    database: D,
+    decoy_analyzer: Option<DecoyAnalyzer>,
+    listener: Option<Arc<dyn TxPoolListener>>,
+}
+
+impl<Tv, D> TxPoolService<Tv, D> {
+    pub fn new(config: TxPoolConfig, tx_verifier: Tv, database: D) -> Self {
+        TxPoolService {
+            pool: Arc::new(Mutex::new(TxPool::new(config))),
+            tx_verifier,
+            database,
+            decoy_analyzer: None,
+            listener: None,
+        }
+    }
+
+    /// Analyzes the rings of the transactions added to the pool with this analyzer.
+    pub fn with_decoy_analyzer(mut self, decoy_analyzer: DecoyAnalyzer) -> Self {
+        self.decoy_analyzer = Some(decoy_analyzer);
+        self
+    }
+
+    /// Tells this listener about every transaction added to the pool.
+    pub fn with_listener(mut self, listener: Arc<dyn TxPoolListener>) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Returns the shared pool.
+    pub fn pool(&self) -> &Arc<Mutex<TxPool>> {
+        &self.pool
+    }
+}
+
+impl<Tv, D> tower::Service<TxPoolRequest> for TxPoolService<Tv, D>
+where
+    Tv: TxVerifierService + Clone + Send + 'static,
+    Tv::Future: Send + 'static,
+    D: Database + Clone + Send + 'static,
+    D::Future: Send + 'static,
+{
+    type Response = TxPoolResponse;
+    type Error = TxPoolError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The verifier and database are cloned for every request and we wait for them to be
+        // ready in the returned future.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: TxPoolRequest) -> Self::Future {
+        let pool = self.pool.clone();
+
+        match req {
+            TxPoolRequest::NewTransaction(tx) => {
+                let span = tx_span(&tx.hash());
+                add_new_transaction(
+                    tx,
+                    pool,
+                    self.tx_verifier.clone(),
+                    self.database.clone(),
+                    self.decoy_analyzer.clone(),
+                    self.listener.clone(),
+                )
+                .instrument(span)
+                .boxed()
+            }
+            TxPoolRequest::BlockTemplateTransactions { max_weight } => {
+                let txs = pool
+                    .lock()
+                    .unwrap()
+                    .block_template_txs(max_weight)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                futures::future::ready(Ok(TxPoolResponse::Transactions(txs))).boxed()
+            }
+            TxPoolRequest::GetTransactionPool => {
+                let txs = pool.lock().unwrap().iter().cloned().collect();
+                futures::future::ready(Ok(TxPoolResponse::Transactions(txs))).boxed()
+            }
+            TxPoolRequest::FluffyBlockTxs(tx_hashes) => {
+                let pool = pool.lock().unwrap();
+                let txs = tx_hashes
+                    .iter()
+                    .map(|hash| pool.get(hash).cloned())
+                    .collect();
+                futures::future::ready(Ok(TxPoolResponse::FluffyBlockTxs(txs))).boxed()
+            }
+            TxPoolRequest::Stats => {
+                let stats = pool.lock().unwrap().stats();
+                futures::future::ready(Ok(TxPoolResponse::Stats(stats))).boxed()
+            }
+            TxPoolRequest::RemoveExpired => {
+                let txs = pool.lock().unwrap().remove_expired(current_time());
+                futures::future::ready(Ok(TxPoolResponse::Transactions(txs))).boxed()
+            }
+            TxPoolRequest::BlockAdded {
+                tx_hashes,
+                key_images,
+            } => {
+                let mut pool = pool.lock().unwrap();
+                for hash in &tx_hashes {
+                    pool.remove(hash);
+                }
+                pool.remove_double_spends(&key_images);
+                futures::future::ready(Ok(TxPoolResponse::Ok)).boxed()
+            }
+        }
+    }
+}
+
+async fn add_new_transaction<Tv: TxVerifierService, D: Database + Clone>(
+    tx: Transaction,
+    pool: Arc<Mutex<TxPool>>,
+    tx_verifier: Tv,
+    database: D,
+    decoy_analyzer: Option<DecoyAnalyzer>,
+    listener: Option<Arc<dyn TxPoolListener>>,
+) -> Result<TxPoolResponse, TxPoolError> {
+    let hash = tx.hash();
+    if pool.lock().unwrap().contains(&hash) {
+        return Ok(TxPoolResponse::Ok);
+    }
+
+    let verified = tx_verifier.oneshot(tx.clone()).await?;
+
+    // Check the pool first, this doesn't need a database request.
+    if pool.lock().unwrap().key_images_spent(&verified.key_images) {
+        return Err(TxPoolError::DoubleSpendInPool);
+    }
+
+    let spent = database
+        .clone()
+        .oneshot(DatabaseRequest::KeyImagesSpent(verified.key_images.clone()))
+        .await
+        .map_err(TxPoolError::Database)?
+        .into_key_images_spent()
+        .map_err(|e| TxPoolError::Database(e.into()))?;
+
+    if spent {
+        return Err(TxPoolError::KeyImageSpentInChain);
+    }
+
+    // Another transaction with the same key images could have been added while we were waiting, so
+    // the pool checks for double spends again when adding.
+    let analyzed_tx = decoy_analyzer.as_ref().map(|_| tx.clone());
+
+    let pool_tx = PoolTx {
+        tx,
+        hash,
+        weight: verified.weight,
+        fee: verified.fee,
+        key_images: verified.key_images,
+        received_at: current_time(),
+    };
+    let added_tx = listener.as_ref().map(|_| pool_tx.clone());
+
+    let added = pool.lock().unwrap().add_transaction(pool_tx)?;
+
+    if let (true, Some(listener), Some(tx)) = (added, listener, added_tx) {
+        listener.tx_added(&tx);
+    }
+
+    if let (Some(decoy_analyzer), Some(tx)) = (decoy_analyzer, analyzed_tx) {
+        match decoy_analyzer.analyze(&tx, database).await {
+            Ok(anomalies) if !anomalies.is_empty() => {
+                tracing::info!(target: TX_TARGET, "Tx has anomalous rings: {:?}", anomalies)
+            }
+            Ok(_) => (),
+            Err(e) => tracing::debug!(target: TX_TARGET, "Failed to analyze the tx's rings: {}", e),
+        }
+    }
+
+    Ok(TxPoolResponse::Ok)
+}
+
+/// Removes expired transactions from the pool as they expire, this runs until the pool returns an
+/// error.
+///
+/// Expiry goes through the pool service so `on_expired` is called with the removed transactions,
+/// which should be used to remove them from anywhere else the pool is stored, like on disk.
+#[cfg(feature = "tokio")]
+pub async fn expire_transactions_task<P>(
+    mut pool: P,
+    mut on_expired: impl FnMut(Vec<PoolTx>),
+) -> Result<(), TxPoolError>
+where
+    P: tower::Service<TxPoolRequest, Response = TxPoolResponse, Error = TxPoolError>,
+{
+    loop {
+        let TxPoolResponse::Transactions(expired) = pool
+            .ready()
+            .await?
+            .call(TxPoolRequest::RemoveExpired)
+            .await?
+        else {
+            panic!("Pool sent incorrect response!");
+        };
+
+        if !expired.is_empty() {
+            tracing::debug!(target: TX_TARGET, "Expired {} txs from the pool", expired.len());
+            on_expired(expired);
+        }
+
+        let TxPoolResponse::Stats(stats) = pool.ready().await?.call(TxPoolRequest::Stats).await?
+        else {
+            panic!("Pool sent incorrect response!");
+        };
+
+        // New transactions can't expire before the ones already in the pool, but they can be
+        // added to an empty pool at any time.
+        let sleep_for = stats
+            .next_expiry
+            .map(|next_expiry| Duration::from_secs(next_expiry.saturating_sub(current_time()) + 1))
+            .unwrap_or(MAX_EXPIRY_CHECK_INTERVAL)
+            .min(MAX_EXPIRY_CHECK_INTERVAL);
+
+        tokio::time::sleep(sleep_for).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tower::ServiceExt;
+
+    use cuprate_common::Network;
+
+    use super::*;
+    use crate::genesis::generate_genesis_block;
+    use crate::test_utils::DummyDatabaseBuilder;
+
+    fn pool_tx(hash: [u8; 32]) -> PoolTx {
+        PoolTx {
+            tx: generate_genesis_block(&Network::Mainnet).miner_tx,
+            hash,
+            weight: 100,
+            fee: 0,
+            key_images: vec![hash],
+            received_at: 0,
+        }
+    }
+
+    fn pool_tx_with_fee(hash: [u8; 32], weight: usize, fee: u64) -> PoolTx {
+        PoolTx {
+            weight,
+            fee,
+            ..pool_tx(hash)
+        }
+    }
+
+    #[test]
+    fn reconstruct_fluffy_block() {
+        let mut pool = TxPool::default();
+
+        // Same prefix, different hash.
+        let mut colliding_hash = [1; 32];
+        colliding_hash[31] = 2;
+
+        assert!(pool.insert(pool_tx([1; 32])));
+        assert!(pool.insert(pool_tx(colliding_hash)));
+        assert!(pool.insert(pool_tx([3; 32])));
+        assert!(!pool.insert(pool_tx([3; 32])));
+        assert_eq!(pool.len(), 3);
+        assert_eq!(pool.total_weight(), 300);
+
+        let txs = pool
+            .reconstruct_fluffy_block(&[[3; 32], colliding_hash, [1; 32]])
+            .unwrap();
+        assert_eq!(txs[1].hash, colliding_hash);
+
+        pool.remove(&[1; 32]).unwrap();
+        assert_eq!(
+            pool.reconstruct_fluffy_block(&[[4; 32], colliding_hash, [1; 32]])
+                .unwrap_err(),
+            vec![0, 2]
+        );
+
+        // The removed entry is reused.
+        assert!(pool.insert(pool_tx([4; 32])));
+        assert_eq!(pool.entries.len(), 3);
+        assert!(pool.contains(&[4; 32]));
+    }
+
+    #[test]
+    fn double_spends_in_pool_are_rejected() {
+        let mut pool = TxPool::default();
+
+        assert!(pool.add_transaction(pool_tx([1; 32])).unwrap());
+
+        let mut double_spend = pool_tx([2; 32]);
+        double_spend.key_images = vec![[5; 32], [1; 32]];
+        assert!(matches!(
+            pool.add_transaction(double_spend),
+            Err(TxPoolError::DoubleSpendInPool)
+        ));
+
+        // The key images are freed when the transaction is removed.
+        assert_eq!(pool.remove_double_spends(&[[1; 32], [1; 32]]).len(), 1);
+        assert!(pool.is_empty());
+        assert!(!pool.key_images_spent(&[[1; 32]]));
+    }
+
+    #[test]
+    fn changes_since() {
+        let mut pool = TxPool::new(TxPoolConfig {
+            removed_txs_age: Duration::from_secs(100),
+            ..Default::default()
+        });
+        pool.removed_since = 0;
+
+        let received_at = |hash, received_at| PoolTx {
+            received_at,
+            ..pool_tx(hash)
+        };
+        pool.insert(received_at([1; 32], 10));
+        pool.insert(received_at([2; 32], 20));
+        pool.insert(received_at([3; 32], 30));
+        pool.remove_at(&[2; 32], 40);
+
+        let changes = pool.changes_since(20).unwrap();
+        assert_eq!(
+            changes.added.iter().map(|tx| tx.hash).collect::<Vec<_>>(),
+            vec![[3; 32]]
+        );
+        assert_eq!(changes.removed, vec![[2; 32]]);
+        assert!(pool.changes_since(41).unwrap().removed.is_empty());
+
+        // The removal at 40 is forgotten.
+        pool.remove_at(&[1; 32], 150);
+        assert!(pool.changes_since(40).is_none());
+        assert_eq!(pool.changes_since(41).unwrap().removed, vec![[1; 32]]);
+    }
+
+    #[test]
+    fn full_pool_evicts_lowest_fee_rate() {
+        let mut pool = TxPool::new(TxPoolConfig {
+            max_weight: 300,
+            ..Default::default()
+        });
+
+        pool.add_transaction(pool_tx_with_fee([1; 32], 100, 300))
+            .unwrap();
+        pool.add_transaction(pool_tx_with_fee([2; 32], 100, 100))
+            .unwrap();
+        pool.add_transaction(pool_tx_with_fee([3; 32], 100, 200))
+            .unwrap();
+
+        // Paying less than everything in the pool.
+        assert!(matches!(
+            pool.add_transaction(pool_tx_with_fee([4; 32], 100, 50)),
+            Err(TxPoolError::PoolFull)
+        ));
+        // Equal fee per byte to the lowest isn't enough either.
+        assert!(matches!(
+            pool.add_transaction(pool_tx_with_fee([4; 32], 200, 200)),
+            Err(TxPoolError::PoolFull)
+        ));
+
+        // Needs both the two lowest transactions evicted.
+        pool.add_transaction(pool_tx_with_fee([4; 32], 200, 500))
+            .unwrap();
+        assert!(pool.contains(&[1; 32]));
+        assert!(!pool.contains(&[2; 32]));
+        assert!(!pool.contains(&[3; 32]));
+        assert_eq!(pool.total_weight(), 300);
+    }
+
+    #[test]
+    fn block_template_txs_highest_fee_rate_first() {
+        let mut pool = TxPool::default();
+
+        pool.insert(pool_tx_with_fee([1; 32], 100, 100));
+        pool.insert(pool_tx_with_fee([2; 32], 300, 900));
+        pool.insert(pool_tx_with_fee([3; 32], 50, 100));
+        pool.insert(pool_tx_with_fee([4; 32], 200, 1000));
+
+        let hashes = |txs: Vec<&PoolTx>| txs.iter().map(|tx| tx.hash[0]).collect::<Vec<_>>();
+
+        assert_eq!(
+            hashes(pool.block_template_txs(usize::MAX)),
+            vec![4, 2, 3, 1]
+        );
+        // [2; 32] doesn't fit but the smaller transactions after it do.
+        assert_eq!(hashes(pool.block_template_txs(400)), vec![4, 3, 1]);
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingListener(Mutex<Vec<[u8; 32]>>);
+
+    impl TxPoolListener for RecordingListener {
+        fn tx_added(&self, tx: &PoolTx) {
+            self.0.lock().unwrap().push(tx.hash);
+        }
+    }
+
+    #[test]
+    fn service_rejects_key_images_spent_in_chain() {
+        let database = DummyDatabaseBuilder::default()
+            .add_spent_key_image([1; 32])
+            .finish();
+
+        let verifier = |key_images: Vec<[u8; 32]>| {
+            tower::service_fn(move |_: Transaction| {
+                futures::future::ready(Ok::<_, ConsensusError>(VerifiedTx {
+                    weight: 100,
+                    fee: 100,
+                    key_images: key_images.clone(),
+                }))
+            })
+        };
+        let tx = generate_genesis_block(&Network::Mainnet).miner_tx;
+
+        let svc = TxPoolService::new(
+            TxPoolConfig::default(),
+            verifier(vec![[1; 32]]),
+            database.clone(),
+        );
+        assert!(matches!(
+            block_on(svc.oneshot(TxPoolRequest::NewTransaction(tx.clone()))),
+            Err(TxPoolError::KeyImageSpentInChain)
+        ));
+
+        let listener = Arc::new(RecordingListener::default());
+        let svc = TxPoolService::new(TxPoolConfig::default(), verifier(vec![[2; 32]]), database)
+            .with_listener(listener.clone());
+        for _ in 0..2 {
+            block_on(
+                svc.clone()
+                    .oneshot(TxPoolRequest::NewTransaction(tx.clone())),
+            )
+            .unwrap();
+        }
+        // The listener is only told about the transaction the first time it's added.
+        assert_eq!(*listener.0.lock().unwrap(), vec![tx.hash()]);
+
+        let TxPoolResponse::Transactions(txs) =
+            block_on(svc.clone().oneshot(TxPoolRequest::GetTransactionPool)).unwrap()
+        else {
+            panic!("Pool sent incorrect response!");
+        };
+        assert_eq!(txs.len(), 1);
+
+        block_on(svc.clone().oneshot(TxPoolRequest::BlockAdded {
+            tx_hashes: vec![],
+            key_images: vec![[2; 32]],
+        }))
+        .unwrap();
+        assert!(svc.pool().lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn old_transactions_expire() {
+        let day = 60 * 60 * 24;
+        let mut pool = TxPool::default();
+        assert_eq!(pool.next_expiry(), None);
+
+        pool.insert(PoolTx {
+            received_at: 10 * day,
+            ..pool_tx([1; 32])
+        });
+        pool.insert(PoolTx {
+            received_at: 11 * day,
+            ..pool_tx([2; 32])
+        });
+
+        let stats = pool.stats();
+        assert_eq!(stats.oldest_received_at, Some(10 * day));
+        assert_eq!(stats.next_expiry, Some(13 * day));
+
+        assert!(pool.remove_expired(13 * day).is_empty());
+        let expired = pool.remove_expired(13 * day + 1);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].hash, [1; 32]);
+        assert_eq!(pool.next_expiry(), Some(14 * day));
+    }
+}
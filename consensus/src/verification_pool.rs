@@ -0,0 +1,163 @@
+//! # Verification Pool
+//!
+//! This module contains [`VerificationPool`], a pool of OS threads that PoW and signature checks are
+//! sent to, so the CPU heavy parts of verification never block the async runtime that is also
+//! waiting on the database. Each check returns a [`VerificationHandle`] which is awaited for the
+//! result, so checks of different transactions or blocks run on every core at once during the
+//! initial sync.
+//!
+//! A check that panics doesn't take its thread down, the panic is resumed in the task awaiting the
+//! handle.
+//!
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+
+use futures::{channel::oneshot, FutureExt};
+
+use crate::block::pow::check_block_pow;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A pool of threads for CPU heavy checks, clones of this send to the same threads.
+///
+/// The threads stop once every clone is dropped and the queued checks are done.
+#[derive(Debug, Clone)]
+pub struct VerificationPool {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Default for VerificationPool {
+    /// Returns a pool with a thread for each core.
+    fn default() -> Self {
+        VerificationPool::new(thread::available_parallelism().map_or(1, NonZeroUsize::get))
+    }
+}
+
+impl VerificationPool {
+    /// Returns a pool with this amount of threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is 0 or a thread can't be spawned.
+    pub fn new(threads: usize) -> VerificationPool {
+        assert_ne!(threads, 0, "The pool needs a thread to run checks");
+
+        let (jobs, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        for i in 0..threads {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("verification-{i}"))
+                .spawn(move || loop {
+                    // The lock is released before the job runs, so the other threads can take jobs.
+                    let job = rx.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => return,
+                    }
+                })
+                .expect("Failed to spawn a verification thread");
+        }
+
+        VerificationPool { jobs }
+    }
+
+    /// Runs `check` on the pool, the returned handle resolves to its result.
+    pub fn spawn<R: Send + 'static>(
+        &self,
+        check: impl FnOnce() -> R + Send + 'static,
+    ) -> VerificationHandle<R> {
+        let (tx, rx) = oneshot::channel();
+
+        let job: Job = Box::new(move || {
+            // The receiver is gone if the handle was dropped, the result isn't wanted.
+            let _ = tx.send(catch_unwind(AssertUnwindSafe(check)));
+        });
+        self.jobs
+            .send(job)
+            .expect("The pool's threads only stop once every sender is dropped");
+
+        VerificationHandle(rx)
+    }
+
+    /// Checks a block's PoW on the pool, `pow_hash` calculates the block's PoW hash. The handle
+    /// resolves to the hash if it is valid for the difficulty, [`None`] if it isn't.
+    pub fn check_pow<E: Send + 'static>(
+        &self,
+        pow_hash: impl FnOnce() -> Result<[u8; 32], E> + Send + 'static,
+        difficulty: u128,
+    ) -> VerificationHandle<Result<Option<[u8; 32]>, E>> {
+        self.spawn(move || {
+            pow_hash().map(|hash| check_block_pow(&hash, difficulty).then_some(hash))
+        })
+    }
+}
+
+/// The result of a check running on a [`VerificationPool`].
+#[derive(Debug)]
+pub struct VerificationHandle<R>(oneshot::Receiver<thread::Result<R>>);
+
+impl<R> Future for VerificationHandle<R> {
+    type Output = R;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.0.poll_unpin(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Ok(Ok(res))) => Poll::Ready(res),
+            Poll::Ready(Ok(Err(panic))) => resume_unwind(panic),
+            Poll::Ready(Err(_)) => unreachable!("Jobs always send their result"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+
+    use futures::executor::block_on;
+
+    use super::*;
+
+    #[test]
+    fn checks_run_in_parallel() {
+        let pool = VerificationPool::new(2);
+        let barrier = Arc::new(Barrier::new(2));
+
+        // Neither check can finish until both are running.
+        let handles: Vec<_> = (0..2)
+            .map(|i| {
+                let barrier = barrier.clone();
+                pool.spawn(move || {
+                    barrier.wait();
+                    i
+                })
+            })
+            .collect();
+
+        assert_eq!(block_on(futures::future::join_all(handles)), vec![0, 1]);
+    }
+
+    #[test]
+    fn panics_are_resumed_in_the_awaiting_task() {
+        let pool = VerificationPool::new(1);
+
+        let handle = pool.spawn(|| panic!("Invalid check"));
+        assert!(catch_unwind(AssertUnwindSafe(|| block_on(handle))).is_err());
+
+        // The thread is still running checks.
+        assert_eq!(
+            block_on(pool.check_pow(|| Ok::<_, ()>([0; 32]), u128::MAX)),
+            Ok(Some([0; 32]))
+        );
+        assert_eq!(
+            block_on(pool.check_pow(|| Ok::<_, ()>([0xff; 32]), 2)),
+            Ok(None)
+        );
+    }
+}
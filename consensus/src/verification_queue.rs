@@ -0,0 +1,224 @@
+//! # Verification Queue
+//!
+//! This module contains the queue the block verifier takes its work from.
+//!
+//! Work is queued with a [`Priority`], new tip blocks (including fluffy blocks) are [`Priority::Tip`] and
+//! batches from the initial sync backlog are [`Priority::Backlog`]. Backlog batches are handed out a
+//! block at a time, so when a tip block arrives while a backlog batch is being verified it is verified
+//! next, before the rest of the batch.
+//!
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+
+use futures::future::poll_fn;
+
+/// The priority of queued verification work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Blocks from the initial sync backlog.
+    Backlog,
+    /// New blocks on the top of the chain.
+    Tip,
+}
+
+/// A queue of verification work.
+#[derive(Debug)]
+pub struct VerificationQueue<T> {
+    tip: VecDeque<T>,
+    /// The backlog batches, the batch at the front is the one currently being verified.
+    backlog: VecDeque<VecDeque<T>>,
+}
+
+impl<T> Default for VerificationQueue<T> {
+    fn default() -> Self {
+        VerificationQueue {
+            tip: VecDeque::new(),
+            backlog: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> VerificationQueue<T> {
+    /// Queues a tip block.
+    pub fn push_tip(&mut self, item: T) {
+        self.tip.push_back(item);
+    }
+
+    /// Queues a batch of backlog blocks, the blocks will be handed out in order.
+    pub fn push_backlog_batch(&mut self, batch: impl IntoIterator<Item = T>) {
+        let batch: VecDeque<T> = batch.into_iter().collect();
+        if !batch.is_empty() {
+            self.backlog.push_back(batch);
+        }
+    }
+
+    /// Returns the next item to verify, tip blocks are always returned before backlog blocks.
+    pub fn pop(&mut self) -> Option<(Priority, T)> {
+        if let Some(item) = self.tip.pop_front() {
+            return Some((Priority::Tip, item));
+        }
+
+        let batch = self.backlog.front_mut()?;
+        let item = batch.pop_front().expect("Empty batches are removed");
+        if batch.is_empty() {
+            self.backlog.pop_front();
+        }
+
+        Some((Priority::Backlog, item))
+    }
+
+    /// Returns the amount of items queued.
+    pub fn len(&self) -> usize {
+        self.tip.len() + self.backlog.iter().map(VecDeque::len).sum::<usize>()
+    }
+
+    /// Returns true if nothing is queued.
+    pub fn is_empty(&self) -> bool {
+        self.tip.is_empty() && self.backlog.is_empty()
+    }
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    queue: VerificationQueue<T>,
+    waker: Option<Waker>,
+    senders: usize,
+}
+
+/// Creates a [`VerificationQueue`] shared between [`QueueSender`]s and a single [`QueueReceiver`],
+/// the receiver should be owned by the task running the verifier.
+pub fn verification_queue<T>() -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Mutex::new(Shared {
+        queue: VerificationQueue::default(),
+        waker: None,
+        senders: 1,
+    }));
+
+    (
+        QueueSender {
+            shared: shared.clone(),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+/// The sending side of a shared [`VerificationQueue`].
+#[derive(Debug)]
+pub struct QueueSender<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> QueueSender<T> {
+    fn push(&self, f: impl FnOnce(&mut VerificationQueue<T>)) {
+        let mut shared = self.shared.lock().unwrap();
+        f(&mut shared.queue);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Queues a tip block.
+    pub fn push_tip(&self, item: T) {
+        self.push(|queue| queue.push_tip(item))
+    }
+
+    /// Queues a batch of backlog blocks.
+    pub fn push_backlog_batch(&self, batch: impl IntoIterator<Item = T>) {
+        self.push(|queue| queue.push_backlog_batch(batch))
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().unwrap().senders += 1;
+        QueueSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.senders -= 1;
+        if shared.senders == 0 {
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// The receiving side of a shared [`VerificationQueue`].
+#[derive(Debug)]
+pub struct QueueReceiver<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> QueueReceiver<T> {
+    /// Waits for the next item to verify.
+    ///
+    /// Returns [`None`] when the queue is empty and every [`QueueSender`] has been dropped.
+    pub async fn next(&mut self) -> Option<(Priority, T)> {
+        poll_fn(|cx| {
+            let mut shared = self.shared.lock().unwrap();
+
+            if let Some(item) = shared.queue.pop() {
+                return Poll::Ready(Some(item));
+            }
+
+            if shared.senders == 0 {
+                return Poll::Ready(None);
+            }
+
+            shared.waker = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::{verification_queue, Priority, VerificationQueue};
+
+    #[test]
+    fn tip_blocks_preempt_backlog() {
+        let mut queue = VerificationQueue::default();
+        queue.push_backlog_batch([1, 2, 3]);
+        queue.push_backlog_batch([4]);
+
+        assert_eq!(queue.pop(), Some((Priority::Backlog, 1)));
+
+        queue.push_tip(100);
+        assert_eq!(queue.len(), 4);
+
+        assert_eq!(queue.pop(), Some((Priority::Tip, 100)));
+        assert_eq!(queue.pop(), Some((Priority::Backlog, 2)));
+        assert_eq!(queue.pop(), Some((Priority::Backlog, 3)));
+        assert_eq!(queue.pop(), Some((Priority::Backlog, 4)));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn receiver_finishes_when_senders_dropped() {
+        let (sender, mut receiver) = verification_queue();
+
+        let other_sender = sender.clone();
+        sender.push_backlog_batch([1, 2]);
+        other_sender.push_tip(3);
+        drop(sender);
+
+        assert_eq!(block_on(receiver.next()), Some((Priority::Tip, 3)));
+
+        drop(other_sender);
+
+        assert_eq!(block_on(receiver.next()), Some((Priority::Backlog, 1)));
+        assert_eq!(block_on(receiver.next()), Some((Priority::Backlog, 2)));
+        assert_eq!(block_on(receiver.next()), None);
+    }
+}
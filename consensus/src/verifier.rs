@@ -1,24 +1,249 @@
-use futures::join;
-use monero_serai::{block::Block, transaction::Transaction};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use monero_serai::block::Block;
 use tower::ServiceExt;
 use tracing::instrument;
 
+use cuprate_common::{Network, PruningSeed};
+
 use crate::{
-    block::{pow::difficulty::DifficultyCache, weight::BlockWeightsCache},
-    hardforks::{HardForkConfig, HardForkState},
-    ConsensusError, Database, DatabaseRequest, DatabaseResponse,
+    alt_chain::AltChainContextCache,
+    block::{
+        pow::{difficulty::DifficultyCache, RandomXCaches},
+        weight::BlockWeightsCache,
+    },
+    checkpoints::Checkpoints,
+    consensus_constants::ConsensusConstants,
+    context::{
+        compare_chains, BlockChainContext, ChainChoice, ChainTip, ContextCacheInit, ContextDump,
+    },
+    fork_metrics::{AltChainStats, ForkMetrics},
+    genesis::seed_genesis_block,
+    hardforks::{ConsistencyCheck, HardForkConfig, HardForkState, UnknownForkPolicy},
+    misbehaviour::{BlockSource, MisbehaviourListener, MisbehaviourReport, Severity},
+    rule_flags::{RuleFlag, RuleFlags},
+    spans::{BLOCK_TARGET, CONTEXT_TARGET},
+    speculative::SpeculativeContext,
+    timings::{BlockTimings, StageHistograms},
+    verification_pool::VerificationPool,
+    BlockError, ConsensusError, Database, DatabaseRequest, InternalError,
 };
 
+/// How signatures should be verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignaturePolicy {
+    /// Verify every signature.
+    VerifyAll,
+    /// Skip signature verification for blocks below this height.
+    SkipBelow(u64),
+}
+
+/// The options that control how much verification is done on blocks.
+///
+/// These should be created from a [`VerificationProfile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerificationOptions {
+    /// Skip PoW checks for blocks below this height.
+    pub skip_pow_below: u64,
+    /// How signatures should be verified.
+    pub signatures: SignaturePolicy,
+    /// Reject blocks that conflict with known checkpoints.
+    pub enforce_checkpoints: bool,
+    /// Check values we calculate against the values stored in the database once blocks are written
+    /// (cumulative difficulty and generated coins), this is slow and is only useful for finding bugs
+    /// or database corruption, see [`Verifier::cross_check_database`].
+    pub cross_check_database: bool,
+}
+
+/// A named bundle of [`VerificationOptions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationProfile {
+    /// Verify everything, this is the default.
+    Full,
+    /// Trust blocks below `trusted_height`, skipping PoW and signature checks for them.
+    ///
+    /// Hash-chain and weight/reward accounting are still done for every block.
+    Fast { trusted_height: u64 },
+    /// Verify everything and cross-check our calculations against the database.
+    Audit,
+    /// Skip PoW checks for every block, for regtest networks where blocks are generated instead of
+    /// mined.
+    Regtest,
+}
+
+impl VerificationProfile {
+    /// Returns the options for this profile.
+    pub fn options(&self) -> VerificationOptions {
+        match self {
+            VerificationProfile::Full => VerificationOptions {
+                skip_pow_below: 0,
+                signatures: SignaturePolicy::VerifyAll,
+                enforce_checkpoints: true,
+                cross_check_database: false,
+            },
+            VerificationProfile::Fast { trusted_height } => VerificationOptions {
+                skip_pow_below: *trusted_height,
+                signatures: SignaturePolicy::SkipBelow(*trusted_height),
+                enforce_checkpoints: true,
+                cross_check_database: false,
+            },
+            VerificationProfile::Audit => VerificationOptions {
+                skip_pow_below: 0,
+                signatures: SignaturePolicy::VerifyAll,
+                enforce_checkpoints: true,
+                cross_check_database: true,
+            },
+            VerificationProfile::Regtest => VerificationOptions {
+                skip_pow_below: u64::MAX,
+                signatures: SignaturePolicy::VerifyAll,
+                enforce_checkpoints: false,
+                cross_check_database: false,
+            },
+        }
+    }
+}
+
 pub struct Config {
     hard_fork_cfg: HardForkConfig,
+    profile: VerificationProfile,
+    checkpoints: Checkpoints,
+    enforce_dns_checkpoints: bool,
+    rule_flags: RuleFlags,
+    pruning_seed: PruningSeed,
+    /// The amount of threads in the [`VerificationPool`], [`None`] for a thread for each core.
+    verification_threads: Option<usize>,
+    misbehaviour_listener: Option<Arc<dyn MisbehaviourListener>>,
+    chain_listener: Option<Arc<dyn ChainListener>>,
 }
 
 impl Config {
     pub fn main_net() -> Config {
+        Config::for_network(Network::Mainnet)
+    }
+
+    /// Returns the default config for this network.
+    ///
+    /// Verifiers don't share any state, so verifiers for different networks can run in the same process.
+    pub fn for_network(network: Network) -> Config {
+        Config::from_constants(ConsensusConstants::for_network(&network))
+    }
+
+    /// Returns the default config for a network with these constants, use this to run a
+    /// [`Network::Regtest`] network with custom fork heights or a fixed difficulty.
+    pub fn from_constants(constants: ConsensusConstants) -> Config {
+        let network = constants.network();
         Config {
-            hard_fork_cfg: HardForkConfig::main_net(),
+            hard_fork_cfg: HardForkConfig::from_constants(constants),
+            profile: VerificationProfile::Full,
+            checkpoints: Checkpoints::for_network(&network),
+            enforce_dns_checkpoints: false,
+            rule_flags: RuleFlags::for_network(&network),
+            pruning_seed: PruningSeed::NOT_PRUNED,
+            verification_threads: None,
+            misbehaviour_listener: None,
+            chain_listener: None,
         }
     }
+
+    /// Returns the config of a regtest network, like `monerod --regtest`: every hard-fork activates at
+    /// height 1, the difficulty is fixed and PoW is not checked, see
+    /// [`ConsensusConstants::regtest`].
+    pub fn regtest() -> Config {
+        Config::from_constants(ConsensusConstants::regtest())
+            .with_profile(VerificationProfile::Regtest)
+    }
+
+    /// Trust the blocks at or below the highest checkpoint, like monerod's `--fast-block-sync`, with
+    /// [`VerificationProfile::Fast`].
+    ///
+    /// The PoW of these blocks and the signatures of their transactions are not checked, and they
+    /// can be synced pruned, with the block weight given by the peer, see
+    /// [`Verifier::check_pruned_block`]. The rest of their checks are still done. On mainnet the
+    /// highest checkpoint is at height 232150, see [`checkpoints`](crate::checkpoints).
+    pub fn with_fast_sync(self) -> Config {
+        let trusted_height = self.checkpoints.top_checkpoint_height() + 1;
+        self.with_profile(VerificationProfile::Fast { trusted_height })
+    }
+
+    /// Reject blocks that conflict with the DNS checkpoints and refuse to reorg past them, like
+    /// monerod's `--enforce-dns-checkpointing`. By default conflicts are only logged, see
+    /// [`dns_checkpoints`](crate::dns_checkpoints).
+    pub fn with_enforced_dns_checkpoints(mut self) -> Config {
+        self.enforce_dns_checkpoints = true;
+        self
+    }
+
+    /// Sets the [`VerificationProfile`] to use.
+    pub fn with_profile(mut self, profile: VerificationProfile) -> Config {
+        self.profile = profile;
+        self
+    }
+
+    /// Sets the amount of threads PoW and signatures are checked on, by default there is a thread for
+    /// each core.
+    pub fn with_verification_threads(mut self, threads: usize) -> Config {
+        self.verification_threads = Some(threads);
+        self
+    }
+
+    /// Sets the [`RuleFlags`] to use.
+    pub fn with_rule_flags(mut self, rule_flags: RuleFlags) -> Config {
+        self.rule_flags = rule_flags;
+        self
+    }
+
+    /// Sets the [`ConsistencyCheck`] of the hard-fork state against the database on init.
+    pub fn with_hard_fork_consistency_check(
+        mut self,
+        consistency_check: ConsistencyCheck,
+    ) -> Config {
+        self.hard_fork_cfg = self.hard_fork_cfg.with_consistency_check(consistency_check);
+        self
+    }
+
+    /// Sets the [`UnknownForkPolicy`] for blocks with a version this node doesn't know about, by
+    /// default they are rejected as invalid.
+    pub fn with_unknown_fork_policy(mut self, policy: UnknownForkPolicy) -> Config {
+        self.hard_fork_cfg = self.hard_fork_cfg.with_unknown_fork_policy(policy);
+        self
+    }
+
+    /// Sets the [`PruningSeed`] of the node, pruned blocks are only accepted if the node prunes.
+    pub fn with_pruning_seed(mut self, pruning_seed: PruningSeed) -> Config {
+        self.pruning_seed = pruning_seed;
+        self
+    }
+
+    /// Tells this listener about every failure reported with [`Verifier::report_failure`] that is
+    /// the source's fault.
+    pub fn with_misbehaviour_listener(mut self, listener: Arc<dyn MisbehaviourListener>) -> Config {
+        self.misbehaviour_listener = Some(listener);
+        self
+    }
+
+    /// Tells this listener about the blocks added to the main chain and the reorgs, see
+    /// [`ChainListener`].
+    pub fn with_chain_listener(mut self, listener: Arc<dyn ChainListener>) -> Config {
+        self.chain_listener = Some(listener);
+        self
+    }
+}
+
+/// Told about changes to the main chain, for monerod's `--block-notify`, `--reorg-notify` and ZMQ
+/// `chain_main`.
+///
+/// This is called by the task verifying blocks so it should not block.
+pub trait ChainListener: fmt::Debug + Send + Sync {
+    /// Called after main chain blocks, the first at `first_height`, are written to the database, see
+    /// [`Verifier::announce_blocks`].
+    fn blocks_added(&self, first_height: u64, blocks: &[Block]);
+
+    /// Called after an alt chain replaced the main chain from `split_height`, the height of the first
+    /// block removed, see [`Verifier::promote_alt_chain`].
+    fn reorg(&self, split_height: u64, blocks_removed: u64, blocks_added: u64);
 }
 
 #[derive(Clone)]
@@ -28,58 +253,71 @@ struct State {
     hard_fork: HardForkState,
     chain_height: u64,
     top_hash: [u8; 32],
+    /// The total amount of coins generated up to and including the top block.
+    already_generated_coins: u64,
 }
 
 impl State {
-    pub async fn init<D: Database + Clone>(
-        config: Config,
-        mut database: D,
-    ) -> Result<State, ConsensusError> {
-        let DatabaseResponse::ChainHeight(chain_height) = database
-            .ready()
-            .await?
-            .call(DatabaseRequest::ChainHeight)
-            .await?
-        else {
-            panic!("Database sent incorrect response")
-        };
-
-        Self::init_at_chain_height(config, chain_height, database).await
-    }
-
-    #[instrument(name = "init_state", skip_all)]
+    #[instrument(target = "cuprate_consensus::context", name = "init_state", skip_all)]
     pub async fn init_at_chain_height<D: Database + Clone>(
         config: Config,
         chain_height: u64,
-        mut database: D,
+        database: D,
     ) -> Result<State, ConsensusError> {
-        let DatabaseResponse::BlockHash(top_hash) = database
-            .ready()
-            .await?
-            .call(DatabaseRequest::BlockHash(chain_height - 1))
-            .await?
-        else {
-            panic!("Database sent incorrect response")
-        };
-
-        let (block_weight, difficulty, hard_fork) = join!(
-            BlockWeightsCache::init_from_chain_height(chain_height, database.clone()),
-            DifficultyCache::init_from_chain_height(chain_height, database.clone()),
-            HardForkState::init_from_chain_height(config.hard_fork_cfg, chain_height, database)
-        );
+        let caches =
+            ContextCacheInit::init_from_chain_height(config.hard_fork_cfg, chain_height, database)
+                .await?;
 
         Ok(State {
-            block_weight: block_weight?,
-            difficulty: difficulty?,
-            hard_fork: hard_fork?,
+            block_weight: caches.block_weight,
+            difficulty: caches.difficulty,
+            hard_fork: caches.hard_fork,
             chain_height,
-            top_hash,
+            top_hash: caches.top_hash,
+            already_generated_coins: caches.already_generated_coins,
         })
     }
 }
 
+impl State {
+    /// Replaces the main chain caches with an alt chain's caches.
+    ///
+    /// This should be called when the alt chain has overtaken the main chain.
+    fn promote_alt_chain(&mut self, alt_chain: AltChainContextCache) {
+        tracing::info!(
+            target: CONTEXT_TARGET,
+            fork_height = alt_chain.fork_height(),
+            height = alt_chain.chain_height,
+            "Promoting alt chain to main chain"
+        );
+
+        self.block_weight = alt_chain.block_weight;
+        self.difficulty = alt_chain.difficulty;
+        self.hard_fork = alt_chain.hard_fork;
+        self.chain_height = alt_chain.chain_height;
+        self.top_hash = alt_chain.top_hash;
+        self.already_generated_coins = alt_chain.already_generated_coins;
+    }
+}
+
 pub struct Verifier {
+    network: Network,
     state: State,
+    options: VerificationOptions,
+    checkpoints: Checkpoints,
+    /// The checkpoints fetched from DNS, see [`dns_checkpoints`](crate::dns_checkpoints).
+    dns_checkpoints: Checkpoints,
+    enforce_dns_checkpoints: bool,
+    rule_flags: RuleFlags,
+    pruning_seed: PruningSeed,
+    verification_pool: VerificationPool,
+    /// The RandomX caches the PoW of blocks is checked with.
+    randomx_caches: RandomXCaches,
+    /// Histograms of the time blocks have spent in each verification stage.
+    histograms: StageHistograms,
+    fork_metrics: ForkMetrics,
+    misbehaviour_listener: Option<Arc<dyn MisbehaviourListener>>,
+    chain_listener: Option<Arc<dyn ChainListener>>,
 }
 
 impl Verifier {
@@ -87,26 +325,770 @@ impl Verifier {
         config: Config,
         mut database: D,
     ) -> Result<Verifier, ConsensusError> {
-        let DatabaseResponse::ChainHeight(chain_height) = database
+        let chain_height = database
             .ready()
             .await?
             .call(DatabaseRequest::ChainHeight)
             .await?
-        else {
-            panic!("Database sent incorrect response")
-        };
+            .into_chain_height()?;
 
         Self::init_at_chain_height(config, chain_height, database).await
     }
 
-    #[instrument(name = "init_verifier", skip_all)]
+    /// Like [`Verifier::init`] but writes the network's genesis block to an empty database first, so
+    /// a new node can sync from height 0, see [`seed_genesis_block`].
+    pub async fn init_with_genesis<D: Database + Clone>(
+        config: Config,
+        database: D,
+    ) -> Result<Verifier, ConsensusError> {
+        let chain_height =
+            seed_genesis_block(&config.hard_fork_cfg.network(), database.clone()).await?;
+
+        Self::init_at_chain_height(config, chain_height, database).await
+    }
+
+    #[instrument(
+        target = "cuprate_consensus::context",
+        name = "init_verifier",
+        skip_all
+    )]
     pub async fn init_at_chain_height<D: Database + Clone>(
         config: Config,
         chain_height: u64,
         database: D,
     ) -> Result<Verifier, ConsensusError> {
+        let options = config.profile.options();
+        let checkpoints = config.checkpoints.clone();
+        let enforce_dns_checkpoints = config.enforce_dns_checkpoints;
+        let rule_flags = config.rule_flags.clone();
+        let pruning_seed = config.pruning_seed;
+        let verification_pool = config
+            .verification_threads
+            .map_or_else(VerificationPool::default, VerificationPool::new);
+        let network = config.hard_fork_cfg.network();
+        let misbehaviour_listener = config.misbehaviour_listener.clone();
+        let chain_listener = config.chain_listener.clone();
+
+        tracing::info!(target: BLOCK_TARGET, "Verifying blocks with options: {:?}", options);
+
         Ok(Verifier {
+            network,
             state: State::init_at_chain_height(config, chain_height, database).await?,
+            options,
+            checkpoints,
+            dns_checkpoints: Checkpoints::default(),
+            enforce_dns_checkpoints,
+            rule_flags,
+            pruning_seed,
+            verification_pool,
+            randomx_caches: RandomXCaches::default(),
+            histograms: StageHistograms::default(),
+            fork_metrics: ForkMetrics::default(),
+            misbehaviour_listener,
+            chain_listener,
         })
     }
+
+    /// Returns the network this verifier is for.
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Returns the [`VerificationOptions`] in use.
+    pub fn options(&self) -> &VerificationOptions {
+        &self.options
+    }
+
+    /// Returns the pool PoW and signatures should be checked on.
+    pub fn verification_pool(&self) -> &VerificationPool {
+        &self.verification_pool
+    }
+
+    /// Returns the RandomX caches the PoW of blocks should be checked with.
+    pub fn randomx_caches(&self) -> &RandomXCaches {
+        &self.randomx_caches
+    }
+
+    /// Returns true if the PoW of the block at this height should be checked.
+    pub fn should_check_pow(&self, height: u64) -> bool {
+        height >= self.options.skip_pow_below
+    }
+
+    /// Checks the block's hash against the checkpoints, if checkpoints are being enforced.
+    ///
+    /// A block that doesn't match a DNS checkpoint is only rejected if DNS checkpoints are
+    /// enforced, see [`Config::with_enforced_dns_checkpoints`].
+    pub fn check_checkpoint(&self, height: u64, hash: &[u8; 32]) -> Result<(), ConsensusError> {
+        if !self.options.enforce_checkpoints {
+            return Ok(());
+        }
+
+        self.checkpoints.check_block(height, hash)?;
+
+        let dns_check = self.dns_checkpoints.check_block(height, hash);
+        if self.enforce_dns_checkpoints {
+            dns_check
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Replaces the DNS checkpoints, these should be fetched with
+    /// [`fetch_dns_checkpoints`](crate::dns_checkpoints::fetch_dns_checkpoints) at startup and then
+    /// periodically, monerod fetches them every hour.
+    pub fn set_dns_checkpoints(&mut self, dns_checkpoints: Checkpoints) {
+        self.dns_checkpoints = dns_checkpoints;
+    }
+
+    /// Returns the DNS checkpoints.
+    pub fn dns_checkpoints(&self) -> &Checkpoints {
+        &self.dns_checkpoints
+    }
+
+    /// Returns true if the signatures of the transactions in the block at this height should be checked.
+    pub fn should_check_signatures(&self, height: u64) -> bool {
+        match self.options.signatures {
+            SignaturePolicy::VerifyAll => true,
+            SignaturePolicy::SkipBelow(skip_below) => height >= skip_below,
+        }
+    }
+
+    /// Returns the [`PruningSeed`] of the node.
+    pub fn pruning_seed(&self) -> PruningSeed {
+        self.pruning_seed
+    }
+
+    /// Checks that the block at this height can be accepted without its prunable data, when the
+    /// chain being synced is `target_height` blocks long.
+    ///
+    /// Pruned blocks have no signatures and their weight, which needs the prunable data, is given
+    /// by the peer, so they are only accepted in the checkpoint zone for heights where signatures
+    /// aren't checked. They are also only accepted if our [`PruningSeed`] would prune the block
+    /// anyway, blocks in our stripe and in the last
+    /// [`CRYPTONOTE_PRUNING_TIP_BLOCKS`](cuprate_common::CRYPTONOTE_PRUNING_TIP_BLOCKS) blocks of the
+    /// chain must be received in full.
+    pub fn check_pruned_block(
+        &self,
+        height: u64,
+        target_height: u64,
+    ) -> Result<(), ConsensusError> {
+        let in_checkpoint_zone =
+            self.options.enforce_checkpoints && self.checkpoints.is_in_checkpoint_zone(height);
+        if !in_checkpoint_zone
+            || self.should_check_signatures(height)
+            || self
+                .pruning_seed
+                .has_unpruned_data(height, target_height)
+                .unwrap_or(true)
+        {
+            return Err(BlockError::PrunedBlockNotAllowed { height }.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the cumulative difficulty and generated coins of the top block of `caches` against the
+    /// values stored in the database, if the profile cross-checks the database, see
+    /// [`VerificationOptions::cross_check_database`].
+    ///
+    /// This is called once the blocks the caches were extended with are written.
+    pub(crate) async fn cross_check_database<D: Database>(
+        &self,
+        caches: &AltChainContextCache,
+        database: &mut D,
+    ) -> Result<(), ConsensusError> {
+        if !self.options.cross_check_database || caches.chain_height == 0 {
+            return Ok(());
+        }
+        let height = caches.chain_height - 1;
+
+        let stored_difficulty = database
+            .ready()
+            .await?
+            .call(DatabaseRequest::CumulativeDifficulty(height))
+            .await?
+            .into_cumulative_difficulty()?;
+        let stored_coins = database
+            .ready()
+            .await?
+            .call(DatabaseRequest::GeneratedCoins(height))
+            .await?
+            .into_generated_coins()?;
+
+        let values = [
+            (
+                "cumulative difficulty",
+                stored_difficulty,
+                caches.cumulative_difficulty(),
+            ),
+            (
+                "generated coins",
+                stored_coins.into(),
+                caches.already_generated_coins.into(),
+            ),
+        ];
+        for (value, stored, calculated) in values {
+            if stored != calculated {
+                return Err(InternalError::DatabaseMismatch {
+                    height,
+                    value,
+                    stored,
+                    calculated,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the total amount of coins generated up to and including the top block, needed to
+    /// calculate the next block's reward.
+    pub fn already_generated_coins(&self) -> u64 {
+        self.state.already_generated_coins
+    }
+
+    /// Adds the coins generated by a new main chain block to the running total.
+    pub fn add_generated_coins(&mut self, generated_coins: u64) {
+        self.state.already_generated_coins = self
+            .state
+            .already_generated_coins
+            .saturating_add(generated_coins);
+    }
+
+    /// Returns true if the rule is active for a block at this height.
+    pub fn is_rule_active(&self, flag: RuleFlag, height: u64) -> bool {
+        self.rule_flags.is_active(flag, height)
+    }
+
+    /// Returns the rule flags this verifier was configured with.
+    pub fn rule_flags(&self) -> &RuleFlags {
+        &self.rule_flags
+    }
+
+    /// Replaces the main chain context with an alt chain's context, used when the alt chain
+    /// has overtaken the main chain.
+    ///
+    /// The [`ChainListener`] is told about the reorg, the alt chain's blocks should be announced
+    /// with [`Verifier::announce_blocks`] once they are written.
+    pub fn promote_alt_chain(&mut self, alt_chain: AltChainContextCache) {
+        let split_height = alt_chain.fork_height();
+        let reorg_depth = self.state.chain_height - split_height;
+        let blocks_added = alt_chain.chain_height - split_height;
+        self.fork_metrics.record_reorg(reorg_depth, Instant::now());
+
+        self.state.promote_alt_chain(alt_chain);
+
+        if let Some(listener) = &self.chain_listener {
+            listener.reorg(split_height, reorg_depth, blocks_added);
+        }
+    }
+
+    /// Tells the [`ChainListener`], if the verifier has one, about main chain blocks written to the
+    /// database, the first at `first_height`.
+    ///
+    /// The verifier's owner should call this after writing new main chain blocks,
+    /// [`import_blocks`](crate::bootstrap::import_blocks) does.
+    pub fn announce_blocks(&self, first_height: u64, blocks: &[Block]) {
+        if blocks.is_empty() {
+            return;
+        }
+
+        if let Some(listener) = &self.chain_listener {
+            listener.blocks_added(first_height, blocks);
+        }
+    }
+
+    /// Picks between the main chain and the alt chain, see [`compare_chains`]. The verifier's owner
+    /// should reorg to the alt chain, and call [`Verifier::promote_alt_chain`], if the alt chain is
+    /// picked.
+    ///
+    /// If DNS checkpoints are enforced, an alt chain that would remove a block at or below the highest
+    /// DNS checkpoint is never picked.
+    pub fn compare_alt_chain(&self, alt_chain: &AltChainContextCache) -> ChainChoice {
+        if self.enforce_dns_checkpoints
+            && self
+                .dns_checkpoints
+                .is_in_checkpoint_zone(alt_chain.fork_height())
+        {
+            tracing::warn!(
+                target: BLOCK_TARGET,
+                fork_height = alt_chain.fork_height(),
+                "Refusing to reorg past the DNS checkpoint at height {}",
+                self.dns_checkpoints.top_checkpoint_height()
+            );
+            return ChainChoice::Main;
+        }
+
+        let main_tip = ChainTip {
+            chain_height: self.state.chain_height,
+            top_hash: self.state.top_hash,
+            cumulative_difficulty: self.state.difficulty.last_cumulative_difficulty(),
+        };
+
+        compare_chains(&main_tip, &alt_chain.tip())
+    }
+
+    /// Records an alt block being added to an alt chain, this should be called after the block
+    /// is added to the alt chain's context.
+    pub fn record_alt_block(&mut self, alt_chain: &AltChainContextCache) {
+        self.fork_metrics.record_alt_block(
+            alt_chain.cumulative_difficulty(),
+            self.state.difficulty.last_cumulative_difficulty(),
+            Instant::now(),
+        );
+    }
+
+    /// Reports that data from `source` failed verification with `error`, returning the report if
+    /// the failure is the source's fault.
+    ///
+    /// The report is given to the [`MisbehaviourListener`], if the verifier has one, so the P2P
+    /// layer can punish the peer, see [`misbehaviour`](crate::misbehaviour).
+    pub fn report_failure(
+        &self,
+        source: BlockSource,
+        error: &ConsensusError,
+    ) -> Option<MisbehaviourReport> {
+        let report = MisbehaviourReport::new(source, error);
+        if report.severity == Severity::None {
+            return None;
+        }
+
+        tracing::info!(
+            target: BLOCK_TARGET,
+            %source,
+            severity = ?report.severity,
+            "Data failed verification: {}",
+            report.reason
+        );
+        if let Some(listener) = &self.misbehaviour_listener {
+            listener.misbehaviour(&report);
+        }
+
+        Some(report)
+    }
+
+    /// Returns a snapshot of the state of the main chain.
+    pub fn context(&self) -> BlockChainContext {
+        BlockChainContext::from_caches(
+            self.network,
+            self.state.chain_height,
+            self.state.top_hash,
+            self.state.already_generated_coins,
+            &self.state.block_weight,
+            &self.state.difficulty,
+            &self.state.hard_fork,
+        )
+    }
+
+    /// Returns a copy-on-write view of the main chain's caches that hypothetical blocks can be added
+    /// to, see [`SpeculativeContext`].
+    pub fn speculative(&self) -> SpeculativeContext {
+        SpeculativeContext::new(self.network, self.main_chain_caches())
+    }
+
+    /// Returns a copy of the main chain's caches, blocks added to them can be put on the main chain
+    /// with [`Verifier::extend_main_chain`].
+    pub(crate) fn main_chain_caches(&self) -> AltChainContextCache {
+        AltChainContextCache {
+            block_weight: self.state.block_weight.clone(),
+            alt_long_term_weights: VecDeque::new(),
+            difficulty: self.state.difficulty.clone(),
+            hard_fork: self.state.hard_fork.clone(),
+            fork_height: self.state.chain_height,
+            chain_height: self.state.chain_height,
+            top_hash: self.state.top_hash,
+            already_generated_coins: self.state.already_generated_coins,
+        }
+    }
+
+    /// Replaces the main chain's caches with caches from [`Verifier::main_chain_caches`] that blocks
+    /// were added to, unlike [`Verifier::promote_alt_chain`] this is not a reorg.
+    pub(crate) fn extend_main_chain(&mut self, caches: AltChainContextCache) {
+        debug_assert_eq!(caches.fork_height(), self.state.chain_height);
+
+        self.state.block_weight = caches.block_weight;
+        self.state.difficulty = caches.difficulty;
+        self.state.hard_fork = caches.hard_fork;
+        self.state.chain_height = caches.chain_height;
+        self.state.top_hash = caches.top_hash;
+        self.state.already_generated_coins = caches.already_generated_coins;
+    }
+
+    /// Returns a summary of the verifier's caches, for debugging.
+    pub fn dump_context(&self) -> ContextDump {
+        let hard_fork = &self.state.hard_fork;
+        let block_weight = &self.state.block_weight;
+
+        ContextDump {
+            chain_height: self.state.chain_height,
+            current_hf: hard_fork.current_hardfork(),
+            next_hf: hard_fork.next_hardfork(),
+            hf_votes: hard_fork.votes(),
+            hf_total_votes: hard_fork.total_votes(),
+            hf_last_height: hard_fork.last_height(),
+            short_term_weights: block_weight.short_term_summary(),
+            long_term_weights: block_weight.long_term_summary(),
+            weights_tip_height: block_weight.tip_height(),
+            difficulty: self.state.difficulty.window_summary(),
+        }
+    }
+
+    /// Returns the alt block and reorg metrics.
+    pub fn alt_chain_stats(&self) -> AltChainStats {
+        self.fork_metrics.stats(Instant::now())
+    }
+
+    /// Adds a verified block's stage timings to the histograms.
+    pub fn record_block_timings(&mut self, timings: &BlockTimings) {
+        self.histograms.record_block(timings);
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_block_timings(timings);
+    }
+
+    /// Returns histograms of the time blocks have spent in each verification stage.
+    pub fn stage_histograms(&self) -> &StageHistograms {
+        &self.histograms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use futures::{executor::block_on, future::ready, join};
+    use monero_serai::block::Block;
+    use tower::Service;
+
+    use cuprate_common::{Network, PruningSeed, CRYPTONOTE_PRUNING_LOG_STRIPES};
+
+    use super::{
+        AltChainContextCache, ChainChoice, ChainListener, Checkpoints, Config, VerificationProfile,
+        Verifier,
+    };
+    use crate::{
+        consensus_constants::{ConsensusConstants, NUMB_OF_HARD_FORKS},
+        hardforks::HardFork,
+        test_utils::{dummy_block, dummy_miner_tx, DummyBlockExtendedHeader, DummyDatabaseBuilder},
+        ConsensusError, DatabaseRequest, DatabaseResponse, InternalError,
+    };
+
+    #[test]
+    fn database_is_only_cross_checked_when_auditing() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                100,
+                DummyBlockExtendedHeader::default().with_generated_coins(10),
+            )
+            .finish();
+        // A database that lost the coins generated by its blocks.
+        let mut corrupt = tower::service_fn({
+            let database = database.clone();
+            move |req| match req {
+                DatabaseRequest::GeneratedCoins(_) => {
+                    ready(Ok(DatabaseResponse::GeneratedCoins(0)))
+                }
+                req => database.clone().call(req),
+            }
+        });
+
+        for (profile, corrupt_is_found) in [
+            (VerificationProfile::Full, false),
+            (VerificationProfile::Audit, true),
+        ] {
+            let verifier = block_on(Verifier::init(
+                Config::main_net().with_profile(profile),
+                database.clone(),
+            ))
+            .unwrap();
+            let caches = verifier.main_chain_caches();
+
+            let mut stored = database.clone();
+            block_on(verifier.cross_check_database(&caches, &mut stored)).unwrap();
+
+            let res = block_on(verifier.cross_check_database(&caches, &mut corrupt));
+            if corrupt_is_found {
+                assert!(matches!(
+                    res,
+                    Err(ConsensusError::Internal(InternalError::DatabaseMismatch {
+                        height: 99,
+                        value: "generated coins",
+                        stored: 0,
+                        ..
+                    }))
+                ));
+            } else {
+                res.unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn independent_verifiers_in_one_process() {
+        let main_net_database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                100,
+                DummyBlockExtendedHeader::default().with_generated_coins(10),
+            )
+            .finish();
+        let test_net_database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                50,
+                DummyBlockExtendedHeader::default().with_generated_coins(20),
+            )
+            .finish();
+
+        let (main_net, test_net) = block_on(async {
+            join!(
+                Verifier::init(Config::for_network(Network::Mainnet), main_net_database),
+                Verifier::init(Config::for_network(Network::Testnet), test_net_database)
+            )
+        });
+        let (mut main_net, test_net) = (main_net.unwrap(), test_net.unwrap());
+
+        assert_eq!(main_net.network(), Network::Mainnet);
+        assert_eq!(test_net.network(), Network::Testnet);
+
+        main_net.add_generated_coins(10);
+        assert_eq!(main_net.already_generated_coins(), 1010);
+        assert_eq!(test_net.already_generated_coins(), 1000);
+
+        // Mainnet has a checkpoint at height 1, testnet does not.
+        assert!(main_net.check_checkpoint(1, &[0; 32]).is_err());
+        assert!(test_net.check_checkpoint(1, &[0; 32]).is_ok());
+    }
+
+    #[test]
+    fn pruned_blocks_only_accepted_below_trusted_height() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+        let seed = PruningSeed::new(1, CRYPTONOTE_PRUNING_LOG_STRIPES).unwrap();
+        let profile = VerificationProfile::Fast {
+            trusted_height: 50_000,
+        };
+
+        let verifier = block_on(Verifier::init(
+            Config::main_net()
+                .with_profile(profile)
+                .with_pruning_seed(seed),
+            database.clone(),
+        ))
+        .unwrap();
+
+        // Block 4096 is in stripe 2, which we don't keep.
+        assert!(verifier.check_pruned_block(4096, 100_000).is_ok());
+        // Block 0 is in our stripe.
+        assert!(verifier.check_pruned_block(0, 100_000).is_err());
+        // Block 4096 is in the tip of this chain.
+        assert!(verifier.check_pruned_block(4096, 5_000).is_err());
+        // Signatures are checked for block 53248, which is in stripe 6.
+        assert!(verifier.check_pruned_block(53_248, 100_000).is_err());
+
+        // Block 233,472 is in stripe 2 but above the last checkpoint, so its weight is not trusted.
+        let trusting = block_on(Verifier::init(
+            Config::main_net()
+                .with_profile(VerificationProfile::Fast {
+                    trusted_height: 1_000_000,
+                })
+                .with_pruning_seed(seed),
+            database.clone(),
+        ))
+        .unwrap();
+        assert!(trusting.check_pruned_block(4096, 2_000_000).is_ok());
+        assert!(trusting.check_pruned_block(233_472, 2_000_000).is_err());
+
+        let unpruned = block_on(Verifier::init(
+            Config::main_net().with_profile(profile),
+            database,
+        ))
+        .unwrap();
+        assert!(unpruned.check_pruned_block(4096, 100_000).is_err());
+    }
+
+    #[test]
+    fn dns_checkpoints_are_only_enforced_when_asked() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                100,
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::V1, HardFork::V1)
+                    .with_weight(1_000, 1_000)
+                    .with_pow_info(50, 10),
+            )
+            .finish();
+        let mut dns_checkpoints = Checkpoints::default();
+        dns_checkpoints.add_checkpoint(95, [1; 32]);
+
+        let mut lenient = block_on(Verifier::init(Config::main_net(), database.clone())).unwrap();
+        let mut enforcing = block_on(Verifier::init(
+            Config::main_net().with_enforced_dns_checkpoints(),
+            database.clone(),
+        ))
+        .unwrap();
+        lenient.set_dns_checkpoints(dns_checkpoints.clone());
+        enforcing.set_dns_checkpoints(dns_checkpoints);
+
+        assert!(lenient.check_checkpoint(95, &[2; 32]).is_ok());
+        assert!(enforcing.check_checkpoint(95, &[2; 32]).is_err());
+        assert!(enforcing.check_checkpoint(95, &[1; 32]).is_ok());
+
+        // An alt chain forking below the DNS checkpoint with more work than the main chain.
+        let state = &lenient.state;
+        let mut alt_chain = AltChainContextCache {
+            block_weight: state.block_weight.clone(),
+            alt_long_term_weights: VecDeque::new(),
+            difficulty: state.difficulty.clone(),
+            hard_fork: state.hard_fork.clone(),
+            fork_height: 90,
+            chain_height: state.chain_height,
+            top_hash: state.top_hash,
+            already_generated_coins: state.already_generated_coins,
+        };
+        block_on(alt_chain.add_block_with_hash(&block(state.top_hash), [3; 32], 1_000, database))
+            .unwrap();
+
+        assert_eq!(lenient.compare_alt_chain(&alt_chain), ChainChoice::Alt);
+        assert_eq!(enforcing.compare_alt_chain(&alt_chain), ChainChoice::Main);
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingListener(Mutex<Vec<String>>);
+
+    impl ChainListener for RecordingListener {
+        fn blocks_added(&self, first_height: u64, blocks: &[Block]) {
+            let event = format!("blocks_added {first_height} {}", blocks.len());
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn reorg(&self, split_height: u64, blocks_removed: u64, blocks_added: u64) {
+            let event = format!("reorg {split_height} {blocks_removed} {blocks_added}");
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn chain_listener_is_told_about_chain_changes() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                100,
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::V1, HardFork::V1)
+                    .with_weight(1_000, 1_000)
+                    .with_pow_info(50, 10),
+            )
+            .finish();
+        let listener = Arc::new(RecordingListener::default());
+        let mut verifier = block_on(Verifier::init(
+            Config::main_net().with_chain_listener(listener.clone()),
+            database.clone(),
+        ))
+        .unwrap();
+
+        let state = &verifier.state;
+        let mut alt_chain = AltChainContextCache {
+            block_weight: state.block_weight.clone(),
+            alt_long_term_weights: VecDeque::new(),
+            difficulty: state.difficulty.clone(),
+            hard_fork: state.hard_fork.clone(),
+            fork_height: 90,
+            chain_height: state.chain_height,
+            top_hash: state.top_hash,
+            already_generated_coins: state.already_generated_coins,
+        };
+        let mut alt_block = dummy_block(
+            HardFork::V1,
+            HardFork::V2,
+            state.top_hash,
+            dummy_miner_tx(1, Some(100), vec![]),
+        );
+        alt_block.header.timestamp = 60;
+        block_on(alt_chain.add_block_with_hash(&alt_block, [3; 32], 1_000, database)).unwrap();
+
+        verifier.promote_alt_chain(alt_chain);
+        verifier.announce_blocks(100, &[]);
+        verifier.announce_blocks(100, &[alt_block]);
+
+        assert_eq!(
+            *listener.0.lock().unwrap(),
+            ["reorg 90 10 11", "blocks_added 100 1"]
+        );
+        assert_eq!(verifier.context().chain_height, 101);
+    }
+
+    #[test]
+    fn dump_context_summarises_caches() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                100,
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::V1, HardFork::V2)
+                    .with_weight(1_000, 2_000)
+                    .with_pow_info(50, 10),
+            )
+            .finish();
+
+        let verifier = block_on(Verifier::init(Config::main_net(), database)).unwrap();
+        let dump = verifier.dump_context();
+
+        assert_eq!(dump.chain_height, 100);
+        assert_eq!(dump.current_hf, HardFork::V1);
+        assert_eq!(dump.hf_votes[1], 100);
+        assert_eq!(dump.hf_total_votes, 100);
+        assert_eq!(dump.hf_last_height, 99);
+
+        assert_eq!(dump.short_term_weights.len, 100);
+        assert_eq!(dump.short_term_weights.min, 1_000);
+        assert_eq!(dump.short_term_weights.max, 1_000);
+        assert_eq!(dump.long_term_weights.median, 2_000);
+        assert_eq!(dump.weights_tip_height, 99);
+
+        assert_eq!(dump.difficulty.newest_timestamp, Some(50));
+        assert_eq!(dump.difficulty.last_accounted_height, 99);
+        assert_eq!(dump.difficulty.last_cumulative_difficulty, 1_000);
+    }
+
+    #[test]
+    fn regtest() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                2,
+                DummyBlockExtendedHeader::default()
+                    .with_hard_fork_info(HardFork::LATEST, HardFork::LATEST),
+            )
+            .finish();
+
+        let verifier = block_on(Verifier::init(Config::regtest(), database)).unwrap();
+
+        assert!(!verifier.should_check_pow(1));
+        assert!(!verifier.should_check_pow(u64::MAX - 1));
+
+        let context = verifier.context();
+        assert_eq!(context.current_hf, HardFork::LATEST);
+        assert_eq!(context.next_difficulty, 1);
+    }
+
+    #[test]
+    fn regtest_constants() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                20,
+                DummyBlockExtendedHeader::default().with_hard_fork_info(HardFork::V1, HardFork::V1),
+            )
+            .finish();
+
+        let mut fork_heights = [30; NUMB_OF_HARD_FORKS];
+        fork_heights[0] = 0;
+        let constants = ConsensusConstants::for_network(&Network::Regtest)
+            .with_fork_heights(fork_heights)
+            .with_fixed_difficulty(5);
+
+        let verifier =
+            block_on(Verifier::init(Config::from_constants(constants), database)).unwrap();
+
+        let context = verifier.context();
+        assert_eq!(context.network, Network::Regtest);
+        assert_eq!(context.current_hf, HardFork::V1);
+        assert_eq!(context.next_difficulty, 5);
+    }
 }
@@ -0,0 +1,347 @@
+//! # Write Batches
+//!
+//! This module contains [`BlockWriter`], which writes verified blocks to the database in batches with
+//! [`DatabaseRequest::WriteBlocks`], and [`WriteBatchSizer`], which picks the size of the batches.
+//!
+//! The batch size isn't a fixed setting, it is tuned from how long the last batches took to commit
+//! and how fast the verifier makes blocks:
+//! - Within [`WriteBatchConfig::tip_distance`] blocks of the top of the chain every block is written
+//!   on its own, so it is in the database as soon as it is verified.
+//! - Further behind, like during the initial sync, the batch size doubles after a full batch commits
+//!   in less than [`WriteBatchConfig::target_commit_latency`] and halves after a commit that took
+//!   longer.
+//! - A batch is never bigger than the amount of blocks the verifier makes in
+//!   [`WriteBatchConfig::max_flush_interval`], so a slow verifier doesn't keep verified blocks out of
+//!   the database.
+//!
+use std::time::{Duration, Instant};
+
+use tower::ServiceExt;
+
+use crate::{
+    block::VerifiedBlockInformation, spans::BLOCK_TARGET, DatabaseRequest, DatabaseResponse,
+};
+
+/// The default smallest batch, used near the top of the chain.
+pub const DEFAULT_MIN_BATCH_SIZE: usize = 1;
+/// The default biggest batch.
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 1_000;
+/// The default commit latency the batch size is tuned to.
+pub const DEFAULT_TARGET_COMMIT_LATENCY: Duration = Duration::from_millis(500);
+/// The default longest time a verified block waits to be written.
+pub const DEFAULT_MAX_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+/// The default distance from the top of the chain blocks are written on their own.
+pub const DEFAULT_TIP_DISTANCE: u64 = 10;
+
+/// The weight of a new block interval in the average interval.
+const BLOCK_INTERVAL_SMOOTHING: f64 = 0.1;
+
+/// Configuration for the [`WriteBatchSizer`].
+#[derive(Debug, Clone)]
+pub struct WriteBatchConfig {
+    /// The smallest batch, this is the batch size near the top of the chain.
+    pub min_batch_size: usize,
+    /// The biggest batch.
+    pub max_batch_size: usize,
+    /// The batch size grows while full batches commit quicker than this and shrinks when they
+    /// don't.
+    pub target_commit_latency: Duration,
+    /// The longest a verified block should wait to be written.
+    pub max_flush_interval: Duration,
+    /// Blocks this close to the top of the chain are written in batches of
+    /// [`WriteBatchConfig::min_batch_size`].
+    pub tip_distance: u64,
+}
+
+impl Default for WriteBatchConfig {
+    fn default() -> Self {
+        WriteBatchConfig {
+            min_batch_size: DEFAULT_MIN_BATCH_SIZE,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            target_commit_latency: DEFAULT_TARGET_COMMIT_LATENCY,
+            max_flush_interval: DEFAULT_MAX_FLUSH_INTERVAL,
+            tip_distance: DEFAULT_TIP_DISTANCE,
+        }
+    }
+}
+
+/// Picks the size of write batches from the commit latency and the verifier's throughput.
+#[derive(Debug, Clone)]
+pub struct WriteBatchSizer {
+    config: WriteBatchConfig,
+    /// The batch size tuned from the commit latency, used away from the top of the chain.
+    tuned_batch_size: usize,
+    /// The average time between verified blocks, in seconds.
+    block_interval: Option<f64>,
+    /// When the last block was verified.
+    last_block: Option<Instant>,
+}
+
+impl WriteBatchSizer {
+    /// # Panics
+    ///
+    /// Panics if `min_batch_size` is 0 or bigger than `max_batch_size`.
+    pub fn new(config: WriteBatchConfig) -> WriteBatchSizer {
+        assert_ne!(config.min_batch_size, 0, "Batches must hold a block");
+        assert!(
+            config.min_batch_size <= config.max_batch_size,
+            "The smallest batch must not be bigger than the biggest"
+        );
+
+        WriteBatchSizer {
+            tuned_batch_size: config.min_batch_size,
+            config,
+            block_interval: None,
+            last_block: None,
+        }
+    }
+
+    pub fn config(&self) -> &WriteBatchConfig {
+        &self.config
+    }
+
+    /// Records that a block was verified at `now`.
+    pub fn block_verified(&mut self, now: Instant) {
+        if let Some(last_block) = self.last_block {
+            let interval = now.saturating_duration_since(last_block).as_secs_f64();
+            self.block_interval = Some(match self.block_interval {
+                Some(average) => average + (interval - average) * BLOCK_INTERVAL_SMOOTHING,
+                None => interval,
+            });
+        }
+        self.last_block = Some(now);
+    }
+
+    /// Records that a batch of `blocks` took `latency` to commit.
+    pub fn commit_finished(&mut self, blocks: usize, latency: Duration) {
+        if latency > self.config.target_commit_latency {
+            self.tuned_batch_size = (self.tuned_batch_size / 2).max(self.config.min_batch_size);
+        } else if blocks >= self.tuned_batch_size {
+            // Only grow after a full batch, a small batch flushed early says nothing about a
+            // bigger one.
+            self.tuned_batch_size = (self.tuned_batch_size * 2).min(self.config.max_batch_size);
+        }
+    }
+
+    /// Returns the amount of blocks to write in the next batch, when the chain is `blocks_behind`
+    /// blocks behind the top of the network's chain.
+    pub fn batch_size(&self, blocks_behind: u64) -> usize {
+        if blocks_behind <= self.config.tip_distance {
+            return self.config.min_batch_size;
+        }
+
+        let mut batch_size = self
+            .tuned_batch_size
+            .min(usize::try_from(blocks_behind).unwrap_or(usize::MAX));
+
+        if let Some(block_interval) = self.block_interval.filter(|interval| *interval > 0.0) {
+            let verified_in_interval =
+                self.config.max_flush_interval.as_secs_f64() / block_interval;
+            batch_size = batch_size.min(verified_in_interval as usize);
+        }
+
+        batch_size.clamp(self.config.min_batch_size, self.config.max_batch_size)
+    }
+}
+
+/// Writes verified blocks to the database in the batches picked by a [`WriteBatchSizer`].
+///
+/// Blocks are held until the batch is full, or until the first held block has waited
+/// [`WriteBatchConfig::max_flush_interval`]. The interval is only checked when a block is written,
+/// so [`BlockWriter::flush`] should be called when there are no more blocks to verify.
+pub struct BlockWriter<D> {
+    database: D,
+    sizer: WriteBatchSizer,
+    batch: Vec<VerifiedBlockInformation>,
+    /// When the first block in the batch was added.
+    batch_started: Option<Instant>,
+}
+
+impl<D> BlockWriter<D>
+where
+    D: tower::Service<DatabaseRequest, Response = DatabaseResponse, Error = tower::BoxError>,
+{
+    pub fn new(database: D, config: WriteBatchConfig) -> BlockWriter<D> {
+        BlockWriter {
+            database,
+            sizer: WriteBatchSizer::new(config),
+            batch: Vec::new(),
+            batch_started: None,
+        }
+    }
+
+    pub fn sizer(&self) -> &WriteBatchSizer {
+        &self.sizer
+    }
+
+    /// Returns the amount of verified blocks waiting to be written.
+    pub fn held_blocks(&self) -> usize {
+        self.batch.len()
+    }
+
+    /// Adds a verified block to the batch, writing the batch if it is due. `blocks_behind` is the
+    /// amount of blocks the chain, including this block, is behind the top of the network's chain.
+    ///
+    /// Returns the amount of blocks written.
+    pub async fn write(
+        &mut self,
+        block: VerifiedBlockInformation,
+        blocks_behind: u64,
+    ) -> Result<usize, tower::BoxError> {
+        let now = Instant::now();
+        self.sizer.block_verified(now);
+
+        self.batch.push(block);
+        let batch_started = *self.batch_started.get_or_insert(now);
+
+        if self.batch.len() >= self.sizer.batch_size(blocks_behind)
+            || now.duration_since(batch_started) >= self.sizer.config.max_flush_interval
+        {
+            self.flush().await
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Writes the held blocks, returning the amount written.
+    ///
+    /// The blocks of a batch are written in one request, if it fails none of them were written and
+    /// they are dropped.
+    pub async fn flush(&mut self) -> Result<usize, tower::BoxError> {
+        self.batch_started = None;
+        let mut batch = std::mem::take(&mut self.batch);
+        let blocks = batch.len();
+
+        let req = match blocks {
+            0 => return Ok(0),
+            1 => DatabaseRequest::WriteBlock(Box::new(batch.pop().unwrap())),
+            _ => DatabaseRequest::WriteBlocks(batch),
+        };
+
+        let started = Instant::now();
+        self.database.ready().await?.call(req).await?;
+        let latency = started.elapsed();
+        self.sizer.commit_finished(blocks, latency);
+
+        tracing::debug!(
+            target: BLOCK_TARGET,
+            batch_size = blocks,
+            latency = ?latency,
+            "Wrote a batch of blocks"
+        );
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_write_batch(blocks);
+
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::hardforks::HardFork;
+    use crate::test_utils::{dummy_block, dummy_miner_tx, dummy_verified_block};
+
+    /// Returns a database that records the heights of each batch written.
+    fn recording_database(
+        batches: Arc<Mutex<Vec<Vec<u64>>>>,
+    ) -> impl tower::Service<
+        DatabaseRequest,
+        Response = DatabaseResponse,
+        Error = tower::BoxError,
+        Future = std::future::Ready<Result<DatabaseResponse, tower::BoxError>>,
+    > {
+        tower::service_fn(move |req| {
+            let heights = match req {
+                DatabaseRequest::WriteBlock(block) => vec![block.height],
+                DatabaseRequest::WriteBlocks(blocks) => {
+                    blocks.iter().map(|block| block.height).collect()
+                }
+                _ => panic!("The writer only writes blocks"),
+            };
+            batches.lock().unwrap().push(heights);
+            std::future::ready(Ok(DatabaseResponse::WriteBlock))
+        })
+    }
+
+    #[test]
+    fn batch_size_follows_commit_latency() {
+        let mut sizer = WriteBatchSizer::new(WriteBatchConfig {
+            max_batch_size: 8,
+            ..Default::default()
+        });
+        assert_eq!(sizer.batch_size(1_000), 1);
+
+        for expected in [2, 4, 8, 8] {
+            let batch_size = sizer.batch_size(1_000);
+            sizer.commit_finished(batch_size, Duration::from_millis(10));
+            assert_eq!(sizer.batch_size(1_000), expected);
+        }
+
+        // A batch that wasn't full doesn't grow the batch size.
+        sizer.commit_finished(1, Duration::from_millis(600));
+        assert_eq!(sizer.batch_size(1_000), 4);
+        sizer.commit_finished(1, Duration::from_millis(10));
+        assert_eq!(sizer.batch_size(1_000), 4);
+
+        // Near the top of the chain blocks are written on their own.
+        assert_eq!(sizer.batch_size(DEFAULT_TIP_DISTANCE), 1);
+        assert_eq!(sizer.batch_size(DEFAULT_TIP_DISTANCE + 2), 4);
+    }
+
+    #[test]
+    fn batch_size_follows_verifier_throughput() {
+        let mut sizer = WriteBatchSizer::new(WriteBatchConfig::default());
+        for _ in 0..10 {
+            let batch_size = sizer.batch_size(100_000);
+            sizer.commit_finished(batch_size, Duration::ZERO);
+        }
+        assert_eq!(sizer.batch_size(100_000), DEFAULT_MAX_BATCH_SIZE);
+
+        // A block a second, only 5 blocks are verified in the flush interval.
+        let start = Instant::now();
+        for secs in 0..3 {
+            sizer.block_verified(start + Duration::from_secs(secs));
+        }
+        assert_eq!(sizer.batch_size(100_000), 5);
+    }
+
+    #[test]
+    fn blocks_are_written_in_growing_batches() {
+        let batches = Arc::default();
+        let mut writer = BlockWriter::new(
+            recording_database(Arc::clone(&batches)),
+            WriteBatchConfig::default(),
+        );
+
+        let block = dummy_block(
+            HardFork::V16,
+            HardFork::V16,
+            [0; 32],
+            dummy_miner_tx(2, None, vec![]),
+        );
+        let block = |height| dummy_verified_block(block.clone(), height);
+
+        let mut written = 0;
+        for height in 0..18 {
+            written += block_on(writer.write(block(height), 1_000)).unwrap();
+        }
+        assert_eq!(written, 15);
+        assert_eq!(writer.held_blocks(), 3);
+
+        // At the top of the chain the held blocks are written with the new block.
+        assert_eq!(block_on(writer.write(block(18), 0)).unwrap(), 4);
+        assert_eq!(block_on(writer.write(block(19), 0)).unwrap(), 1);
+        assert_eq!(block_on(writer.flush()).unwrap(), 0);
+
+        let sizes: Vec<usize> = batches.lock().unwrap().iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![1, 2, 4, 8, 4, 1]);
+        let heights: Vec<u64> = batches.lock().unwrap().concat();
+        assert_eq!(heights, (0..20).collect::<Vec<_>>());
+    }
+}
@@ -2,17 +2,28 @@
 //!
 //! This is where you specify the subcommands of your application.
 //!
-//! The default application comes with two subcommands:
+//! The application has these subcommands:
 //!
 //! - `start`: launches the application
+//! - `self-test`: runs quick end-to-end checks and prints a PASS/FAIL report
+//! - `compact-db`: rewrites the database to reclaim its free space
+//! - `import`: adds the blocks of a monerod `blockchain.raw` file to the database
+//! - `export`: writes the database to a monerod `blockchain.raw` file
 //! - `--version`: print application version
 //!
 //! See the `impl Configurable` below for how to specify the path to the
 //! application's configuration file.
 
+mod compact_db;
+mod export;
+mod import;
+mod self_test;
 mod start;
 
-use self::start::StartCmd;
+use self::{
+    compact_db::CompactDbCmd, export::ExportCmd, import::ImportCmd, self_test::SelfTestCmd,
+    start::StartCmd,
+};
 use crate::config::CuprateConfig;
 use abscissa_core::{config::Override, Command, Configurable, FrameworkError, Runnable};
 use std::path::PathBuf;
@@ -26,6 +37,14 @@ pub const CONFIG_FILE: &str = "cuprate.toml";
 pub enum CuprateCmd {
     /// The `start` subcommand
     Start(StartCmd),
+    /// The `self-test` subcommand
+    SelfTest(SelfTestCmd),
+    /// The `compact-db` subcommand
+    CompactDb(CompactDbCmd),
+    /// The `import` subcommand
+    Import(ImportCmd),
+    /// The `export` subcommand
+    Export(ExportCmd),
 }
 
 /// Entry point for the application. It needs to be a struct to allow using subcommands!
@@ -78,6 +97,10 @@ impl Configurable<CuprateConfig> for EntryPoint {
     fn process_config(&self, config: CuprateConfig) -> Result<CuprateConfig, FrameworkError> {
         match &self.cmd {
             CuprateCmd::Start(cmd) => cmd.override_config(config),
+            CuprateCmd::SelfTest(_)
+            | CuprateCmd::CompactDb(_)
+            | CuprateCmd::Import(_)
+            | CuprateCmd::Export(_) => Ok(config),
             //
             // If you don't need special overrides for some
             // subcommands, you can just use a catch all
@@ -0,0 +1,60 @@
+//! `compact-db` subcommand - rewrites the database to reclaim the space freed by pruning and reorgs
+//!
+//! The node must be stopped first, nothing else can have the database open while it is compacted.
+//! Stopping the command part way, even by killing it, leaves the database as it was.
+//!
+//! With `--on-start` the database is only marked, it is compacted the next time the node starts.
+
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+
+use abscissa_core::{Command, Runnable};
+
+use cuprate_database::compaction::{compact, request_compaction};
+
+use crate::error::Error;
+
+/// `compact-db` subcommand
+#[derive(clap::Parser, Command, Debug)]
+pub struct CompactDbCmd {
+    /// The database directory.
+    path: PathBuf,
+
+    /// Compact the database the next time the node starts instead of now.
+    #[arg(long)]
+    on_start: bool,
+}
+
+impl Runnable for CompactDbCmd {
+    /// Compacts the database, printing the progress of each table.
+    fn run(&self) {
+        if self.on_start {
+            if let Err(e) = request_compaction(&self.path) {
+                Error::from(e).exit();
+            }
+            println!("The database will be compacted the next time the node starts");
+            return;
+        }
+
+        let interrupt = AtomicBool::new(false);
+        let res = compact(&self.path, &interrupt, |progress| {
+            println!(
+                "[{}/{}] {}: {}/{} entries",
+                progress.tables_done + 1,
+                progress.tables_total,
+                progress.table,
+                progress.entries_copied,
+                progress.table_entries
+            );
+        });
+
+        match res {
+            Ok(report) => println!(
+                "Compacted the database from {} MB to {} MB",
+                report.size_before / 1_000_000,
+                report.size_after / 1_000_000
+            ),
+            Err(e) => Error::from(e).exit(),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! `export` subcommand - writes the database to a monerod `blockchain.raw` bootstrap file
+//!
+//! The node must be stopped first. The file can be imported by `monero-blockchain-import` or by the
+//! `import` subcommand.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use abscissa_core::{Command, Runnable};
+
+use cuprate_database::{database::Database, service::DatabaseService};
+use monero_consensus::bootstrap::{export_chain, RawWriter};
+
+use crate::error::{Error, ErrorKind};
+
+/// `export` subcommand
+#[derive(clap::Parser, Command, Debug)]
+pub struct ExportCmd {
+    /// The database directory.
+    path: PathBuf,
+
+    /// The bootstrap file to write, an existing file is overwritten.
+    file: PathBuf,
+}
+
+impl Runnable for ExportCmd {
+    /// Exports the database, printing the amount of blocks written.
+    fn run(&self) {
+        if let Err(e) = self.export() {
+            e.exit();
+        }
+    }
+}
+
+impl ExportCmd {
+    fn export(&self) -> Result<(), Error> {
+        let db = <libmdbx::Database<libmdbx::NoWriteMap> as Database>::open(self.path.clone())
+            .map_err(|e| ErrorKind::Database.context(e))?;
+        db.check_all_tables_exist()
+            .map_err(|e| ErrorKind::Database.context(e))?;
+        let database = DatabaseService::new(Arc::new(db));
+
+        let mut writer = RawWriter::new(BufWriter::new(File::create(&self.file)?))?;
+
+        let runtime = tokio::runtime::Runtime::new()?;
+        let exported = runtime.block_on(export_chain(&mut writer, database))?;
+        writer.finish()?;
+
+        println!("Exported {exported} blocks");
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+//! `import` subcommand - adds the blocks of a monerod `blockchain.raw` bootstrap file to the database
+//!
+//! The node must be stopped first. Blocks already in the database are skipped, so a stopped import
+//! can be run again to finish it. An empty database is started from the network's genesis block.
+//!
+//! By default every block is fully verified like a block synced from a peer: its PoW, timestamp,
+//! miner transaction and the inputs and signatures of its transactions, see [`ImportMode::Verify`].
+//! With `--verify off` the file is trusted, only use it for a file from a trusted source, see
+//! [`ImportMode::Trusted`].
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use abscissa_core::{Command, Runnable};
+
+use cuprate_database::{database::Database, service::DatabaseService};
+use monero_consensus::{
+    bootstrap::{import_blocks, ImportMode, RawReader},
+    verifier::{Config, Verifier},
+    write_batch::WriteBatchConfig,
+};
+
+use crate::config::NetworkConfig;
+use crate::error::{Error, ErrorKind};
+
+/// `import` subcommand
+#[derive(clap::Parser, Command, Debug)]
+pub struct ImportCmd {
+    /// The database directory.
+    path: PathBuf,
+
+    /// The bootstrap file to import.
+    file: PathBuf,
+
+    /// The network of the file.
+    #[arg(long, value_enum, default_value_t = NetworkConfig::Mainnet)]
+    network: NetworkConfig,
+
+    /// Fully verify each block like a synced block, or trust the file.
+    #[arg(long, value_enum, default_value_t = Verify::On)]
+    verify: Verify,
+}
+
+/// How much of the file to check, see [`ImportMode`].
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Verify {
+    /// Verify the PoW, timestamp, miner transaction, inputs and signatures of each block.
+    On,
+    /// Only check the blocks build on each other, for a file from a trusted source.
+    Off,
+}
+
+impl Runnable for ImportCmd {
+    /// Imports the file, printing the amount of blocks added.
+    fn run(&self) {
+        if let Err(e) = self.import() {
+            e.exit();
+        }
+    }
+}
+
+impl ImportCmd {
+    fn import(&self) -> Result<(), Error> {
+        let db = <libmdbx::Database<libmdbx::NoWriteMap> as Database>::open(self.path.clone())
+            .map_err(|e| ErrorKind::Database.context(e))?;
+        db.build().map_err(|e| ErrorKind::Database.context(e))?;
+        let database = DatabaseService::new(Arc::new(db));
+
+        let mut reader = RawReader::new(BufReader::new(File::open(&self.file)?))?;
+        let mode = match self.verify {
+            Verify::On => ImportMode::Verify,
+            Verify::Off => ImportMode::Trusted,
+        };
+
+        let runtime = tokio::runtime::Runtime::new()?;
+        let report = runtime.block_on(async {
+            let config = Config::for_network(self.network.into());
+            let mut verifier = Verifier::init_with_genesis(config, database.clone()).await?;
+
+            import_blocks(
+                &mut reader,
+                &mut verifier,
+                mode,
+                database,
+                WriteBatchConfig::default(),
+            )
+            .await
+        })?;
+
+        println!(
+            "Imported {} blocks, skipped {} blocks already in the database",
+            report.imported, report.skipped
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//! `self-test` subcommand - runs quick end-to-end checks on this build of Cuprate
+//!
+//! Each check exercises one component the node relies on, so a report from a user can show which
+//! part of their build is broken:
+//!
+//! - a RandomX hash against a known test vector
+//! - an epee round-trip and a levin round-trip of a network message
+//! - opening a database in a temporary directory, writing to it and reading it back
+//! - generating the genesis block of each network and checking its hash
+
+use std::panic::{catch_unwind, UnwindSafe};
+
+use abscissa_core::{Command, Runnable};
+use bytes::BytesMut;
+use randomx_rs::{RandomXCache, RandomXFlag, RandomXVM};
+
+use cuprate_common::Network;
+use cuprate_database::{
+    database::Database, table, transaction::Transaction, transaction::WriteTransaction,
+};
+use monero_consensus::genesis::generate_genesis_block;
+use monero_wire::{Message, MoneroWireCodec, PingResponse, ResponseMessage};
+
+/// The error a check fails with.
+type CheckError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The RandomX test vector from the reference implementation's tests.
+const RANDOMX_KEY: &[u8] = b"test key 000";
+const RANDOMX_INPUT: &[u8] = b"This is a test";
+const RANDOMX_HASH: &str = "639183aae1bf4c9a35884cb46b09cad9175f04efd7684e7262a0ac1c2f0b4e3f";
+
+/// `self-test` subcommand
+#[derive(clap::Parser, Command, Debug)]
+pub struct SelfTestCmd {}
+
+impl Runnable for SelfTestCmd {
+    /// Runs every check and prints a PASS/FAIL report, exits with an error if a check failed.
+    fn run(&self) {
+        let mut results = vec![
+            ("RandomX hash", run_check(check_randomx)),
+            ("epee round-trip", run_check(check_epee)),
+            ("levin round-trip", run_check(check_levin)),
+            ("database open/write/read", run_check(check_database)),
+        ];
+        for (name, network) in [
+            ("mainnet genesis block", Network::Mainnet),
+            ("testnet genesis block", Network::Testnet),
+            ("stagenet genesis block", Network::Stagenet),
+        ] {
+            results.push((name, run_check(move || check_genesis(network))));
+        }
+
+        let mut failed = 0;
+        for (name, result) in &results {
+            match result {
+                Ok(()) => println!("PASS {name}"),
+                Err(e) => {
+                    failed += 1;
+                    println!("FAIL {name}: {e}");
+                }
+            }
+        }
+
+        println!("{} passed, {} failed", results.len() - failed, failed);
+
+        if failed != 0 {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Runs a check, a panicking check fails instead of stopping the report.
+fn run_check(
+    check: impl FnOnce() -> Result<(), CheckError> + UnwindSafe,
+) -> Result<(), CheckError> {
+    catch_unwind(check).unwrap_or_else(|panic| {
+        let msg = panic
+            .downcast_ref::<&str>()
+            .map(|msg| msg.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(format!("panicked: {msg}").into())
+    })
+}
+
+fn check_randomx() -> Result<(), CheckError> {
+    let flags = RandomXFlag::get_recommended_flags();
+    let cache = RandomXCache::new(flags, RANDOMX_KEY)?;
+    let vm = RandomXVM::new(flags, Some(cache), None)?;
+
+    let hash = hex::encode(vm.calculate_hash(RANDOMX_INPUT)?);
+    if hash != RANDOMX_HASH {
+        return Err(format!("got hash {hash}, expected {RANDOMX_HASH}").into());
+    }
+    Ok(())
+}
+
+fn ping_response() -> PingResponse {
+    PingResponse {
+        status: "OK".to_string(),
+        peer_id: 0x1234_5678_9abc_def0,
+    }
+}
+
+fn check_epee() -> Result<(), CheckError> {
+    let ping = ping_response();
+
+    let bytes = epee_encoding::to_bytes(&ping)?;
+    let decoded: PingResponse = epee_encoding::from_bytes(&bytes)?;
+
+    if decoded != ping {
+        return Err(format!("decoded {decoded:?}, expected {ping:?}").into());
+    }
+    Ok(())
+}
+
+fn check_levin() -> Result<(), CheckError> {
+    let mut codec = MoneroWireCodec::default();
+    let mut buf = BytesMut::new();
+
+    codec.encode_message(
+        Message::Response(ResponseMessage::Ping(ping_response())),
+        &mut buf,
+    )?;
+
+    match codec.decode_message(&mut buf)? {
+        Some(Message::Response(ResponseMessage::Ping(ping))) if ping == ping_response() => {}
+        Some(_) => return Err("decoded a different message".into()),
+        None => return Err("the encoded message was incomplete".into()),
+    }
+    if !buf.is_empty() {
+        return Err(format!("{} bytes left after decoding", buf.len()).into());
+    }
+    Ok(())
+}
+
+fn check_database() -> Result<(), CheckError> {
+    let path = std::env::temp_dir().join(format!("cuprate-self-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path)?;
+
+    let res = (|| -> Result<(), CheckError> {
+        let db = <libmdbx::Database<libmdbx::NoWriteMap> as Database>::open(path.clone())?;
+        db.build()?;
+        db.check_all_tables_exist()?;
+
+        // libmdbx's transactions have their own `put` and `get`, so call the database traits' ones.
+        let rw_tx = db.tx_mut()?;
+        WriteTransaction::put::<table::properties>(&rw_tx, &0, &42)?;
+        Transaction::commit(rw_tx)?;
+
+        let value = Transaction::get::<table::properties>(&db.tx()?, &0)?;
+        if value != Some(42) {
+            return Err(format!("read back {value:?}, expected Some(42)").into());
+        }
+        Ok(())
+    })();
+
+    let _ = std::fs::remove_dir_all(&path);
+    res
+}
+
+fn check_genesis(network: Network) -> Result<(), CheckError> {
+    let expected = match network {
+        Network::Mainnet | Network::Regtest => "418015bb9ae982a1975da7d79277c2705727a56894ba0fb246adaabb1f4632e3",
+        Network::Testnet => "48ca7cd3c8de5b6a4d53d2861fbdaedca141553559f9be9520068053cda8430b",
+        Network::Stagenet => "76ee3cc98646292206cd3e86f74d88b4dcc1d937088645e9b0cbca84b7ce74eb",
+    };
+
+    let hash = hex::encode(generate_genesis_block(&network).hash());
+    if hash != expected {
+        return Err(format!("got hash {hash}, expected {expected}").into());
+    }
+    Ok(())
+}
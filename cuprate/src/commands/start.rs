@@ -1,11 +1,46 @@
 //! `start` subcommand - example of how to write a subcommand
+//!
+//! If the config has a `[node]` section the node is run, see [`cuprate_node`], until one of its
+//! subsystems fails. A new database is started from the network's genesis block. The node runs a tx
+//! pool, which verifies new transactions against the node's chain.
+//!
+//! With `prune_blockchain` set a new database is pruned to a random stripe, like monerod's
+//! `--prune-blockchain`. A database keeps the pruning seed it was created with.
+//!
+//! With `zmq_pub` set new blocks are published on ZMQ, like monerod's `--zmq-pub`.
+//!
+//! The node verifies the blocks submitted with the RPC's `submit_block` and adds the valid ones to
+//! its chain. It doesn't connect to peers, so it doesn't sync the chain from the network.
+//!
+//! A database marked by `compact-db --on-start`, or by the RPC's `compact_db`, is compacted before
+//! it is opened.
 
 /// App-local prelude includes `app_reader()`/`app_writer()`/`app_config()`
 /// accessors along with logging macros. Customize as you see fit.
 use crate::prelude::*;
 
-use crate::config::CuprateConfig;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use crate::config::{CuprateConfig, NodeSection};
+use crate::error::{Error, ErrorKind};
 use abscissa_core::{config, Command, FrameworkError, Runnable};
+use cuprate_common::{PruningSeed, CRYPTONOTE_PRUNING_LOG_STRIPES};
+use cuprate_database::{
+    compaction::{compact_if_requested, request_compaction},
+    database::Database,
+    service::DatabaseService,
+};
+use cuprate_node::NodeBuilder;
+use cuprate_rpc::{
+    policy::RpcConfig,
+    zmq::{run_publisher, ZmqPublisher},
+    DatabaseCompactor,
+};
+use futures::future::{pending, try_join3, Either};
+use monero_consensus::{txpool::TxPoolConfig, verifier::Config};
+use rand::Rng;
 
 /// `start` subcommand
 ///
@@ -25,9 +60,117 @@ impl Runnable for StartCmd {
     fn run(&self) {
         let config = APP.config();
         println!("Hello, {}!", &config.hello.recipient);
+
+        let node = config.node.clone();
+        drop(config);
+        if let Some(node) = node {
+            if let Err(e) = run_node(&node) {
+                e.exit();
+            }
+        }
     }
 }
 
+/// Marks the database in this directory to be compacted the next time the node starts.
+#[derive(Debug)]
+struct OnStartCompactor(PathBuf);
+
+impl DatabaseCompactor for OnStartCompactor {
+    fn request_compaction(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        request_compaction(&self.0)?;
+        Ok(())
+    }
+}
+
+/// Opens the database, compacting it first if it was marked, and runs the node on it.
+fn run_node(config: &NodeSection) -> Result<(), Error> {
+    let interrupt = AtomicBool::new(false);
+    let report = compact_if_requested(&config.data_dir, &interrupt, |progress| {
+        println!(
+            "[{}/{}] {}: {}/{} entries",
+            progress.tables_done + 1,
+            progress.tables_total,
+            progress.table,
+            progress.entries_copied,
+            progress.table_entries
+        );
+    })?;
+    if let Some(report) = report {
+        println!(
+            "Compacted the database from {} MB to {} MB",
+            report.size_before / 1_000_000,
+            report.size_after / 1_000_000
+        );
+    }
+
+    let db = <libmdbx::Database<libmdbx::NoWriteMap> as Database>::open(config.data_dir.clone())
+        .map_err(|e| ErrorKind::Database.context(e))?;
+    db.build().map_err(|e| ErrorKind::Database.context(e))?;
+    let database = DatabaseService::new(Arc::new(db));
+
+    let mut pruning_seed = database
+        .pruning_seed()
+        .map_err(|e| ErrorKind::Database.context(e))?;
+    if config.prune_blockchain && !pruning_seed.is_pruned() {
+        let stripe = rand::thread_rng().gen_range(1..=1 << CRYPTONOTE_PRUNING_LOG_STRIPES);
+        pruning_seed = PruningSeed::new(stripe, CRYPTONOTE_PRUNING_LOG_STRIPES)
+            .expect("The stripe is in range");
+        // This fails if the database already has blocks, it is not pruned after the fact.
+        database
+            .set_pruning_seed(pruning_seed)
+            .map_err(|e| ErrorKind::Database.context(e))?;
+    }
+
+    let mut builder = NodeBuilder::from_config(
+        Config::for_network(config.network.into()).with_pruning_seed(pruning_seed),
+    )
+    .with_genesis()
+    .with_tx_pool(TxPoolConfig::default())
+    .with_block_submission();
+    if let Some(addr) = config.rpc_address {
+        builder = builder
+            .with_rpc(
+                addr,
+                RpcConfig {
+                    restricted: config.restricted_rpc,
+                },
+            )
+            .with_database_compactor(Arc::new(OnStartCompactor(config.data_dir.clone())));
+    }
+
+    let mut zmq = None;
+    if let Some(endpoint) = &config.zmq_pub {
+        let (zmq_publisher, messages) = ZmqPublisher::new();
+        builder = builder.with_zmq_publisher(zmq_publisher);
+        zmq = Some((endpoint, messages));
+    }
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let mut node = builder.build(database).await?;
+        let tasks = node.take_tasks();
+
+        let zmq = match zmq {
+            Some((endpoint, messages)) => Either::Left(async move {
+                run_publisher(endpoint, messages)
+                    .await
+                    .map_err(|e| Error::from(ErrorKind::Io.context(e)))
+            }),
+            None => Either::Right(pending()),
+        };
+
+        // Only an error on our side, like a database failure, stops the verification.
+        let verify = async {
+            node.verify_queued_blocks()
+                .await
+                .map_err(|e| Error::from(ErrorKind::Database.context(e)))
+        };
+
+        try_join3(async { Ok(tasks.run().await?) }, zmq, verify).await?;
+        Ok::<_, Error>(())
+    })
+}
+
 impl config::Override<CuprateConfig> for StartCmd {
     // Process the given command line options, overriding settings from
     // a configuration file using explicit flags taken from command-line
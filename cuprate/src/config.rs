@@ -4,14 +4,22 @@
 //! application's configuration file and/or command-line options
 //! for specifying it.
 
+use std::{net::SocketAddr, path::PathBuf};
+
 use serde::{Deserialize, Serialize};
 
+use cuprate_common::Network;
+
 /// Cuprate Configuration
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct CuprateConfig {
     /// An example configuration section
     pub hello: ExampleSection,
+
+    /// The node to run with `start`, no node is run without this section
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<NodeSection>,
 }
 
 /// Default configuration settings.
@@ -22,6 +30,7 @@ impl Default for CuprateConfig {
     fn default() -> Self {
         Self {
             hello: ExampleSection::default(),
+            node: None,
         }
     }
 }
@@ -43,3 +52,51 @@ impl Default for ExampleSection {
         }
     }
 }
+
+/// Node configuration section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeSection {
+    /// The network to run on
+    #[serde(default)]
+    pub network: NetworkConfig,
+    /// The database directory
+    pub data_dir: PathBuf,
+    /// The address to run the RPC server on, no RPC server is run if this is not set
+    #[serde(default)]
+    pub rpc_address: Option<SocketAddr>,
+    /// Hide the privileged RPC methods, for public nodes
+    #[serde(default)]
+    pub restricted_rpc: bool,
+    /// The endpoint to publish ZMQ messages on, like monerod's `--zmq-pub`, nothing is published if
+    /// this is not set
+    #[serde(default)]
+    pub zmq_pub: Option<String>,
+    /// Prune a new database to a random stripe, like monerod's `--prune-blockchain`, a database that
+    /// already has blocks keeps its pruning seed
+    #[serde(default)]
+    pub prune_blockchain: bool,
+}
+
+/// The network of the node.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkConfig {
+    /// Main-net
+    #[default]
+    Mainnet,
+    /// Test-net
+    Testnet,
+    /// Stage-net
+    Stagenet,
+}
+
+impl From<NetworkConfig> for Network {
+    fn from(network: NetworkConfig) -> Self {
+        match network {
+            NetworkConfig::Mainnet => Network::Mainnet,
+            NetworkConfig::Testnet => Network::Testnet,
+            NetworkConfig::Stagenet => Network::Stagenet,
+        }
+    }
+}
@@ -1,6 +1,23 @@
 //! Error types
+//!
+//! Each [`ErrorKind`] exits the process with its own code, so supervisors and scripts can tell the
+//! failures apart without reading the logs. The codes follow `sysexits.h`:
+//!
+//! | Kind                               | Exit code |
+//! |------------------------------------|-----------|
+//! | [`ErrorKind::Config`]              | 78        |
+//! | [`ErrorKind::Io`]                  | 74        |
+//! | [`ErrorKind::Database`]            | 65        |
+//! | [`ErrorKind::PortInUse`]           | 69        |
+//! | [`ErrorKind::IncompatibleNetwork`] | 76        |
+//!
+//! Commands exit with [`Error::exit`], which prints the error and its code as the final line. Errors
+//! found by the framework before a command runs, like a config file that doesn't parse, exit with 1.
 
 use abscissa_core::error::{BoxError, Context};
+use cuprate_database::compaction::CompactionError;
+use cuprate_node::NodeError;
+use monero_consensus::{bootstrap::BootstrapError, ConsensusError, InternalError};
 use std::{
     fmt::{self, Display},
     io,
@@ -18,9 +35,32 @@ pub enum ErrorKind {
     /// Input/output error
     #[error("I/O error")]
     Io,
+
+    /// The database is corrupt or can't be read
+    #[error("database error")]
+    Database,
+
+    /// A port we need to listen on is taken
+    #[error("port in use")]
+    PortInUse,
+
+    /// The database or a peer is for a different network
+    #[error("incompatible network")]
+    IncompatibleNetwork,
 }
 
 impl ErrorKind {
+    /// The code the process exits with for this kind of error
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Config => 78,
+            ErrorKind::Io => 74,
+            ErrorKind::Database => 65,
+            ErrorKind::PortInUse => 69,
+            ErrorKind::IncompatibleNetwork => 76,
+        }
+    }
+
     /// Create an error context from this error
     pub fn context(self, source: impl Into<BoxError>) -> Context<ErrorKind> {
         Context::new(self, Some(source.into()))
@@ -39,6 +79,15 @@ impl Deref for Error {
     }
 }
 
+impl Error {
+    /// Prints the error as the final line and exits with its kind's exit code
+    pub fn exit(&self) -> ! {
+        let code = self.kind().exit_code();
+        eprintln!("Cuprate exiting: {self} (exit code {code})");
+        std::process::exit(code)
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.0.fmt(f)
@@ -65,6 +114,52 @@ impl From<Context<ErrorKind>> for Error {
 
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
-        ErrorKind::Io.context(err).into()
+        match err.kind() {
+            io::ErrorKind::AddrInUse => ErrorKind::PortInUse.context(err).into(),
+            _ => ErrorKind::Io.context(err).into(),
+        }
+    }
+}
+
+impl From<CompactionError> for Error {
+    fn from(err: CompactionError) -> Self {
+        match err {
+            CompactionError::Io(err) => err.into(),
+            err => ErrorKind::Database.context(err).into(),
+        }
+    }
+}
+
+impl From<NodeError> for Error {
+    fn from(err: NodeError) -> Self {
+        match err {
+            NodeError::Rpc(err) => {
+                // The server fails to start with the I/O error of binding its port.
+                let mut source = std::error::Error::source(&err);
+                while let Some(e) = source {
+                    if let Some(io_err) = e.downcast_ref::<io::Error>() {
+                        if io_err.kind() == io::ErrorKind::AddrInUse {
+                            return ErrorKind::PortInUse.context(err).into();
+                        }
+                    }
+                    source = e.source();
+                }
+                ErrorKind::Io.context(err).into()
+            }
+            err => ErrorKind::Database.context(err).into(),
+        }
+    }
+}
+
+impl From<BootstrapError> for Error {
+    fn from(err: BootstrapError) -> Self {
+        match err {
+            BootstrapError::Io(err) => err.into(),
+            BootstrapError::GenesisMismatch(_)
+            | BootstrapError::Consensus(ConsensusError::Internal(
+                InternalError::GenesisMismatch { .. },
+            )) => ErrorKind::IncompatibleNetwork.context(err).into(),
+            err => ErrorKind::Database.context(err).into(),
+        }
     }
 }
@@ -2,6 +2,9 @@
 //!
 //! Application based on the [Abscissa] framework.
 //!
+//! The node itself is the `cuprate-node` library, this application only reads the config and runs
+//! it, see the `start` subcommand.
+//!
 //! [Abscissa]: https://github.com/iqlusioninc/abscissa
 
 // Tip: Deny warnings with `RUSTFLAGS="-D warnings"` environment variable in CI
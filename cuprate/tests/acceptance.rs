@@ -28,7 +28,7 @@ use once_cell::sync::Lazy;
 /// the runner acquire a mutex when executing commands and inspecting
 /// exit statuses, serializing what would otherwise be multithreaded
 /// invocations as `cargo test` executes tests in parallel by default.
-pub static RUNNER: Lazy<CmdRunner> = Lazy::new(|| CmdRunner::default());
+pub static RUNNER: Lazy<CmdRunner> = Lazy::new(CmdRunner::default);
 
 /// Use `CuprateConfig::default()` value if no config or args
 #[test]
@@ -44,7 +44,7 @@ fn start_no_args() {
 fn start_with_args() {
     let mut runner = RUNNER.clone();
     let mut cmd = runner
-        .args(&["start", "acceptance", "test"])
+        .args(["start", "acceptance", "test"])
         .capture_stdout()
         .run();
 
@@ -74,7 +74,7 @@ fn start_with_config_and_args() {
     let mut runner = RUNNER.clone();
     let mut cmd = runner
         .config(&config)
-        .args(&["start", "acceptance", "test"])
+        .args(["start", "acceptance", "test"])
         .capture_stdout()
         .run();
 
@@ -89,3 +89,25 @@ fn version_no_args() {
     let mut cmd = runner.arg("--version").capture_stdout().run();
     cmd.stdout().expect_regex(r"\A\w+ [\d\.\-]+\z");
 }
+
+/// Every self test check passes on a working build
+#[test]
+fn self_test_passes() {
+    let mut runner = RUNNER.clone();
+    let mut cmd = runner.arg("self-test").capture_stdout().run();
+    cmd.stdout().expect_line("PASS RandomX hash");
+    cmd.wait().unwrap().expect_success();
+}
+
+/// Failures exit with the code of their kind of error
+#[test]
+fn compact_db_missing_database() {
+    // Run the binary itself, `cargo run` prints the workspace's build warnings to stderr.
+    let mut runner = CmdRunner::new(env!("CARGO_BIN_EXE_cuprate"));
+    let mut cmd = runner
+        .args(["compact-db", "/nonexistent/cuprate-database"])
+        .capture_stderr()
+        .run();
+    cmd.stderr().expect_regex(r"\ACuprate exiting: .* \(exit code 74\)\z");
+    cmd.wait().unwrap().expect_code(74);
+}
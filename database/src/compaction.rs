@@ -0,0 +1,227 @@
+//! ### Compaction module
+//! This module contains [`compact`], which rewrites a database to reclaim its free pages. MDBX reuses the pages freed by pruning
+//! or reorgs but never gives them back to the filesystem, [`compact`] copies every table in key order into a new database, which
+//! only has the pages the data needs, and swaps it in.
+//!
+//! The copy is made next to the database and is only swapped in once every table is copied, so an interrupted compaction leaves
+//! the database as it was. The database must not be open while it is compacted, a running node can instead call
+//! [`request_compaction`] and compact the database with [`compact_if_requested`] the next time it starts.
+
+use crate::{
+    error::DB_FAILURES,
+    table::{self, Table},
+};
+use libmdbx::{NoWriteMap, WriteFlags};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// The amount of entries copied in each write transaction, the interrupt flag is checked between batches.
+pub const COMPACTION_BATCH_SIZE: usize = 10_000;
+
+/// The file marking a database to be compacted by [`compact_if_requested`].
+const COMPACTION_REQUEST_FILE: &str = "compact.request";
+/// The name of MDBX's data file in the database directory.
+const MDBX_DATA_FILE: &str = "mdbx.dat";
+
+/// Every table, and if it is a duplicated table.
+const TABLES: [(&str, bool); 14] = [
+    (table::blockhash::TABLE_NAME, true),
+    (table::blockmetadata::TABLE_NAME, true),
+    (table::blocks::TABLE_NAME, false),
+    (table::altblock::TABLE_NAME, false),
+    (table::txspruned::TABLE_NAME, false),
+    (table::txsprunablehash::TABLE_NAME, true),
+    (table::txsprunabletip::TABLE_NAME, false),
+    (table::txsprunable::TABLE_NAME, false),
+    (table::txsoutputs::TABLE_NAME, true),
+    (table::txsidentifier::TABLE_NAME, true),
+    (table::prerctoutputmetadata::TABLE_NAME, true),
+    (table::rctoutputs::TABLE_NAME, false),
+    (table::spentkeys::TABLE_NAME, true),
+    (table::properties::TABLE_NAME, false),
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum CompactionError {
+    #[error("The compaction was interrupted, the database was not changed")]
+    Interrupted,
+    #[error("Database error: {0}")]
+    Database(#[from] DB_FAILURES),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<libmdbx::Error> for CompactionError {
+    fn from(e: libmdbx::Error) -> Self {
+        CompactionError::Database(e.into())
+    }
+}
+
+/// The progress of a compaction, given after each batch of entries is copied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionProgress {
+    /// The table being copied.
+    pub table: &'static str,
+    /// The amount of tables copied before this one.
+    pub tables_done: usize,
+    pub tables_total: usize,
+    /// The amount of entries of this table copied.
+    pub entries_copied: usize,
+    pub table_entries: usize,
+}
+
+/// The size of the database's data file before and after a compaction, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionReport {
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// Marks the database at `path` to be compacted by [`compact_if_requested`].
+pub fn request_compaction(path: &Path) -> Result<(), std::io::Error> {
+    fs::write(path.join(COMPACTION_REQUEST_FILE), [])
+}
+
+/// Compacts the database at `path` if [`request_compaction`] was called for it, returns [`None`] if it wasn't.
+///
+/// The request is removed by the compaction, the copy doesn't have it, an interrupted compaction is run again next time.
+pub fn compact_if_requested(
+    path: &Path,
+    interrupt: &AtomicBool,
+    progress: impl FnMut(&CompactionProgress),
+) -> Result<Option<CompactionReport>, CompactionError> {
+    if !path.join(COMPACTION_REQUEST_FILE).exists() {
+        return Ok(None);
+    }
+
+    compact(path, interrupt, progress).map(Some)
+}
+
+/// Rewrites the database at `path` without its free pages.
+///
+/// `progress` is called after every [`COMPACTION_BATCH_SIZE`] entries, setting `interrupt` stops the compaction after the
+/// current batch with [`CompactionError::Interrupted`].
+pub fn compact(
+    path: &Path,
+    interrupt: &AtomicBool,
+    mut progress: impl FnMut(&CompactionProgress),
+) -> Result<CompactionReport, CompactionError> {
+    let (copy_path, old_path) = (sibling(path, "compacting"), sibling(path, "old"));
+    recover(path, &copy_path, &old_path)?;
+
+    let size_before = fs::metadata(path.join(MDBX_DATA_FILE))?.len();
+
+    fs::create_dir_all(&copy_path)?;
+    let res = copy_tables(path, &copy_path, interrupt, &mut progress);
+    if let Err(e) = res {
+        fs::remove_dir_all(&copy_path)?;
+        return Err(e);
+    }
+
+    // A crash between the renames is undone by `recover`.
+    fs::rename(path, &old_path)?;
+    fs::rename(&copy_path, path)?;
+    fs::remove_dir_all(&old_path)?;
+
+    Ok(CompactionReport {
+        size_before,
+        size_after: fs::metadata(path.join(MDBX_DATA_FILE))?.len(),
+    })
+}
+
+/// Copies every table of the database at `path` into a new database at `copy_path`.
+fn copy_tables(
+    path: &Path,
+    copy_path: &Path,
+    interrupt: &AtomicBool,
+    progress: &mut impl FnMut(&CompactionProgress),
+) -> Result<(), CompactionError> {
+    use crate::database::Database;
+
+    let db = <libmdbx::Database<NoWriteMap> as Database>::open(path.to_path_buf())?;
+    db.check_all_tables_exist()?;
+    let copy = <libmdbx::Database<NoWriteMap> as Database>::open(copy_path.to_path_buf())?;
+    copy.build()?;
+
+    let ro_tx = db.begin_ro_txn()?;
+
+    for (tables_done, (table_name, dup)) in TABLES.into_iter().enumerate() {
+        let table = ro_tx.open_table(Some(table_name))?;
+        let mut cursor = ro_tx.cursor(&table)?;
+
+        let mut state = CompactionProgress {
+            table: table_name,
+            tables_done,
+            tables_total: TABLES.len(),
+            entries_copied: 0,
+            table_entries: ro_tx.table_stat(&table)?.entries(),
+        };
+
+        let mut pair = cursor.first::<Vec<u8>, Vec<u8>>()?;
+        let mut last_key: Option<Vec<u8>> = None;
+
+        while pair.is_some() {
+            if interrupt.load(Ordering::Relaxed) {
+                return Err(CompactionError::Interrupted);
+            }
+
+            let rw_tx = copy.begin_rw_txn()?;
+            {
+                let copy_table = rw_tx.open_table(Some(table_name))?;
+                let mut write_cursor = rw_tx.cursor(&copy_table)?;
+
+                for _ in 0..COMPACTION_BATCH_SIZE {
+                    let Some((key, value)) = pair else {
+                        break;
+                    };
+
+                    // The entries are read in order, so they can always be appended.
+                    let flags = if dup && last_key.as_ref() == Some(&key) {
+                        WriteFlags::APPEND_DUP
+                    } else {
+                        WriteFlags::APPEND
+                    };
+                    write_cursor.put(&key, &value, flags)?;
+
+                    last_key = Some(key);
+                    state.entries_copied += 1;
+                    pair = cursor.next::<Vec<u8>, Vec<u8>>()?;
+                }
+            }
+            if !rw_tx.commit()? {
+                return Err(DB_FAILURES::FailedToCommit.into());
+            }
+
+            progress(&state);
+        }
+    }
+
+    Ok(())
+}
+
+/// Cleans up after an interrupted compaction, restoring the old database if it was moved.
+fn recover(path: &Path, copy_path: &Path, old_path: &Path) -> Result<(), std::io::Error> {
+    if !path.exists() && old_path.exists() {
+        fs::rename(old_path, path)?;
+    }
+
+    for leftover in [copy_path, old_path] {
+        match fs::remove_dir_all(leftover) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Returns a path next to the database's directory, `<database>.<suffix>`.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
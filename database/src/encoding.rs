@@ -20,7 +20,7 @@ impl<'src, R: Reader> Read for ReaderCompat<'src, R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         self.0
             .read(buf)
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "bincode reader Error"))?;
+            .map_err(|_| std::io::Error::other("bincode reader Error"))?;
         Ok(buf.len())
     }
 }
@@ -9,9 +9,10 @@
 //! -| Outputs      |-
 //! -| SpentKeys    |-
 //! -| Categories   |-
+//!
+//! Blocks are added by the service's `write_block`, which writes their outputs, key images and
+//! transaction indexes with [`Interface::add_block_write_batch`].
 
-// TODO: add_transaction() not finished due to ringct zeroCommit missing function
-// TODO: in add_transaction_data() Investigate unprunable_size == 0 condition of monerod
 // TODO: Do we need correct_block_cumulative_difficulties()
 // TODO: remove_tx_outputs() can be done otherwise since we don't use global output index
 // TODO: Check all documentations
@@ -21,12 +22,10 @@ use crate::{
     error::DB_FAILURES,
     table::{self},
     transaction::{self, DupCursor, DupWriteCursor, Transaction, WriteCursor, WriteTransaction},
-    types::{
-        calculate_prunable_hash, get_transaction_prunable_blob, AltBlock, BlockMetadata,
-        OutputMetadata, TransactionPruned, TxIndex, TxOutputIdx,
-    },
-    BINCODE_CONFIG,
+    types::{AltBlock, BlockWriteBatch, OutputMetadata, RctOutput, TransactionPruned, TxOutputIdx},
 };
+use std::collections::{hash_map::Entry, HashMap};
+
 use monero::{
     blockdata::transaction::KeyImage, cryptonote::hash::Hashable, util::ringct::Key, Block,
     BlockHeader, Hash, TxIn, TxOut,
@@ -48,116 +47,6 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
 
     // ----------------------------------| Blocks |---------------------------------
 
-    /// `add_block` add the block and metadata to the db.
-    ///
-    /// In case of failures, a `DB_FAILURES`
-    ///
-    /// Parameters:
-    /// `blk`: is the block to be added
-    /// `txs`: is the collection of transactions related to this block
-    /// `block_weight`: is the weight of the block (data's total)
-    /// `long_term_block_weight`: is the long term weight of the block (data's total)
-    /// `cumulative_difficulty`: is the accumulated difficulty at this block.
-    /// `coins_generated` is the number of coins generated after this block.
-    fn add_block(
-        &'service self,
-        blk: Block,
-        txs: Vec<monero::Transaction>,
-        block_weight: u64,
-        long_term_block_weight: u64,
-        cumulative_difficulty: u128,
-        coins_generated: u64,
-    ) -> Result<(), DB_FAILURES> {
-        // *sanity*
-        if blk.tx_hashes.len() != txs.len() {
-            return Err(DB_FAILURES::Other("sanity : Inconsistent tx/hashed sizes"));
-        }
-
-        let blk_hash = blk.id();
-
-        // let parent_height = self.height()?;
-
-        let mut num_rct_outs = 0u64;
-        self.add_transaction(blk.miner_tx.clone())?;
-
-        if blk.miner_tx.prefix.version.0 == 2 {
-            num_rct_outs += blk.miner_tx.prefix.outputs.len() as u64;
-        }
-
-        // let mut tx_hash = Hash::null();
-        for tx in txs.into_iter()
-        /*.zip(0usize..)*/
-        {
-            // tx_hash = blk.tx_hashes[tx.1];
-            for out in tx.prefix.outputs.iter() {
-                if out.amount.0 == 0 {
-                    num_rct_outs += 1;
-                }
-            }
-            self.add_transaction(tx /*.0*/)?;
-        }
-
-        let blk_metadata = BlockMetadata {
-            timestamp: blk.header.timestamp.0,
-            total_coins_generated: coins_generated,
-            weight: block_weight,
-            cumulative_difficulty,
-            block_hash: blk_hash.into(),
-            cum_rct: num_rct_outs, // num_rct_outs here is the rct outs of the block only. The cumulative rct will be added in `add_block_data` fn
-            long_term_block_weight,
-        };
-
-        self.add_block_data(blk, blk_metadata)
-    }
-
-    /// `add_block_data` add the actual block's data and metadata to the db. Underlying function of `add_block`
-    ///
-    /// In case of failures, a `DB_FAILURES` will be return.
-    ///
-    /// Parameters:
-    /// `blk`: is the block to add
-    /// `blk_metadata`: is the block's metadata to add
-    fn add_block_data(
-        &'service self,
-        blk: Block,
-        mut blk_metadata: BlockMetadata,
-    ) -> Result<(), DB_FAILURES> {
-        let height = self.height()?;
-
-        let mut cursor_blockhash = self.write_cursor_dup::<table::blockhash>()?;
-        let mut cursor_blockmetadata = self.write_cursor_dup::<table::blockmetadata>()?;
-
-        if cursor_blockhash
-            .get_dup(&(), &blk_metadata.block_hash)?
-            .is_some()
-        {
-            return Err(DB_FAILURES::AlreadyExist(
-                "Attempting to insert a block already existent in the database",
-            ))?;
-        }
-
-        if height > 0 {
-            let parent_height: u64 = cursor_blockhash
-                .get_dup(&(), &blk.header.prev_id.into())?
-                .ok_or(DB_FAILURES::NotFound("Can't find parent block"))?;
-
-            if parent_height != height - 1 {
-                return Err(DB_FAILURES::Other("Top block is not a new block's parent"));
-            }
-        }
-
-        if blk.header.major_version.0 > 3 {
-            let last_height = height - 1;
-
-            let parent_cum_rct = self.get_block_cumulative_rct_outputs(last_height)?;
-            blk_metadata.cum_rct += parent_cum_rct;
-        }
-        self.put::<table::blocks>(&height, &blk.into())?;
-        cursor_blockhash.put_cursor_dup(&(), &blk_metadata.block_hash, &height)?;
-        cursor_blockmetadata.put_cursor_dup(&(), &height, &blk_metadata)
-        // blockhfversion missing but do we really need this table?
-    }
-
     /// `pop_block` pops the top block off the blockchain.
     ///
     /// Return the block that was popped. In case of failures, a `DB_FAILURES` will be return.
@@ -420,116 +309,6 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
 
     // ------------------------------|  Transactions  |-----------------------------
 
-    /// `add_transaction` add the corresponding transaction and its hash to the specified block.
-    ///
-    /// In case of failures, a DB_FAILURES will be return. Precisely, a TX_EXISTS will be returned if the
-    /// transaction to be added already exists in the database.
-    ///
-    /// Parameters:
-    /// `blk_hash`: is the hash of the block which inherit the transaction
-    /// `tx`: is obviously the transaction to add
-    /// `tx_hash`: is the hash of the transaction.
-    /// `tx_prunable_hash_ptr`: is the hash of the prunable part of the transaction.
-    fn add_transaction(&'service self, tx: monero::Transaction) -> Result<(), DB_FAILURES> {
-        let is_coinbase: bool = tx.prefix.inputs.is_empty();
-        let tx_hash = tx.hash();
-
-        let mut tx_prunable_blob = Vec::new();
-        get_transaction_prunable_blob(&tx, &mut tx_prunable_blob).unwrap();
-
-        let tx_prunable_hash: Option<Hash> = calculate_prunable_hash(&tx, &tx_prunable_blob);
-
-        for txin in tx.prefix.inputs.iter() {
-            if let TxIn::ToKey {
-                amount: _,
-                key_offsets: _,
-                k_image,
-            } = txin
-            {
-                self.add_spent_key(k_image.clone())?;
-            } else {
-                return Err(DB_FAILURES::Other(
-                    "Unsupported input type, aborting transaction addition",
-                ));
-            }
-        }
-
-        let tx_id =
-            self.add_transaction_data(tx.clone(), tx_prunable_blob, tx_hash, tx_prunable_hash)?;
-
-        let tx_num_outputs = tx.prefix.outputs.len();
-        let amount_output_dinces: Vec<u64> = Vec::with_capacity(tx_num_outputs);
-
-        for txout in tx.prefix.outputs.iter().zip(0..tx_num_outputs) {
-            if is_coinbase && tx.prefix.version.0 == 2 {
-                let commitment: Option<Key> = None;
-                // ZeroCommit is from RingCT Module, not finishable yet
-            }
-        }
-        todo!()
-    }
-
-    /// `add_transaction_data` add the specified transaction data to its storage.
-    ///
-    /// It only add the transaction blob and tx's metadata, not the collection of outputs.
-    ///
-    /// Return the hash of the transaction added. In case of failures, a DB_FAILURES will be return.
-    ///
-    /// Parameters:
-    /// `tx`: is the transaction to add
-    /// `tx_prunable_blob`; is its prunable blob.
-    /// `tx_hash`: is the transaction's hash
-    /// `tx_prunable_hash`: is the hash of the prunable part of the transaction
-    fn add_transaction_data(
-        &'service self,
-        tx: monero::Transaction,
-        tx_prunable_blob: Vec<u8>,
-        tx_hash: Hash,
-        tx_prunable_hash: Option<Hash>,
-    ) -> Result<u64, DB_FAILURES> {
-        // Checking if the transaction already exist in the database
-        let res = self.get::<table::txsidentifier>(&tx_hash.into())?;
-        if res.is_some() {
-            return Err(DB_FAILURES::AlreadyExist(
-                "Attempting to add transaction that's already in the db",
-            ));
-        }
-
-        // Inserting tx index in table::txsindetifier
-        let height = self.height()?;
-        let tx_id = self.get_num_tx()?;
-
-        let txindex = TxIndex {
-            tx_id,
-            unlock_time: tx.prefix.unlock_time.0,
-            height,
-        };
-
-        self.put::<table::txsidentifier>(&tx_hash.into(), &txindex)?;
-
-        // TODO: Investigate unprunable_size == 0 condition
-        // Inserting tx pruned part in table::txspruned
-        let tx_pruned = TransactionPruned {
-            prefix: tx.prefix.clone(),
-            rct_signatures: tx.rct_signatures,
-        };
-        self.put::<table::txspruned>(&tx_id, &tx_pruned)?;
-
-        // Inserting tx prunable part in table::txs
-        self.put::<table::txsprunable>(&tx_id, &tx_prunable_blob)?;
-
-        // Checking to see if the database is pruned and inserting into table::txsprunabletip accordingly
-        if self.get_blockchain_pruning_seed()? > 0 {
-            self.put::<table::txsprunabletip>(&tx_id, &height)?;
-        }
-
-        // V2 Tx store hash of their prunable part
-        if let Some(tx_prunable_hash) = tx_prunable_hash {
-            self.put::<table::txsprunablehash>(&tx_id, &tx_prunable_hash.into())?;
-        }
-        Ok(tx_id)
-    }
-
     fn remove_transaction(&'service self, tx_hash: Hash) -> Result<(), DB_FAILURES> {
         let txpruned = self.get_pruned_tx(tx_hash)?;
 
@@ -744,6 +523,9 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
     /// It internally keep track of the global output count. The global output count is also used to index outputs based on
     /// their order of creations.
     ///
+    /// RingCT outputs are appended to the end of [`table::rctoutputs`] at the next global index, Pre-RingCT outputs are inserted
+    /// in [`table::prerctoutputmetadata`] under their amount.
+    ///
     /// Should return the amount output index. In case of failures, a DB_FAILURES will be return.
     ///
     /// Parameters:
@@ -762,29 +544,39 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
     ) -> Result<u64, DB_FAILURES> {
         let height = self.height()?;
 
-        let mut cursor_outputmetadata = self.write_cursor_dup::<table::outputmetadata>()?;
-
-        let pubkey = output.target.as_one_time_key().map(Into::into);
-        let mut out_metadata = OutputMetadata {
-            tx_hash: tx_hash.into(),
-            local_index,
-            pubkey,
-            unlock_time,
-            height,
-            commitment: None,
-        };
+        let pubkey = output.target.as_one_time_key();
 
         // RingCT Outputs
         if let Some(commitment) = commitment {
-            out_metadata.commitment = Some(commitment.into());
+            let pubkey = pubkey.ok_or(DB_FAILURES::Other(
+                "RingCT outputs must have a one time key",
+            ))?;
+            let rct_output = RctOutput {
+                tx_hash: tx_hash.into(),
+                local_index,
+                pubkey: pubkey.into(),
+                unlock_time,
+                height,
+                commitment: commitment.into(),
+            };
 
-            let amount_index = self.get_rct_num_outputs()? + 1;
-            cursor_outputmetadata.put_cursor_dup(&(), &amount_index, &out_metadata)?;
+            let amount_index = self.get_rct_num_outputs()?;
+            let mut cursor_rctoutputs = self.write_cursor::<table::rctoutputs>()?;
+            cursor_rctoutputs.append_cursor(&amount_index, &rct_output)?;
             Ok(amount_index)
         }
         // Pre-RingCT Outputs
         else {
-            let amount_index = self.get_pre_rct_num_outputs(output.amount.0)? + 1;
+            let out_metadata = OutputMetadata {
+                tx_hash: tx_hash.into(),
+                local_index,
+                pubkey: pubkey.map(Into::into),
+                unlock_time,
+                height,
+                commitment: None,
+            };
+
+            let amount_index = self.get_pre_rct_num_outputs(output.amount.0)?;
             let mut cursor = self.write_cursor_dup::<table::prerctoutputmetadata>()?;
             cursor.put_cursor_dup(&output.amount.0, &amount_index, &out_metadata)?;
             Ok(amount_index)
@@ -792,17 +584,8 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
     }
 
     fn remove_output(&'service self, amount: Option<u64>, index: u64) -> Result<(), DB_FAILURES> {
-        let mut cursor_outputmetadata = self.write_cursor_dup::<table::outputmetadata>()?;
-
-        if let Some(amount) = amount {
-            if amount == 0 {
-                cursor_outputmetadata
-                    .get_dup(&(), &index)?
-                    .ok_or(DB_FAILURES::NotFound(
-                        "Failed to find PostRCT output metadata",
-                    ))?;
-                cursor_outputmetadata.del()
-            } else {
+        match amount {
+            Some(amount) if amount != 0 => {
                 let mut cursor = self.write_cursor_dup::<table::prerctoutputmetadata>()?;
                 let _ = cursor
                     .get_dup(&amount, &index)?
@@ -811,13 +594,13 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
                     ))?;
                 cursor.del()
             }
-        } else {
-            cursor_outputmetadata
-                .get_dup(&(), &index)?
-                .ok_or(DB_FAILURES::NotFound(
-                    "Failed to find PostRCT output metadata",
-                ))?;
-            cursor_outputmetadata.del()
+            _ => {
+                let mut cursor_rctoutputs = self.write_cursor::<table::rctoutputs>()?;
+                transaction::Cursor::set(&mut cursor_rctoutputs, &index)?.ok_or(
+                    DB_FAILURES::NotFound("Failed to find PostRCT output metadata"),
+                )?;
+                cursor_rctoutputs.del()
+            }
         }
     }
 
@@ -837,7 +620,6 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
         index: u64,
     ) -> Result<OutputMetadata, DB_FAILURES> {
         let ro_tx = self.db.tx().map_err(Into::into)?;
-        let mut cursor_outputmetadata = ro_tx.cursor_dup::<table::outputmetadata>()?;
 
         if let Some(amount) = amount {
             if amount > 0 {
@@ -849,8 +631,9 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
                     ));
             }
         }
-        cursor_outputmetadata
-            .get_dup(&(), &index)?
+        ro_tx
+            .get::<table::rctoutputs>(&index)?
+            .map(Into::into)
             .ok_or(DB_FAILURES::NotFound(
                 "Failed to find PostRCT output metadata",
             ))
@@ -870,7 +653,6 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
         offsets: Vec<u64>,
     ) -> Result<Vec<OutputMetadata>, DB_FAILURES> {
         let ro_tx = self.db.tx().map_err(Into::into)?;
-        let mut cursor_outputmetadata = ro_tx.cursor_dup::<table::outputmetadata>()?;
         let mut result: Vec<OutputMetadata> = Vec::new();
 
         // Pre-RingCT output to be found.
@@ -879,10 +661,13 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
 
             for ofs in amounts.into_iter().zip(offsets) {
                 if ofs.0 == 0 {
-                    let output = cursor_outputmetadata.get_dup(&(), &ofs.1)?.ok_or(
-                        DB_FAILURES::NotFound("An output hasn't been found in the database"),
-                    )?;
-                    result.push(output);
+                    let output =
+                        ro_tx
+                            .get::<table::rctoutputs>(&ofs.1)?
+                            .ok_or(DB_FAILURES::NotFound(
+                                "An output hasn't been found in the database",
+                            ))?;
+                    result.push(output.into());
                 } else {
                     let output = cursor
                         .get_dup(&ofs.0, &ofs.1)?
@@ -895,13 +680,12 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
         // No Pre-RingCT outputs to be found.
         } else {
             for ofs in offsets {
-                let output =
-                    cursor_outputmetadata
-                        .get_dup(&(), &ofs)?
-                        .ok_or(DB_FAILURES::NotFound(
-                            "An output hasn't been found in the database",
-                        ))?;
-                result.push(output);
+                let output = ro_tx
+                    .get::<table::rctoutputs>(&ofs)?
+                    .ok_or(DB_FAILURES::NotFound(
+                        "An output hasn't been found in the database",
+                    ))?;
+                result.push(output.into());
             }
         }
 
@@ -910,15 +694,12 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
 
     /// `get_rct_num_outputs` fetches the number post-RingCT output.
     ///
-    /// Return the number of post-RingCT outputs. In case of failures a `DB_FAILURES` will be return.
+    /// Return the number of post-RingCT outputs, which is also the global index of the next RingCT output. In case of failures a `DB_FAILURES` will be return.
     ///
     /// No parameters is required
     fn get_rct_num_outputs(&'service self) -> Result<u64, DB_FAILURES> {
         let ro_tx = self.db.tx().map_err(Into::into)?;
-
-        ro_tx
-            .num_entries::<table::outputmetadata>()
-            .map(|n| n as u64)
+        rct_num_outputs(&ro_tx)
     }
 
     /// `get_pre_rct_num_outputs` fetches the number of preRCT outputs of a given amount.
@@ -929,15 +710,24 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
     /// `amount`: is the output amount being looked up.
     fn get_pre_rct_num_outputs(&'service self, amount: u64) -> Result<u64, DB_FAILURES> {
         let ro_tx = self.db.tx().map_err(Into::into)?;
-        let mut cursor = ro_tx.cursor_dup::<table::prerctoutputmetadata>()?;
+        pre_rct_num_outputs(&ro_tx, amount)
+    }
 
-        transaction::Cursor::set(&mut cursor, &amount)?;
-        let out_metadata: Option<(u64, OutputMetadata)> =
-            transaction::DupCursor::last_dup(&mut cursor)?;
-        if let Some(out_metadata) = out_metadata {
-            return Ok(out_metadata.0);
-        }
-        Err(DB_FAILURES::Other("failed to decode the subkey and value"))
+    /// `add_block_write_batch` add every output, key image and transaction index of a verified block in one go.
+    ///
+    /// Each collection is sorted by its table's key and written with a single cursor, so the cursor never has to search for
+    /// its position from the root of the B-tree. RingCT outputs have sequential global indexes and are appended to the end of
+    /// [`table::rctoutputs`].
+    ///
+    /// Return the amount index of each output, in the order of `batch.outputs`. In case of failures, a DB_FAILURES will be return.
+    ///
+    /// Parameters:
+    /// `batch`: is the block's outputs, key images and transaction indexes.
+    fn add_block_write_batch(
+        &'service self,
+        batch: BlockWriteBatch,
+    ) -> Result<Vec<u64>, DB_FAILURES> {
+        write_block_batch(&**self, batch)
     }
 
     // ------------------------------| Spent Keys |------------------------------
@@ -1034,3 +824,270 @@ impl<'service, D: Database<'service>> Interface<'service, D> {
             .ok_or(DB_FAILURES::NotFound("Can't find prunning seed"))
     }
 }
+
+/// `write_block_batch` is the implementation of [`Interface::add_block_write_batch`], it writes the batch with the given
+/// write transaction so it can be part of a bigger write, like adding a whole block.
+pub(crate) fn write_block_batch<'a, T: WriteTransaction<'a>>(
+    rw_tx: &T,
+    batch: BlockWriteBatch,
+) -> Result<Vec<u64>, DB_FAILURES> {
+    let BlockWriteBatch {
+        outputs,
+        mut key_images,
+        mut tx_indices,
+    } = batch;
+
+    let mut amount_indices = Vec::with_capacity(outputs.len());
+    let mut pre_rct_outputs = Vec::new();
+
+    // RingCT Outputs, appended in order of creation.
+    let mut next_rct_index = rct_num_outputs(rw_tx)?;
+    let mut cursor_rctoutputs = rw_tx.write_cursor::<table::rctoutputs>()?;
+    // The next amount index of each pre-RingCT amount in this block.
+    let mut next_pre_rct_indices: HashMap<u64, u64> = HashMap::new();
+
+    for (amount, out_metadata) in outputs {
+        match out_metadata {
+            OutputMetadata {
+                tx_hash,
+                local_index,
+                pubkey: Some(pubkey),
+                unlock_time,
+                height,
+                commitment: Some(commitment),
+            } => {
+                let rct_output = RctOutput {
+                    tx_hash,
+                    local_index,
+                    pubkey,
+                    unlock_time,
+                    height,
+                    commitment,
+                };
+                cursor_rctoutputs.append_cursor(&next_rct_index, &rct_output)?;
+                amount_indices.push(next_rct_index);
+                next_rct_index += 1;
+            }
+            OutputMetadata {
+                commitment: Some(_),
+                ..
+            } => {
+                return Err(DB_FAILURES::Other(
+                    "RingCT outputs must have a one time key",
+                ))
+            }
+            out_metadata => {
+                let next_index = match next_pre_rct_indices.entry(amount) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(pre_rct_num_outputs(rw_tx, amount)?),
+                };
+                amount_indices.push(*next_index);
+                pre_rct_outputs.push((amount, *next_index, out_metadata));
+                *next_index += 1;
+            }
+        }
+    }
+
+    // Pre-RingCT Outputs, sorted by amount then amount index.
+    pre_rct_outputs.sort_unstable_by_key(|(amount, amount_index, _)| (*amount, *amount_index));
+    let mut cursor = rw_tx.write_cursor_dup::<table::prerctoutputmetadata>()?;
+    for (amount, amount_index, out_metadata) in pre_rct_outputs {
+        cursor.put_cursor_dup(&amount, &amount_index, &out_metadata)?;
+    }
+
+    // Key images, sorted.
+    key_images.sort_unstable_by(|a, b| a.image.as_bytes().cmp(b.image.as_bytes()));
+    let mut cursor_spentkeys = rw_tx.write_cursor_dup::<table::spentkeys>()?;
+    for key_image in key_images {
+        cursor_spentkeys.put_cursor_dup(&(), &key_image.into(), &())?;
+    }
+
+    // Transaction indexes, sorted by hash.
+    tx_indices.sort_unstable_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+    let mut cursor_txsidentifier = rw_tx.write_cursor_dup::<table::txsidentifier>()?;
+    for (tx_hash, tx_index) in tx_indices {
+        cursor_txsidentifier.put_cursor_dup(&tx_hash.into(), &(), &tx_index)?;
+    }
+
+    Ok(amount_indices)
+}
+
+/// `pre_rct_num_outputs` fetch the number of Pre-RingCT outputs of the given amount, which is also the amount index of the
+/// next one. Amount indexes start at 0, like monerod's.
+///
+/// The count is taken from the amount index of the last output, not from the number of entries, so as long as outputs are
+/// only removed from the top (see [`remove_last_output`]) popping blocks releases their indexes and re-adding the same
+/// blocks gives their outputs the same indexes.
+pub(crate) fn pre_rct_num_outputs<'a, T: Transaction<'a>>(
+    tx: &T,
+    amount: u64,
+) -> Result<u64, DB_FAILURES> {
+    let mut cursor = tx.cursor_dup::<table::prerctoutputmetadata>()?;
+
+    // No outputs with this amount yet.
+    if transaction::DupCursor::set_dup(&mut cursor, &amount)?.is_none() {
+        return Ok(0);
+    }
+    let out_metadata: Option<(u64, OutputMetadata)> =
+        transaction::DupCursor::last_dup(&mut cursor)?;
+    if let Some(out_metadata) = out_metadata {
+        return Ok(out_metadata.0 + 1);
+    }
+    Err(DB_FAILURES::Other("failed to decode the subkey and value"))
+}
+
+/// `rct_num_outputs` fetch the number of RingCT outputs, which is also the global index of the next one.
+///
+/// Like [`pre_rct_num_outputs`] this is taken from the index of the last output.
+pub(crate) fn rct_num_outputs<'a, T: Transaction<'a>>(tx: &T) -> Result<u64, DB_FAILURES> {
+    let mut cursor = tx.cursor::<table::rctoutputs>()?;
+    let last: Option<(u64, RctOutput)> = transaction::Cursor::last(&mut cursor)?;
+    Ok(last.map_or(0, |(index, _)| index + 1))
+}
+
+/// `remove_last_output` remove an output, which must be the newest output of its amount (or the newest RingCT output).
+///
+/// Outputs are only ever removed from the top of the chain, newest first, this is checked so a removal can never leave a gap
+/// that would change the indexes given to the next outputs.
+///
+/// Parameters:
+/// `amount`: is the output's amount, ignored for RingCT outputs.
+/// `is_rct`: is whether the output is a RingCT output.
+/// `amount_index`: is the output's amount index, its global index for RingCT outputs.
+pub(crate) fn remove_last_output<'a, T: WriteTransaction<'a>>(
+    rw_tx: &T,
+    amount: u64,
+    is_rct: bool,
+    amount_index: u64,
+) -> Result<(), DB_FAILURES> {
+    let num_outputs = if is_rct {
+        rct_num_outputs(rw_tx)?
+    } else {
+        pre_rct_num_outputs(rw_tx, amount)?
+    };
+    if num_outputs == 0 {
+        return Err(DB_FAILURES::NotFound("Failed to find output to remove"));
+    }
+    if amount_index != num_outputs - 1 {
+        return Err(DB_FAILURES::Other(
+            "Attempting to remove an output that isn't the newest of its amount",
+        ));
+    }
+
+    if is_rct {
+        let mut cursor_rctoutputs = rw_tx.write_cursor::<table::rctoutputs>()?;
+        transaction::Cursor::set(&mut cursor_rctoutputs, &amount_index)?.ok_or(
+            DB_FAILURES::NotFound("Failed to find PostRCT output metadata"),
+        )?;
+        WriteCursor::del(&mut cursor_rctoutputs)
+    } else {
+        let mut cursor = rw_tx.write_cursor_dup::<table::prerctoutputmetadata>()?;
+        cursor
+            .get_dup(&amount, &amount_index)?
+            .ok_or(DB_FAILURES::NotFound(
+                "Failed to find PreRCT output metadata",
+            ))?;
+        WriteCursor::del(&mut cursor)
+    }
+}
+
+#[cfg(all(test, feature = "mdbx"))]
+mod tests {
+    use monero::{util::ringct::Key, Hash, PublicKey};
+
+    use super::{remove_last_output, write_block_batch};
+    use crate::{
+        database::Database,
+        transaction::WriteTransaction,
+        types::{BlockWriteBatch, OutputMetadata},
+    };
+
+    fn open_database(name: &str) -> libmdbx::Database<libmdbx::NoWriteMap> {
+        let path = std::env::temp_dir().join(format!(
+            "cuprate-database-test-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+
+        let db = <libmdbx::Database<libmdbx::NoWriteMap> as Database>::open(path).unwrap();
+        db.build().unwrap();
+        db
+    }
+
+    /// An output with this amount, RingCT outputs have a commitment.
+    fn output(amount: u64, is_rct: bool, height: u64) -> (u64, OutputMetadata) {
+        let mut generator = [0x66; 32];
+        generator[0] = 0x58;
+
+        (
+            amount,
+            OutputMetadata {
+                tx_hash: Hash::zero().into(),
+                local_index: 0,
+                pubkey: Some(PublicKey::from_slice(&generator).unwrap().into()),
+                unlock_time: 0,
+                height,
+                commitment: is_rct.then(|| Key { key: [1; 32] }.into()),
+            },
+        )
+    }
+
+    fn block_batch(height: u64) -> BlockWriteBatch {
+        BlockWriteBatch {
+            outputs: vec![
+                output(1_000, true, height),
+                output(5, false, height),
+                output(0, true, height),
+                output(5, false, height),
+                output(7, false, height),
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// Removes the outputs of a block like a pop does, newest first.
+    fn pop_outputs<'a, T: WriteTransaction<'a>>(
+        rw_tx: &T,
+        batch: &BlockWriteBatch,
+        amount_indices: &[u64],
+    ) {
+        for ((amount, out_metadata), amount_index) in batch.outputs.iter().zip(amount_indices).rev()
+        {
+            remove_last_output(
+                rw_tx,
+                *amount,
+                out_metadata.commitment.is_some(),
+                *amount_index,
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn output_indices_survive_pop_and_re_add() {
+        let db = open_database("output-indices");
+        let rw_tx = db.tx_mut().unwrap();
+
+        // Indexes start at 0, RingCT outputs share one index and the others are indexed by amount.
+        let first = write_block_batch(&rw_tx, block_batch(0)).unwrap();
+        assert_eq!(first, vec![0, 0, 1, 1, 0]);
+        let second = write_block_batch(&rw_tx, block_batch(1)).unwrap();
+        assert_eq!(second, vec![2, 2, 3, 3, 1]);
+
+        for _ in 0..3 {
+            pop_outputs(&rw_tx, &block_batch(1), &second);
+            assert_eq!(write_block_batch(&rw_tx, block_batch(1)).unwrap(), second);
+        }
+
+        // Removing an output that isn't the newest of its amount would leave a gap, so is refused.
+        assert!(remove_last_output(&rw_tx, 5, false, 0).is_err());
+        assert!(remove_last_output(&rw_tx, 0, true, 0).is_err());
+
+        // Popping both blocks and re-adding them gives the same indexes.
+        pop_outputs(&rw_tx, &block_batch(1), &second);
+        pop_outputs(&rw_tx, &block_batch(0), &first);
+        assert_eq!(write_block_batch(&rw_tx, block_batch(0)).unwrap(), first);
+        assert_eq!(write_block_batch(&rw_tx, block_batch(1)).unwrap(), second);
+    }
+}
@@ -20,6 +20,8 @@
 //! At the moment, the only storage engine available is MDBX.
 //! The next storage engine planned is HSE (Heteregeonous Storage Engine) from Micron.
 //!
+//! The consensus crate reads the database through [`service::DatabaseService`], which answers its `DatabaseRequest`s.
+//!
 //! For more informations, please consult this docs:
 
 #![deny(unused_attributes)]
@@ -28,10 +30,8 @@
 #![deny(clippy::expect_used, clippy::panic)]
 #![allow(dead_code, unused_macros)] // temporary
 
-use monero::{util::ringct::RctSig, Block, BlockHeader, Hash};
-use std::ops::Range;
-use thiserror::Error;
-
+#[cfg(feature = "mdbx")]
+pub mod compaction;
 #[cfg(feature = "mdbx")]
 pub mod mdbx;
 //#[cfg(feature = "hse")]
@@ -40,15 +40,19 @@ pub mod mdbx;
 pub mod encoding;
 pub mod error;
 pub mod interface;
+pub mod service;
 pub mod table;
 pub mod types;
 
 const DEFAULT_BLOCKCHAIN_DATABASE_DIRECTORY: &str = "blockchain";
 const DEFAULT_TXPOOL_DATABASE_DIRECTORY: &str = "txpool_mem";
+/// Integers are big endian so the byte order MDBX sorts keys and duplicates by is their numeric order.
 const BINCODE_CONFIG: bincode::config::Configuration<
-    bincode::config::LittleEndian,
+    bincode::config::BigEndian,
     bincode::config::Fixint,
-> = bincode::config::standard().with_fixed_int_encoding();
+> = bincode::config::standard()
+    .with_big_endian()
+    .with_fixed_int_encoding();
 
 // ------------------------------------------|      Database      |------------------------------------------
 
@@ -111,7 +115,7 @@ pub mod database {
         type Target = <D as Database<'service>>::TXMut;
 
         fn deref(&self) -> &Self::Target {
-            return self.tx.as_ref().unwrap();
+            self.tx.as_ref().unwrap()
         }
     }
 }
@@ -150,6 +154,9 @@ pub mod transaction {
     pub trait DupCursor<'t, T: DupTable>: Cursor<'t, T> {
         fn first_dup(&mut self) -> Result<Option<(T::SubKey, T::Value)>, DB_FAILURES>;
 
+        // Like `Cursor::set` but decodes the subkey stored in front of the value.
+        fn set_dup(&mut self, key: &T::Key) -> Result<Option<(T::SubKey, T::Value)>, DB_FAILURES>;
+
         fn get_dup(
             &mut self,
             key: &T::Key,
@@ -167,6 +174,10 @@ pub mod transaction {
     pub trait WriteCursor<'t, T: Table>: Cursor<'t, T> {
         fn put_cursor(&mut self, key: &T::Key, value: &T::Value) -> Result<(), DB_FAILURES>;
 
+        /// Add a record to the end of the table. `key` must be greater than every key in the table, this skips the
+        /// search for the record's position and only ever writes to the last page.
+        fn append_cursor(&mut self, key: &T::Key, value: &T::Value) -> Result<(), DB_FAILURES>;
+
         fn del(&mut self) -> Result<(), DB_FAILURES>;
     }
 
@@ -44,7 +44,7 @@ fn mdbx_encode<T: bincode::Encode>(src: &T) -> Result<Vec<u8>, DB_FAILURES> {
 /// [`mdbx_open_table`] is a simple function used for syntax clarity. It try to open the table, and return a `DB_FAILURES` if it failed.
 fn mdbx_open_table<'db, K: TransactionKind, E: DatabaseKind, T: Table>(
     tx: &'db libmdbx::Transaction<'db, K, E>,
-) -> Result<libmdbx::Table, DB_FAILURES> {
+) -> Result<libmdbx::Table<'db>, DB_FAILURES> {
     tx.open_table(Some(T::TABLE_NAME))
         .map_err(std::convert::Into::<DB_FAILURES>::into)
 }
@@ -114,7 +114,7 @@ where
         ro_tx.open_table(Some(table::txsidentifier::TABLE_NAME))?;
         // ---- OUTPUTS -----
         ro_tx.open_table(Some(table::prerctoutputmetadata::TABLE_NAME))?;
-        ro_tx.open_table(Some(table::outputmetadata::TABLE_NAME))?;
+        ro_tx.open_table(Some(table::rctoutputs::TABLE_NAME))?;
         // ---- SPT KEYS ----
         ro_tx.open_table(Some(table::spentkeys::TABLE_NAME))?;
         // --- PROPERTIES ---
@@ -127,56 +127,31 @@ where
     fn build(&'a self) -> Result<(), Self::Error> {
         let rw_tx = self.begin_rw_txn()?;
 
-        // Constructing the tables
+        // Constructing the tables. Keys are compared bytewise, see `BINCODE_CONFIG`, and `DUP_FIXED` is only set on the
+        // tables with fixed size duplicates.
+        let dup_fixed = TableFlags::DUP_SORT | TableFlags::DUP_FIXED;
         // ----- BLOCKS -----
-        rw_tx.create_table(
-            Some(table::blockhash::TABLE_NAME),
-            TableFlags::INTEGER_KEY | TableFlags::DUP_FIXED | TableFlags::DUP_SORT,
-        )?;
-        rw_tx.create_table(
-            Some(table::blockmetadata::TABLE_NAME),
-            TableFlags::INTEGER_KEY | TableFlags::DUP_FIXED | TableFlags::DUP_SORT,
-        )?;
-        rw_tx.create_table(Some(table::blocks::TABLE_NAME), TableFlags::INTEGER_KEY)?;
-        rw_tx.create_table(Some(table::altblock::TABLE_NAME), TableFlags::INTEGER_KEY)?;
+        rw_tx.create_table(Some(table::blockhash::TABLE_NAME), dup_fixed)?;
+        rw_tx.create_table(Some(table::blockmetadata::TABLE_NAME), dup_fixed)?;
+        rw_tx.create_table(Some(table::blocks::TABLE_NAME), TableFlags::empty())?;
+        rw_tx.create_table(Some(table::altblock::TABLE_NAME), TableFlags::empty())?;
         // ------ TXNs ------
-        rw_tx.create_table(Some(table::txspruned::TABLE_NAME), TableFlags::INTEGER_KEY)?;
-        rw_tx.create_table(
-            Some(table::txsprunable::TABLE_NAME),
-            TableFlags::INTEGER_KEY,
-        )?;
-        rw_tx.create_table(
-            Some(table::txsprunablehash::TABLE_NAME),
-            TableFlags::INTEGER_KEY | TableFlags::DUP_FIXED | TableFlags::DUP_SORT,
-        )?;
-        rw_tx.create_table(
-            Some(table::txsprunabletip::TABLE_NAME),
-            TableFlags::INTEGER_KEY,
-        )?;
-        rw_tx.create_table(
-            Some(table::txsoutputs::TABLE_NAME),
-            TableFlags::INTEGER_KEY | TableFlags::DUP_FIXED | TableFlags::DUP_SORT,
-        )?;
-        rw_tx.create_table(
-            Some(table::txsidentifier::TABLE_NAME),
-            TableFlags::INTEGER_KEY | TableFlags::DUP_FIXED | TableFlags::DUP_SORT,
-        )?;
+        rw_tx.create_table(Some(table::txspruned::TABLE_NAME), TableFlags::empty())?;
+        rw_tx.create_table(Some(table::txsprunable::TABLE_NAME), TableFlags::empty())?;
+        rw_tx.create_table(Some(table::txsprunablehash::TABLE_NAME), dup_fixed)?;
+        rw_tx.create_table(Some(table::txsprunabletip::TABLE_NAME), TableFlags::empty())?;
+        rw_tx.create_table(Some(table::txsoutputs::TABLE_NAME), TableFlags::DUP_SORT)?;
+        rw_tx.create_table(Some(table::txsidentifier::TABLE_NAME), dup_fixed)?;
         // ---- OUTPUTS -----
         rw_tx.create_table(
             Some(table::prerctoutputmetadata::TABLE_NAME),
-            TableFlags::INTEGER_KEY | TableFlags::DUP_FIXED | TableFlags::DUP_SORT,
-        )?;
-        rw_tx.create_table(
-            Some(table::outputmetadata::TABLE_NAME),
-            TableFlags::INTEGER_KEY | TableFlags::DUP_FIXED | TableFlags::DUP_SORT,
+            TableFlags::DUP_SORT,
         )?;
+        rw_tx.create_table(Some(table::rctoutputs::TABLE_NAME), TableFlags::empty())?;
         // ---- SPT KEYS ----
-        rw_tx.create_table(
-            Some(table::spentkeys::TABLE_NAME),
-            TableFlags::INTEGER_KEY | TableFlags::DUP_FIXED | TableFlags::DUP_SORT,
-        )?;
+        rw_tx.create_table(Some(table::spentkeys::TABLE_NAME), dup_fixed)?;
         // --- PROPERTIES ---
-        rw_tx.create_table(Some(table::properties::TABLE_NAME), TableFlags::INTEGER_KEY)?;
+        rw_tx.create_table(Some(table::properties::TABLE_NAME), TableFlags::empty())?;
 
         rw_tx.commit()?;
         Ok(())
@@ -262,6 +237,19 @@ where
         Ok(None)
     }
 
+    fn set_dup(&mut self, key: &T::Key) -> Result<Option<(T::SubKey, T::Value)>, DB_FAILURES> {
+        let encoded_key = mdbx_encode(key)?;
+
+        let value = self
+            .set::<Vec<u8>>(&encoded_key)
+            .map_err(std::convert::Into::<DB_FAILURES>::into)?;
+
+        if let Some(value) = value {
+            return Ok(Some(mdbx_decode(value.as_slice())?.0));
+        }
+        Ok(None)
+    }
+
     fn get_dup(
         &mut self,
         key: &T::Key,
@@ -269,14 +257,17 @@ where
     ) -> Result<Option<<T>::Value>, DB_FAILURES> {
         let (encoded_key, encoded_subkey) = (mdbx_encode(key)?, mdbx_encode(subkey)?);
 
+        // The duplicates are the subkey followed by the value, so look for the first duplicate starting with the subkey.
         let value = self
-            .get_both::<Vec<u8>>(&encoded_key, &encoded_subkey)
+            .get_both_range::<Vec<u8>>(&encoded_key, &encoded_subkey)
             .map_err(std::convert::Into::<DB_FAILURES>::into)?;
 
-        if let Some(value) = value {
-            return Ok(Some(mdbx_decode(value.as_slice())?.0));
+        match value {
+            Some(value) if value.starts_with(&encoded_subkey) => {
+                Ok(Some(mdbx_decode(&value[encoded_subkey.len()..])?.0))
+            }
+            _ => Ok(None),
         }
-        Ok(None)
     }
 
     fn last_dup(&mut self) -> Result<Option<(T::SubKey, T::Value)>, DB_FAILURES> {
@@ -333,6 +324,13 @@ where
             .map_err(Into::into)
     }
 
+    fn append_cursor(&mut self, key: &T::Key, value: &T::Value) -> Result<(), DB_FAILURES> {
+        let (encoded_key, encoded_value) = (mdbx_encode(key)?, mdbx_encode(value)?);
+
+        self.put(&encoded_key, &encoded_value, WriteFlags::APPEND)
+            .map_err(Into::into)
+    }
+
     fn del(&mut self) -> Result<(), DB_FAILURES> {
         self.del(WriteFlags::empty()).map_err(Into::into)
     }
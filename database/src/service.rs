@@ -0,0 +1,1755 @@
+//! ### Service module
+//! This module contains [`DatabaseService`], the adapter between a [`Database`] and the consensus crate. It implements
+//! [`tower::Service`] for every [`DatabaseRequest`] variant, so the verifier, the tx pool and the RPC server can run on
+//! a real storage engine.
+//!
+//! Each read request is answered from a single read-only transaction, so a response is consistent even if blocks are being
+//! added at the same time. Blocks and transactions are stored as `monero-rs` types, they are converted to `monero-serai`
+//! types by re-parsing their blobs.
+//!
+//! [`DatabaseRequest::WriteBlock`] and [`DatabaseRequest::PopBlock`] are done in a single write transaction which is only
+//! committed once every table has been updated, so a failed write or pop leaves the database as it was. The blocks of a
+//! [`DatabaseRequest::WriteBlocks`] share one write transaction, if one of them fails none of them are written.
+//!
+//! Databases with a pruning seed keep the prunable data of the last [`CRYPTONOTE_PRUNING_TIP_BLOCKS`] blocks and of the
+//! blocks in their stripe. The prunable data of a block is removed when it leaves the tip, and blocks below the tip can be
+//! written pruned. The seed is set with [`DatabaseService::set_pruning_seed`] before the first block is written.
+
+use std::{
+    future::Future,
+    iter::once,
+    ops::Range,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use curve25519_dalek::scalar::Scalar;
+use futures::FutureExt;
+use monero::{
+    blockdata::transaction::KeyImage,
+    cryptonote::hash::Hashable,
+    util::ringct::{Key, RctSig},
+    Block, Hash, TxIn,
+};
+use monero_serai::transaction::Timelock;
+
+use cuprate_common::{BlockID, PruningSeed, CRYPTONOTE_PRUNING_TIP_BLOCKS};
+use monero_consensus::{
+    block::{pow::BlockPOWInfo, weight::BlockWeightInfo},
+    block::{VerifiedBlockInformation, VerifiedBlockTxs},
+    hardforks::BlockHFInfo,
+    outputs::{BlockBlobs, OutputOnChain, OutputTimeLock, TxBlob},
+    DatabaseRequest, DatabaseResponse,
+};
+
+use crate::{
+    database::Database,
+    error::{DB_FAILURES, DB_SERIAL},
+    interface::{pre_rct_num_outputs, rct_num_outputs, remove_last_output, write_block_batch},
+    table,
+    transaction::{DupCursor, DupWriteCursor, Transaction, WriteCursor, WriteTransaction},
+    types::{
+        calculate_prunable_hash, get_transaction_prunable_blob, BlockMetadata, BlockWriteBatch,
+        OutputMetadata, TransactionPruned, TxIndex, TxOutputIdx,
+    },
+    BINCODE_CONFIG,
+};
+
+/// Unlock times below this are block heights, the others are UNIX timestamps. Same as monerod's `CRYPTONOTE_MAX_BLOCK_NUMBER`.
+const MAX_BLOCK_NUMBER: u64 = 500_000_000;
+
+/// [`DatabaseService`] answers the consensus crate's [`DatabaseRequest`]s from a [`Database`]. Clones of the service share the same database.
+///
+/// The storage engine's reads, writes and commits block the calling thread, so every request is answered on tokio's blocking
+/// thread pool with [`tokio::task::spawn_blocking`], the service must be called from inside a tokio runtime. Reads don't wait
+/// on writers, writes wait for any other write to finish.
+pub struct DatabaseService<D> {
+    db: Arc<D>,
+}
+
+impl<D> Clone for DatabaseService<D> {
+    fn clone(&self) -> Self {
+        DatabaseService {
+            db: self.db.clone(),
+        }
+    }
+}
+
+impl<D> DatabaseService<D> {
+    pub fn new(db: Arc<D>) -> Self {
+        DatabaseService { db }
+    }
+}
+
+impl<D: for<'a> Database<'a>> DatabaseService<D> {
+    /// `pruning_seed` fetch the database's [`PruningSeed`], [`PruningSeed::NOT_PRUNED`] if it keeps the prunable data of
+    /// every block. Unlike requests this blocks the calling thread, it is meant to be called before the node is started.
+    pub fn pruning_seed(&self) -> Result<PruningSeed, DB_FAILURES> {
+        let ro_tx = self.db.tx().map_err(Into::into)?;
+        pruning_seed(&ro_tx)
+    }
+
+    /// `set_pruning_seed` set the database's [`PruningSeed`], like monerod's `--prune-blockchain`. Blocks written after this
+    /// only keep their prunable data while they are in the tip or in the seed's stripe.
+    ///
+    /// The blocks already written would keep all their prunable data, so the seed can only be changed on a database without
+    /// blocks. Setting the seed the database already has does nothing.
+    pub fn set_pruning_seed(&self, seed: PruningSeed) -> Result<(), DB_FAILURES> {
+        let rw_tx = self.db.tx_mut().map_err(Into::into)?;
+        if pruning_seed(&rw_tx)? == seed {
+            return Ok(());
+        }
+        if rw_tx.num_entries::<table::blockhash>()? != 0 {
+            return Err(DB_FAILURES::Other(
+                "The pruning seed of a database with blocks can't be changed",
+            ));
+        }
+
+        rw_tx.put::<table::properties>(&0, &u32::from(seed))?;
+        rw_tx.commit()
+    }
+}
+
+impl<D> tower::Service<DatabaseRequest> for DatabaseService<D>
+where
+    D: for<'a> Database<'a> + Send + Sync + 'static,
+{
+    type Response = DatabaseResponse;
+    type Error = tower::BoxError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<DatabaseResponse, tower::BoxError>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: DatabaseRequest) -> Self::Future {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || answer(db.as_ref(), req))
+            .map(|res| res?)
+            .boxed()
+    }
+}
+
+/// `answer` open a read-only transaction and answer the request with it, writes get their own write transaction.
+fn answer<D: for<'a> Database<'a>>(
+    db: &D,
+    req: DatabaseRequest,
+) -> Result<DatabaseResponse, tower::BoxError> {
+    match req {
+        DatabaseRequest::WriteBlock(block) => {
+            let rw_tx = db.tx_mut().map_err(Into::<DB_FAILURES>::into)?;
+            write_block(&rw_tx, *block)?;
+            rw_tx.commit()?;
+            return Ok(DatabaseResponse::WriteBlock);
+        }
+        DatabaseRequest::WriteBlocks(blocks) => {
+            let rw_tx = db.tx_mut().map_err(Into::<DB_FAILURES>::into)?;
+            for block in blocks {
+                write_block(&rw_tx, block)?;
+            }
+            rw_tx.commit()?;
+            return Ok(DatabaseResponse::WriteBlock);
+        }
+        DatabaseRequest::PopBlock => {
+            let rw_tx = db.tx_mut().map_err(Into::<DB_FAILURES>::into)?;
+            let (block, txs) = pop_block(&rw_tx)?;
+            rw_tx.commit()?;
+            return Ok(DatabaseResponse::PopBlock(Box::new(block), txs));
+        }
+        _ => (),
+    }
+
+    let ro_tx = db.tx().map_err(Into::<DB_FAILURES>::into)?;
+
+    Ok(match req {
+        DatabaseRequest::BlockHFInfo(id) => {
+            let height = block_id_height(&ro_tx, id)?;
+            DatabaseResponse::BlockHFInfo(block_hf_info(&ro_tx, height)?)
+        }
+        DatabaseRequest::BlockPOWInfo(id) => {
+            let height = block_id_height(&ro_tx, id)?;
+            DatabaseResponse::BlockPOWInfo(block_pow_info(&block_metadata(&ro_tx, height)?))
+        }
+        DatabaseRequest::BlockWeights(id) => {
+            let height = block_id_height(&ro_tx, id)?;
+            DatabaseResponse::BlockWeights(block_weights(&block_metadata(&ro_tx, height)?))
+        }
+        DatabaseRequest::BlockHash(height) => {
+            DatabaseResponse::BlockHash(block_metadata(&ro_tx, height)?.block_hash.0 .0)
+        }
+        DatabaseRequest::GeneratedCoins(height) => {
+            DatabaseResponse::GeneratedCoins(block_metadata(&ro_tx, height)?.total_coins_generated)
+        }
+        DatabaseRequest::CumulativeDifficulty(height) => DatabaseResponse::CumulativeDifficulty(
+            block_metadata(&ro_tx, height)?.cumulative_difficulty,
+        ),
+
+        DatabaseRequest::BlockHfInfoInRange(range) => DatabaseResponse::BlockHfInfoInRange(
+            range
+                .map(|height| block_hf_info(&ro_tx, height))
+                .collect::<Result<_, _>>()?,
+        ),
+        DatabaseRequest::BlockWeightsInRange(range) => DatabaseResponse::BlockWeightsInRange(
+            range
+                .map(|height| block_metadata(&ro_tx, height).map(|m| block_weights(&m)))
+                .collect::<Result<_, _>>()?,
+        ),
+        DatabaseRequest::BlockPOWInfoInRange(range) => DatabaseResponse::BlockPOWInfoInRange(
+            range
+                .map(|height| block_metadata(&ro_tx, height).map(|m| block_pow_info(&m)))
+                .collect::<Result<_, _>>()?,
+        ),
+
+        DatabaseRequest::ChainHeight => {
+            DatabaseResponse::ChainHeight(ro_tx.num_entries::<table::blockhash>()? as u64)
+        }
+
+        DatabaseRequest::KeyImagesSpent(key_images) => {
+            let mut cursor_spentkeys = ro_tx.cursor_dup::<table::spentkeys>()?;
+            let mut spent = false;
+            for key_image in key_images {
+                let key_image = KeyImage {
+                    image: Hash(key_image),
+                };
+                if cursor_spentkeys.get_dup(&(), &key_image.into())?.is_some() {
+                    spent = true;
+                    break;
+                }
+            }
+            DatabaseResponse::KeyImagesSpent(spent)
+        }
+
+        DatabaseRequest::Block(id) => {
+            let height = block_id_height(&ro_tx, id)?;
+            DatabaseResponse::Block(Box::new(to_serai_block(&block(&ro_tx, height)?)?))
+        }
+        DatabaseRequest::BlockHeight(hash) => {
+            DatabaseResponse::BlockHeight(block_height(&ro_tx, Hash(hash))?)
+        }
+
+        DatabaseRequest::BlockBlobsInRange { range, pruned } => {
+            DatabaseResponse::BlockBlobsInRange(block_blobs_in_range(&ro_tx, range, pruned)?)
+        }
+        DatabaseRequest::TxOutputIndices(hash) => {
+            let txindex = tx_index(&ro_tx, Hash(hash))?;
+            DatabaseResponse::TxOutputIndices(tx_output_indices(&ro_tx, &txindex)?)
+        }
+        DatabaseRequest::Outputs(outputs) => DatabaseResponse::Outputs(
+            outputs
+                .into_iter()
+                .map(|(amount, index)| output(&ro_tx, amount, index))
+                .collect::<Result<_, _>>()?,
+        ),
+        DatabaseRequest::OutputTimeLocks(outputs) => DatabaseResponse::OutputTimeLocks(
+            outputs
+                .into_iter()
+                .map(|(amount, index)| output_time_lock(&ro_tx, amount, index))
+                .collect::<Result<_, _>>()?,
+        ),
+        DatabaseRequest::NumOutputsInRange { amount, range } => {
+            DatabaseResponse::NumOutputsInRange(num_outputs_in_range(&ro_tx, amount, range)?)
+        }
+        DatabaseRequest::OutputDistribution {
+            amount,
+            from_height,
+            to_height,
+        } => DatabaseResponse::OutputDistribution(output_distribution(
+            &ro_tx,
+            amount,
+            from_height,
+            to_height,
+        )?),
+        DatabaseRequest::NumberOutputsWithAmount(amounts) => {
+            DatabaseResponse::NumberOutputsWithAmount(
+                amounts
+                    .into_iter()
+                    .map(|amount| {
+                        let count = if amount == 0 {
+                            rct_num_outputs(&ro_tx)?
+                        } else {
+                            pre_rct_num_outputs(&ro_tx, amount)?
+                        };
+                        Ok((amount, count as usize))
+                    })
+                    .collect::<Result<_, DB_FAILURES>>()?,
+            )
+        }
+
+        DatabaseRequest::BlockBatchInRange(range) => DatabaseResponse::BlockBatchInRange(
+            range
+                .map(|height| to_serai_block(&block(&ro_tx, height)?))
+                .collect::<Result<_, _>>()?,
+        ),
+        DatabaseRequest::Transactions(hashes) => DatabaseResponse::Transactions(
+            hashes
+                .into_iter()
+                .map(|hash| {
+                    let blob = tx_blob(&ro_tx, &tx_index(&ro_tx, Hash(hash))?)?;
+                    let tx = monero_serai::transaction::Transaction::read(&mut blob.as_slice());
+                    tx.map_err(|_| DB_FAILURES::SerializeIssue(DB_SERIAL::ConsensusDecode(blob)))
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+
+        DatabaseRequest::WriteBlock(_)
+        | DatabaseRequest::WriteBlocks(_)
+        | DatabaseRequest::PopBlock => {
+            unreachable!("Writes are answered with a write transaction")
+        }
+    })
+}
+
+// ----------------------------------| Blocks |---------------------------------
+
+/// `block_height` fetch the height of the block with the given hash, `None` if it isn't in the main chain.
+fn block_height<'a, T: Transaction<'a>>(ro_tx: &T, hash: Hash) -> Result<Option<u64>, DB_FAILURES> {
+    let mut cursor_blockhash = ro_tx.cursor_dup::<table::blockhash>()?;
+    cursor_blockhash.get_dup(&(), &hash.into())
+}
+
+/// `block_id_height` fetch the height of the block with the given [`BlockID`].
+fn block_id_height<'a, T: Transaction<'a>>(ro_tx: &T, id: BlockID) -> Result<u64, DB_FAILURES> {
+    match id {
+        BlockID::Height(height) => Ok(height),
+        BlockID::Hash(hash) => block_height(ro_tx, Hash(hash))?
+            .ok_or(DB_FAILURES::NotFound("Failed to find block height")),
+    }
+}
+
+fn block<'a, T: Transaction<'a>>(ro_tx: &T, height: u64) -> Result<Block, DB_FAILURES> {
+    Ok(ro_tx
+        .get::<table::blocks>(&height)?
+        .ok_or(DB_FAILURES::NotFound("Can't find block"))?
+        .0)
+}
+
+fn block_metadata<'a, T: Transaction<'a>>(
+    ro_tx: &T,
+    height: u64,
+) -> Result<BlockMetadata, DB_FAILURES> {
+    let mut cursor_blockmetadata = ro_tx.cursor_dup::<table::blockmetadata>()?;
+    cursor_blockmetadata
+        .get_dup(&(), &height)?
+        .ok_or(DB_FAILURES::NotFound("Failed to find block's metadata"))
+}
+
+fn block_hf_info<'a, T: Transaction<'a>>(
+    ro_tx: &T,
+    height: u64,
+) -> Result<BlockHFInfo, tower::BoxError> {
+    let header = block(ro_tx, height)?.header;
+    Ok(BlockHFInfo::from_major_minor(
+        header.major_version.0 as u8,
+        header.minor_version.0 as u8,
+    )?)
+}
+
+fn block_pow_info(metadata: &BlockMetadata) -> BlockPOWInfo {
+    BlockPOWInfo {
+        timestamp: metadata.timestamp,
+        cumulative_difficulty: metadata.cumulative_difficulty,
+    }
+}
+
+fn block_weights(metadata: &BlockMetadata) -> BlockWeightInfo {
+    BlockWeightInfo {
+        block_weight: metadata.weight as usize,
+        long_term_weight: metadata.long_term_block_weight as usize,
+    }
+}
+
+/// `to_serai_block` convert a block to the `monero-serai` type used by the consensus crate.
+fn to_serai_block(block: &Block) -> Result<monero_serai::block::Block, DB_FAILURES> {
+    let blob = monero::consensus::serialize(block);
+    let block = monero_serai::block::Block::read(&mut blob.as_slice());
+    block.map_err(|_| DB_FAILURES::SerializeIssue(DB_SERIAL::ConsensusDecode(blob)))
+}
+
+/// `block_blobs_in_range` fetch the blobs of the blocks in the range, with their transactions and output indices.
+fn block_blobs_in_range<'a, T: Transaction<'a>>(
+    ro_tx: &T,
+    range: Range<u64>,
+    pruned: bool,
+) -> Result<Vec<BlockBlobs>, DB_FAILURES> {
+    range
+        .map(|height| {
+            let block = block(ro_tx, height)?;
+            let metadata = block_metadata(ro_tx, height)?;
+
+            let mut output_indices = Vec::with_capacity(block.tx_hashes.len() + 1);
+            output_indices.push(tx_output_indices(
+                ro_tx,
+                &tx_index(ro_tx, block.miner_tx.hash())?,
+            )?);
+
+            let mut txs = Vec::with_capacity(block.tx_hashes.len());
+            for tx_hash in &block.tx_hashes {
+                let txindex = tx_index(ro_tx, *tx_hash)?;
+                output_indices.push(tx_output_indices(ro_tx, &txindex)?);
+
+                txs.push(if pruned {
+                    TxBlob {
+                        blob: pruned_tx_blob(ro_tx, &txindex)?.into(),
+                        prunable_hash: ro_tx
+                            .get::<table::txsprunablehash>(&txindex.tx_id)?
+                            .map(|prunable_hash| prunable_hash.0 .0),
+                    }
+                } else {
+                    TxBlob {
+                        blob: tx_blob(ro_tx, &txindex)?.into(),
+                        prunable_hash: None,
+                    }
+                });
+            }
+
+            Ok(BlockBlobs {
+                block: monero::consensus::serialize(&block),
+                block_weight: metadata.weight as usize,
+                txs,
+                output_indices,
+            })
+        })
+        .collect()
+}
+
+// ------------------------------|  Transactions  |-----------------------------
+
+fn tx_index<'a, T: Transaction<'a>>(ro_tx: &T, hash: Hash) -> Result<TxIndex, DB_FAILURES> {
+    ro_tx
+        .get::<table::txsidentifier>(&hash.into())?
+        .ok_or(DB_FAILURES::NotFound("txindex not found"))
+}
+
+fn tx_output_indices<'a, T: Transaction<'a>>(
+    ro_tx: &T,
+    txindex: &TxIndex,
+) -> Result<Vec<u64>, DB_FAILURES> {
+    Ok(ro_tx
+        .get::<table::txsoutputs>(&txindex.tx_id)?
+        .ok_or(DB_FAILURES::NotFound("Failed to find tx's outputs indices"))?
+        .0)
+}
+
+/// `pruned_tx_blob` fetch the blob of a transaction without its prunable part.
+fn pruned_tx_blob<'a, T: Transaction<'a>>(
+    ro_tx: &T,
+    txindex: &TxIndex,
+) -> Result<Vec<u8>, DB_FAILURES> {
+    let pruned_tx = ro_tx
+        .get::<table::txspruned>(&txindex.tx_id)?
+        .ok_or(DB_FAILURES::NotFound(
+            "failed to find prefix of a transaction",
+        ))?;
+
+    bincode::encode_to_vec(&pruned_tx, BINCODE_CONFIG)
+        .map_err(|err| DB_FAILURES::SerializeIssue(err.into()))
+}
+
+/// `tx_blob` fetch the blob of a transaction. The pruned blob is followed by the prunable part, so the full blob is both
+/// joined together.
+fn tx_blob<'a, T: Transaction<'a>>(ro_tx: &T, txindex: &TxIndex) -> Result<Vec<u8>, DB_FAILURES> {
+    let mut blob = pruned_tx_blob(ro_tx, txindex)?;
+    blob.extend(
+        ro_tx
+            .get::<table::txsprunable>(&txindex.tx_id)?
+            .ok_or(DB_FAILURES::NotFound(
+                "failed to find prunable part of a transaction",
+            ))?,
+    );
+    Ok(blob)
+}
+
+// --------------------------------|  Outputs  |--------------------------------
+
+/// `output_metadata` fetch the metadata of the output with the given amount and amount index, RingCT outputs have an
+/// amount of 0.
+fn output_metadata<'a, T: Transaction<'a>>(
+    ro_tx: &T,
+    amount: u64,
+    index: u64,
+) -> Result<OutputMetadata, DB_FAILURES> {
+    if amount == 0 {
+        Ok(ro_tx
+            .get::<table::rctoutputs>(&index)?
+            .ok_or(DB_FAILURES::NotFound(
+                "Failed to find PostRCT output metadata",
+            ))?
+            .into())
+    } else {
+        let mut cursor = ro_tx.cursor_dup::<table::prerctoutputmetadata>()?;
+        cursor
+            .get_dup(&amount, &index)?
+            .ok_or(DB_FAILURES::NotFound(
+                "Failed to find PreRCT output metadata",
+            ))
+    }
+}
+
+/// `time_lock` converts a stored unlock time to a [`Timelock`].
+fn time_lock(unlock_time: u64) -> Timelock {
+    match unlock_time {
+        0 => Timelock::None,
+        height if height < MAX_BLOCK_NUMBER => Timelock::Block(height as usize),
+        time => Timelock::Time(time),
+    }
+}
+
+/// `output` fetch the output with the given amount and amount index, RingCT outputs have an amount of 0.
+fn output<'a, T: Transaction<'a>>(
+    ro_tx: &T,
+    amount: u64,
+    index: u64,
+) -> Result<OutputOnChain, DB_FAILURES> {
+    let out_metadata = output_metadata(ro_tx, amount, index)?;
+
+    Ok(OutputOnChain {
+        height: out_metadata.height,
+        time_lock: time_lock(out_metadata.unlock_time),
+        key: out_metadata
+            .pubkey
+            .ok_or(DB_FAILURES::Other("output doesn't have a one time key"))?
+            .to_bytes(),
+        mask: match out_metadata.commitment {
+            Some(commitment) => commitment.key,
+            // Pre-RingCT outputs are treated as a commitment to their amount with a mask of 1.
+            None => zero_commitment(amount).key,
+        },
+        txid: out_metadata.tx_hash.0 .0,
+    })
+}
+
+/// `output_time_lock` fetch the time-lock of the output with the given amount and amount index, RingCT outputs have an
+/// amount of 0.
+fn output_time_lock<'a, T: Transaction<'a>>(
+    ro_tx: &T,
+    amount: u64,
+    index: u64,
+) -> Result<OutputTimeLock, DB_FAILURES> {
+    let out_metadata = output_metadata(ro_tx, amount, index)?;
+    // The output is a miner output if it was created by the miner transaction of its block.
+    let miner_tx_hash = block(ro_tx, out_metadata.height)?.miner_tx.hash();
+
+    Ok(OutputTimeLock {
+        height: out_metadata.height,
+        time_lock: time_lock(out_metadata.unlock_time),
+        is_coinbase: out_metadata.tx_hash.0 == miner_tx_hash,
+    })
+}
+
+/// `num_outputs_in_range` fetch the amount of outputs with the given amount created in each block of the range, RingCT
+/// outputs have an amount of 0.
+fn num_outputs_in_range<'a, T: Transaction<'a>>(
+    ro_tx: &T,
+    amount: u64,
+    range: Range<u64>,
+) -> Result<Vec<u64>, DB_FAILURES> {
+    if amount == 0 {
+        // The blocks' metadata hold the cumulative amount of RingCT outputs.
+        let mut cum_rct = match range.start {
+            0 => 0,
+            start => block_metadata(ro_tx, start - 1)?.cum_rct,
+        };
+        return range
+            .map(|height| {
+                let block_cum_rct = block_metadata(ro_tx, height)?.cum_rct;
+                let count = block_cum_rct - cum_rct;
+                cum_rct = block_cum_rct;
+                Ok(count)
+            })
+            .collect();
+    }
+
+    let mut counts = vec![0; (range.end - range.start) as usize];
+
+    // Amount indices are given in the order the outputs are created, so the outputs' heights only go up.
+    let mut cursor = ro_tx.cursor_dup::<table::prerctoutputmetadata>()?;
+    let mut next = cursor
+        .set_dup(&amount)?
+        .map(|(_, out_metadata)| out_metadata);
+    while let Some(out_metadata) = next {
+        if out_metadata.height >= range.end {
+            break;
+        }
+        if out_metadata.height >= range.start {
+            counts[(out_metadata.height - range.start) as usize] += 1;
+        }
+        next = cursor
+            .next_dup()?
+            .map(|(_, (_, out_metadata))| out_metadata);
+    }
+
+    Ok(counts)
+}
+
+/// `output_distribution` returns the amount of outputs with `amount` created up to and including each block from
+/// `from_height` to `to_height`.
+fn output_distribution<'a, T: Transaction<'a>>(
+    ro_tx: &T,
+    amount: u64,
+    from_height: u64,
+    to_height: u64,
+) -> Result<Vec<u64>, DB_FAILURES> {
+    if amount == 0 {
+        // The blocks' metadata already hold the cumulative amount.
+        return (from_height..=to_height)
+            .map(|height| Ok(block_metadata(ro_tx, height)?.cum_rct))
+            .collect();
+    }
+
+    let counts = num_outputs_in_range(ro_tx, amount, 0..to_height + 1)?;
+    let mut total = 0;
+    Ok(counts
+        .into_iter()
+        .map(|count| {
+            total += count;
+            total
+        })
+        .skip(from_height as usize)
+        .collect())
+}
+
+/// `zero_commitment` is the commitment to an amount with a mask of 1, used for the outputs of RingCT miner transactions.
+fn zero_commitment(amount: u64) -> Key {
+    Key {
+        key: monero_serai::Commitment::new(Scalar::ONE, amount)
+            .calculate()
+            .compress()
+            .to_bytes(),
+    }
+}
+
+// ---------------------------------|  Writes  |--------------------------------
+
+/// `write_block` add a verified block to the top of the chain, with its transactions, outputs and key images.
+fn write_block<'a, T: WriteTransaction<'a>>(
+    rw_tx: &T,
+    verified: VerifiedBlockInformation,
+) -> Result<(), DB_FAILURES> {
+    let height = rw_tx.num_entries::<table::blockhash>()? as u64;
+    if verified.height != height {
+        return Err(DB_FAILURES::Other(
+            "Block is not the next block of the chain",
+        ));
+    }
+
+    let blk: Block = from_serai_blob(verified.block.serialize())?;
+    let parent_metadata = match height {
+        0 => None,
+        _ => Some(block_metadata(rw_tx, height - 1)?),
+    };
+    if parent_metadata
+        .as_ref()
+        .is_some_and(|parent| parent.block_hash.0 != blk.header.prev_id)
+    {
+        return Err(DB_FAILURES::Other(
+            "Top block is not the new block's parent",
+        ));
+    }
+
+    let pruning_seed = pruning_seed(rw_tx)?;
+    let txs = match verified.txs {
+        VerifiedBlockTxs::Full(txs) => txs
+            .iter()
+            .map(|tx| split_tx(from_serai_blob(tx.serialize())?))
+            .collect::<Result<Vec<_>, _>>()?,
+        VerifiedBlockTxs::Pruned(txs) => {
+            if !pruning_seed.is_pruned() {
+                return Err(DB_FAILURES::Other(
+                    "Can't add a pruned block to a database that doesn't prune",
+                ));
+            }
+            txs.into_iter()
+                .map(tx_blob_parts)
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+    if blk.tx_hashes.len() != txs.len() {
+        return Err(DB_FAILURES::Other("sanity : Inconsistent tx/hashed sizes"));
+    }
+
+    let first_tx_id = rw_tx.num_entries::<table::txspruned>()? as u64;
+
+    let mut batch = BlockWriteBatch::default();
+    let mut outputs_per_tx = Vec::with_capacity(txs.len() + 1);
+
+    // Pruned transactions can't be hashed, the hashes in the block were checked by the verifier.
+    let tx_hashes = once(blk.miner_tx.hash()).chain(blk.tx_hashes.iter().copied());
+    let txs = once(split_tx(blk.miner_tx.clone())?).chain(txs);
+    for (i, (tx_hash, tx_parts)) in tx_hashes.zip(txs).enumerate() {
+        let (tx, tx_prunable_blob, tx_prunable_hash) = tx_parts;
+        let tx_id = first_tx_id + i as u64;
+        let is_miner_tx = i == 0;
+
+        batch.tx_indices.push((
+            tx_hash,
+            TxIndex {
+                tx_id,
+                unlock_time: tx.prefix.unlock_time.0,
+                height,
+            },
+        ));
+
+        for input in &tx.prefix.inputs {
+            if let TxIn::ToKey { k_image, .. } = input {
+                batch.key_images.push(k_image.clone());
+            }
+        }
+
+        for (local_index, out) in tx.prefix.outputs.iter().enumerate() {
+            // Outputs of v2 transactions are RingCT outputs, the miner transaction has no commitments so they are
+            // commitments to the output's amount with a mask of 1.
+            let commitment = match tx.prefix.version.0 {
+                1 => None,
+                _ if is_miner_tx => Some(zero_commitment(out.amount.0)),
+                _ => Some(
+                    tx.rct_signatures
+                        .sig
+                        .as_ref()
+                        .and_then(|sig| sig.out_pk.get(local_index))
+                        .ok_or(DB_FAILURES::Other("RingCT output without a commitment"))?
+                        .mask,
+                ),
+            };
+
+            batch.outputs.push((
+                out.amount.0,
+                OutputMetadata {
+                    tx_hash: tx_hash.into(),
+                    local_index: local_index as u64,
+                    pubkey: out.target.as_one_time_key().map(Into::into),
+                    unlock_time: tx.prefix.unlock_time.0,
+                    height,
+                    commitment: commitment.map(Into::into),
+                },
+            ));
+        }
+        outputs_per_tx.push(tx.prefix.outputs.len());
+
+        write_tx_data(
+            rw_tx,
+            &tx,
+            tx_prunable_blob.as_deref(),
+            tx_prunable_hash,
+            tx_id,
+            height,
+            pruning_seed.is_pruned(),
+        )?;
+    }
+
+    let num_rct_outs = batch
+        .outputs
+        .iter()
+        .filter(|(_, out_metadata)| out_metadata.commitment.is_some())
+        .count() as u64;
+
+    let mut amount_indices = write_block_batch(rw_tx, batch)?.into_iter();
+    for (i, num_outputs) in outputs_per_tx.into_iter().enumerate() {
+        let tx_amount_indices = TxOutputIdx(amount_indices.by_ref().take(num_outputs).collect());
+        rw_tx.put::<table::txsoutputs>(&(first_tx_id + i as u64), &tx_amount_indices)?;
+    }
+
+    let (parent_coins_generated, parent_cum_rct) = match parent_metadata {
+        Some(parent) => (parent.total_coins_generated, parent.cum_rct),
+        None => (0, 0),
+    };
+    let blk_metadata = BlockMetadata {
+        timestamp: blk.header.timestamp.0,
+        total_coins_generated: parent_coins_generated + verified.generated_coins,
+        weight: verified.weight as u64,
+        cumulative_difficulty: verified.cumulative_difficulty,
+        block_hash: Hash(verified.block_hash).into(),
+        cum_rct: parent_cum_rct + num_rct_outs,
+        long_term_block_weight: verified.long_term_weight as u64,
+    };
+
+    let mut cursor_blockhash = rw_tx.write_cursor_dup::<table::blockhash>()?;
+    if cursor_blockhash
+        .get_dup(&(), &blk_metadata.block_hash)?
+        .is_some()
+    {
+        return Err(DB_FAILURES::AlreadyExist(
+            "Attempting to insert a block already existent in the database",
+        ));
+    }
+    cursor_blockhash.put_cursor_dup(&(), &blk_metadata.block_hash, &height)?;
+    rw_tx
+        .write_cursor_dup::<table::blockmetadata>()?
+        .put_cursor_dup(&(), &height, &blk_metadata)?;
+    rw_tx.put::<table::blocks>(&height, &blk.into())?;
+
+    prune_block_leaving_tip(rw_tx, height, pruning_seed)
+}
+
+/// The pruned part of a transaction, its prunable part if we have it and the hash of its prunable part if it has one.
+type TxParts = (TransactionPruned, Option<Vec<u8>>, Option<Hash>);
+
+/// `split_tx` split a transaction in its pruned and prunable parts.
+fn split_tx(tx: monero::Transaction) -> Result<TxParts, DB_FAILURES> {
+    let mut tx_prunable_blob = Vec::new();
+    get_transaction_prunable_blob(&tx, &mut tx_prunable_blob)
+        .map_err(|_| DB_FAILURES::SerializeIssue(DB_SERIAL::ConsensusEncode))?;
+    let tx_prunable_hash = calculate_prunable_hash(&tx, &tx_prunable_blob);
+
+    let tx_pruned = TransactionPruned {
+        prefix: tx.prefix,
+        rct_signatures: RctSig {
+            sig: tx.rct_signatures.sig,
+            p: None,
+        },
+    };
+    Ok((tx_pruned, Some(tx_prunable_blob), tx_prunable_hash))
+}
+
+/// `tx_blob_parts` parse a transaction of a pruned block. Version 1 transactions can't be pruned so they are complete.
+fn tx_blob_parts(tx: TxBlob) -> Result<TxParts, DB_FAILURES> {
+    let Some(tx_prunable_hash) = tx.prunable_hash else {
+        return split_tx(from_serai_blob(tx.blob.into())?);
+    };
+
+    let (tx_pruned, _): (TransactionPruned, _) =
+        bincode::decode_from_slice(&tx.blob, BINCODE_CONFIG)
+            .map_err(|err| DB_FAILURES::SerializeIssue(err.into()))?;
+    if tx_pruned.prefix.version.0 == 1 {
+        return Err(DB_FAILURES::Other("Version 1 transactions can't be pruned"));
+    }
+    Ok((tx_pruned, None, Some(Hash(tx_prunable_hash))))
+}
+
+/// `write_tx_data` add a transaction's pruned part and, if we have it, its prunable part. Its outputs and key images are
+/// added with the block's [`BlockWriteBatch`].
+fn write_tx_data<'a, T: WriteTransaction<'a>>(
+    rw_tx: &T,
+    tx_pruned: &TransactionPruned,
+    tx_prunable_blob: Option<&[u8]>,
+    tx_prunable_hash: Option<Hash>,
+    tx_id: u64,
+    height: u64,
+    is_pruned: bool,
+) -> Result<(), DB_FAILURES> {
+    if let Some(tx_prunable_hash) = tx_prunable_hash {
+        rw_tx.put::<table::txsprunablehash>(&tx_id, &tx_prunable_hash.into())?;
+    }
+
+    rw_tx.put::<table::txspruned>(&tx_id, tx_pruned)?;
+
+    if let Some(tx_prunable_blob) = tx_prunable_blob {
+        rw_tx.put::<table::txsprunable>(&tx_id, &tx_prunable_blob.to_vec())?;
+
+        if is_pruned {
+            rw_tx.put::<table::txsprunabletip>(&tx_id, &height)?;
+        }
+    }
+    Ok(())
+}
+
+/// `pruning_seed` fetch the database's pruning seed, databases without one don't prune.
+fn pruning_seed<'a, T: Transaction<'a>>(ro_tx: &T) -> Result<PruningSeed, DB_FAILURES> {
+    let seed = ro_tx.get::<table::properties>(&0)?.unwrap_or(0);
+    PruningSeed::try_from(seed)
+        .map_err(|_| DB_FAILURES::Other("The database's pruning seed is invalid"))
+}
+
+/// `prune_block_leaving_tip` is called once the block at `height` is added. The block [`CRYPTONOTE_PRUNING_TIP_BLOCKS`]
+/// below it leaves the tip, so its transactions are removed from `txsprunabletip` and their prunable data is removed if the
+/// block isn't in the database's stripe.
+fn prune_block_leaving_tip<'a, T: WriteTransaction<'a>>(
+    rw_tx: &T,
+    height: u64,
+    pruning_seed: PruningSeed,
+) -> Result<(), DB_FAILURES> {
+    if !pruning_seed.is_pruned() || height < CRYPTONOTE_PRUNING_TIP_BLOCKS {
+        return Ok(());
+    }
+    let pruned_height = height - CRYPTONOTE_PRUNING_TIP_BLOCKS;
+    let keep_prunable = pruning_seed
+        .has_unpruned_data(pruned_height, height + 1)
+        .map_err(|_| DB_FAILURES::Other("Failed to find the pruning stripe of a block"))?;
+
+    let blk = block(rw_tx, pruned_height)?;
+    for tx_hash in once(blk.miner_tx.hash()).chain(blk.tx_hashes) {
+        let tx_id = tx_index(rw_tx, tx_hash)?.tx_id;
+        rw_tx.delete::<table::txsprunabletip>(&tx_id, &None)?;
+        if !keep_prunable {
+            rw_tx.delete::<table::txsprunable>(&tx_id, &None)?;
+        }
+    }
+    Ok(())
+}
+
+/// `pop_block` remove the top block of the chain, with its transactions, outputs and key images.
+///
+/// Return the block and its transactions, not including the miner transaction.
+fn pop_block<'a, T: WriteTransaction<'a>>(
+    rw_tx: &T,
+) -> Result<
+    (
+        monero_serai::block::Block,
+        Vec<monero_serai::transaction::Transaction>,
+    ),
+    DB_FAILURES,
+> {
+    let height = rw_tx.num_entries::<table::blockhash>()? as u64;
+    if height == 0 {
+        return Err(DB_FAILURES::Other(
+            "Attempting to remove block from an empty blockchain",
+        ));
+    }
+    let top_height = height - 1;
+
+    let blk = block(rw_tx, top_height)?;
+    let metadata = block_metadata(rw_tx, top_height)?;
+
+    // The transactions are removed newest first, so transaction IDs stay contiguous.
+    let mut txs = Vec::with_capacity(blk.tx_hashes.len());
+    for tx_hash in blk.tx_hashes.iter().rev() {
+        let txindex = tx_index(rw_tx, *tx_hash)?;
+        let blob = tx_blob(rw_tx, &txindex)?;
+        let tx = monero_serai::transaction::Transaction::read(&mut blob.as_slice());
+        txs.push(tx.map_err(|_| DB_FAILURES::SerializeIssue(DB_SERIAL::ConsensusDecode(blob)))?);
+
+        remove_tx(rw_tx, *tx_hash, &txindex)?;
+    }
+    txs.reverse();
+
+    let miner_tx_hash = blk.miner_tx.hash();
+    remove_tx(rw_tx, miner_tx_hash, &tx_index(rw_tx, miner_tx_hash)?)?;
+
+    let mut cursor_blockhash = rw_tx.write_cursor_dup::<table::blockhash>()?;
+    if cursor_blockhash
+        .get_dup(&(), &metadata.block_hash)?
+        .is_some()
+    {
+        cursor_blockhash.del()?;
+    }
+    let mut cursor_blockmetadata = rw_tx.write_cursor_dup::<table::blockmetadata>()?;
+    if cursor_blockmetadata.get_dup(&(), &top_height)?.is_some() {
+        cursor_blockmetadata.del()?;
+    }
+    rw_tx.delete::<table::blocks>(&top_height, &None)?;
+
+    Ok((to_serai_block(&blk)?, txs))
+}
+
+/// `remove_tx` remove a transaction, its outputs and the key images it spent.
+fn remove_tx<'a, T: WriteTransaction<'a>>(
+    rw_tx: &T,
+    tx_hash: Hash,
+    txindex: &TxIndex,
+) -> Result<(), DB_FAILURES> {
+    let prefix = rw_tx
+        .get::<table::txspruned>(&txindex.tx_id)?
+        .ok_or(DB_FAILURES::NotFound(
+            "Attempting to remove transaction that isn't in the db",
+        ))?
+        .prefix;
+
+    let mut cursor_spentkeys = rw_tx.write_cursor_dup::<table::spentkeys>()?;
+    for input in &prefix.inputs {
+        if let TxIn::ToKey { k_image, .. } = input {
+            if cursor_spentkeys
+                .get_dup(&(), &k_image.clone().into())?
+                .is_some()
+            {
+                cursor_spentkeys.del()?;
+            }
+        }
+    }
+
+    // The outputs of v2 transactions are RingCT outputs, the others are indexed by amount. They are removed newest first so
+    // each is the newest of its amount when removed.
+    let amount_indices = tx_output_indices(rw_tx, txindex)?;
+    for (out, amount_index) in prefix.outputs.iter().zip(amount_indices).rev() {
+        remove_last_output(rw_tx, out.amount.0, prefix.version.0 > 1, amount_index)?;
+    }
+
+    rw_tx.delete::<table::txsoutputs>(&txindex.tx_id, &None)?;
+    rw_tx.delete::<table::txspruned>(&txindex.tx_id, &None)?;
+    rw_tx.delete::<table::txsprunable>(&txindex.tx_id, &None)?;
+    if rw_tx
+        .get::<table::txsprunabletip>(&txindex.tx_id)?
+        .is_some()
+    {
+        rw_tx.delete::<table::txsprunabletip>(&txindex.tx_id, &None)?;
+    }
+    if prefix.version.0 > 1 {
+        rw_tx.delete::<table::txsprunablehash>(&txindex.tx_id, &None)?;
+    }
+    rw_tx.delete::<table::txsidentifier>(&tx_hash.into(), &None)
+}
+
+/// `from_serai_blob` parse a `monero-serai` type's blob as the `monero-rs` type stored in the database.
+fn from_serai_blob<M: monero::consensus::Decodable>(blob: Vec<u8>) -> Result<M, DB_FAILURES> {
+    let res = monero::consensus::deserialize(&blob);
+    res.map_err(|_| DB_FAILURES::SerializeIssue(DB_SERIAL::ConsensusDecode(blob)))
+}
+
+#[cfg(all(test, feature = "mdbx"))]
+mod tests {
+    use std::collections::HashMap;
+
+    use curve25519_dalek::{
+        constants::{ED25519_BASEPOINT_COMPRESSED, ED25519_BASEPOINT_POINT},
+        edwards::CompressedEdwardsY,
+    };
+    use monero_serai::{
+        block::Block,
+        transaction::{Input, Output, RingSignature, Transaction},
+    };
+    use tower::ServiceExt;
+
+    use cuprate_common::{CRYPTONOTE_PRUNING_LOG_STRIPES, CRYPTONOTE_PRUNING_STRIPE_SIZE};
+    use monero_consensus::{
+        hardforks::HardFork,
+        test_utils::{dummy_block, dummy_miner_tx, dummy_verified_block},
+    };
+
+    use super::*;
+    use crate::transaction::Transaction as _;
+
+    type Mdbx = libmdbx::Database<libmdbx::NoWriteMap>;
+
+    /// The version and vote of the blocks of [`chain`].
+    const HARD_FORKS: [(HardFork, HardFork); 3] = [
+        (HardFork::V1, HardFork::V1),
+        (HardFork::V1, HardFork::V2),
+        (HardFork::V2, HardFork::V2),
+    ];
+
+    fn open_database(name: &str) -> DatabaseService<Mdbx> {
+        let path = std::env::temp_dir().join(format!(
+            "cuprate-database-service-test-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+
+        let db = <Mdbx as Database>::open(path).unwrap();
+        db.build().unwrap();
+        DatabaseService::new(Arc::new(db))
+    }
+
+    fn output(amount: u64) -> Output {
+        Output {
+            amount: Some(amount),
+            key: ED25519_BASEPOINT_COMPRESSED,
+            view_tag: None,
+        }
+    }
+
+    /// A version 1 transaction spending one output of 10 with `key_image`, creating two outputs of 5
+    /// locked until block 50.
+    fn v1_tx(key_image: CompressedEdwardsY) -> Transaction {
+        let mut tx = dummy_miner_tx(1, None, vec![output(5), output(5)]);
+        tx.prefix.timelock = Timelock::Block(50);
+        tx.prefix.inputs = vec![Input::ToKey {
+            amount: Some(10),
+            key_offsets: vec![0],
+            key_image: key_image.decompress().unwrap(),
+        }];
+        tx.signatures = vec![RingSignature {
+            sigs: vec![(Scalar::ONE, Scalar::ONE)],
+        }];
+        tx
+    }
+
+    /// A chain of 3 blocks, each miner transaction creates a RingCT output of `1_000 + height` and
+    /// block 1 has a [`v1_tx`].
+    fn chain() -> Vec<VerifiedBlockInformation> {
+        let mut previous = [0; 32];
+        HARD_FORKS
+            .iter()
+            .enumerate()
+            .map(|(height, (version, vote))| {
+                let height = height as u64;
+                let mut block = dummy_block(
+                    *version,
+                    *vote,
+                    previous,
+                    dummy_miner_tx(2, Some(height), vec![output(1_000 + height)]),
+                );
+                block.header.timestamp = 100 * height;
+
+                let txs = match height {
+                    1 => vec![v1_tx(ED25519_BASEPOINT_COMPRESSED)],
+                    _ => vec![],
+                };
+                block.txs = txs.iter().map(Transaction::hash).collect();
+                previous = block.hash();
+
+                VerifiedBlockInformation {
+                    block_hash: block.hash(),
+                    txs: VerifiedBlockTxs::Full(txs),
+                    generated_coins: 1_000 + height,
+                    weight: 300 + height as usize,
+                    long_term_weight: 200 + height as usize,
+                    cumulative_difficulty: 10 * u128::from(height + 1),
+                    ..dummy_verified_block(block, height)
+                }
+            })
+            .collect()
+    }
+
+    /// Returns a database holding [`chain`].
+    async fn filled_database(name: &str) -> DatabaseService<Mdbx> {
+        let service = open_database(name);
+        service
+            .clone()
+            .oneshot(DatabaseRequest::WriteBlocks(chain()))
+            .await
+            .unwrap()
+            .into_write_block()
+            .unwrap();
+        service
+    }
+
+    async fn read(service: &DatabaseService<Mdbx>, req: DatabaseRequest) -> DatabaseResponse {
+        service.clone().oneshot(req).await.unwrap()
+    }
+
+    fn block_hash(height: usize) -> [u8; 32] {
+        chain()[height].block_hash
+    }
+
+    fn tx_hash(height: usize, i: usize) -> [u8; 32] {
+        chain()[height].block.txs[i]
+    }
+
+    fn miner_tx_hash(height: usize) -> [u8; 32] {
+        chain()[height].block.miner_tx.hash()
+    }
+
+    #[tokio::test]
+    async fn block_hf_info() {
+        let service = filled_database("block-hf-info").await;
+
+        for id in [BlockID::Height(1), BlockID::Hash(block_hash(1))] {
+            let hf_info = read(&service, DatabaseRequest::BlockHFInfo(id))
+                .await
+                .into_block_hf_info()
+                .unwrap();
+            assert_eq!((hf_info.version, hf_info.vote), HARD_FORKS[1]);
+        }
+        assert!(service
+            .clone()
+            .oneshot(DatabaseRequest::BlockHFInfo(BlockID::Hash([9; 32])))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn block_pow_info() {
+        let service = filled_database("block-pow-info").await;
+
+        let pow_info = read(&service, DatabaseRequest::BlockPOWInfo(BlockID::Height(2)))
+            .await
+            .into_block_pow_info()
+            .unwrap();
+        assert_eq!(
+            (pow_info.timestamp, pow_info.cumulative_difficulty),
+            (200, 30)
+        );
+    }
+
+    #[tokio::test]
+    async fn block_weights() {
+        let service = filled_database("block-weights").await;
+
+        let weights = read(
+            &service,
+            DatabaseRequest::BlockWeights(BlockID::Hash(block_hash(0))),
+        )
+        .await
+        .into_block_weights()
+        .unwrap();
+        assert_eq!((weights.block_weight, weights.long_term_weight), (300, 200));
+    }
+
+    #[tokio::test]
+    async fn block_hash_generated_coins_and_cumulative_difficulty() {
+        let service = filled_database("block-hash").await;
+
+        let hash = read(&service, DatabaseRequest::BlockHash(2))
+            .await
+            .into_block_hash()
+            .unwrap();
+        assert_eq!(hash, block_hash(2));
+        assert!(service
+            .clone()
+            .oneshot(DatabaseRequest::BlockHash(3))
+            .await
+            .is_err());
+
+        // The totals are up to and including the block.
+        let coins = read(&service, DatabaseRequest::GeneratedCoins(2))
+            .await
+            .into_generated_coins()
+            .unwrap();
+        assert_eq!(coins, 1_000 + 1_001 + 1_002);
+        let difficulty = read(&service, DatabaseRequest::CumulativeDifficulty(1))
+            .await
+            .into_cumulative_difficulty()
+            .unwrap();
+        assert_eq!(difficulty, 20);
+    }
+
+    #[tokio::test]
+    async fn block_info_in_range() {
+        let service = filled_database("block-info-in-range").await;
+
+        let hf_infos = read(&service, DatabaseRequest::BlockHfInfoInRange(0..3))
+            .await
+            .into_block_hf_info_in_range()
+            .unwrap();
+        let hard_forks: Vec<_> = hf_infos
+            .iter()
+            .map(|hf_info| (hf_info.version, hf_info.vote))
+            .collect();
+        assert_eq!(hard_forks, HARD_FORKS);
+
+        let weights = read(&service, DatabaseRequest::BlockWeightsInRange(1..3))
+            .await
+            .into_block_weights_in_range()
+            .unwrap();
+        let weights: Vec<_> = weights.iter().map(|w| w.block_weight).collect();
+        assert_eq!(weights, [301, 302]);
+
+        let pow_infos = read(&service, DatabaseRequest::BlockPOWInfoInRange(0..2))
+            .await
+            .into_block_pow_info_in_range()
+            .unwrap();
+        let timestamps: Vec<_> = pow_infos.iter().map(|p| p.timestamp).collect();
+        assert_eq!(timestamps, [0, 100]);
+
+        // Every block of the range must be in the chain.
+        assert!(service
+            .clone()
+            .oneshot(DatabaseRequest::BlockWeightsInRange(2..4))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn chain_height() {
+        let service = open_database("chain-height");
+        let height = read(&service, DatabaseRequest::ChainHeight)
+            .await
+            .into_chain_height()
+            .unwrap();
+        assert_eq!(height, 0);
+
+        let service = filled_database("chain-height-filled").await;
+        let height = read(&service, DatabaseRequest::ChainHeight)
+            .await
+            .into_chain_height()
+            .unwrap();
+        assert_eq!(height, 3);
+    }
+
+    #[tokio::test]
+    async fn key_images_spent() {
+        let service = filled_database("key-images-spent").await;
+
+        let spent = read(
+            &service,
+            DatabaseRequest::KeyImagesSpent(vec![[9; 32], ED25519_BASEPOINT_COMPRESSED.0]),
+        )
+        .await
+        .into_key_images_spent()
+        .unwrap();
+        assert!(spent);
+
+        let spent = read(&service, DatabaseRequest::KeyImagesSpent(vec![[9; 32]]))
+            .await
+            .into_key_images_spent()
+            .unwrap();
+        assert!(!spent);
+    }
+
+    #[tokio::test]
+    async fn block_and_block_height() {
+        let service = filled_database("block").await;
+
+        let block = read(
+            &service,
+            DatabaseRequest::Block(BlockID::Hash(block_hash(1))),
+        )
+        .await
+        .into_block()
+        .unwrap();
+        assert_eq!(*block, chain()[1].block);
+
+        let height = read(&service, DatabaseRequest::BlockHeight(block_hash(1)))
+            .await
+            .into_block_height()
+            .unwrap();
+        assert_eq!(height, Some(1));
+        let height = read(&service, DatabaseRequest::BlockHeight([9; 32]))
+            .await
+            .into_block_height()
+            .unwrap();
+        assert_eq!(height, None);
+    }
+
+    #[tokio::test]
+    async fn block_blobs_in_range() {
+        let service = filled_database("block-blobs-in-range").await;
+
+        let blobs = read(
+            &service,
+            DatabaseRequest::BlockBlobsInRange {
+                range: 1..3,
+                pruned: false,
+            },
+        )
+        .await
+        .into_block_blobs_in_range()
+        .unwrap();
+        assert_eq!(
+            blobs[0],
+            BlockBlobs {
+                block: chain()[1].block.serialize(),
+                block_weight: 301,
+                txs: vec![TxBlob {
+                    blob: v1_tx(ED25519_BASEPOINT_COMPRESSED).serialize().into(),
+                    prunable_hash: None,
+                }],
+                output_indices: vec![vec![1], vec![0, 1]],
+            }
+        );
+        assert_eq!(blobs[1].output_indices, vec![vec![2]]);
+
+        // Version 1 transactions have no prunable part.
+        let blobs = read(
+            &service,
+            DatabaseRequest::BlockBlobsInRange {
+                range: 1..2,
+                pruned: true,
+            },
+        )
+        .await
+        .into_block_blobs_in_range()
+        .unwrap();
+        assert_eq!(blobs[0].txs[0].prunable_hash, None);
+    }
+
+    #[tokio::test]
+    async fn tx_output_indices() {
+        let service = filled_database("tx-output-indices").await;
+
+        let indices = read(&service, DatabaseRequest::TxOutputIndices(miner_tx_hash(2)))
+            .await
+            .into_tx_output_indices()
+            .unwrap();
+        assert_eq!(indices, [2]);
+        let indices = read(&service, DatabaseRequest::TxOutputIndices(tx_hash(1, 0)))
+            .await
+            .into_tx_output_indices()
+            .unwrap();
+        assert_eq!(indices, [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn outputs() {
+        let service = filled_database("outputs").await;
+
+        let outputs = read(&service, DatabaseRequest::Outputs(vec![(0, 1), (5, 1)]))
+            .await
+            .into_outputs()
+            .unwrap();
+        assert_eq!(
+            outputs,
+            [
+                // The miner transaction's outputs are commitments to their amount with a mask of 1.
+                OutputOnChain {
+                    height: 1,
+                    time_lock: Timelock::Block(61),
+                    key: ED25519_BASEPOINT_COMPRESSED.0,
+                    mask: zero_commitment(1_001).key,
+                    txid: miner_tx_hash(1),
+                },
+                OutputOnChain {
+                    height: 1,
+                    time_lock: Timelock::Block(50),
+                    key: ED25519_BASEPOINT_COMPRESSED.0,
+                    mask: zero_commitment(5).key,
+                    txid: tx_hash(1, 0),
+                },
+            ]
+        );
+        assert!(service
+            .clone()
+            .oneshot(DatabaseRequest::Outputs(vec![(5, 2)]))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn output_time_locks() {
+        let service = filled_database("output-time-locks").await;
+
+        let time_locks = read(
+            &service,
+            DatabaseRequest::OutputTimeLocks(vec![(5, 0), (0, 2)]),
+        )
+        .await
+        .into_output_time_locks()
+        .unwrap();
+        assert_eq!(
+            time_locks,
+            [
+                OutputTimeLock {
+                    height: 1,
+                    time_lock: Timelock::Block(50),
+                    is_coinbase: false,
+                },
+                OutputTimeLock {
+                    height: 2,
+                    time_lock: Timelock::Block(62),
+                    is_coinbase: true,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn num_outputs_in_range_and_distribution() {
+        let service = filled_database("num-outputs-in-range").await;
+
+        for (amount, counts, distribution) in [(0, [1, 1, 1], [1, 2, 3]), (5, [0, 2, 0], [0, 2, 2])]
+        {
+            let got = read(
+                &service,
+                DatabaseRequest::NumOutputsInRange {
+                    amount,
+                    range: 0..3,
+                },
+            )
+            .await
+            .into_num_outputs_in_range()
+            .unwrap();
+            assert_eq!(got, counts);
+
+            let got = read(
+                &service,
+                DatabaseRequest::OutputDistribution {
+                    amount,
+                    from_height: 1,
+                    to_height: 2,
+                },
+            )
+            .await
+            .into_output_distribution()
+            .unwrap();
+            assert_eq!(got, distribution[1..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn number_outputs_with_amount() {
+        let service = filled_database("number-outputs-with-amount").await;
+
+        let counts = read(
+            &service,
+            DatabaseRequest::NumberOutputsWithAmount(vec![0, 5, 7]),
+        )
+        .await
+        .into_number_outputs_with_amount()
+        .unwrap();
+        assert_eq!(counts, HashMap::from([(0, 3), (5, 2), (7, 0)]));
+    }
+
+    #[tokio::test]
+    async fn block_batch_in_range_and_transactions() {
+        let service = filled_database("block-batch-in-range").await;
+
+        let blocks = read(&service, DatabaseRequest::BlockBatchInRange(0..3))
+            .await
+            .into_block_batch_in_range()
+            .unwrap();
+        let expected: Vec<Block> = chain().into_iter().map(|block| block.block).collect();
+        assert_eq!(blocks, expected);
+
+        let txs = read(&service, DatabaseRequest::Transactions(vec![tx_hash(1, 0)]))
+            .await
+            .into_transactions()
+            .unwrap();
+        assert_eq!(txs, [v1_tx(ED25519_BASEPOINT_COMPRESSED)]);
+        assert!(service
+            .clone()
+            .oneshot(DatabaseRequest::Transactions(vec![[9; 32]]))
+            .await
+            .is_err());
+    }
+
+    /// The number of entries in each table.
+    fn table_sizes(service: &DatabaseService<Mdbx>) -> Vec<usize> {
+        let ro_tx = service.db.tx().unwrap();
+        vec![
+            ro_tx.num_entries::<table::blockhash>().unwrap(),
+            ro_tx.num_entries::<table::blockmetadata>().unwrap(),
+            ro_tx.num_entries::<table::blocks>().unwrap(),
+            ro_tx.num_entries::<table::altblock>().unwrap(),
+            ro_tx.num_entries::<table::txspruned>().unwrap(),
+            ro_tx.num_entries::<table::txsprunable>().unwrap(),
+            ro_tx.num_entries::<table::txsprunablehash>().unwrap(),
+            ro_tx.num_entries::<table::txsprunabletip>().unwrap(),
+            ro_tx.num_entries::<table::txsoutputs>().unwrap(),
+            ro_tx.num_entries::<table::txsidentifier>().unwrap(),
+            ro_tx.num_entries::<table::prerctoutputmetadata>().unwrap(),
+            ro_tx.num_entries::<table::rctoutputs>().unwrap(),
+            ro_tx.num_entries::<table::spentkeys>().unwrap(),
+            ro_tx.num_entries::<table::properties>().unwrap(),
+        ]
+    }
+
+    #[tokio::test]
+    async fn popping_a_block_restores_every_table() {
+        let service = open_database("write-pop-round-trip");
+        let mut chain = chain().into_iter();
+
+        let write = |block| {
+            service
+                .clone()
+                .oneshot(DatabaseRequest::WriteBlock(Box::new(block)))
+        };
+        write(chain.next().unwrap()).await.unwrap();
+        let sizes = table_sizes(&service);
+
+        // Block 1 spends a key image and creates a RingCT output and two outputs of 5.
+        let block = chain.next().unwrap();
+        write(block.clone()).await.unwrap();
+
+        let height = read(&service, DatabaseRequest::ChainHeight)
+            .await
+            .into_chain_height()
+            .unwrap();
+        assert_eq!(height, 2);
+        let height = read(&service, DatabaseRequest::BlockHeight(block.block_hash))
+            .await
+            .into_block_height()
+            .unwrap();
+        assert_eq!(height, Some(1));
+        let outputs = read(
+            &service,
+            DatabaseRequest::Outputs(vec![(0, 1), (5, 0), (5, 1)]),
+        )
+        .await
+        .into_outputs()
+        .unwrap();
+        assert!(outputs.iter().all(|output| output.height == 1));
+        let spent = read(
+            &service,
+            DatabaseRequest::KeyImagesSpent(vec![ED25519_BASEPOINT_COMPRESSED.0]),
+        )
+        .await
+        .into_key_images_spent()
+        .unwrap();
+        assert!(spent);
+
+        let (popped, txs) = read(&service, DatabaseRequest::PopBlock)
+            .await
+            .into_pop_block()
+            .unwrap();
+        assert_eq!(*popped, block.block);
+        assert_eq!(txs, [v1_tx(ED25519_BASEPOINT_COMPRESSED)]);
+
+        assert_eq!(table_sizes(&service), sizes);
+        let height = read(&service, DatabaseRequest::BlockHeight(block.block_hash))
+            .await
+            .into_block_height()
+            .unwrap();
+        assert_eq!(height, None);
+        let spent = read(
+            &service,
+            DatabaseRequest::KeyImagesSpent(vec![ED25519_BASEPOINT_COMPRESSED.0]),
+        )
+        .await
+        .into_key_images_spent()
+        .unwrap();
+        assert!(!spent);
+        for output in [(0, 1), (5, 0)] {
+            assert!(service
+                .clone()
+                .oneshot(DatabaseRequest::Outputs(vec![output]))
+                .await
+                .is_err());
+        }
+
+        // The block can be written again and gets the same indices.
+        write(block).await.unwrap();
+        let indices = read(&service, DatabaseRequest::TxOutputIndices(tx_hash(1, 0)))
+            .await
+            .into_tx_output_indices()
+            .unwrap();
+        assert_eq!(indices, [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn pruning_seed_is_only_set_on_new_databases() {
+        let seed = PruningSeed::new(2, CRYPTONOTE_PRUNING_LOG_STRIPES).unwrap();
+
+        let service = open_database("pruning-seed");
+        assert_eq!(service.pruning_seed().unwrap(), PruningSeed::NOT_PRUNED);
+        service.set_pruning_seed(seed).unwrap();
+        service.set_pruning_seed(seed).unwrap();
+        assert_eq!(service.pruning_seed().unwrap(), seed);
+
+        let service = filled_database("pruning-seed-filled").await;
+        assert!(service.set_pruning_seed(seed).is_err());
+        service.set_pruning_seed(PruningSeed::NOT_PRUNED).unwrap();
+        assert_eq!(service.pruning_seed().unwrap(), PruningSeed::NOT_PRUNED);
+    }
+
+    #[tokio::test]
+    async fn pruned_blocks_are_only_written_to_pruned_databases() {
+        // A version 2 transaction without inputs has no prunable data, its prunable hash is zero.
+        let tx = dummy_miner_tx(2, None, vec![]);
+        let mut block = dummy_block(
+            HardFork::V1,
+            HardFork::V2,
+            block_hash(0),
+            dummy_miner_tx(2, Some(1), vec![output(1_001)]),
+        );
+        block.txs = vec![tx.hash()];
+        let pruned_block = VerifiedBlockInformation {
+            block_hash: block.hash(),
+            txs: VerifiedBlockTxs::Pruned(vec![TxBlob {
+                blob: tx.serialize().into(),
+                prunable_hash: Some([0; 32]),
+            }]),
+            ..dummy_verified_block(block, 1)
+        };
+        let write = |service: DatabaseService<Mdbx>, seed| {
+            let pruned_block = pruned_block.clone();
+            async move {
+                service.set_pruning_seed(seed).unwrap();
+                let genesis = chain().swap_remove(0);
+                read(&service, DatabaseRequest::WriteBlock(Box::new(genesis))).await;
+                service
+                    .oneshot(DatabaseRequest::WriteBlock(Box::new(pruned_block)))
+                    .await
+            }
+        };
+
+        let service = open_database("unpruned-write");
+        assert!(write(service.clone(), PruningSeed::NOT_PRUNED)
+            .await
+            .is_err());
+        let height = read(&service, DatabaseRequest::ChainHeight)
+            .await
+            .into_chain_height()
+            .unwrap();
+        assert_eq!(height, 1);
+
+        let service = open_database("pruned-write");
+        let seed = PruningSeed::new(2, CRYPTONOTE_PRUNING_LOG_STRIPES).unwrap();
+        write(service.clone(), seed).await.unwrap();
+
+        let blobs = read(
+            &service,
+            DatabaseRequest::BlockBlobsInRange {
+                range: 1..2,
+                pruned: true,
+            },
+        )
+        .await
+        .into_block_blobs_in_range()
+        .unwrap();
+        assert_eq!(blobs[0].txs[0].prunable_hash, Some([0; 32]));
+        assert!(tx.serialize().starts_with(&blobs[0].txs[0].blob));
+
+        // The prunable data was never received, so the block can't be read in full.
+        assert!(service
+            .clone()
+            .oneshot(DatabaseRequest::BlockBlobsInRange {
+                range: 1..2,
+                pruned: false,
+            })
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn prunable_data_is_only_kept_for_the_tip_and_our_stripe() {
+        let service = open_database("pruning-stripes");
+        let seed = PruningSeed::new(2, CRYPTONOTE_PRUNING_LOG_STRIPES).unwrap();
+        service.set_pruning_seed(seed).unwrap();
+
+        // Block 0 is in stripe 1 and the first block of stripe 2 is ours, both have left the tip.
+        let stripe_start = CRYPTONOTE_PRUNING_STRIPE_SIZE;
+        let chain_height = stripe_start + CRYPTONOTE_PRUNING_TIP_BLOCKS + 1;
+        let tx =
+            |height: u64| v1_tx((ED25519_BASEPOINT_POINT * Scalar::from(height + 1)).compress());
+
+        let mut previous = [0; 32];
+        let blocks = (0..chain_height)
+            .map(|height| {
+                let mut block = dummy_block(
+                    HardFork::V1,
+                    HardFork::V1,
+                    previous,
+                    dummy_miner_tx(2, Some(height), vec![output(1)]),
+                );
+                let txs = match [0, stripe_start, chain_height - 1].contains(&height) {
+                    true => vec![tx(height)],
+                    false => vec![],
+                };
+                block.txs = txs.iter().map(Transaction::hash).collect();
+                previous = block.hash();
+
+                VerifiedBlockInformation {
+                    block_hash: block.hash(),
+                    txs: VerifiedBlockTxs::Full(txs),
+                    ..dummy_verified_block(block, height)
+                }
+            })
+            .collect();
+        read(&service, DatabaseRequest::WriteBlocks(blocks)).await;
+
+        let full_tx_blob = |height: u64| {
+            service.clone().oneshot(DatabaseRequest::BlockBlobsInRange {
+                range: height..height + 1,
+                pruned: false,
+            })
+        };
+        assert!(full_tx_blob(0).await.is_err());
+        for height in [stripe_start, chain_height - 1] {
+            let blobs = full_tx_blob(height)
+                .await
+                .unwrap()
+                .into_block_blobs_in_range()
+                .unwrap();
+            assert_eq!(blobs[0].txs[0].blob, tx(height).serialize());
+        }
+
+        // The pruned part of a block outside our stripe can still be read.
+        let blobs = read(
+            &service,
+            DatabaseRequest::BlockBlobsInRange {
+                range: 0..1,
+                pruned: true,
+            },
+        )
+        .await
+        .into_block_blobs_in_range()
+        .unwrap();
+        assert!(tx(0).serialize().starts_with(&blobs[0].txs[0].blob));
+        assert_ne!(blobs[0].txs[0].blob, tx(0).serialize());
+
+        let txs = read(
+            &service,
+            DatabaseRequest::Transactions(vec![tx(stripe_start).hash()]),
+        )
+        .await
+        .into_transactions()
+        .unwrap();
+        assert_eq!(txs, [tx(stripe_start)]);
+        assert!(service
+            .clone()
+            .oneshot(DatabaseRequest::Transactions(vec![tx(0).hash()]))
+            .await
+            .is_err());
+    }
+}
@@ -7,7 +7,7 @@
 use crate::{
     encoding::Compat,
     types::{
-        /*OutTx,*/ AltBlock, BlockMetadata, /*RctOutkey,*/ OutputMetadata,
+        /*OutTx,*/ AltBlock, BlockMetadata, /*RctOutkey,*/ OutputMetadata, RctOutput,
         TransactionPruned, TxIndex, /*OutAmountIdx,*/ /*KeyImage,*/ TxOutputIdx,
     },
 };
@@ -36,7 +36,7 @@ macro_rules! impl_table {
 	( $(#[$docs:meta])* $table:ident , $key:ty , $value:ty ) => {
         #[derive(Clone)]
 		$(#[$docs])*
-		pub(crate) struct $table;
+		pub struct $table;
 
    		impl Table for $table {
 	 		const TABLE_NAME: &'static str = stringify!($table);
@@ -148,17 +148,19 @@ impl_duptable!(
 
 impl_duptable!(
     /// `prerctoutputmetadata` is a duplicated table storing Pre-RingCT output's metadata. The key is the amount of this output, and the subkey is its amount idx.
+    /// Only Pre-RingCT outputs need this amount index, RingCT outputs all have an amount of 0 and are stored in [`rctoutputs`].
     prerctoutputmetadata,
     u64,
     u64,
     OutputMetadata
 );
-impl_duptable!(
-    /// `prerctoutputmetadata` is a table storing RingCT output's metadata. The key is the amount idx of this output since amount is always 0 for RingCT outputs.
-    outputmetadata,
-    (),
+
+impl_table!(
+    /// `rctoutputs` is a table storing RingCT output's metadata. The key is the global RingCT output index, as outputs are only ever added at the next index
+    /// and every [`RctOutput`] record has the same size, the table is written by appending records to its end, which keeps the write amplification low during IBD.
+    rctoutputs,
     u64,
-    OutputMetadata
+    RctOutput
 );
 
 // ---- SPT KEYS ----
@@ -8,6 +8,7 @@
 use crate::encoding::{Compat, ReaderCompat};
 use bincode::{enc::write::Writer, Decode, Encode};
 use monero::{
+    blockdata::transaction::KeyImage,
     consensus::{encode, Decodable},
     util::ringct::{Key, RctSig, RctSigBase, RctSigPrunable, RctType, Signature},
     Block, Hash, PublicKey, Transaction, TransactionPrefix, TxIn,
@@ -298,7 +299,7 @@ pub struct RctOutkey {
 }
 
 #[derive(Clone, Debug, Encode, Decode)]
-/// [`OutputMetadata`] is a struct containing Outputs Metadata. It is used in [`crate::table::outputmetadata`]. It is a struct merging the
+/// [`OutputMetadata`] is a struct containing Outputs Metadata. It is used in [`crate::table::prerctoutputmetadata`]. It is a struct merging the
 /// `out_tx_index` tuple with `output_data_t` structure in monerod, without the output ID.
 pub struct OutputMetadata {
     pub tx_hash: Compat<Hash>,
@@ -314,6 +315,56 @@ pub struct OutputMetadata {
     pub commitment: Option<Compat<Key>>,
 }
 
+#[derive(Clone, Debug, Encode, Decode)]
+/// [`RctOutput`] is a struct containing a RingCT output's metadata. It is used in [`crate::table::rctoutputs`].
+/// Every field is fixed size so every record is [`RctOutput::SIZE`] bytes, this lets the table be written by appending records in
+/// order of global index instead of inserting them into the B-tree.
+pub struct RctOutput {
+    /// Output's transaction hash
+    pub tx_hash: Compat<Hash>,
+    /// Local index of the output
+    pub local_index: u64,
+    /// The output's public key (for spend verification)
+    pub pubkey: Compat<PublicKey>,
+    /// The output's unlock time
+    pub unlock_time: u64,
+    /// The height of the block which created this output
+    pub height: u64,
+    /// The output's amount commitment (for spend verification)
+    pub commitment: Compat<Key>,
+}
+
+impl RctOutput {
+    /// The size of an encoded [`RctOutput`]: three 32 bytes keys and three `u64`.
+    pub const SIZE: usize = 32 * 3 + 8 * 3;
+}
+
+impl From<RctOutput> for OutputMetadata {
+    fn from(value: RctOutput) -> Self {
+        OutputMetadata {
+            tx_hash: value.tx_hash,
+            local_index: value.local_index,
+            pubkey: Some(value.pubkey),
+            unlock_time: value.unlock_time,
+            height: value.height,
+            commitment: Some(value.commitment),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// [`BlockWriteBatch`] contains every output, key image and transaction index created by a verified block. It is written with
+/// [`crate::database::Interface::add_block_write_batch`], which sorts each collection by its table's key before writing it so the cursors
+/// only ever move forward.
+pub struct BlockWriteBatch {
+    /// The block's outputs, in the order they were created, alongside their amount. RingCT outputs are the ones with a commitment.
+    pub outputs: Vec<(u64, OutputMetadata)>,
+    /// The key images spent by the block's transactions.
+    pub key_images: Vec<KeyImage>,
+    /// The hash and index of every transaction in the block.
+    pub tx_indices: Vec<(Hash, TxIndex)>,
+}
+
 //#[derive(Clone, Debug, Encode, Decode)]
 //// [`OutAmountIdx`] is a struct tuple used to contain the two keys used in [`crate::table::outputamounts`] table.
 //// In monerod, the database key is the amount while the *cursor key* (the amount index) is the prefix of the actual data being returned.
@@ -333,9 +384,28 @@ pub struct OutputMetadata {
 
 #[cfg(test)]
 mod tests {
-    use monero::Hash;
+    use monero::{util::ringct::Key, Hash, PublicKey};
+
+    use super::{get_transaction_prunable_blob, RctOutput};
+    use crate::BINCODE_CONFIG;
 
-    use super::get_transaction_prunable_blob;
+    #[test]
+    fn rct_output_is_fixed_size() {
+        let mut generator = [0x66; 32];
+        generator[0] = 0x58;
+
+        let rct_output = RctOutput {
+            tx_hash: Hash::zero().into(),
+            local_index: 1,
+            pubkey: PublicKey::from_slice(&generator).unwrap().into(),
+            unlock_time: u64::MAX,
+            height: 3_000_000,
+            commitment: Key { key: [1; 32] }.into(),
+        };
+
+        let encoded = bincode::encode_to_vec(&rct_output, BINCODE_CONFIG).unwrap();
+        assert_eq!(encoded.len(), RctOutput::SIZE);
+    }
 
     #[test]
     fn calculate_tx_prunable_hash() {
@@ -0,0 +1,233 @@
+//! The derive macro for `epee_encoding::EpeeObject`, see the `epee-encoding` crate for the
+//! attributes it supports.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Fields, Ident, LitStr, Type};
+
+#[proc_macro_derive(
+    EpeeObject,
+    attributes(epee_default, epee_try_from_into, epee_alt_name, epee_flatten)
+)]
+pub fn derive_epee_object(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// A struct field and its epee attributes.
+struct EpeeField {
+    ident: Ident,
+    ty: Type,
+    /// The name of the field in the data.
+    name: String,
+    default: Option<Expr>,
+    /// The type the field is (de)serialized as.
+    try_from_into: Option<Type>,
+    flatten: bool,
+}
+
+impl EpeeField {
+    fn parse(field: &syn::Field) -> syn::Result<EpeeField> {
+        let ident = field.ident.clone().expect("Only called for named fields");
+
+        let mut epee_field = EpeeField {
+            name: ident.to_string(),
+            ident,
+            ty: field.ty.clone(),
+            default: None,
+            try_from_into: None,
+            flatten: false,
+        };
+
+        for attr in &field.attrs {
+            if attr.path().is_ident("epee_default") {
+                epee_field.default = Some(attr.parse_args()?);
+            } else if attr.path().is_ident("epee_try_from_into") {
+                epee_field.try_from_into = Some(attr.parse_args()?);
+            } else if attr.path().is_ident("epee_alt_name") {
+                epee_field.name = attr.parse_args::<LitStr>()?.value();
+            } else if attr.path().is_ident("epee_flatten") {
+                attr.meta.require_path_only()?;
+                epee_field.flatten = true;
+            }
+        }
+
+        if epee_field.flatten
+            && (epee_field.default.is_some() || epee_field.try_from_into.is_some())
+        {
+            return Err(syn::Error::new_spanned(
+                field,
+                "epee_flatten can't be used with epee_default or epee_try_from_into",
+            ));
+        }
+
+        Ok(epee_field)
+    }
+
+    /// The type stored in the builder.
+    fn stored_ty(&self) -> &Type {
+        self.try_from_into.as_ref().unwrap_or(&self.ty)
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "EpeeObject can't be derived for generic types",
+        ));
+    }
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "EpeeObject can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &data.fields,
+            "EpeeObject can only be derived for structs with named fields",
+        ));
+    };
+
+    let fields = fields
+        .named
+        .iter()
+        .map(EpeeField::parse)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let struct_name = &input.ident;
+    let vis = &input.vis;
+    let builder_name = format_ident!("__{}EpeeBuilder", struct_name);
+
+    let mut builder_fields = Vec::new();
+    let mut add_field_arms = Vec::new();
+    let mut add_flattened_fields = Vec::new();
+    let mut finish_fields = Vec::new();
+    let mut count_fields = Vec::new();
+    let mut write_fields = Vec::new();
+
+    for field in &fields {
+        let ident = &field.ident;
+        let ty = &field.ty;
+        let name = &field.name;
+
+        if field.flatten {
+            builder_fields.push(quote! {
+                #ident: <#ty as epee_encoding::EpeeObject>::Builder
+            });
+            add_flattened_fields.push(quote! {
+                if epee_encoding::EpeeObjectBuilder::add_field(&mut self.#ident, name, r)? {
+                    return Ok(true);
+                }
+            });
+            finish_fields.push(quote! {
+                #ident: epee_encoding::EpeeObjectBuilder::finish(self.#ident)?
+            });
+            count_fields.push(quote! {
+                epee_encoding::EpeeObject::number_of_fields(&self.#ident)
+            });
+            write_fields.push(quote! {
+                epee_encoding::EpeeObject::write_fields(&self.#ident, w)?;
+            });
+            continue;
+        }
+
+        let stored_ty = field.stored_ty();
+
+        builder_fields.push(quote! {
+            #ident: Option<#stored_ty>
+        });
+        add_field_arms.push(quote! {
+            #name => {
+                if self.#ident.is_some() {
+                    return Err(epee_encoding::Error::Format("Duplicate field in data"));
+                }
+                self.#ident = Some(epee_encoding::read_epee_value(r)?);
+                true
+            }
+        });
+
+        let default = match &field.default {
+            Some(default) => quote! { Some(#default) },
+            None => quote! { <#stored_ty as epee_encoding::EpeeValue>::epee_default_value() },
+        };
+        let value = quote! {
+            self.#ident
+                .or_else(|| #default)
+                .ok_or(epee_encoding::Error::Format("Required field was not in data"))?
+        };
+
+        if field.try_from_into.is_some() {
+            finish_fields.push(quote! {
+                #ident: <#ty as ::core::convert::TryFrom<#stored_ty>>::try_from(#value)
+                    .map_err(|_| epee_encoding::Error::Value("Failed to convert field"))?
+            });
+            count_fields.push(quote! { 1 });
+            write_fields.push(quote! {
+                let value = <#stored_ty as ::core::convert::TryFrom<#ty>>::try_from(self.#ident.clone())
+                    .map_err(|_| epee_encoding::Error::Value("Failed to convert field"))?;
+                epee_encoding::write_field(&value, #name, w)?;
+            });
+        } else {
+            finish_fields.push(quote! { #ident: #value });
+            count_fields.push(quote! {
+                u64::from(epee_encoding::EpeeValue::should_write(&self.#ident))
+            });
+            write_fields.push(quote! {
+                epee_encoding::write_field(&self.#ident, #name, w)?;
+            });
+        }
+    }
+
+    Ok(quote! {
+        #[doc(hidden)]
+        #[derive(Default)]
+        #vis struct #builder_name {
+            #(#builder_fields,)*
+        }
+
+        impl epee_encoding::EpeeObjectBuilder<#struct_name> for #builder_name {
+            fn add_field<R: epee_encoding::io::Read>(
+                &mut self,
+                name: &str,
+                r: &mut R,
+            ) -> epee_encoding::Result<bool> {
+                Ok(match name {
+                    #(#add_field_arms)*
+                    _ => {
+                        #(#add_flattened_fields)*
+                        false
+                    }
+                })
+            }
+
+            fn finish(self) -> epee_encoding::Result<#struct_name> {
+                Ok(#struct_name {
+                    #(#finish_fields,)*
+                })
+            }
+        }
+
+        impl epee_encoding::EpeeObject for #struct_name {
+            type Builder = #builder_name;
+
+            fn number_of_fields(&self) -> u64 {
+                0 #(+ #count_fields)*
+            }
+
+            fn write_fields<W: epee_encoding::io::Write>(
+                &self,
+                w: &mut W,
+            ) -> epee_encoding::Result<()> {
+                #(#write_fields)*
+                Ok(())
+            }
+        }
+    })
+}
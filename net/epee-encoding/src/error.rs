@@ -0,0 +1,29 @@
+//! The epee-encoding error type.
+
+use core::fmt::{Display, Formatter};
+
+/// Possible errors when (de)serializing epee data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The data ended early or there was no space left to write to.
+    IO(&'static str),
+    /// The data is not in the epee format or breaks one of our limits.
+    Format(&'static str),
+    /// The data is valid epee but a value was not valid for its type.
+    Value(&'static str),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::IO(e) => write!(f, "IO error: {e}"),
+            Error::Format(e) => write!(f, "Format error: {e}"),
+            Error::Value(e) => write!(f, "Value error: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+pub type Result<T> = core::result::Result<T, Error>;
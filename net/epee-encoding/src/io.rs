@@ -0,0 +1,103 @@
+//! The readers and writers epee data is (de)serialized with.
+
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::error::{Error, Result};
+use crate::MAX_OBJECT_DEPTH;
+
+/// A source of epee data.
+pub trait Read {
+    /// Fills `buf` with the next bytes of the data.
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()>;
+
+    /// Reads the next `len` bytes of the data, this does not copy when the reader is backed by
+    /// [`Bytes`].
+    fn read_bytes(&mut self, len: usize) -> Result<Bytes>;
+
+    /// Skips the next `len` bytes of the data.
+    fn skip(&mut self, len: usize) -> Result<()>;
+
+    /// Returns the amount of bytes left in the data.
+    fn remaining(&self) -> usize;
+
+    /// Called before reading a nested object, returns an error if the object would go over
+    /// [`MAX_OBJECT_DEPTH`].
+    fn enter_object(&mut self) -> Result<()>;
+
+    /// Called after reading a nested object.
+    fn exit_object(&mut self);
+}
+
+/// A [`Read`] implementation over a [`Buf`].
+pub struct Reader<B> {
+    buf: B,
+    depth: usize,
+}
+
+impl<B: Buf> Reader<B> {
+    pub fn new(buf: B) -> Self {
+        Reader { buf, depth: 0 }
+    }
+
+    pub fn into_inner(self) -> B {
+        self.buf
+    }
+
+    fn check_remaining(&self, len: usize) -> Result<()> {
+        if self.buf.remaining() < len {
+            return Err(Error::IO("Not enough bytes left in the data"));
+        }
+        Ok(())
+    }
+}
+
+impl<B: Buf> Read for Reader<B> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.check_remaining(buf.len())?;
+        self.buf.copy_to_slice(buf);
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<Bytes> {
+        self.check_remaining(len)?;
+        Ok(self.buf.copy_to_bytes(len))
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        self.check_remaining(len)?;
+        self.buf.advance(len);
+        Ok(())
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.remaining()
+    }
+
+    fn enter_object(&mut self) -> Result<()> {
+        if self.depth >= MAX_OBJECT_DEPTH {
+            return Err(Error::Format("Objects are nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn exit_object(&mut self) {
+        self.depth -= 1;
+    }
+}
+
+/// A destination for epee data.
+pub trait Write {
+    /// Writes all of `buf`.
+    fn write_all(&mut self, buf: &[u8]) -> Result<()>;
+}
+
+impl<B: BufMut> Write for B {
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        if self.remaining_mut() < buf.len() {
+            return Err(Error::IO("Not enough space left to write the data"));
+        }
+        self.put_slice(buf);
+        Ok(())
+    }
+}
@@ -0,0 +1,453 @@
+// Rust Epee Encoding Library
+// Written in 2023 by
+//   Cuprate Contributors
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+
+//! # Epee Encoding
+//!
+//! A crate for (de)serializing the epee binary format, the format Monero uses for its p2p messages
+//! and binary RPC calls.
+//!
+//! Types that are epee objects implement [`EpeeObject`], which can be derived:
+//!
+//! ```rust
+//! use epee_encoding::{from_bytes, to_bytes, EpeeObject};
+//!
+//! #[derive(EpeeObject, Debug, PartialEq)]
+//! struct Ping {
+//!     status: String,
+//!     #[epee_default(0)]
+//!     peer_id: u64,
+//! }
+//!
+//! let ping = Ping { status: "OK".to_string(), peer_id: 5 };
+//! let bytes = to_bytes(&ping).unwrap();
+//! assert_eq!(from_bytes::<Ping>(&bytes).unwrap(), ping);
+//! ```
+//!
+//! The derive supports these field attributes:
+//! - `#[epee_default(expr)]`: the value to use if the field is not in the data.
+//! - `#[epee_alt_name("name")]`: the name of the field in the data.
+//! - `#[epee_try_from_into(ty)]`: (de)serialize the field as `ty`, converting with [`TryFrom`].
+//! - `#[epee_flatten]`: the fields of this (object) field are part of the outer object.
+//!
+//! ## Limits
+//!
+//! The data is untrusted so decoding is strict: objects can only be nested [`MAX_OBJECT_DEPTH`]
+//! deep, and strings, sequences and objects can't claim more items than there are bytes left in
+//! the data, so a small message can't make us allocate a lot of memory.
+//!
+//! Byte strings read into [`bytes::Bytes`] are not copied when decoding with [`from_buf`] from
+//! [`bytes::Bytes`].
+//!
+//! ## Features
+//!
+//! - `std` (default): implements [`std::error::Error`] for [`Error`]. Without it the crate is
+//!   `no_std`, it still needs an allocator.
+//!
+//! ## License
+//!
+//! This project is licensed under the MIT License.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+// Coding conventions
+#![forbid(unsafe_code)]
+#![deny(non_upper_case_globals)]
+#![deny(non_camel_case_types)]
+#![deny(unused_mut)]
+//#![deny(missing_docs)]
+
+extern crate alloc;
+
+// Lets the derive macro refer to this crate by name from inside it.
+extern crate self as epee_encoding;
+
+pub mod error;
+pub mod io;
+pub mod marker;
+mod value;
+mod varint;
+
+pub use epee_encoding_derive::EpeeObject;
+pub use error::{Error, Result};
+pub use value::EpeeValue;
+pub use varint::{read_varint, write_varint};
+
+use alloc::vec::Vec;
+
+use bytes::Buf;
+
+use io::{Read, Reader, Write};
+use marker::{InnerMarker, Marker};
+
+/// The header at the start of all epee data: the two signatures and the format version.
+const HEADER: &[u8] = &[0x01, 0x11, 0x01, 0x01, 0x01, 0x01, 0x02, 0x01, 0x01];
+
+/// The maximum depth objects can be nested, the outer object counts as 1.
+pub const MAX_OBJECT_DEPTH: usize = 100;
+
+/// A type that can be (de)serialized as an epee object.
+pub trait EpeeObject: Sized {
+    type Builder: EpeeObjectBuilder<Self>;
+
+    /// Returns the number of fields that will be written.
+    fn number_of_fields(&self) -> u64;
+
+    /// Writes every field of the object.
+    fn write_fields<W: Write>(&self, w: &mut W) -> Result<()>;
+}
+
+/// A builder that an [`EpeeObject`] is built with when deserializing.
+pub trait EpeeObjectBuilder<T>: Default + Sized {
+    /// Reads the value of the field `name`, returning `false` if this object does not have the
+    /// field. The value is only read if `true` is returned.
+    fn add_field<R: Read>(&mut self, name: &str, r: &mut R) -> Result<bool>;
+
+    /// Builds the object from the fields read.
+    fn finish(self) -> Result<T>;
+}
+
+/// Deserializes an object from epee data.
+pub fn from_bytes<T: EpeeObject>(buf: &[u8]) -> Result<T> {
+    from_buf(buf)
+}
+
+/// Deserializes an object from epee data in a [`Buf`].
+pub fn from_buf<T: EpeeObject, B: Buf>(buf: B) -> Result<T> {
+    let mut r = Reader::new(buf);
+
+    let mut header = [0; HEADER.len()];
+    r.read_exact(&mut header)?;
+    if header != HEADER {
+        return Err(Error::Format("Data does not have a valid epee header"));
+    }
+
+    T::read(&mut r, &T::MARKER)
+}
+
+/// Serializes an object into epee data.
+pub fn to_bytes<T: EpeeObject>(val: &T) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    buf.write_all(HEADER)?;
+    write_object(val, &mut buf)?;
+    Ok(buf)
+}
+
+/// Reads a value marker.
+pub fn read_marker<R: Read>(r: &mut R) -> Result<Marker> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Marker::try_from(buf[0])
+}
+
+/// Reads a value with its marker.
+pub fn read_epee_value<T: EpeeValue, R: Read>(r: &mut R) -> Result<T> {
+    let marker = read_marker(r)?;
+    T::read(r, &marker)
+}
+
+/// Writes a field, the field is left out if [`EpeeValue::should_write`] returns `false`.
+pub fn write_field<T: EpeeValue, W: Write>(val: &T, field_name: &str, w: &mut W) -> Result<()> {
+    if !val.should_write() {
+        return Ok(());
+    }
+
+    let name_len: u8 = field_name
+        .len()
+        .try_into()
+        .map_err(|_| Error::Value("Field name is longer than 255 bytes"))?;
+    w.write_all(&[name_len])?;
+    w.write_all(field_name.as_bytes())?;
+
+    w.write_all(&[T::MARKER.as_u8()])?;
+    val.write(w)
+}
+
+/// Reads the fields of an object, the caller must track the object's depth.
+fn read_object<T: EpeeObject, R: Read>(r: &mut R) -> Result<T> {
+    let number_of_fields = read_varint(r)?;
+    if number_of_fields > r.remaining() as u64 {
+        return Err(Error::Format(
+            "Object has more fields than the remaining data",
+        ));
+    }
+
+    let mut builder = T::Builder::default();
+    let mut name_buf = [0; 255];
+    for _ in 0..number_of_fields {
+        let name = read_field_name(r, &mut name_buf)?;
+        if !builder.add_field(name, r)? {
+            skip_epee_value(r)?;
+        }
+    }
+
+    builder.finish()
+}
+
+fn write_object<T: EpeeObject, W: Write>(val: &T, w: &mut W) -> Result<()> {
+    write_varint(val.number_of_fields(), w)?;
+    val.write_fields(w)
+}
+
+fn read_field_name<'a, R: Read>(r: &mut R, buf: &'a mut [u8; 255]) -> Result<&'a str> {
+    let mut len = [0; 1];
+    r.read_exact(&mut len)?;
+
+    let name = &mut buf[..len[0].into()];
+    r.read_exact(name)?;
+    core::str::from_utf8(name).map_err(|_| Error::Value("Field name was not valid UTF-8"))
+}
+
+/// Skips a value we don't know, with its marker.
+fn skip_epee_value<R: Read>(r: &mut R) -> Result<()> {
+    let marker = read_marker(r)?;
+
+    if marker.is_seq {
+        let len = read_varint(r)?;
+        if len > r.remaining() as u64 {
+            return Err(Error::Format("Sequence is longer than the remaining data"));
+        }
+        for _ in 0..len {
+            skip_inner_value(r, marker.inner_marker)?;
+        }
+        Ok(())
+    } else {
+        skip_inner_value(r, marker.inner_marker)
+    }
+}
+
+fn skip_inner_value<R: Read>(r: &mut R, marker: InnerMarker) -> Result<()> {
+    if let Some(size) = marker.size() {
+        return r.skip(size);
+    }
+
+    match marker {
+        InnerMarker::String => {
+            let len = read_varint(r)?;
+            r.skip(
+                len.try_into()
+                    .map_err(|_| Error::Format("String is longer than the remaining data"))?,
+            )
+        }
+        InnerMarker::Object => {
+            r.enter_object()?;
+            let res = skip_object(r);
+            r.exit_object();
+            res
+        }
+        _ => unreachable!("Every other marker has a fixed size"),
+    }
+}
+
+fn skip_object<R: Read>(r: &mut R) -> Result<()> {
+    let number_of_fields = read_varint(r)?;
+    let mut name_buf = [0; 255];
+    for _ in 0..number_of_fields {
+        read_field_name(r, &mut name_buf)?;
+        skip_epee_value(r)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[derive(EpeeObject, Debug, Clone, PartialEq)]
+    struct Inner {
+        numbers: Vec<u64>,
+        #[epee_default(Vec::new())]
+        hashes: Vec<[u8; 32]>,
+    }
+
+    #[derive(EpeeObject, Debug, Clone, PartialEq)]
+    struct Flattened {
+        #[epee_default(0)]
+        flattened_field: u16,
+    }
+
+    #[derive(EpeeObject, Debug, Clone, PartialEq)]
+    struct Outer {
+        a: u8,
+        #[epee_alt_name("b_alt")]
+        b: i64,
+        #[epee_default(true)]
+        c: bool,
+        #[epee_try_from_into(u32)]
+        d: u64,
+        inner: Inner,
+        inners: Vec<Inner>,
+        name: String,
+        blob: Bytes,
+        optional: Option<f64>,
+        #[epee_flatten]
+        flattened: Flattened,
+    }
+
+    fn outer() -> Outer {
+        Outer {
+            a: 1,
+            b: -2,
+            c: false,
+            d: 3,
+            inner: Inner {
+                numbers: vec![4, 5],
+                hashes: vec![[6; 32], [7; 32]],
+            },
+            inners: vec![
+                Inner {
+                    numbers: vec![8],
+                    hashes: vec![],
+                },
+                Inner {
+                    numbers: vec![],
+                    hashes: vec![[9; 32]],
+                },
+            ],
+            name: "name".to_string(),
+            blob: Bytes::from_static(&[10, 11, 12]),
+            optional: Some(13.5),
+            flattened: Flattened {
+                flattened_field: 14,
+            },
+        }
+    }
+
+    /// Builds epee data for an object with `fields` fields, from the data after the field count.
+    fn object_bytes(fields: u8, body: &[u8]) -> Vec<u8> {
+        let mut bytes = HEADER.to_vec();
+        write_varint(fields.into(), &mut bytes).unwrap();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let outer = outer();
+        let bytes = to_bytes(&outer).unwrap();
+        assert_eq!(from_bytes::<Outer>(&bytes).unwrap(), outer);
+
+        let mut outer = outer;
+        outer.optional = None;
+        outer.inner.numbers.clear();
+        let bytes = to_bytes(&outer).unwrap();
+        assert_eq!(from_bytes::<Outer>(&bytes).unwrap(), outer);
+    }
+
+    #[test]
+    fn alt_name_and_flatten_are_used_on_the_wire() {
+        let bytes = to_bytes(&outer()).unwrap();
+        let contains = |name: &[u8]| bytes.windows(name.len()).any(|w| w == name);
+
+        assert!(contains(b"b_alt"));
+        assert!(contains(b"flattened_field"));
+        assert!(!contains(b"flattened\x0c"));
+    }
+
+    #[test]
+    fn defaults_are_used_for_missing_fields() {
+        // {"numbers": [1]}
+        let bytes = object_bytes(1, b"\x07numbers\x85\x04\x01\0\0\0\0\0\0\0");
+        let inner: Inner = from_bytes(&bytes).unwrap();
+        assert_eq!(inner.numbers, vec![1]);
+        assert!(inner.hashes.is_empty());
+
+        // An empty object is missing the required field `a`.
+        assert_eq!(
+            from_bytes::<Flattened>(&object_bytes(0, &[])).unwrap(),
+            Flattened { flattened_field: 0 }
+        );
+        assert!(from_bytes::<Outer>(&object_bytes(0, &[])).is_err());
+    }
+
+    #[test]
+    fn unknown_fields_are_skipped() {
+        // {"x": {"y": ["a"]}, "flattened_field": 2}
+        let bytes = object_bytes(
+            2,
+            b"\x01x\x0c\x04\x01y\x8a\x04\x04a\x0fflattened_field\x07\x02\0",
+        );
+        assert_eq!(
+            from_bytes::<Flattened>(&bytes).unwrap(),
+            Flattened { flattened_field: 2 }
+        );
+    }
+
+    #[test]
+    fn duplicate_fields_are_rejected() {
+        let bytes = object_bytes(
+            2,
+            b"\x0fflattened_field\x07\x02\0\x0fflattened_field\x07\x02\0",
+        );
+        assert!(from_bytes::<Flattened>(&bytes).is_err());
+    }
+
+    #[test]
+    fn wrong_marker_is_rejected() {
+        // flattened_field as a u32.
+        let bytes = object_bytes(1, b"\x0fflattened_field\x06\x02\0\0\0");
+        assert!(from_bytes::<Flattened>(&bytes).is_err());
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let nested = |depth: usize| {
+            // {"x": {"x": {... {}}}}
+            let mut body = Vec::new();
+            for i in 1..=depth {
+                body.extend_from_slice(b"\x01x\x0c");
+                body.push(if i == depth { 0 } else { 4 });
+            }
+            object_bytes(1, &body)
+        };
+
+        assert!(from_bytes::<Flattened>(&nested(MAX_OBJECT_DEPTH - 2)).is_ok());
+        assert_eq!(
+            from_bytes::<Flattened>(&nested(MAX_OBJECT_DEPTH)),
+            Err(Error::Format("Objects are nested too deeply"))
+        );
+    }
+
+    #[test]
+    fn lengths_longer_than_the_data_are_rejected() {
+        // {"numbers": [u64; 2^22]}
+        let bytes = object_bytes(1, b"\x07numbers\x85\x02\0\0\x01");
+        assert_eq!(
+            from_bytes::<Inner>(&bytes),
+            Err(Error::Format("Length is longer than the remaining data"))
+        );
+
+        // An object claiming 2^30 fields.
+        let mut bytes = HEADER.to_vec();
+        write_varint(1 << 30, &mut bytes).unwrap();
+        assert!(from_bytes::<Inner>(&bytes).is_err());
+    }
+
+    #[test]
+    fn byte_fields_are_not_copied_from_bytes() {
+        let data = Bytes::from(to_bytes(&outer()).unwrap());
+        let outer: Outer = from_buf(data.clone()).unwrap();
+
+        let data_range = data.as_ptr_range();
+        assert!(data_range.contains(&outer.blob.as_ptr()));
+    }
+
+    #[test]
+    fn invalid_header_is_rejected() {
+        let mut bytes = to_bytes(&outer()).unwrap();
+        bytes[0] = 0;
+        assert!(from_bytes::<Outer>(&bytes).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! The type markers written before every epee value.
+
+use crate::error::{Error, Result};
+
+/// The flag set on a marker when the value is a sequence.
+const SEQ_FLAG: u8 = 0x80;
+
+/// The type of a single epee value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InnerMarker {
+    I64,
+    I32,
+    I16,
+    I8,
+    U64,
+    U32,
+    U16,
+    U8,
+    F64,
+    String,
+    Bool,
+    Object,
+}
+
+impl InnerMarker {
+    pub const fn as_u8(&self) -> u8 {
+        match self {
+            InnerMarker::I64 => 1,
+            InnerMarker::I32 => 2,
+            InnerMarker::I16 => 3,
+            InnerMarker::I8 => 4,
+            InnerMarker::U64 => 5,
+            InnerMarker::U32 => 6,
+            InnerMarker::U16 => 7,
+            InnerMarker::U8 => 8,
+            InnerMarker::F64 => 9,
+            InnerMarker::String => 10,
+            InnerMarker::Bool => 11,
+            InnerMarker::Object => 12,
+        }
+    }
+
+    pub fn from_u8(marker: u8) -> Option<InnerMarker> {
+        Some(match marker {
+            1 => InnerMarker::I64,
+            2 => InnerMarker::I32,
+            3 => InnerMarker::I16,
+            4 => InnerMarker::I8,
+            5 => InnerMarker::U64,
+            6 => InnerMarker::U32,
+            7 => InnerMarker::U16,
+            8 => InnerMarker::U8,
+            9 => InnerMarker::F64,
+            10 => InnerMarker::String,
+            11 => InnerMarker::Bool,
+            12 => InnerMarker::Object,
+            _ => return None,
+        })
+    }
+
+    /// Returns the size of a value of this type, or [`None`] if the size is not fixed.
+    pub const fn size(&self) -> Option<usize> {
+        match self {
+            InnerMarker::I64 | InnerMarker::U64 | InnerMarker::F64 => Some(8),
+            InnerMarker::I32 | InnerMarker::U32 => Some(4),
+            InnerMarker::I16 | InnerMarker::U16 => Some(2),
+            InnerMarker::I8 | InnerMarker::U8 | InnerMarker::Bool => Some(1),
+            InnerMarker::String | InnerMarker::Object => None,
+        }
+    }
+}
+
+/// An epee type marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Marker {
+    pub inner_marker: InnerMarker,
+    pub is_seq: bool,
+}
+
+impl Marker {
+    pub const fn new(inner_marker: InnerMarker) -> Marker {
+        Marker {
+            inner_marker,
+            is_seq: false,
+        }
+    }
+
+    /// Returns the marker for a sequence of this marker's type.
+    pub const fn into_seq(self) -> Marker {
+        Marker {
+            inner_marker: self.inner_marker,
+            is_seq: true,
+        }
+    }
+
+    pub const fn as_u8(&self) -> u8 {
+        if self.is_seq {
+            self.inner_marker.as_u8() | SEQ_FLAG
+        } else {
+            self.inner_marker.as_u8()
+        }
+    }
+}
+
+impl TryFrom<u8> for Marker {
+    type Error = Error;
+
+    fn try_from(marker: u8) -> Result<Self> {
+        let inner_marker = InnerMarker::from_u8(marker & !SEQ_FLAG)
+            .ok_or(Error::Format("Unknown value marker"))?;
+
+        Ok(Marker {
+            inner_marker,
+            is_seq: marker & SEQ_FLAG != 0,
+        })
+    }
+}
@@ -0,0 +1,334 @@
+//! The [`EpeeValue`] trait and its implementations for the types epee supports.
+
+use alloc::{string::String, vec, vec::Vec};
+
+use bytes::Bytes;
+
+use crate::error::{Error, Result};
+use crate::io::{Read, Write};
+use crate::marker::{InnerMarker, Marker};
+use crate::varint::{read_varint, write_varint};
+use crate::{read_object, write_object, EpeeObject};
+
+/// A type that can be stored as an epee value.
+pub trait EpeeValue: Sized {
+    const MARKER: Marker;
+
+    /// Reads the value, `marker` is the marker that was read before the value.
+    fn read<R: Read>(r: &mut R, marker: &Marker) -> Result<Self>;
+
+    /// Returns if this value should be written, fields with values that should not be written are
+    /// left out of the object.
+    fn should_write(&self) -> bool {
+        true
+    }
+
+    /// The value to use when the field is not in the data, [`None`] means the field is required.
+    fn epee_default_value() -> Option<Self> {
+        None
+    }
+
+    /// Writes the value, without the marker.
+    fn write<W: Write>(&self, w: &mut W) -> Result<()>;
+}
+
+fn check_marker<T: EpeeValue>(marker: &Marker) -> Result<()> {
+    if marker != &T::MARKER {
+        return Err(Error::Format("Marker does not match expected Marker"));
+    }
+    Ok(())
+}
+
+/// Reads the length of a string or sequence, making sure the data could hold it.
+///
+/// Every item takes at least 1 byte so this stops us allocating space for more items than the data
+/// could hold.
+fn read_len<R: Read>(r: &mut R) -> Result<usize> {
+    let len = read_varint(r)?;
+    if len > r.remaining() as u64 {
+        return Err(Error::Format("Length is longer than the remaining data"));
+    }
+    Ok(len as usize)
+}
+
+fn write_len<W: Write>(len: usize, w: &mut W) -> Result<()> {
+    write_varint(len as u64, w)
+}
+
+macro_rules! epee_numb {
+    ($numb:ty, $marker:ident) => {
+        impl EpeeValue for $numb {
+            const MARKER: Marker = Marker::new(InnerMarker::$marker);
+
+            fn read<R: Read>(r: &mut R, marker: &Marker) -> Result<Self> {
+                check_marker::<Self>(marker)?;
+
+                let mut buf = [0; core::mem::size_of::<$numb>()];
+                r.read_exact(&mut buf)?;
+                Ok(<$numb>::from_le_bytes(buf))
+            }
+
+            fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+                w.write_all(&self.to_le_bytes())
+            }
+        }
+    };
+}
+
+epee_numb!(i64, I64);
+epee_numb!(i32, I32);
+epee_numb!(i16, I16);
+epee_numb!(i8, I8);
+epee_numb!(u64, U64);
+epee_numb!(u32, U32);
+epee_numb!(u16, U16);
+epee_numb!(u8, U8);
+epee_numb!(f64, F64);
+
+impl EpeeValue for bool {
+    const MARKER: Marker = Marker::new(InnerMarker::Bool);
+
+    fn read<R: Read>(r: &mut R, marker: &Marker) -> Result<Self> {
+        check_marker::<Self>(marker)?;
+
+        let mut buf = [0; 1];
+        r.read_exact(&mut buf)?;
+        match buf[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Value("Bool was not 0 or 1")),
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&[u8::from(*self)])
+    }
+}
+
+impl EpeeValue for Vec<u8> {
+    const MARKER: Marker = Marker::new(InnerMarker::String);
+
+    fn read<R: Read>(r: &mut R, marker: &Marker) -> Result<Self> {
+        check_marker::<Self>(marker)?;
+
+        let len = read_len(r)?;
+        let mut buf = vec![0; len];
+        r.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn should_write(&self) -> bool {
+        !self.is_empty()
+    }
+
+    fn epee_default_value() -> Option<Self> {
+        Some(Vec::new())
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_len(self.len(), w)?;
+        w.write_all(self)
+    }
+}
+
+/// Byte strings read into [`Bytes`] are not copied when the data is also [`Bytes`].
+impl EpeeValue for Bytes {
+    const MARKER: Marker = Marker::new(InnerMarker::String);
+
+    fn read<R: Read>(r: &mut R, marker: &Marker) -> Result<Self> {
+        check_marker::<Self>(marker)?;
+
+        let len = read_len(r)?;
+        r.read_bytes(len)
+    }
+
+    fn should_write(&self) -> bool {
+        !self.is_empty()
+    }
+
+    fn epee_default_value() -> Option<Self> {
+        Some(Bytes::new())
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_len(self.len(), w)?;
+        w.write_all(self)
+    }
+}
+
+impl EpeeValue for String {
+    const MARKER: Marker = Marker::new(InnerMarker::String);
+
+    fn read<R: Read>(r: &mut R, marker: &Marker) -> Result<Self> {
+        String::from_utf8(Vec::<u8>::read(r, marker)?)
+            .map_err(|_| Error::Value("String was not valid UTF-8"))
+    }
+
+    fn should_write(&self) -> bool {
+        !self.is_empty()
+    }
+
+    fn epee_default_value() -> Option<Self> {
+        Some(String::new())
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_len(self.len(), w)?;
+        w.write_all(self.as_bytes())
+    }
+}
+
+/// Fixed size byte arrays are stored as strings that must be exactly `N` bytes long.
+impl<const N: usize> EpeeValue for [u8; N] {
+    const MARKER: Marker = Marker::new(InnerMarker::String);
+
+    fn read<R: Read>(r: &mut R, marker: &Marker) -> Result<Self> {
+        check_marker::<Self>(marker)?;
+
+        if read_len(r)? != N {
+            return Err(Error::Value("Byte array was not the expected length"));
+        }
+
+        let mut buf = [0; N];
+        r.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_len(N, w)?;
+        w.write_all(self)
+    }
+}
+
+/// Lists of byte arrays are stored as a single string of every array, like monerod does for its
+/// lists of hashes.
+impl<const N: usize> EpeeValue for Vec<[u8; N]> {
+    const MARKER: Marker = Marker::new(InnerMarker::String);
+
+    fn read<R: Read>(r: &mut R, marker: &Marker) -> Result<Self> {
+        check_marker::<Self>(marker)?;
+
+        let len = read_len(r)?;
+        if N == 0 || len % N != 0 {
+            return Err(Error::Value(
+                "Byte string is not a multiple of the array length",
+            ));
+        }
+
+        let mut res = vec![[0; N]; len / N];
+        for arr in res.iter_mut() {
+            r.read_exact(arr)?;
+        }
+        Ok(res)
+    }
+
+    fn should_write(&self) -> bool {
+        !self.is_empty()
+    }
+
+    fn epee_default_value() -> Option<Self> {
+        Some(Vec::new())
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_len(self.len() * N, w)?;
+        for arr in self {
+            w.write_all(arr)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: EpeeObject> EpeeValue for T {
+    const MARKER: Marker = Marker::new(InnerMarker::Object);
+
+    fn read<R: Read>(r: &mut R, marker: &Marker) -> Result<Self> {
+        check_marker::<Self>(marker)?;
+
+        r.enter_object()?;
+        let res = read_object(r);
+        r.exit_object();
+        res
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_object(self, w)
+    }
+}
+
+impl<T: EpeeValue> EpeeValue for Option<T> {
+    const MARKER: Marker = T::MARKER;
+
+    fn read<R: Read>(r: &mut R, marker: &Marker) -> Result<Self> {
+        Ok(Some(T::read(r, marker)?))
+    }
+
+    fn should_write(&self) -> bool {
+        self.as_ref().is_some_and(T::should_write)
+    }
+
+    fn epee_default_value() -> Option<Self> {
+        Some(None)
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        match self {
+            Some(value) => value.write(w),
+            None => Err(Error::Value("Can't write a value that is not present")),
+        }
+    }
+}
+
+macro_rules! epee_seq {
+    ($($(#[$attr:meta])* impl EpeeValue for Vec<$val:ty> $(where $bound:ident: $bound_trait:path)?;)*) => {$(
+        $(#[$attr])*
+        impl$(<$bound: $bound_trait>)? EpeeValue for Vec<$val> {
+            const MARKER: Marker = <$val>::MARKER.into_seq();
+
+            fn read<R: Read>(r: &mut R, marker: &Marker) -> Result<Self> {
+                check_marker::<Self>(marker)?;
+
+                let len = read_len(r)?;
+                let item_marker = <$val>::MARKER;
+
+                let mut res = Vec::with_capacity(len);
+                for _ in 0..len {
+                    res.push(<$val>::read(r, &item_marker)?);
+                }
+                Ok(res)
+            }
+
+            fn should_write(&self) -> bool {
+                !self.is_empty()
+            }
+
+            fn epee_default_value() -> Option<Self> {
+                Some(Vec::new())
+            }
+
+            fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+                write_len(self.len(), w)?;
+                for item in self {
+                    item.write(w)?;
+                }
+                Ok(())
+            }
+        }
+    )*};
+}
+
+epee_seq! {
+    impl EpeeValue for Vec<i64>;
+    impl EpeeValue for Vec<i32>;
+    impl EpeeValue for Vec<i16>;
+    impl EpeeValue for Vec<i8>;
+    impl EpeeValue for Vec<u64>;
+    impl EpeeValue for Vec<u32>;
+    impl EpeeValue for Vec<u16>;
+    impl EpeeValue for Vec<f64>;
+    impl EpeeValue for Vec<bool>;
+    impl EpeeValue for Vec<String>;
+    impl EpeeValue for Vec<Vec<u8>>;
+    impl EpeeValue for Vec<Bytes>;
+    impl EpeeValue for Vec<T> where T: EpeeObject;
+}
@@ -0,0 +1,65 @@
+//! Epee varints.
+//!
+//! The lowest 2 bits of the first byte give the size of the varint: 1, 2, 4 or 8 bytes, the rest
+//! of the bits hold the number in little endian.
+
+use crate::error::{Error, Result};
+use crate::io::{Read, Write};
+
+const SIZE_MASK: u8 = 0b11;
+
+/// The largest number that fits in a varint.
+pub const MAX_VARINT: u64 = (1 << 62) - 1;
+
+pub fn read_varint<R: Read>(r: &mut R) -> Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf[..1])?;
+
+    let len = 1 << (buf[0] & SIZE_MASK);
+    r.read_exact(&mut buf[1..len])?;
+
+    Ok(u64::from_le_bytes(buf) >> 2)
+}
+
+pub fn write_varint<W: Write>(number: u64, w: &mut W) -> Result<()> {
+    let (size_marker, len) = match number {
+        0..=63 => (0, 1),
+        64..=16_383 => (1, 2),
+        16_384..=1_073_741_823 => (2, 4),
+        1_073_741_824..=MAX_VARINT => (3, 8),
+        _ => return Err(Error::Value("Number is too large to fit in a varint")),
+    };
+
+    let buf = ((number << 2) | size_marker).to_le_bytes();
+    w.write_all(&buf[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::Reader;
+
+    #[test]
+    fn varint_round_trip() {
+        for (number, len) in [
+            (0, 1),
+            (63, 1),
+            (64, 2),
+            (16_383, 2),
+            (16_384, 4),
+            (1_073_741_823, 4),
+            (1_073_741_824, 8),
+            (MAX_VARINT, 8),
+        ] {
+            let mut buf = Vec::new();
+            write_varint(number, &mut buf).unwrap();
+            assert_eq!(buf.len(), len);
+            assert_eq!(
+                read_varint(&mut Reader::new(buf.as_slice())).unwrap(),
+                number
+            );
+        }
+
+        assert!(write_varint(MAX_VARINT + 1, &mut Vec::new()).is_err());
+    }
+}
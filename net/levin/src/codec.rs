@@ -13,12 +13,16 @@
 // copies or substantial portions of the Software.
 //
 
-//! A tokio-codec for levin buckets
+//! Codecs for levin buckets and messages.
+//!
+//! The codecs decode from and encode into [`BytesMut`] buffers and do no I/O themselves. With the
+//! `tokio` feature they also implement the tokio-util [`Decoder`] and [`Encoder`] traits, so they
+//! can be used with `Framed` streams.
 
-use std::io::ErrorKind;
-use std::marker::PhantomData;
+use core::marker::PhantomData;
 
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{BufMut, BytesMut};
+#[cfg(feature = "tokio")]
 use tokio_util::codec::{Decoder, Encoder};
 
 use crate::{
@@ -26,7 +30,7 @@ use crate::{
     LEVIN_DEFAULT_MAX_PACKET_SIZE,
 };
 
-/// The levin tokio-codec for decoding and encoding levin buckets
+/// The levin codec for decoding and encoding levin buckets
 #[derive(Default)]
 pub enum LevinCodec {
     /// Waiting for the peer to send a header.
@@ -36,10 +40,12 @@ pub enum LevinCodec {
     WaitingForBody(BucketHead),
 }
 
-impl Decoder for LevinCodec {
-    type Item = Bucket;
-    type Error = BucketError;
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+impl LevinCodec {
+    /// Decodes the next bucket in `src`, returning `None` if `src` does not contain all of it yet.
+    ///
+    /// The decoded bytes are removed from `src`, call this again with the same `src` once more data
+    /// has been added to it.
+    pub fn decode_bucket(&mut self, src: &mut BytesMut) -> Result<Option<Bucket>, BucketError> {
         loop {
             match self {
                 LevinCodec::WaitingForHeader => {
@@ -48,7 +54,7 @@ impl Decoder for LevinCodec {
                     };
 
                     let head = BucketHead::from_bytes(src)?;
-                    let _ = std::mem::replace(self, LevinCodec::WaitingForBody(head));
+                    let _ = core::mem::replace(self, LevinCodec::WaitingForBody(head));
                 }
                 LevinCodec::WaitingForBody(head) => {
                     // We size check header while decoding it.
@@ -62,32 +68,48 @@ impl Decoder for LevinCodec {
                     }
 
                     let LevinCodec::WaitingForBody(header) =
-                        std::mem::replace(self, LevinCodec::WaitingForHeader)
+                        core::mem::replace(self, LevinCodec::WaitingForHeader)
                     else {
                         unreachable!()
                     };
 
+                    // This does not copy the body, it splits it off the buffer.
                     return Ok(Some(Bucket {
                         header,
-                        body: src.copy_to_bytes(body_len).into(),
+                        body: src.split_to(body_len).freeze(),
                     }));
                 }
             }
         }
     }
+
+    /// Writes the bucket to `dst`.
+    pub fn encode_bucket(&mut self, item: Bucket, dst: &mut BytesMut) {
+        item.header.write_bytes(dst);
+        dst.put_slice(&item.body);
+    }
 }
 
+#[cfg(feature = "tokio")]
+impl Decoder for LevinCodec {
+    type Item = Bucket;
+    type Error = BucketError;
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode_bucket(src)
+    }
+}
+
+#[cfg(feature = "tokio")]
 impl Encoder<Bucket> for LevinCodec {
     type Error = BucketError;
     fn encode(&mut self, item: Bucket, dst: &mut BytesMut) -> Result<(), Self::Error> {
         if dst.capacity() < BucketHead::SIZE + item.body.len() {
             return Err(BucketError::IO(std::io::Error::new(
-                ErrorKind::OutOfMemory,
+                std::io::ErrorKind::OutOfMemory,
                 "Not enough capacity to write the bucket",
             )));
         }
-        item.header.write_bytes(dst);
-        dst.put_slice(&item.body);
+        self.encode_bucket(item, dst);
         Ok(())
     }
 }
@@ -96,10 +118,10 @@ impl Encoder<Bucket> for LevinCodec {
 enum MessageState {
     #[default]
     WaitingForBucket,
-    WaitingForRestOfFragment(Vec<u8>, MessageType, u32),
+    WaitingForRestOfFragment(BytesMut, MessageType, u32),
 }
 
-/// A tokio-codec for levin messages or in other words the decoded body
+/// A codec for levin messages or in other words the decoded body
 /// of a levin bucket.
 pub struct LevinMessageCodec<T> {
     message_ty: PhantomData<T>,
@@ -107,14 +129,26 @@ pub struct LevinMessageCodec<T> {
     state: MessageState,
 }
 
-impl<T: LevinBody> Decoder for LevinMessageCodec<T> {
-    type Item = T;
-    type Error = BucketError;
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+impl<T> Default for LevinMessageCodec<T> {
+    fn default() -> Self {
+        Self {
+            message_ty: Default::default(),
+            bucket_codec: Default::default(),
+            state: Default::default(),
+        }
+    }
+}
+
+impl<T: LevinBody> LevinMessageCodec<T> {
+    /// Decodes the next message in `src`, returning `None` if `src` does not contain all of it yet.
+    ///
+    /// Fragmented messages are put back together, so this can consume several buckets before
+    /// returning a message.
+    pub fn decode_message(&mut self, src: &mut BytesMut) -> Result<Option<T>, BucketError> {
         loop {
             match &mut self.state {
                 MessageState::WaitingForBucket => {
-                    let Some(bucket) = self.bucket_codec.decode(src)? else {
+                    let Some(bucket) = self.bucket_codec.decode_bucket(src)? else {
                         return Ok(None);
                     };
 
@@ -146,10 +180,10 @@ impl<T: LevinBody> Decoder for LevinMessageCodec<T> {
                     )?;
 
                     if start_fragment {
-                        let _ = std::mem::replace(
+                        let _ = core::mem::replace(
                             &mut self.state,
                             MessageState::WaitingForRestOfFragment(
-                                bucket.body.to_vec(),
+                                BytesMut::from(bucket.body.as_ref()),
                                 message_type,
                                 bucket.header.protocol_version,
                             ),
@@ -165,7 +199,7 @@ impl<T: LevinBody> Decoder for LevinMessageCodec<T> {
                     )?));
                 }
                 MessageState::WaitingForRestOfFragment(bytes, ty, command) => {
-                    let Some(bucket) = self.bucket_codec.decode(src)? else {
+                    let Some(bucket) = self.bucket_codec.decode_bucket(src)? else {
                         return Ok(None);
                     };
 
@@ -210,11 +244,11 @@ impl<T: LevinBody> Decoder for LevinMessageCodec<T> {
                         ));
                     }
 
-                    bytes.append(&mut bucket.body.to_vec());
+                    bytes.extend_from_slice(&bucket.body);
 
                     if end_fragment {
                         let MessageState::WaitingForRestOfFragment(bytes, ty, command) =
-                            std::mem::replace(&mut self.state, MessageState::WaitingForBucket)
+                            core::mem::replace(&mut self.state, MessageState::WaitingForBucket)
                         else {
                             unreachable!();
                         };
@@ -225,8 +259,27 @@ impl<T: LevinBody> Decoder for LevinMessageCodec<T> {
             }
         }
     }
+
+    /// Writes the message to `dst` as a single bucket.
+    pub fn encode_message(&mut self, item: T, dst: &mut BytesMut) -> Result<(), BucketError> {
+        let mut bucket_builder = BucketBuilder::default();
+        item.encode(&mut bucket_builder)?;
+        let bucket = bucket_builder.finish();
+        self.bucket_codec.encode_bucket(bucket, dst);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: LevinBody> Decoder for LevinMessageCodec<T> {
+    type Item = T;
+    type Error = BucketError;
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode_message(src)
+    }
 }
 
+#[cfg(feature = "tokio")]
 impl<T: LevinBody> Encoder<T> for LevinMessageCodec<T> {
     type Error = BucketError;
     fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
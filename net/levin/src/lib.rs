@@ -22,10 +22,20 @@
 //! header serialization and allows developers to define their own bucket bodies, for a complete
 //! monero protocol crate see: monero-wire.
 //!
+//! The framing in [`codec`] works on [`bytes::BytesMut`] buffers and does no I/O itself, so it can
+//! be used with any transport.
+//!
+//! ## Features
+//!
+//! - `std` (default): adds [`BucketError::IO`] and implements [`std::error::Error`] for
+//!   [`BucketError`]. Without it the crate is `no_std`, it still needs an allocator.
+//! - `tokio` (default): implements the tokio-util `Decoder` and `Encoder` traits for the codecs.
+//!
 //! ## License
 //!
 //! This project is licensed under the MIT License.
 
+#![cfg_attr(not(feature = "std"), no_std)]
 // Coding conventions
 #![forbid(unsafe_code)]
 #![deny(non_upper_case_globals)]
@@ -33,38 +43,69 @@
 #![deny(unused_mut)]
 //#![deny(missing_docs)]
 
+extern crate alloc;
+
 pub mod codec;
 pub mod header;
 
 pub use codec::LevinCodec;
 pub use header::BucketHead;
 
-use std::fmt::Debug;
+use alloc::boxed::Box;
+use core::fmt::{Debug, Display, Formatter};
 
-use thiserror::Error;
+use bytes::Bytes;
 
 const PROTOCOL_VERSION: u32 = 1;
 const LEVIN_SIGNATURE: u64 = 0x0101010101012101;
 const LEVIN_DEFAULT_MAX_PACKET_SIZE: u64 = 100_000_000; // 100MB
 
 /// Possible Errors when working with levin buckets
-#[derive(Error, Debug)]
+#[derive(Debug)]
 pub enum BucketError {
     /// Invalid header flags
-    #[error("Invalid header flags: {0}")]
     InvalidHeaderFlags(&'static str),
     /// Levin bucket exceeded max size
-    #[error("Levin bucket exceeded max size")]
     BucketExceededMaxSize,
     /// Invalid Fragmented Message
-    #[error("Levin fragmented message was invalid: {0}")]
     InvalidFragmentedMessage(&'static str),
     /// Error decoding the body
-    #[error("Error decoding bucket body")]
-    BodyDecodingError(Box<dyn Debug>),
+    BodyDecodingError(Box<dyn Debug + Send + Sync>),
     /// I/O error
-    #[error("I/O error: {0}")]
-    IO(#[from] std::io::Error),
+    #[cfg(feature = "std")]
+    IO(std::io::Error),
+}
+
+impl Display for BucketError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BucketError::InvalidHeaderFlags(e) => write!(f, "Invalid header flags: {e}"),
+            BucketError::BucketExceededMaxSize => write!(f, "Levin bucket exceeded max size"),
+            BucketError::InvalidFragmentedMessage(e) => {
+                write!(f, "Levin fragmented message was invalid: {e}")
+            }
+            BucketError::BodyDecodingError(_) => write!(f, "Error decoding bucket body"),
+            #[cfg(feature = "std")]
+            BucketError::IO(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BucketError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BucketError::IO(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for BucketError {
+    fn from(e: std::io::Error) -> Self {
+        BucketError::IO(e)
+    }
 }
 
 /// A levin Bucket
@@ -72,8 +113,8 @@ pub enum BucketError {
 pub struct Bucket {
     /// The bucket header
     pub header: BucketHead,
-    /// The bucket body
-    pub body: Vec<u8>,
+    /// The bucket body, this is a reference-counted slice of the buffer the bucket was read from.
+    pub body: Bytes,
 }
 
 /// An enum representing if the message is a request, response or notification.
@@ -135,7 +176,7 @@ pub struct BucketBuilder {
     command: Option<u32>,
     return_code: Option<i32>,
     protocol_version: Option<u32>,
-    body: Option<Vec<u8>>,
+    body: Option<Bytes>,
 }
 
 impl Default for BucketBuilder {
@@ -172,8 +213,8 @@ impl BucketBuilder {
         self.protocol_version = Some(version)
     }
 
-    pub fn set_body(&mut self, body: Vec<u8>) {
-        self.body = Some(body)
+    pub fn set_body(&mut self, body: impl Into<Bytes>) {
+        self.body = Some(body.into())
     }
 
     pub fn finish(self) -> Bucket {
@@ -0,0 +1,128 @@
+// Rust Levin Library
+// Written in 2023 by
+//   Cuprate Contributors
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+
+//! # Capture
+//!
+//! This module contains [`CapturingCodec`], a [`MoneroWireCodec`] that keeps the raw levin bytes
+//! of every message it decodes, so real payloads from monerod peers can be added to the wire
+//! corpus in `net/monero-wire/corpus`.
+//!
+//! The codec is only built with the `capture` feature, it copies every buffer it decodes so should
+//! not be used outside of recording a corpus.
+//!
+use std::{fs, io, path::Path};
+
+use bytes::BytesMut;
+use levin_cuprate::{BucketError, BucketHead};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{Message, MoneroWireCodec};
+
+/// The raw levin bytes of a decoded message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedFrame {
+    /// The command of the message.
+    pub command: u32,
+    /// The return code of the message, 0 for requests and notifications and >0 for responses.
+    pub return_code: i32,
+    /// The levin buckets of the message, headers included.
+    pub bytes: Vec<u8>,
+}
+
+impl CapturedFrame {
+    /// Returns the name the frame is saved under, the command and the kind of message.
+    pub fn name(&self) -> String {
+        let kind = if self.return_code == 0 {
+            "request"
+        } else {
+            "response"
+        };
+        format!("{}_{}", self.command, kind)
+    }
+}
+
+/// A [`MoneroWireCodec`] that records the bytes of every message it decodes.
+#[derive(Default)]
+pub struct CapturingCodec {
+    codec: MoneroWireCodec,
+    /// The bytes consumed since the last decoded message.
+    pending: Vec<u8>,
+    frames: Vec<CapturedFrame>,
+}
+
+impl CapturingCodec {
+    /// Returns the frames captured so far.
+    pub fn frames(&self) -> &[CapturedFrame] {
+        &self.frames
+    }
+
+    /// Takes the frames captured so far.
+    pub fn take_frames(&mut self) -> Vec<CapturedFrame> {
+        std::mem::take(&mut self.frames)
+    }
+
+    /// Decodes a message, recording the bytes it was decoded from.
+    pub fn decode_message(&mut self, src: &mut BytesMut) -> Result<Option<Message>, BucketError> {
+        let before = src.clone();
+        let message = self.codec.decode_message(src)?;
+
+        let consumed = before.len() - src.len();
+        self.pending.extend_from_slice(&before[..consumed]);
+
+        if message.is_some() {
+            let bytes = std::mem::take(&mut self.pending);
+            let head = BucketHead::from_bytes(&mut BytesMut::from(&bytes[..BucketHead::SIZE]))?;
+            self.frames.push(CapturedFrame {
+                command: head.command,
+                return_code: head.return_code,
+                bytes,
+            });
+        }
+
+        Ok(message)
+    }
+
+    /// Saves the frames captured so far to `dir` as hex files, in the format of the wire corpus.
+    ///
+    /// Files are named `<index>_<command>_<request|response>.hex`, the index starting from `first_index`
+    /// so several captures can be saved to one directory.
+    pub fn save(&mut self, dir: impl AsRef<Path>, first_index: usize) -> io::Result<()> {
+        fs::create_dir_all(dir.as_ref())?;
+        for (i, frame) in self.take_frames().into_iter().enumerate() {
+            let path = dir
+                .as_ref()
+                .join(format!("{:05}_{}.hex", first_index + i, frame.name()));
+            fs::write(path, hex::encode(&frame.bytes) + "\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl Decoder for CapturingCodec {
+    type Item = Message;
+    type Error = BucketError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode_message(src)
+    }
+}
+
+impl Encoder<Message> for CapturingCodec {
+    type Error = BucketError;
+
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.codec.encode_message(item, dst)
+    }
+}
@@ -0,0 +1,176 @@
+// Rust Levin Library
+// Written in 2023 by
+//   Cuprate Contributors
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+
+//! Tests over the wire corpus, the levin bytes of real messages sent by monerod peers.
+//!
+//! Our encoding of a message isn't byte for byte the same as monerod's (fields are written in a
+//! different order) so frames are checked to decode to the same message after a round trip.
+
+use std::{fs, path::Path};
+
+use bytes::BytesMut;
+use levin_cuprate::BucketHead;
+
+use crate::{Message, MoneroWireCodec, ProtocolMessage, RequestMessage, ResponseMessage};
+
+/// The main-net network ID.
+const MAINNET_NETWORK_ID: [u8; 16] = [
+    0x12, 0x30, 0xF1, 0x71, 0x61, 0x04, 0x41, 0x61, 0x17, 0x31, 0x00, 0x82, 0x16, 0xA1, 0xA1, 0x10,
+];
+
+fn corpus_dir() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/corpus"))
+}
+
+fn read_frame(name: &str) -> Vec<u8> {
+    let hex = fs::read_to_string(corpus_dir().join(name)).unwrap();
+    hex::decode(hex.trim()).unwrap()
+}
+
+/// Decodes a frame, checking the codec consumes all of it.
+fn decode(frame: &[u8]) -> Message {
+    let mut src = BytesMut::from(frame);
+    let message = MoneroWireCodec::default()
+        .decode_message(&mut src)
+        .unwrap()
+        .unwrap();
+    assert!(src.is_empty());
+    message
+}
+
+fn encode(message: Message) -> Vec<u8> {
+    let mut dst = BytesMut::new();
+    MoneroWireCodec::default()
+        .encode_message(message, &mut dst)
+        .unwrap();
+    dst.to_vec()
+}
+
+fn head(frame: &[u8]) -> BucketHead {
+    BucketHead::from_bytes(&mut BytesMut::from(&frame[..BucketHead::SIZE])).unwrap()
+}
+
+#[test]
+fn every_frame_round_trips() {
+    let mut frames = 0;
+    for entry in fs::read_dir(corpus_dir()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() != Some("hex".as_ref()) {
+            continue;
+        }
+        frames += 1;
+
+        let frame = read_frame(path.file_name().unwrap().to_str().unwrap());
+        let encoded = encode(decode(&frame));
+        // The message must keep its command and type...
+        let (original, ours) = (head(&frame), head(&encoded));
+        assert_eq!(original.command, ours.command, "{path:?}");
+        assert_eq!(original.return_code, ours.return_code, "{path:?}");
+        assert_eq!(original.flags, ours.flags, "{path:?}");
+        assert_eq!(
+            original.have_to_return_data, ours.have_to_return_data,
+            "{path:?}"
+        );
+        // ...and our encoding must be stable.
+        assert_eq!(encode(decode(&encoded)), encoded, "{path:?}");
+    }
+    assert!(frames > 0);
+}
+
+#[test]
+fn handshake_request() {
+    let frame = read_frame("handshake_request.hex");
+    let Message::Request(RequestMessage::Handshake(handshake)) = decode(&frame) else {
+        panic!("frame is not a handshake request");
+    };
+    assert_eq!(handshake.node_data.network_id, MAINNET_NETWORK_ID);
+
+    let Message::Request(RequestMessage::Handshake(round_trip)) = decode(&encode(
+        Message::Request(RequestMessage::Handshake(handshake.clone())),
+    )) else {
+        panic!("round trip changed the message type");
+    };
+    assert_eq!(handshake, round_trip);
+}
+
+#[test]
+fn handshake_response() {
+    let frame = read_frame("handshake_response.hex");
+    let Message::Response(ResponseMessage::Handshake(handshake)) = decode(&frame) else {
+        panic!("frame is not a handshake response");
+    };
+    assert_eq!(handshake.node_data.network_id, MAINNET_NETWORK_ID);
+    assert!(!handshake.local_peerlist_new.is_empty());
+
+    let Message::Response(ResponseMessage::Handshake(round_trip)) = decode(&encode(
+        Message::Response(ResponseMessage::Handshake(handshake.clone())),
+    )) else {
+        panic!("round trip changed the message type");
+    };
+    assert_eq!(handshake, round_trip);
+}
+
+#[test]
+fn new_transactions() {
+    let frame = read_frame("new_transactions.hex");
+    let Message::Protocol(ProtocolMessage::NewTransactions(txs)) = decode(&frame) else {
+        panic!("frame is not a new transactions notification");
+    };
+    assert!(!txs.txs.is_empty());
+
+    let Message::Protocol(ProtocolMessage::NewTransactions(round_trip)) = decode(&encode(
+        Message::Protocol(ProtocolMessage::NewTransactions(txs.clone())),
+    )) else {
+        panic!("round trip changed the message type");
+    };
+    assert_eq!(txs, round_trip);
+}
+
+#[test]
+fn new_fluffy_block() {
+    let frame = read_frame("new_fluffy_block.hex");
+    let Message::Protocol(ProtocolMessage::NewFluffyBlock(block)) = decode(&frame) else {
+        panic!("frame is not a new fluffy block notification");
+    };
+    assert!(!block.b.block.is_empty());
+
+    let Message::Protocol(ProtocolMessage::NewFluffyBlock(round_trip)) = decode(&encode(
+        Message::Protocol(ProtocolMessage::NewFluffyBlock(block.clone())),
+    )) else {
+        panic!("round trip changed the message type");
+    };
+    assert_eq!(block, round_trip);
+}
+
+#[cfg(feature = "capture")]
+#[test]
+fn captured_frames_match_the_corpus() {
+    use crate::capture::CapturingCodec;
+
+    let frame = read_frame("new_fluffy_block.hex");
+    let mut codec = CapturingCodec::default();
+
+    // Feed the frame in two parts, like a socket would.
+    let mut src = BytesMut::from(&frame[..100]);
+    assert!(codec.decode_message(&mut src).unwrap().is_none());
+    src.extend_from_slice(&frame[100..]);
+    assert!(codec.decode_message(&mut src).unwrap().is_some());
+
+    let frames = codec.take_frames();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].command, 2008);
+    assert_eq!(frames[0].name(), "2008_request");
+    assert_eq!(frames[0].bytes, frame);
+}
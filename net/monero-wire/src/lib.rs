@@ -18,6 +18,12 @@
 //! A crate defining Monero network messages and network addresses,
 //! built on top of the levin-cuprate crate.
 //!
+//! ## Corpus
+//!
+//! `corpus/` holds the levin bytes of real messages sent by monerod peers, every frame in it is
+//! checked to decode and round trip through [`MoneroWireCodec`]. More frames can be recorded from
+//! peers with the `CapturingCodec` of the `capture` feature.
+//!
 //! ## License
 //!
 //! This project is licensed under the MIT License.
@@ -29,6 +35,10 @@
 #![deny(unused_mut)]
 //#![deny(missing_docs)]
 
+#[cfg(feature = "capture")]
+pub mod capture;
+#[cfg(test)]
+mod corpus;
 pub mod messages;
 pub mod network_address;
 
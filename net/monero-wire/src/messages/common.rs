@@ -38,27 +38,23 @@ impl From<PeerSupportFlags> for u32 {
     }
 }
 
-/*
 impl PeerSupportFlags {
     const FLUFFY_BLOCKS: u32 = 0b0000_0001;
     /// checks if `self` has all the flags that `other` has
     pub fn contains(&self, other: &PeerSupportFlags) -> bool {
-        self.0. & other.0 == other.0
+        self.0 & other.0 == other.0
     }
     pub fn supports_fluffy_blocks(&self) -> bool {
         self.0 & Self::FLUFFY_BLOCKS == Self::FLUFFY_BLOCKS
     }
     pub fn get_support_flag_fluffy_blocks() -> Self {
-        PeerSupportFlags {
-            support_flags: Self::FLUFFY_BLOCKS,
-        }
+        PeerSupportFlags(Self::FLUFFY_BLOCKS)
     }
 
     pub fn is_empty(&self) -> bool {
         self.0 == 0
     }
 }
-*/
 impl From<u8> for PeerSupportFlags {
     fn from(value: u8) -> Self {
         PeerSupportFlags(value.into())
@@ -93,7 +93,7 @@ impl<'de> Deserialize<'de> for SingleBlob {
                 Ok(SingleBlob::Pruned(v))
             }
 
-            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            fn visit_newtype_struct<D>(self, _deserializer: D) -> Result<Self::Value, D::Error>
             where
                 D: Deserializer<'de>,
             {
@@ -17,7 +17,7 @@
 //! Monero network. Core Monero has 4 main addresses: IPv4, IPv6, Tor,
 //! I2p. Currently this module only has IPv(4/6).
 //!
-use std::net::{SocketAddrV4, SocketAddrV6};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
 use std::{hash::Hash, net};
 
 mod builder;
@@ -46,14 +46,42 @@ impl NetworkAddress {
         }
     }
 
+    /// Returns the IP of the address.
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            NetworkAddress::IPv4(ip) => IpAddr::V4(*ip.ip()),
+            NetworkAddress::IPv6(ip) => IpAddr::V6(*ip.ip()),
+        }
+    }
+
+    /// Returns the IP of the address with IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) turned back
+    /// into IPv4 addresses, monerod sends IPv4 peers like this.
+    pub fn canonical_ip(&self) -> IpAddr {
+        match self {
+            NetworkAddress::IPv4(ip) => IpAddr::V4(*ip.ip()),
+            NetworkAddress::IPv6(ip) => match ip.ip().to_ipv4_mapped() {
+                Some(ip) => IpAddr::V4(ip),
+                None => IpAddr::V6(*ip.ip()),
+            },
+        }
+    }
+
+    /// Returns true if this is an IPv6 address that is not an IPv4-mapped address.
+    pub fn is_ipv6(&self) -> bool {
+        self.canonical_ip().is_ipv6()
+    }
+
     pub fn is_loopback(&self) -> bool {
-        // TODO
-        false
+        self.canonical_ip().is_loopback()
     }
 
+    /// Returns true if the address is in a private or link-local range, which can't be reached from
+    /// the internet.
     pub fn is_local(&self) -> bool {
-        // TODO
-        false
+        match self.canonical_ip() {
+            IpAddr::V4(ip) => is_local_ipv4(&ip),
+            IpAddr::V6(ip) => is_local_ipv6(&ip),
+        }
     }
 
     pub fn port(&self) -> u16 {
@@ -64,6 +92,16 @@ impl NetworkAddress {
     }
 }
 
+fn is_local_ipv4(ip: &Ipv4Addr) -> bool {
+    ip.is_private() || ip.is_link_local()
+}
+
+fn is_local_ipv6(ip: &Ipv6Addr) -> bool {
+    let first_segment = ip.segments()[0];
+    // Unique local, fc00::/7, and link-local, fe80::/10, addresses.
+    first_segment & 0xfe00 == 0xfc00 || first_segment & 0xffc0 == 0xfe80
+}
+
 impl From<net::SocketAddrV4> for NetworkAddress {
     fn from(value: net::SocketAddrV4) -> Self {
         NetworkAddress::IPv4(value)
@@ -84,3 +122,45 @@ impl From<net::SocketAddr> for NetworkAddress {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::NetworkAddress;
+
+    fn addr(addr: &str) -> NetworkAddress {
+        addr.parse::<SocketAddr>().unwrap().into()
+    }
+
+    #[test]
+    fn address_kinds() {
+        assert!(addr("127.0.0.1:18080").is_loopback());
+        assert!(addr("[::1]:18080").is_loopback());
+        assert!(addr("[::ffff:127.0.0.1]:18080").is_loopback());
+
+        assert!(addr("192.168.1.2:18080").is_local());
+        assert!(addr("[fd00::1]:18080").is_local());
+        assert!(addr("[fe80::1]:18080").is_local());
+        assert!(addr("[::ffff:10.0.0.1]:18080").is_local());
+        assert!(!addr("8.8.8.8:18080").is_local());
+        assert!(!addr("[2001:db8::1]:18080").is_local());
+
+        assert!(addr("[2001:db8::1]:18080").is_ipv6());
+        assert!(!addr("[::ffff:8.8.8.8]:18080").is_ipv6());
+        assert_eq!(
+            addr("[::ffff:8.8.8.8]:18080").canonical_ip(),
+            addr("8.8.8.8:18080").ip()
+        );
+    }
+
+    #[test]
+    fn ipv6_round_trip() {
+        let ip = addr("[2001:db8::1]:18080");
+
+        let bytes = epee_encoding::to_bytes(&ip).unwrap();
+        let decoded: NetworkAddress = epee_encoding::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded, ip);
+    }
+}
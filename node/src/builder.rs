@@ -0,0 +1,687 @@
+//! # Builder
+//!
+//! This module contains [`NodeBuilder`], which picks the subsystems a node runs and builds it.
+//!
+//! Every node has a verifier, the context service and a block queue. The tx pool and the RPC server
+//! are only run if they are added to the builder. The tx pool verifies new transactions with a
+//! [`TxVerifier`] against the node's context, unless it is given another verifier with
+//! [`NodeBuilder::with_tx_verifier`].
+//!
+//! The database's reads are scheduled with a [`ReadScheduler`], the RPC server reads with
+//! [`ReadPriority::Bulk`] and everything else, the verifier and the tx pool, with
+//! [`ReadPriority::Critical`], so a flood of RPC requests can't stall following the chain.
+//!
+//! The node doesn't verify the queued blocks itself, the owner of [`Node::block_receiver`] does, for
+//! example with [`Node::verify_queued_blocks`], so the RPC server only takes blocks from
+//! `submit_block` if the builder is told the queue is drained with
+//! [`NodeBuilder::with_block_submission`]. Likewise `compact_db` is only served if the builder is
+//! given a [`DatabaseCompactor`] with [`NodeBuilder::with_database_compactor`], and
+//! `get_public_nodes` if it is given the address book with [`NodeBuilder::with_address_book`].
+//!
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::sync::broadcast;
+use tower::{util::BoxCloneService, Service};
+
+use cuprate_common::Network;
+use cuprate_peer::address_book::{AddressBookError, AddressBookRequest, AddressBookResponse};
+use cuprate_rpc::{
+    notify::Notifier, policy::RpcConfig, zmq::ZmqPublisher, DatabaseCompactor, RpcHandler,
+};
+use monero_consensus::{
+    context::ContextService,
+    read_scheduler::{ReadPriority, ReadScheduler, ReadSchedulerConfig},
+    tx_verifier::TxVerifier,
+    txpool::{TxPoolConfig, TxPoolListener, TxPoolService, TxVerifierService},
+    verification_queue::verification_queue,
+    verifier::{Config, Verifier},
+    Database,
+};
+
+use crate::{
+    events::{EventListener, TxPoolListeners},
+    public_nodes::AddressBookPublicNodes,
+    Node, NodeAddressBook, NodeError, NodeHandles, NodeTasks, NodeTxPool, TxVerifierSvc,
+};
+
+/// The default amount of events a subscriber can fall behind before it misses events.
+pub const DEFAULT_EVENT_CAPACITY: usize = 1_000;
+
+/// Builds a [`Node`].
+pub struct NodeBuilder {
+    config: Config,
+    tx_pool: Option<TxPoolConfig>,
+    tx_verifier: Option<TxVerifierSvc>,
+    rpc: Option<(SocketAddr, RpcConfig)>,
+    block_submission: bool,
+    database_compactor: Option<Arc<dyn DatabaseCompactor>>,
+    address_book: Option<NodeAddressBook>,
+    read_scheduler: ReadSchedulerConfig,
+    notifier: Option<Notifier>,
+    zmq_publisher: Option<ZmqPublisher>,
+    event_capacity: usize,
+    seed_genesis: bool,
+}
+
+impl NodeBuilder {
+    /// Returns a builder for a node on this network, with the network's default verifier config and
+    /// no optional subsystems.
+    pub fn new(network: Network) -> NodeBuilder {
+        NodeBuilder::from_config(Config::for_network(network))
+    }
+
+    /// Returns a builder for a node with this verifier config, and no optional subsystems.
+    pub fn from_config(config: Config) -> NodeBuilder {
+        NodeBuilder {
+            config,
+            tx_pool: None,
+            tx_verifier: None,
+            rpc: None,
+            block_submission: false,
+            database_compactor: None,
+            address_book: None,
+            read_scheduler: ReadSchedulerConfig::default(),
+            notifier: None,
+            zmq_publisher: None,
+            event_capacity: DEFAULT_EVENT_CAPACITY,
+            seed_genesis: false,
+        }
+    }
+
+    /// Runs a tx pool, new transactions are verified with a [`TxVerifier`] before being added.
+    pub fn with_tx_pool(mut self, config: TxPoolConfig) -> NodeBuilder {
+        self.tx_pool = Some(config);
+        self
+    }
+
+    /// Verifies the tx pool's new transactions with `tx_verifier` instead of a [`TxVerifier`]. This
+    /// does nothing without [`NodeBuilder::with_tx_pool`].
+    pub fn with_tx_verifier<Tv>(mut self, tx_verifier: Tv) -> NodeBuilder
+    where
+        Tv: TxVerifierService + Clone + Send + 'static,
+        Tv::Future: Send + 'static,
+    {
+        self.tx_verifier = Some(BoxCloneService::new(tx_verifier));
+        self
+    }
+
+    /// Runs the RPC server on this address.
+    pub fn with_rpc(mut self, addr: SocketAddr, config: RpcConfig) -> NodeBuilder {
+        self.rpc = Some((addr, config));
+        self
+    }
+
+    /// Queues the blocks from the RPC's `submit_block`, the owner of the node must verify the blocks
+    /// it takes from [`Node::block_receiver`]. Without this `submit_block` is not served.
+    pub fn with_block_submission(mut self) -> NodeBuilder {
+        self.block_submission = true;
+        self
+    }
+
+    /// Marks the database for compaction with this from the RPC's `compact_db`, the owner of the
+    /// node must compact the database before building the next node on it. Without this
+    /// `compact_db` is not served.
+    pub fn with_database_compactor(
+        mut self,
+        database_compactor: Arc<dyn DatabaseCompactor>,
+    ) -> NodeBuilder {
+        self.database_compactor = Some(database_compactor);
+        self
+    }
+
+    /// Lists the public nodes of this address book in the RPC's `get_public_nodes`, and checks their
+    /// advertised RPC ports in the background, removing the ports that can't be reached. Without
+    /// this `get_public_nodes` is not served.
+    pub fn with_address_book<A>(mut self, address_book: A) -> NodeBuilder
+    where
+        A: Service<AddressBookRequest, Response = AddressBookResponse, Error = AddressBookError>
+            + Clone
+            + Send
+            + 'static,
+        A::Future: Send + 'static,
+    {
+        self.address_book = Some(BoxCloneService::new(address_book));
+        self
+    }
+
+    /// Sets the amount of database reads that can run at once, and how many of the RPC's reads can
+    /// wait for a reader.
+    pub fn with_read_scheduler(mut self, config: ReadSchedulerConfig) -> NodeBuilder {
+        self.read_scheduler = config;
+        self
+    }
+
+    /// Subscribes this notifier to the node's events, so it is told about new blocks, reorgs and pool
+    /// transactions. The notifications are run by whoever has the notifier's receiver.
+    pub fn with_notifier(mut self, notifier: Notifier) -> NodeBuilder {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Publishes new main chain blocks and the transactions added to the tx pool with this publisher,
+    /// like monerod's `--zmq-pub`. The messages are sent by whoever has the publisher's receiver.
+    pub fn with_zmq_publisher(mut self, zmq_publisher: ZmqPublisher) -> NodeBuilder {
+        self.zmq_publisher = Some(zmq_publisher);
+        self
+    }
+
+    /// Sets the amount of events a subscriber can fall behind before it misses events.
+    pub fn with_event_capacity(mut self, event_capacity: usize) -> NodeBuilder {
+        self.event_capacity = event_capacity;
+        self
+    }
+
+    /// Writes the network's genesis block to the database if it is empty, so a new node can sync
+    /// from height 0, see [`Verifier::init_with_genesis`].
+    pub fn with_genesis(mut self) -> NodeBuilder {
+        self.seed_genesis = true;
+        self
+    }
+
+    /// Builds the node on this database, initializing the verifier from the top of its chain.
+    pub async fn build<D>(self, database: D) -> Result<Node<D>, NodeError>
+    where
+        D: Database + Clone + Send + Sync + 'static,
+        D::Future: Send + 'static,
+    {
+        let read_scheduler = ReadScheduler::new(self.read_scheduler);
+        let bulk_database = read_scheduler.schedule(database.clone(), ReadPriority::Bulk);
+        let database = read_scheduler.schedule(database, ReadPriority::Critical);
+
+        let (events, _) = broadcast::channel(self.event_capacity);
+        let notifier = self.notifier.map(|notifier| (notifier, events.subscribe()));
+        let zmq_publisher = self
+            .zmq_publisher
+            .map(|zmq_publisher| (zmq_publisher, events.subscribe()));
+        let config = self
+            .config
+            .with_misbehaviour_listener(Arc::new(EventListener(events.clone())))
+            .with_chain_listener(Arc::new(EventListener(events.clone())));
+
+        let verifier = if self.seed_genesis {
+            Verifier::init_with_genesis(config, database.clone()).await?
+        } else {
+            Verifier::init(config, database.clone()).await?
+        };
+        let context = ContextService::new(&verifier);
+
+        let (block_queue, block_receiver) = verification_queue();
+
+        let tx_pool: Option<NodeTxPool<D>> = self.tx_pool.map(|config| {
+            let tx_verifier = self.tx_verifier.unwrap_or_else(|| {
+                BoxCloneService::new(TxVerifier::new(
+                    context.clone(),
+                    database.clone(),
+                    verifier.verification_pool().clone(),
+                    verifier.rule_flags().clone(),
+                ))
+            });
+
+            let mut listeners: Vec<Arc<dyn TxPoolListener>> =
+                vec![Arc::new(EventListener(events.clone()))];
+            if let Some((zmq_publisher, _)) = &zmq_publisher {
+                listeners.push(Arc::new(zmq_publisher.clone()));
+            }
+
+            TxPoolService::new(config, tx_verifier, database.clone())
+                .with_listener(Arc::new(TxPoolListeners(listeners)))
+        });
+
+        let rpc = self.rpc.map(|(addr, config)| {
+            let mut handler = RpcHandler::new(config, bulk_database, context.clone());
+            if self.block_submission {
+                handler = handler.with_block_queue(block_queue.clone());
+            }
+            if let Some(database_compactor) = self.database_compactor {
+                handler = handler.with_database_compactor(database_compactor);
+            }
+            if let Some(tx_pool) = &tx_pool {
+                handler = handler.with_tx_pool(tx_pool.pool().clone());
+            }
+            if let Some(address_book) = &self.address_book {
+                handler = handler
+                    .with_public_nodes(Arc::new(AddressBookPublicNodes::new(address_book.clone())));
+            }
+            (addr, handler)
+        });
+
+        tracing::info!(
+            height = verifier.context().chain_height,
+            tx_pool = tx_pool.is_some(),
+            rpc = ?rpc.as_ref().map(|(addr, _)| addr),
+            block_submission = self.block_submission,
+            notifier = notifier.is_some(),
+            zmq = zmq_publisher.is_some(),
+            address_book = self.address_book.is_some(),
+            "Built node"
+        );
+
+        Ok(Node {
+            verifier,
+            handles: NodeHandles {
+                database,
+                context,
+                tx_pool: tx_pool.clone(),
+                block_queue,
+                events: events.clone(),
+            },
+            block_receiver,
+            tasks: NodeTasks {
+                rpc,
+                tx_pool,
+                notifier,
+                zmq_publisher,
+                address_book: self.address_book,
+                events,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use futures::{future::ready, StreamExt};
+    use tokio::sync::Semaphore;
+    use tower::ServiceExt;
+
+    use cuprate_rpc::{
+        json_rpc::{INVALID_PARAMS, METHOD_NOT_FOUND},
+        notify::{NotifierConfig, NotifyTarget},
+        zmq::{TOPIC_FULL_CHAIN_MAIN, TOPIC_MINIMAL_CHAIN_MAIN},
+    };
+    use monero_consensus::{
+        alt_chain::AltChainContextCache,
+        context::{ContextRequest, ContextResponse},
+        genesis::generate_genesis_block,
+        hardforks::HardForkConfig,
+        misbehaviour::{BlockSource, MisbehaviourReport, PeerId, QueuedBlock, Severity},
+        test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder},
+        txpool::{TxPoolError, TxPoolRequest, VerifiedTx},
+        verification_queue::Priority,
+        BlockError, ConsensusError, DatabaseRequest, TransactionError,
+    };
+
+    use monero_wire::{messages::PeerListEntryBase, network_address::NetZone};
+
+    use super::*;
+    use crate::NodeEvent;
+
+    #[tokio::test]
+    async fn subsystems_are_only_run_when_added() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+
+        let node = NodeBuilder::new(Network::Mainnet)
+            .build(database)
+            .await
+            .unwrap();
+        assert!(node.handles.tx_pool().is_none());
+        assert!(node.tasks.rpc.is_none());
+
+        let ContextResponse::BlockChainContext(context) = node
+            .handles
+            .context()
+            .clone()
+            .oneshot(ContextRequest::BlockChainContext)
+            .await
+            .unwrap()
+        else {
+            panic!("wrong response");
+        };
+        assert_eq!(context.chain_height, 10);
+    }
+
+    #[tokio::test]
+    async fn submit_block_is_only_served_with_block_submission() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+        let body = br#"{"jsonrpc": "2.0", "id": 1, "method": "submit_block", "params": []}"#;
+        let addr = "127.0.0.1:18081".parse().unwrap();
+
+        let node = NodeBuilder::new(Network::Mainnet)
+            .with_rpc(addr, RpcConfig::default())
+            .build(database.clone())
+            .await
+            .unwrap();
+        let (_, handler) = node.tasks.rpc.unwrap();
+        let error = handler.handle_body(body).await.error.unwrap();
+        assert_eq!(error.code, METHOD_NOT_FOUND);
+
+        let node = NodeBuilder::new(Network::Mainnet)
+            .with_rpc(addr, RpcConfig::default())
+            .with_block_submission()
+            .build(database)
+            .await
+            .unwrap();
+        let (_, handler) = node.tasks.rpc.unwrap();
+        let error = handler.handle_body(body).await.error.unwrap();
+        assert_eq!(error.code, INVALID_PARAMS);
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingCompactor(AtomicUsize);
+
+    impl DatabaseCompactor for RecordingCompactor {
+        fn request_compaction(&self) -> Result<(), tower::BoxError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn compact_db_is_only_served_with_a_compactor() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+        let body = br#"{"jsonrpc": "2.0", "id": 1, "method": "compact_db"}"#;
+        let addr = "127.0.0.1:18081".parse().unwrap();
+
+        let node = NodeBuilder::new(Network::Mainnet)
+            .with_rpc(addr, RpcConfig::default())
+            .build(database.clone())
+            .await
+            .unwrap();
+        let (_, handler) = node.tasks.rpc.unwrap();
+        let error = handler.handle_body(body).await.error.unwrap();
+        assert_eq!(error.code, METHOD_NOT_FOUND);
+
+        let compactor = Arc::new(RecordingCompactor::default());
+        let node = NodeBuilder::new(Network::Mainnet)
+            .with_rpc(addr, RpcConfig::default())
+            .with_database_compactor(compactor.clone())
+            .build(database)
+            .await
+            .unwrap();
+        let (_, handler) = node.tasks.rpc.unwrap();
+        let result = handler.handle_body(body).await.result.unwrap();
+        assert_eq!(result["status"], "OK");
+        assert_eq!(compactor.0.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn get_public_nodes_is_served_from_the_address_book() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+        let body = br#"{"jsonrpc": "2.0", "id": 1, "method": "get_public_nodes", "params": {"gray": true}}"#;
+        let addr = "127.0.0.1:18081".parse().unwrap();
+
+        let node = NodeBuilder::new(Network::Mainnet)
+            .with_rpc(addr, RpcConfig::default())
+            .build(database.clone())
+            .await
+            .unwrap();
+        let (_, handler) = node.tasks.rpc.unwrap();
+        let error = handler.handle_body(body).await.error.unwrap();
+        assert_eq!(error.code, METHOD_NOT_FOUND);
+
+        let peer = |ip: [u8; 4]| PeerListEntryBase {
+            adr: SocketAddr::from((ip, 18080)).into(),
+            id: 1,
+            last_seen: 20,
+            pruning_seed: 0,
+            rpc_port: 18089,
+            rpc_credits_per_hash: 0,
+        };
+        let address_book = tower::service_fn(move |req| {
+            let AddressBookRequest::GetPublicNodes(NetZone::Public) = req else {
+                panic!("Unexpected request: {req}");
+            };
+            ready(Ok::<_, AddressBookError>(
+                AddressBookResponse::PublicNodes {
+                    white: vec![peer([1, 1, 1, 1])],
+                    gray: vec![peer([2, 2, 2, 2])],
+                },
+            ))
+        });
+
+        let node = NodeBuilder::new(Network::Mainnet)
+            .with_rpc(addr, RpcConfig::default())
+            .with_address_book(address_book)
+            .build(database)
+            .await
+            .unwrap();
+        assert!(node.tasks.address_book.is_some());
+        let (_, handler) = node.tasks.rpc.unwrap();
+        let result = handler.handle_body(body).await.result.unwrap();
+        assert_eq!(result["white"][0]["host"], "1.1.1.1");
+        assert_eq!(result["white"][0]["rpc_port"], 18089);
+        assert_eq!(result["white"][0]["last_seen"], 20);
+        assert_eq!(result["gray"][0]["host"], "2.2.2.2");
+    }
+
+    #[tokio::test]
+    async fn rpc_reads_do_not_delay_critical_reads() {
+        let dummy = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+        // Once gated every read waits for a permit, so reads only finish when we let them.
+        let gated = Arc::new(AtomicBool::new(false));
+        let gate = Arc::new(Semaphore::new(0));
+        let database = {
+            let (gated, gate) = (gated.clone(), gate.clone());
+            tower::service_fn(move |req| {
+                let (gated, gate, dummy) = (gated.clone(), gate.clone(), dummy.clone());
+                async move {
+                    if gated.load(Ordering::Relaxed) {
+                        gate.acquire().await.unwrap().forget();
+                    }
+                    dummy.oneshot(req).await
+                }
+            })
+        };
+
+        let node = NodeBuilder::new(Network::Mainnet)
+            .with_rpc("127.0.0.1:18081".parse().unwrap(), RpcConfig::default())
+            .with_read_scheduler(ReadSchedulerConfig {
+                max_concurrent_reads: 1,
+                max_queued_bulk_reads: 100,
+            })
+            .build(database)
+            .await
+            .unwrap();
+        gated.store(true, Ordering::Relaxed);
+        // The service's future isn't `Send` for every lifetime, so the reads run on this thread.
+        let local = tokio::task::LocalSet::new();
+        let (_, handler) = node.tasks.rpc.unwrap();
+
+        let finished = Arc::new(Mutex::new(Vec::new()));
+        let body = br#"{"jsonrpc": "2.0", "id": 1, "method": "get_block_header_by_height", "params": {"height": 1}}"#;
+        for _ in 0..50 {
+            let (handler, finished) = (handler.clone(), finished.clone());
+            local.spawn_local(async move {
+                handler.handle_body(body).await;
+                finished.lock().unwrap().push("bulk");
+            });
+        }
+        local.run_until(tokio::task::yield_now()).await;
+        assert!(finished.lock().unwrap().is_empty());
+
+        let database = node.handles.database().clone();
+        let critical_finished = finished.clone();
+        local.spawn_local(async move {
+            database
+                .oneshot(DatabaseRequest::ChainHeight)
+                .await
+                .unwrap();
+            critical_finished.lock().unwrap().push("critical");
+        });
+
+        local
+            .run_until(async {
+                while !finished.lock().unwrap().contains(&"critical") {
+                    let done = finished.lock().unwrap().len();
+                    gate.add_permits(1);
+                    while finished.lock().unwrap().len() == done {
+                        tokio::task::yield_now().await;
+                    }
+                }
+            })
+            .await;
+
+        // Only the bulk read that had the reader before the critical read queued finished first.
+        assert_eq!(*finished.lock().unwrap(), ["bulk", "critical"]);
+    }
+
+    #[tokio::test]
+    async fn notifier_is_subscribed_to_the_events() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+        let config = NotifierConfig::default()
+            .with_block_notify(NotifyTarget::Command("block %s".into()))
+            .with_reorg_notify(NotifyTarget::Command("reorg %h".into()));
+        let (notifier, mut notifications) = Notifier::new(config);
+
+        let mut node = NodeBuilder::new(Network::Mainnet)
+            .with_notifier(notifier)
+            .build(database.clone())
+            .await
+            .unwrap();
+        tokio::spawn(node.tasks.run());
+
+        let genesis = generate_genesis_block(&Network::Mainnet);
+        node.verifier
+            .announce_blocks(10, &[genesis.clone(), genesis.clone()]);
+        let alt_chain =
+            AltChainContextCache::fork_from_main_chain(HardForkConfig::main_net(), 5, database)
+                .await
+                .unwrap();
+        node.verifier.promote_alt_chain(alt_chain);
+
+        let mut args = Vec::new();
+        for _ in 0..3 {
+            args.push(notifications.next().await.unwrap().command_args().unwrap());
+        }
+        let hash = hex::encode(genesis.hash());
+        assert_eq!(args, [["block", &hash], ["block", &hash], ["reorg", "5"]]);
+    }
+
+    #[tokio::test]
+    async fn zmq_publisher_is_subscribed_to_the_events() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+        let (zmq_publisher, mut messages) = ZmqPublisher::new();
+
+        let node = NodeBuilder::new(Network::Mainnet)
+            .with_zmq_publisher(zmq_publisher)
+            .build(database)
+            .await
+            .unwrap();
+        tokio::spawn(node.tasks.run());
+
+        let genesis = generate_genesis_block(&Network::Mainnet);
+        node.verifier.announce_blocks(10, &[genesis]);
+
+        let minimal = messages.next().await.unwrap();
+        assert!(minimal.starts_with(&format!("{TOPIC_MINIMAL_CHAIN_MAIN}:")));
+        assert!(minimal.contains(r#""first_height":10"#));
+        let full = messages.next().await.unwrap();
+        assert!(full.starts_with(&format!("{TOPIC_FULL_CHAIN_MAIN}:")));
+    }
+
+    #[tokio::test]
+    async fn tx_pool_verifies_transactions_against_the_context() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+
+        let node = NodeBuilder::new(Network::Mainnet)
+            .with_tx_pool(TxPoolConfig::default())
+            .build(database)
+            .await
+            .unwrap();
+
+        let miner_tx = generate_genesis_block(&Network::Mainnet).miner_tx;
+        let err = node
+            .handles
+            .tx_pool()
+            .unwrap()
+            .clone()
+            .oneshot(TxPoolRequest::NewTransaction(miner_tx))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            TxPoolError::Verification(ConsensusError::Transaction(TransactionError::MinerInput))
+        ));
+        assert!(node
+            .handles
+            .tx_pool()
+            .unwrap()
+            .pool()
+            .lock()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn handles_reach_the_node() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+        let tx_verifier = tower::service_fn(|_| {
+            ready(Ok::<_, ConsensusError>(VerifiedTx {
+                weight: 100,
+                fee: 200,
+                key_images: vec![[1; 32]],
+            }))
+        });
+
+        let mut node = NodeBuilder::new(Network::Mainnet)
+            .with_tx_pool(TxPoolConfig::default())
+            .with_tx_verifier(tx_verifier)
+            .build(database)
+            .await
+            .unwrap();
+        let handles = node.handles.clone();
+        let mut events = handles.subscribe();
+
+        let genesis = generate_genesis_block(&Network::Mainnet);
+        let tx = genesis.miner_tx.clone();
+        handles
+            .tx_pool()
+            .unwrap()
+            .clone()
+            .oneshot(TxPoolRequest::NewTransaction(tx.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            events.recv().await.unwrap(),
+            NodeEvent::TxAdded {
+                hash: tx.hash(),
+                weight: 100,
+                fee: 200,
+            }
+        );
+
+        let queued = QueuedBlock {
+            source: BlockSource::Rpc,
+            block: genesis,
+        };
+        handles.block_queue().push_tip(queued.clone());
+        let (priority, block) = node.block_receiver.next().await.unwrap();
+        assert_eq!(priority, Priority::Tip);
+        assert_eq!(block, queued);
+
+        let error: ConsensusError = BlockError::InvalidPow { height: 10 }.into();
+        let source = BlockSource::Peer(PeerId(3));
+        node.verifier.report_failure(source, &error);
+        assert_eq!(
+            events.recv().await.unwrap(),
+            NodeEvent::Misbehaviour(MisbehaviourReport {
+                source,
+                severity: Severity::Ban,
+                reason: error.to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! # Chain
+//!
+//! This module contains [`NodeChain`], which answers the P2P code's requests for our chain from the
+//! node's database and context: the core sync data of handshakes and timed syncs, and the blocks
+//! peers request from us, see [`Node::chain`]. It is handed to the P2P code in a
+//! [`ClientChain`](cuprate_peer::client::ClientChain).
+//!
+use std::task::{Context, Poll};
+
+use futures::{future::BoxFuture, FutureExt};
+use tower::{Service, ServiceExt};
+
+use cuprate_common::PruningSeed;
+use cuprate_peer::client::{BlockKnown, BlockchainRequest, BlockchainResponse, CoreSyncData};
+use monero_consensus::{
+    context::{BlockChainContext, ContextRequest, ContextService},
+    read_scheduler::Scheduled,
+    ConsensusError, Database, DatabaseRequest,
+};
+use monero_wire::messages::common::{BlockCompleteEntry, PrunedTxBlobEntry, TransactionBlobs};
+
+use crate::{sync::chain_history, Node};
+
+/// A service answering the P2P code's requests for our chain, see the [module docs](self).
+pub struct NodeChain<D> {
+    database: Scheduled<D>,
+    context: ContextService,
+    pruning_seed: PruningSeed,
+}
+
+impl<D: Clone> Clone for NodeChain<D> {
+    fn clone(&self) -> Self {
+        NodeChain {
+            database: self.database.clone(),
+            context: self.context.clone(),
+            pruning_seed: self.pruning_seed,
+        }
+    }
+}
+
+impl<D> Node<D> {
+    /// Returns a service answering the P2P code's requests for the node's chain, for the handshakes
+    /// with peers and the [`PeerRequestHandler`](cuprate_peer::request_handler::PeerRequestHandler)
+    /// serving our blocks. It is wrapped in a [`ClientChain`](cuprate_peer::client::ClientChain)
+    /// when it is handed to them.
+    pub fn chain(&self) -> NodeChain<D>
+    where
+        D: Clone,
+    {
+        NodeChain {
+            database: self.handles.database.clone(),
+            context: self.handles.context.clone(),
+            pruning_seed: self.verifier.pruning_seed(),
+        }
+    }
+}
+
+impl<D> Service<BlockchainRequest> for NodeChain<D>
+where
+    D: Database + Clone + Send + Sync + 'static,
+    D::Future: Send + 'static,
+{
+    type Response = BlockchainResponse;
+    type Error = ConsensusError;
+    type Future = BoxFuture<'static, Result<BlockchainResponse, ConsensusError>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The database and context service are cloned for every request and we wait for them to be
+        // ready in the returned future.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: BlockchainRequest) -> Self::Future {
+        self.clone().handle(req).boxed()
+    }
+}
+
+impl<D> NodeChain<D>
+where
+    D: Database + Clone + Send + Sync + 'static,
+    D::Future: Send + 'static,
+{
+    async fn handle(self, req: BlockchainRequest) -> Result<BlockchainResponse, ConsensusError> {
+        Ok(match req {
+            BlockchainRequest::CurrentHeight => {
+                BlockchainResponse::CurrentHeight(self.context().await?.chain_height)
+            }
+            BlockchainRequest::CumulativeDifficulty => BlockchainResponse::CumulativeDifficulty(
+                self.context().await?.cumulative_difficulty,
+            ),
+            BlockchainRequest::CoreSyncData => {
+                let context = self.context().await?;
+                BlockchainResponse::CoreSyncData(CoreSyncData::new(
+                    context.cumulative_difficulty,
+                    context.chain_height,
+                    self.pruning_seed.into(),
+                    context.top_hash,
+                    context.current_hf as u8,
+                ))
+            }
+            BlockchainRequest::Chain => {
+                let chain_height = self.context().await?.chain_height;
+                BlockchainResponse::Chain(chain_history(self.database.clone(), chain_height).await?)
+            }
+            BlockchainRequest::BlockHeight(id) => {
+                BlockchainResponse::BlockHeight(self.block_height(id).await?)
+            }
+            BlockchainRequest::BlockKnown(id) => {
+                BlockchainResponse::BlockKnown(match self.block_height(id).await? {
+                    Some(_) => BlockKnown::OnMainChain,
+                    None => BlockKnown::No,
+                })
+            }
+            BlockchainRequest::ChainEntry {
+                start_height,
+                count,
+            } => {
+                let chain_height = self.context().await?.chain_height;
+                let end_height = start_height
+                    .saturating_add(count as u64)
+                    .min(chain_height)
+                    .max(start_height);
+                self.chain_entry(start_height..end_height).await?
+            }
+            BlockchainRequest::BlockCompleteEntry { id, pruned } => {
+                BlockchainResponse::BlockCompleteEntry(match self.block_height(id).await? {
+                    Some(height) => Some(self.block_complete_entry(height, pruned).await?),
+                    None => None,
+                })
+            }
+        })
+    }
+
+    async fn context(&self) -> Result<BlockChainContext, ConsensusError> {
+        Ok(self
+            .context
+            .clone()
+            .oneshot(ContextRequest::BlockChainContext)
+            .await?
+            .into_block_chain_context()?)
+    }
+
+    async fn call_database(
+        &self,
+        req: DatabaseRequest,
+    ) -> Result<monero_consensus::DatabaseResponse, ConsensusError> {
+        Ok(self.database.clone().oneshot(req).await?)
+    }
+
+    async fn block_height(&self, id: [u8; 32]) -> Result<Option<u64>, ConsensusError> {
+        Ok(self
+            .call_database(DatabaseRequest::BlockHeight(id))
+            .await?
+            .into_block_height()?)
+    }
+
+    async fn chain_entry(
+        &self,
+        range: std::ops::Range<u64>,
+    ) -> Result<BlockchainResponse, ConsensusError> {
+        let mut block_ids = Vec::with_capacity((range.end - range.start) as usize);
+        for height in range.clone() {
+            block_ids.push(
+                self.call_database(DatabaseRequest::BlockHash(height))
+                    .await?
+                    .into_block_hash()?,
+            );
+        }
+
+        let block_weights = self
+            .call_database(DatabaseRequest::BlockWeightsInRange(range))
+            .await?
+            .into_block_weights_in_range()?
+            .into_iter()
+            .map(|weights| weights.block_weight as u64)
+            .collect();
+
+        Ok(BlockchainResponse::ChainEntry {
+            block_ids,
+            block_weights,
+        })
+    }
+
+    async fn block_complete_entry(
+        &self,
+        height: u64,
+        pruned: bool,
+    ) -> Result<BlockCompleteEntry, ConsensusError> {
+        let blobs = self
+            .call_database(DatabaseRequest::BlockBlobsInRange {
+                range: height..height + 1,
+                pruned,
+            })
+            .await?
+            .into_block_blobs_in_range()?
+            .pop()
+            .expect("The database returns a block for every height in the range");
+
+        let txs = match pruned {
+            _ if blobs.txs.is_empty() => None,
+            // Version 1 transactions can't be pruned, they are sent complete with a zero prunable
+            // hash.
+            true => Some(TransactionBlobs::Pruned(
+                blobs
+                    .txs
+                    .into_iter()
+                    .map(|tx| PrunedTxBlobEntry {
+                        tx: tx.blob.to_vec(),
+                        prunable_hash: tx.prunable_hash.unwrap_or([0; 32]),
+                    })
+                    .collect(),
+            )),
+            false => Some(TransactionBlobs::Normal(
+                blobs.txs.into_iter().map(|tx| tx.blob.to_vec()).collect(),
+            )),
+        };
+
+        Ok(BlockCompleteEntry {
+            pruned,
+            block: blobs.block,
+            block_weight: blobs.block_weight as u64,
+            txs,
+        })
+    }
+}
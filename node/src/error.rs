@@ -0,0 +1,12 @@
+use monero_consensus::{txpool::TxPoolError, ConsensusError};
+
+/// An error that stopped the node.
+#[derive(Debug, thiserror::Error)]
+pub enum NodeError {
+    #[error("Failed to initialize the verifier: {0}")]
+    Init(#[from] ConsensusError),
+    #[error("The RPC server failed: {0}")]
+    Rpc(#[from] hyper::Error),
+    #[error("The tx pool failed: {0}")]
+    TxPool(#[from] TxPoolError),
+}
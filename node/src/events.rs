@@ -0,0 +1,160 @@
+//! # Events
+//!
+//! This module contains [`NodeEvent`], the events a node publishes to its subscribers, see
+//! [`NodeHandles::subscribe`](crate::NodeHandles::subscribe).
+//!
+//! Events are sent on a [`broadcast`] channel, a subscriber that falls more than the channel's
+//! capacity behind misses the oldest events, see
+//! [`NodeBuilder::with_event_capacity`](crate::NodeBuilder::with_event_capacity).
+//!
+//! A [`Notifier`] given to [`NodeBuilder::with_notifier`](crate::NodeBuilder::with_notifier) is
+//! subscribed to the events, so it runs the configured commands for new blocks, reorgs and pool
+//! transactions. Likewise a [`ZmqPublisher`] given to
+//! [`NodeBuilder::with_zmq_publisher`](crate::NodeBuilder::with_zmq_publisher) publishes
+//! `chain_main` for new blocks, it is given the pool's transactions as a [`TxPoolListener`] as the
+//! events don't carry the transactions.
+//!
+use std::sync::Arc;
+
+use monero_serai::block::Block;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use cuprate_rpc::{notify::Notifier, zmq::ZmqPublisher};
+use monero_consensus::{
+    misbehaviour::{MisbehaviourListener, MisbehaviourReport},
+    txpool::{PoolTx, TxPoolListener},
+    verifier::ChainListener,
+};
+
+/// An event published by the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeEvent {
+    /// Blocks were written to the main chain, see
+    /// [`Verifier::announce_blocks`](monero_consensus::verifier::Verifier::announce_blocks).
+    BlocksAdded {
+        first_height: u64,
+        blocks: Vec<Block>,
+    },
+    /// An alt chain replaced the main chain from `split_height`, the height of the first block
+    /// removed.
+    Reorg {
+        split_height: u64,
+        blocks_removed: u64,
+        blocks_added: u64,
+    },
+    /// A transaction was added to the tx pool.
+    TxAdded {
+        hash: [u8; 32],
+        weight: usize,
+        fee: u64,
+    },
+    /// Transactions expired from the tx pool.
+    TxsExpired(Vec<[u8; 32]>),
+    /// Data failed verification because of its source, the P2P layer should punish the peer, see
+    /// [`Verifier::report_failure`](monero_consensus::verifier::Verifier::report_failure).
+    Misbehaviour(MisbehaviourReport),
+}
+
+/// A [`TxPoolListener`], [`MisbehaviourListener`] and [`ChainListener`] publishing the transactions
+/// added to the pool, the reported verification failures and the changes to the main chain.
+#[derive(Debug)]
+pub(crate) struct EventListener(pub(crate) broadcast::Sender<NodeEvent>);
+
+impl TxPoolListener for EventListener {
+    fn tx_added(&self, tx: &PoolTx) {
+        // An error only means there are no subscribers.
+        let _ = self.0.send(NodeEvent::TxAdded {
+            hash: tx.hash,
+            weight: tx.weight,
+            fee: tx.fee,
+        });
+    }
+}
+
+impl MisbehaviourListener for EventListener {
+    fn misbehaviour(&self, report: &MisbehaviourReport) {
+        let _ = self.0.send(NodeEvent::Misbehaviour(report.clone()));
+    }
+}
+
+impl ChainListener for EventListener {
+    fn blocks_added(&self, first_height: u64, blocks: &[Block]) {
+        let _ = self.0.send(NodeEvent::BlocksAdded {
+            first_height,
+            blocks: blocks.to_vec(),
+        });
+    }
+
+    fn reorg(&self, split_height: u64, blocks_removed: u64, blocks_added: u64) {
+        let _ = self.0.send(NodeEvent::Reorg {
+            split_height,
+            blocks_removed,
+            blocks_added,
+        });
+    }
+}
+
+/// Tells every listener about the transactions added to the pool.
+#[derive(Debug)]
+pub(crate) struct TxPoolListeners(pub(crate) Vec<Arc<dyn TxPoolListener>>);
+
+impl TxPoolListener for TxPoolListeners {
+    fn tx_added(&self, tx: &PoolTx) {
+        for listener in &self.0 {
+            listener.tx_added(tx);
+        }
+    }
+}
+
+/// Gives the node's events to the notifier, until the node's event sender is dropped.
+pub(crate) async fn notify_events(notifier: Notifier, mut events: broadcast::Receiver<NodeEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(NodeEvent::BlocksAdded {
+                first_height,
+                blocks,
+            }) => {
+                for (height, block) in (first_height..).zip(&blocks) {
+                    notifier.new_block(height, block.hash());
+                }
+            }
+            Ok(NodeEvent::Reorg {
+                split_height,
+                blocks_removed,
+                blocks_added,
+            }) => notifier.reorg(split_height, blocks_removed, blocks_added),
+            Ok(NodeEvent::TxAdded { hash, weight, fee }) => notifier.pool_tx(hash, weight, fee),
+            Ok(NodeEvent::TxsExpired(_) | NodeEvent::Misbehaviour(_)) => (),
+            Err(RecvError::Lagged(missed)) => {
+                tracing::warn!(
+                    "The notifier fell behind, {} events were not notified",
+                    missed
+                );
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Publishes the blocks from the node's events on ZMQ, until the node's event sender is dropped.
+pub(crate) async fn publish_events(
+    publisher: ZmqPublisher,
+    mut events: broadcast::Receiver<NodeEvent>,
+) {
+    loop {
+        match events.recv().await {
+            Ok(NodeEvent::BlocksAdded {
+                first_height,
+                blocks,
+            }) => publisher.chain_main(first_height, &blocks),
+            Ok(_) => (),
+            Err(RecvError::Lagged(missed)) => {
+                tracing::warn!(
+                    "The ZMQ publisher fell behind, {} events were not published",
+                    missed
+                );
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! # Harness
+//!
+//! This module contains [`TestNode`], a node on a [`MemoryDatabase`] for tests that run several
+//! nodes in one process. Nodes share no state, so nodes of different networks can run side by side.
+//! They connect to each other over in-memory streams with [`TestNode::connect`], with the same
+//! handshake and block requests as over TCP, and each node serves its side of a connection from its
+//! own chain.
+//!
+//! A [`TestNode::new`] node checks blocks with [`VerificationProfile::Regtest`], so the blocks it
+//! mines need no PoW and are accepted on any network.
+//!
+//! ```ignore
+//! let mut miner = TestNode::new(Network::Mainnet).await?;
+//! let mut follower = TestNode::new(Network::Mainnet).await?;
+//!
+//! miner.mine_blocks(20).await?;
+//! let peer = follower.connect(&miner).await?;
+//! follower.sync_from_peers(vec![peer]).await?;
+//! assert_eq!(follower.top_hash(), miner.top_hash());
+//! ```
+//!
+use std::future::{ready, Ready};
+use std::net::SocketAddr;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use monero_serai::{
+    block::{Block, BlockHeader},
+    transaction::Output,
+};
+use tower::{Service, ServiceExt};
+
+use cuprate_common::{blob::RawBlock, Network};
+use cuprate_peer::{
+    address_book::{AddressBookError, AddressBookRequest, AddressBookResponse},
+    block_downloader::BlockDownloaderConfig,
+    client::{ClientChain, ClientPeer, HandShakeError, NetworkAddress, NetworkConfig},
+    peer::{Handshake, Handshaker},
+    protocol::Direction,
+    request_handler::{PeerRequestHandler, RequestHandlerConfig},
+};
+use monero_consensus::{
+    block::reward::calculate_base_reward,
+    hardforks::HardFork,
+    misbehaviour::BlockSource,
+    sync::add_synced_blocks,
+    test_utils::{dummy_miner_tx, dummy_output, MemoryDatabase},
+    verifier::{Config, VerificationProfile},
+    ConsensusError,
+};
+
+use crate::{chain::NodeChain, Node, NodeBuilder, NodeError, SyncError};
+
+/// The size of the in-memory streams between nodes.
+const STREAM_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// A connection from a [`TestNode`] to another.
+pub type TestPeer = ClientPeer<NodeChain<MemoryDatabase>>;
+
+/// A node on a [`MemoryDatabase`], see the [module docs](self).
+pub struct TestNode {
+    node: Node<MemoryDatabase>,
+    database: MemoryDatabase,
+    /// The handshaker of the node's connections, answering from its chain.
+    handshaker: Handshaker<ClientChain<NodeChain<MemoryDatabase>>, NoAddressBook>,
+    /// The nonce of the blocks we mine, so two nodes mining on the same chain fork it.
+    miner_nonce: u32,
+}
+
+impl TestNode {
+    /// Returns a node for this network with only its genesis block, checking blocks with
+    /// [`VerificationProfile::Regtest`].
+    pub async fn new(network: Network) -> Result<TestNode, NodeError> {
+        let config = Config::for_network(network).with_profile(VerificationProfile::Regtest);
+        TestNode::from_builder(network, NodeBuilder::from_config(config)).await
+    }
+
+    /// Returns a node for this network built with `builder`, with only its genesis block.
+    pub async fn from_builder(
+        network: Network,
+        builder: NodeBuilder,
+    ) -> Result<TestNode, NodeError> {
+        let database = MemoryDatabase::default();
+        let node = builder.with_genesis().build(database.clone()).await?;
+
+        let handshaker = Handshaker::new(
+            NetworkConfig::for_network(network),
+            NoAddressBook,
+            ClientChain::new(node.chain()),
+        );
+
+        Ok(TestNode {
+            node,
+            database,
+            handshaker,
+            miner_nonce: rand::random(),
+        })
+    }
+
+    /// Returns the node.
+    pub fn node(&self) -> &Node<MemoryDatabase> {
+        &self.node
+    }
+
+    /// Returns the node, to run its tasks or verify its queued blocks.
+    pub fn node_mut(&mut self) -> &mut Node<MemoryDatabase> {
+        &mut self.node
+    }
+
+    /// Returns the node's database.
+    pub fn database(&self) -> &MemoryDatabase {
+        &self.database
+    }
+
+    /// Returns the height of the node's chain.
+    pub fn chain_height(&self) -> u64 {
+        self.node.verifier.context().chain_height
+    }
+
+    /// Returns the hash of the top block of the node's chain.
+    pub fn top_hash(&self) -> [u8; 32] {
+        self.node.verifier.context().top_hash
+    }
+
+    /// Adds `count` blocks without transactions to the top of the node's chain.
+    ///
+    /// The blocks are added like blocks synced from a peer and have a nonce of our choosing,
+    /// different for every node, so their PoW is only valid for a node that doesn't check it.
+    pub async fn mine_blocks(&mut self, count: u64) -> Result<(), ConsensusError> {
+        let context = self.node.verifier.context();
+        let hf = context.current_hf;
+
+        let mut previous = context.top_hash;
+        let mut generated_coins = context.already_generated_coins;
+        let blocks = (context.chain_height..context.chain_height + count)
+            .map(|height| {
+                // The blocks are far below the median weight, so they get the whole base reward.
+                let reward = calculate_base_reward(generated_coins, &hf);
+                generated_coins += reward;
+                let output = Output {
+                    view_tag: (hf >= HardFork::V15).then_some(0),
+                    ..dummy_output(Some(reward))
+                };
+
+                let block = Block {
+                    header: BlockHeader {
+                        major_version: hf as u8,
+                        minor_version: hf as u8,
+                        timestamp: height * 120,
+                        previous,
+                        nonce: self.miner_nonce,
+                    },
+                    miner_tx: dummy_miner_tx(
+                        if hf >= HardFork::V4 { 2 } else { 1 },
+                        Some(height),
+                        vec![output],
+                    ),
+                    txs: vec![],
+                };
+                previous = block.hash();
+
+                RawBlock {
+                    block: Bytes::from(block.serialize()),
+                    txs: vec![],
+                    prunable_hashes: None,
+                    block_weight: 0,
+                }
+            })
+            .collect();
+
+        let Node {
+            verifier, handles, ..
+        } = &mut self.node;
+        add_synced_blocks(
+            verifier,
+            blocks,
+            BlockSource::Rpc,
+            context.chain_height + count,
+            handles.database.clone(),
+        )
+        .await?;
+        handles.context.update(verifier);
+        Ok(())
+    }
+
+    /// Connects to `other` over an in-memory stream, returning our side of the connection.
+    ///
+    /// `other` answers our requests from its chain until the connection is dropped, like a node
+    /// serving an inbound peer.
+    pub async fn connect(&self, other: &TestNode) -> Result<TestPeer, HandShakeError> {
+        let (our_stream, their_stream) = tokio::io::duplex(STREAM_BUFFER_SIZE);
+
+        let their_handshake = other.handshaker.clone().oneshot(Handshake {
+            stream: their_stream,
+            direction: Direction::Inbound,
+            addr: loopback_address(1),
+        });
+        let mut request_handler = PeerRequestHandler::new(
+            ClientChain::new(other.node.chain()),
+            RequestHandlerConfig::default(),
+        );
+        tokio::spawn(async move {
+            let Ok(mut peer) = their_handshake.await else {
+                return;
+            };
+            // Messages that are not requests, like new blocks, are ignored.
+            while request_handler.serve(&mut peer).await.is_ok() {}
+        });
+
+        self.handshaker
+            .clone()
+            .oneshot(Handshake {
+                stream: our_stream,
+                direction: Direction::Outbound,
+                addr: loopback_address(2),
+            })
+            .await
+    }
+
+    /// Syncs the node's chain from the connected peers, see [`Node::sync_from_peers`], returning
+    /// the peers that are still connected.
+    pub async fn sync_from_peers(
+        &mut self,
+        peers: Vec<TestPeer>,
+    ) -> Result<Vec<TestPeer>, SyncError> {
+        self.node
+            .sync_from_peers(peers, NoAddressBook, BlockDownloaderConfig::default())
+            .await
+    }
+}
+
+/// Returns an address on the loopback interface, only used in logs and bans.
+fn loopback_address(port: u16) -> NetworkAddress {
+    SocketAddr::from(([127, 0, 0, 1], port)).into()
+}
+
+/// An address book that stores nothing, nodes are connected to each other by the tests.
+#[derive(Debug, Clone, Copy)]
+struct NoAddressBook;
+
+impl Service<AddressBookRequest> for NoAddressBook {
+    type Response = AddressBookResponse;
+    type Error = AddressBookError;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: AddressBookRequest) -> Self::Future {
+        ready(match req {
+            AddressBookRequest::GetPeersToGossip(_) => Ok(AddressBookResponse::Peers(vec![])),
+            AddressBookRequest::GetRandomGrayPeer(_)
+            | AddressBookRequest::GetRandomWhitePeer(_)
+            | AddressBookRequest::GetPublicNodes(_)
+            | AddressBookRequest::GetOutboundCandidate(..) => Err(AddressBookError::PeerListEmpty),
+            _ => Ok(AddressBookResponse::Ok),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn nodes_sync_from_each_other() {
+        let mut miner = TestNode::new(Network::Mainnet).await.unwrap();
+        let mut follower = TestNode::new(Network::Mainnet).await.unwrap();
+        assert_eq!(miner.top_hash(), follower.top_hash());
+
+        miner.mine_blocks(150).await.unwrap();
+        assert_eq!(miner.chain_height(), 151);
+
+        let peer = follower.connect(&miner).await.unwrap();
+        follower.sync_from_peers(vec![peer]).await.unwrap();
+        assert_eq!(follower.chain_height(), 151);
+        assert_eq!(follower.top_hash(), miner.top_hash());
+        assert_eq!(follower.database().block_hash(150), Some(miner.top_hash()));
+
+        // The follower now serves the chain it synced to a third node.
+        let mut third = TestNode::new(Network::Mainnet).await.unwrap();
+        let peer = third.connect(&follower).await.unwrap();
+        third.sync_from_peers(vec![peer]).await.unwrap();
+        assert_eq!(third.top_hash(), miner.top_hash());
+    }
+
+    #[tokio::test]
+    async fn nodes_of_different_networks_run_side_by_side() {
+        let mut mainnet = TestNode::new(Network::Mainnet).await.unwrap();
+        let mut testnet = TestNode::new(Network::Testnet).await.unwrap();
+        assert_ne!(mainnet.top_hash(), testnet.top_hash());
+
+        mainnet.mine_blocks(5).await.unwrap();
+        testnet.mine_blocks(10).await.unwrap();
+        assert_eq!(mainnet.chain_height(), 6);
+        assert_eq!(testnet.chain_height(), 11);
+
+        // Peers of another network are rejected in the handshake.
+        assert!(mainnet.connect(&testnet).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn nodes_mining_on_the_same_chain_fork_it() {
+        let mut a = TestNode::new(Network::Mainnet).await.unwrap();
+        let mut b = TestNode::new(Network::Mainnet).await.unwrap();
+
+        a.mine_blocks(3).await.unwrap();
+        b.mine_blocks(3).await.unwrap();
+        assert_eq!(a.chain_height(), b.chain_height());
+        assert_ne!(a.top_hash(), b.top_hash());
+    }
+}
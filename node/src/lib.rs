@@ -0,0 +1,55 @@
+//! # Cuprate Node
+//!
+//! This crate is a Monero full node as a library, so other Rust applications can run a node in
+//! their own process. The `cuprate` binary is a thin wrapper around it.
+//!
+//! A node is built with [`NodeBuilder`], which picks the subsystems to run and takes their config:
+//!
+//! ```ignore
+//! let node = NodeBuilder::new(Network::Mainnet)
+//!     .with_tx_pool(TxPoolConfig::default())
+//!     .with_rpc("127.0.0.1:18081".parse()?, RpcConfig::default())
+//!     .build(database)
+//!     .await?;
+//!
+//! let mut events = node.handles.subscribe();
+//! tokio::spawn(node.tasks.run());
+//! ```
+//!
+//! The built [`Node`] is split in parts that can be moved to different tasks:
+//! - [`Node::verifier`], the main chain's [`Verifier`](monero_consensus::verifier::Verifier).
+//! - [`Node::handles`], cloneable handles to the node's services: the database, the context, the tx
+//!   pool and the block queue, along with a stream of [`NodeEvent`]s.
+//! - [`Node::block_receiver`], the blocks queued for the verifier, tagged with their source, like the
+//!   blocks from the RPC's `submit_block` when the node is built with
+//!   [`NodeBuilder::with_block_submission`].
+//! - [`Node::tasks`], the background tasks of the subsystems: the RPC server, the tx pool's expiry
+//!   and the subscriptions of the notifier and the ZMQ publisher to the events, and the checks of
+//!   the public nodes' RPC ports when the node is given an address book with
+//!   [`NodeBuilder::with_address_book`]. [`Node::take_tasks`] takes them while the rest of the
+//!   node is kept together.
+//!
+//! The owner of the verifier syncs the chain from connected peers with [`Node::sync_from_peers`],
+//! verifies the queued blocks with [`Node::verify_queued_blocks`], and [`Node::chain`] answers the
+//! peers' requests for our chain.
+//!
+//! With the `test_utils` feature, the [`harness`] runs several nodes in one process on in-memory
+//! databases, connected to each other over in-memory streams.
+//!
+pub mod builder;
+pub mod chain;
+mod error;
+pub mod events;
+#[cfg(any(test, feature = "test_utils"))]
+pub mod harness;
+pub mod node;
+pub mod public_nodes;
+pub mod sync;
+
+pub use builder::NodeBuilder;
+pub use chain::NodeChain;
+pub use error::NodeError;
+pub use events::NodeEvent;
+pub use node::{Node, NodeHandles, NodeTasks, NodeTxPool, TxVerifierSvc};
+pub use public_nodes::NodeAddressBook;
+pub use sync::SyncError;
@@ -0,0 +1,190 @@
+//! # Node
+//!
+//! This module contains [`Node`], a built node, split in parts that can be moved to different tasks.
+//!
+use std::net::SocketAddr;
+
+use futures::future::{pending, try_join5, Either};
+use monero_serai::transaction::Transaction;
+use tokio::sync::broadcast;
+use tower::util::BoxCloneService;
+
+use cuprate_peer::address_book::{check_rpc_ports, DEFAULT_RPC_PORT_CHECK_INTERVAL};
+use cuprate_rpc::{notify::Notifier, zmq::ZmqPublisher, RpcHandler};
+use monero_consensus::{
+    context::ContextService,
+    misbehaviour::QueuedBlock,
+    read_scheduler::Scheduled,
+    txpool::{expire_transactions_task, TxPoolService, VerifiedTx},
+    verification_queue::{QueueReceiver, QueueSender},
+    verifier::Verifier,
+    ConsensusError, Database,
+};
+
+use crate::{
+    events::{notify_events, publish_events},
+    public_nodes::NodeAddressBook,
+    NodeError, NodeEvent,
+};
+
+/// A boxed [`TxVerifierService`](monero_consensus::txpool::TxVerifierService).
+pub type TxVerifierSvc = BoxCloneService<Transaction, VerifiedTx, ConsensusError>;
+
+/// The tx pool service of a node.
+pub type NodeTxPool<D> = TxPoolService<TxVerifierSvc, Scheduled<D>>;
+
+/// A built node, see [`NodeBuilder`](crate::NodeBuilder).
+pub struct Node<D> {
+    /// The verifier of the main chain.
+    ///
+    /// The owner of the verifier should call [`ContextService::update`] on
+    /// [`NodeHandles::context`] after every change to the chain, and
+    /// [`Verifier::report_failure`] for every queued block that fails verification.
+    pub verifier: Verifier,
+    /// Handles to the node's services.
+    pub handles: NodeHandles<D>,
+    /// The blocks queued for the verifier, see [`NodeHandles::block_queue`].
+    pub block_receiver: QueueReceiver<QueuedBlock>,
+    /// The background tasks of the node's subsystems.
+    pub tasks: NodeTasks<D>,
+}
+
+/// Handles to a node's services, clones of the handles share the same services.
+pub struct NodeHandles<D> {
+    pub(crate) database: Scheduled<D>,
+    pub(crate) context: ContextService,
+    pub(crate) tx_pool: Option<NodeTxPool<D>>,
+    pub(crate) block_queue: QueueSender<QueuedBlock>,
+    pub(crate) events: broadcast::Sender<NodeEvent>,
+}
+
+impl<D: Clone> Clone for NodeHandles<D> {
+    fn clone(&self) -> Self {
+        NodeHandles {
+            database: self.database.clone(),
+            context: self.context.clone(),
+            tx_pool: self.tx_pool.clone(),
+            block_queue: self.block_queue.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<D> NodeHandles<D> {
+    /// Returns the node's database, its reads are started before the RPC server's.
+    pub fn database(&self) -> &Scheduled<D> {
+        &self.database
+    }
+
+    /// Returns the service of the main chain's context.
+    pub fn context(&self) -> &ContextService {
+        &self.context
+    }
+
+    /// Returns the tx pool, [`None`] if the node was built without one.
+    pub fn tx_pool(&self) -> Option<&NodeTxPool<D>> {
+        self.tx_pool.as_ref()
+    }
+
+    /// Returns the queue of blocks waiting for the verifier, the verifier takes them from
+    /// [`Node::block_receiver`].
+    pub fn block_queue(&self) -> &QueueSender<QueuedBlock> {
+        &self.block_queue
+    }
+
+    /// Subscribes to the node's events, only events sent after subscribing are received.
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+}
+
+impl<D> Node<D> {
+    /// Takes the background tasks, leaving none behind, so they can be run while the node is
+    /// borrowed to sync or to verify its queued blocks.
+    pub fn take_tasks(&mut self) -> NodeTasks<D> {
+        let events = self.tasks.events.clone();
+        std::mem::replace(
+            &mut self.tasks,
+            NodeTasks {
+                rpc: None,
+                tx_pool: None,
+                notifier: None,
+                zmq_publisher: None,
+                address_book: None,
+                events,
+            },
+        )
+    }
+}
+
+/// The background tasks of a node's subsystems.
+pub struct NodeTasks<D> {
+    pub(crate) rpc: Option<(SocketAddr, RpcHandler<Scheduled<D>, ContextService>)>,
+    pub(crate) tx_pool: Option<NodeTxPool<D>>,
+    pub(crate) notifier: Option<(Notifier, broadcast::Receiver<NodeEvent>)>,
+    pub(crate) zmq_publisher: Option<(ZmqPublisher, broadcast::Receiver<NodeEvent>)>,
+    pub(crate) address_book: Option<NodeAddressBook>,
+    pub(crate) events: broadcast::Sender<NodeEvent>,
+}
+
+impl<D> NodeTasks<D>
+where
+    D: Database + Clone + Send + Sync + 'static,
+    D::Future: Send + 'static,
+{
+    /// Runs the tasks, returning when one of them fails.
+    ///
+    /// Without a subsystem that needs a task this never returns.
+    pub async fn run(self) -> Result<(), NodeError> {
+        let rpc = match self.rpc {
+            Some((addr, handler)) => Either::Left(async move {
+                cuprate_rpc::server::serve(addr, handler)
+                    .await
+                    .map_err(NodeError::from)
+            }),
+            None => Either::Right(pending()),
+        };
+
+        let events = self.events;
+        let tx_pool = match self.tx_pool {
+            Some(tx_pool) => Either::Left(async move {
+                expire_transactions_task(tx_pool, |expired| {
+                    let _ = events.send(NodeEvent::TxsExpired(
+                        expired.into_iter().map(|tx| tx.hash).collect(),
+                    ));
+                })
+                .await
+                .map_err(NodeError::from)
+            }),
+            None => Either::Right(pending()),
+        };
+
+        let notifier = match self.notifier {
+            Some((notifier, events)) => Either::Left(async move {
+                notify_events(notifier, events).await;
+                Ok(())
+            }),
+            None => Either::Right(pending()),
+        };
+
+        let zmq_publisher = match self.zmq_publisher {
+            Some((zmq_publisher, events)) => Either::Left(async move {
+                publish_events(zmq_publisher, events).await;
+                Ok(())
+            }),
+            None => Either::Right(pending()),
+        };
+
+        let rpc_port_checks = match self.address_book {
+            Some(address_book) => Either::Left(async move {
+                check_rpc_ports(DEFAULT_RPC_PORT_CHECK_INTERVAL, address_book).await;
+                Ok(())
+            }),
+            None => Either::Right(pending()),
+        };
+
+        try_join5(rpc, tx_pool, notifier, zmq_publisher, rpc_port_checks)
+            .await
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,60 @@
+//! # Public Nodes
+//!
+//! This module contains [`AddressBookPublicNodes`], which lists the public nodes of the address book
+//! given to [`NodeBuilder::with_address_book`](crate::NodeBuilder::with_address_book) for the RPC's
+//! `get_public_nodes`. The node checks the advertised RPC ports of these nodes in the background,
+//! see [`check_rpc_ports`](cuprate_peer::address_book::check_rpc_ports).
+//!
+use std::sync::Mutex;
+
+use futures::{future::BoxFuture, FutureExt};
+use tower::{util::BoxCloneService, ServiceExt};
+
+use cuprate_peer::address_book::{AddressBookError, AddressBookRequest, AddressBookResponse};
+use cuprate_rpc::{methods::PublicNode, PublicNodeSource, PublicNodes};
+use monero_wire::{messages::PeerListEntryBase, network_address::NetZone};
+
+/// A boxed address book service.
+pub type NodeAddressBook =
+    BoxCloneService<AddressBookRequest, AddressBookResponse, AddressBookError>;
+
+/// A [`PublicNodeSource`] listing the public nodes of an address book.
+#[derive(Debug)]
+pub struct AddressBookPublicNodes(Mutex<NodeAddressBook>);
+
+impl AddressBookPublicNodes {
+    /// Returns a source listing the public nodes of this address book.
+    pub fn new(address_book: NodeAddressBook) -> Self {
+        AddressBookPublicNodes(Mutex::new(address_book))
+    }
+}
+
+impl PublicNodeSource for AddressBookPublicNodes {
+    fn public_nodes(&self) -> BoxFuture<'static, Result<PublicNodes, tower::BoxError>> {
+        let address_book = self.0.lock().unwrap().clone();
+
+        async move {
+            let AddressBookResponse::PublicNodes { white, gray } = address_book
+                .oneshot(AddressBookRequest::GetPublicNodes(NetZone::Public))
+                .await?
+            else {
+                panic!("Address book sent incorrect response!");
+            };
+
+            Ok(PublicNodes {
+                white: white.iter().map(public_node).collect(),
+                gray: gray.iter().map(public_node).collect(),
+            })
+        }
+        .boxed()
+    }
+}
+
+fn public_node(peer: &PeerListEntryBase) -> PublicNode {
+    PublicNode {
+        host: peer.adr.ip().to_string(),
+        last_seen: peer.last_seen.try_into().unwrap_or(0),
+        rpc_port: peer.rpc_port,
+        rpc_credits_per_hash: peer.rpc_credits_per_hash,
+    }
+}
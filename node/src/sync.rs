@@ -0,0 +1,211 @@
+//! # Sync
+//!
+//! This module syncs a node's chain from connected peers, see [`Node::sync_from_peers`].
+//!
+//! The blocks are downloaded with a [`BlockDownloader`] and added to the chain a batch at a time with
+//! [`add_synced_blocks`]. A peer that sends an invalid block is reported to the verifier like any
+//! other misbehaving peer and the sync stops.
+//!
+//! The blocks queued for the verifier, like the blocks from the RPC's `submit_block`, are added the
+//! same way by [`Node::verify_queued_blocks`], with their transactions taken from the tx pool.
+//!
+use bytes::Bytes;
+use futures::{channel::mpsc, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower::{BoxError, Service, ServiceExt};
+
+use cuprate_common::blob::RawBlock;
+use cuprate_peer::{
+    address_book::{AddressBookError, AddressBookRequest, AddressBookResponse},
+    block_downloader::{BlockDownloadError, BlockDownloader, BlockDownloaderConfig},
+    client::{BlockchainRequest, BlockchainResponse, ClientChain, Peer},
+};
+use monero_consensus::{
+    fluffy_block::FluffyBlock,
+    misbehaviour::{BlockSource, PeerId, QueuedBlock},
+    spans::BLOCK_TARGET,
+    sync::add_synced_blocks,
+    ConsensusError, Database, DatabaseRequest,
+};
+
+use crate::Node;
+
+/// An error that stopped a sync.
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    #[error("Failed to download blocks: {0}")]
+    Download(#[from] BlockDownloadError),
+    #[error("Failed to add downloaded blocks: {0}")]
+    Verify(#[from] ConsensusError),
+}
+
+impl<D> Node<D>
+where
+    D: Database + Clone + Send + Sync + 'static,
+    D::Future: Send + 'static,
+{
+    /// Downloads the blocks on the peers' chains that are not on ours and adds them to our chain, until
+    /// no peer has a block we need.
+    ///
+    /// Returns the peers that are still connected.
+    pub async fn sync_from_peers<S, Bc, AdrBook>(
+        &mut self,
+        peers: Vec<Peer<S, ClientChain<Bc>>>,
+        address_book: AdrBook,
+        config: BlockDownloaderConfig,
+    ) -> Result<Vec<Peer<S, ClientChain<Bc>>>, SyncError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        Bc: Service<BlockchainRequest, Response = BlockchainResponse>,
+        Bc::Error: Into<BoxError>,
+        AdrBook:
+            Service<AddressBookRequest, Response = AddressBookResponse, Error = AddressBookError>,
+    {
+        let target_height = peers
+            .iter()
+            .map(|peer| peer.info().core_sync_data.current_height)
+            .max()
+            .unwrap_or(0);
+        let chain_history = chain_history(
+            self.handles.database.clone(),
+            self.verifier.context().chain_height,
+        )
+        .await?;
+
+        // The downloader bounds the batches it holds, so it is only let one batch ahead of us.
+        let (batch_tx, mut batch_rx) = mpsc::channel(0);
+        let download = BlockDownloader::new(peers, address_book, config)
+            .download_blocks(chain_history, batch_tx);
+
+        let (verifier, handles) = (&mut self.verifier, &self.handles);
+        let verify = async move {
+            while let Some(batch) = batch_rx.next().await {
+                add_synced_blocks(
+                    verifier,
+                    batch.blocks,
+                    BlockSource::Peer(PeerId(batch.peer_id)),
+                    target_height,
+                    handles.database.clone(),
+                )
+                .await?;
+                handles.context.update(verifier);
+            }
+            Ok::<_, ConsensusError>(())
+        };
+
+        let (downloaded, verified) = futures::join!(download, verify);
+        // An invalid block stops the download, so its error is the one returned.
+        verified?;
+        Ok(downloaded?)
+    }
+
+    /// Verifies the blocks queued for the verifier and adds the valid ones to our chain, until every
+    /// [`QueueSender`](monero_consensus::verification_queue::QueueSender) of the queue is dropped.
+    ///
+    /// The transactions of a block are taken from the tx pool, a block with a transaction not in
+    /// the pool is dropped. An invalid block is reported with its source and dropped, only an error
+    /// on our side, like a database failure, stops this.
+    pub async fn verify_queued_blocks(&mut self) -> Result<(), ConsensusError> {
+        while let Some((_, QueuedBlock { source, block })) = self.block_receiver.next().await {
+            let mut fluffy_block = match FluffyBlock::new(block, vec![]) {
+                Ok(fluffy_block) => fluffy_block,
+                Err(e) => {
+                    tracing::warn!(target: BLOCK_TARGET, "Dropping queued block: {e}");
+                    continue;
+                }
+            };
+            if let Some(tx_pool) = self.handles.tx_pool.clone() {
+                if let Err(e) = fluffy_block.add_pool_txs(tx_pool).await {
+                    tracing::warn!(target: BLOCK_TARGET, "Dropping queued block: {e}");
+                    continue;
+                }
+            }
+            let full_block = match fluffy_block.complete() {
+                Ok(full_block) => full_block,
+                Err(e) => {
+                    tracing::warn!(target: BLOCK_TARGET, "Dropping queued block: {e}");
+                    continue;
+                }
+            };
+
+            let raw = RawBlock {
+                block: Bytes::from(full_block.block.serialize()),
+                txs: full_block
+                    .txs
+                    .iter()
+                    .map(|tx| Bytes::from(tx.serialize()))
+                    .collect(),
+                prunable_hashes: None,
+                block_weight: 0,
+            };
+            let target_height = self.verifier.context().chain_height + 1;
+            match add_synced_blocks(
+                &mut self.verifier,
+                vec![raw],
+                source,
+                target_height,
+                self.handles.database.clone(),
+            )
+            .await
+            {
+                Ok(()) => self.handles.context.update(&self.verifier),
+                // The failure was reported to the verifier.
+                Err(ConsensusError::Internal(e)) => return Err(ConsensusError::Internal(e)),
+                Err(_) => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns our sparse chain history, the IDs of the blocks at [`chain_history_heights`].
+pub(crate) async fn chain_history<D: Database>(
+    mut database: D,
+    chain_height: u64,
+) -> Result<Vec<[u8; 32]>, ConsensusError> {
+    let mut history = Vec::new();
+    for height in chain_history_heights(chain_height) {
+        history.push(
+            database
+                .ready()
+                .await?
+                .call(DatabaseRequest::BlockHash(height))
+                .await?
+                .into_block_hash()?,
+        );
+    }
+    Ok(history)
+}
+
+/// Returns the heights of the blocks in our sparse chain history, like monerod: the top 11 blocks,
+/// then blocks twice as far apart each time and lastly the genesis block.
+fn chain_history_heights(chain_height: u64) -> Vec<u64> {
+    let mut heights = Vec::new();
+    let (mut back_offset, mut multiplier) = (1, 1);
+    while back_offset < chain_height {
+        heights.push(chain_height - back_offset);
+        if heights.len() <= 10 {
+            back_offset += 1;
+        } else {
+            multiplier *= 2;
+            back_offset += multiplier;
+        }
+    }
+    heights.push(0);
+    heights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::chain_history_heights;
+
+    #[test]
+    fn chain_history_is_sparse() {
+        assert_eq!(chain_history_heights(1), [0]);
+        assert_eq!(chain_history_heights(3), [2, 1, 0]);
+        assert_eq!(
+            chain_history_heights(30),
+            [29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 17, 13, 5, 0]
+        );
+    }
+}
@@ -1,12 +1,133 @@
+//! # Address Book
+//!
+//! This module contains the address book, which keeps the peers we know about for each network
+//! zone in three lists:
+//! - The white list: peers we have connected to.
+//! - The gray list: peers other peers told us about.
+//! - The anchor list: peers we were connected to, which we should connect to first on start up.
+//!
+//! Peers that fail to connect are moved from the white list to the gray list and removed from the
+//! gray list after [`AddressBookConfig`]'s `max_failures` failures. Peers can be banned until a time,
+//! either by address or by [`IpSubnet`], banned peers are removed from the lists and ignored in new
+//! peer lists.
+//!
+//! Ban lists, see [`parse_ban_list`], ban their subnets until the list they came from is updated
+//! without them.
+//!
+//! Outbound candidates are never picked from the [diversity subnet](IpSubnet::diversity_subnet) of
+//! a peer we already have an outbound connection to, so one network can't fill all our connections.
+//! [`Ipv6Peers`] sets whether IPv6 peers are used.
+//!
+//! The lists are loaded from an [`AddressBookStore`] when the address book starts and saved to it
+//! periodically and when the address book is dropped, [`PeerFileStore`] keeps them in files on disk.
+//!
+//! The RPC ports peers advertise are kept with them, so public nodes can be listed, and are checked
+//! by [`check_rpc_ports`].
+//!
 mod addr_book_client;
+#[allow(clippy::module_inception)]
 pub(crate) mod address_book;
+mod ban_list;
+mod peer_store;
+mod rpc_ports;
 
 pub use addr_book_client::start_address_book;
+#[cfg(feature = "ban-list")]
+pub use ban_list::{download_ban_list, subscribe_to_ban_list};
+pub use ban_list::{parse_ban_list, DEFAULT_BAN_LIST_REFRESH_INTERVAL};
+pub use peer_store::PeerFileStore;
+pub use rpc_ports::{check_rpc_ports, DEFAULT_RPC_PORT_CHECK_INTERVAL, RPC_PORT_CHECKS_PER_ROUND};
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::Duration;
 
 use monero_wire::{messages::PeerListEntryBase, network_address::NetZone, NetworkAddress};
 
 const MAX_WHITE_LIST_PEERS: usize = 1000;
 const MAX_GRAY_LIST_PEERS: usize = 5000;
+/// The default amount of connection failures after which a gray peer is removed.
+pub const DEFAULT_MAX_PEER_FAILURES: u32 = 3;
+/// The default interval between saves of the peer lists, monerod uses the same.
+pub const DEFAULT_SAVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// The chance, in percent, that an outbound candidate is picked from the white list.
+const WHITE_LIST_CANDIDATE_PERCENT: u32 = 70;
+/// The prefix length of the subnets outbound connections to IPv4 peers are spread over.
+pub const IPV4_DIVERSITY_PREFIX_LEN: u8 = 16;
+/// The prefix length of the subnets outbound connections to IPv6 peers are spread over, a /64 is
+/// usually given to a single customer.
+pub const IPV6_DIVERSITY_PREFIX_LEN: u8 = 64;
+
+/// An IP subnet, all the addresses with the first `prefix_len` bits of `ip`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpSubnet {
+    ip: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpSubnet {
+    /// Returns the subnet of `ip` with this prefix length, or [`None`] if the prefix is longer than
+    /// the address.
+    pub fn new(ip: IpAddr, prefix_len: u8) -> Option<IpSubnet> {
+        let ip = match ip {
+            IpAddr::V4(ip) if prefix_len <= 32 => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(prefix_len))
+                    .unwrap_or(0);
+                IpAddr::V4((u32::from(ip) & mask).into())
+            }
+            IpAddr::V6(ip) if prefix_len <= 128 => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(prefix_len))
+                    .unwrap_or(0);
+                IpAddr::V6((u128::from(ip) & mask).into())
+            }
+            _ => return None,
+        };
+
+        Some(IpSubnet { ip, prefix_len })
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns the subnet of the address that we keep at most one outbound connection to, a /16
+    /// for IPv4 addresses and a /64 for IPv6 addresses.
+    ///
+    /// IPv4-mapped IPv6 addresses are treated as IPv4 addresses.
+    pub fn diversity_subnet(addr: &NetworkAddress) -> IpSubnet {
+        let ip = addr.canonical_ip();
+        let prefix_len = match ip {
+            IpAddr::V4(_) => IPV4_DIVERSITY_PREFIX_LEN,
+            IpAddr::V6(_) => IPV6_DIVERSITY_PREFIX_LEN,
+        };
+        IpSubnet::new(ip, prefix_len).unwrap()
+    }
+
+    /// Returns true if the address is in this subnet.
+    ///
+    /// IPv4-mapped IPv6 addresses are in the IPv4 subnets they map to.
+    pub fn contains(&self, addr: &NetworkAddress) -> bool {
+        IpSubnet::new(addr.canonical_ip(), self.prefix_len) == Some(*self)
+    }
+}
+
+/// Whether IPv6 peers are used, IPv4-mapped IPv6 addresses count as IPv4.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Ipv6Peers {
+    /// IPv6 peers are ignored in peer lists and never picked as outbound candidates.
+    Decline,
+    /// IPv4 and IPv6 peers are treated the same.
+    #[default]
+    Allow,
+    /// IPv6 peers are picked as outbound candidates when there are any.
+    Prefer,
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum AddressBookError {
@@ -27,12 +148,33 @@ pub enum AddressBookRequest {
     HandleNewPeerList(Vec<PeerListEntryBase>, NetZone),
     SetPeerSeen(NetworkAddress, i64),
     BanPeer(NetworkAddress, chrono::NaiveDateTime),
+    /// Bans every peer in the subnet until the time.
+    BanSubnet(IpSubnet, chrono::NaiveDateTime),
+    /// Replaces the subnets banned by the ban list from this source, usually its URL.
+    UpdateBanList(String, Vec<IpSubnet>),
+    /// Records that we could not connect to a peer.
+    PeerConnectionFailed(NetworkAddress),
     AddPeerToAnchor(NetworkAddress),
     RemovePeerFromAnchor(NetworkAddress),
     UpdatePeerInfo(PeerListEntryBase),
+    /// Sets the RPC port and credits per hash a peer advertised in its handshake, this replaces
+    /// the values other peers told us.
+    SetPeerRpcPort(NetworkAddress, u16, u32),
+    /// Removes a peer's RPC port, for when the port could not be reached.
+    RemovePeerRpcPort(NetworkAddress),
 
     GetRandomGrayPeer(NetZone),
     GetRandomWhitePeer(NetZone),
+    /// Gets the peers that have advertised an RPC port.
+    GetPublicNodes(NetZone),
+    /// Gets a peer to make an outbound connection to, from the white list most of the time.
+    ///
+    /// The set is the [diversity subnets](IpSubnet::diversity_subnet) of our outbound connections,
+    /// peers in them are not picked.
+    GetOutboundCandidate(NetZone, HashSet<IpSubnet>),
+    /// Gets up to [`P2P_MAX_PEERS_IN_HANDSHAKE`](crate::protocol::P2P_MAX_PEERS_IN_HANDSHAKE)
+    /// random white peers to send to a peer, with their last seen time removed.
+    GetPeersToGossip(NetZone),
 }
 
 impl std::fmt::Display for AddressBookRequest {
@@ -41,11 +183,19 @@ impl std::fmt::Display for AddressBookRequest {
             Self::HandleNewPeerList(_, _) => f.write_str("HandleNewPeerList"),
             Self::SetPeerSeen(_, _) => f.write_str("SetPeerSeen"),
             Self::BanPeer(_, _) => f.write_str("BanPeer"),
+            Self::BanSubnet(_, _) => f.write_str("BanSubnet"),
+            Self::UpdateBanList(_, _) => f.write_str("UpdateBanList"),
+            Self::PeerConnectionFailed(_) => f.write_str("PeerConnectionFailed"),
             Self::AddPeerToAnchor(_) => f.write_str("AddPeerToAnchor"),
             Self::RemovePeerFromAnchor(_) => f.write_str("RemovePeerFromAnchor"),
             Self::UpdatePeerInfo(_) => f.write_str("UpdatePeerInfo"),
+            Self::SetPeerRpcPort(_, _, _) => f.write_str("SetPeerRpcPort"),
+            Self::RemovePeerRpcPort(_) => f.write_str("RemovePeerRpcPort"),
             Self::GetRandomGrayPeer(_) => f.write_str("GetRandomGrayPeer"),
             Self::GetRandomWhitePeer(_) => f.write_str("GetRandomWhitePeer"),
+            Self::GetPublicNodes(_) => f.write_str("GetPublicNodes"),
+            Self::GetOutboundCandidate(_, _) => f.write_str("GetOutboundCandidate"),
+            Self::GetPeersToGossip(_) => f.write_str("GetPeersToGossip"),
         }
     }
 }
@@ -56,12 +206,20 @@ impl AddressBookRequest {
             Self::HandleNewPeerList(_, zone) => *zone,
             Self::SetPeerSeen(peer, _) => peer.get_zone(),
             Self::BanPeer(peer, _) => peer.get_zone(),
+            Self::BanSubnet(_, _) => NetZone::Public,
+            Self::UpdateBanList(_, _) => NetZone::Public,
+            Self::PeerConnectionFailed(peer) => peer.get_zone(),
             Self::AddPeerToAnchor(peer) => peer.get_zone(),
             Self::RemovePeerFromAnchor(peer) => peer.get_zone(),
             Self::UpdatePeerInfo(peer) => peer.adr.get_zone(),
+            Self::SetPeerRpcPort(peer, _, _) => peer.get_zone(),
+            Self::RemovePeerRpcPort(peer) => peer.get_zone(),
 
             Self::GetRandomGrayPeer(zone) => *zone,
             Self::GetRandomWhitePeer(zone) => *zone,
+            Self::GetPublicNodes(zone) => *zone,
+            Self::GetOutboundCandidate(zone, _) => *zone,
+            Self::GetPeersToGossip(zone) => *zone,
         }
     }
 }
@@ -70,12 +228,24 @@ impl AddressBookRequest {
 pub enum AddressBookResponse {
     Ok,
     Peer(PeerListEntryBase),
+    Peers(Vec<PeerListEntryBase>),
+    /// The peers with an RPC port in the white and gray list.
+    PublicNodes {
+        white: Vec<PeerListEntryBase>,
+        gray: Vec<PeerListEntryBase>,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub struct AddressBookConfig {
     max_white_peers: usize,
     max_gray_peers: usize,
+    /// The amount of connection failures after which a gray peer is removed.
+    max_failures: u32,
+    /// The interval between saves of the peer lists to the peer store.
+    save_interval: Duration,
+    /// Whether IPv6 peers are used.
+    ipv6_peers: Ipv6Peers,
 }
 
 impl Default for AddressBookConfig {
@@ -83,10 +253,30 @@ impl Default for AddressBookConfig {
         AddressBookConfig {
             max_white_peers: MAX_WHITE_LIST_PEERS,
             max_gray_peers: MAX_GRAY_LIST_PEERS,
+            max_failures: DEFAULT_MAX_PEER_FAILURES,
+            save_interval: DEFAULT_SAVE_INTERVAL,
+            ipv6_peers: Ipv6Peers::Allow,
         }
     }
 }
 
+impl AddressBookConfig {
+    pub fn with_max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = max_failures;
+        self
+    }
+
+    pub fn with_save_interval(mut self, save_interval: Duration) -> Self {
+        self.save_interval = save_interval;
+        self
+    }
+
+    pub fn with_ipv6_peers(mut self, ipv6_peers: Ipv6Peers) -> Self {
+        self.ipv6_peers = ipv6_peers;
+        self
+    }
+}
+
 #[async_trait::async_trait]
 pub trait AddressBookStore: Clone {
     type Error: Into<AddressBookError>;
@@ -95,7 +285,8 @@ pub trait AddressBookStore: Clone {
     /// the white list,
     /// the gray list,
     /// the anchor list,
-    /// the ban list
+    /// the ban list,
+    /// the subnet ban list
     async fn load_peers(
         &mut self,
         zone: NetZone,
@@ -105,6 +296,7 @@ pub trait AddressBookStore: Clone {
             Vec<PeerListEntryBase>,                       // gray list
             Vec<NetworkAddress>,                          // anchor list
             Vec<(NetworkAddress, chrono::NaiveDateTime)>, // ban list
+            Vec<(IpSubnet, chrono::NaiveDateTime)>,       // subnet ban list
         ),
         Self::Error,
     >;
@@ -116,5 +308,6 @@ pub trait AddressBookStore: Clone {
         gray: Vec<PeerListEntryBase>,
         anchor: Vec<NetworkAddress>,
         bans: Vec<(NetworkAddress, chrono::NaiveDateTime)>, // ban lists
+        subnet_bans: Vec<(IpSubnet, chrono::NaiveDateTime)>,
     ) -> Result<(), Self::Error>;
 }
@@ -32,7 +32,7 @@ pub async fn start_address_book<S>(
     AddressBookError,
 >
 where
-    S: AddressBookStore,
+    S: AddressBookStore + Send + 'static,
 {
     let mut builder = AddressBookBuilder::new(peer_store, config);
 
@@ -59,21 +59,29 @@ pub struct AddressBookBuilder<S> {
 
 impl<S> AddressBookBuilder<S>
 where
-    S: AddressBookStore,
+    S: AddressBookStore + Send + 'static,
 {
     fn new(peer_store: S, config: AddressBookConfig) -> Self {
         AddressBookBuilder { peer_store, config }
     }
 
     async fn build(&mut self, zone: NetZone) -> Result<AddressBookClient, AddressBookError> {
-        let (white, gray, anchor, bans) =
+        let (white, gray, anchor, bans, subnet_bans) =
             self.peer_store.load_peers(zone).await.map_err(Into::into)?;
 
-        let book = AddressBook::new(self.config.clone(), zone, white, gray, anchor, bans);
+        let book = AddressBook::new(
+            self.config.clone(),
+            zone,
+            white,
+            gray,
+            anchor,
+            bans,
+            subnet_bans,
+        );
 
         let (tx, rx) = mpsc::channel(5);
 
-        spawn(book.run(rx));
+        spawn(book.run(rx, self.peer_store.clone()));
 
         Ok(AddressBookClient { book: tx })
     }
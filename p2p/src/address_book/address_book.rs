@@ -4,13 +4,17 @@ use futures::{
     channel::{mpsc, oneshot},
     StreamExt,
 };
-use rand::{Rng, SeedableRng};
-use std::time::Duration;
+use rand::{seq::IteratorRandom, Rng, SeedableRng};
+use tokio::time;
 
 use cuprate_common::PruningSeed;
 use monero_wire::{messages::PeerListEntryBase, network_address::NetZone, NetworkAddress};
 
-use super::{AddressBookConfig, AddressBookError, AddressBookRequest, AddressBookResponse};
+use super::{
+    AddressBookConfig, AddressBookError, AddressBookRequest, AddressBookResponse, AddressBookStore,
+    IpSubnet, Ipv6Peers, WHITE_LIST_CANDIDATE_PERCENT,
+};
+use crate::protocol::P2P_MAX_PEERS_IN_HANDSHAKE;
 
 mod peer_list;
 use peer_list::PeerList;
@@ -30,9 +34,13 @@ pub struct AddressBook {
     anchor_list: HashSet<NetworkAddress>,
 
     baned_peers: HashMap<NetworkAddress, chrono::NaiveDateTime>,
+    banned_subnets: HashMap<IpSubnet, chrono::NaiveDateTime>,
+    /// The subnets banned by each ban list, these bans last until the list is updated.
+    ban_lists: HashMap<String, HashSet<IpSubnet>>,
+    /// The connection failures of peers since they were last seen.
+    peer_failures: HashMap<NetworkAddress, u32>,
 
     rng: rand::rngs::StdRng,
-    //banned_subnets:,
 }
 
 impl AddressBook {
@@ -43,12 +51,14 @@ impl AddressBook {
         gray_peers: Vec<PeerListEntryBase>,
         anchor_peers: Vec<NetworkAddress>,
         baned_peers: Vec<(NetworkAddress, chrono::NaiveDateTime)>,
+        banned_subnets: Vec<(IpSubnet, chrono::NaiveDateTime)>,
     ) -> AddressBook {
         let rng = rand::prelude::StdRng::from_entropy();
         let white_list = PeerList::new(white_peers);
         let gray_list = PeerList::new(gray_peers);
         let anchor_list = HashSet::from_iter(anchor_peers);
         let baned_peers = HashMap::from_iter(baned_peers);
+        let banned_subnets = HashMap::from_iter(banned_subnets);
 
         let mut book = AddressBook {
             zone,
@@ -57,10 +67,14 @@ impl AddressBook {
             gray_list,
             anchor_list,
             baned_peers,
+            banned_subnets,
+            ban_lists: HashMap::new(),
+            peer_failures: HashMap::new(),
             rng,
         };
 
         book.check_unban_peers();
+        book.remove_banned_peers();
 
         book
     }
@@ -73,10 +87,12 @@ impl AddressBook {
         }
     }
 
+    #[cfg(test)]
     fn len_white_list(&self) -> usize {
         self.white_list.len()
     }
 
+    #[cfg(test)]
     fn len_gray_list(&self) -> usize {
         self.gray_list.len()
     }
@@ -91,11 +107,47 @@ impl AddressBook {
 
     fn is_peer_banned(&self, peer: &NetworkAddress) -> bool {
         self.baned_peers.contains_key(peer)
+            || self
+                .banned_subnets
+                .keys()
+                .any(|subnet| subnet.contains(peer))
+            || self
+                .ban_lists
+                .values()
+                .flatten()
+                .any(|subnet| subnet.contains(peer))
+    }
+
+    fn is_ipv6_declined(&self, peer: &NetworkAddress) -> bool {
+        self.config.ipv6_peers == Ipv6Peers::Decline && peer.is_ipv6()
     }
 
     fn check_unban_peers(&mut self) {
         let mut now = chrono::Utc::now().naive_utc();
-        self.baned_peers.retain(|_, time| time > &mut now)
+        self.baned_peers.retain(|_, time| time > &mut now);
+        self.banned_subnets.retain(|_, time| time > &mut now);
+    }
+
+    fn remove_peer_from_lists(&mut self, peer: &NetworkAddress) {
+        let _ = self.white_list.remove_peer(peer);
+        let _ = self.gray_list.remove_peer(peer);
+        let _ = self.anchor_list.remove(peer);
+        let _ = self.peer_failures.remove(peer);
+    }
+
+    fn remove_banned_peers(&mut self) {
+        let banned_peers: Vec<_> = self
+            .white_list
+            .iter()
+            .chain(self.gray_list.iter())
+            .map(|peer| peer.adr)
+            .chain(self.anchor_list.iter().copied())
+            .filter(|peer| self.is_peer_banned(peer))
+            .collect();
+
+        for peer in banned_peers {
+            self.remove_peer_from_lists(&peer);
+        }
     }
 
     fn ban_peer(&mut self, peer: NetworkAddress, till: chrono::NaiveDateTime) {
@@ -107,6 +159,51 @@ impl AddressBook {
         tracing::debug!("Banning peer: {peer:?} until: {till}");
 
         self.baned_peers.insert(peer, till);
+        self.remove_peer_from_lists(&peer);
+    }
+
+    fn ban_subnet(&mut self, subnet: IpSubnet, till: chrono::NaiveDateTime) {
+        let now = chrono::Utc::now().naive_utc();
+        if now > till {
+            return;
+        }
+
+        tracing::debug!("Banning subnet: {subnet:?} until: {till}");
+
+        self.banned_subnets.insert(subnet, till);
+        self.remove_banned_peers();
+    }
+
+    fn update_ban_list(&mut self, source: String, subnets: Vec<IpSubnet>) {
+        tracing::debug!("Updating ban list: {source}, entries: {}", subnets.len());
+
+        if subnets.is_empty() {
+            let _ = self.ban_lists.remove(&source);
+        } else {
+            self.ban_lists.insert(source, subnets.into_iter().collect());
+            self.remove_banned_peers();
+        }
+    }
+
+    /// Records a failed connection to a peer, white peers are moved to the gray list and gray
+    /// peers are removed once they have failed `max_failures` times.
+    fn peer_connection_failed(&mut self, peer: NetworkAddress) -> Result<(), AddressBookError> {
+        let _ = self.anchor_list.remove(&peer);
+
+        if let Some(peer_eb) = self.white_list.remove_peer(&peer) {
+            tracing::debug!("Moving peer: {peer:?} to the gray list after a failed connection");
+            self.gray_list.add_new_peer(peer_eb);
+        } else if !self.gray_list.contains_peer(&peer) {
+            return Err(AddressBookError::PeerNotFound);
+        }
+
+        let failures = self.peer_failures.entry(peer).or_default();
+        *failures += 1;
+        if *failures >= self.config.max_failures {
+            tracing::debug!("Removing peer: {peer:?} after {failures} failed connections");
+            self.remove_peer_from_lists(&peer);
+        }
+        Ok(())
     }
 
     fn add_peer_to_anchor(&mut self, peer: NetworkAddress) -> Result<(), AddressBookError> {
@@ -134,9 +231,13 @@ impl AddressBook {
         peer: NetworkAddress,
         last_seen: i64,
     ) -> Result<(), AddressBookError> {
+        let _ = self.peer_failures.remove(&peer);
+
         if let Some(mut peer) = self.gray_list.remove_peer(&peer) {
             peer.last_seen = last_seen;
             self.white_list.add_new_peer(peer);
+            self.white_list
+                .reduce_list(&self.anchor_list, self.max_white_peers());
         } else {
             let peer = self
                 .white_list
@@ -151,7 +252,11 @@ impl AddressBook {
         if self.white_list.contains_peer(&peer.adr) {
             return;
         };
-        if !self.gray_list.contains_peer(&peer.adr) {
+        if let Some(peer_stored) = self.gray_list.get_peer_mut(&peer.adr) {
+            // Keep the most recently advertised RPC port.
+            peer_stored.rpc_port = peer.rpc_port;
+            peer_stored.rpc_credits_per_hash = peer.rpc_credits_per_hash;
+        } else {
             peer.last_seen = 0;
             self.gray_list.add_new_peer(peer);
         }
@@ -167,43 +272,93 @@ impl AddressBook {
 
         let mut err = None;
         peers.retain(|peer| {
-            if err.is_some() {
-                false
-            } else if peer.adr.is_local() || peer.adr.is_loopback() {
-                false
-            } else if peer.adr.port() == peer.rpc_port {
-                false
-            } else if PruningSeed::try_from(peer.pruning_seed).is_err() {
+            if err.is_some()
+                || peer.adr.is_local()
+                || peer.adr.is_loopback()
+                || peer.adr.port() == peer.rpc_port
+                || PruningSeed::try_from(peer.pruning_seed).is_err()
+            {
                 false
             } else if peer.adr.get_zone() != self.zone {
                 tracing::info!("Received an address from a different network zone, ignoring list.");
                 err = Some(AddressBookError::PeerSentAnAddressOutOfZone);
                 false
-            } else if self.is_peer_banned(&peer.adr) {
-                false
             } else {
-                true
+                !self.is_peer_banned(&peer.adr) && !self.is_ipv6_declined(&peer.adr)
             }
         });
 
         if let Some(e) = err {
             return Err(e);
-        } else {
-            for peer in peers {
-                self.add_peer_to_gray_list(peer);
-            }
-            self.gray_list
-                .reduce_list(&HashSet::new(), self.max_gray_peers());
-            Ok(())
         }
+
+        for peer in peers {
+            self.add_peer_to_gray_list(peer);
+        }
+        self.gray_list
+            .reduce_list(&HashSet::new(), self.max_gray_peers());
+        // Forget the failures of peers the gray list dropped.
+        self.peer_failures.retain(|peer, _| {
+            self.gray_list.contains_peer(peer) || self.white_list.contains_peer(peer)
+        });
+        Ok(())
     }
 
     fn get_random_gray_peer(&mut self) -> Option<PeerListEntryBase> {
-        self.gray_list.get_random_peer(&mut self.rng).map(|p| *p)
+        self.gray_list.get_random_peer(&mut self.rng).copied()
     }
 
     fn get_random_white_peer(&mut self) -> Option<PeerListEntryBase> {
-        self.white_list.get_random_peer(&mut self.rng).map(|p| *p)
+        self.white_list.get_random_peer(&mut self.rng).copied()
+    }
+
+    /// Returns a peer to make an outbound connection to that is not in one of the
+    /// `connected_subnets`, the diversity subnets of our outbound connections.
+    fn get_outbound_candidate(
+        &mut self,
+        connected_subnets: &HashSet<IpSubnet>,
+    ) -> Option<PeerListEntryBase> {
+        let (first, second) = if self.rng.gen_ratio(WHITE_LIST_CANDIDATE_PERCENT, 100) {
+            (&self.white_list, &self.gray_list)
+        } else {
+            (&self.gray_list, &self.white_list)
+        };
+
+        let ipv6_peers = self.config.ipv6_peers;
+        let usable = |peer: &&PeerListEntryBase| {
+            (ipv6_peers != Ipv6Peers::Decline || !peer.adr.is_ipv6())
+                && !connected_subnets.contains(&IpSubnet::diversity_subnet(&peer.adr))
+        };
+        let rng = &mut self.rng;
+        let mut pick = |list: &PeerList| {
+            if ipv6_peers == Ipv6Peers::Prefer {
+                if let Some(peer) = list
+                    .iter()
+                    .filter(usable)
+                    .filter(|peer| peer.adr.is_ipv6())
+                    .choose(rng)
+                {
+                    return Some(*peer);
+                }
+            }
+            list.iter().filter(usable).choose(rng).copied()
+        };
+
+        pick(first).or_else(|| pick(second))
+    }
+
+    fn get_peers_to_gossip(&mut self) -> Vec<PeerListEntryBase> {
+        self.white_list
+            .iter()
+            .copied()
+            .choose_multiple(&mut self.rng, P2P_MAX_PEERS_IN_HANDSHAKE)
+            .into_iter()
+            .map(|mut peer| {
+                // Don't tell peers when we last saw other peers.
+                peer.last_seen = 0;
+                peer
+            })
+            .collect()
     }
 
     fn update_peer_info(&mut self, peer: PeerListEntryBase) -> Result<(), AddressBookError> {
@@ -214,15 +369,99 @@ impl AddressBook {
             *peer_stored = peer;
             Ok(())
         } else {
-            return Err(AddressBookError::PeerNotFound);
+            Err(AddressBookError::PeerNotFound)
+        }
+    }
+
+    fn get_peer_mut(&mut self, peer: &NetworkAddress) -> Option<&mut PeerListEntryBase> {
+        match self.white_list.get_peer_mut(peer) {
+            Some(peer) => Some(peer),
+            None => self.gray_list.get_peer_mut(peer),
+        }
+    }
+
+    fn set_peer_rpc_port(
+        &mut self,
+        peer: NetworkAddress,
+        rpc_port: u16,
+        rpc_credits_per_hash: u32,
+    ) -> Result<(), AddressBookError> {
+        let peer_stored = self
+            .get_peer_mut(&peer)
+            .ok_or(AddressBookError::PeerNotFound)?;
+
+        // A peer can't have its RPC server on its p2p port.
+        if peer.port() == rpc_port {
+            peer_stored.rpc_port = 0;
+            peer_stored.rpc_credits_per_hash = 0;
+        } else {
+            peer_stored.rpc_port = rpc_port;
+            peer_stored.rpc_credits_per_hash = rpc_credits_per_hash;
+        }
+        Ok(())
+    }
+
+    fn remove_peer_rpc_port(&mut self, peer: NetworkAddress) -> Result<(), AddressBookError> {
+        let peer_stored = self
+            .get_peer_mut(&peer)
+            .ok_or(AddressBookError::PeerNotFound)?;
+
+        tracing::debug!("Removing RPC port of peer: {peer:?}");
+
+        peer_stored.rpc_port = 0;
+        peer_stored.rpc_credits_per_hash = 0;
+        Ok(())
+    }
+
+    fn get_public_nodes(&self) -> AddressBookResponse {
+        AddressBookResponse::PublicNodes {
+            white: self.white_list.get_peers_with_rpc_port().copied().collect(),
+            gray: self.gray_list.get_peers_with_rpc_port().copied().collect(),
+        }
+    }
+
+    async fn save_peers<S: AddressBookStore>(&mut self, peer_store: &mut S) {
+        self.check_unban_peers();
+
+        tracing::debug!("{} saving peers", self.book_name());
+
+        let res = peer_store
+            .save_peers(
+                self.zone,
+                self.white_list.iter().copied().collect(),
+                self.gray_list.iter().copied().collect(),
+                self.anchor_list.iter().copied().collect(),
+                self.baned_peers.iter().map(|(k, v)| (*k, *v)).collect(),
+                self.banned_subnets.iter().map(|(k, v)| (*k, *v)).collect(),
+            )
+            .await
+            .map_err(Into::into);
+
+        if let Err(e) = res {
+            tracing::warn!("{} failed to save peers, err: {e}", self.book_name());
         }
     }
 
-    pub(crate) async fn run(mut self, mut rx: mpsc::Receiver<AddressBookClientRequest>) {
+    pub(crate) async fn run<S: AddressBookStore>(
+        mut self,
+        mut rx: mpsc::Receiver<AddressBookClientRequest>,
+        mut peer_store: S,
+    ) {
+        let mut next_save = time::Instant::now() + self.config.save_interval;
+
         loop {
-            let Some(req) = rx.next().await else {
-                // the client has been dropped the node has *possibly* shut down 
-                return;
+            let req = match time::timeout_at(next_save, rx.next()).await {
+                Ok(Some(req)) => req,
+                Ok(None) => {
+                    // the client has been dropped the node has *possibly* shut down
+                    self.save_peers(&mut peer_store).await;
+                    return;
+                }
+                Err(_) => {
+                    self.save_peers(&mut peer_store).await;
+                    next_save = time::Instant::now() + self.config.save_interval;
+                    continue;
+                }
             };
 
             self.check_unban_peers();
@@ -243,6 +482,17 @@ impl AddressBook {
                     self.ban_peer(peer, till);
                     Ok(AddressBookResponse::Ok)
                 }
+                AddressBookRequest::BanSubnet(subnet, till) => {
+                    self.ban_subnet(subnet, till);
+                    Ok(AddressBookResponse::Ok)
+                }
+                AddressBookRequest::UpdateBanList(source, subnets) => {
+                    self.update_ban_list(source, subnets);
+                    Ok(AddressBookResponse::Ok)
+                }
+                AddressBookRequest::PeerConnectionFailed(peer) => self
+                    .peer_connection_failed(peer)
+                    .map(|_| AddressBookResponse::Ok),
                 AddressBookRequest::AddPeerToAnchor(peer) => self
                     .add_peer_to_anchor(peer)
                     .map(|_| AddressBookResponse::Ok),
@@ -253,6 +503,12 @@ impl AddressBook {
                 AddressBookRequest::UpdatePeerInfo(peer) => {
                     self.update_peer_info(peer).map(|_| AddressBookResponse::Ok)
                 }
+                AddressBookRequest::SetPeerRpcPort(peer, rpc_port, rpc_credits_per_hash) => self
+                    .set_peer_rpc_port(peer, rpc_port, rpc_credits_per_hash)
+                    .map(|_| AddressBookResponse::Ok),
+                AddressBookRequest::RemovePeerRpcPort(peer) => self
+                    .remove_peer_rpc_port(peer)
+                    .map(|_| AddressBookResponse::Ok),
 
                 AddressBookRequest::GetRandomGrayPeer(_) => match self.get_random_gray_peer() {
                     Some(peer) => Ok(AddressBookResponse::Peer(peer)),
@@ -262,6 +518,16 @@ impl AddressBook {
                     Some(peer) => Ok(AddressBookResponse::Peer(peer)),
                     None => Err(AddressBookError::PeerListEmpty),
                 },
+                AddressBookRequest::GetPublicNodes(_) => Ok(self.get_public_nodes()),
+                AddressBookRequest::GetOutboundCandidate(_, connected_subnets) => {
+                    match self.get_outbound_candidate(&connected_subnets) {
+                        Some(peer) => Ok(AddressBookResponse::Peer(peer)),
+                        None => Err(AddressBookError::PeerListEmpty),
+                    }
+                }
+                AddressBookRequest::GetPeersToGossip(_) => {
+                    Ok(AddressBookResponse::Peers(self.get_peers_to_gossip()))
+                }
             };
 
             if let Err(e) = &res {
@@ -272,3 +538,247 @@ impl AddressBook {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::SocketAddr;
+
+    use monero_wire::{messages::PeerListEntryBase, network_address::NetZone, NetworkAddress};
+
+    use super::AddressBook;
+    use crate::address_book::{AddressBookConfig, AddressBookResponse, IpSubnet, Ipv6Peers};
+
+    fn addr(port: u16) -> NetworkAddress {
+        SocketAddr::from(([8, 8, 8, 8], port)).into()
+    }
+
+    fn empty_book(config: AddressBookConfig) -> AddressBook {
+        AddressBook::new(
+            config,
+            NetZone::Public,
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+        )
+    }
+
+    fn peer(port: u16, rpc_port: u16) -> PeerListEntryBase {
+        PeerListEntryBase {
+            adr: addr(port),
+            id: port.into(),
+            last_seen: 0,
+            pruning_seed: 0,
+            rpc_port,
+            rpc_credits_per_hash: 0,
+        }
+    }
+
+    fn public_nodes(book: &AddressBook) -> (Vec<PeerListEntryBase>, Vec<PeerListEntryBase>) {
+        let AddressBookResponse::PublicNodes { white, gray } = book.get_public_nodes() else {
+            unreachable!()
+        };
+        (white, gray)
+    }
+
+    #[test]
+    fn public_nodes_track_advertised_rpc_ports() {
+        let mut book = AddressBook::new(
+            AddressBookConfig::default(),
+            NetZone::Public,
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+        );
+
+        book.handle_new_peerlist(vec![peer(1, 0), peer(2, 18081)])
+            .unwrap();
+        let (white, gray) = public_nodes(&book);
+        assert!(white.is_empty());
+        assert_eq!(gray, vec![peer(2, 18081)]);
+
+        // A newer peer list advertises a different port.
+        book.handle_new_peerlist(vec![peer(2, 18089)]).unwrap();
+        assert_eq!(public_nodes(&book).1, vec![peer(2, 18089)]);
+
+        // The port the peer told us itself is used once it is in the white list.
+        book.set_peer_seen(addr(1), 10).unwrap();
+        book.set_peer_rpc_port(addr(1), 18081, 5).unwrap();
+        let (white, _) = public_nodes(&book);
+        assert_eq!(white.len(), 1);
+        assert_eq!(white[0].rpc_port, 18081);
+        assert_eq!(white[0].rpc_credits_per_hash, 5);
+
+        book.remove_peer_rpc_port(addr(2)).unwrap();
+        assert!(public_nodes(&book).1.is_empty());
+
+        // A peer can't have its RPC server on its p2p port.
+        book.set_peer_rpc_port(addr(1), 1, 0).unwrap();
+        assert!(public_nodes(&book).0.is_empty());
+    }
+
+    #[test]
+    fn subnet_bans() {
+        let mut book = empty_book(AddressBookConfig::default());
+        let other_subnet_peer = PeerListEntryBase {
+            adr: SocketAddr::from(([9, 9, 9, 9], 1)).into(),
+            ..peer(1, 0)
+        };
+
+        book.handle_new_peerlist(vec![peer(1, 0), other_subnet_peer])
+            .unwrap();
+        book.set_peer_seen(addr(1), 10).unwrap();
+
+        let till = chrono::Utc::now().naive_utc() + chrono::Duration::hours(1);
+        book.ban_subnet(IpSubnet::new([8, 8, 0, 0].into(), 16).unwrap(), till);
+        assert_eq!(book.len_white_list(), 0);
+        assert_eq!(book.len_gray_list(), 1);
+
+        // Peers in the subnet are ignored in new peer lists.
+        book.handle_new_peerlist(vec![peer(2, 0)]).unwrap();
+        assert_eq!(book.len_gray_list(), 1);
+
+        // Bans that have ended are removed.
+        let past = chrono::Utc::now().naive_utc() - chrono::Duration::hours(1);
+        book.banned_subnets
+            .insert(IpSubnet::new([9, 9, 9, 0].into(), 24).unwrap(), past);
+        book.check_unban_peers();
+        assert_eq!(book.banned_subnets.len(), 1);
+    }
+
+    #[test]
+    fn failed_peers_are_demoted_then_removed() {
+        let mut book = empty_book(AddressBookConfig::default().with_max_failures(2));
+
+        book.handle_new_peerlist(vec![peer(1, 0)]).unwrap();
+        book.set_peer_seen(addr(1), 10).unwrap();
+        book.add_peer_to_anchor(addr(1)).unwrap();
+
+        book.peer_connection_failed(addr(1)).unwrap();
+        assert_eq!(book.len_white_list(), 0);
+        assert_eq!(book.len_gray_list(), 1);
+        assert!(book.anchor_list.is_empty());
+
+        book.peer_connection_failed(addr(1)).unwrap();
+        assert_eq!(book.len_gray_list(), 0);
+        assert!(book.peer_failures.is_empty());
+
+        assert!(book.peer_connection_failed(addr(1)).is_err());
+    }
+
+    #[test]
+    fn white_list_is_capped_keeping_anchors() {
+        let mut book = empty_book(AddressBookConfig {
+            max_white_peers: 1,
+            ..AddressBookConfig::default()
+        });
+
+        book.handle_new_peerlist(vec![peer(1, 0), peer(2, 0)])
+            .unwrap();
+        book.set_peer_seen(addr(1), 10).unwrap();
+        book.add_peer_to_anchor(addr(1)).unwrap();
+
+        book.set_peer_seen(addr(2), 10).unwrap();
+        assert_eq!(book.len_white_list(), 1);
+        assert!(book.white_list.contains_peer(&addr(1)));
+    }
+
+    #[test]
+    fn peer_selection() {
+        let mut book = empty_book(AddressBookConfig::default());
+        assert!(book.get_outbound_candidate(&HashSet::new()).is_none());
+
+        book.handle_new_peerlist(vec![peer(1, 0), peer(2, 0)])
+            .unwrap();
+        assert!(book.get_outbound_candidate(&HashSet::new()).is_some());
+        // Only white peers are gossiped.
+        assert!(book.get_peers_to_gossip().is_empty());
+
+        book.set_peer_seen(addr(1), 10).unwrap();
+        let gossip = book.get_peers_to_gossip();
+        assert_eq!(gossip, vec![peer(1, 0)]);
+        assert_eq!(gossip[0].last_seen, 0);
+    }
+
+    #[test]
+    fn ban_lists_replace_their_bans() {
+        let mut book = empty_book(AddressBookConfig::default());
+        let subnet = IpSubnet::new([8, 8, 8, 0].into(), 24).unwrap();
+
+        book.handle_new_peerlist(vec![peer(1, 0)]).unwrap();
+        book.update_ban_list("list".to_string(), vec![subnet]);
+        assert_eq!(book.len_gray_list(), 0);
+        assert!(book.is_peer_banned(&addr(1)));
+
+        // Another list doesn't touch the first list's bans.
+        book.update_ban_list("other list".to_string(), vec![]);
+        assert!(book.is_peer_banned(&addr(1)));
+
+        // The subnet was removed from the list.
+        book.update_ban_list("list".to_string(), vec![]);
+        assert!(!book.is_peer_banned(&addr(1)));
+        book.handle_new_peerlist(vec![peer(1, 0)]).unwrap();
+        assert_eq!(book.len_gray_list(), 1);
+    }
+
+    #[test]
+    fn outbound_candidates_are_diverse() {
+        let mut book = empty_book(AddressBookConfig::default());
+        let same_ipv4_subnet = PeerListEntryBase {
+            adr: SocketAddr::from(([8, 8, 9, 9], 1)).into(),
+            ..peer(1, 0)
+        };
+        let same_ipv6_subnet = |ip: &str| PeerListEntryBase {
+            adr: format!("[{ip}]:18080")
+                .parse::<SocketAddr>()
+                .unwrap()
+                .into(),
+            ..peer(2, 0)
+        };
+
+        book.handle_new_peerlist(vec![same_ipv4_subnet]).unwrap();
+        let connected = HashSet::from([IpSubnet::diversity_subnet(&addr(1))]);
+        assert!(book.get_outbound_candidate(&connected).is_none());
+
+        // IPv4-mapped addresses are in the IPv4 subnet.
+        let mapped: NetworkAddress = "[::ffff:8.8.1.1]:1".parse::<SocketAddr>().unwrap().into();
+        assert!(connected.contains(&IpSubnet::diversity_subnet(&mapped)));
+
+        book.handle_new_peerlist(vec![same_ipv6_subnet("2001:db8::1")])
+            .unwrap();
+        let connected = HashSet::from([IpSubnet::diversity_subnet(
+            &same_ipv6_subnet("2001:db8::ffff:1").adr,
+        )]);
+        assert_eq!(
+            book.get_outbound_candidate(&connected).unwrap().adr,
+            same_ipv4_subnet.adr
+        );
+    }
+
+    #[test]
+    fn ipv6_peer_config() {
+        let ipv6_peer = PeerListEntryBase {
+            adr: "[2001:db8::1]:18080".parse::<SocketAddr>().unwrap().into(),
+            ..peer(2, 0)
+        };
+
+        let mut book = empty_book(AddressBookConfig::default().with_ipv6_peers(Ipv6Peers::Decline));
+        book.handle_new_peerlist(vec![peer(1, 0), ipv6_peer])
+            .unwrap();
+        assert_eq!(book.len_gray_list(), 1);
+
+        let mut book = empty_book(AddressBookConfig::default().with_ipv6_peers(Ipv6Peers::Prefer));
+        book.handle_new_peerlist(vec![peer(1, 0), ipv6_peer])
+            .unwrap();
+        for _ in 0..10 {
+            assert_eq!(
+                book.get_outbound_candidate(&HashSet::new()).unwrap().adr,
+                ipv6_peer.adr
+            );
+        }
+    }
+}
@@ -32,14 +32,19 @@ impl PeerList {
     }
 
     pub fn add_new_peer(&mut self, peer: PeerListEntryBase) {
-        if self.peers.insert(peer.adr, peer.clone()).is_none() {
+        if self.peers.insert(peer.adr, peer).is_none() {
             self.pruning_idxs
                 .entry(peer.pruning_seed)
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(peer.adr);
         }
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = &PeerListEntryBase> {
+        self.peers.values()
+    }
+
+    #[cfg(test)]
     pub fn get_peer(&self, peer: &NetworkAddress) -> Option<&PeerListEntryBase> {
         self.peers.get(peer)
     }
@@ -67,6 +72,12 @@ impl PeerList {
         self.peers.contains_key(peer)
     }
 
+    /// Returns the peers that have advertised an RPC port.
+    pub fn get_peers_with_rpc_port(&self) -> impl Iterator<Item = &PeerListEntryBase> {
+        self.peers.values().filter(|peer| peer.rpc_port != 0)
+    }
+
+    #[cfg(test)]
     pub fn get_peers_by_pruning_seed(
         &self,
         seed: &u32,
@@ -102,7 +113,7 @@ impl PeerList {
         let mut removed_count = 0;
         let mut peers_to_remove: Vec<NetworkAddress> = Vec::with_capacity(target_removed);
 
-        for (peer_adr, _) in &self.peers {
+        for peer_adr in self.peers.keys() {
             if removed_count >= target_removed {
                 break;
             }
@@ -120,31 +131,40 @@ impl PeerList {
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashSet, vec};
+    use std::{
+        collections::HashSet,
+        net::{Ipv4Addr, SocketAddrV4},
+    };
 
     use monero_wire::{messages::PeerListEntryBase, NetworkAddress};
     use rand::Rng;
 
     use super::PeerList;
 
-    fn make_fake_peer_list(numb_o_peers: usize) -> PeerList {
-        let mut peer_list = vec![PeerListEntryBase::default(); numb_o_peers];
-        for (idx, peer) in peer_list.iter_mut().enumerate() {
-            let NetworkAddress::IPv4(ip) =  &mut peer.adr else {panic!("this test requires default to be ipv4")};
-            ip.m_ip += idx as u32;
+    fn make_fake_address(idx: u32) -> NetworkAddress {
+        NetworkAddress::IPv4(SocketAddrV4::new(Ipv4Addr::from(idx), 0))
+    }
+
+    fn make_fake_peer(idx: u32) -> PeerListEntryBase {
+        PeerListEntryBase {
+            adr: make_fake_address(idx),
+            id: 0,
+            last_seen: 0,
+            pruning_seed: 0,
+            rpc_port: 0,
+            rpc_credits_per_hash: 0,
         }
+    }
 
-        PeerList::new(peer_list)
+    fn make_fake_peer_list(numb_o_peers: u32) -> PeerList {
+        PeerList::new((0..numb_o_peers).map(make_fake_peer).collect())
     }
 
-    fn make_fake_peer_list_with_random_pruning_seeds(numb_o_peers: usize) -> PeerList {
+    fn make_fake_peer_list_with_random_pruning_seeds(numb_o_peers: u32) -> PeerList {
         let mut r = rand::thread_rng();
 
-        let mut peer_list = vec![PeerListEntryBase::default(); numb_o_peers];
-        for (idx, peer) in peer_list.iter_mut().enumerate() {
-            let NetworkAddress::IPv4(ip) =  &mut peer.adr else {panic!("this test requires default to be ipv4")};
-            ip.m_ip += idx as u32;
-
+        let mut peer_list: Vec<_> = (0..numb_o_peers).map(make_fake_peer).collect();
+        for peer in peer_list.iter_mut() {
             peer.pruning_seed = if r.gen_bool(0.4) {
                 0
             } else {
@@ -170,7 +190,7 @@ mod tests {
     #[test]
     fn peer_list_reduce_length_with_peers_we_need() {
         let mut peer_list = make_fake_peer_list(500);
-        let must_keep_peers = HashSet::from_iter(peer_list.peers.iter().map(|(adr, _)| *adr));
+        let must_keep_peers = peer_list.peers.keys().copied().collect();
 
         let target_len = 49;
 
@@ -207,9 +227,7 @@ mod tests {
         let mut peer_list = make_fake_peer_list_with_random_pruning_seeds(100);
 
         // generate peer at a random point in the list
-        let mut peer = NetworkAddress::default();
-        let NetworkAddress::IPv4(ip) =  &mut peer else {panic!("this test requires default to be ipv4")};
-        ip.m_ip += 50;
+        let peer = make_fake_address(50);
 
         assert!(peer_list.remove_peer(&peer).is_some());
 
@@ -241,11 +259,9 @@ mod tests {
     #[test]
     fn peer_list_add_new_peer() {
         let mut peer_list = make_fake_peer_list(10);
-        let mut new_peer = PeerListEntryBase::default();
-        let NetworkAddress::IPv4(ip) =  &mut new_peer.adr else {panic!("this test requires default to be ipv4")};
-        ip.m_ip += 50;
+        let new_peer = make_fake_peer(50);
 
-        peer_list.add_new_peer(new_peer.clone());
+        peer_list.add_new_peer(new_peer);
 
         assert_eq!(peer_list.len(), 11);
         assert_eq!(peer_list.get_peer(&new_peer.adr), Some(&new_peer));
@@ -259,12 +275,9 @@ mod tests {
     #[test]
     fn peer_list_add_existing_peer() {
         let mut peer_list = make_fake_peer_list(10);
-        let existing_peer = peer_list
-            .get_peer(&NetworkAddress::default())
-            .unwrap()
-            .clone();
+        let existing_peer = *peer_list.get_peer(&make_fake_address(0)).unwrap();
 
-        peer_list.add_new_peer(existing_peer.clone());
+        peer_list.add_new_peer(existing_peer);
 
         assert_eq!(peer_list.len(), 10);
         assert_eq!(peer_list.get_peer(&existing_peer.adr), Some(&existing_peer));
@@ -273,9 +286,7 @@ mod tests {
     #[test]
     fn peer_list_get_non_existent_peer() {
         let peer_list = make_fake_peer_list(10);
-        let mut non_existent_peer = NetworkAddress::default();
-        let NetworkAddress::IPv4(ip) =  &mut non_existent_peer else {panic!("this test requires default to be ipv4")};
-        ip.m_ip += 50;
+        let non_existent_peer = make_fake_address(50);
 
         assert_eq!(peer_list.get_peer(&non_existent_peer), None);
     }
@@ -0,0 +1,146 @@
+//! # Ban Lists
+//!
+//! This module contains the parser for ban lists, plain text files with one IP or subnet in CIDR
+//! notation per line, the format of monerod's `--ban-list` files and of the lists many public node
+//! operators share. Anything after a `#` is a comment.
+//!
+//! With the `ban-list` feature, [`subscribe_to_ban_list`] downloads a ban list from a URL and
+//! downloads it again periodically. The bans of a list are tagged with its URL in the address book
+//! and each download replaces them, so entries removed from the list are unbanned. If a download
+//! fails the bans from the last download are kept.
+//!
+use std::net::IpAddr;
+use std::time::Duration;
+
+use super::IpSubnet;
+
+/// The default interval between downloads of a ban list.
+pub const DEFAULT_BAN_LIST_REFRESH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+fn parse_entry(entry: &str) -> Option<IpSubnet> {
+    match entry.split_once('/') {
+        Some((ip, prefix_len)) => IpSubnet::new(ip.parse().ok()?, prefix_len.parse().ok()?),
+        None => {
+            let ip: IpAddr = entry.parse().ok()?;
+            IpSubnet::new(ip, if ip.is_ipv4() { 32 } else { 128 })
+        }
+    }
+}
+
+/// Parses a ban list, invalid entries are skipped.
+pub fn parse_ban_list(list: &str) -> Vec<IpSubnet> {
+    list.lines()
+        .filter_map(|line| {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                return None;
+            }
+
+            let subnet = parse_entry(entry);
+            if subnet.is_none() {
+                tracing::debug!("Skipping invalid ban list entry: {entry}");
+            }
+            subnet
+        })
+        .collect()
+}
+
+#[cfg(feature = "ban-list")]
+mod subscription {
+    use std::time::Duration;
+
+    use tower::{Service, ServiceExt};
+
+    use super::parse_ban_list;
+    use crate::address_book::{
+        AddressBookError, AddressBookRequest, AddressBookResponse, IpSubnet,
+    };
+
+    /// Downloads and parses the ban list at the URL.
+    pub async fn download_ban_list(
+        client: &reqwest::Client,
+        url: &str,
+    ) -> Result<Vec<IpSubnet>, reqwest::Error> {
+        let list = client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(parse_ban_list(&list))
+    }
+
+    /// Downloads the ban list at the URL every `refresh_interval` and replaces the address book's
+    /// bans from it, returns when the address book is closed.
+    pub async fn subscribe_to_ban_list<AdrBook>(
+        url: String,
+        refresh_interval: Duration,
+        mut address_book: AdrBook,
+    ) where
+        AdrBook:
+            Service<AddressBookRequest, Response = AddressBookResponse, Error = AddressBookError>,
+    {
+        let client = reqwest::Client::new();
+        let mut interval = tokio::time::interval(refresh_interval);
+
+        loop {
+            interval.tick().await;
+
+            let subnets = match download_ban_list(&client, &url).await {
+                Ok(subnets) => subnets,
+                Err(e) => {
+                    tracing::warn!("Failed to download ban list: {url}, err: {e}");
+                    continue;
+                }
+            };
+
+            tracing::info!("Downloaded ban list: {url}, entries: {}", subnets.len());
+
+            let res = match address_book.ready().await {
+                Ok(address_book) => {
+                    address_book
+                        .call(AddressBookRequest::UpdateBanList(url.clone(), subnets))
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+
+            match res {
+                Ok(_) => (),
+                Err(AddressBookError::AddressBooksChannelClosed) => return,
+                Err(e) => tracing::warn!("Failed to update ban list: {url}, err: {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ban-list")]
+pub use subscription::{download_ban_list, subscribe_to_ban_list};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ban_list_entries() {
+        let list = "\
+# A comment
+1.2.3.4
+10.0.0.0/8 # a subnet
+
+2001:db8::/32
+not an ip
+1.2.3.4/33
+";
+
+        assert_eq!(
+            parse_ban_list(list),
+            vec![
+                IpSubnet::new([1, 2, 3, 4].into(), 32).unwrap(),
+                IpSubnet::new([10, 0, 0, 0].into(), 8).unwrap(),
+                IpSubnet::new("2001:db8::".parse().unwrap(), 32).unwrap(),
+            ]
+        );
+    }
+}
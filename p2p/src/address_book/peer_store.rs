@@ -0,0 +1,230 @@
+//! # Peer Store
+//!
+//! This module contains [`PeerFileStore`], an [`AddressBookStore`] that keeps the peer lists of each
+//! network zone in an epee encoded file in a directory.
+//!
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+use epee_encoding::{from_bytes, to_bytes, EpeeObject};
+use monero_wire::{messages::PeerListEntryBase, network_address::NetZone, NetworkAddress};
+
+use super::{AddressBookError, AddressBookStore, IpSubnet};
+
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+struct BanEntry {
+    adr: NetworkAddress,
+    /// The UNIX time the ban ends.
+    till: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+struct SubnetBanEntry {
+    /// The 4 or 16 bytes of the subnet's IP.
+    ip: Vec<u8>,
+    prefix_len: u8,
+    /// The UNIX time the ban ends.
+    till: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+struct PeerFile {
+    white: Vec<PeerListEntryBase>,
+    gray: Vec<PeerListEntryBase>,
+    anchor: Vec<NetworkAddress>,
+    bans: Vec<BanEntry>,
+    subnet_bans: Vec<SubnetBanEntry>,
+}
+
+fn ban_end(till: i64) -> Result<chrono::NaiveDateTime, AddressBookError> {
+    chrono::DateTime::from_timestamp(till, 0)
+        .map(|till| till.naive_utc())
+        .ok_or(AddressBookError::PeerStoreError(
+            "Peer file has an invalid ban time",
+        ))
+}
+
+/// Keeps the peer lists in files named `p2p_state_<zone>.bin` in a directory, the directory is
+/// created on the first save.
+#[derive(Debug, Clone)]
+pub struct PeerFileStore {
+    dir: PathBuf,
+}
+
+impl PeerFileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> PeerFileStore {
+        PeerFileStore { dir: dir.into() }
+    }
+
+    fn file_path(&self, zone: NetZone) -> PathBuf {
+        self.dir.join(match zone {
+            NetZone::Public => "p2p_state_public.bin",
+            NetZone::Tor => "p2p_state_tor.bin",
+            NetZone::I2p => "p2p_state_i2p.bin",
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl AddressBookStore for PeerFileStore {
+    type Error = AddressBookError;
+
+    async fn load_peers(
+        &mut self,
+        zone: NetZone,
+    ) -> Result<
+        (
+            Vec<PeerListEntryBase>,
+            Vec<PeerListEntryBase>,
+            Vec<NetworkAddress>,
+            Vec<(NetworkAddress, chrono::NaiveDateTime)>,
+            Vec<(IpSubnet, chrono::NaiveDateTime)>,
+        ),
+        Self::Error,
+    > {
+        let bytes = match tokio::fs::read(self.file_path(zone)).await {
+            Ok(bytes) => bytes,
+            // We have not saved this zone's peers yet.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok((vec![], vec![], vec![], vec![], vec![]))
+            }
+            Err(_) => return Err(AddressBookError::PeerStoreError("Could not read peer file")),
+        };
+
+        let file: PeerFile = from_bytes(&bytes)
+            .map_err(|_| AddressBookError::PeerStoreError("Peer file is not valid"))?;
+
+        let bans = file
+            .bans
+            .into_iter()
+            .map(|ban| Ok((ban.adr, ban_end(ban.till)?)))
+            .collect::<Result<_, AddressBookError>>()?;
+
+        let subnet_bans = file
+            .subnet_bans
+            .into_iter()
+            .map(|ban| {
+                let ip = match ban.ip.len() {
+                    4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(ban.ip).unwrap())),
+                    16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(ban.ip).unwrap())),
+                    _ => {
+                        return Err(AddressBookError::PeerStoreError(
+                            "Peer file has an invalid IP",
+                        ))
+                    }
+                };
+                let subnet = IpSubnet::new(ip, ban.prefix_len).ok_or(
+                    AddressBookError::PeerStoreError("Peer file has an invalid subnet"),
+                )?;
+                Ok((subnet, ban_end(ban.till)?))
+            })
+            .collect::<Result<_, AddressBookError>>()?;
+
+        Ok((file.white, file.gray, file.anchor, bans, subnet_bans))
+    }
+
+    async fn save_peers(
+        &mut self,
+        zone: NetZone,
+        white: Vec<PeerListEntryBase>,
+        gray: Vec<PeerListEntryBase>,
+        anchor: Vec<NetworkAddress>,
+        bans: Vec<(NetworkAddress, chrono::NaiveDateTime)>,
+        subnet_bans: Vec<(IpSubnet, chrono::NaiveDateTime)>,
+    ) -> Result<(), Self::Error> {
+        let file = PeerFile {
+            white,
+            gray,
+            anchor,
+            bans: bans
+                .into_iter()
+                .map(|(adr, till)| BanEntry {
+                    adr,
+                    till: till.and_utc().timestamp(),
+                })
+                .collect(),
+            subnet_bans: subnet_bans
+                .into_iter()
+                .map(|(subnet, till)| SubnetBanEntry {
+                    ip: match subnet.ip() {
+                        IpAddr::V4(ip) => ip.octets().to_vec(),
+                        IpAddr::V6(ip) => ip.octets().to_vec(),
+                    },
+                    prefix_len: subnet.prefix_len(),
+                    till: till.and_utc().timestamp(),
+                })
+                .collect(),
+        };
+
+        let bytes = to_bytes(&file)
+            .map_err(|_| AddressBookError::PeerStoreError("Could not encode peer file"))?;
+
+        let write_err = |_| AddressBookError::PeerStoreError("Could not write peer file");
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(write_err)?;
+
+        // Write to a temporary file first so a crash can't leave a half written peer file.
+        let path = self.file_path(zone);
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, bytes)
+            .await
+            .map_err(write_err)?;
+        tokio::fs::rename(tmp_path, path).await.map_err(write_err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn peer_file_round_trip() {
+        let dir =
+            std::env::temp_dir().join(format!("cuprate-peer-store-{}", rand::random::<u64>()));
+        let mut store = PeerFileStore::new(&dir);
+
+        let (white, ..) = store.load_peers(NetZone::Public).await.unwrap();
+        assert!(white.is_empty());
+
+        let addr: NetworkAddress = "8.8.8.8:18080".parse::<SocketAddr>().unwrap().into();
+        let peer = PeerListEntryBase {
+            adr: addr,
+            id: 1,
+            last_seen: 10,
+            pruning_seed: 0,
+            rpc_port: 18081,
+            rpc_credits_per_hash: 0,
+        };
+        let till = ban_end(2_000_000_000).unwrap();
+        let subnet = IpSubnet::new("10.1.0.0".parse().unwrap(), 16).unwrap();
+
+        store
+            .save_peers(
+                NetZone::Public,
+                vec![peer],
+                vec![],
+                vec![addr],
+                vec![(addr, till)],
+                vec![(subnet, till)],
+            )
+            .await
+            .unwrap();
+
+        let loaded = store.load_peers(NetZone::Public).await.unwrap();
+        assert_eq!(
+            loaded,
+            (
+                vec![peer],
+                vec![],
+                vec![addr],
+                vec![(addr, till)],
+                vec![(subnet, till)]
+            )
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+//! # RPC Port Checks
+//!
+//! Peers advertise the port of their RPC server in their handshake and in the peer lists they send,
+//! which is how wallets find public nodes with `get_public_nodes`. Nothing stops a peer advertising a
+//! port that isn't open, so [`check_rpc_ports`] validates the ports opportunistically: every interval
+//! it picks up to [`RPC_PORT_CHECKS_PER_ROUND`] random public nodes from the address book and tries
+//! to open a TCP connection to each advertised port. The RPC port of a peer that can't be reached is
+//! removed with [`AddressBookRequest::RemovePeerRpcPort`], until a peer advertises it again.
+//!
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::future::join_all;
+use rand::seq::SliceRandom;
+use tokio::net::TcpStream;
+use tower::{Service, ServiceExt};
+
+use monero_wire::{messages::PeerListEntryBase, network_address::NetZone};
+
+use super::{AddressBookError, AddressBookRequest, AddressBookResponse};
+
+/// The default interval between rounds of RPC port checks.
+pub const DEFAULT_RPC_PORT_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// The most public nodes checked per round.
+pub const RPC_PORT_CHECKS_PER_ROUND: usize = 16;
+/// How long we wait for a connection to an RPC port.
+const RPC_PORT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checks the advertised RPC ports of random public nodes every `interval`, see the
+/// [module docs](self). Returns when the address book is closed.
+pub async fn check_rpc_ports<AdrBook>(interval: Duration, mut address_book: AdrBook)
+where
+    AdrBook: Service<AddressBookRequest, Response = AddressBookResponse, Error = AddressBookError>,
+{
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        match check_rpc_ports_once(&mut address_book, RPC_PORT_CONNECT_TIMEOUT).await {
+            Ok(removed) if removed > 0 => {
+                tracing::debug!("Removed {removed} unreachable RPC ports");
+            }
+            Ok(_) => (),
+            Err(AddressBookError::AddressBooksChannelClosed) => return,
+            Err(e) => tracing::warn!("Failed to check RPC ports, err: {e}"),
+        }
+    }
+}
+
+/// Runs one round of checks, returning the amount of RPC ports removed.
+async fn check_rpc_ports_once<AdrBook>(
+    address_book: &mut AdrBook,
+    timeout: Duration,
+) -> Result<usize, AddressBookError>
+where
+    AdrBook: Service<AddressBookRequest, Response = AddressBookResponse, Error = AddressBookError>,
+{
+    let AddressBookResponse::PublicNodes { white, gray } = address_book
+        .ready()
+        .await?
+        .call(AddressBookRequest::GetPublicNodes(NetZone::Public))
+        .await?
+    else {
+        panic!("Address book sent incorrect response!");
+    };
+
+    let mut nodes: Vec<PeerListEntryBase> = white.into_iter().chain(gray).collect();
+    nodes.shuffle(&mut rand::thread_rng());
+    nodes.truncate(RPC_PORT_CHECKS_PER_ROUND);
+
+    let reachable =
+        join_all(nodes.iter().map(|node| {
+            rpc_port_reachable(SocketAddr::new(node.adr.ip(), node.rpc_port), timeout)
+        }))
+        .await;
+
+    let mut removed = 0;
+    for (node, reachable) in nodes.into_iter().zip(reachable) {
+        if reachable {
+            continue;
+        }
+
+        match address_book
+            .ready()
+            .await?
+            .call(AddressBookRequest::RemovePeerRpcPort(node.adr))
+            .await
+        {
+            Ok(_) => removed += 1,
+            // The peer was removed from the address book while we were checking it.
+            Err(AddressBookError::PeerNotFound) => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Returns true if a TCP connection can be opened to the address before the timeout.
+async fn rpc_port_reachable(addr: SocketAddr, timeout: Duration) -> bool {
+    matches!(
+        tokio::time::timeout(timeout, TcpStream::connect(addr)).await,
+        Ok(Ok(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::future::ready;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn node(port: u16, rpc_port: u16) -> PeerListEntryBase {
+        PeerListEntryBase {
+            adr: SocketAddr::from(([127, 0, 0, 1], port)).into(),
+            id: port.into(),
+            last_seen: 0,
+            pruning_seed: 0,
+            rpc_port,
+            rpc_credits_per_hash: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn unreachable_rpc_ports_are_removed() {
+        let open = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open_port = open.local_addr().unwrap().port();
+        let closed_port = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().port()
+        };
+
+        let removed = Arc::new(Mutex::new(vec![]));
+        let mut address_book = {
+            let removed = removed.clone();
+            tower::service_fn(move |req| {
+                let res = match req {
+                    AddressBookRequest::GetPublicNodes(NetZone::Public) => {
+                        AddressBookResponse::PublicNodes {
+                            white: vec![node(1, open_port)],
+                            gray: vec![node(2, closed_port)],
+                        }
+                    }
+                    AddressBookRequest::RemovePeerRpcPort(peer) => {
+                        removed.lock().unwrap().push(peer);
+                        AddressBookResponse::Ok
+                    }
+                    req => panic!("Unexpected request: {req}"),
+                };
+                ready(Ok::<_, AddressBookError>(res))
+            })
+        };
+
+        let count = check_rpc_ports_once(&mut address_book, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(*removed.lock().unwrap(), vec![node(2, closed_port).adr]);
+    }
+}
@@ -0,0 +1,614 @@
+//! # Block Downloader
+//!
+//! This module contains [`BlockDownloader`], which downloads the blocks we are missing from a set of
+//! connected peers and sends them, in order, to the block verifier.
+//!
+//! The downloader first asks the peer with the highest cumulative difficulty for a chain entry, which
+//! gives us the IDs of the next blocks on the peer's chain. The IDs are split into batches and each
+//! batch is requested with `GET_OBJECTS` from a different peer, so blocks are downloaded from every
+//! peer in parallel. Peers that time out or send an invalid response, like a block that is not the
+//! one we asked for, are dropped and banned, their batch is handed to another peer.
+//!
+//! Downloaded batches are held until every batch below them has been downloaded, so the verifier
+//! always gets the blocks in order. The amount of batches being downloaded or waiting to be sent is
+//! limited by [`BlockDownloaderConfig::max_batches_in_memory`], which bounds our memory usage.
+//!
+//! The blobs of a downloaded batch are copied once into a buffer from a [`BlobPool`] and sent on as
+//! [`RawBlock`]s, so the verifier and database share that buffer and its space is reused once they
+//! are done with the batch.
+//!
+use std::collections::{BTreeMap, VecDeque};
+use std::iter::once;
+use std::time::Duration;
+
+use futures::{
+    channel::mpsc,
+    stream::{FuturesUnordered, StreamExt},
+    SinkExt,
+};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time;
+use tower::{Service, ServiceExt};
+
+use cuprate_common::blob::{BlobPool, RawBlock};
+use monero_serai::block::Block;
+use monero_wire::{
+    messages::common::{BlockCompleteEntry, TransactionBlobs},
+    NetworkAddress,
+};
+
+use crate::address_book::{AddressBookError, AddressBookRequest, AddressBookResponse};
+use crate::peer::{Peer, PeerError};
+use crate::protocol::{
+    temp_database::{DataBaseRequest, DataBaseResponse, DatabaseError},
+    BLOCKS_IDS_SYNCHRONIZING_MAX_COUNT,
+};
+
+/// The amount of time a peer is banned for after failing a request.
+const PEER_BAN_TIME: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Error)]
+pub enum BlockDownloadError {
+    #[error("There are no peers left to download blocks from")]
+    NoPeersLeft,
+    #[error("The block verifier stopped accepting blocks")]
+    VerifierChannelClosed,
+    #[error("Address book err: {0}")]
+    AddressBookError(#[from] AddressBookError),
+}
+
+/// The config for the [`BlockDownloader`].
+#[derive(Debug, Clone)]
+pub struct BlockDownloaderConfig {
+    /// The amount of blocks requested from a peer at once.
+    pub batch_size: usize,
+    /// The maximum amount of batches that are being downloaded or waiting to be verified.
+    pub max_batches_in_memory: usize,
+    /// The amount of time a peer has to respond to a request before it is dropped.
+    pub request_timeout: Duration,
+    /// The capacity of the buffers downloaded blobs are copied into.
+    pub blob_buffer_capacity: usize,
+}
+
+impl Default for BlockDownloaderConfig {
+    fn default() -> Self {
+        BlockDownloaderConfig {
+            batch_size: 100,
+            max_batches_in_memory: 10,
+            request_timeout: Duration::from_secs(30),
+            blob_buffer_capacity: 2 * 1024 * 1024,
+        }
+    }
+}
+
+/// An ordered batch of downloaded blocks.
+#[derive(Debug, Clone)]
+pub struct BlockBatch {
+    /// The height of the first block in the batch.
+    pub start_height: u64,
+    /// The [ID](crate::peer::ConnectionInfo::peer_id) of the peer the batch was downloaded from.
+    pub peer_id: u64,
+    pub blocks: Vec<RawBlock>,
+}
+
+/// A batch of block IDs we need to download.
+#[derive(Debug, Clone)]
+struct BatchRequest {
+    start_height: u64,
+    ids: Vec<[u8; 32]>,
+}
+
+/// Downloads blocks from a set of connected peers.
+pub struct BlockDownloader<S, Bc, AdrBook> {
+    peers: Vec<Peer<S, Bc>>,
+    address_book: AdrBook,
+    config: BlockDownloaderConfig,
+    /// The buffers downloaded blobs are copied into, a buffer for every batch we hold.
+    blob_pool: BlobPool,
+}
+
+impl<S, Bc, AdrBook> BlockDownloader<S, Bc, AdrBook>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    Bc: Service<DataBaseRequest, Response = DataBaseResponse, Error = DatabaseError>,
+    AdrBook: Service<AddressBookRequest, Response = AddressBookResponse, Error = AddressBookError>,
+{
+    pub fn new(
+        peers: Vec<Peer<S, Bc>>,
+        address_book: AdrBook,
+        config: BlockDownloaderConfig,
+    ) -> Self {
+        BlockDownloader {
+            peers,
+            address_book,
+            blob_pool: BlobPool::new(config.blob_buffer_capacity, config.max_batches_in_memory),
+            config,
+        }
+    }
+
+    /// Downloads every block on the best peer's chain that is not in our chain, sending the blocks
+    /// to the verifier over `verifier_tx` in order.
+    ///
+    /// `chain_history` is our sparse chain history, the top block ID first and the genesis ID last.
+    ///
+    /// Returns the peers that are still connected when the download is complete.
+    pub async fn download_blocks(
+        mut self,
+        mut chain_history: Vec<[u8; 32]>,
+        mut verifier_tx: mpsc::Sender<BlockBatch>,
+    ) -> Result<Vec<Peer<S, Bc>>, BlockDownloadError> {
+        loop {
+            let Some((start_height, ids)) = self.next_chain_entry(&chain_history).await? else {
+                tracing::debug!("Block download complete");
+                return Ok(self.peers);
+            };
+
+            let last_id = *ids.last().expect("chain entries are not empty");
+
+            self.download_chain_entry(start_height, ids, &mut verifier_tx)
+                .await?;
+
+            chain_history.insert(0, last_id);
+        }
+    }
+
+    /// Requests the next chain entry from our best peer, returning the height and IDs of the blocks
+    /// we need, or [`None`] if no peer has any blocks we need.
+    async fn next_chain_entry(
+        &mut self,
+        chain_history: &[[u8; 32]],
+    ) -> Result<Option<(u64, Vec<[u8; 32]>)>, BlockDownloadError> {
+        loop {
+            // Ask the peer with the most work.
+            let Some(peer_idx) = self
+                .peers
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, peer)| peer.info().core_sync_data.cumulative_difficulty())
+                .map(|(idx, _)| idx)
+            else {
+                return Err(BlockDownloadError::NoPeersLeft);
+            };
+
+            let mut peer = self.peers.swap_remove(peer_idx);
+
+            match request_chain_entry(&mut peer, chain_history, self.config.request_timeout).await {
+                Ok(res) => {
+                    self.peers.push(peer);
+                    return Ok(res);
+                }
+                Err(e) => self.ban_peer(peer, e).await?,
+            }
+        }
+    }
+
+    /// Downloads the blocks in a chain entry from every peer in parallel.
+    async fn download_chain_entry(
+        &mut self,
+        start_height: u64,
+        ids: Vec<[u8; 32]>,
+        verifier_tx: &mut mpsc::Sender<BlockBatch>,
+    ) -> Result<(), BlockDownloadError> {
+        let mut queued_batches: VecDeque<BatchRequest> = ids
+            .chunks(self.config.batch_size)
+            .enumerate()
+            .map(|(i, ids)| BatchRequest {
+                start_height: start_height + (i * self.config.batch_size) as u64,
+                ids: ids.to_vec(),
+            })
+            .collect();
+
+        let mut next_height_to_send = start_height;
+        let mut downloaded_batches: BTreeMap<u64, BlockBatch> = BTreeMap::new();
+        let mut in_flight = FuturesUnordered::new();
+
+        loop {
+            // Hand out batches to idle peers, as long as we stay under the memory limit. The next batch
+            // to send is always handed out, otherwise a failed batch could block every batch above it.
+            while in_flight.len() + downloaded_batches.len() < self.config.max_batches_in_memory
+                || queued_batches
+                    .front()
+                    .is_some_and(|batch| batch.start_height == next_height_to_send)
+            {
+                let Some(peer) = self.peers.pop() else {
+                    break;
+                };
+                let Some(batch) = queued_batches.pop_front() else {
+                    self.peers.push(peer);
+                    break;
+                };
+
+                in_flight.push(request_batch(peer, batch, self.config.request_timeout));
+            }
+
+            if in_flight.is_empty() {
+                if queued_batches.is_empty() {
+                    return Ok(());
+                }
+                if self.peers.is_empty() {
+                    return Err(BlockDownloadError::NoPeersLeft);
+                }
+            }
+
+            let Some((peer, batch, res)) = in_flight.next().await else {
+                continue;
+            };
+
+            match res {
+                Ok(blocks) => {
+                    let peer_id = peer.info().peer_id;
+                    self.peers.push(peer);
+                    downloaded_batches.insert(
+                        batch.start_height,
+                        BlockBatch {
+                            start_height: batch.start_height,
+                            peer_id,
+                            blocks: blocks
+                                .into_iter()
+                                .map(|block| raw_block(&self.blob_pool, block))
+                                .collect(),
+                        },
+                    );
+                }
+                Err(e) => {
+                    // Give the batch to the next free peer.
+                    queued_batches.push_front(batch);
+                    self.ban_peer(peer, e).await?;
+                }
+            }
+
+            // Send every batch that is next in order.
+            while let Some(batch) = downloaded_batches.remove(&next_height_to_send) {
+                next_height_to_send += batch.blocks.len() as u64;
+                verifier_tx
+                    .send(batch)
+                    .await
+                    .map_err(|_| BlockDownloadError::VerifierChannelClosed)?;
+            }
+        }
+    }
+
+    /// Drops the connection to a peer and bans it for [`PEER_BAN_TIME`].
+    async fn ban_peer(
+        &mut self,
+        peer: Peer<S, Bc>,
+        err: PeerError,
+    ) -> Result<(), BlockDownloadError> {
+        let addr: NetworkAddress = peer.info().addr;
+        tracing::debug!(
+            "Dropping peer: {:?} during block download, err: {}",
+            addr,
+            err
+        );
+        drop(peer);
+
+        let ban_until = chrono::Utc::now().naive_utc()
+            + chrono::Duration::from_std(PEER_BAN_TIME).expect("Ban time is in range");
+        self.address_book
+            .ready()
+            .await?
+            .call(AddressBookRequest::BanPeer(addr, ban_until))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Requests the next chain entry from a peer, returning [`None`] if the peer has no blocks we need.
+async fn request_chain_entry<S, Bc>(
+    peer: &mut Peer<S, Bc>,
+    chain_history: &[[u8; 32]],
+    timeout: Duration,
+) -> Result<Option<(u64, Vec<[u8; 32]>)>, PeerError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    Bc: Service<DataBaseRequest, Response = DataBaseResponse, Error = DatabaseError>,
+{
+    let res = time::timeout(timeout, peer.get_chain_entry(chain_history.to_vec()))
+        .await
+        .map_err(|_| PeerError::ResponseError("Peer timed out"))??;
+
+    if res.m_block_ids.is_empty() || res.m_block_ids.len() > BLOCKS_IDS_SYNCHRONIZING_MAX_COUNT {
+        return Err(PeerError::ResponseError(
+            "Peer sent an invalid amount of block IDs",
+        ));
+    }
+
+    if !chain_history.contains(&res.m_block_ids[0]) {
+        return Err(PeerError::ResponseError(
+            "Peer sent a chain entry that doesn't start in our chain",
+        ));
+    }
+
+    // The first ID is a block we already have.
+    let ids = res.m_block_ids[1..].to_vec();
+    if ids.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some((res.start_height + 1, ids)))
+}
+
+/// Requests a batch of blocks from a peer, the peer and batch are returned so they can be reused if
+/// the request fails.
+async fn request_batch<S, Bc>(
+    mut peer: Peer<S, Bc>,
+    batch: BatchRequest,
+    timeout: Duration,
+) -> (
+    Peer<S, Bc>,
+    BatchRequest,
+    Result<Vec<BlockCompleteEntry>, PeerError>,
+)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    Bc: Service<DataBaseRequest, Response = DataBaseResponse, Error = DatabaseError>,
+{
+    let res = time::timeout(timeout, peer.get_blocks(batch.ids.clone()))
+        .await
+        .map_err(|_| PeerError::ResponseError("Peer timed out"))
+        .and_then(|res| res)
+        .and_then(|res| {
+            if !res.missed_ids.is_empty() || res.blocks.len() != batch.ids.len() {
+                return Err(PeerError::ResponseError(
+                    "Peer did not send every block we requested",
+                ));
+            }
+            if res
+                .blocks
+                .iter()
+                .zip(&batch.ids)
+                .any(|(block, id)| block_hash(&block.block).as_ref() != Some(id))
+            {
+                return Err(PeerError::ResponseError(
+                    "Peer sent a block we did not request",
+                ));
+            }
+            Ok(res.blocks)
+        });
+
+    (peer, batch, res)
+}
+
+/// Returns the hash of a block blob, [`None`] if the blob is not a block.
+fn block_hash(mut blob: &[u8]) -> Option<[u8; 32]> {
+    Block::read(&mut blob).ok().map(|block| block.hash())
+}
+
+/// Copies a downloaded block into a buffer from the pool.
+fn raw_block(pool: &BlobPool, entry: BlockCompleteEntry) -> RawBlock {
+    let (txs, prunable_hashes) = match entry.txs {
+        None => (vec![], entry.pruned.then(Vec::new)),
+        Some(TransactionBlobs::Normal(txs)) => (txs, None),
+        Some(TransactionBlobs::Pruned(txs)) => {
+            let prunable_hashes = txs.iter().map(|tx| tx.prunable_hash).collect();
+            (
+                txs.into_iter().map(|tx| tx.tx).collect(),
+                Some(prunable_hashes),
+            )
+        }
+    };
+
+    let mut blobs = pool.copy_blobs(once(&entry.block).chain(&txs).map(Vec::as_slice));
+    let block = blobs.remove(0);
+    RawBlock {
+        block,
+        txs: blobs,
+        prunable_hashes,
+        block_weight: entry.block_weight,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use cuprate_common::Network;
+    use monero_consensus::{
+        hardforks::HardFork,
+        test_utils::{dummy_block, dummy_miner_tx},
+    };
+    use monero_wire::{ChainResponse, GetObjectsResponse, ProtocolMessage};
+
+    use super::*;
+    use crate::client::{connect, ClientChain, ClientPeer, PeerListRecorder, StaticChain};
+    use crate::peer::{Handshake, Handshaker, NetworkConfig};
+    use crate::protocol::Direction;
+
+    fn chain(height: u64) -> StaticChain {
+        StaticChain::new(monero_wire::messages::CoreSyncData::new(
+            height.into(),
+            height,
+            0,
+            [height as u8; 32],
+            1,
+        ))
+    }
+
+    /// Returns a chain of this many blocks, the first block is our genesis.
+    fn blocks(len: u64) -> Vec<Block> {
+        let mut previous = [0; 32];
+        (0..len)
+            .map(|height| {
+                let block = dummy_block(
+                    HardFork::V1,
+                    HardFork::V1,
+                    previous,
+                    dummy_miner_tx(1, Some(height), vec![]),
+                );
+                previous = block.hash();
+                block
+            })
+            .collect()
+    }
+
+    /// Connects to a mock peer that serves `blocks`, if `honest` is false it sends the genesis block
+    /// in place of every block we ask for.
+    async fn mock_peer(blocks: Arc<Vec<Block>>, addr: NetworkAddress, honest: bool) -> ClientPeer {
+        let (client_stream, node_stream) = tokio::io::duplex(1024 * 1024);
+        let height = blocks.len() as u64;
+
+        tokio::spawn(async move {
+            let mut node = Handshaker::new(
+                NetworkConfig::for_network(Network::Mainnet),
+                PeerListRecorder::default(),
+                ClientChain::new(chain(height)),
+            )
+            .oneshot(Handshake {
+                stream: node_stream,
+                direction: Direction::Inbound,
+                addr,
+            })
+            .await
+            .unwrap();
+
+            // Runs until the downloader drops the connection.
+            while let Ok(message) = node.next_message().await {
+                let response = match message {
+                    ProtocolMessage::ChainRequest(req) => {
+                        let start = blocks
+                            .iter()
+                            .rposition(|block| req.block_ids.contains(&block.hash()))
+                            .unwrap();
+                        ProtocolMessage::ChainEntryResponse(ChainResponse::new(
+                            start as u64,
+                            height,
+                            height.into(),
+                            blocks[start..].iter().map(Block::hash).collect(),
+                            vec![],
+                            vec![],
+                        ))
+                    }
+                    ProtocolMessage::GetObjectsRequest(req) => {
+                        ProtocolMessage::GetObjectsResponse(GetObjectsResponse {
+                            blocks: req
+                                .blocks
+                                .iter()
+                                .map(|id| {
+                                    let block = match honest {
+                                        true => blocks.iter().find(|b| b.hash() == *id).unwrap(),
+                                        false => &blocks[0],
+                                    };
+                                    BlockCompleteEntry {
+                                        pruned: false,
+                                        block: block.serialize(),
+                                        block_weight: 0,
+                                        txs: None,
+                                    }
+                                })
+                                .collect(),
+                            missed_ids: vec![],
+                            current_blockchain_height: height,
+                        })
+                    }
+                    _ => continue,
+                };
+                if node.send_protocol_message(response).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (peer, _) = connect(
+            NetworkConfig::for_network(Network::Mainnet),
+            chain(1),
+            client_stream,
+            addr,
+        )
+        .await
+        .unwrap();
+        peer
+    }
+
+    fn addr(port: u16) -> NetworkAddress {
+        SocketAddr::from(([127, 0, 0, 1], port)).into()
+    }
+
+    /// Returns an address book that records the peers banned.
+    fn ban_recorder(
+        banned: Arc<Mutex<Vec<NetworkAddress>>>,
+    ) -> impl Service<
+        AddressBookRequest,
+        Response = AddressBookResponse,
+        Error = AddressBookError,
+        Future = std::future::Ready<Result<AddressBookResponse, AddressBookError>>,
+    > {
+        tower::service_fn(move |req| {
+            if let AddressBookRequest::BanPeer(addr, _) = req {
+                banned.lock().unwrap().push(addr);
+            }
+            std::future::ready(Ok(AddressBookResponse::Ok))
+        })
+    }
+
+    /// Downloads the chain after our genesis block from these peers, returning the batches sent to
+    /// the verifier.
+    async fn download(
+        peers: Vec<ClientPeer>,
+        blocks: &[Block],
+        banned: Arc<Mutex<Vec<NetworkAddress>>>,
+    ) -> Vec<BlockBatch> {
+        let config = BlockDownloaderConfig {
+            batch_size: 5,
+            max_batches_in_memory: 3,
+            request_timeout: Duration::from_secs(5),
+            blob_buffer_capacity: 1024,
+        };
+        let (batch_tx, batch_rx) = mpsc::channel(100);
+
+        BlockDownloader::new(peers, ban_recorder(banned), config)
+            .download_blocks(vec![blocks[0].hash()], batch_tx)
+            .await
+            .unwrap();
+        batch_rx.collect().await
+    }
+
+    /// Checks the batches have every block after the genesis block, in order.
+    fn assert_blocks_in_order(batches: &[BlockBatch], blocks: &[Block]) {
+        let mut height = 1;
+        for batch in batches {
+            assert_eq!(batch.start_height, height);
+            for block in &batch.blocks {
+                assert_eq!(block.block, blocks[height as usize].serialize());
+                height += 1;
+            }
+        }
+        assert_eq!(height, blocks.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn blocks_are_downloaded_in_order_from_every_peer() {
+        let blocks = Arc::new(blocks(31));
+        let banned = Arc::new(Mutex::new(vec![]));
+        let peers = vec![
+            mock_peer(blocks.clone(), addr(18080), true).await,
+            mock_peer(blocks.clone(), addr(18081), true).await,
+        ];
+
+        let batches = download(peers, &blocks, banned.clone()).await;
+
+        assert_blocks_in_order(&batches, &blocks);
+        assert_eq!(batches.len(), 6);
+        let peer_ids: std::collections::HashSet<u64> =
+            batches.iter().map(|batch| batch.peer_id).collect();
+        assert_eq!(peer_ids.len(), 2);
+        assert!(banned.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn peers_sending_other_blocks_are_banned() {
+        let blocks = Arc::new(blocks(31));
+        let banned = Arc::new(Mutex::new(vec![]));
+        let peers = vec![
+            mock_peer(blocks.clone(), addr(18080), true).await,
+            mock_peer(blocks.clone(), addr(18081), false).await,
+        ];
+
+        let batches = download(peers, &blocks, banned.clone()).await;
+
+        // The bad peer's batch is downloaded from the other peer.
+        assert_blocks_in_order(&batches, &blocks);
+        assert_eq!(*banned.lock().unwrap(), [addr(18081)]);
+    }
+}
@@ -0,0 +1,482 @@
+//! # Client
+//!
+//! This module contains the API for using this crate outside of a node, for example in block
+//! explorers, network crawlers or wallets, to connect to Monero nodes and request blocks and
+//! transactions from them.
+//!
+//! A node answers the peer's handshake and timed syncs with data from its database and stores the
+//! peers it learns about in its address book. A client has neither, so [`connect`] uses a
+//! [`StaticChain`], which answers with fixed core sync data, and returns the peer list the node sent
+//! in its handshake response instead of storing it.
+//!
+//! The chain answering a peer's requests is any service for [`BlockchainRequest`]s, wrapped in a
+//! [`ClientChain`] when it is handed to this crate. A node serves its own chain this way, the
+//! requests for blocks are only sent by peers syncing from us.
+//!
+//! The returned [`Peer`] can request blocks and transactions with [`Peer::get_chain_entry`],
+//! [`Peer::get_blocks`] and [`Peer::get_block_txs`] and receive the node's protocol messages with
+//! [`Peer::next_message`].
+//!
+use std::future::{ready, Ready};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::{
+    future::{ErrInto, Map},
+    FutureExt, TryFutureExt,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower::{BoxError, Service, ServiceExt};
+
+use crate::address_book::{AddressBookError, AddressBookRequest, AddressBookResponse};
+use crate::peer::{Handshake, Handshaker};
+use crate::protocol::{
+    temp_database::{self, DataBaseRequest, DataBaseResponse, DatabaseError},
+    Direction,
+};
+
+pub use crate::peer::{ConnectionInfo, HandShakeError, NetworkConfig, Peer, PeerError};
+pub use crate::transport::BoxedPeerStream;
+pub use monero_wire::{
+    messages::{common::BlockCompleteEntry, CoreSyncData, PeerListEntryBase},
+    ChainResponse, GetObjectsResponse, NetworkAddress, NewFluffyBlock, ProtocolMessage,
+};
+
+/// A connection to a peer, answering its requests from the chain `C`.
+pub type ClientPeer<C = StaticChain> = Peer<BoxedPeerStream, ClientChain<C>>;
+
+/// A request for our chain, made to answer a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockchainRequest {
+    /// The height of our chain.
+    CurrentHeight,
+    /// The cumulative difficulty of our chain.
+    CumulativeDifficulty,
+    /// The core sync data sent in handshakes and timed syncs.
+    CoreSyncData,
+    /// Our sparse chain history, from the top block to the genesis block.
+    Chain,
+    /// The height of a main chain block.
+    BlockHeight([u8; 32]),
+    /// Where we know a block from.
+    BlockKnown([u8; 32]),
+    /// The IDs and weights of up to `count` main chain blocks, starting at `start_height`.
+    ChainEntry { start_height: u64, count: usize },
+    /// A main chain block with its transactions, without their prunable data if `pruned`.
+    BlockCompleteEntry { id: [u8; 32], pruned: bool },
+}
+
+/// The response to a [`BlockchainRequest`], with the variant of the same name.
+#[derive(Debug, Clone)]
+pub enum BlockchainResponse {
+    CurrentHeight(u64),
+    CumulativeDifficulty(u128),
+    CoreSyncData(CoreSyncData),
+    Chain(Vec<[u8; 32]>),
+    BlockHeight(Option<u64>),
+    BlockKnown(BlockKnown),
+    ChainEntry {
+        block_ids: Vec<[u8; 32]>,
+        block_weights: Vec<u64>,
+    },
+    BlockCompleteEntry(Option<BlockCompleteEntry>),
+}
+
+/// Implements a method for each [`BlockchainResponse`] variant that returns the variant's data, or a
+/// [`ChainProtocolError`] if the response is another variant.
+macro_rules! blockchain_response_accessors {
+    ($($variant:ident($data:ty) => $accessor:ident,)*) => {
+        impl BlockchainResponse {
+            /// Returns the name of this response's variant.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(BlockchainResponse::$variant(_) => stringify!($variant),)*
+                    BlockchainResponse::ChainEntry { .. } => "ChainEntry",
+                }
+            }
+
+            $(
+                pub fn $accessor(self) -> Result<$data, ChainProtocolError> {
+                    match self {
+                        BlockchainResponse::$variant(data) => Ok(data),
+                        res => Err(ChainProtocolError {
+                            expected: stringify!($variant),
+                            got: res.name(),
+                        }),
+                    }
+                }
+            )*
+
+            /// Returns the block IDs and weights of a [`BlockchainResponse::ChainEntry`].
+            #[allow(clippy::type_complexity)]
+            pub fn into_chain_entry(self) -> Result<(Vec<[u8; 32]>, Vec<u64>), ChainProtocolError> {
+                match self {
+                    BlockchainResponse::ChainEntry {
+                        block_ids,
+                        block_weights,
+                    } => Ok((block_ids, block_weights)),
+                    res => Err(ChainProtocolError {
+                        expected: "ChainEntry",
+                        got: res.name(),
+                    }),
+                }
+            }
+        }
+    };
+}
+
+blockchain_response_accessors! {
+    CurrentHeight(u64) => into_current_height,
+    CumulativeDifficulty(u128) => into_cumulative_difficulty,
+    CoreSyncData(CoreSyncData) => into_core_sync_data,
+    Chain(Vec<[u8; 32]>) => into_chain,
+    BlockHeight(Option<u64>) => into_block_height,
+    BlockKnown(BlockKnown) => into_block_known,
+    BlockCompleteEntry(Option<BlockCompleteEntry>) => into_block_complete_entry,
+}
+
+/// The chain answered a request with the wrong [`BlockchainResponse`], the chain is buggy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("expected a {expected} response, got a {got} response")]
+pub struct ChainProtocolError {
+    pub expected: &'static str,
+    pub got: &'static str,
+}
+
+/// Where we know a block from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKnown {
+    No,
+    OnMainChain,
+    OnSideChain,
+    KnownBad,
+}
+
+impl BlockKnown {
+    pub fn is_known(&self) -> bool {
+        !matches!(self, BlockKnown::No)
+    }
+}
+
+/// Wraps a service for [`BlockchainRequest`]s, so it can answer peers in the handshake and the
+/// connection.
+#[derive(Debug, Clone)]
+pub struct ClientChain<C>(C);
+
+impl<C> ClientChain<C> {
+    pub fn new(chain: C) -> Self {
+        ClientChain(chain)
+    }
+
+    /// Returns the wrapped chain.
+    pub fn into_inner(self) -> C {
+        self.0
+    }
+}
+
+type ChainResult = Result<BlockchainResponse, BoxError>;
+type DatabaseResult = Result<DataBaseResponse, DatabaseError>;
+
+impl<C> Service<DataBaseRequest> for ClientChain<C>
+where
+    C: Service<BlockchainRequest, Response = BlockchainResponse>,
+    C::Error: Into<BoxError>,
+{
+    type Response = DataBaseResponse;
+    type Error = DatabaseError;
+    type Future = Map<ErrInto<C::Future, BoxError>, fn(ChainResult) -> DatabaseResult>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx).map_err(|e| database_error(e.into()))
+    }
+
+    fn call(&mut self, req: DataBaseRequest) -> Self::Future {
+        let req = match req {
+            DataBaseRequest::CurrentHeight => BlockchainRequest::CurrentHeight,
+            DataBaseRequest::CumulativeDifficulty => BlockchainRequest::CumulativeDifficulty,
+            DataBaseRequest::CoreSyncData => BlockchainRequest::CoreSyncData,
+            DataBaseRequest::Chain => BlockchainRequest::Chain,
+            DataBaseRequest::BlockHeight(id) => BlockchainRequest::BlockHeight(id),
+            DataBaseRequest::BlockKnown(id) => BlockchainRequest::BlockKnown(id),
+            DataBaseRequest::ChainEntry {
+                start_height,
+                count,
+            } => BlockchainRequest::ChainEntry {
+                start_height,
+                count,
+            },
+            DataBaseRequest::BlockCompleteEntry { id, pruned } => {
+                BlockchainRequest::BlockCompleteEntry { id, pruned }
+            }
+        };
+
+        self.0
+            .call(req)
+            .err_into()
+            .map(database_response as fn(ChainResult) -> DatabaseResult)
+    }
+}
+
+fn database_error(e: BoxError) -> DatabaseError {
+    DatabaseError::Internal(e.to_string())
+}
+
+fn database_response(res: ChainResult) -> DatabaseResult {
+    Ok(match res.map_err(database_error)? {
+        BlockchainResponse::CurrentHeight(height) => DataBaseResponse::CurrentHeight(height),
+        BlockchainResponse::CumulativeDifficulty(cumulative_difficulty) => {
+            DataBaseResponse::CumulativeDifficulty(cumulative_difficulty)
+        }
+        BlockchainResponse::CoreSyncData(core_sync_data) => {
+            DataBaseResponse::CoreSyncData(core_sync_data)
+        }
+        BlockchainResponse::Chain(history) => DataBaseResponse::Chain(history),
+        BlockchainResponse::BlockHeight(height) => DataBaseResponse::BlockHeight(height),
+        BlockchainResponse::BlockKnown(known) => DataBaseResponse::BlockKnown(match known {
+            BlockKnown::No => temp_database::BlockKnown::No,
+            BlockKnown::OnMainChain => temp_database::BlockKnown::OnMainChain,
+            BlockKnown::OnSideChain => temp_database::BlockKnown::OnSideChain,
+            BlockKnown::KnownBad => temp_database::BlockKnown::KnownBad,
+        }),
+        BlockchainResponse::ChainEntry {
+            block_ids,
+            block_weights,
+        } => DataBaseResponse::ChainEntry {
+            block_ids,
+            block_weights,
+        },
+        BlockchainResponse::BlockCompleteEntry(entry) => {
+            DataBaseResponse::BlockCompleteEntry(entry)
+        }
+    })
+}
+
+/// A chain that never changes, used to answer the peer's requests for our core sync data.
+///
+/// Clients that don't keep a chain can use the genesis block of the network as the top block, nodes
+/// will not ask a peer at height 1 for blocks.
+#[derive(Debug, Clone)]
+pub struct StaticChain {
+    core_sync_data: CoreSyncData,
+}
+
+impl StaticChain {
+    pub fn new(core_sync_data: CoreSyncData) -> Self {
+        StaticChain { core_sync_data }
+    }
+}
+
+impl Service<BlockchainRequest> for StaticChain {
+    type Response = BlockchainResponse;
+    type Error = BoxError;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: BlockchainRequest) -> Self::Future {
+        let core_sync_data = &self.core_sync_data;
+        let is_top = |id: &[u8; 32]| *id == core_sync_data.top_id;
+
+        ready(Ok(match req {
+            BlockchainRequest::CurrentHeight => {
+                BlockchainResponse::CurrentHeight(core_sync_data.current_height)
+            }
+            BlockchainRequest::CumulativeDifficulty => {
+                BlockchainResponse::CumulativeDifficulty(core_sync_data.cumulative_difficulty())
+            }
+            BlockchainRequest::CoreSyncData => {
+                BlockchainResponse::CoreSyncData(core_sync_data.clone())
+            }
+            BlockchainRequest::Chain => BlockchainResponse::Chain(vec![core_sync_data.top_id]),
+            BlockchainRequest::BlockHeight(id) => BlockchainResponse::BlockHeight(
+                is_top(&id).then(|| core_sync_data.current_height.saturating_sub(1)),
+            ),
+            BlockchainRequest::BlockKnown(id) => BlockchainResponse::BlockKnown(if is_top(&id) {
+                BlockKnown::OnMainChain
+            } else {
+                BlockKnown::No
+            }),
+            // We only know the top block's ID, not its blob or weight.
+            BlockchainRequest::ChainEntry { .. } => BlockchainResponse::ChainEntry {
+                block_ids: vec![],
+                block_weights: vec![],
+            },
+            BlockchainRequest::BlockCompleteEntry { .. } => {
+                BlockchainResponse::BlockCompleteEntry(None)
+            }
+        }))
+    }
+}
+
+/// An address book that only records the peer lists it is given.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PeerListRecorder {
+    peers: Arc<Mutex<Vec<PeerListEntryBase>>>,
+}
+
+impl Service<AddressBookRequest> for PeerListRecorder {
+    type Response = AddressBookResponse;
+    type Error = AddressBookError;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: AddressBookRequest) -> Self::Future {
+        ready(match req {
+            AddressBookRequest::HandleNewPeerList(peers, _) => {
+                self.peers.lock().unwrap().extend(peers);
+                Ok(AddressBookResponse::Ok)
+            }
+            AddressBookRequest::GetPeersToGossip(_) => Ok(AddressBookResponse::Peers(vec![])),
+            AddressBookRequest::GetRandomGrayPeer(_)
+            | AddressBookRequest::GetRandomWhitePeer(_)
+            | AddressBookRequest::GetPublicNodes(_)
+            | AddressBookRequest::GetOutboundCandidate(..) => Err(AddressBookError::PeerListEmpty),
+            _ => Ok(AddressBookResponse::Ok),
+        })
+    }
+}
+
+/// Connects to the node on the other end of `stream`, returning the connection and the peer list the
+/// node sent in its handshake response.
+///
+/// `addr` is the node's address, it is used to pick the transport from `config` and is otherwise
+/// only used for logging and in the returned [`ConnectionInfo`].
+pub async fn connect<C, S>(
+    config: NetworkConfig,
+    chain: C,
+    stream: S,
+    addr: NetworkAddress,
+) -> Result<(ClientPeer<C>, Vec<PeerListEntryBase>), HandShakeError>
+where
+    C: Service<BlockchainRequest, Response = BlockchainResponse> + Clone + Send + 'static,
+    C::Error: Into<BoxError>,
+    C::Future: Send,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let address_book = PeerListRecorder::default();
+
+    let peer = Handshaker::new(config, address_book.clone(), ClientChain::new(chain))
+        .oneshot(Handshake {
+            stream,
+            direction: Direction::Outbound,
+            addr,
+        })
+        .await?;
+
+    let peers = std::mem::take(&mut *address_book.peers.lock().unwrap());
+    Ok((peer, peers))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use cuprate_common::Network;
+
+    use super::*;
+
+    fn chain(height: u64) -> StaticChain {
+        StaticChain::new(CoreSyncData::new(
+            height.into(),
+            height,
+            0,
+            [height as u8; 32],
+            1,
+        ))
+    }
+
+    #[tokio::test]
+    async fn client_requests_blocks() {
+        let (client_stream, node_stream) = tokio::io::duplex(1024 * 1024);
+        let addr: NetworkAddress = "127.0.0.1:18080".parse::<SocketAddr>().unwrap().into();
+
+        let node = tokio::spawn(async move {
+            let mut node = Handshaker::new(
+                NetworkConfig::for_network(Network::Mainnet),
+                PeerListRecorder::default(),
+                ClientChain::new(chain(10)),
+            )
+            .oneshot(Handshake {
+                stream: node_stream,
+                direction: Direction::Inbound,
+                addr,
+            })
+            .await
+            .unwrap();
+
+            let ProtocolMessage::GetObjectsRequest(req) = node.next_message().await.unwrap() else {
+                panic!("Client sent the wrong request");
+            };
+            node.send_protocol_message(ProtocolMessage::GetObjectsResponse(GetObjectsResponse {
+                blocks: vec![BlockCompleteEntry {
+                    pruned: false,
+                    block: vec![1, 2, 3],
+                    block_weight: 0,
+                    txs: None,
+                }],
+                missed_ids: req.blocks[1..].to_vec(),
+                current_blockchain_height: 10,
+            }))
+            .await
+            .unwrap();
+        });
+
+        let (mut peer, peers) = connect(
+            NetworkConfig::for_network(Network::Mainnet),
+            chain(1),
+            client_stream,
+            addr,
+        )
+        .await
+        .unwrap();
+
+        assert!(peers.is_empty());
+        assert_eq!(peer.info().core_sync_data.current_height, 10);
+
+        let res = peer.get_blocks(vec![[1; 32], [2; 32]]).await.unwrap();
+        assert_eq!(res.blocks.len(), 1);
+        assert_eq!(res.missed_ids, vec![[2; 32]]);
+
+        node.await.unwrap();
+    }
+
+    #[test]
+    fn static_chain_knows_its_top_block() {
+        let mut chain = chain(5);
+
+        let height = chain
+            .call(BlockchainRequest::BlockHeight([5; 32]))
+            .into_inner()
+            .unwrap()
+            .into_block_height()
+            .unwrap();
+        assert_eq!(height, Some(4));
+
+        let known = chain
+            .call(BlockchainRequest::BlockKnown([0; 32]))
+            .into_inner()
+            .unwrap()
+            .into_block_known()
+            .unwrap();
+        assert!(!known.is_known());
+
+        let err = chain
+            .call(BlockchainRequest::CurrentHeight)
+            .into_inner()
+            .unwrap()
+            .into_block_known()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ChainProtocolError {
+                expected: "BlockKnown",
+                got: "CurrentHeight"
+            }
+        );
+    }
+}
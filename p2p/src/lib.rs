@@ -1,3 +1,17 @@
+//! # Cuprate P2P
+//!
+//! This crate contains Cuprate's Monero P2P code: the handshake, connections to peers, the address
+//! book, the block downloader, the handler that serves blocks to peers and the transaction relay
+//! router.
+//!
+//! Projects that only want to talk to Monero nodes should use the [`client`] module, which needs no
+//! database or address book.
+//!
 pub mod address_book;
+pub mod block_downloader;
+pub mod client;
 pub mod peer;
-mod protocol;
+pub mod protocol;
+pub mod request_handler;
+pub mod transport;
+pub mod tx_relay;
@@ -1,11 +1,13 @@
-pub mod client;
 pub mod connection;
 pub mod handshaker;
 
+pub use connection::{ConnectionInfo, Peer};
+pub use handshaker::{HandShakeError, Handshake, Handshaker, NetworkConfig};
+
 #[cfg(test)]
 mod tests;
 
-use monero_wire::levin::BucketError;
+use levin_cuprate::BucketError;
 use thiserror::Error;
 
 #[derive(Debug, Error, Clone, Copy)]
@@ -31,6 +33,8 @@ pub enum PeerError {
     InternalService(#[from] RequestServiceError),
     #[error("Internal peer sync channel closed")]
     InternalPeerSyncChannelClosed,
+    #[error("The peer sent a request we only accept during the handshake")]
+    PeerSentHandshakeAgain,
     #[error("Levin Error")]
     LevinError, // remove me, this is just temporary
 }
@@ -1,196 +1,301 @@
-use std::collections::HashSet;
+//! # Connection
+//!
+//! This module contains [`Peer`], a connection to a peer that has completed the handshake.
+//!
+//! Admin requests from the peer (ping, support flags and timed syncs) are answered by the [`Peer`]
+//! itself, protocol messages are handed to the caller with [`Peer::next_message`].
+//!
+//! Blocks and transactions can be requested with [`Peer::get_chain_entry`], [`Peer::get_blocks`]
+//! and [`Peer::get_block_txs`], protocol messages received while waiting for the response are
+//! queued for [`Peer::next_message`].
+//!
+use std::collections::VecDeque;
 
-use futures::channel::{mpsc, oneshot};
-use futures::stream::Fuse;
-use futures::{AsyncRead, AsyncWrite, SinkExt, StreamExt};
-
-use levin::{MessageSink, MessageStream};
-use monero_wire::messages::CoreSyncData;
-use monero_wire::{levin, Message, NetworkAddress};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
 use tower::{Service, ServiceExt};
 
-use crate::protocol::{
-    InternalMessageRequest, InternalMessageResponse, BLOCKS_IDS_SYNCHRONIZING_MAX_COUNT,
-    P2P_MAX_PEERS_IN_HANDSHAKE,
+use monero_wire::{
+    messages::{
+        admin::{PingResponse, SupportFlagsResponse, PING_OK_RESPONSE_STATUS_TEXT},
+        common::PeerSupportFlags,
+        BasicNodeData, CoreSyncData, TimedSyncRequest, TimedSyncResponse,
+    },
+    ChainRequest, ChainResponse, FluffyMissingTransactionsRequest, GetObjectsRequest,
+    GetObjectsResponse, Message, MoneroWireCodec, NetworkAddress, NewFluffyBlock, ProtocolMessage,
+    RequestMessage, ResponseMessage,
 };
 
 use super::PeerError;
+use crate::protocol::{
+    temp_database::{DataBaseRequest, DataBaseResponse, DatabaseError},
+    Direction,
+};
 
-pub enum PeerSyncChange {
-    CoreSyncData(NetworkAddress, CoreSyncData),
-    ObjectsResponse(NetworkAddress, Vec<[u8; 32]>, u64),
-    PeerDisconnected(NetworkAddress),
-}
+/// The maximum amount of protocol messages queued while waiting for a response, the oldest message
+/// is dropped when the queue is full.
+const MAX_PENDING_MESSAGES: usize = 100;
 
-pub struct ClientRequest {
-    pub req: InternalMessageRequest,
-    pub tx: oneshot::Sender<Result<InternalMessageResponse, PeerError>>,
+/// Information about a peer learnt during the handshake.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub addr: NetworkAddress,
+    pub direction: Direction,
+    pub support_flags: PeerSupportFlags,
+    /// Peer ID
+    pub peer_id: u64,
+    pub rpc_port: u16,
+    pub rpc_credits_per_hash: u32,
+    /// The peer's most recent core sync data.
+    pub core_sync_data: CoreSyncData,
 }
 
-pub enum State {
-    WaitingForRequest,
-    WaitingForResponse {
-        request: InternalMessageRequest,
-        tx: oneshot::Sender<Result<InternalMessageResponse, PeerError>>,
-    },
+/// A connection to a peer that has completed the handshake.
+pub struct Peer<S, Bc> {
+    framed: Framed<S, MoneroWireCodec>,
+    info: ConnectionInfo,
+    /// Our node data, used to answer the peer's admin requests.
+    our_basic_node_data: BasicNodeData,
+    blockchain: Bc,
+    /// Protocol messages received while waiting for a response.
+    pending_messages: VecDeque<ProtocolMessage>,
 }
 
-impl State {
-    pub fn expected_response_id(&self) -> Option<u32> {
-        match self {
-            Self::WaitingForRequest => None,
-            Self::WaitingForResponse { request, tx: _ } => request.expected_id(),
+impl<S, Bc> Peer<S, Bc>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    Bc: Service<DataBaseRequest, Response = DataBaseResponse, Error = DatabaseError>,
+{
+    pub(crate) fn new(
+        framed: Framed<S, MoneroWireCodec>,
+        info: ConnectionInfo,
+        our_basic_node_data: BasicNodeData,
+        blockchain: Bc,
+    ) -> Self {
+        Peer {
+            framed,
+            info,
+            our_basic_node_data,
+            blockchain,
+            pending_messages: VecDeque::new(),
         }
     }
-}
 
-pub struct Connection<Svc, Aw, Ar> {
-    address: NetworkAddress,
-    state: State,
-    sink: MessageSink<Aw, Message>,
-    stream: Fuse<MessageStream<Ar, Message>>,
-    client_rx: mpsc::Receiver<ClientRequest>,
-    sync_state_tx: mpsc::Sender<PeerSyncChange>,
-    svc: Svc,
-}
+    /// Returns the information we have about this peer.
+    pub fn info(&self) -> &ConnectionInfo {
+        &self.info
+    }
 
-impl<Svc, Aw, Ar> Connection<Svc, Aw, Ar>
-where
-    Svc: Service<InternalMessageRequest, Response = InternalMessageResponse, Error = PeerError>,
-    Aw: AsyncWrite + std::marker::Unpin,
-    Ar: AsyncRead + std::marker::Unpin,
-{
-    pub fn new(
-        address: NetworkAddress,
-        sink: MessageSink<Aw, Message>,
-        stream: MessageStream<Ar, Message>,
-        client_rx: mpsc::Receiver<ClientRequest>,
-        sync_state_tx: mpsc::Sender<PeerSyncChange>,
-        svc: Svc,
-    ) -> Connection<Svc, Aw, Ar> {
-        Connection {
-            address,
-            state: State::WaitingForRequest,
-            sink,
-            stream: stream.fuse(),
-            client_rx,
-            sync_state_tx,
-            svc,
-        }
+    /// Sends a protocol message to the peer.
+    pub async fn send_protocol_message(
+        &mut self,
+        message: ProtocolMessage,
+    ) -> Result<(), PeerError> {
+        self.send(Message::Protocol(message)).await
     }
-    async fn handle_response(&mut self, res: InternalMessageResponse) -> Result<(), PeerError> {
-        let state = std::mem::replace(&mut self.state, State::WaitingForRequest);
-        if let State::WaitingForResponse { request, tx } = state {
-            match (request, &res) {
-                (InternalMessageRequest::Handshake(_), InternalMessageResponse::Handshake(_)) => {}
-                (
-                    InternalMessageRequest::SupportFlags(_),
-                    InternalMessageResponse::SupportFlags(_),
-                ) => {}
-                (InternalMessageRequest::TimedSync(_), InternalMessageResponse::TimedSync(res)) => {
-                }
-                (
-                    InternalMessageRequest::GetObjectsRequest(req),
-                    InternalMessageResponse::GetObjectsResponse(res),
-                ) => {}
-                (
-                    InternalMessageRequest::ChainRequest(_),
-                    InternalMessageResponse::ChainResponse(res),
-                ) => {}
-                (
-                    InternalMessageRequest::FluffyMissingTransactionsRequest(req),
-                    InternalMessageResponse::NewFluffyBlock(blk),
-                ) => {}
-                (
-                    InternalMessageRequest::GetTxPoolCompliment(_),
-                    InternalMessageResponse::NewTransactions(_),
-                ) => {
-                    // we could check we received no transactions that we said we knew about but thats going to happen later anyway when they get added to our
-                    // mempool
-                }
-                _ => return Err(PeerError::ResponseError("Peer sent incorrect response")),
+
+    /// Waits for the next protocol message from the peer, answering any admin requests received
+    /// in the meantime.
+    pub async fn next_message(&mut self) -> Result<ProtocolMessage, PeerError> {
+        if let Some(message) = self.pending_messages.pop_front() {
+            return Ok(message);
+        }
+
+        loop {
+            match self.receive().await? {
+                Message::Protocol(message) => return Ok(message),
+                Message::Request(req) => self.handle_request(req).await?,
+                Message::Response(_) => return Err(PeerError::PeerSentUnSolicitedResponse),
             }
-            // response passed our tests we can send it to the requestor
-            let _ = tx.send(Ok(res));
-            Ok(())
-        } else {
-            unreachable!("This will only be called when in state WaitingForResponse");
         }
     }
 
-    async fn send_message_to_peer(&mut self, mes: impl Into<Message>) -> Result<(), PeerError> {
-        Ok(self.sink.send(mes.into()).await?)
+    /// Pings the peer.
+    pub async fn ping(&mut self) -> Result<(), PeerError> {
+        let ResponseMessage::Ping(res) = self.request(RequestMessage::Ping).await? else {
+            return Err(PeerError::PeerSentUnexpectedResponse);
+        };
+
+        if res.status != PING_OK_RESPONSE_STATUS_TEXT || res.peer_id != self.info.peer_id {
+            return Err(PeerError::ResponseError(
+                "Peer sent an invalid ping response",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Sends our core sync data to the peer and returns their response, the peer's core sync
+    /// data in [`ConnectionInfo`] is updated.
+    pub async fn timed_sync(&mut self) -> Result<TimedSyncResponse, PeerError> {
+        let payload_data = self.our_core_sync().await?;
+
+        let ResponseMessage::TimedSync(res) = self
+            .request(RequestMessage::TimedSync(TimedSyncRequest { payload_data }))
+            .await?
+        else {
+            return Err(PeerError::PeerSentUnexpectedResponse);
+        };
+
+        self.info.core_sync_data = res.payload_data.clone();
+        Ok(res)
     }
 
-    async fn handle_peer_request(&mut self, req: InternalMessageRequest) -> Result<(), PeerError> {
-        // we should check contents of peer requests for obvious errors like we do with responses
-        let ready_svc = self.svc.ready().await?;
-        let res = ready_svc.call(req).await?;
-        self.send_message_to_peer(res).await
+    /// Requests the IDs of the blocks on the peer's chain after our chain.
+    ///
+    /// `block_ids` is our sparse chain history, the top block ID first and the genesis ID last. The
+    /// first ID in the response is the highest block in `block_ids` that is on the peer's chain.
+    pub async fn get_chain_entry(
+        &mut self,
+        block_ids: Vec<[u8; 32]>,
+    ) -> Result<ChainResponse, PeerError> {
+        self.send_protocol_message(ProtocolMessage::ChainRequest(ChainRequest {
+            block_ids,
+            prune: false,
+        }))
+        .await?;
+
+        self.protocol_response(|message| match message {
+            ProtocolMessage::ChainEntryResponse(res) => Ok(res),
+            message => Err(message),
+        })
+        .await
     }
 
-    async fn handle_client_request(&mut self, req: ClientRequest) -> Result<(), PeerError> {
-        // check we need a response
-        if let Some(_) = req.req.expected_id() {
-            self.state = State::WaitingForResponse {
-                request: req.req.clone(),
-                tx: req.tx,
-            };
-        }
-        self.send_message_to_peer(req.req).await
-    }
-
-    async fn state_waiting_for_request(&mut self) -> Result<(), PeerError> {
-        futures::select! {
-            peer_message = self.stream.next() => {
-                match peer_message.expect("MessageStream will never return None") {
-                    Ok(message) => {
-                        self.handle_peer_request(message.try_into().map_err(|_| PeerError::PeerSentUnexpectedResponse)?).await
-                    },
-                    Err(e) => Err(e.into()),
-                }
-            },
-            client_req = self.client_rx.next() => {
-                self.handle_client_request(client_req.ok_or(PeerError::ClientChannelClosed)?).await
+    /// Requests blocks, with their transactions, by ID.
+    ///
+    /// The IDs of blocks the peer does not have are returned in `missed_ids`.
+    pub async fn get_blocks(
+        &mut self,
+        ids: Vec<[u8; 32]>,
+    ) -> Result<GetObjectsResponse, PeerError> {
+        self.send_protocol_message(ProtocolMessage::GetObjectsRequest(GetObjectsRequest {
+            blocks: ids,
+            pruned: false,
+        }))
+        .await?;
+
+        self.protocol_response(|message| match message {
+            ProtocolMessage::GetObjectsResponse(res) => Ok(res),
+            message => Err(message),
+        })
+        .await
+    }
+
+    /// Requests transactions of a block by their index in the block, the peer responds with the
+    /// block and the requested transactions.
+    ///
+    /// `current_blockchain_height` is the height of the chain the block is being added to. The next
+    /// fluffy block the peer sends is returned, so callers should check it is the block requested.
+    pub async fn get_block_txs(
+        &mut self,
+        block_hash: [u8; 32],
+        current_blockchain_height: u64,
+        missing_tx_indices: Vec<u64>,
+    ) -> Result<NewFluffyBlock, PeerError> {
+        self.send_protocol_message(ProtocolMessage::FluffyMissingTransactionsRequest(
+            FluffyMissingTransactionsRequest {
+                block_hash,
+                current_blockchain_height,
+                missing_tx_indices,
             },
-        }
+        ))
+        .await?;
+
+        self.protocol_response(|message| match message {
+            ProtocolMessage::NewFluffyBlock(res) => Ok(res),
+            message => Err(message),
+        })
+        .await
     }
 
-    async fn state_waiting_for_response(&mut self) -> Result<(), PeerError> {
-        // put a timeout on this
-        let peer_message = self
-            .stream
-            .next()
-            .await
-            .expect("MessageStream will never return None")?;
-
-        if !peer_message.is_request()
-            && self.state.expected_response_id() == Some(peer_message.id())
-        {
-            if let Ok(res) = peer_message.try_into() {
-                Ok(self.handle_response(res).await?)
-            } else {
-                // im almost certain this is impossible to hit, but im not certain enough to use unreachable!()
-                Err(PeerError::ResponseError("Peer sent incorrect response"))
-            }
-        } else {
-            if let Ok(req) = peer_message.try_into() {
-                self.handle_peer_request(req).await
-            } else {
-                // this can be hit if the peer sends a protocol response with the wrong id
-                Err(PeerError::ResponseError("Peer sent incorrect response"))
+    /// Waits for the protocol message `response` accepts, other protocol messages are queued for
+    /// [`Peer::next_message`].
+    async fn protocol_response<T>(
+        &mut self,
+        mut response: impl FnMut(ProtocolMessage) -> Result<T, ProtocolMessage>,
+    ) -> Result<T, PeerError> {
+        loop {
+            match self.receive().await? {
+                Message::Protocol(message) => match response(message) {
+                    Ok(res) => return Ok(res),
+                    Err(message) => self.queue_message(message),
+                },
+                Message::Request(req) => self.handle_request(req).await?,
+                Message::Response(_) => return Err(PeerError::PeerSentUnSolicitedResponse),
             }
         }
     }
 
-    pub async fn run(mut self) {
+    fn queue_message(&mut self, message: ProtocolMessage) {
+        if self.pending_messages.len() == MAX_PENDING_MESSAGES {
+            self.pending_messages.pop_front();
+        }
+        self.pending_messages.push_back(message);
+    }
+
+    /// Sends a request to the peer and waits for the response.
+    ///
+    /// Protocol messages received while waiting are queued for [`Peer::next_message`].
+    async fn request(&mut self, req: RequestMessage) -> Result<ResponseMessage, PeerError> {
+        self.send(Message::Request(req)).await?;
+
         loop {
-            let _res = match self.state {
-                State::WaitingForRequest => self.state_waiting_for_request().await,
-                State::WaitingForResponse { request: _, tx: _ } => {
-                    self.state_waiting_for_response().await
-                }
-            };
+            match self.receive().await? {
+                Message::Response(res) => return Ok(res),
+                Message::Request(req) => self.handle_request(req).await?,
+                Message::Protocol(message) => self.queue_message(message),
+            }
+        }
+    }
+
+    async fn handle_request(&mut self, req: RequestMessage) -> Result<(), PeerError> {
+        let res = match req {
+            RequestMessage::Ping => ResponseMessage::Ping(PingResponse {
+                status: PING_OK_RESPONSE_STATUS_TEXT.to_string(),
+                peer_id: self.our_basic_node_data.peer_id,
+            }),
+            RequestMessage::SupportFlags => ResponseMessage::SupportFlags(SupportFlagsResponse {
+                support_flags: self.our_basic_node_data.support_flags,
+            }),
+            RequestMessage::TimedSync(req) => {
+                self.info.core_sync_data = req.payload_data;
+
+                ResponseMessage::TimedSync(TimedSyncResponse {
+                    payload_data: self.our_core_sync().await?,
+                    local_peerlist_new: Vec::new(),
+                })
+            }
+            RequestMessage::Handshake(_) => return Err(PeerError::PeerSentHandshakeAgain),
+        };
+
+        self.send(Message::Response(res)).await
+    }
+
+    async fn our_core_sync(&mut self) -> Result<CoreSyncData, PeerError> {
+        let DataBaseResponse::CoreSyncData(core_sync) = self
+            .blockchain
+            .ready()
+            .await
+            .map_err(|_| PeerError::InternalServiceDidNotRespond)?
+            .call(DataBaseRequest::CoreSyncData)
+            .await
+            .map_err(|_| PeerError::InternalServiceDidNotRespond)?
+        else {
+            unreachable!("Database will always return the requested item")
+        };
+        Ok(core_sync)
+    }
+
+    async fn send(&mut self, message: Message) -> Result<(), PeerError> {
+        self.framed.send(message).await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Message, PeerError> {
+        match self.framed.next().await {
+            Some(message) => Ok(message?),
+            None => Err(PeerError::PeerConnectionClosed),
         }
     }
 }
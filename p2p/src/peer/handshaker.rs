@@ -1,38 +1,44 @@
+//! # Handshaker
+//!
+//! This module contains [`Handshaker`], a [`tower::Service`] that completes the Levin handshake
+//! (`COMMAND_HANDSHAKE`) with a peer over any tokio stream and returns a [`Peer`].
+//!
+//! For outbound connections we send our handshake request and wait for the peer's response, for
+//! inbound connections we wait for the peer's request and respond. In both roles the peer's
+//! support flags are requested if the peer did not send them in its node data.
+//!
+//! Before the handshake the stream is wrapped by the [`Transport`](crate::transport::Transport) of
+//! the peer's network zone, the handshake timeout includes the time the transport takes.
+//!
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 
-use futures::FutureExt;
-use futures::{channel::mpsc, AsyncRead, AsyncWrite, SinkExt, StreamExt};
-use monero_wire::messages::admin::{SupportFlagsRequest, SupportFlagsResponse};
-use monero_wire::messages::MessageRequest;
+use futures::{FutureExt, SinkExt, StreamExt};
 use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::time;
+use tokio_util::codec::Framed;
 use tower::{Service, ServiceExt};
+use tracing::Instrument;
 
 use crate::address_book::{AddressBookError, AddressBookRequest, AddressBookResponse};
 use crate::protocol::temp_database::{DataBaseRequest, DataBaseResponse, DatabaseError};
-use crate::protocol::{
-    Direction, InternalMessageRequest, InternalMessageResponse, P2P_MAX_PEERS_IN_HANDSHAKE,
-};
-use cuprate_common::{HardForks, Network, PruningSeed};
+use crate::protocol::{Direction, P2P_MAX_PEERS_IN_HANDSHAKE};
+use crate::transport::{BoxedPeerStream, Transport, Transports};
+use cuprate_common::Network;
+use levin_cuprate::BucketError;
 use monero_wire::{
-    levin::{BucketError, MessageSink, MessageStream},
     messages::{
-        admin::{HandshakeRequest, HandshakeResponse},
+        admin::{HandshakeRequest, HandshakeResponse, SupportFlagsResponse},
         common::PeerSupportFlags,
-        BasicNodeData, CoreSyncData, MessageResponse, PeerID, PeerListEntryBase,
+        BasicNodeData, CoreSyncData,
     },
-    Message, NetworkAddress,
+    network_address::NetZone,
+    Message, MoneroWireCodec, NetworkAddress, RequestMessage, ResponseMessage,
 };
-use tracing::Instrument;
 
-use super::client::Client;
-use super::{
-    client::ConnectionInfo,
-    connection::{ClientRequest, Connection, PeerSyncChange},
-    PeerError,
-};
+use super::connection::{ConnectionInfo, Peer};
 
 #[derive(Debug, Error)]
 pub enum HandShakeError {
@@ -46,16 +52,22 @@ pub enum HandShakeError {
     PeerDoesNotHaveTheMinimumSupportFlags,
     #[error("The peer is on a different network")]
     PeerIsOnADifferentNetwork,
+    #[error("The peer has our peer ID, we connected to ourself")]
+    PeerIsOurself,
     #[error("Address book err: {0}")]
     AddressBookError(#[from] AddressBookError),
     #[error("The peer sent too many peers, considered spamming")]
     PeerSentTooManyPeers,
     #[error("The peer sent a wrong response to our handshake")]
     PeerSentWrongResponse,
+    #[error("The peer closed the connection during the handshake")]
+    PeerClosedConnection,
     #[error("The syncer returned an error")]
     DataBaseError(#[from] DatabaseError),
     #[error("Bucket error while communicating with peer: {0}")]
     BucketError(#[from] BucketError),
+    #[error("The transport failed to wrap the connection: {0}")]
+    TransportError(std::io::Error),
 }
 
 pub struct NetworkConfig {
@@ -64,7 +76,7 @@ pub struct NetworkConfig {
     /// The Network
     network: Network,
     /// Peer ID
-    peer_id: PeerID,
+    peer_id: u64,
     /// RPC Port
     rpc_port: u16,
     /// RPC Credits Per Hash
@@ -72,28 +84,60 @@ pub struct NetworkConfig {
     our_support_flags: PeerSupportFlags,
     minimum_peer_support_flags: PeerSupportFlags,
     handshake_timeout: time::Duration,
-    max_in_peers: u32,
-    target_out_peers: u32,
+    transports: Transports,
 }
 
 impl Default for NetworkConfig {
     fn default() -> Self {
+        NetworkConfig::for_network(Network::Mainnet)
+    }
+}
+
+impl NetworkConfig {
+    /// Returns the default config for this network, with a random peer ID.
+    pub fn for_network(network: Network) -> Self {
         NetworkConfig {
-            my_port: 18080,
-            network: Network::MainNet,
-            peer_id: PeerID(21),
+            my_port: match network {
+                Network::Mainnet | Network::Regtest => 18080,
+                Network::Testnet => 28080,
+                Network::Stagenet => 38080,
+            },
+            network,
+            peer_id: rand::random(),
             rpc_port: 0,
             rpc_credits_per_hash: 0,
             our_support_flags: PeerSupportFlags::get_support_flag_fluffy_blocks(),
             minimum_peer_support_flags: PeerSupportFlags::from(0_u32),
             handshake_timeout: time::Duration::from_secs(5),
-            max_in_peers: 13,
-            target_out_peers: 21,
+            transports: Transports::default(),
         }
     }
-}
 
-impl NetworkConfig {
+    pub fn with_my_port(mut self, my_port: u32) -> Self {
+        self.my_port = my_port;
+        self
+    }
+
+    pub fn with_handshake_timeout(mut self, handshake_timeout: time::Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    pub fn with_minimum_peer_support_flags(mut self, support_flags: PeerSupportFlags) -> Self {
+        self.minimum_peer_support_flags = support_flags;
+        self
+    }
+
+    /// Sets the transport used for connections to peers in this zone.
+    pub fn with_transport(mut self, zone: NetZone, transport: impl Transport + 'static) -> Self {
+        self.transports = self.transports.with_transport(zone, transport);
+        self
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
     pub fn basic_node_data(&self) -> BasicNodeData {
         BasicNodeData {
             my_port: self.my_port,
@@ -106,23 +150,33 @@ impl NetworkConfig {
     }
 }
 
-pub struct Handshake<W, R> {
-    sink: MessageSink<W, Message>,
-    stream: MessageStream<R, Message>,
-    direction: Direction,
-    addr: NetworkAddress,
+/// A request to handshake with the peer on the other end of `stream`.
+pub struct Handshake<S> {
+    pub stream: S,
+    pub direction: Direction,
+    pub addr: NetworkAddress,
 }
 
-pub struct Handshaker<Bc, Svc, AdrBook> {
-    config: NetworkConfig,
+#[derive(Clone)]
+pub struct Handshaker<Bc, AdrBook> {
+    config: Arc<NetworkConfig>,
     parent_span: tracing::Span,
     address_book: AdrBook,
     blockchain: Bc,
-    peer_sync_states: mpsc::Sender<PeerSyncChange>,
-    peer_request_service: Svc,
 }
 
-impl<Bc, Svc, AdrBook, W, R> tower::Service<Handshake<W, R>> for Handshaker<Bc, Svc, AdrBook>
+impl<Bc, AdrBook> Handshaker<Bc, AdrBook> {
+    pub fn new(config: NetworkConfig, address_book: AdrBook, blockchain: Bc) -> Self {
+        Handshaker {
+            config: Arc::new(config),
+            parent_span: tracing::Span::current(),
+            address_book,
+            blockchain,
+        }
+    }
+}
+
+impl<Bc, AdrBook, S> Service<Handshake<S>> for Handshaker<Bc, AdrBook>
 where
     Bc: Service<DataBaseRequest, Response = DataBaseResponse, Error = DatabaseError>
         + Clone
@@ -130,66 +184,58 @@ where
         + 'static,
     Bc::Future: Send,
 
-    Svc: Service<InternalMessageRequest, Response = InternalMessageResponse, Error = PeerError>
-        + Clone
-        + Send
-        + 'static,
-    Svc::Future: Send,
-
     AdrBook: Service<AddressBookRequest, Response = AddressBookResponse, Error = AddressBookError>
         + Clone
         + Send
         + 'static,
     AdrBook::Future: Send,
 
-    W: AsyncWrite + std::marker::Unpin + Send + 'static,
-    R: AsyncRead + std::marker::Unpin + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     type Error = HandShakeError;
-    type Response = Client;
+    type Response = Peer<BoxedPeerStream, Bc>;
     type Future =
         Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
 
     fn poll_ready(
         &mut self,
-        cx: &mut std::task::Context<'_>,
+        _: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Result<(), Self::Error>> {
         std::task::Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Handshake<W, R>) -> Self::Future {
+    fn call(&mut self, req: Handshake<S>) -> Self::Future {
         let Handshake {
-            sink: mut peer_sink,
-            stream: mut peer_stream,
+            stream,
             direction,
             addr,
         } = req;
 
-        let span = tracing::debug_span!("Handshaker");
-
-        let connection_span = tracing::debug_span!(parent: &self.parent_span, "Connection");
+        let span = tracing::debug_span!(parent: &self.parent_span, "handshaker", ?addr, ?direction);
 
-        let blockchain = self.blockchain.clone();
+        let transport = self.config.transports.get(addr.get_zone()).clone();
+        let config = self.config.clone();
         let address_book = self.address_book.clone();
-        let syncer_tx = self.peer_sync_states.clone();
-        let peer_request_service = self.peer_request_service.clone();
+        let blockchain = self.blockchain.clone();
 
-        let state_machine = HandshakeSM {
-            peer_sink,
-            peer_stream,
-            direction,
-            addr,
-            network: self.config.network,
-            basic_node_data: self.config.basic_node_data(),
-            minimum_support_flags: self.config.minimum_peer_support_flags,
-            address_book,
-            blockchain,
-            peer_request_service,
-            connection_span,
-            state: HandshakeState::Start,
+        let handshake = async move {
+            let stream = transport
+                .wrap(Box::new(stream), addr, direction)
+                .await
+                .map_err(HandShakeError::TransportError)?;
+
+            let state_machine = HandshakeSM {
+                framed: Framed::new(stream, MoneroWireCodec::default()),
+                direction,
+                addr,
+                config,
+                address_book,
+                blockchain,
+            };
+            state_machine.do_handshake().await
         };
 
-        let ret = time::timeout(self.config.handshake_timeout, state_machine.do_handshake());
+        let ret = time::timeout(self.config.handshake_timeout, handshake);
 
         async move {
             match ret.await {
@@ -197,137 +243,138 @@ where
                 Err(_) => Err(HandShakeError::PeerTimedOut),
             }
         }
+        .instrument(span)
         .boxed()
     }
 }
 
-enum HandshakeState {
-    Start,
-    WaitingForHandshakeResponse,
-    WaitingForSupportFlagResponse(BasicNodeData),
-    Complete(BasicNodeData),
-}
-
-impl HandshakeState {
-    pub fn is_complete(&self) -> bool {
-        matches!(self, HandshakeState::Complete(_))
-    }
-
-    pub fn peer_basic_node_data(self) -> Option<BasicNodeData> {
-        match self {
-            HandshakeState::Complete(sup) => Some(sup),
-            _ => None,
-        }
-    }
-}
-
-struct HandshakeSM<Bc, Svc, AdrBook, W, R> {
-    peer_sink: MessageSink<W, Message>,
-    peer_stream: MessageStream<R, Message>,
+struct HandshakeSM<Bc, AdrBook, S> {
+    framed: Framed<S, MoneroWireCodec>,
     direction: Direction,
     addr: NetworkAddress,
-    network: Network,
+    config: Arc<NetworkConfig>,
 
-    basic_node_data: BasicNodeData,
-    minimum_support_flags: PeerSupportFlags,
     address_book: AdrBook,
     blockchain: Bc,
-    peer_request_service: Svc,
-    connection_span: tracing::Span,
-
-    state: HandshakeState,
 }
 
-impl<Bc, Svc, AdrBook, W, R> HandshakeSM<Bc, Svc, AdrBook, W, R>
+impl<Bc, AdrBook, S> HandshakeSM<Bc, AdrBook, S>
 where
-    Bc: Service<DataBaseRequest, Response = DataBaseResponse, Error = DatabaseError>
-        + Clone
-        + Send
-        + 'static,
-    Bc::Future: Send,
-
-    Svc: Service<InternalMessageRequest, Response = InternalMessageResponse, Error = PeerError>
-        + Clone
-        + Send
-        + 'static,
-    Svc::Future: Send,
-
-    AdrBook: Service<AddressBookRequest, Response = AddressBookResponse, Error = AddressBookError>
-        + Clone
-        + Send
-        + 'static,
-    AdrBook::Future: Send,
-
-    W: AsyncWrite + std::marker::Unpin + Send + 'static,
-    R: AsyncRead + std::marker::Unpin + Send + 'static,
+    Bc: Service<DataBaseRequest, Response = DataBaseResponse, Error = DatabaseError>,
+    AdrBook: Service<AddressBookRequest, Response = AddressBookResponse, Error = AddressBookError>,
+    S: AsyncRead + AsyncWrite + Unpin,
 {
     async fn get_our_core_sync(&mut self) -> Result<CoreSyncData, DatabaseError> {
         let blockchain = self.blockchain.ready().await?;
-        let DataBaseResponse::CoreSyncData(core_sync) = blockchain.call(DataBaseRequest::CoreSyncData).await? else {
+        let DataBaseResponse::CoreSyncData(core_sync) =
+            blockchain.call(DataBaseRequest::CoreSyncData).await?
+        else {
             unreachable!("Database will always return the requested item")
         };
         Ok(core_sync)
     }
 
-    async fn send_handshake_req(
-        &mut self,
-        node_data: BasicNodeData,
-        payload_data: CoreSyncData,
-    ) -> Result<(), HandShakeError> {
-        let handshake_req = HandshakeRequest {
-            node_data,
-            payload_data,
-        };
-
-        tracing::trace!("Sending handshake request: {handshake_req:?}");
-
-        let message: Message = Message::Request(handshake_req.into());
-        self.peer_sink.send(message).await?;
+    async fn send(&mut self, message: Message) -> Result<(), HandShakeError> {
+        self.framed.send(message).await?;
         Ok(())
     }
 
-    async fn get_handshake_res(&mut self) -> Result<HandshakeResponse, HandShakeError> {
-        // put a timeout on this
-        let Message::Response(MessageResponse::Handshake(handshake_res)) =  self.peer_stream.next().await.expect("MessageSink will not return None")? else {
-            return Err(HandShakeError::PeerSentWrongResponse);
-        };
+    async fn receive(&mut self) -> Result<Message, HandShakeError> {
+        match self.framed.next().await {
+            Some(message) => Ok(message?),
+            None => Err(HandShakeError::PeerClosedConnection),
+        }
+    }
+
+    /// Checks the peer's node data, common to both roles.
+    fn check_peer_node_data(&self, peer_node_data: &BasicNodeData) -> Result<(), HandShakeError> {
+        if peer_node_data.network_id != self.config.network.network_id() {
+            tracing::debug!("Handshake failed: peer is on a different network");
+            return Err(HandShakeError::PeerIsOnADifferentNetwork);
+        }
 
-        tracing::trace!("Received handshake response: {handshake_res:?}");
+        // Like monerod, a peer with our peer ID is assumed to be us, connected through one of our
+        // own addresses.
+        if peer_node_data.peer_id == self.config.peer_id {
+            tracing::debug!("Handshake failed: connected to ourself");
+            return Err(HandShakeError::PeerIsOurself);
+        }
 
-        Ok(handshake_res)
+        if !peer_node_data.support_flags.is_empty()
+            && !peer_node_data
+                .support_flags
+                .contains(&self.config.minimum_peer_support_flags)
+        {
+            tracing::debug!("Handshake failed: peer does not have minimum support flags");
+            return Err(HandShakeError::PeerDoesNotHaveTheMinimumSupportFlags);
+        }
+
+        Ok(())
     }
 
-    async fn send_support_flag_req(&mut self) -> Result<(), HandShakeError> {
+    /// Requests the peer's support flags, old peers do not send them in their node data.
+    async fn get_support_flags(&mut self) -> Result<PeerSupportFlags, HandShakeError> {
         tracing::trace!("Peer sent no support flags, sending request");
 
-        let message: Message = Message::Request(SupportFlagsRequest.into());
-        self.peer_sink.send(message).await?;
+        self.send(Message::Request(RequestMessage::SupportFlags))
+            .await?;
 
-        Ok(())
+        loop {
+            match self.receive().await? {
+                Message::Response(ResponseMessage::SupportFlags(res)) => {
+                    if !res
+                        .support_flags
+                        .contains(&self.config.minimum_peer_support_flags)
+                    {
+                        tracing::debug!(
+                            "Handshake failed: peer does not have minimum support flags"
+                        );
+                        return Err(HandShakeError::PeerDoesNotHaveTheMinimumSupportFlags);
+                    }
+                    return Ok(res.support_flags);
+                }
+                Message::Request(RequestMessage::SupportFlags) => self.send_support_flags().await?,
+                _ => return Err(HandShakeError::PeerSentWrongResponse),
+            }
+        }
     }
 
-    async fn handle_handshake_response(
+    async fn send_support_flags(&mut self) -> Result<(), HandShakeError> {
+        let message = Message::Response(ResponseMessage::SupportFlags(SupportFlagsResponse {
+            support_flags: self.config.our_support_flags,
+        }));
+        self.send(message).await
+    }
+
+    async fn do_outbound_handshake(
         &mut self,
-        res: HandshakeResponse,
-    ) -> Result<(), HandShakeError> {
+    ) -> Result<(BasicNodeData, CoreSyncData), HandShakeError> {
+        let handshake_req = HandshakeRequest {
+            node_data: self.config.basic_node_data(),
+            payload_data: self.get_our_core_sync().await?,
+        };
+
+        tracing::trace!("Sending handshake request");
+        self.send(Message::Request(RequestMessage::Handshake(handshake_req)))
+            .await?;
+
+        let res = loop {
+            match self.receive().await? {
+                Message::Response(ResponseMessage::Handshake(res)) => break res,
+                Message::Request(RequestMessage::SupportFlags) => self.send_support_flags().await?,
+                _ => return Err(HandShakeError::PeerSentWrongResponse),
+            }
+        };
+
+        tracing::trace!("Received handshake response");
+
         let HandshakeResponse {
             node_data: peer_node_data,
             payload_data: peer_core_sync,
             local_peerlist_new,
         } = res;
 
-        if !peer_node_data
-            .support_flags
-            .contains(&self.minimum_support_flags)
-        {
-            tracing::debug!("Handshake failed: peer does not have minimum support flags");
-            return Err(HandShakeError::PeerDoesNotHaveTheMinimumSupportFlags);
-        }
-
-        if peer_node_data.network_id != self.network.network_id() {
-            tracing::debug!("Handshake failed: peer is on a different network");
-            return Err(HandShakeError::PeerIsOnADifferentNetwork);
-        }
+        self.check_peer_node_data(&peer_node_data)?;
 
         if local_peerlist_new.len() > P2P_MAX_PEERS_IN_HANDSHAKE {
             tracing::debug!("Handshake failed: peer sent too many peers in response");
@@ -344,111 +391,88 @@ where
             ))
             .await?;
 
-        // coresync, pruning seed
-
-        if peer_node_data.support_flags.is_empty() {
-            self.send_support_flag_req().await?;
-            self.state = HandshakeState::WaitingForSupportFlagResponse(peer_node_data);
-        } else {
-            self.state = HandshakeState::Complete(peer_node_data);
+        // Record the RPC port the peer advertised, the peer might not be in our address book if we
+        // did not get its address from a peer list.
+        match self
+            .address_book
+            .ready()
+            .await?
+            .call(AddressBookRequest::SetPeerRpcPort(
+                self.addr,
+                peer_node_data.rpc_port,
+                peer_node_data.rpc_credits_per_hash,
+            ))
+            .await
+        {
+            Ok(_) | Err(AddressBookError::PeerNotFound) => (),
+            Err(e) => return Err(e.into()),
         }
 
-        Ok(())
+        Ok((peer_node_data, peer_core_sync))
     }
 
-    async fn handle_message_response(
+    async fn do_inbound_handshake(
         &mut self,
-        response: MessageResponse,
-    ) -> Result<(), HandShakeError> {
-        match (&mut self.state, response) {
-            (
-                HandshakeState::WaitingForHandshakeResponse,
-                MessageResponse::Handshake(handshake),
-            ) => self.handle_handshake_response(handshake).await,
-            (
-                HandshakeState::WaitingForSupportFlagResponse(bnd),
-                MessageResponse::SupportFlags(support_flags),
-            ) => {
-                bnd.support_flags = support_flags.support_flags;
-                self.state = HandshakeState::Complete(bnd.clone());
-                Ok(())
-            }
-            _ => Err(HandShakeError::PeerSentWrongResponse),
-        }
-    }
+    ) -> Result<(BasicNodeData, CoreSyncData), HandShakeError> {
+        let Message::Request(RequestMessage::Handshake(req)) = self.receive().await? else {
+            tracing::debug!("Handshake failed: peer did not start with a handshake request");
+            return Err(HandShakeError::PeerSentWrongResponse);
+        };
 
-    async fn send_support_flags(
-        &mut self,
-        support_flags: PeerSupportFlags,
-    ) -> Result<(), HandShakeError> {
-        let message = Message::Response(SupportFlagsResponse { support_flags }.into());
-        self.peer_sink.send(message).await?;
-        Ok(())
-    }
+        tracing::trace!("Received handshake request");
+
+        self.check_peer_node_data(&req.node_data)?;
+
+        let AddressBookResponse::Peers(local_peerlist_new) = self
+            .address_book
+            .ready()
+            .await?
+            .call(AddressBookRequest::GetPeersToGossip(self.addr.get_zone()))
+            .await?
+        else {
+            unreachable!("Address book will always return the requested item")
+        };
 
-    async fn do_outbound_handshake(&mut self) -> Result<(), HandShakeError> {
-        let core_sync = self.get_our_core_sync().await?;
-        self.send_handshake_req(self.basic_node_data.clone(), core_sync)
+        let handshake_res = HandshakeResponse {
+            node_data: self.config.basic_node_data(),
+            payload_data: self.get_our_core_sync().await?,
+            local_peerlist_new,
+        };
+
+        tracing::trace!("Sending handshake response");
+        self.send(Message::Response(ResponseMessage::Handshake(handshake_res)))
             .await?;
-        self.state = HandshakeState::WaitingForHandshakeResponse;
-
-        while !self.state.is_complete() {
-            match self.peer_stream.next().await {
-                Some(mes) => {
-                    let mes = mes?;
-                    match mes {
-                        Message::Request(MessageRequest::SupportFlags(_)) => {
-                            self.send_support_flags(self.basic_node_data.support_flags)
-                                .await?
-                        }
-                        Message::Response(response) => {
-                            self.handle_message_response(response).await?
-                        }
-                        _ => return Err(HandShakeError::PeerSentWrongResponse),
-                    }
-                }
-                None => unreachable!("peer_stream wont return None"),
-            }
-        }
 
-        Ok(())
+        Ok((req.node_data, req.payload_data))
     }
 
-    async fn do_handshake(mut self) -> Result<Client, HandShakeError> {
-        match self.direction {
+    async fn do_handshake(mut self) -> Result<Peer<S, Bc>, HandShakeError> {
+        let (mut peer_node_data, peer_core_sync) = match self.direction {
             Direction::Outbound => self.do_outbound_handshake().await?,
-            Direction::Inbound => todo!(),
-        }
+            Direction::Inbound => self.do_inbound_handshake().await?,
+        };
 
-        let (server_tx, server_rx) = mpsc::channel(3);
+        if peer_node_data.support_flags.is_empty() {
+            peer_node_data.support_flags = self.get_support_flags().await?;
+        }
 
-        let (replace_me, replace_me_rx) = mpsc::channel(3);
+        tracing::debug!("Handshake complete");
 
-        let peer_node_data = self
-            .state
-            .peer_basic_node_data()
-            .expect("We must be in state complete to be here");
         let connection_info = ConnectionInfo {
             addr: self.addr,
+            direction: self.direction,
             support_flags: peer_node_data.support_flags,
             peer_id: peer_node_data.peer_id,
             rpc_port: peer_node_data.rpc_port,
             rpc_credits_per_hash: peer_node_data.rpc_credits_per_hash,
+            core_sync_data: peer_core_sync,
         };
 
-        let connection = Connection::new(
-            self.addr,
-            self.peer_sink,
-            self.peer_stream,
-            server_rx,
-            replace_me,
-            self.peer_request_service,
-        );
-
-        let client = Client::new(connection_info.into(), server_tx);
-
-        tokio::task::spawn(connection.run().instrument(self.connection_span));
-
-        Ok(client)
+        Ok(Peer::new(
+            self.framed,
+            connection_info,
+            self.config.basic_node_data(),
+            self.blockchain,
+        ))
     }
 }
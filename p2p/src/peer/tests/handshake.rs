@@ -1 +1,239 @@
-pub use crate::peer::handshaker::{Handshake, Handshaker};
+use std::net::SocketAddr;
+
+use futures::future::ready;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tower::{service_fn, ServiceExt};
+
+use cuprate_common::Network;
+use monero_wire::{messages::CoreSyncData, network_address::NetZone, NetworkAddress};
+
+use crate::address_book::{AddressBookError, AddressBookRequest, AddressBookResponse};
+use crate::peer::{HandShakeError, Handshake, Handshaker, NetworkConfig, PeerError};
+use crate::protocol::{
+    temp_database::{DataBaseRequest, DataBaseResponse, DatabaseError},
+    Direction,
+};
+use crate::transport::{BoxedPeerStream, Transport};
+
+fn handshaker(
+    network: Network,
+    height: u64,
+) -> Handshaker<
+    impl tower::Service<
+            DataBaseRequest,
+            Response = DataBaseResponse,
+            Error = DatabaseError,
+            Future = impl Send,
+        > + Clone
+        + Send
+        + 'static,
+    impl tower::Service<
+            AddressBookRequest,
+            Response = AddressBookResponse,
+            Error = AddressBookError,
+            Future = impl Send,
+        > + Clone
+        + Send
+        + 'static,
+> {
+    handshaker_with_config(NetworkConfig::for_network(network), height)
+}
+
+fn handshaker_with_config(
+    config: NetworkConfig,
+    height: u64,
+) -> Handshaker<
+    impl tower::Service<
+            DataBaseRequest,
+            Response = DataBaseResponse,
+            Error = DatabaseError,
+            Future = impl Send,
+        > + Clone
+        + Send
+        + 'static,
+    impl tower::Service<
+            AddressBookRequest,
+            Response = AddressBookResponse,
+            Error = AddressBookError,
+            Future = impl Send,
+        > + Clone
+        + Send
+        + 'static,
+> {
+    let blockchain = service_fn(move |req| {
+        let DataBaseRequest::CoreSyncData = req else {
+            panic!("Handshaker should only ask for core sync data")
+        };
+        ready(Ok(DataBaseResponse::CoreSyncData(CoreSyncData::new(
+            height.into(),
+            height,
+            0,
+            [0; 32],
+            1,
+        ))))
+    });
+    let address_book = service_fn(|req: AddressBookRequest| {
+        ready(Ok(match req {
+            AddressBookRequest::GetPeersToGossip(_) => AddressBookResponse::Peers(vec![]),
+            _ => AddressBookResponse::Ok,
+        }))
+    });
+
+    Handshaker::new(config, address_book, blockchain)
+}
+
+/// A transport that sends a magic before the Levin messages.
+struct MagicTransport;
+
+#[async_trait::async_trait]
+impl Transport for MagicTransport {
+    async fn wrap(
+        &self,
+        mut stream: BoxedPeerStream,
+        _: NetworkAddress,
+        direction: Direction,
+    ) -> Result<BoxedPeerStream, std::io::Error> {
+        match direction {
+            Direction::Outbound => stream.write_all(b"magic").await?,
+            Direction::Inbound => {
+                let mut magic = [0; 5];
+                stream.read_exact(&mut magic).await?;
+                if &magic != b"magic" {
+                    return Err(std::io::ErrorKind::InvalidData.into());
+                }
+            }
+        }
+        Ok(stream)
+    }
+}
+
+fn addr() -> monero_wire::NetworkAddress {
+    "127.0.0.1:18080".parse::<SocketAddr>().unwrap().into()
+}
+
+#[tokio::test]
+async fn handshake_and_ping() {
+    let (outbound_stream, inbound_stream) = tokio::io::duplex(1024 * 1024);
+
+    let outbound = handshaker(Network::Mainnet, 10).oneshot(Handshake {
+        stream: outbound_stream,
+        direction: Direction::Outbound,
+        addr: addr(),
+    });
+    let inbound = handshaker(Network::Mainnet, 20).oneshot(Handshake {
+        stream: inbound_stream,
+        direction: Direction::Inbound,
+        addr: addr(),
+    });
+
+    let (outbound, inbound) = tokio::join!(outbound, inbound);
+    let (mut outbound, mut inbound) = (outbound.unwrap(), inbound.unwrap());
+
+    assert_eq!(outbound.info().core_sync_data.current_height, 20);
+    assert_eq!(inbound.info().core_sync_data.current_height, 10);
+    assert!(outbound.info().support_flags.supports_fluffy_blocks());
+
+    // The inbound peer answers the ping while waiting for a protocol message.
+    let inbound_task = tokio::spawn(async move { inbound.next_message().await.err() });
+
+    outbound.ping().await.unwrap();
+    outbound.timed_sync().await.unwrap();
+    drop(outbound);
+
+    assert!(matches!(
+        inbound_task.await.unwrap(),
+        Some(PeerError::PeerConnectionClosed)
+    ));
+}
+
+#[tokio::test]
+async fn handshake_fails_across_networks() {
+    let (outbound_stream, inbound_stream) = tokio::io::duplex(1024 * 1024);
+
+    let outbound = handshaker(Network::Mainnet, 10).oneshot(Handshake {
+        stream: outbound_stream,
+        direction: Direction::Outbound,
+        addr: addr(),
+    });
+    let inbound = handshaker(Network::Testnet, 10).oneshot(Handshake {
+        stream: inbound_stream,
+        direction: Direction::Inbound,
+        addr: addr(),
+    });
+
+    let (outbound, inbound) = tokio::join!(outbound, inbound);
+
+    assert!(matches!(
+        inbound.err(),
+        Some(HandShakeError::PeerIsOnADifferentNetwork)
+    ));
+    assert!(outbound.is_err());
+}
+
+#[tokio::test]
+async fn handshake_fails_with_ourself() {
+    let (outbound_stream, inbound_stream) = tokio::io::duplex(1024 * 1024);
+
+    // Both sides share a config, so they have the same peer ID.
+    let handshaker = handshaker(Network::Mainnet, 10);
+    let outbound = handshaker.clone().oneshot(Handshake {
+        stream: outbound_stream,
+        direction: Direction::Outbound,
+        addr: addr(),
+    });
+    let inbound = handshaker.oneshot(Handshake {
+        stream: inbound_stream,
+        direction: Direction::Inbound,
+        addr: addr(),
+    });
+
+    let (outbound, inbound) = tokio::join!(outbound, inbound);
+
+    assert!(matches!(inbound.err(), Some(HandShakeError::PeerIsOurself)));
+    assert!(outbound.is_err());
+}
+
+#[tokio::test]
+async fn handshake_over_transport() {
+    let config = || {
+        NetworkConfig::for_network(Network::Mainnet).with_transport(NetZone::Public, MagicTransport)
+    };
+
+    let (outbound_stream, inbound_stream) = tokio::io::duplex(1024 * 1024);
+
+    let outbound = handshaker_with_config(config(), 10).oneshot(Handshake {
+        stream: outbound_stream,
+        direction: Direction::Outbound,
+        addr: addr(),
+    });
+    let inbound = handshaker_with_config(config(), 20).oneshot(Handshake {
+        stream: inbound_stream,
+        direction: Direction::Inbound,
+        addr: addr(),
+    });
+
+    let (outbound, inbound) = tokio::join!(outbound, inbound);
+    assert_eq!(outbound.unwrap().info().core_sync_data.current_height, 20);
+    assert_eq!(inbound.unwrap().info().core_sync_data.current_height, 10);
+
+    // A peer without the transport sends a Levin header instead of the magic.
+    let (outbound_stream, inbound_stream) = tokio::io::duplex(1024 * 1024);
+
+    let outbound = handshaker(Network::Mainnet, 10).oneshot(Handshake {
+        stream: outbound_stream,
+        direction: Direction::Outbound,
+        addr: addr(),
+    });
+    let inbound = handshaker_with_config(config(), 20).oneshot(Handshake {
+        stream: inbound_stream,
+        direction: Direction::Inbound,
+        addr: addr(),
+    });
+
+    let (outbound, inbound) = tokio::join!(outbound, inbound);
+    assert!(matches!(
+        inbound.err(),
+        Some(HandShakeError::TransportError(_))
+    ));
+    assert!(outbound.is_err());
+}
@@ -1,4 +1,4 @@
-use monero_wire::messages::CoreSyncData;
+use monero_wire::messages::{common::BlockCompleteEntry, CoreSyncData};
 use thiserror::Error;
 
 pub enum BlockKnown {
@@ -21,6 +21,16 @@ pub enum DataBaseRequest {
     Chain,
     BlockHeight([u8; 32]),
     BlockKnown([u8; 32]),
+    /// The IDs and weights of up to `count` main chain blocks, starting at `start_height`.
+    ChainEntry {
+        start_height: u64,
+        count: usize,
+    },
+    /// A main chain block with its transactions, without their prunable data if `pruned`.
+    BlockCompleteEntry {
+        id: [u8; 32],
+        pruned: bool,
+    },
 }
 
 pub enum DataBaseResponse {
@@ -30,7 +40,16 @@ pub enum DataBaseResponse {
     Chain(Vec<[u8; 32]>),
     BlockHeight(Option<u64>),
     BlockKnown(BlockKnown),
+    ChainEntry {
+        block_ids: Vec<[u8; 32]>,
+        block_weights: Vec<u64>,
+    },
+    BlockCompleteEntry(Option<BlockCompleteEntry>),
 }
 
 #[derive(Debug, Error, PartialEq, Eq)]
-pub enum DatabaseError {}
+pub enum DatabaseError {
+    /// The database behind the service failed.
+    #[error("{0}")]
+    Internal(String),
+}
@@ -0,0 +1,646 @@
+//! # Request Handler
+//!
+//! This module contains [`PeerRequestHandler`], which answers the block requests of a connected peer
+//! from our database, so other nodes can sync from us:
+//! - `REQUEST_CHAIN`, answered with the IDs of the blocks on our chain after the last block we share
+//!   with the peer.
+//! - `GET_OBJECTS`, answered with the requested blocks and their transactions.
+//! - `FLUFFY_MISSING_TX`, answered with a fluffy block holding the requested transactions.
+//!
+//! A pruned node only has the prunable data of the blocks in its stripe and the blocks near the top of
+//! the chain, blocks requested unpruned outside of these are returned in `missed_ids` so the peer can
+//! ask a node that has them.
+//!
+//! Each peer gets its own handler and every request takes a token from the handler's bucket, which is
+//! refilled at [`RequestHandlerConfig::requests_per_second`]. A peer that requests faster than this
+//! is sent no response, the caller should drop it.
+//!
+use std::time::Instant;
+
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower::{Service, ServiceExt};
+
+use cuprate_common::{
+    pruning::{PruningError, PruningSeed},
+    CRYPTONOTE_MAX_BLOCK_NUMBER,
+};
+use monero_wire::{
+    messages::common::{BlockCompleteEntry, TransactionBlobs},
+    ChainRequest, ChainResponse, FluffyMissingTransactionsRequest, GetObjectsRequest,
+    GetObjectsResponse, NewFluffyBlock, ProtocolMessage,
+};
+
+use crate::peer::{Peer, PeerError};
+use crate::protocol::{
+    temp_database::{DataBaseRequest, DataBaseResponse, DatabaseError},
+    BLOCKS_IDS_SYNCHRONIZING_DEFAULT_COUNT,
+};
+
+/// The maximum amount of blocks a peer can request in one `GET_OBJECTS` request.
+pub const MAX_OBJECT_REQUEST_COUNT: usize = 100;
+/// The maximum amount of block IDs in a `REQUEST_CHAIN` request. monerod sends the IDs of its top
+/// 10 blocks, then the IDs of blocks a power of 2 further down its chain, then the genesis block.
+pub const MAX_CHAIN_REQUEST_IDS: usize =
+    10 + (u64::BITS - CRYPTONOTE_MAX_BLOCK_NUMBER.leading_zeros()) as usize + 1;
+
+/// The default amount of requests a peer can send at once.
+pub const DEFAULT_REQUEST_BURST: u32 = 50;
+/// The default amount of requests a peer can send a second once its burst is used.
+pub const DEFAULT_REQUESTS_PER_SECOND: u32 = 10;
+
+#[derive(Debug, Error)]
+pub enum RequestHandlerError {
+    #[error("The peer sent requests faster than the rate limit")]
+    RateLimited,
+    #[error("The peer requested more than {MAX_OBJECT_REQUEST_COUNT} blocks")]
+    TooManyBlocks,
+    #[error("The peer's chain history has more than {MAX_CHAIN_REQUEST_IDS} block IDs")]
+    TooManyBlockIds,
+    #[error("The peer's chain history does not end with our genesis block")]
+    GenesisMismatch,
+    #[error("The peer requested a block we don't have")]
+    UnknownBlock,
+    #[error("The peer requested a transaction the block does not have")]
+    InvalidTxIndex,
+    #[error("The peer requested prunable data we have pruned")]
+    PrunedData,
+    #[error("Pruning error: {0}")]
+    Pruning(#[from] PruningError),
+    #[error("Peer error: {0}")]
+    Peer(#[from] PeerError),
+    #[error("Database error: {0}")]
+    Database(#[from] DatabaseError),
+}
+
+/// A request from a peer for data from our chain.
+#[derive(Debug, Clone)]
+pub enum PeerRequest {
+    Chain(ChainRequest),
+    GetObjects(GetObjectsRequest),
+    FluffyMissingTxs(FluffyMissingTransactionsRequest),
+}
+
+impl TryFrom<ProtocolMessage> for PeerRequest {
+    type Error = ProtocolMessage;
+
+    /// Returns the message back if it is not a request.
+    fn try_from(message: ProtocolMessage) -> Result<Self, Self::Error> {
+        match message {
+            ProtocolMessage::ChainRequest(req) => Ok(PeerRequest::Chain(req)),
+            ProtocolMessage::GetObjectsRequest(req) => Ok(PeerRequest::GetObjects(req)),
+            ProtocolMessage::FluffyMissingTransactionsRequest(req) => {
+                Ok(PeerRequest::FluffyMissingTxs(req))
+            }
+            message => Err(message),
+        }
+    }
+}
+
+/// The config for a [`PeerRequestHandler`].
+#[derive(Debug, Clone)]
+pub struct RequestHandlerConfig {
+    /// The amount of requests a peer can send at once.
+    pub burst: u32,
+    /// The amount of requests a peer can send a second once its burst is used.
+    pub requests_per_second: u32,
+}
+
+impl Default for RequestHandlerConfig {
+    fn default() -> Self {
+        RequestHandlerConfig {
+            burst: DEFAULT_REQUEST_BURST,
+            requests_per_second: DEFAULT_REQUESTS_PER_SECOND,
+        }
+    }
+}
+
+/// A token bucket, a request takes a token and tokens are refilled over time up to the burst.
+#[derive(Debug)]
+struct RateLimit {
+    config: RequestHandlerConfig,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimit {
+    fn new(config: RequestHandlerConfig, now: Instant) -> Self {
+        RateLimit {
+            tokens: config.burst.into(),
+            config,
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token, returns false if there are none left.
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * f64::from(self.config.requests_per_second))
+            .min(self.config.burst.into());
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Answers the block requests of a single peer from our database.
+pub struct PeerRequestHandler<Bc> {
+    blockchain: Bc,
+    rate_limit: RateLimit,
+}
+
+impl<Bc> PeerRequestHandler<Bc>
+where
+    Bc: Service<DataBaseRequest, Response = DataBaseResponse, Error = DatabaseError>,
+{
+    pub fn new(blockchain: Bc, config: RequestHandlerConfig) -> Self {
+        PeerRequestHandler {
+            blockchain,
+            rate_limit: RateLimit::new(config, Instant::now()),
+        }
+    }
+
+    /// Returns the response to the peer's request.
+    pub async fn handle_request(
+        &mut self,
+        req: PeerRequest,
+    ) -> Result<ProtocolMessage, RequestHandlerError> {
+        if !self.rate_limit.take(Instant::now()) {
+            return Err(RequestHandlerError::RateLimited);
+        }
+
+        match req {
+            PeerRequest::Chain(req) => self
+                .chain_entry(req)
+                .await
+                .map(ProtocolMessage::ChainEntryResponse),
+            PeerRequest::GetObjects(req) => self
+                .get_objects(req)
+                .await
+                .map(ProtocolMessage::GetObjectsResponse),
+            PeerRequest::FluffyMissingTxs(req) => self
+                .fluffy_missing_txs(req)
+                .await
+                .map(ProtocolMessage::NewFluffyBlock),
+        }
+    }
+
+    /// Answers the peer's requests until it sends a message that is not a request, which is returned.
+    pub async fn serve<S, PBc>(
+        &mut self,
+        peer: &mut Peer<S, PBc>,
+    ) -> Result<ProtocolMessage, RequestHandlerError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        PBc: Service<DataBaseRequest, Response = DataBaseResponse, Error = DatabaseError>,
+    {
+        loop {
+            let req = match PeerRequest::try_from(peer.next_message().await?) {
+                Ok(req) => req,
+                Err(message) => return Ok(message),
+            };
+
+            let res = self.handle_request(req).await?;
+            peer.send_protocol_message(res).await?;
+        }
+    }
+
+    async fn chain_entry(
+        &mut self,
+        req: ChainRequest,
+    ) -> Result<ChainResponse, RequestHandlerError> {
+        if req.block_ids.len() > MAX_CHAIN_REQUEST_IDS {
+            return Err(RequestHandlerError::TooManyBlockIds);
+        }
+        let Some(genesis_id) = req.block_ids.last() else {
+            return Err(RequestHandlerError::GenesisMismatch);
+        };
+        if self.block_height(*genesis_id).await? != Some(0) {
+            return Err(RequestHandlerError::GenesisMismatch);
+        }
+
+        // The IDs are ordered from the top of the peer's chain, the first we have is the split point.
+        let mut start_height = 0;
+        for id in &req.block_ids {
+            if let Some(height) = self.block_height(*id).await? {
+                start_height = height;
+                break;
+            }
+        }
+
+        let DataBaseResponse::ChainEntry {
+            block_ids,
+            block_weights,
+        } = self
+            .call(DataBaseRequest::ChainEntry {
+                start_height,
+                count: BLOCKS_IDS_SYNCHRONIZING_DEFAULT_COUNT,
+            })
+            .await?
+        else {
+            unreachable!("Database will always return the requested item")
+        };
+
+        let first_block = match block_ids.first() {
+            Some(id) => {
+                self.block_complete_entry(*id, true)
+                    .await?
+                    .ok_or(RequestHandlerError::UnknownBlock)?
+                    .block
+            }
+            None => vec![],
+        };
+
+        let DataBaseResponse::CumulativeDifficulty(cumulative_difficulty) =
+            self.call(DataBaseRequest::CumulativeDifficulty).await?
+        else {
+            unreachable!("Database will always return the requested item")
+        };
+        let (chain_height, _) = self.chain_height_and_seed().await?;
+
+        Ok(ChainResponse::new(
+            start_height,
+            chain_height,
+            cumulative_difficulty,
+            block_ids,
+            block_weights,
+            first_block,
+        ))
+    }
+
+    async fn get_objects(
+        &mut self,
+        req: GetObjectsRequest,
+    ) -> Result<GetObjectsResponse, RequestHandlerError> {
+        if req.blocks.len() > MAX_OBJECT_REQUEST_COUNT {
+            return Err(RequestHandlerError::TooManyBlocks);
+        }
+
+        let (chain_height, pruning_seed) = self.chain_height_and_seed().await?;
+
+        let mut blocks = Vec::with_capacity(req.blocks.len());
+        let mut missed_ids = Vec::new();
+
+        for id in req.blocks {
+            let Some(height) = self.block_height(id).await? else {
+                missed_ids.push(id);
+                continue;
+            };
+
+            if !req.pruned && !pruning_seed.has_unpruned_data(height, chain_height)? {
+                missed_ids.push(id);
+                continue;
+            }
+
+            match self.block_complete_entry(id, req.pruned).await? {
+                Some(block) => blocks.push(block),
+                None => missed_ids.push(id),
+            }
+        }
+
+        Ok(GetObjectsResponse {
+            blocks,
+            missed_ids,
+            current_blockchain_height: chain_height,
+        })
+    }
+
+    async fn fluffy_missing_txs(
+        &mut self,
+        req: FluffyMissingTransactionsRequest,
+    ) -> Result<NewFluffyBlock, RequestHandlerError> {
+        let height = self
+            .block_height(req.block_hash)
+            .await?
+            .ok_or(RequestHandlerError::UnknownBlock)?;
+
+        let (chain_height, pruning_seed) = self.chain_height_and_seed().await?;
+        if !pruning_seed.has_unpruned_data(height, chain_height)? {
+            return Err(RequestHandlerError::PrunedData);
+        }
+
+        let block = self
+            .block_complete_entry(req.block_hash, false)
+            .await?
+            .ok_or(RequestHandlerError::UnknownBlock)?;
+
+        let txs = match block.txs {
+            Some(TransactionBlobs::Normal(txs)) => txs,
+            Some(TransactionBlobs::Pruned(_)) => return Err(RequestHandlerError::PrunedData),
+            None => vec![],
+        };
+
+        let missing_txs = req
+            .missing_tx_indices
+            .iter()
+            .map(|&i| {
+                usize::try_from(i)
+                    .ok()
+                    .and_then(|i| txs.get(i).cloned())
+                    .ok_or(RequestHandlerError::InvalidTxIndex)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(NewFluffyBlock {
+            b: BlockCompleteEntry {
+                pruned: false,
+                block: block.block,
+                block_weight: block.block_weight,
+                txs: Some(TransactionBlobs::Normal(missing_txs)),
+            },
+            current_blockchain_height: chain_height,
+        })
+    }
+
+    async fn chain_height_and_seed(&mut self) -> Result<(u64, PruningSeed), RequestHandlerError> {
+        let DataBaseResponse::CoreSyncData(core_sync_data) =
+            self.call(DataBaseRequest::CoreSyncData).await?
+        else {
+            unreachable!("Database will always return the requested item")
+        };
+
+        Ok((
+            core_sync_data.current_height,
+            PruningSeed::try_from(core_sync_data.pruning_seed)?,
+        ))
+    }
+
+    async fn block_height(&mut self, id: [u8; 32]) -> Result<Option<u64>, RequestHandlerError> {
+        let DataBaseResponse::BlockHeight(height) =
+            self.call(DataBaseRequest::BlockHeight(id)).await?
+        else {
+            unreachable!("Database will always return the requested item")
+        };
+        Ok(height)
+    }
+
+    async fn block_complete_entry(
+        &mut self,
+        id: [u8; 32],
+        pruned: bool,
+    ) -> Result<Option<BlockCompleteEntry>, RequestHandlerError> {
+        let DataBaseResponse::BlockCompleteEntry(block) = self
+            .call(DataBaseRequest::BlockCompleteEntry { id, pruned })
+            .await?
+        else {
+            unreachable!("Database will always return the requested item")
+        };
+        Ok(block)
+    }
+
+    async fn call(&mut self, req: DataBaseRequest) -> Result<DataBaseResponse, DatabaseError> {
+        self.blockchain.ready().await?.call(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::{ready, Ready};
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+    use monero_wire::messages::CoreSyncData;
+
+    use super::*;
+
+    /// Our chain's height, long enough that a pruned node has pruned some blocks.
+    const CHAIN_HEIGHT: u64 = 20_000;
+
+    fn id(height: u64) -> [u8; 32] {
+        let mut id = [1; 32];
+        id[..8].copy_from_slice(&height.to_le_bytes());
+        id
+    }
+
+    fn height(id: &[u8; 32]) -> Option<u64> {
+        let height = u64::from_le_bytes(id[..8].try_into().unwrap());
+        (id[8..] == [1; 24] && height < CHAIN_HEIGHT).then_some(height)
+    }
+
+    /// Returns a database for a chain where block `n` has `n % 4` transactions.
+    fn blockchain(
+        pruning_seed: u32,
+    ) -> impl Service<
+        DataBaseRequest,
+        Response = DataBaseResponse,
+        Error = DatabaseError,
+        Future = Ready<Result<DataBaseResponse, DatabaseError>>,
+    > {
+        tower::service_fn(move |req| {
+            ready(Ok(match req {
+                DataBaseRequest::CoreSyncData => DataBaseResponse::CoreSyncData(CoreSyncData::new(
+                    100,
+                    CHAIN_HEIGHT,
+                    pruning_seed,
+                    id(CHAIN_HEIGHT - 1),
+                    16,
+                )),
+                DataBaseRequest::CumulativeDifficulty => {
+                    DataBaseResponse::CumulativeDifficulty(100)
+                }
+                DataBaseRequest::BlockHeight(id) => DataBaseResponse::BlockHeight(height(&id)),
+                DataBaseRequest::ChainEntry {
+                    start_height,
+                    count,
+                } => {
+                    let heights = start_height..CHAIN_HEIGHT.min(start_height + count as u64);
+                    DataBaseResponse::ChainEntry {
+                        block_ids: heights.clone().map(id).collect(),
+                        block_weights: heights.collect(),
+                    }
+                }
+                DataBaseRequest::BlockCompleteEntry { id, pruned } => {
+                    DataBaseResponse::BlockCompleteEntry(height(&id).map(|height| {
+                        let txs = (0..height % 4).map(|i| vec![i as u8]);
+                        BlockCompleteEntry {
+                            pruned,
+                            block: height.to_le_bytes().to_vec(),
+                            block_weight: height,
+                            txs: Some(if pruned {
+                                TransactionBlobs::Pruned(vec![])
+                            } else {
+                                TransactionBlobs::Normal(txs.collect())
+                            }),
+                        }
+                    }))
+                }
+                _ => unreachable!("The handler doesn't make this request"),
+            }))
+        })
+    }
+
+    #[test]
+    fn chain_entry_starts_at_the_split_point() {
+        let mut handler = PeerRequestHandler::new(blockchain(0), RequestHandlerConfig::default());
+
+        // The peer's top block is not on our chain.
+        let req = ChainRequest {
+            block_ids: vec![[7; 32], id(19_990), id(19_000), id(0)],
+            prune: false,
+        };
+        let Ok(ProtocolMessage::ChainEntryResponse(res)) =
+            block_on(handler.handle_request(PeerRequest::Chain(req)))
+        else {
+            panic!("Handler sent the wrong response");
+        };
+
+        assert_eq!(res.start_height, 19_990);
+        assert_eq!(res.total_height, CHAIN_HEIGHT);
+        assert_eq!(res.cumulative_difficulty(), 100);
+        assert_eq!(
+            res.m_block_ids,
+            (19_990..CHAIN_HEIGHT).map(id).collect::<Vec<_>>()
+        );
+        assert_eq!(res.first_block, 19_990_u64.to_le_bytes());
+
+        let req = ChainRequest {
+            block_ids: vec![id(5), [7; 32]],
+            prune: false,
+        };
+        assert!(matches!(
+            block_on(handler.handle_request(PeerRequest::Chain(req))),
+            Err(RequestHandlerError::GenesisMismatch)
+        ));
+    }
+
+    #[test]
+    fn long_chain_histories_are_refused_before_lookups() {
+        let blockchain = tower::service_fn(|_| -> Ready<Result<DataBaseResponse, DatabaseError>> {
+            unreachable!("The history must be refused before looking up its blocks")
+        });
+        let mut handler = PeerRequestHandler::new(blockchain, RequestHandlerConfig::default());
+
+        let req = ChainRequest {
+            block_ids: vec![[7; 32]; MAX_CHAIN_REQUEST_IDS + 1],
+            prune: false,
+        };
+        assert!(matches!(
+            block_on(handler.handle_request(PeerRequest::Chain(req))),
+            Err(RequestHandlerError::TooManyBlockIds)
+        ));
+    }
+
+    #[test]
+    fn get_objects_respects_our_pruning_stripe() {
+        // Stripe 1 keeps the prunable data of blocks 0 to 4095, and the blocks at the top.
+        let seed = u32::from(PruningSeed::new(1, 3).unwrap());
+        let mut handler =
+            PeerRequestHandler::new(blockchain(seed), RequestHandlerConfig::default());
+
+        let req = GetObjectsRequest {
+            blocks: vec![id(0), id(4096), id(CHAIN_HEIGHT - 1), [7; 32]],
+            pruned: false,
+        };
+        let Ok(ProtocolMessage::GetObjectsResponse(res)) =
+            block_on(handler.handle_request(PeerRequest::GetObjects(req.clone())))
+        else {
+            panic!("Handler sent the wrong response");
+        };
+        assert_eq!(res.blocks.len(), 2);
+        assert_eq!(res.missed_ids, vec![id(4096), [7; 32]]);
+        assert_eq!(res.current_blockchain_height, CHAIN_HEIGHT);
+
+        // Pruned blocks can be served from any stripe.
+        let Ok(ProtocolMessage::GetObjectsResponse(res)) = block_on(handler.handle_request(
+            PeerRequest::GetObjects(GetObjectsRequest {
+                pruned: true,
+                ..req
+            }),
+        )) else {
+            panic!("Handler sent the wrong response");
+        };
+        assert_eq!(res.blocks.len(), 3);
+        assert!(res.blocks.iter().all(|block| block.pruned));
+
+        let req = GetObjectsRequest {
+            blocks: vec![id(0); MAX_OBJECT_REQUEST_COUNT + 1],
+            pruned: true,
+        };
+        assert!(matches!(
+            block_on(handler.handle_request(PeerRequest::GetObjects(req))),
+            Err(RequestHandlerError::TooManyBlocks)
+        ));
+    }
+
+    #[test]
+    fn fluffy_blocks_only_hold_the_requested_txs() {
+        let mut handler = PeerRequestHandler::new(blockchain(0), RequestHandlerConfig::default());
+
+        let req = FluffyMissingTransactionsRequest {
+            block_hash: id(19_999),
+            current_blockchain_height: CHAIN_HEIGHT,
+            missing_tx_indices: vec![2, 0],
+        };
+        let Ok(ProtocolMessage::NewFluffyBlock(res)) =
+            block_on(handler.handle_request(PeerRequest::FluffyMissingTxs(req.clone())))
+        else {
+            panic!("Handler sent the wrong response");
+        };
+        assert_eq!(res.b.block, 19_999_u64.to_le_bytes());
+        assert_eq!(
+            res.b.txs,
+            Some(TransactionBlobs::Normal(vec![vec![2], vec![0]]))
+        );
+
+        let req = FluffyMissingTransactionsRequest {
+            missing_tx_indices: vec![3],
+            ..req
+        };
+        assert!(matches!(
+            block_on(handler.handle_request(PeerRequest::FluffyMissingTxs(req))),
+            Err(RequestHandlerError::InvalidTxIndex)
+        ));
+    }
+
+    #[test]
+    fn rate_limit_refills_up_to_the_burst() {
+        let now = Instant::now();
+        let mut rate_limit = RateLimit::new(
+            RequestHandlerConfig {
+                burst: 2,
+                requests_per_second: 1,
+            },
+            now,
+        );
+
+        assert!(rate_limit.take(now));
+        assert!(rate_limit.take(now));
+        assert!(!rate_limit.take(now));
+
+        assert!(rate_limit.take(now + Duration::from_secs(1)));
+        assert!(!rate_limit.take(now + Duration::from_secs(1)));
+
+        // Tokens don't build up past the burst.
+        let later = now + Duration::from_secs(60);
+        assert!(rate_limit.take(later));
+        assert!(rate_limit.take(later));
+        assert!(!rate_limit.take(later));
+    }
+
+    #[test]
+    fn requests_past_the_rate_limit_are_refused() {
+        let mut handler = PeerRequestHandler::new(
+            blockchain(0),
+            RequestHandlerConfig {
+                burst: 1,
+                requests_per_second: 1,
+            },
+        );
+        let req = PeerRequest::GetObjects(GetObjectsRequest {
+            blocks: vec![id(0)],
+            pruned: true,
+        });
+
+        assert!(block_on(handler.handle_request(req.clone())).is_ok());
+        assert!(matches!(
+            block_on(handler.handle_request(req)),
+            Err(RequestHandlerError::RateLimited)
+        ));
+    }
+}
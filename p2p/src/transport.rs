@@ -0,0 +1,92 @@
+//! # Transports
+//!
+//! This module contains [`Transport`], a hook that wraps the stream of a connection before the Levin
+//! codec is put on it, so obfuscation layers or encrypted transports can be tried without changing
+//! the rest of the P2P code.
+//!
+//! The transport is picked by the network zone of the peer from the [`Transports`] in the
+//! [`NetworkConfig`](crate::peer::NetworkConfig). Every zone uses [`PlainTransport`], which sends
+//! Levin messages straight over the stream, unless configured otherwise. Both ends of a connection
+//! must use the same transport.
+//!
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use monero_wire::{network_address::NetZone, NetworkAddress};
+
+use crate::protocol::Direction;
+
+/// A stream to a peer.
+pub trait PeerStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> PeerStream for T {}
+
+/// The stream of a connection after it has been wrapped by a [`Transport`].
+pub type BoxedPeerStream = Box<dyn PeerStream>;
+
+#[async_trait::async_trait]
+pub trait Transport: Send + Sync {
+    /// Wraps the stream of a new connection, before the handshake. Anything the transport needs
+    /// to exchange with the peer before Levin messages, like keys, should be done here.
+    async fn wrap(
+        &self,
+        stream: BoxedPeerStream,
+        addr: NetworkAddress,
+        direction: Direction,
+    ) -> Result<BoxedPeerStream, std::io::Error>;
+}
+
+/// The default transport, Levin messages are sent straight over the stream.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlainTransport;
+
+#[async_trait::async_trait]
+impl Transport for PlainTransport {
+    async fn wrap(
+        &self,
+        stream: BoxedPeerStream,
+        _: NetworkAddress,
+        _: Direction,
+    ) -> Result<BoxedPeerStream, std::io::Error> {
+        Ok(stream)
+    }
+}
+
+/// The transport of each network zone.
+#[derive(Clone)]
+pub struct Transports {
+    public: Arc<dyn Transport>,
+    tor: Arc<dyn Transport>,
+    i2p: Arc<dyn Transport>,
+}
+
+impl Default for Transports {
+    fn default() -> Self {
+        Transports {
+            public: Arc::new(PlainTransport),
+            tor: Arc::new(PlainTransport),
+            i2p: Arc::new(PlainTransport),
+        }
+    }
+}
+
+impl Transports {
+    pub fn with_transport(mut self, zone: NetZone, transport: impl Transport + 'static) -> Self {
+        let transport = Arc::new(transport);
+        match zone {
+            NetZone::Public => self.public = transport,
+            NetZone::Tor => self.tor = transport,
+            NetZone::I2p => self.i2p = transport,
+        }
+        self
+    }
+
+    pub fn get(&self, zone: NetZone) -> &Arc<dyn Transport> {
+        match zone {
+            NetZone::Public => &self.public,
+            NetZone::Tor => &self.tor,
+            NetZone::I2p => &self.i2p,
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! # Tx Relay
+//!
+//! This module contains [`TxRelayRouter`], which picks the peers a transaction is relayed to.
+//!
+//! Transactions are relayed with Dandelion++, in the stem phase a transaction is sent to a single
+//! outbound peer and in the fluff phase it is sent to every peer. The router keeps the connected
+//! peers of each network zone, a transaction is only relayed in the zone it came from so a
+//! transaction received over Tor is never linked to our public address.
+//!
+//! Transactions submitted to this node, from the RPC server or a wallet, are the ones an observer
+//! most wants to link to our address. When anonymity zones are configured with
+//! [`TxRelayConfig::with_anonymity_zone`] their stem phase only uses outbound peers in those zones,
+//! like monerod's `--tx-proxy`. If no anonymity peers are connected [`AnonymityFallback`] decides if
+//! the transaction waits for one or is stemmed over public peers.
+//!
+//! Peers are added to the router with the zone they were connected through.
+//!
+use rand::{rngs::StdRng, seq::IteratorRandom, SeedableRng};
+use thiserror::Error;
+
+use monero_wire::{network_address::NetZone, NetworkAddress, NewTransactions, ProtocolMessage};
+
+use crate::protocol::Direction;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TxRelayError {
+    #[error("There are no peers to relay the transaction to")]
+    NoPeers,
+    #[error("There are no anonymity network peers to relay the local transaction to")]
+    NoAnonymityPeers,
+}
+
+/// Where a transaction came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxOrigin {
+    /// Submitted to this node.
+    Local,
+    /// Relayed to us by a peer in this zone.
+    Peer(NetZone),
+}
+
+/// What to do with a local transaction when no anonymity network peers are connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnonymityFallback {
+    /// Don't relay the transaction, it should be tried again once an anonymity peer connects.
+    #[default]
+    Hold,
+    /// Stem the transaction over public peers.
+    Public,
+}
+
+/// The config for the [`TxRelayRouter`].
+#[derive(Debug, Clone, Default)]
+pub struct TxRelayConfig {
+    /// The zones local transactions are stemmed over, public peers are used if this is empty.
+    pub anonymity_zones: Vec<NetZone>,
+    pub fallback: AnonymityFallback,
+}
+
+impl TxRelayConfig {
+    /// Stem local transactions over peers in this zone.
+    pub fn with_anonymity_zone(mut self, zone: NetZone) -> Self {
+        if !self.anonymity_zones.contains(&zone) {
+            self.anonymity_zones.push(zone);
+        }
+        self
+    }
+
+    pub fn with_fallback(mut self, fallback: AnonymityFallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+}
+
+/// The peers to send a transaction to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayRoute {
+    pub zone: NetZone,
+    pub peers: Vec<NetworkAddress>,
+    /// False if the transaction is in the stem phase.
+    pub fluff: bool,
+}
+
+impl RelayRoute {
+    /// Returns the message to send the route's peers.
+    pub fn message(&self, txs: Vec<Vec<u8>>) -> ProtocolMessage {
+        ProtocolMessage::NewTransactions(NewTransactions {
+            txs,
+            dandelionpp_fluff: self.fluff,
+            padding: vec![],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RelayPeer {
+    zone: NetZone,
+    addr: NetworkAddress,
+    direction: Direction,
+}
+
+/// Picks the peers transactions are relayed to.
+#[derive(Debug)]
+pub struct TxRelayRouter {
+    config: TxRelayConfig,
+    peers: Vec<RelayPeer>,
+    rng: StdRng,
+}
+
+impl TxRelayRouter {
+    pub fn new(config: TxRelayConfig) -> Self {
+        TxRelayRouter {
+            config,
+            peers: Vec::new(),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Adds a connected peer, `zone` is the zone the peer was connected through.
+    pub fn add_peer(&mut self, zone: NetZone, addr: NetworkAddress, direction: Direction) {
+        self.remove_peer(zone, &addr);
+        self.peers.push(RelayPeer {
+            zone,
+            addr,
+            direction,
+        });
+    }
+
+    pub fn remove_peer(&mut self, zone: NetZone, addr: &NetworkAddress) {
+        self.peers
+            .retain(|peer| peer.zone != zone || peer.addr != *addr);
+    }
+
+    /// Returns the peer to stem a transaction to.
+    pub fn stem_route(&mut self, origin: TxOrigin) -> Result<RelayRoute, TxRelayError> {
+        let zones = match origin {
+            TxOrigin::Peer(zone) => vec![zone],
+            TxOrigin::Local if self.config.anonymity_zones.is_empty() => vec![NetZone::Public],
+            TxOrigin::Local => {
+                let zones = self.config.anonymity_zones.clone();
+                match self.random_outbound_peer(&zones) {
+                    Some(peer) => return Ok(stem(peer)),
+                    None if self.config.fallback == AnonymityFallback::Hold => {
+                        return Err(TxRelayError::NoAnonymityPeers)
+                    }
+                    None => {
+                        tracing::debug!("No anonymity peers, stemming local tx over public peers");
+                        vec![NetZone::Public]
+                    }
+                }
+            }
+        };
+
+        self.random_outbound_peer(&zones)
+            .map(stem)
+            .ok_or(TxRelayError::NoPeers)
+    }
+
+    /// Returns every peer in the zone, to fluff a transaction to.
+    pub fn fluff_route(&self, zone: NetZone) -> Result<RelayRoute, TxRelayError> {
+        let peers: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|peer| peer.zone == zone)
+            .map(|peer| peer.addr)
+            .collect();
+
+        if peers.is_empty() {
+            return Err(TxRelayError::NoPeers);
+        }
+
+        Ok(RelayRoute {
+            zone,
+            peers,
+            fluff: true,
+        })
+    }
+
+    fn random_outbound_peer(&mut self, zones: &[NetZone]) -> Option<RelayPeer> {
+        self.peers
+            .iter()
+            .filter(|peer| peer.direction == Direction::Outbound && zones.contains(&peer.zone))
+            .choose(&mut self.rng)
+            .copied()
+    }
+}
+
+fn stem(peer: RelayPeer) -> RelayRoute {
+    RelayRoute {
+        zone: peer.zone,
+        peers: vec![peer.addr],
+        fluff: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::*;
+
+    fn addr(i: u8) -> NetworkAddress {
+        NetworkAddress::IPv4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, i), 18080))
+    }
+
+    fn router(config: TxRelayConfig) -> TxRelayRouter {
+        let mut router = TxRelayRouter::new(config);
+        router.add_peer(NetZone::Public, addr(1), Direction::Outbound);
+        router.add_peer(NetZone::Public, addr(2), Direction::Inbound);
+        router.add_peer(NetZone::Tor, addr(3), Direction::Inbound);
+        router
+    }
+
+    #[test]
+    fn local_txs_are_stemmed_over_anonymity_peers() {
+        let mut router = router(TxRelayConfig::default().with_anonymity_zone(NetZone::Tor));
+
+        // Inbound Tor peers aren't used for the stem.
+        assert_eq!(
+            router.stem_route(TxOrigin::Local),
+            Err(TxRelayError::NoAnonymityPeers)
+        );
+
+        router.add_peer(NetZone::Tor, addr(4), Direction::Outbound);
+        for _ in 0..10 {
+            let route = router.stem_route(TxOrigin::Local).unwrap();
+            assert_eq!(route.zone, NetZone::Tor);
+            assert_eq!(route.peers, vec![addr(4)]);
+            assert!(!route.fluff);
+        }
+
+        // Public transactions still use public peers.
+        let route = router.stem_route(TxOrigin::Peer(NetZone::Public)).unwrap();
+        assert_eq!(route.peers, vec![addr(1)]);
+    }
+
+    #[test]
+    fn local_txs_fall_back_to_public_peers_if_configured() {
+        let config = TxRelayConfig::default()
+            .with_anonymity_zone(NetZone::I2p)
+            .with_fallback(AnonymityFallback::Public);
+        let mut i2p_router = router(config);
+
+        let route = i2p_router.stem_route(TxOrigin::Local).unwrap();
+        assert_eq!(route.zone, NetZone::Public);
+        assert_eq!(route.peers, vec![addr(1)]);
+
+        // Without anonymity zones local transactions are always stemmed over public peers.
+        let mut public_router = router(TxRelayConfig::default());
+        assert_eq!(
+            public_router.stem_route(TxOrigin::Local).unwrap().peers,
+            vec![addr(1)]
+        );
+    }
+
+    #[test]
+    fn txs_stay_in_their_zone() {
+        let mut router = router(TxRelayConfig::default());
+
+        assert_eq!(
+            router.stem_route(TxOrigin::Peer(NetZone::Tor)),
+            Err(TxRelayError::NoPeers)
+        );
+
+        let route = router.fluff_route(NetZone::Tor).unwrap();
+        assert_eq!(route.peers, vec![addr(3)]);
+        let ProtocolMessage::NewTransactions(message) = route.message(vec![vec![1]]) else {
+            panic!("Route made the wrong message");
+        };
+        assert!(message.dandelionpp_fluff);
+
+        router.remove_peer(NetZone::Tor, &addr(3));
+        assert_eq!(router.fluff_route(NetZone::Tor), Err(TxRelayError::NoPeers));
+        assert_eq!(router.fluff_route(NetZone::Public).unwrap().peers.len(), 2);
+    }
+}
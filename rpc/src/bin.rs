@@ -0,0 +1,282 @@
+//! # Binary Methods
+//!
+//! This module contains the requests and responses of the `.bin` endpoints, which wallets use to sync.
+//! They are encoded with epee and the field names match monerod's.
+//!
+use epee_encoding::{
+    error::Error,
+    io::{Read, Write},
+    marker::InnerMarker,
+    read_epee_value, read_marker, write_field, EpeeObject, EpeeObjectBuilder, EpeeValue,
+};
+
+/// The most blocks `get_blocks.bin` returns per call, monerod has the same limit.
+pub const GET_BLOCKS_MAX_BLOCK_COUNT: u64 = 1000;
+/// The amount of blocks `get_blocks.bin` reads from the database at a time, a request dropped by its
+/// client stops between chunks.
+pub const GET_BLOCKS_CHUNK_SIZE: u64 = 100;
+/// The most bytes of pool transactions `get_blocks.bin` returns per call, the hashes of the other
+/// added transactions are returned in `remaining_added_pool_txids`.
+pub const GET_BLOCKS_MAX_POOL_TXS_SIZE: usize = 10 * 1024 * 1024;
+
+/// [`GetBlocksRequest::requested_info`]: only blocks are wanted.
+pub const REQUESTED_INFO_BLOCKS_ONLY: u8 = 0;
+/// [`GetBlocksRequest::requested_info`]: blocks and the pool are wanted.
+pub const REQUESTED_INFO_BLOCKS_AND_POOL: u8 = 1;
+/// [`GetBlocksRequest::requested_info`]: only the pool is wanted.
+pub const REQUESTED_INFO_POOL_ONLY: u8 = 2;
+
+/// [`GetBlocksResponse::pool_info_extent`]: the pool was not returned.
+pub const POOL_INFO_EXTENT_NONE: u8 = 0;
+/// [`GetBlocksResponse::pool_info_extent`]: only the changes to the pool since `pool_info_since`
+/// were returned.
+pub const POOL_INFO_EXTENT_INCREMENTAL: u8 = 1;
+/// [`GetBlocksResponse::pool_info_extent`]: every transaction in the pool was returned.
+pub const POOL_INFO_EXTENT_FULL: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct GetBlocksRequest {
+    /// One of the `REQUESTED_INFO_*` values. The pool is only returned with blocks if the blocks
+    /// reach the top of the chain.
+    #[epee_default(0)]
+    pub requested_info: u8,
+    /// The short chain history of the wallet, newest first, ending with the genesis block.
+    pub block_ids: Vec<[u8; 32]>,
+    /// The height to start at, if it is higher than the highest block in `block_ids`.
+    #[epee_default(0)]
+    pub start_height: u64,
+    #[epee_default(false)]
+    pub prune: bool,
+    /// Leave the miner transactions out of `output_indices`.
+    #[epee_default(false)]
+    pub no_miner_tx: bool,
+    /// The `daemon_time` of the last response, only the changes to the pool since then are returned
+    /// if we still know them. 0 asks for the whole pool.
+    #[epee_default(0)]
+    pub pool_info_since: u64,
+}
+
+/// A transaction added to the pool.
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct PoolTxInfo {
+    pub tx_hash: [u8; 32],
+    pub tx_blob: Vec<u8>,
+    pub double_spend_seen: bool,
+}
+
+/// A transaction without its prunable data.
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct PrunedTxBlobEntry {
+    pub blob: Vec<u8>,
+    pub prunable_hash: [u8; 32],
+}
+
+/// The transactions of a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionBlobs {
+    Normal(Vec<Vec<u8>>),
+    Pruned(Vec<PrunedTxBlobEntry>),
+}
+
+impl TransactionBlobs {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            TransactionBlobs::Normal(txs) => txs.is_empty(),
+            TransactionBlobs::Pruned(txs) => txs.is_empty(),
+        }
+    }
+}
+
+/// A block with its transactions.
+///
+/// `txs` is a list of blobs if the block is not pruned and a list of [`PrunedTxBlobEntry`] if it
+/// is, so this object is (de)serialized by hand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockCompleteEntry {
+    pub pruned: bool,
+    pub block: Vec<u8>,
+    pub block_weight: u64,
+    pub txs: TransactionBlobs,
+}
+
+impl EpeeObject for BlockCompleteEntry {
+    type Builder = BlockCompleteEntryBuilder;
+
+    fn number_of_fields(&self) -> u64 {
+        3 + u64::from(!self.txs.is_empty())
+    }
+
+    fn write_fields<W: Write>(&self, w: &mut W) -> epee_encoding::Result<()> {
+        write_field(&self.pruned, "pruned", w)?;
+        write_field(&self.block, "block", w)?;
+        write_field(&self.block_weight, "block_weight", w)?;
+        match &self.txs {
+            TransactionBlobs::Normal(txs) => write_field(txs, "txs", w),
+            TransactionBlobs::Pruned(txs) => write_field(txs, "txs", w),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct BlockCompleteEntryBuilder {
+    pruned: Option<bool>,
+    block: Option<Vec<u8>>,
+    block_weight: Option<u64>,
+    txs: Option<TransactionBlobs>,
+}
+
+impl EpeeObjectBuilder<BlockCompleteEntry> for BlockCompleteEntryBuilder {
+    fn add_field<R: Read>(&mut self, name: &str, r: &mut R) -> epee_encoding::Result<bool> {
+        match name {
+            "pruned" => self.pruned = Some(read_epee_value(r)?),
+            "block" => self.block = Some(read_epee_value(r)?),
+            "block_weight" => self.block_weight = Some(read_epee_value(r)?),
+            "txs" => {
+                let marker = read_marker(r)?;
+                self.txs = Some(match marker.inner_marker {
+                    InnerMarker::String => {
+                        TransactionBlobs::Normal(Vec::<Vec<u8>>::read(r, &marker)?)
+                    }
+                    InnerMarker::Object => {
+                        TransactionBlobs::Pruned(Vec::<PrunedTxBlobEntry>::read(r, &marker)?)
+                    }
+                    _ => return Err(Error::Format("Unexpected marker")),
+                });
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn finish(self) -> epee_encoding::Result<BlockCompleteEntry> {
+        let pruned = self.pruned.unwrap_or(false);
+
+        Ok(BlockCompleteEntry {
+            pruned,
+            block: self
+                .block
+                .ok_or(Error::Format("Required field was not in data"))?,
+            block_weight: self.block_weight.unwrap_or(0),
+            txs: self.txs.unwrap_or(if pruned {
+                TransactionBlobs::Pruned(vec![])
+            } else {
+                TransactionBlobs::Normal(vec![])
+            }),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct TxOutputIndices {
+    pub indices: Vec<u64>,
+}
+
+/// The output indices of the transactions in a block.
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct BlockOutputIndices {
+    pub indices: Vec<TxOutputIndices>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct GetBlocksResponse {
+    pub blocks: Vec<BlockCompleteEntry>,
+    /// The height of the first block in `blocks`.
+    pub start_height: u64,
+    /// The height of the chain.
+    pub current_height: u64,
+    pub output_indices: Vec<BlockOutputIndices>,
+    /// One of the `POOL_INFO_EXTENT_*` values.
+    #[epee_default(0)]
+    pub pool_info_extent: u8,
+    pub added_pool_txs: Vec<PoolTxInfo>,
+    pub remaining_added_pool_txids: Vec<[u8; 32]>,
+    /// Only set if `pool_info_extent` is [`POOL_INFO_EXTENT_INCREMENTAL`].
+    pub removed_pool_txids: Vec<[u8; 32]>,
+    /// Our UNIX time, to be sent back as `pool_info_since`.
+    #[epee_default(0)]
+    pub daemon_time: u64,
+    pub status: String,
+    pub untrusted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct GetOIndexesRequest {
+    pub txid: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct GetOIndexesResponse {
+    pub o_indexes: Vec<u64>,
+    pub status: String,
+    pub untrusted: bool,
+}
+
+/// An output, identified by its amount and its index among the outputs with that amount.
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct GetOutputsOut {
+    pub amount: u64,
+    pub index: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct GetOutsRequest {
+    pub outputs: Vec<GetOutputsOut>,
+    #[epee_default(false)]
+    pub get_txid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct OutKey {
+    pub key: [u8; 32],
+    pub mask: [u8; 32],
+    pub unlocked: bool,
+    pub height: u64,
+    /// All zeros if the request did not set `get_txid`.
+    pub txid: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, EpeeObject)]
+pub struct GetOutsResponse {
+    pub outs: Vec<OutKey>,
+    pub status: String,
+    pub untrusted: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use epee_encoding::{from_bytes, to_bytes};
+
+    use super::*;
+
+    #[test]
+    fn block_complete_entry_round_trip() {
+        let entries = [
+            BlockCompleteEntry {
+                pruned: false,
+                block: vec![1, 2, 3],
+                block_weight: 100,
+                txs: TransactionBlobs::Normal(vec![vec![4], vec![5, 6]]),
+            },
+            BlockCompleteEntry {
+                pruned: true,
+                block: vec![1, 2, 3],
+                block_weight: 100,
+                txs: TransactionBlobs::Pruned(vec![PrunedTxBlobEntry {
+                    blob: vec![4],
+                    prunable_hash: [7; 32],
+                }]),
+            },
+            BlockCompleteEntry {
+                pruned: true,
+                block: vec![1, 2, 3],
+                block_weight: 0,
+                txs: TransactionBlobs::Pruned(vec![]),
+            },
+        ];
+
+        for entry in entries {
+            let bytes = to_bytes(&entry).unwrap();
+            assert_eq!(from_bytes::<BlockCompleteEntry>(&bytes).unwrap(), entry);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! # Output Distributions
+//!
+//! This module contains [`OutputDistributions`], a cache of the amount of outputs created in each
+//! block for `get_output_distribution`. Wallets ask for the RingCT distribution from the genesis
+//! block to pick decoys, counting it reads every block so the counts are kept and later requests
+//! only count the blocks added since.
+//!
+//! Blocks are counted [`DISTRIBUTION_CHUNK_SIZE`] at a time and the counts are saved after each
+//! chunk, so a request that is dropped part way through keeps its progress and the next request for
+//! the amount carries on from where it stopped. Requests yield before each chunk, so one abandoned
+//! by its client is dropped between chunks. The hash of the last block of each chunk is kept to
+//! find reorgs, the counts of chunks that are no longer in the main chain are thrown away.
+//!
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tower::ServiceExt;
+
+use monero_consensus::{Database, DatabaseRequest};
+
+use crate::yield_now;
+
+/// The amount of blocks counted with each database request.
+pub const DISTRIBUTION_CHUNK_SIZE: u64 = 10_000;
+
+/// The counts of an amount's outputs from the genesis block.
+#[derive(Debug, Default)]
+struct AmountDistribution {
+    /// The amount of outputs created in each block, the next block to count is at `counts.len()`.
+    counts: Vec<u64>,
+    /// The end of each counted chunk and the hash of the chunk's last block.
+    checkpoints: Vec<(u64, [u8; 32])>,
+}
+
+/// A cache of output distributions, clones of this cache share the same counts.
+#[derive(Debug, Clone, Default)]
+pub struct OutputDistributions {
+    amounts: Arc<Mutex<HashMap<u64, Arc<futures::lock::Mutex<AmountDistribution>>>>>,
+}
+
+impl OutputDistributions {
+    /// Returns the amount of outputs with this amount created in each block from the genesis block up
+    /// to and including `to_height`, RingCT outputs have an amount of 0.
+    ///
+    /// Requests for the same amount wait for each other, so the blocks are only counted once.
+    pub async fn get<D: Database + Clone>(
+        &self,
+        database: D,
+        amount: u64,
+        to_height: u64,
+    ) -> Result<Vec<u64>, tower::BoxError> {
+        let distribution = self
+            .amounts
+            .lock()
+            .unwrap()
+            .entry(amount)
+            .or_default()
+            .clone();
+        let mut distribution = distribution.lock().await;
+
+        distribution.pop_reorged_chunks(&database).await?;
+
+        while distribution.counted() <= to_height {
+            yield_now().await;
+            distribution
+                .count_next_chunk(&database, amount, to_height + 1)
+                .await?;
+        }
+
+        Ok(distribution.counts[..=usize::try_from(to_height)?].to_vec())
+    }
+}
+
+impl AmountDistribution {
+    /// Returns the amount of blocks counted.
+    fn counted(&self) -> u64 {
+        self.counts.len() as u64
+    }
+
+    /// Removes the counts of the chunks that are no longer in the main chain.
+    async fn pop_reorged_chunks<D: Database + Clone>(
+        &mut self,
+        database: &D,
+    ) -> Result<(), tower::BoxError> {
+        let chain_height = chain_height(database).await?;
+
+        while let Some(&(end, hash)) = self.checkpoints.last() {
+            if end <= chain_height && block_hash(database, end - 1).await? == hash {
+                break;
+            }
+
+            self.checkpoints.pop();
+            let start = self.checkpoints.last().map_or(0, |&(end, _)| end);
+            self.counts.truncate(usize::try_from(start)?);
+        }
+
+        Ok(())
+    }
+
+    /// Counts the blocks up to the end of the next chunk, or up to `end` if that is sooner.
+    async fn count_next_chunk<D: Database + Clone>(
+        &mut self,
+        database: &D,
+        amount: u64,
+        end: u64,
+    ) -> Result<(), tower::BoxError> {
+        let start = self.counted();
+        // Chunks start at multiples of the chunk size, so a chunk counted part way is finished by
+        // the next request instead of adding a checkpoint for every new block.
+        let chunk_start = start - start % DISTRIBUTION_CHUNK_SIZE;
+        let end = (chunk_start + DISTRIBUTION_CHUNK_SIZE).min(end);
+
+        // The hash is read before the counts, if the block is reorged in between the checkpoint
+        // won't match the chain and the chunk will be counted again.
+        let hash = block_hash(database, end - 1).await?;
+
+        let counts = database
+            .clone()
+            .oneshot(DatabaseRequest::NumOutputsInRange {
+                amount,
+                range: start..end,
+            })
+            .await?
+            .into_num_outputs_in_range()?;
+
+        if start != chunk_start {
+            self.checkpoints.pop();
+        }
+        self.checkpoints.push((end, hash));
+        self.counts.extend(counts);
+
+        Ok(())
+    }
+}
+
+async fn chain_height<D: Database + Clone>(database: &D) -> Result<u64, tower::BoxError> {
+    let chain_height = database
+        .clone()
+        .oneshot(DatabaseRequest::ChainHeight)
+        .await?
+        .into_chain_height()?;
+
+    Ok(chain_height)
+}
+
+async fn block_hash<D: Database + Clone>(
+    database: &D,
+    height: u64,
+) -> Result<[u8; 32], tower::BoxError> {
+    let hash = database
+        .clone()
+        .oneshot(DatabaseRequest::BlockHash(height))
+        .await?
+        .into_block_hash()?;
+
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use monero_consensus::test_utils::{DummyBlockExtendedHeader, DummyDatabaseBuilder};
+
+    use super::*;
+
+    fn checkpoint_ends(distributions: &OutputDistributions, amount: u64) -> Vec<u64> {
+        let distribution = distributions.amounts.lock().unwrap()[&amount].clone();
+        let distribution = block_on(distribution.lock());
+        distribution
+            .checkpoints
+            .iter()
+            .map(|(end, _)| *end)
+            .collect()
+    }
+
+    #[test]
+    fn counts_are_cached_and_survive_reorgs() {
+        let chunk_size = DISTRIBUTION_CHUNK_SIZE;
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(
+                usize::try_from(chunk_size).unwrap() + 10,
+                DummyBlockExtendedHeader::default(),
+            )
+            .finish();
+        let distributions = OutputDistributions::default();
+
+        let counts = block_on(distributions.get(database.clone(), 0, 5)).unwrap();
+        assert_eq!(counts, vec![1; 6]);
+        assert_eq!(checkpoint_ends(&distributions, 0), vec![6]);
+
+        // The partly counted first chunk is finished with a single checkpoint.
+        let counts = block_on(distributions.get(database.clone(), 0, chunk_size + 5)).unwrap();
+        assert_eq!(counts.len() as u64, chunk_size + 6);
+        assert_eq!(
+            checkpoint_ends(&distributions, 0),
+            vec![chunk_size, chunk_size + 6]
+        );
+
+        // The blocks of the second chunk left the main chain, so its counts are thrown away.
+        for _ in 0..10 {
+            database.pop_block();
+        }
+        let counts = block_on(distributions.get(database.clone(), 0, chunk_size - 1)).unwrap();
+        assert_eq!(counts.len() as u64, chunk_size);
+        assert_eq!(checkpoint_ends(&distributions, 0), vec![chunk_size]);
+
+        // Pre-RingCT amounts are counted separately.
+        let counts = block_on(distributions.get(database, 1, 5)).unwrap();
+        assert_eq!(counts, vec![0; 6]);
+    }
+}
@@ -0,0 +1,1977 @@
+//! # Handler
+//!
+//! This module contains [`RpcHandler`], which answers JSON-RPC requests and requests to the `.bin`
+//! endpoints using the database service and the
+//! [`ContextService`](monero_consensus::context::ContextService).
+//!
+//! If the handler is given the [`TxPool`] with [`RpcHandler::with_tx_pool`], `get_blocks.bin`
+//! also returns the pool's transactions, or only the changes to the pool since the wallet last
+//! asked, so synced wallets don't need to poll the pool separately.
+//!
+//! The handler keeps the last [`BLOCK_TEMPLATE_CACHE_SIZE`] templates from `get_block_template`, a
+//! block submitted from one of them must only change the header's nonce and the template's reserved
+//! space. Pool software writing past its `reserve_size` would otherwise submit a corrupted block.
+//! Blocks not from our templates, like P2Pool's, are not checked.
+//!
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use curve25519_dalek::Scalar;
+use epee_encoding::EpeeObject;
+use futures::future::BoxFuture;
+use monero_serai::{
+    block::Block,
+    transaction::Transaction,
+    wallet::address::{MoneroAddress, Network as AddressNetwork},
+};
+use rand::{rngs::OsRng, RngCore};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tower::ServiceExt;
+
+use cuprate_common::{BlockID, Network};
+use monero_consensus::{
+    block::pow::randomx_seed_height,
+    block_template::{build_block_template, BlockTemplate},
+    context::{
+        BlockChainContext, ContextDump, ContextRequest, ContextResponse, WeightWindowSummary,
+    },
+    fee::estimate_backlog,
+    fork_metrics::AltChainStats,
+    hardforks::HardFork,
+    miner_tx::{MinerTxKeys, MAX_EXTRA_NONCE_SIZE},
+    misbehaviour::{BlockSource, QueuedBlock},
+    outputs::is_output_unlocked,
+    tx_report::tx_report,
+    txpool::TxPool,
+    verification_queue::QueueSender,
+    ConsensusError, ContextProtocolError, Database, DatabaseProtocolError, DatabaseRequest,
+    DatabaseResponse,
+};
+
+use crate::bin::*;
+use crate::distribution::OutputDistributions;
+use crate::json_rpc::{
+    Request, Response, CORE_RPC_ERROR_CODE_BLOCK_NOT_ACCEPTED, CORE_RPC_ERROR_CODE_INTERNAL_ERROR,
+    CORE_RPC_ERROR_CODE_TOO_BIG_HEIGHT, CORE_RPC_ERROR_CODE_TOO_BIG_RESERVE_SIZE,
+    CORE_RPC_ERROR_CODE_WRONG_BLOCKBLOB, CORE_RPC_ERROR_CODE_WRONG_PARAM,
+    CORE_RPC_ERROR_CODE_WRONG_WALLET_ADDRESS, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND,
+    PARSE_ERROR,
+};
+use crate::methods::*;
+use crate::policy::RpcConfig;
+use crate::yield_now;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RpcError {
+    #[error("Method not found: {0}")]
+    MethodNotFound(String),
+    #[error("Invalid params: {0}")]
+    InvalidParams(String),
+    #[error(
+        "Requested block height: {height} greater than current top block height: {top_height}"
+    )]
+    TooBigHeight { height: u64, top_height: u64 },
+    #[error("Invalid hash: {0}")]
+    InvalidHash(String),
+    #[error("Failed to parse wallet address: {0}")]
+    WrongWalletAddress(String),
+    #[error("{0}")]
+    ReserveSize(String),
+    #[error("Wrong block blob")]
+    WrongBlockBlob,
+    #[error("Block not accepted: {0}")]
+    BlockNotAccepted(String),
+    #[error("Internal error: {0}")]
+    Internal(tower::BoxError),
+}
+
+impl RpcError {
+    /// Returns the JSON-RPC error code for this error.
+    pub fn code(&self) -> i64 {
+        match self {
+            RpcError::MethodNotFound(_) => METHOD_NOT_FOUND,
+            RpcError::InvalidParams(_) => INVALID_PARAMS,
+            RpcError::TooBigHeight { .. } => CORE_RPC_ERROR_CODE_TOO_BIG_HEIGHT,
+            RpcError::InvalidHash(_) => CORE_RPC_ERROR_CODE_WRONG_PARAM,
+            RpcError::WrongWalletAddress(_) => CORE_RPC_ERROR_CODE_WRONG_WALLET_ADDRESS,
+            RpcError::ReserveSize(_) => CORE_RPC_ERROR_CODE_TOO_BIG_RESERVE_SIZE,
+            RpcError::WrongBlockBlob => CORE_RPC_ERROR_CODE_WRONG_BLOCKBLOB,
+            RpcError::BlockNotAccepted(_) => CORE_RPC_ERROR_CODE_BLOCK_NOT_ACCEPTED,
+            RpcError::Internal(_) => CORE_RPC_ERROR_CODE_INTERNAL_ERROR,
+        }
+    }
+}
+
+impl From<ConsensusError> for RpcError {
+    fn from(e: ConsensusError) -> Self {
+        RpcError::Internal(e.into())
+    }
+}
+
+impl From<DatabaseProtocolError> for RpcError {
+    fn from(e: DatabaseProtocolError) -> Self {
+        RpcError::Internal(e.into())
+    }
+}
+
+impl From<ContextProtocolError> for RpcError {
+    fn from(e: ContextProtocolError) -> Self {
+        RpcError::Internal(e.into())
+    }
+}
+
+/// Marks the node's database to be compacted, for `compact_db`.
+pub trait DatabaseCompactor: std::fmt::Debug + Send + Sync {
+    /// Requests a compaction, which runs the next time the node starts.
+    fn request_compaction(&self) -> Result<(), tower::BoxError>;
+}
+
+/// The peers that advertised an RPC port, for `get_public_nodes`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PublicNodes {
+    pub white: Vec<PublicNode>,
+    pub gray: Vec<PublicNode>,
+}
+
+/// Lists the node's public nodes for `get_public_nodes`, usually from the address book.
+pub trait PublicNodeSource: std::fmt::Debug + Send + Sync {
+    fn public_nodes(&self) -> BoxFuture<'static, Result<PublicNodes, tower::BoxError>>;
+}
+
+/// The amount of templates from `get_block_template` kept to check submitted blocks against.
+pub const BLOCK_TEMPLATE_CACHE_SIZE: usize = 16;
+
+/// Answers JSON-RPC requests, clones of this handler share the same services.
+#[derive(Debug, Clone)]
+pub struct RpcHandler<D, C> {
+    config: RpcConfig,
+    /// The UNIX timestamp of when the handler was created.
+    start_time: u64,
+    database: D,
+    context_svc: C,
+    tx_pool: Option<Arc<Mutex<TxPool>>>,
+    block_queue: Option<QueueSender<QueuedBlock>>,
+    database_compactor: Option<Arc<dyn DatabaseCompactor>>,
+    public_nodes: Option<Arc<dyn PublicNodeSource>>,
+    /// The last templates given out, newest last.
+    block_templates: Arc<Mutex<VecDeque<BlockTemplate>>>,
+    output_distributions: OutputDistributions,
+}
+
+impl<D, C> RpcHandler<D, C>
+where
+    D: Database + Clone,
+    C: tower::Service<ContextRequest, Response = ContextResponse, Error = ConsensusError> + Clone,
+{
+    pub fn new(config: RpcConfig, database: D, context_svc: C) -> Self {
+        RpcHandler {
+            config,
+            start_time: current_time(),
+            database,
+            context_svc,
+            tx_pool: None,
+            block_queue: None,
+            database_compactor: None,
+            public_nodes: None,
+            block_templates: Arc::default(),
+            output_distributions: OutputDistributions::default(),
+        }
+    }
+
+    /// Returns the transactions in this pool from `get_blocks.bin`.
+    pub fn with_tx_pool(mut self, tx_pool: Arc<Mutex<TxPool>>) -> Self {
+        self.tx_pool = Some(tx_pool);
+        self
+    }
+
+    /// Queues blocks from `submit_block` on this queue as tip blocks from [`BlockSource::Rpc`], the
+    /// block verifier should own the receiver.
+    pub fn with_block_queue(mut self, block_queue: QueueSender<QueuedBlock>) -> Self {
+        self.block_queue = Some(block_queue);
+        self
+    }
+
+    /// Marks the database for compaction with this from `compact_db`, the database can't be
+    /// compacted while the node has it open.
+    pub fn with_database_compactor(
+        mut self,
+        database_compactor: Arc<dyn DatabaseCompactor>,
+    ) -> Self {
+        self.database_compactor = Some(database_compactor);
+        self
+    }
+
+    /// Lists the public nodes from this in `get_public_nodes`.
+    pub fn with_public_nodes(mut self, public_nodes: Arc<dyn PublicNodeSource>) -> Self {
+        self.public_nodes = Some(public_nodes);
+        self
+    }
+
+    /// Handles the body of a JSON-RPC HTTP request.
+    pub async fn handle_body(&self, body: &[u8]) -> Response {
+        let value: Value = match serde_json::from_slice(body) {
+            Ok(value) => value,
+            Err(e) => return Response::error(Value::Null, PARSE_ERROR, e.to_string()),
+        };
+
+        let id = value.get("id").cloned().unwrap_or_default();
+        match serde_json::from_value::<Request>(value) {
+            Ok(req) if req.jsonrpc == "2.0" => self.handle_request(req).await,
+            Ok(_) => Response::error(id, INVALID_REQUEST, "jsonrpc must be \"2.0\"".to_string()),
+            Err(e) => Response::error(id, INVALID_REQUEST, e.to_string()),
+        }
+    }
+
+    /// Handles a JSON-RPC request.
+    pub async fn handle_request(&self, req: Request) -> Response {
+        tracing::debug!("Handling RPC request: {}", req.method);
+
+        match self.call_method(&req.method, req.params).await {
+            Ok(result) => Response::result(req.id, result),
+            Err(e) => Response::error(req.id, e.code(), e.to_string()),
+        }
+    }
+
+    /// Handles the body of a request to one of the `.bin` endpoints, `endpoint` is the path without
+    /// the leading `/`, like `get_blocks.bin`.
+    pub async fn handle_bin(&self, endpoint: &str, body: &[u8]) -> Result<Vec<u8>, RpcError> {
+        tracing::debug!("Handling binary RPC request: {}", endpoint);
+
+        if !self.config.is_allowed(endpoint) {
+            return Err(RpcError::MethodNotFound(endpoint.to_string()));
+        }
+
+        match endpoint {
+            "get_blocks.bin" | "getblocks.bin" => {
+                to_epee(&self.get_blocks(endpoint, from_epee(body)?).await?)
+            }
+            "get_o_indexes.bin" => to_epee(&self.get_o_indexes(from_epee(body)?).await?),
+            "get_outs.bin" => to_epee(&self.get_outs(endpoint, from_epee(body)?).await?),
+            _ => Err(RpcError::MethodNotFound(endpoint.to_string())),
+        }
+    }
+
+    /// Handles a request to the `/metrics` endpoint, returning the node's metrics in the Prometheus
+    /// text format.
+    pub async fn handle_metrics(&self) -> Result<String, RpcError> {
+        if !self.config.is_allowed("metrics") {
+            return Err(RpcError::MethodNotFound("metrics".to_string()));
+        }
+
+        let mut metrics = String::new();
+        self.alt_chain_stats()
+            .await?
+            .write_prometheus(&mut metrics)
+            .expect("Writing to a String can't fail");
+        Ok(metrics)
+    }
+
+    async fn call_method(&self, method: &str, params: Option<Value>) -> Result<Value, RpcError> {
+        // Methods hidden by the policy look the same as methods that don't exist.
+        if !self.config.is_allowed(method) {
+            return Err(RpcError::MethodNotFound(method.to_string()));
+        }
+
+        // monerod also accepts the old names of some methods.
+        let result = match method {
+            "get_info" => to_value(self.get_info().await?),
+            "get_block" => to_value(self.get_block(parse_params(params)?).await?),
+            "get_block_header_by_height" | "getblockheaderbyheight" => to_value(
+                self.get_block_header_by_height(parse_params(params)?)
+                    .await?,
+            ),
+            "get_block_header_by_hash" | "getblockheaderbyhash" => {
+                to_value(self.get_block_header_by_hash(parse_params(params)?).await?)
+            }
+            "get_block_count" | "getblockcount" => to_value(self.get_block_count().await?),
+            "get_last_block_header" | "getlastblockheader" => {
+                to_value(self.get_last_block_header().await?)
+            }
+            "get_fee_estimate" => to_value(self.get_fee_estimate().await?),
+            "estimate_backlog" => to_value(self.estimate_backlog(parse_params(params)?).await?),
+            "get_output_distribution" => {
+                to_value(self.get_output_distribution(parse_params(params)?).await?)
+            }
+            "get_block_template" | "getblocktemplate" => {
+                to_value(self.get_block_template(parse_params(params)?).await?)
+            }
+            "submit_block" | "submitblock" => match &self.block_queue {
+                Some(block_queue) => to_value(
+                    self.submit_block(block_queue, parse_params(params)?)
+                        .await?,
+                ),
+                // Without the block queue the method is hidden, like the methods of the policy.
+                None => return Err(RpcError::MethodNotFound(method.to_string())),
+            },
+            "dump_context" => to_value(self.dump_context().await?),
+            "get_alt_chain_stats" => to_value(self.get_alt_chain_stats().await?),
+            "get_public_nodes" => match &self.public_nodes {
+                Some(public_nodes) => to_value(
+                    self.get_public_nodes(public_nodes.as_ref(), parse_params(params)?)
+                        .await?,
+                ),
+                // Without the address book the method is hidden, like `compact_db`.
+                None => return Err(RpcError::MethodNotFound(method.to_string())),
+            },
+            "tx_report" => to_value(self.tx_report(parse_params(params)?).await?),
+            "compact_db" => match &self.database_compactor {
+                Some(database_compactor) => to_value(self.compact_db(database_compactor.as_ref())?),
+                // Without a compactor the method is hidden, like `submit_block` without the queue.
+                None => return Err(RpcError::MethodNotFound(method.to_string())),
+            },
+            _ => return Err(RpcError::MethodNotFound(method.to_string())),
+        };
+
+        Ok(result)
+    }
+
+    async fn context(&self) -> Result<BlockChainContext, RpcError> {
+        Ok(self
+            .context_svc
+            .clone()
+            .oneshot(ContextRequest::BlockChainContext)
+            .await?
+            .into_block_chain_context()?)
+    }
+
+    async fn context_dump(&self) -> Result<ContextDump, RpcError> {
+        Ok(self
+            .context_svc
+            .clone()
+            .oneshot(ContextRequest::DumpContext)
+            .await?
+            .into_dump_context()?)
+    }
+
+    async fn alt_chain_stats(&self) -> Result<AltChainStats, RpcError> {
+        Ok(self
+            .context_svc
+            .clone()
+            .oneshot(ContextRequest::AltChainStats)
+            .await?
+            .into_alt_chain_stats()?)
+    }
+
+    async fn database_request(&self, req: DatabaseRequest) -> Result<DatabaseResponse, RpcError> {
+        self.database
+            .clone()
+            .oneshot(req)
+            .await
+            .map_err(RpcError::Internal)
+    }
+
+    async fn get_info(&self) -> Result<GetInfoResponse, RpcError> {
+        let context = self.context().await?;
+
+        let difficulty = WideDifficulty::from(context.next_difficulty);
+        let cumulative_difficulty = WideDifficulty::from(context.cumulative_difficulty);
+
+        Ok(GetInfoResponse {
+            height: context.chain_height,
+            top_block_hash: hex::encode(context.top_hash),
+            difficulty: difficulty.low,
+            wide_difficulty: difficulty.wide,
+            difficulty_top64: difficulty.top64,
+            cumulative_difficulty: cumulative_difficulty.low,
+            wide_cumulative_difficulty: cumulative_difficulty.wide,
+            cumulative_difficulty_top64: cumulative_difficulty.top64,
+            target: context.current_hf.block_time().as_secs(),
+            block_weight_limit: context.next_block_weight_limit,
+            block_size_limit: context.next_block_weight_limit,
+            block_weight_median: context.effective_median_weight,
+            block_size_median: context.effective_median_weight,
+            mainnet: context.network == Network::Mainnet,
+            testnet: context.network == Network::Testnet,
+            stagenet: context.network == Network::Stagenet,
+            nettype: match context.network {
+                Network::Mainnet => "mainnet",
+                Network::Testnet => "testnet",
+                Network::Stagenet => "stagenet",
+                Network::Regtest => "fakechain",
+            }
+            .to_string(),
+            start_time: if self.config.restricted {
+                0
+            } else {
+                self.start_time
+            },
+            version: if self.config.restricted {
+                String::new()
+            } else {
+                env!("CARGO_PKG_VERSION").to_string()
+            },
+            status: STATUS_OK.to_string(),
+            untrusted: false,
+        })
+    }
+
+    async fn get_block_count(&self) -> Result<GetBlockCountResponse, RpcError> {
+        let context = self.context().await?;
+
+        Ok(GetBlockCountResponse {
+            count: context.chain_height,
+            status: STATUS_OK.to_string(),
+        })
+    }
+
+    async fn get_fee_estimate(&self) -> Result<GetFeeEstimateResponse, RpcError> {
+        let estimate = self.context().await?.fee_estimate();
+
+        Ok(GetFeeEstimateResponse {
+            fee: estimate.fee,
+            fees: estimate.fees.to_vec(),
+            quantization_mask: estimate.quantization_mask,
+            status: STATUS_OK.to_string(),
+            untrusted: false,
+        })
+    }
+
+    async fn estimate_backlog(
+        &self,
+        req: EstimateBacklogRequest,
+    ) -> Result<EstimateBacklogResponse, RpcError> {
+        let Some(tx_pool) = &self.tx_pool else {
+            return Err(RpcError::Internal(
+                "The RPC server was not given the transaction pool".into(),
+            ));
+        };
+        if req.fees.len() as u64 > self.config.max_results("estimate_backlog") {
+            return Err(RpcError::InvalidParams("Too many fees".to_string()));
+        }
+
+        let context = self.context().await?;
+        let fees = if req.fees.is_empty() {
+            context.fee_estimate().fees.to_vec()
+        } else {
+            req.fees
+        };
+
+        let pool: Vec<(usize, u64)> = tx_pool
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|tx| (tx.weight, tx.fee))
+            .collect();
+
+        Ok(EstimateBacklogResponse {
+            backlog: estimate_backlog(&pool, &fees, context.effective_median_weight)
+                .into_iter()
+                .map(|backlog| FeeBacklog {
+                    fee: backlog.fee_per_byte,
+                    weight_ahead: backlog.weight_ahead,
+                    blocks_ahead: backlog.blocks_ahead,
+                })
+                .collect(),
+            pool_weight: pool.iter().map(|(weight, _)| *weight as u64).sum(),
+            block_weight: context.effective_median_weight as u64,
+            status: STATUS_OK.to_string(),
+        })
+    }
+
+    async fn get_output_distribution(
+        &self,
+        req: GetOutputDistributionRequest,
+    ) -> Result<GetOutputDistributionResponse, RpcError> {
+        // Like monerod, public nodes only give the RingCT distribution.
+        if self.config.restricted && req.amounts != [0] {
+            return Err(RpcError::InvalidParams(
+                "Restricted RPC can only get the distribution of RingCT outputs".to_string(),
+            ));
+        }
+
+        let context = self.context().await?;
+        let to_height = match req.to_height {
+            0 => context.chain_height - 1,
+            to_height => to_height,
+        };
+        check_height(to_height, &context)?;
+        if req.from_height > to_height {
+            return Err(RpcError::InvalidParams(
+                "from_height is above to_height".to_string(),
+            ));
+        }
+
+        let mut distributions = Vec::with_capacity(req.amounts.len());
+        for amount in req.amounts {
+            let counts = self
+                .output_distributions
+                .get(self.database.clone(), amount, to_height)
+                .await
+                .map_err(RpcError::Internal)?;
+            let (before, counts) = counts.split_at(req.from_height as usize);
+
+            let base = before.iter().sum();
+            let distribution = if req.cumulative {
+                counts
+                    .iter()
+                    .scan(base, |total, count| {
+                        *total += count;
+                        Some(*total)
+                    })
+                    .collect()
+            } else {
+                counts.to_vec()
+            };
+
+            distributions.push(OutputDistribution {
+                amount,
+                start_height: req.from_height,
+                distribution,
+                base,
+            });
+        }
+
+        Ok(GetOutputDistributionResponse {
+            distributions,
+            status: STATUS_OK.to_string(),
+        })
+    }
+
+    async fn get_block_template(
+        &self,
+        req: GetBlockTemplateRequest,
+    ) -> Result<GetBlockTemplateResponse, RpcError> {
+        let too_big = || {
+            RpcError::ReserveSize(format!(
+                "Too big reserved size, maximum {MAX_EXTRA_NONCE_SIZE}"
+            ))
+        };
+
+        // Like monerod, the reserved space is either zeros or the given extra nonce. The size is
+        // checked before the zeros are allocated.
+        let extra_nonce = match (req.reserve_size, req.extra_nonce.is_empty()) {
+            (0, _) => hex::decode(&req.extra_nonce).map_err(|_| {
+                RpcError::InvalidParams("extra_nonce should be a hex string".to_string())
+            })?,
+            (_, false) => {
+                return Err(RpcError::ReserveSize(
+                    "Cannot specify both a reserve_size and an extra_nonce".to_string(),
+                ))
+            }
+            (reserve_size, true) => {
+                let reserve_size = usize::try_from(reserve_size)
+                    .ok()
+                    .filter(|size| *size <= MAX_EXTRA_NONCE_SIZE)
+                    .ok_or_else(too_big)?;
+                vec![0; reserve_size]
+            }
+        };
+        if extra_nonce.len() > MAX_EXTRA_NONCE_SIZE {
+            return Err(too_big());
+        }
+
+        let context = self.context().await?;
+        let network = match context.network {
+            Network::Mainnet | Network::Regtest => AddressNetwork::Mainnet,
+            Network::Testnet => AddressNetwork::Testnet,
+            Network::Stagenet => AddressNetwork::Stagenet,
+        };
+        let address = MoneroAddress::from_str(network, &req.wallet_address)
+            .map_err(|e| RpcError::WrongWalletAddress(e.to_string()))?;
+        if address.is_subaddress() {
+            return Err(RpcError::WrongWalletAddress(
+                "Mining to a subaddress is not supported".to_string(),
+            ));
+        }
+
+        let Some(tx_pool) = &self.tx_pool else {
+            return Err(RpcError::Internal(
+                "The RPC server was not given the transaction pool".into(),
+            ));
+        };
+
+        let mut tx_key = [0; 64];
+        OsRng.fill_bytes(&mut tx_key);
+        let miner_keys = MinerTxKeys::derive(
+            &address.spend,
+            &address.view,
+            &Scalar::from_bytes_mod_order_wide(&tx_key),
+        );
+
+        let template = build_block_template(
+            &context,
+            &tx_pool.lock().unwrap(),
+            &miner_keys,
+            &extra_nonce,
+            current_time(),
+        )
+        .map_err(|e| RpcError::Internal(e.into()))?;
+
+        let (seed_height, seed_hash) = if context.current_hf >= HardFork::V12 {
+            let seed_height = randomx_seed_height(template.height);
+            let seed_hash = self
+                .database_request(DatabaseRequest::BlockHash(seed_height))
+                .await?
+                .into_block_hash()?;
+            (seed_height, hex::encode(seed_hash))
+        } else {
+            (0, String::new())
+        };
+
+        let difficulty = WideDifficulty::from(template.difficulty);
+        let res = GetBlockTemplateResponse {
+            blocktemplate_blob: hex::encode(&template.blob),
+            blockhashing_blob: hex::encode(&template.hashing_blob),
+            difficulty: difficulty.low,
+            wide_difficulty: difficulty.wide,
+            difficulty_top64: difficulty.top64,
+            expected_reward: template.reward,
+            height: template.height,
+            prev_hash: hex::encode(context.top_hash),
+            reserved_offset: if template.reserve_size == 0 {
+                0
+            } else {
+                template.reserved_offset as u64
+            },
+            seed_hash,
+            seed_height,
+            next_seed_hash: String::new(),
+            status: STATUS_OK.to_string(),
+            untrusted: false,
+        };
+
+        let mut templates = self.block_templates.lock().unwrap();
+        if templates.len() == BLOCK_TEMPLATE_CACHE_SIZE {
+            templates.pop_front();
+        }
+        templates.push_back(template);
+
+        Ok(res)
+    }
+
+    /// Queues the block for verification, like monerod only the first blob is used.
+    ///
+    /// The block is verified after this returns, so the response doesn't mean it was added to the
+    /// chain.
+    async fn submit_block(
+        &self,
+        block_queue: &QueueSender<QueuedBlock>,
+        blobs: Vec<String>,
+    ) -> Result<SubmitBlockResponse, RpcError> {
+        let [blob] = blobs.as_slice() else {
+            return Err(RpcError::InvalidParams(
+                "Expected one block blob".to_string(),
+            ));
+        };
+        let blob = hex::decode(blob).map_err(|_| RpcError::WrongBlockBlob)?;
+
+        if let Some(template) = self
+            .block_templates
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|template| template.is_template_of(&blob))
+        {
+            template
+                .check_blob(&blob)
+                .map_err(|e| RpcError::BlockNotAccepted(e.to_string()))?;
+        }
+
+        let block = Block::read(&mut blob.as_slice()).map_err(|_| RpcError::WrongBlockBlob)?;
+        let block_id = hex::encode(block.hash());
+        block_queue.push_tip(QueuedBlock {
+            source: BlockSource::Rpc,
+            block,
+        });
+
+        Ok(SubmitBlockResponse {
+            block_id,
+            status: STATUS_OK.to_string(),
+        })
+    }
+
+    async fn dump_context(&self) -> Result<DumpContextResponse, RpcError> {
+        let dump = self.context_dump().await?;
+
+        Ok(DumpContextResponse {
+            height: dump.chain_height,
+            current_hf: dump.current_hf as u8,
+            next_hf: dump.next_hf.map(|hf| hf as u8),
+            hf_votes: dump.hf_votes.to_vec(),
+            hf_total_votes: dump.hf_total_votes,
+            hf_last_height: dump.hf_last_height,
+            short_term_weights: weight_window(dump.short_term_weights),
+            long_term_weights: weight_window(dump.long_term_weights),
+            weights_tip_height: dump.weights_tip_height,
+            difficulty: DifficultyWindow {
+                len: dump.difficulty.len,
+                oldest_timestamp: dump.difficulty.oldest_timestamp,
+                newest_timestamp: dump.difficulty.newest_timestamp,
+                wide_cumulative_difficulty: WideDifficulty::from(
+                    dump.difficulty.last_cumulative_difficulty,
+                )
+                .wide,
+                last_accounted_height: dump.difficulty.last_accounted_height,
+            },
+            status: STATUS_OK.to_string(),
+        })
+    }
+
+    async fn get_alt_chain_stats(&self) -> Result<GetAltChainStatsResponse, RpcError> {
+        let stats = self.alt_chain_stats().await?;
+
+        Ok(GetAltChainStatsResponse {
+            alt_blocks: stats.alt_blocks,
+            reorgs: stats.reorgs,
+            max_reorg_depth: stats.max_reorg_depth,
+            reorg_depths: stats
+                .reorg_depths
+                .buckets()
+                .map(|(le, count)| ReorgDepthBucket { le, count })
+                .collect(),
+            time_below_alt_tip: stats.time_below_alt_tip.as_secs_f64(),
+            below_alt_tip: stats.below_alt_tip,
+            status: STATUS_OK.to_string(),
+        })
+    }
+
+    async fn get_public_nodes(
+        &self,
+        public_nodes: &dyn PublicNodeSource,
+        req: GetPublicNodesRequest,
+    ) -> Result<GetPublicNodesResponse, RpcError> {
+        let nodes = public_nodes
+            .public_nodes()
+            .await
+            .map_err(RpcError::Internal)?;
+
+        Ok(GetPublicNodesResponse {
+            white: if req.white { nodes.white } else { vec![] },
+            gray: if req.gray { nodes.gray } else { vec![] },
+            status: STATUS_OK.to_string(),
+            untrusted: false,
+        })
+    }
+
+    fn compact_db(
+        &self,
+        database_compactor: &dyn DatabaseCompactor,
+    ) -> Result<CompactDbResponse, RpcError> {
+        database_compactor
+            .request_compaction()
+            .map_err(RpcError::Internal)?;
+
+        Ok(CompactDbResponse {
+            status: STATUS_OK.to_string(),
+        })
+    }
+
+    async fn tx_report(&self, req: TxReportRequest) -> Result<TxReportResponse, RpcError> {
+        let blob = hex::decode(&req.tx_as_hex)
+            .map_err(|_| RpcError::InvalidParams("tx_as_hex is not hex".to_string()))?;
+        let tx = Transaction::read(&mut blob.as_slice())
+            .map_err(|e| RpcError::InvalidParams(format!("Invalid transaction: {e}")))?;
+
+        let context = self.context().await?;
+        let report = tx_report(&tx, tx.weight(), &context, self.database.clone()).await?;
+
+        Ok(TxReportResponse {
+            version: report.version,
+            weight: report.weight,
+            fee: report.fee,
+            hf: report.hf as u8,
+            passed: report.passed(),
+            checks: report
+                .checks
+                .into_iter()
+                .map(|check| TxRuleCheck {
+                    rule: check.rule.to_string(),
+                    passed: check.passed,
+                    details: check.details,
+                })
+                .collect(),
+            ring_ages: report.ring_ages,
+            status: STATUS_OK.to_string(),
+        })
+    }
+
+    async fn get_blocks(
+        &self,
+        endpoint: &str,
+        req: GetBlocksRequest,
+    ) -> Result<GetBlocksResponse, RpcError> {
+        let context = self.context().await?;
+        let daemon_time = current_time();
+
+        let (start_height, end_height) = if req.requested_info == REQUESTED_INFO_POOL_ONLY {
+            (context.chain_height, context.chain_height)
+        } else {
+            let start_height = self
+                .find_split_height(&req.block_ids)
+                .await?
+                .max(req.start_height);
+            let max_blocks = self
+                .config
+                .max_results(endpoint)
+                .min(GET_BLOCKS_MAX_BLOCK_COUNT);
+            let end_height = start_height
+                .saturating_add(max_blocks)
+                .min(context.chain_height);
+            (start_height, end_height)
+        };
+
+        let mut block_blobs = Vec::new();
+        for chunk_start in (start_height..end_height).step_by(GET_BLOCKS_CHUNK_SIZE as usize) {
+            if chunk_start != start_height {
+                yield_now().await;
+            }
+
+            block_blobs.extend(
+                self.database_request(DatabaseRequest::BlockBlobsInRange {
+                    range: chunk_start..(chunk_start + GET_BLOCKS_CHUNK_SIZE).min(end_height),
+                    pruned: req.prune,
+                })
+                .await?
+                .into_block_blobs_in_range()?,
+            );
+        }
+
+        let mut blocks = Vec::with_capacity(block_blobs.len());
+        let mut output_indices = Vec::with_capacity(block_blobs.len());
+
+        for block_blobs in block_blobs {
+            let txs = if req.prune {
+                TransactionBlobs::Pruned(
+                    block_blobs
+                        .txs
+                        .into_iter()
+                        .map(|tx| PrunedTxBlobEntry {
+                            blob: tx.blob.into(),
+                            prunable_hash: tx.prunable_hash.unwrap_or_default(),
+                        })
+                        .collect(),
+                )
+            } else {
+                TransactionBlobs::Normal(
+                    block_blobs
+                        .txs
+                        .into_iter()
+                        .map(|tx| tx.blob.into())
+                        .collect(),
+                )
+            };
+
+            blocks.push(BlockCompleteEntry {
+                pruned: req.prune,
+                block: block_blobs.block,
+                block_weight: block_blobs.block_weight as u64,
+                txs,
+            });
+
+            output_indices.push(BlockOutputIndices {
+                indices: block_blobs
+                    .output_indices
+                    .into_iter()
+                    .skip(usize::from(req.no_miner_tx))
+                    .map(|indices| TxOutputIndices { indices })
+                    .collect(),
+            });
+        }
+
+        let mut res = GetBlocksResponse {
+            blocks,
+            start_height,
+            current_height: context.chain_height,
+            output_indices,
+            pool_info_extent: POOL_INFO_EXTENT_NONE,
+            added_pool_txs: vec![],
+            remaining_added_pool_txids: vec![],
+            removed_pool_txids: vec![],
+            daemon_time,
+            status: STATUS_OK.to_string(),
+            untrusted: false,
+        };
+
+        // The pool is only useful to wallets that are synced.
+        let synced = end_height == context.chain_height;
+        match req.requested_info {
+            REQUESTED_INFO_POOL_ONLY => self.add_pool_info(&mut res, req.pool_info_since),
+            REQUESTED_INFO_BLOCKS_AND_POOL if synced => {
+                self.add_pool_info(&mut res, req.pool_info_since)
+            }
+            _ => (),
+        }
+
+        Ok(res)
+    }
+
+    /// Adds the pool's transactions to a `get_blocks.bin` response, only the changes since the UNIX
+    /// timestamp `since` are added if the pool remembers them.
+    fn add_pool_info(&self, res: &mut GetBlocksResponse, since: u64) {
+        let Some(tx_pool) = &self.tx_pool else {
+            return;
+        };
+
+        let pool = tx_pool.lock().unwrap();
+        let added = match pool.changes_since(since).filter(|_| since != 0) {
+            Some(changes) => {
+                res.pool_info_extent = POOL_INFO_EXTENT_INCREMENTAL;
+                res.removed_pool_txids = changes.removed;
+                changes.added
+            }
+            None => {
+                res.pool_info_extent = POOL_INFO_EXTENT_FULL;
+                pool.iter().cloned().collect()
+            }
+        };
+        drop(pool);
+
+        let mut size = 0;
+        for tx in added {
+            if size >= GET_BLOCKS_MAX_POOL_TXS_SIZE {
+                res.remaining_added_pool_txids.push(tx.hash);
+                continue;
+            }
+
+            let tx_blob = tx.tx.serialize();
+            size += tx_blob.len();
+            res.added_pool_txs.push(PoolTxInfo {
+                tx_hash: tx.hash,
+                tx_blob,
+                // Double spends are never added to the pool.
+                double_spend_seen: false,
+            });
+        }
+    }
+
+    /// Returns the height of the first block in a short chain history that is in the main chain.
+    async fn find_split_height(&self, block_ids: &[[u8; 32]]) -> Result<u64, RpcError> {
+        let genesis = self
+            .database_request(DatabaseRequest::BlockHash(0))
+            .await?
+            .into_block_hash()?;
+
+        if block_ids.last() != Some(&genesis) {
+            return Err(RpcError::InvalidParams(
+                "The last block id must be the genesis block".to_string(),
+            ));
+        }
+
+        for id in block_ids {
+            let height = self
+                .database_request(DatabaseRequest::BlockHeight(*id))
+                .await?
+                .into_block_height()?;
+
+            if let Some(height) = height {
+                return Ok(height);
+            }
+        }
+
+        unreachable!("The genesis block is in the main chain")
+    }
+
+    async fn get_o_indexes(
+        &self,
+        req: GetOIndexesRequest,
+    ) -> Result<GetOIndexesResponse, RpcError> {
+        let o_indexes = self
+            .database_request(DatabaseRequest::TxOutputIndices(req.txid))
+            .await?
+            .into_tx_output_indices()?;
+
+        Ok(GetOIndexesResponse {
+            o_indexes,
+            status: STATUS_OK.to_string(),
+            untrusted: false,
+        })
+    }
+
+    async fn get_outs(
+        &self,
+        endpoint: &str,
+        req: GetOutsRequest,
+    ) -> Result<GetOutsResponse, RpcError> {
+        if req.outputs.len() as u64 > self.config.max_results(endpoint) {
+            return Err(RpcError::InvalidParams(
+                "Too many outputs requested".to_string(),
+            ));
+        }
+
+        let context = self.context().await?;
+
+        let outputs = self
+            .database_request(DatabaseRequest::Outputs(
+                req.outputs
+                    .iter()
+                    .map(|output| (output.amount, output.index))
+                    .collect(),
+            ))
+            .await?
+            .into_outputs()?;
+
+        let now = current_time();
+
+        Ok(GetOutsResponse {
+            outs: outputs
+                .into_iter()
+                .map(|output| OutKey {
+                    key: output.key,
+                    mask: output.mask,
+                    unlocked: is_output_unlocked(
+                        &output.time_lock,
+                        context.chain_height,
+                        now,
+                        &context.current_hf,
+                    ),
+                    height: output.height,
+                    txid: if req.get_txid { output.txid } else { [0; 32] },
+                })
+                .collect(),
+            status: STATUS_OK.to_string(),
+            untrusted: false,
+        })
+    }
+
+    async fn get_last_block_header(&self) -> Result<BlockHeaderResponse, RpcError> {
+        let context = self.context().await?;
+        let (_, block_header) = self
+            .block_header((context.chain_height - 1).into(), &context)
+            .await?;
+
+        Ok(block_header_response(block_header))
+    }
+
+    async fn get_block_header_by_height(
+        &self,
+        req: GetBlockHeaderByHeightRequest,
+    ) -> Result<BlockHeaderResponse, RpcError> {
+        let context = self.context().await?;
+        check_height(req.height, &context)?;
+
+        let (_, block_header) = self.block_header(req.height.into(), &context).await?;
+        Ok(block_header_response(block_header))
+    }
+
+    async fn get_block_header_by_hash(
+        &self,
+        req: GetBlockHeaderByHashRequest,
+    ) -> Result<BlockHeaderResponse, RpcError> {
+        let context = self.context().await?;
+
+        let (_, block_header) = self
+            .block_header(BlockID::Hash(parse_hash(&req.hash)?), &context)
+            .await?;
+        Ok(block_header_response(block_header))
+    }
+
+    async fn get_block(&self, req: GetBlockRequest) -> Result<GetBlockResponse, RpcError> {
+        let context = self.context().await?;
+
+        let id = match (req.hash, req.height) {
+            (Some(hash), _) => BlockID::Hash(parse_hash(&hash)?),
+            (None, Some(height)) => {
+                check_height(height, &context)?;
+                BlockID::Height(height)
+            }
+            (None, None) => {
+                return Err(RpcError::InvalidParams(
+                    "One of height or hash must be given".to_string(),
+                ))
+            }
+        };
+
+        let (block, block_header) = self.block_header(id, &context).await?;
+
+        Ok(GetBlockResponse {
+            blob: hex::encode(block.serialize()),
+            miner_tx_hash: block_header.miner_tx_hash.clone(),
+            block_header,
+            tx_hashes: block.txs.iter().map(hex::encode).collect(),
+            status: STATUS_OK.to_string(),
+            untrusted: false,
+        })
+    }
+
+    /// Returns a block and its header.
+    async fn block_header(
+        &self,
+        id: BlockID,
+        context: &BlockChainContext,
+    ) -> Result<(monero_serai::block::Block, BlockHeader), RpcError> {
+        let block = self
+            .database_request(DatabaseRequest::Block(id))
+            .await?
+            .into_block()?;
+
+        let height = block.number() as u64;
+
+        let pow_info = self
+            .database_request(DatabaseRequest::BlockPOWInfo(height.into()))
+            .await?
+            .into_block_pow_info()?;
+
+        let prev_cumulative_difficulty = if height == 0 {
+            0
+        } else {
+            let prev_pow_info = self
+                .database_request(DatabaseRequest::BlockPOWInfo((height - 1).into()))
+                .await?
+                .into_block_pow_info()?;
+            prev_pow_info.cumulative_difficulty
+        };
+
+        let weights = self
+            .database_request(DatabaseRequest::BlockWeights(height.into()))
+            .await?
+            .into_block_weights()?;
+
+        let difficulty =
+            WideDifficulty::from(pow_info.cumulative_difficulty - prev_cumulative_difficulty);
+        let cumulative_difficulty = WideDifficulty::from(pow_info.cumulative_difficulty);
+
+        let block_header = BlockHeader {
+            major_version: block.header.major_version,
+            minor_version: block.header.minor_version,
+            timestamp: block.header.timestamp,
+            prev_hash: hex::encode(block.header.previous),
+            nonce: block.header.nonce,
+            orphan_status: false,
+            height,
+            depth: context.chain_height.saturating_sub(height + 1),
+            hash: hex::encode(block.hash()),
+            difficulty: difficulty.low,
+            wide_difficulty: difficulty.wide,
+            difficulty_top64: difficulty.top64,
+            cumulative_difficulty: cumulative_difficulty.low,
+            wide_cumulative_difficulty: cumulative_difficulty.wide,
+            cumulative_difficulty_top64: cumulative_difficulty.top64,
+            reward: block
+                .miner_tx
+                .prefix
+                .outputs
+                .iter()
+                .map(|output| output.amount.unwrap_or(0))
+                .sum(),
+            block_size: weights.block_weight,
+            block_weight: weights.block_weight,
+            long_term_weight: weights.long_term_weight,
+            num_txes: block.txs.len(),
+            miner_tx_hash: hex::encode(block.miner_tx.hash()),
+        };
+
+        Ok((*block, block_header))
+    }
+}
+
+/// Returns the current UNIX timestamp.
+fn current_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn from_epee<T: EpeeObject>(body: &[u8]) -> Result<T, RpcError> {
+    epee_encoding::from_bytes(body).map_err(|e| RpcError::InvalidParams(e.to_string()))
+}
+
+fn to_epee<T: EpeeObject>(res: &T) -> Result<Vec<u8>, RpcError> {
+    epee_encoding::to_bytes(res).map_err(|e| RpcError::Internal(e.into()))
+}
+
+fn to_value<T: serde::Serialize>(res: T) -> Value {
+    serde_json::to_value(res).expect("RPC responses can always be serialized")
+}
+
+fn parse_params<T: DeserializeOwned>(params: Option<Value>) -> Result<T, RpcError> {
+    serde_json::from_value(params.unwrap_or_else(|| Value::Object(Default::default())))
+        .map_err(|e| RpcError::InvalidParams(e.to_string()))
+}
+
+fn parse_hash(hash: &str) -> Result<[u8; 32], RpcError> {
+    hex::decode(hash)
+        .ok()
+        .and_then(|hash| hash.try_into().ok())
+        .ok_or_else(|| RpcError::InvalidHash(hash.to_string()))
+}
+
+fn check_height(height: u64, context: &BlockChainContext) -> Result<(), RpcError> {
+    if height >= context.chain_height {
+        return Err(RpcError::TooBigHeight {
+            height,
+            top_height: context.chain_height - 1,
+        });
+    }
+    Ok(())
+}
+
+fn weight_window(summary: WeightWindowSummary) -> WeightWindow {
+    WeightWindow {
+        len: summary.len,
+        min: summary.min,
+        median: summary.median,
+        max: summary.max,
+    }
+}
+
+fn block_header_response(block_header: BlockHeader) -> BlockHeaderResponse {
+    BlockHeaderResponse {
+        block_header,
+        status: STATUS_OK.to_string(),
+        untrusted: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::task::Context;
+    use std::time::Instant;
+
+    use epee_encoding::{from_bytes, to_bytes, EpeeObject};
+    use futures::{
+        executor::block_on,
+        future::{ready, BoxFuture},
+        task::noop_waker_ref,
+        FutureExt,
+    };
+    use monero_serai::transaction::Timelock;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use cuprate_common::Network;
+    use monero_consensus::{
+        block_template::BlockTemplate,
+        context::{
+            BlockChainContext, ContextDump, ContextRequest, ContextResponse,
+            DifficultyWindowSummary, WeightWindowSummary,
+        },
+        fork_metrics::ForkMetrics,
+        genesis::generate_genesis_block,
+        hardforks::HardFork,
+        outputs::OutputOnChain,
+        test_utils::{
+            dummy_context, DummyBlockExtendedHeader, DummyDatabase, DummyDatabaseBuilder,
+        },
+        txpool::{PoolTx, TxPool},
+        verification_queue::verification_queue,
+        ConsensusError, Database, DatabaseRequest,
+    };
+
+    use super::{DatabaseCompactor, PublicNodeSource, PublicNodes, RpcError, RpcHandler};
+    use crate::bin::*;
+    use crate::json_rpc::{
+        Response, CORE_RPC_ERROR_CODE_BLOCK_NOT_ACCEPTED, CORE_RPC_ERROR_CODE_INTERNAL_ERROR,
+        CORE_RPC_ERROR_CODE_TOO_BIG_HEIGHT, CORE_RPC_ERROR_CODE_TOO_BIG_RESERVE_SIZE,
+        CORE_RPC_ERROR_CODE_WRONG_BLOCKBLOB, CORE_RPC_ERROR_CODE_WRONG_WALLET_ADDRESS,
+        INVALID_PARAMS, METHOD_NOT_FOUND,
+    };
+    use crate::methods::PublicNode;
+    use crate::policy::RpcConfig;
+
+    fn call(method: &str, params: Value) -> Response {
+        call_with_config(RpcConfig::default(), method, params)
+    }
+
+    fn handler(
+        config: RpcConfig,
+    ) -> RpcHandler<
+        DummyDatabase,
+        impl tower::Service<ContextRequest, Response = ContextResponse, Error = ConsensusError> + Clone,
+    > {
+        let output = |time_lock| OutputOnChain {
+            height: 3,
+            time_lock,
+            key: [1; 32],
+            mask: [2; 32],
+            txid: [3; 32],
+        };
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .add_tx_output_indices([5; 32], vec![1, 2, 3])
+            .add_output(0, 7, output(Timelock::None))
+            .add_output(0, 8, output(Timelock::Block(100)))
+            .finish();
+
+        handler_with_database(config, database, 10)
+    }
+
+    /// Returns a handler using this database, the chain height of the context is `chain_height`.
+    fn handler_with_database<D: Database + Clone>(
+        config: RpcConfig,
+        database: D,
+        chain_height: u64,
+    ) -> RpcHandler<
+        D,
+        impl tower::Service<ContextRequest, Response = ContextResponse, Error = ConsensusError> + Clone,
+    > {
+        let context = BlockChainContext {
+            chain_height,
+            top_hash: [1; 32],
+            cumulative_difficulty: u128::from(u64::MAX) + 1,
+            next_difficulty: 100,
+            adjusted_time: None,
+            already_generated_coins: 0,
+            ..dummy_context(HardFork::V16)
+        };
+        let dump = ContextDump {
+            chain_height: 10,
+            current_hf: HardFork::V16,
+            next_hf: None,
+            hf_votes: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10],
+            hf_total_votes: 10,
+            hf_last_height: 9,
+            short_term_weights: WeightWindowSummary {
+                len: 10,
+                min: 100,
+                median: 300,
+                max: 500,
+            },
+            long_term_weights: WeightWindowSummary::default(),
+            weights_tip_height: 9,
+            difficulty: DifficultyWindowSummary {
+                len: 10,
+                oldest_timestamp: Some(0),
+                newest_timestamp: Some(1_000),
+                last_cumulative_difficulty: 1_000,
+                last_accounted_height: 9,
+            },
+        };
+        let now = Instant::now();
+        let mut fork_metrics = ForkMetrics::default();
+        fork_metrics.record_alt_block(10, 20, now);
+        fork_metrics.record_reorg(3, now);
+        fork_metrics.record_reorg(30, now);
+        let alt_chain_stats = fork_metrics.stats(now);
+
+        let context_svc = tower::service_fn(move |req| {
+            ready(Ok::<_, ConsensusError>(match req {
+                ContextRequest::BlockChainContext => {
+                    ContextResponse::BlockChainContext(context.clone())
+                }
+                ContextRequest::DumpContext => ContextResponse::DumpContext(dump.clone()),
+                ContextRequest::AltChainStats => {
+                    ContextResponse::AltChainStats(alt_chain_stats.clone())
+                }
+            }))
+        });
+
+        RpcHandler::new(config, database, context_svc)
+    }
+
+    fn call_with_config(config: RpcConfig, method: &str, params: Value) -> Response {
+        let body = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        block_on(handler(config).handle_body(body.to_string().as_bytes()))
+    }
+
+    fn call_bin<T: EpeeObject>(
+        config: RpcConfig,
+        endpoint: &str,
+        req: &impl EpeeObject,
+    ) -> Result<T, RpcError> {
+        let res = block_on(handler(config).handle_bin(endpoint, &to_bytes(req).unwrap()))?;
+        Ok(from_bytes(&res).unwrap())
+    }
+
+    /// Returns the hash the dummy database gives the block at this height.
+    fn block_hash(height: u64) -> [u8; 32] {
+        let mut hash = [0; 32];
+        hash[0..8].copy_from_slice(&height.to_le_bytes());
+        hash
+    }
+
+    fn get_blocks_request(block_ids: Vec<[u8; 32]>, start_height: u64) -> GetBlocksRequest {
+        GetBlocksRequest {
+            requested_info: 0,
+            block_ids,
+            start_height,
+            prune: true,
+            no_miner_tx: false,
+            pool_info_since: 0,
+        }
+    }
+
+    #[test]
+    fn get_blocks_bin() {
+        let req = get_blocks_request(vec![[9; 32], block_hash(4), block_hash(0)], 0);
+        let res: GetBlocksResponse =
+            call_bin(RpcConfig::default(), "get_blocks.bin", &req).unwrap();
+
+        assert_eq!(res.start_height, 4);
+        assert_eq!(res.current_height, 10);
+        assert_eq!(res.blocks.len(), 6);
+        assert_eq!(res.blocks[0].block, block_hash(4));
+        assert!(res.blocks[0].pruned);
+        assert_eq!(res.output_indices[0].indices[0].indices, [4]);
+
+        let req = GetBlocksRequest {
+            no_miner_tx: true,
+            ..get_blocks_request(vec![block_hash(0)], 8)
+        };
+        let res: GetBlocksResponse = call_bin(RpcConfig::default(), "getblocks.bin", &req).unwrap();
+
+        assert_eq!(res.start_height, 8);
+        assert_eq!(res.blocks.len(), 2);
+        assert!(res.output_indices[0].indices.is_empty());
+
+        let req = get_blocks_request(vec![block_hash(4)], 0);
+        assert!(matches!(
+            call_bin::<GetBlocksResponse>(RpcConfig::default(), "get_blocks.bin", &req),
+            Err(RpcError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn dropped_get_blocks_stops_between_chunks() {
+        let chain_height = GET_BLOCKS_CHUNK_SIZE * 2 + 50;
+        let blob_requests = Arc::new(AtomicUsize::new(0));
+        let database = {
+            let database = DummyDatabaseBuilder::default()
+                .add_blocks(
+                    usize::try_from(chain_height).unwrap(),
+                    DummyBlockExtendedHeader::default(),
+                )
+                .finish();
+            let blob_requests = blob_requests.clone();
+            tower::service_fn(move |req| {
+                if matches!(req, DatabaseRequest::BlockBlobsInRange { .. }) {
+                    blob_requests.fetch_add(1, Ordering::SeqCst);
+                }
+                database.clone().oneshot(req)
+            })
+        };
+        let handler = handler_with_database(RpcConfig::default(), database, chain_height);
+        let req = to_bytes(&get_blocks_request(vec![block_hash(0)], 0)).unwrap();
+
+        let res: GetBlocksResponse =
+            from_bytes(&block_on(handler.handle_bin("get_blocks.bin", &req)).unwrap()).unwrap();
+        assert_eq!(res.blocks.len() as u64, chain_height);
+        assert_eq!(
+            res.blocks[GET_BLOCKS_CHUNK_SIZE as usize].block,
+            block_hash(GET_BLOCKS_CHUNK_SIZE)
+        );
+        assert_eq!(blob_requests.swap(0, Ordering::SeqCst), 3);
+
+        // The server drops the future when the client disconnects.
+        let mut res = Box::pin(handler.handle_bin("get_blocks.bin", &req));
+        assert!(res
+            .poll_unpin(&mut Context::from_waker(noop_waker_ref()))
+            .is_pending());
+        drop(res);
+        assert_eq!(blob_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn get_blocks_bin_pool_info() {
+        let mut pool = TxPool::default();
+        pool.insert(PoolTx {
+            tx: generate_genesis_block(&Network::Mainnet).miner_tx,
+            hash: [7; 32],
+            weight: 100,
+            fee: 0,
+            key_images: vec![],
+            received_at: 0,
+        });
+        pool.remove(&[7; 32]).unwrap();
+
+        let handler = handler(RpcConfig::default()).with_tx_pool(Arc::new(Mutex::new(pool)));
+        let call = |req: &GetBlocksRequest| -> GetBlocksResponse {
+            let res = block_on(handler.handle_bin("get_blocks.bin", &to_bytes(req).unwrap()));
+            from_bytes(&res.unwrap()).unwrap()
+        };
+
+        let res = call(&GetBlocksRequest {
+            requested_info: REQUESTED_INFO_POOL_ONLY,
+            pool_info_since: 1,
+            ..get_blocks_request(vec![], 0)
+        });
+        assert!(res.blocks.is_empty());
+        assert_eq!(res.pool_info_extent, POOL_INFO_EXTENT_INCREMENTAL);
+        assert_eq!(res.removed_pool_txids, vec![[7; 32]]);
+        assert!(res.daemon_time > 0);
+
+        // The blocks reach the top of the chain so the whole pool is returned with them.
+        let res = call(&GetBlocksRequest {
+            requested_info: REQUESTED_INFO_BLOCKS_AND_POOL,
+            ..get_blocks_request(vec![block_hash(0)], 0)
+        });
+        assert_eq!(res.blocks.len(), 10);
+        assert_eq!(res.pool_info_extent, POOL_INFO_EXTENT_FULL);
+        assert!(res.added_pool_txs.is_empty());
+        assert!(res.removed_pool_txids.is_empty());
+
+        let res = call(&get_blocks_request(vec![block_hash(0)], 0));
+        assert_eq!(res.pool_info_extent, POOL_INFO_EXTENT_NONE);
+    }
+
+    #[test]
+    fn get_o_indexes_bin() {
+        let res: GetOIndexesResponse = call_bin(
+            RpcConfig::default(),
+            "get_o_indexes.bin",
+            &GetOIndexesRequest { txid: [5; 32] },
+        )
+        .unwrap();
+
+        assert_eq!(res.o_indexes, [1, 2, 3]);
+    }
+
+    #[test]
+    fn get_outs_bin() {
+        let req = GetOutsRequest {
+            outputs: vec![
+                GetOutputsOut {
+                    amount: 0,
+                    index: 7,
+                },
+                GetOutputsOut {
+                    amount: 0,
+                    index: 8,
+                },
+            ],
+            get_txid: false,
+        };
+        let res: GetOutsResponse = call_bin(RpcConfig::default(), "get_outs.bin", &req).unwrap();
+
+        assert_eq!(res.outs.len(), 2);
+        assert!(res.outs[0].unlocked);
+        assert!(!res.outs[1].unlocked);
+        assert_eq!(res.outs[0].key, [1; 32]);
+        assert_eq!(res.outs[0].txid, [0; 32]);
+
+        let req = GetOutsRequest {
+            outputs: vec![
+                GetOutputsOut {
+                    amount: 0,
+                    index: 7,
+                };
+                5001
+            ],
+            get_txid: true,
+        };
+        assert!(matches!(
+            call_bin::<GetOutsResponse>(RpcConfig { restricted: true }, "get_outs.bin", &req),
+            Err(RpcError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn get_info() {
+        let res = call("get_info", Value::Null).result.unwrap();
+
+        assert_eq!(res["height"], 10);
+        assert_eq!(res["target"], 120);
+        assert_eq!(res["cumulative_difficulty"], 0);
+        assert_eq!(res["cumulative_difficulty_top64"], 1);
+        assert_eq!(res["wide_cumulative_difficulty"], "0x10000000000000000");
+        assert_eq!(res["nettype"], "mainnet");
+        assert_ne!(res["version"], "");
+        assert_ne!(res["start_time"], 0);
+    }
+
+    #[test]
+    fn restricted_get_info_is_stripped() {
+        let res = call_with_config(RpcConfig { restricted: true }, "get_info", Value::Null)
+            .result
+            .unwrap();
+
+        assert_eq!(res["height"], 10);
+        assert_eq!(res["version"], "");
+        assert_eq!(res["start_time"], 0);
+    }
+
+    #[test]
+    fn dump_context() {
+        let res = call("dump_context", Value::Null).result.unwrap();
+
+        assert_eq!(res["current_hf"], 16);
+        assert_eq!(res["next_hf"], Value::Null);
+        assert_eq!(res["hf_votes"][15], 10);
+        assert_eq!(res["short_term_weights"]["median"], 300);
+        assert_eq!(res["difficulty"]["wide_cumulative_difficulty"], "0x3e8");
+
+        let res = call_with_config(RpcConfig { restricted: true }, "dump_context", Value::Null);
+        assert_eq!(res.error.unwrap().code, METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn get_alt_chain_stats() {
+        let res = call("get_alt_chain_stats", Value::Null).result.unwrap();
+
+        assert_eq!(res["alt_blocks"], 1);
+        assert_eq!(res["reorgs"], 2);
+        assert_eq!(res["max_reorg_depth"], 30);
+        assert_eq!(res["reorg_depths"][3], json!({"le": 5, "count": 1}));
+        assert_eq!(res["reorg_depths"][6], json!({"le": 50, "count": 1}));
+        assert_eq!(res["reorg_depths"][8], json!({"le": null, "count": 0}));
+        assert_eq!(res["below_alt_tip"], false);
+
+        let res = call_with_config(
+            RpcConfig { restricted: true },
+            "get_alt_chain_stats",
+            Value::Null,
+        );
+        assert_eq!(res.error.unwrap().code, METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn metrics_are_exported_for_prometheus() {
+        let metrics = block_on(handler(RpcConfig::default()).handle_metrics()).unwrap();
+        assert!(metrics.contains("cuprate_alt_blocks_total 1\n"));
+        assert!(metrics.contains("cuprate_reorgs_total 2\n"));
+        assert!(metrics.contains("cuprate_reorg_depth_bucket{le=\"5\"} 1\n"));
+
+        assert!(matches!(
+            block_on(handler(RpcConfig { restricted: true }).handle_metrics()),
+            Err(RpcError::MethodNotFound(_))
+        ));
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingCompactor(AtomicUsize);
+
+    impl DatabaseCompactor for RecordingCompactor {
+        fn request_compaction(&self) -> Result<(), tower::BoxError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn compact_db() {
+        let res = call("compact_db", Value::Null);
+        assert_eq!(res.error.unwrap().code, METHOD_NOT_FOUND);
+
+        let compactor = Arc::new(RecordingCompactor::default());
+        let body = json!({"jsonrpc": "2.0", "id": 1, "method": "compact_db"}).to_string();
+        let res = block_on(
+            handler(RpcConfig::default())
+                .with_database_compactor(compactor.clone())
+                .handle_body(body.as_bytes()),
+        );
+        assert_eq!(res.result.unwrap()["status"], "OK");
+        assert_eq!(compactor.0.load(Ordering::Relaxed), 1);
+
+        let res = block_on(
+            handler(RpcConfig { restricted: true })
+                .with_database_compactor(compactor.clone())
+                .handle_body(body.as_bytes()),
+        );
+        assert_eq!(res.error.unwrap().code, METHOD_NOT_FOUND);
+        assert_eq!(compactor.0.load(Ordering::Relaxed), 1);
+    }
+
+    #[derive(Debug)]
+    struct StaticPublicNodes;
+
+    impl PublicNodeSource for StaticPublicNodes {
+        fn public_nodes(&self) -> BoxFuture<'static, Result<PublicNodes, tower::BoxError>> {
+            let node = |host: &str| PublicNode {
+                host: host.to_string(),
+                last_seen: 10,
+                rpc_port: 18089,
+                rpc_credits_per_hash: 0,
+            };
+            ready(Ok(PublicNodes {
+                white: vec![node("1.1.1.1")],
+                gray: vec![node("2.2.2.2")],
+            }))
+            .boxed()
+        }
+    }
+
+    #[test]
+    fn get_public_nodes() {
+        let res = call("get_public_nodes", Value::Null);
+        assert_eq!(res.error.unwrap().code, METHOD_NOT_FOUND);
+
+        let public_nodes = |params: Value| {
+            let body =
+                json!({"jsonrpc": "2.0", "id": 1, "method": "get_public_nodes", "params": params});
+            block_on(
+                handler(RpcConfig { restricted: true })
+                    .with_public_nodes(Arc::new(StaticPublicNodes))
+                    .handle_body(body.to_string().as_bytes()),
+            )
+            .result
+            .unwrap()
+        };
+
+        // Only the white list by default, like monerod.
+        let res = public_nodes(json!({}));
+        assert_eq!(
+            res["white"],
+            json!([{"host": "1.1.1.1", "last_seen": 10, "rpc_port": 18089, "rpc_credits_per_hash": 0}])
+        );
+        assert_eq!(res["gray"], json!([]));
+
+        let res = public_nodes(json!({"white": false, "gray": true}));
+        assert_eq!(res["white"], json!([]));
+        assert_eq!(res["gray"][0]["host"], "2.2.2.2");
+    }
+
+    #[test]
+    fn block_template_reserved_space() {
+        let error_code = |params| call("get_block_template", params).error.unwrap().code;
+
+        assert_eq!(
+            error_code(json!({ "wallet_address": "", "reserve_size": 8, "extra_nonce": "00" })),
+            CORE_RPC_ERROR_CODE_TOO_BIG_RESERVE_SIZE
+        );
+        assert_eq!(
+            error_code(json!({ "wallet_address": "", "reserve_size": 256 })),
+            CORE_RPC_ERROR_CODE_TOO_BIG_RESERVE_SIZE
+        );
+        // Rejected before anything is allocated.
+        assert_eq!(
+            error_code(json!({ "wallet_address": "", "reserve_size": u64::MAX })),
+            CORE_RPC_ERROR_CODE_TOO_BIG_RESERVE_SIZE
+        );
+        assert_eq!(
+            error_code(json!({ "wallet_address": "", "extra_nonce": "00".repeat(256) })),
+            CORE_RPC_ERROR_CODE_TOO_BIG_RESERVE_SIZE
+        );
+        assert_eq!(
+            error_code(json!({ "wallet_address": "", "extra_nonce": "not hex" })),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            error_code(json!({ "wallet_address": "not an address", "reserve_size": 8 })),
+            CORE_RPC_ERROR_CODE_WRONG_WALLET_ADDRESS
+        );
+
+        let res = call_with_config(
+            RpcConfig { restricted: true },
+            "get_block_template",
+            json!({ "wallet_address": "", "reserve_size": 8 }),
+        );
+        assert_eq!(res.error.unwrap().code, METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn submitted_blocks_keep_to_the_reserved_space() {
+        // The header, the miner transaction's public key, an 8 byte extra nonce and the rest of the
+        // block.
+        let mut blob = [vec![16, 16, 0xe8, 0x07], vec![0; 36], vec![1; 20]].concat();
+        blob.push(0x01);
+        blob.extend([9; 32]);
+        blob.extend([0x02, 8]);
+        blob.extend([0; 8]);
+        blob.extend([2; 10]);
+
+        let mut block = generate_genesis_block(&Network::Mainnet);
+        block.header.major_version = 16;
+        block.header.minor_version = 16;
+        block.header.timestamp = 1_000;
+        let template = BlockTemplate {
+            block,
+            height: 10,
+            difficulty: 100,
+            reward: 0,
+            fees: 0,
+            weight: 0,
+            reserved_offset: blob.len() - 18,
+            reserve_size: 8,
+            hashing_blob: vec![],
+            blob,
+        };
+
+        let submit = |handler: &RpcHandler<_, _>, blobs: Vec<String>| {
+            let body =
+                json!({"jsonrpc": "2.0", "id": 1, "method": "submit_block", "params": blobs});
+            block_on(handler.handle_body(body.to_string().as_bytes()))
+                .error
+                .unwrap()
+                .code
+        };
+
+        let handler = handler(RpcConfig::default());
+        assert_eq!(
+            submit(&handler, vec![hex::encode(&template.blob)]),
+            METHOD_NOT_FOUND
+        );
+
+        let (block_queue, _block_rx) = verification_queue();
+        let handler = handler.with_block_queue(block_queue);
+        handler
+            .block_templates
+            .lock()
+            .unwrap()
+            .push_back(template.clone());
+
+        // The miner wrote a byte past its reserved space.
+        let mut mined = template.blob.clone();
+        mined[template.reserved_offset + 8] = 5;
+        assert_eq!(
+            submit(&handler, vec![hex::encode(mined)]),
+            CORE_RPC_ERROR_CODE_BLOCK_NOT_ACCEPTED
+        );
+
+        assert_eq!(
+            submit(&handler, vec!["not hex".to_string()]),
+            CORE_RPC_ERROR_CODE_WRONG_BLOCKBLOB
+        );
+        assert_eq!(submit(&handler, vec![]), INVALID_PARAMS);
+    }
+
+    #[test]
+    fn tx_report_needs_a_tx_blob() {
+        let res = call("tx_report", json!({ "tx_as_hex": "not hex" }));
+        assert_eq!(res.error.unwrap().code, INVALID_PARAMS);
+
+        let res = call("tx_report", Value::Null);
+        assert_eq!(res.error.unwrap().code, INVALID_PARAMS);
+
+        let res = call_with_config(
+            RpcConfig { restricted: true },
+            "tx_report",
+            json!({ "tx_as_hex": "00" }),
+        );
+        assert_eq!(res.error.unwrap().code, METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn get_block_count_and_old_name() {
+        assert_eq!(
+            call("get_block_count", Value::Null).result.unwrap()["count"],
+            10
+        );
+        assert_eq!(
+            call("getblockcount", Value::Null).result.unwrap()["count"],
+            10
+        );
+    }
+
+    #[test]
+    fn wrong_context_responses_are_internal_errors() {
+        let database = DummyDatabaseBuilder::default()
+            .add_blocks(10, DummyBlockExtendedHeader::default())
+            .finish();
+        let stats = ForkMetrics::default().stats(Instant::now());
+        let context_svc = tower::service_fn(move |_| {
+            ready(Ok::<_, ConsensusError>(ContextResponse::AltChainStats(
+                stats.clone(),
+            )))
+        });
+        let handler = RpcHandler::new(RpcConfig::default(), database, context_svc);
+
+        let body = json!({"jsonrpc": "2.0", "id": 1, "method": "get_block_count"});
+        let res = block_on(handler.handle_body(body.to_string().as_bytes()));
+        assert_eq!(res.error.unwrap().code, CORE_RPC_ERROR_CODE_INTERNAL_ERROR);
+    }
+
+    #[test]
+    fn get_fee_estimate() {
+        let res = call("get_fee_estimate", Value::Null).result.unwrap();
+        assert_eq!(res["quantization_mask"], 10_000);
+        assert_eq!(res["fees"].as_array().unwrap().len(), 4);
+        assert_eq!(res["fee"], res["fees"][0]);
+    }
+
+    #[test]
+    fn estimate_backlog() {
+        let mut pool = TxPool::default();
+        for (hash, fee) in [(1, 600_000_000), (2, 6_000_000)] {
+            pool.insert(PoolTx {
+                tx: generate_genesis_block(&Network::Mainnet).miner_tx,
+                hash: [hash; 32],
+                weight: 300_000,
+                fee,
+                key_images: vec![],
+                received_at: 0,
+            });
+        }
+
+        let handler = handler(RpcConfig::default()).with_tx_pool(Arc::new(Mutex::new(pool)));
+        let estimate = |params: Value| {
+            let body =
+                json!({"jsonrpc": "2.0", "id": 1, "method": "estimate_backlog", "params": params});
+            block_on(handler.handle_body(body.to_string().as_bytes()))
+                .result
+                .unwrap()
+        };
+
+        let res = estimate(json!({"fees": [2_000, 20, 1]}));
+        assert_eq!(res["pool_weight"], 600_000);
+        assert_eq!(res["block_weight"], 300_000);
+        let blocks: Vec<_> = res["backlog"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|backlog| backlog["blocks_ahead"].as_u64().unwrap())
+            .collect();
+        assert_eq!(blocks, [1, 2, 2]);
+
+        // Without fees the backlog of each priority is estimated.
+        let res = estimate(json!({}));
+        assert_eq!(res["backlog"].as_array().unwrap().len(), 4);
+        let fees = call("get_fee_estimate", json!({})).result.unwrap();
+        assert_eq!(res["backlog"][0]["fee"], fees["fees"][0]);
+
+        // The pool is needed.
+        assert!(
+            call_with_config(RpcConfig::default(), "estimate_backlog", json!({}))
+                .error
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn get_output_distribution() {
+        let res = call(
+            "get_output_distribution",
+            json!({"amounts": [0, 1], "from_height": 4}),
+        )
+        .result
+        .unwrap();
+        assert_eq!(
+            res["distributions"][0],
+            json!({"amount": 0, "start_height": 4, "distribution": [1, 1, 1, 1, 1, 1], "base": 4})
+        );
+        assert_eq!(
+            res["distributions"][1]["distribution"],
+            json!([0, 0, 0, 0, 0, 0])
+        );
+
+        let res = call(
+            "get_output_distribution",
+            json!({"amounts": [0], "from_height": 4, "to_height": 6, "cumulative": true}),
+        )
+        .result
+        .unwrap();
+        assert_eq!(res["distributions"][0]["distribution"], json!([5, 6, 7]));
+
+        assert_eq!(
+            call(
+                "get_output_distribution",
+                json!({"amounts": [0], "to_height": 10})
+            )
+            .error
+            .unwrap()
+            .code,
+            CORE_RPC_ERROR_CODE_TOO_BIG_HEIGHT
+        );
+        assert!(call_with_config(
+            RpcConfig { restricted: true },
+            "get_output_distribution",
+            json!({"amounts": [1]})
+        )
+        .error
+        .is_some());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            call("not_a_method", Value::Null).error.unwrap().code,
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            call("get_block_header_by_height", json!({"height": 10}))
+                .error
+                .unwrap()
+                .code,
+            CORE_RPC_ERROR_CODE_TOO_BIG_HEIGHT
+        );
+        assert!(call("get_block_header_by_height", json!({}))
+            .error
+            .is_some());
+        assert!(call("get_block_header_by_hash", json!({"hash": "00"}))
+            .error
+            .is_some());
+    }
+}
@@ -0,0 +1,71 @@
+//! # JSON-RPC
+//!
+//! This module contains the JSON-RPC 2.0 request and response envelopes, the method specific
+//! params and results are in [`methods`](crate::methods).
+//!
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+
+// monerod's error codes.
+pub const CORE_RPC_ERROR_CODE_WRONG_PARAM: i64 = -1;
+pub const CORE_RPC_ERROR_CODE_TOO_BIG_HEIGHT: i64 = -2;
+pub const CORE_RPC_ERROR_CODE_WRONG_WALLET_ADDRESS: i64 = -2;
+pub const CORE_RPC_ERROR_CODE_TOO_BIG_RESERVE_SIZE: i64 = -3;
+pub const CORE_RPC_ERROR_CODE_INTERNAL_ERROR: i64 = -5;
+pub const CORE_RPC_ERROR_CODE_WRONG_BLOCKBLOB: i64 = -6;
+pub const CORE_RPC_ERROR_CODE_BLOCK_NOT_ACCEPTED: i64 = -7;
+
+/// A JSON-RPC request.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    /// The request's ID, echoed back in the response.
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Option<Value>,
+}
+
+/// A JSON-RPC error object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorObject {
+    pub code: i64,
+    pub message: String,
+}
+
+/// A JSON-RPC response, exactly one of `result` and `error` will be set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorObject>,
+}
+
+impl Response {
+    pub fn result(id: Value, result: Value) -> Response {
+        Response {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn error(id: Value, code: i64, message: String) -> Response {
+        Response {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(ErrorObject { code, message }),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! # Cuprate RPC
+//!
+//! This crate contains a monerod compatible JSON-RPC 2.0 server, so wallets and block explorers can
+//! use a Cuprate node.
+//!
+//! [`RpcHandler`] answers requests using the consensus crate's database service and
+//! [`ContextService`](monero_consensus::context::ContextService), it is independent of HTTP so it
+//! can be tested without a server. The HTTP server is in [`server`], behind the `server` feature.
+//!
+//! The supported methods are:
+//! - `get_info`
+//! - `get_block`
+//! - `get_block_header_by_height`
+//! - `get_block_header_by_hash`
+//! - `get_block_count`
+//! - `get_last_block_header`
+//! - `get_fee_estimate`
+//! - `estimate_backlog`, the blocks of pool transactions ahead of each fee, needs the [`TxPool`](monero_consensus::txpool::TxPool)
+//! - `get_output_distribution`, counts are cached, see [`distribution`]
+//! - `get_block_template`, unrestricted only, needs the [`TxPool`](monero_consensus::txpool::TxPool)
+//! - `submit_block`, unrestricted only, only served with the block queue, see
+//!   [`RpcHandler::with_block_queue`]
+//! - `dump_context`, unrestricted only
+//! - `get_alt_chain_stats`, the alt blocks and reorgs seen since the node started, unrestricted only
+//! - `get_public_nodes`, the peers that advertised an RPC port, only served with a
+//!   [`PublicNodeSource`], see [`RpcHandler::with_public_nodes`]
+//! - `tx_report`, every rule a transaction blob is checked against, unrestricted only, see
+//!   [`tx_report`](monero_consensus::tx_report)
+//! - `compact_db`, marks the database to be compacted when the node restarts, unrestricted only, only
+//!   served with a compactor, see [`RpcHandler::with_database_compactor`]
+//!
+//! Wallets sync using the epee encoded endpoints, see [`bin`]:
+//! - `/get_blocks.bin`
+//! - `/get_o_indexes.bin`
+//! - `/get_outs.bin`
+//!
+//! The node's metrics are served in the Prometheus text format on `/metrics`, unrestricted only, see
+//! [`RpcHandler::handle_metrics`].
+//!
+//! The server can be run in restricted mode for public nodes, see [`policy`]. Public nodes should also give the handler a
+//! database with bulk priority, see [`read_scheduler`](monero_consensus::read_scheduler), so RPC
+//! reads can't stall block verification.
+//!
+//! Events can be published over ZMQ in monerod's format, see [`zmq`], or run commands and POST to
+//! URLs like monerod's `--block-notify`, see [`notify`].
+//!
+//! The server drops a request's future when its client disconnects. The database answers reads
+//! straight away, so the long running requests, `get_blocks.bin` and `get_output_distribution`,
+//! read in chunks and yield between them, letting an abandoned request stop after its current chunk
+//! instead of holding a database reader until it finishes.
+//!
+pub mod bin;
+pub mod distribution;
+pub mod handler;
+pub mod json_rpc;
+pub mod methods;
+pub mod notify;
+pub mod policy;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod zmq;
+
+pub use handler::{DatabaseCompactor, PublicNodeSource, PublicNodes, RpcHandler};
+
+/// Returns [`Poll::Pending`](std::task::Poll::Pending) once, so a future dropped by the server
+/// stops here.
+pub(crate) async fn yield_now() {
+    let mut yielded = false;
+    futures::future::poll_fn(|cx| {
+        if yielded {
+            return std::task::Poll::Ready(());
+        }
+
+        yielded = true;
+        cx.waker().wake_by_ref();
+        std::task::Poll::Pending
+    })
+    .await
+}
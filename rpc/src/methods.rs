@@ -0,0 +1,383 @@
+//! # Methods
+//!
+//! This module contains the params and results of the RPC methods, the field names match monerod's
+//! so existing clients can use them.
+//!
+use serde::{Deserialize, Serialize};
+
+/// The status monerod returns for successful requests.
+pub const STATUS_OK: &str = "OK";
+
+/// A 128 bit difficulty, split the ways monerod returns it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WideDifficulty {
+    /// The lowest 64 bits.
+    pub low: u64,
+    /// The highest 64 bits.
+    pub top64: u64,
+    /// The full difficulty as a hex string, like `0x1a2b`.
+    pub wide: String,
+}
+
+impl From<u128> for WideDifficulty {
+    fn from(difficulty: u128) -> Self {
+        WideDifficulty {
+            low: difficulty as u64,
+            top64: (difficulty >> 64) as u64,
+            wide: format!("{:#x}", difficulty),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub major_version: u8,
+    pub minor_version: u8,
+    pub timestamp: u64,
+    pub prev_hash: String,
+    pub nonce: u32,
+    /// Always false, only main chain blocks are returned.
+    pub orphan_status: bool,
+    pub height: u64,
+    /// The amount of blocks on top of this block.
+    pub depth: u64,
+    pub hash: String,
+    pub difficulty: u64,
+    pub wide_difficulty: String,
+    pub difficulty_top64: u64,
+    pub cumulative_difficulty: u64,
+    pub wide_cumulative_difficulty: String,
+    pub cumulative_difficulty_top64: u64,
+    pub reward: u64,
+    pub block_size: usize,
+    pub block_weight: usize,
+    pub long_term_weight: usize,
+    pub num_txes: usize,
+    pub miner_tx_hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetInfoResponse {
+    pub height: u64,
+    pub top_block_hash: String,
+    pub difficulty: u64,
+    pub wide_difficulty: String,
+    pub difficulty_top64: u64,
+    pub cumulative_difficulty: u64,
+    pub wide_cumulative_difficulty: String,
+    pub cumulative_difficulty_top64: u64,
+    /// The target block time, in seconds.
+    pub target: u64,
+    pub block_weight_limit: usize,
+    pub block_size_limit: usize,
+    pub block_weight_median: usize,
+    pub block_size_median: usize,
+    pub mainnet: bool,
+    pub testnet: bool,
+    pub stagenet: bool,
+    pub nettype: String,
+    /// The UNIX timestamp the server started at, 0 in restricted mode.
+    pub start_time: u64,
+    /// The version of the node, empty in restricted mode.
+    pub version: String,
+    pub status: String,
+    pub untrusted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetBlockCountResponse {
+    pub count: u64,
+    pub status: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetBlockHeaderByHeightRequest {
+    pub height: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetBlockHeaderByHashRequest {
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeaderResponse {
+    pub block_header: BlockHeader,
+    pub status: String,
+    pub untrusted: bool,
+}
+
+/// The params of `get_block`, one of `height` and `hash` must be set, `hash` is used if both are.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetBlockRequest {
+    #[serde(default)]
+    pub height: Option<u64>,
+    #[serde(default)]
+    pub hash: Option<String>,
+}
+
+/// The result of `get_block`.
+///
+/// Unlike monerod this does not include the block as a JSON string, the block can be parsed from `blob`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetBlockResponse {
+    pub blob: String,
+    pub block_header: BlockHeader,
+    pub miner_tx_hash: String,
+    pub tx_hashes: Vec<String>,
+    pub status: String,
+    pub untrusted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetFeeEstimateResponse {
+    /// The fee per byte.
+    pub fee: u64,
+    /// The fee per byte of each priority, lowest first.
+    pub fees: Vec<u64>,
+    /// Fees should be rounded up to a multiple of this.
+    pub quantization_mask: u64,
+    pub status: String,
+    pub untrusted: bool,
+}
+
+/// The params of `estimate_backlog`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EstimateBacklogRequest {
+    /// The fees per byte to estimate the backlog of, the fee of each priority if empty.
+    #[serde(default)]
+    pub fees: Vec<u64>,
+}
+
+/// The backlog ahead of a transaction paying a fee per byte.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeBacklog {
+    pub fee: u64,
+    /// The weight of the pool's transactions paying the same fee per byte or more.
+    pub weight_ahead: u64,
+    /// The amount of full blocks ahead, the transaction is expected in the block after these.
+    pub blocks_ahead: u64,
+}
+
+/// The result of `estimate_backlog`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EstimateBacklogResponse {
+    pub backlog: Vec<FeeBacklog>,
+    /// The total weight of the pool's transactions.
+    pub pool_weight: u64,
+    /// The weight of transactions a block is expected to hold, the effective median weight.
+    pub block_weight: u64,
+    pub status: String,
+}
+
+/// The params of `get_output_distribution`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetOutputDistributionRequest {
+    /// The amounts to get the distributions of, RingCT outputs have an amount of 0.
+    pub amounts: Vec<u64>,
+    #[serde(default)]
+    pub from_height: u64,
+    /// The last block of the distributions, 0 means the top block.
+    #[serde(default)]
+    pub to_height: u64,
+    /// Return the total amount of outputs up to each block instead of the amount in each block.
+    #[serde(default)]
+    pub cumulative: bool,
+}
+
+/// The distribution of the outputs with an amount.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputDistribution {
+    pub amount: u64,
+    pub start_height: u64,
+    /// The amount of outputs created in each block from `start_height`, if the distribution is
+    /// cumulative these include `base`.
+    pub distribution: Vec<u64>,
+    /// The amount of outputs created before `start_height`.
+    pub base: u64,
+}
+
+/// The result of `get_output_distribution`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetOutputDistributionResponse {
+    pub distributions: Vec<OutputDistribution>,
+    pub status: String,
+}
+
+/// A summary of a window of block weights.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeightWindow {
+    pub len: usize,
+    pub min: usize,
+    pub median: usize,
+    pub max: usize,
+}
+
+/// A summary of the difficulty window.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DifficultyWindow {
+    pub len: usize,
+    pub oldest_timestamp: Option<u64>,
+    pub newest_timestamp: Option<u64>,
+    pub wide_cumulative_difficulty: String,
+    pub last_accounted_height: u64,
+}
+
+/// The result of `dump_context`, a summary of the consensus caches for debugging.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpContextResponse {
+    pub height: u64,
+    pub current_hf: u8,
+    pub next_hf: Option<u8>,
+    /// The votes for each hard-fork in the voting window, index 0 is hard-fork 1.
+    pub hf_votes: Vec<u64>,
+    pub hf_total_votes: u64,
+    pub hf_last_height: u64,
+    pub short_term_weights: WeightWindow,
+    pub long_term_weights: WeightWindow,
+    pub weights_tip_height: u64,
+    pub difficulty: DifficultyWindow,
+    pub status: String,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// The params of `get_public_nodes`, which lists to return.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetPublicNodesRequest {
+    #[serde(default)]
+    pub gray: bool,
+    #[serde(default = "default_true")]
+    pub white: bool,
+    /// Accepted for monerod compatibility, banned peers are never in the address book.
+    #[serde(default)]
+    pub include_blocked: bool,
+}
+
+/// A peer that advertised an RPC port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicNode {
+    /// The peer's IP.
+    pub host: String,
+    pub last_seen: u64,
+    pub rpc_port: u16,
+    pub rpc_credits_per_hash: u32,
+}
+
+/// The result of `get_public_nodes`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetPublicNodesResponse {
+    /// Peers we have connected to.
+    pub white: Vec<PublicNode>,
+    /// Peers other peers told us about.
+    pub gray: Vec<PublicNode>,
+    pub status: String,
+    pub untrusted: bool,
+}
+
+/// A bucket of the reorg depth histogram.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReorgDepthBucket {
+    /// The deepest reorg counted in this bucket, [`None`] for the last bucket.
+    pub le: Option<u64>,
+    /// The amount of reorgs in this bucket, not counting the smaller buckets.
+    pub count: u64,
+}
+
+/// The result of `get_alt_chain_stats`, the alt blocks and reorgs seen since the node started.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetAltChainStatsResponse {
+    pub alt_blocks: u64,
+    pub reorgs: u64,
+    pub max_reorg_depth: u64,
+    pub reorg_depths: Vec<ReorgDepthBucket>,
+    /// The total time in seconds an alt chain has had more work than the main chain.
+    pub time_below_alt_tip: f64,
+    /// True if an alt chain currently has more work than the main chain.
+    pub below_alt_tip: bool,
+    pub status: String,
+}
+
+/// The params of `tx_report`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxReportRequest {
+    /// The transaction's blob, as hex.
+    pub tx_as_hex: String,
+}
+
+/// A rule the transaction was checked against.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxRuleCheck {
+    pub rule: String,
+    pub passed: bool,
+    /// The values the rule was checked with, or why it failed.
+    pub details: String,
+}
+
+/// The result of `tx_report`, every rule the transaction was checked against for debugging rejected
+/// transactions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxReportResponse {
+    pub version: u64,
+    pub weight: usize,
+    /// The fee, null if the amounts of a version 1 transaction are invalid.
+    pub fee: Option<u64>,
+    /// The hard-fork the transaction was checked for, the next block's.
+    pub hf: u8,
+    /// True if every rule passed, the signatures and range proofs are not checked.
+    pub passed: bool,
+    pub checks: Vec<TxRuleCheck>,
+    /// The age in blocks of each ring's members.
+    pub ring_ages: Vec<Vec<u64>>,
+    pub status: String,
+}
+
+/// The result of `compact_db`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactDbResponse {
+    pub status: String,
+}
+
+/// The params of `get_block_template`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetBlockTemplateRequest {
+    /// The address the miner transaction pays.
+    pub wallet_address: String,
+    /// The amount of zeros to reserve in the miner transaction's extra nonce.
+    #[serde(default)]
+    pub reserve_size: u64,
+    /// The extra nonce to put in the miner transaction, as hex, it can't be given with a
+    /// `reserve_size`.
+    #[serde(default)]
+    pub extra_nonce: String,
+}
+
+/// The result of `get_block_template`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetBlockTemplateResponse {
+    pub blocktemplate_blob: String,
+    pub blockhashing_blob: String,
+    pub difficulty: u64,
+    pub wide_difficulty: String,
+    pub difficulty_top64: u64,
+    pub expected_reward: u64,
+    pub height: u64,
+    pub prev_hash: String,
+    /// The offset of the extra nonce in the template blob, 0 if there is no extra nonce.
+    pub reserved_offset: u64,
+    pub seed_hash: String,
+    pub seed_height: u64,
+    /// Always empty, the seed of the next epoch is not given early.
+    pub next_seed_hash: String,
+    pub status: String,
+    pub untrusted: bool,
+}
+
+/// The result of `submit_block`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubmitBlockResponse {
+    pub block_id: String,
+    pub status: String,
+}
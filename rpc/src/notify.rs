@@ -0,0 +1,342 @@
+//! # Notify
+//!
+//! This module runs user configured commands, or POSTs to URLs, on chain events, like monerod's
+//! `--block-notify` and `--reorg-notify`.
+//!
+//! The events are:
+//! - a new main chain block, `%s` in commands is the block's hash.
+//! - a reorg, `%s` is the new chain height, `%h` the height the chains split at, `%d` the amount of
+//!   blocks removed and `%n` the amount of blocks added.
+//! - a transaction added to the pool with a weight of at least
+//!   [`NotifierConfig::tx_notify_min_weight`], `%s` is the transaction's hash.
+//!
+//! Like monerod commands are split on whitespace and run without a shell, they are not waited on.
+//! URLs are sent the event as JSON, only `http://` URLs are supported.
+//!
+//! [`Notifier`] is a cheap handle that queues notifications, [`run_notifier`] runs them and is behind
+//! the `notify` feature. The node should call [`Notifier::new_block`] and [`Notifier::reorg`] after
+//! the verifier changes the main chain, and give the notifier to the tx pool as a
+//! [`TxPoolListener`], `cuprate-node` does both by subscribing the notifier to its events.
+//!
+use futures::channel::mpsc;
+use serde::Serialize;
+
+use monero_consensus::txpool::{PoolTx, TxPoolListener};
+
+/// Where a notification is sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyTarget {
+    /// A command to run, placeholders in its arguments are replaced with the event's values.
+    Command(String),
+    /// A URL the event is POSTed to as JSON.
+    Url(String),
+}
+
+/// An event the notifier is told about.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ChainEvent {
+    NewBlock {
+        height: u64,
+        hash: String,
+    },
+    Reorg {
+        split_height: u64,
+        new_height: u64,
+        blocks_removed: u64,
+        blocks_added: u64,
+    },
+    PoolTx {
+        hash: String,
+        weight: usize,
+        fee: u64,
+    },
+}
+
+impl ChainEvent {
+    /// Returns the values of this event's placeholders.
+    fn placeholders(&self) -> Vec<(&'static str, String)> {
+        match self {
+            ChainEvent::NewBlock { hash, .. } | ChainEvent::PoolTx { hash, .. } => {
+                vec![("%s", hash.clone())]
+            }
+            ChainEvent::Reorg {
+                split_height,
+                new_height,
+                blocks_removed,
+                blocks_added,
+            } => vec![
+                ("%s", new_height.to_string()),
+                ("%h", split_height.to_string()),
+                ("%d", blocks_removed.to_string()),
+                ("%n", blocks_added.to_string()),
+            ],
+        }
+    }
+}
+
+/// The targets notified of each event.
+#[derive(Debug, Clone, Default)]
+pub struct NotifierConfig {
+    pub block_notify: Vec<NotifyTarget>,
+    pub reorg_notify: Vec<NotifyTarget>,
+    pub tx_notify: Vec<NotifyTarget>,
+    /// Only pool transactions with at least this weight are notified, so a busy pool doesn't run a
+    /// command for every transaction.
+    pub tx_notify_min_weight: usize,
+}
+
+impl NotifierConfig {
+    /// Notifies this target of new main chain blocks.
+    pub fn with_block_notify(mut self, target: NotifyTarget) -> Self {
+        self.block_notify.push(target);
+        self
+    }
+
+    /// Notifies this target of reorgs.
+    pub fn with_reorg_notify(mut self, target: NotifyTarget) -> Self {
+        self.reorg_notify.push(target);
+        self
+    }
+
+    /// Notifies this target of pool transactions with at least `min_weight` weight.
+    pub fn with_tx_notify(mut self, target: NotifyTarget, min_weight: usize) -> Self {
+        self.tx_notify.push(target);
+        self.tx_notify_min_weight = min_weight;
+        self
+    }
+}
+
+/// An event and the target to notify of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub target: NotifyTarget,
+    pub event: ChainEvent,
+}
+
+impl Notification {
+    /// Returns the program and arguments of a [`NotifyTarget::Command`] with the placeholders
+    /// replaced, or [`None`] for URLs and empty commands.
+    pub fn command_args(&self) -> Option<Vec<String>> {
+        let NotifyTarget::Command(command) = &self.target else {
+            return None;
+        };
+
+        let placeholders = self.event.placeholders();
+        let args: Vec<String> = command
+            .split_whitespace()
+            .map(|arg| {
+                placeholders
+                    .iter()
+                    .fold(arg.to_string(), |arg, (placeholder, value)| {
+                        arg.replace(placeholder, value)
+                    })
+            })
+            .collect();
+
+        (!args.is_empty()).then_some(args)
+    }
+}
+
+/// Queues notifications for [`run_notifier`], clones of this handle queue to the same runner.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    config: std::sync::Arc<NotifierConfig>,
+    notifications: mpsc::UnboundedSender<Notification>,
+}
+
+impl Notifier {
+    /// Returns a notifier and the receiver of its notifications, which should be given to
+    /// [`run_notifier`].
+    pub fn new(config: NotifierConfig) -> (Notifier, mpsc::UnboundedReceiver<Notification>) {
+        let (notifications, rx) = mpsc::unbounded();
+        (
+            Notifier {
+                config: std::sync::Arc::new(config),
+                notifications,
+            },
+            rx,
+        )
+    }
+
+    /// Notifies the block targets of a block added to the main chain.
+    pub fn new_block(&self, height: u64, hash: [u8; 32]) {
+        self.notify(
+            &self.config.block_notify,
+            ChainEvent::NewBlock {
+                height,
+                hash: hex::encode(hash),
+            },
+        );
+    }
+
+    /// Notifies the reorg targets of a reorg, `split_height` is the height of the first block that
+    /// was removed.
+    pub fn reorg(&self, split_height: u64, blocks_removed: u64, blocks_added: u64) {
+        self.notify(
+            &self.config.reorg_notify,
+            ChainEvent::Reorg {
+                split_height,
+                new_height: split_height + blocks_added,
+                blocks_removed,
+                blocks_added,
+            },
+        );
+    }
+
+    /// Notifies the transaction targets of a transaction added to the pool, if it is big enough.
+    pub fn pool_tx(&self, hash: [u8; 32], weight: usize, fee: u64) {
+        if weight < self.config.tx_notify_min_weight {
+            return;
+        }
+
+        self.notify(
+            &self.config.tx_notify,
+            ChainEvent::PoolTx {
+                hash: hex::encode(hash),
+                weight,
+                fee,
+            },
+        );
+    }
+
+    fn notify(&self, targets: &[NotifyTarget], event: ChainEvent) {
+        for target in targets {
+            let notification = Notification {
+                target: target.clone(),
+                event: event.clone(),
+            };
+
+            if self.notifications.unbounded_send(notification).is_err() {
+                tracing::debug!("Notifier stopped, dropping {:?}", event);
+                return;
+            }
+        }
+    }
+}
+
+impl TxPoolListener for Notifier {
+    fn tx_added(&self, tx: &PoolTx) {
+        self.pool_tx(tx.hash, tx.weight, tx.fee);
+    }
+}
+
+/// Runs the notifications from a [`Notifier`], this runs until every clone of the notifier is
+/// dropped.
+///
+/// Failed notifications are logged and not retried.
+#[cfg(feature = "notify")]
+pub async fn run_notifier(mut notifications: mpsc::UnboundedReceiver<Notification>) {
+    use futures::StreamExt;
+
+    let client = hyper::Client::new();
+
+    while let Some(notification) = notifications.next().await {
+        match &notification.target {
+            NotifyTarget::Command(_) => {
+                let Some(args) = notification.command_args() else {
+                    continue;
+                };
+
+                if let Err(e) = std::process::Command::new(&args[0])
+                    .args(&args[1..])
+                    .spawn()
+                {
+                    tracing::warn!("Failed to run notify command {}: {}", args[0], e);
+                }
+            }
+            NotifyTarget::Url(url) => {
+                let body = serde_json::to_string(&notification.event)
+                    .expect("Chain events can always be serialized");
+
+                let request = match hyper::Request::post(url)
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(hyper::Body::from(body))
+                {
+                    Ok(request) => request,
+                    Err(e) => {
+                        tracing::warn!("Invalid notify URL {}: {}", url, e);
+                        continue;
+                    }
+                };
+
+                if let Err(e) = client.request(request).await {
+                    tracing::warn!("Failed to notify {}: {}", url, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde_json::json;
+
+    use super::*;
+
+    fn notifications(
+        notifier: Notifier,
+        rx: mpsc::UnboundedReceiver<Notification>,
+    ) -> Vec<Notification> {
+        drop(notifier);
+        futures::executor::block_on(rx.collect())
+    }
+
+    #[test]
+    fn command_placeholders() {
+        let config = NotifierConfig::default()
+            .with_block_notify(NotifyTarget::Command("/usr/bin/notify block %s".into()))
+            .with_reorg_notify(NotifyTarget::Command(
+                "notify reorg --height=%s --split=%h %d %n".into(),
+            ));
+        let (notifier, rx) = Notifier::new(config);
+
+        notifier.new_block(10, [1; 32]);
+        notifier.reorg(100, 3, 5);
+
+        let notifications = notifications(notifier, rx);
+        assert_eq!(
+            notifications[0].command_args().unwrap(),
+            vec!["/usr/bin/notify", "block", &hex::encode([1; 32])]
+        );
+        assert_eq!(
+            notifications[1].command_args().unwrap(),
+            vec!["notify", "reorg", "--height=105", "--split=100", "3", "5"]
+        );
+    }
+
+    #[test]
+    fn events_go_to_their_targets() {
+        let config = NotifierConfig::default()
+            .with_block_notify(NotifyTarget::Url("http://127.0.0.1:8080/block".into()))
+            .with_tx_notify(NotifyTarget::Url("http://127.0.0.1:8080/tx".into()), 1_000);
+        let (notifier, rx) = Notifier::new(config);
+
+        notifier.new_block(10, [1; 32]);
+        // No reorg targets.
+        notifier.reorg(5, 1, 1);
+        // Too small.
+        notifier.pool_tx([2; 32], 999, 10);
+        notifier.pool_tx([3; 32], 1_000, 10);
+
+        let notifications = notifications(notifier, rx);
+        assert_eq!(notifications.len(), 2);
+        assert_eq!(notifications[0].command_args(), None);
+        assert_eq!(
+            serde_json::to_value(&notifications[0].event).unwrap(),
+            json!({"event": "new_block", "height": 10, "hash": hex::encode([1; 32])})
+        );
+        assert_eq!(
+            notifications[1],
+            Notification {
+                target: NotifyTarget::Url("http://127.0.0.1:8080/tx".into()),
+                event: ChainEvent::PoolTx {
+                    hash: hex::encode([3; 32]),
+                    weight: 1_000,
+                    fee: 10
+                }
+            }
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! # Policy
+//!
+//! This module contains the per method policy table used to run the server in restricted mode, like
+//! monerod's `--restricted-rpc`, for servers exposed to the public.
+//!
+//! In restricted mode privileged methods are hidden (they return method not found) and methods that
+//! return lists have the amount of items they can return per call capped.
+//!
+/// The most blocks that can be requested per call in restricted mode.
+pub const RESTRICTED_BLOCK_COUNT: u64 = 1000;
+/// The most block headers that can be requested per call in restricted mode.
+pub const RESTRICTED_BLOCK_HEADER_RANGE: u64 = 1000;
+/// The most transactions that can be requested per call in restricted mode.
+pub const RESTRICTED_TRANSACTIONS_COUNT: u64 = 100;
+/// The most outputs that can be requested per call in restricted mode.
+pub const RESTRICTED_OUTPUTS_COUNT: u64 = 5000;
+/// The most fees the backlog can be estimated for per call in restricted mode.
+pub const RESTRICTED_BACKLOG_FEES: u64 = 100;
+
+/// The config for the RPC server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RpcConfig {
+    /// Hide privileged methods, cap result sizes and strip sensitive fields from results.
+    pub restricted: bool,
+}
+
+/// Who can call a method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Callable in restricted mode.
+    Public,
+    /// Only callable when the server is not restricted.
+    Unrestricted,
+}
+
+/// The policy for a method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MethodPolicy {
+    pub access: Access,
+    /// The most items a call can return in restricted mode, [`None`] if the method doesn't return a
+    /// list.
+    pub restricted_max_results: Option<u64>,
+}
+
+const fn public() -> MethodPolicy {
+    MethodPolicy {
+        access: Access::Public,
+        restricted_max_results: None,
+    }
+}
+
+const fn public_capped(max_results: u64) -> MethodPolicy {
+    MethodPolicy {
+        access: Access::Public,
+        restricted_max_results: Some(max_results),
+    }
+}
+
+const fn unrestricted() -> MethodPolicy {
+    MethodPolicy {
+        access: Access::Unrestricted,
+        restricted_max_results: None,
+    }
+}
+
+/// The policy of every method, methods missing from this table are not found.
+///
+/// Privileged monerod methods we don't support yet are in here so they are hidden when they are added.
+const METHOD_POLICIES: &[(&str, MethodPolicy)] = &[
+    // JSON-RPC methods.
+    ("get_info", public()),
+    ("get_block", public()),
+    ("get_block_header_by_height", public()),
+    ("getblockheaderbyheight", public()),
+    ("get_block_header_by_hash", public()),
+    ("getblockheaderbyhash", public()),
+    (
+        "get_block_headers_range",
+        public_capped(RESTRICTED_BLOCK_HEADER_RANGE),
+    ),
+    (
+        "getblockheadersrange",
+        public_capped(RESTRICTED_BLOCK_HEADER_RANGE),
+    ),
+    ("get_block_count", public()),
+    ("getblockcount", public()),
+    ("get_last_block_header", public()),
+    ("getlastblockheader", public()),
+    ("get_fee_estimate", public()),
+    ("estimate_backlog", public_capped(RESTRICTED_BACKLOG_FEES)),
+    ("get_output_distribution", public()),
+    ("get_block_template", unrestricted()),
+    ("getblocktemplate", unrestricted()),
+    ("submit_block", unrestricted()),
+    ("submitblock", unrestricted()),
+    ("dump_context", unrestricted()),
+    ("get_alt_chain_stats", unrestricted()),
+    ("get_public_nodes", public()),
+    ("tx_report", unrestricted()),
+    ("compact_db", unrestricted()),
+    ("set_bans", unrestricted()),
+    ("get_bans", unrestricted()),
+    ("banned", unrestricted()),
+    ("flush_txpool", unrestricted()),
+    ("flush_cache", unrestricted()),
+    ("get_connections", unrestricted()),
+    ("sync_info", unrestricted()),
+    ("prune_blockchain", unrestricted()),
+    ("generateblocks", unrestricted()),
+    ("relay_tx", unrestricted()),
+    // Other endpoints.
+    ("metrics", unrestricted()),
+    ("get_blocks.bin", public_capped(RESTRICTED_BLOCK_COUNT)),
+    ("getblocks.bin", public_capped(RESTRICTED_BLOCK_COUNT)),
+    (
+        "get_blocks_by_height.bin",
+        public_capped(RESTRICTED_BLOCK_COUNT),
+    ),
+    (
+        "getblocks_by_height.bin",
+        public_capped(RESTRICTED_BLOCK_COUNT),
+    ),
+    (
+        "get_transactions",
+        public_capped(RESTRICTED_TRANSACTIONS_COUNT),
+    ),
+    (
+        "gettransactions",
+        public_capped(RESTRICTED_TRANSACTIONS_COUNT),
+    ),
+    ("get_o_indexes.bin", public()),
+    ("get_outs.bin", public_capped(RESTRICTED_OUTPUTS_COUNT)),
+    ("get_outs", public_capped(RESTRICTED_OUTPUTS_COUNT)),
+    ("start_mining", unrestricted()),
+    ("stop_mining", unrestricted()),
+    ("mining_status", unrestricted()),
+    ("set_log_level", unrestricted()),
+    ("set_log_categories", unrestricted()),
+    ("set_limit", unrestricted()),
+    ("stop_daemon", unrestricted()),
+    ("save_bc", unrestricted()),
+    ("out_peers", unrestricted()),
+    ("in_peers", unrestricted()),
+];
+
+/// Returns the policy for a method, or [`None`] if the method is unknown.
+pub fn method_policy(method: &str) -> Option<MethodPolicy> {
+    METHOD_POLICIES
+        .iter()
+        .find(|(name, _)| *name == method)
+        .map(|(_, policy)| *policy)
+}
+
+impl RpcConfig {
+    /// Returns true if the method can be called with this config.
+    pub fn is_allowed(&self, method: &str) -> bool {
+        match method_policy(method) {
+            Some(policy) => !self.restricted || policy.access == Access::Public,
+            None => false,
+        }
+    }
+
+    /// Returns the most items a call to the method can return with this config.
+    pub fn max_results(&self, method: &str) -> u64 {
+        if !self.restricted {
+            return u64::MAX;
+        }
+
+        method_policy(method)
+            .and_then(|policy| policy.restricted_max_results)
+            .unwrap_or(u64::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restricted_hides_privileged_methods() {
+        let restricted = RpcConfig { restricted: true };
+        let unrestricted = RpcConfig::default();
+
+        for method in ["set_bans", "flush_txpool", "start_mining"] {
+            assert!(!restricted.is_allowed(method));
+            assert!(unrestricted.is_allowed(method));
+        }
+
+        assert!(restricted.is_allowed("get_info"));
+        assert!(!unrestricted.is_allowed("not_a_method"));
+    }
+
+    #[test]
+    fn restricted_caps_results() {
+        let restricted = RpcConfig { restricted: true };
+
+        assert_eq!(
+            restricted.max_results("get_blocks.bin"),
+            RESTRICTED_BLOCK_COUNT
+        );
+        assert_eq!(restricted.max_results("get_info"), u64::MAX);
+        assert_eq!(RpcConfig::default().max_results("get_blocks.bin"), u64::MAX);
+    }
+}
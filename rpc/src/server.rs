@@ -0,0 +1,144 @@
+//! # Server
+//!
+//! This module contains the HTTP server, JSON-RPC requests are sent to `/json_rpc` and epee encoded
+//! requests to their own paths, like monerod. Prometheus scrapes the metrics with `GET /metrics`.
+//!
+use std::net::SocketAddr;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, StatusCode, Uri},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+
+use monero_consensus::{
+    context::{ContextRequest, ContextResponse},
+    ConsensusError, Database,
+};
+
+use crate::{handler::RpcError, json_rpc::Response, RpcHandler};
+
+/// The paths of the epee encoded endpoints.
+const BIN_ENDPOINTS: &[&str] = &[
+    "/get_blocks.bin",
+    "/getblocks.bin",
+    "/get_o_indexes.bin",
+    "/get_outs.bin",
+];
+
+/// Returns the [`Router`] for the RPC server.
+pub fn router<D, C>(handler: RpcHandler<D, C>) -> Router
+where
+    D: Database + Clone + Send + Sync + 'static,
+    D::Future: Send,
+    C: tower::Service<ContextRequest, Response = ContextResponse, Error = ConsensusError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    C::Future: Send,
+{
+    BIN_ENDPOINTS
+        .iter()
+        .fold(
+            Router::new()
+                .route("/json_rpc", post(json_rpc::<D, C>))
+                .route("/metrics", get(metrics::<D, C>)),
+            |router, path| router.route(path, post(bin::<D, C>)),
+        )
+        .with_state(handler)
+}
+
+async fn json_rpc<D, C>(State(handler): State<RpcHandler<D, C>>, body: Bytes) -> Json<Response>
+where
+    D: Database + Clone + Send + Sync + 'static,
+    D::Future: Send,
+    C: tower::Service<ContextRequest, Response = ContextResponse, Error = ConsensusError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    C::Future: Send,
+{
+    Json(handler.handle_body(&body).await)
+}
+
+async fn bin<D, C>(
+    State(handler): State<RpcHandler<D, C>>,
+    uri: Uri,
+    body: Bytes,
+) -> impl IntoResponse
+where
+    D: Database + Clone + Send + Sync + 'static,
+    D::Future: Send,
+    C: tower::Service<ContextRequest, Response = ContextResponse, Error = ConsensusError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    C::Future: Send,
+{
+    let endpoint = uri.path().trim_start_matches('/');
+
+    match handler.handle_bin(endpoint, &body).await {
+        Ok(res) => ([(header::CONTENT_TYPE, "application/octet-stream")], res).into_response(),
+        Err(e) => (status_code(&e), e.to_string()).into_response(),
+    }
+}
+
+async fn metrics<D, C>(State(handler): State<RpcHandler<D, C>>) -> impl IntoResponse
+where
+    D: Database + Clone + Send + Sync + 'static,
+    D::Future: Send,
+    C: tower::Service<ContextRequest, Response = ContextResponse, Error = ConsensusError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    C::Future: Send,
+{
+    match handler.handle_metrics().await {
+        Ok(metrics) => (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            metrics,
+        )
+            .into_response(),
+        Err(e) => (status_code(&e), e.to_string()).into_response(),
+    }
+}
+
+fn status_code(e: &RpcError) -> StatusCode {
+    match e {
+        RpcError::MethodNotFound(_) => StatusCode::NOT_FOUND,
+        RpcError::InvalidParams(_)
+        | RpcError::TooBigHeight { .. }
+        | RpcError::InvalidHash(_)
+        | RpcError::WrongWalletAddress(_)
+        | RpcError::ReserveSize(_)
+        | RpcError::WrongBlockBlob
+        | RpcError::BlockNotAccepted(_) => StatusCode::BAD_REQUEST,
+        RpcError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Runs the RPC server on this address until it fails.
+pub async fn serve<D, C>(addr: SocketAddr, handler: RpcHandler<D, C>) -> Result<(), hyper::Error>
+where
+    D: Database + Clone + Send + Sync + 'static,
+    D::Future: Send,
+    C: tower::Service<ContextRequest, Response = ContextResponse, Error = ConsensusError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    C::Future: Send,
+{
+    tracing::info!("Starting RPC server on: {}", addr);
+
+    axum::Server::bind(&addr)
+        .serve(router(handler).into_make_service())
+        .await
+}
@@ -0,0 +1,428 @@
+//! # ZMQ
+//!
+//! This module contains a ZMQ publisher compatible with monerod's `--zmq-pub`, so block explorers and
+//! payment processors that subscribe to monerod can subscribe to Cuprate.
+//!
+//! The supported topics are:
+//! - `json-minimal-chain_main`: the height, previous block hash and hashes of new main chain blocks.
+//! - `json-full-chain_main`: the new main chain blocks with their miner transactions.
+//! - `json-minimal-txpool_add`: the hash, size, weight and fee of transactions added to the pool.
+//!
+//! Like monerod each message is a single frame of the topic and the JSON payload separated by a `:`,
+//! subscribers filter on the topic prefix.
+//!
+//! [`ZmqPublisher`] is a cheap handle that formats the messages and queues them for the socket,
+//! [`run_publisher`] owns the socket and is behind the `zmq` feature. The node should call
+//! [`ZmqPublisher::chain_main`] after the verifier commits blocks to the main chain, and give the
+//! publisher to the tx pool as a [`TxPoolListener`], `cuprate-node` does both when it is given the
+//! publisher.
+//!
+use futures::channel::mpsc;
+use monero_serai::{
+    block::Block,
+    ringct::{RctPrunable, RctType},
+    transaction::{Input, Timelock, Transaction},
+};
+use serde::Serialize;
+
+use monero_consensus::txpool::{PoolTx, TxPoolListener};
+
+/// The endpoint monerod publishes on by default.
+pub const DEFAULT_ZMQ_PUB_ENDPOINT: &str = "tcp://127.0.0.1:18083";
+
+pub const TOPIC_MINIMAL_CHAIN_MAIN: &str = "json-minimal-chain_main";
+pub const TOPIC_FULL_CHAIN_MAIN: &str = "json-full-chain_main";
+pub const TOPIC_MINIMAL_TXPOOL_ADD: &str = "json-minimal-txpool_add";
+
+/// The payload of `json-minimal-chain_main`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MinimalChainMain {
+    /// The height of the first new block.
+    pub first_height: u64,
+    /// The hash of the block before the first new block.
+    pub first_prev_id: String,
+    /// The hashes of the new blocks.
+    pub ids: Vec<String>,
+}
+
+impl MinimalChainMain {
+    /// Returns the payload for these new blocks, `blocks` must not be empty.
+    pub fn new(first_height: u64, blocks: &[Block]) -> MinimalChainMain {
+        MinimalChainMain {
+            first_height,
+            first_prev_id: hex::encode(blocks[0].header.previous),
+            ids: blocks
+                .iter()
+                .map(|block| hex::encode(block.hash()))
+                .collect(),
+        }
+    }
+}
+
+/// A block in `json-full-chain_main`, in monerod's JSON format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FullBlock {
+    pub major_version: u8,
+    pub minor_version: u8,
+    pub timestamp: u64,
+    pub prev_id: String,
+    pub nonce: u32,
+    pub miner_tx: MinerTx,
+    pub tx_hashes: Vec<String>,
+}
+
+impl From<&Block> for FullBlock {
+    fn from(block: &Block) -> Self {
+        FullBlock {
+            major_version: block.header.major_version,
+            minor_version: block.header.minor_version,
+            timestamp: block.header.timestamp,
+            prev_id: hex::encode(block.header.previous),
+            nonce: block.header.nonce,
+            miner_tx: MinerTx::from(&block.miner_tx),
+            tx_hashes: block.txs.iter().map(hex::encode).collect(),
+        }
+    }
+}
+
+/// A miner transaction in monerod's JSON format.
+///
+/// Miner transactions have no ring signatures or encrypted amounts, so `signatures` and
+/// `ringct.encrypted` are always empty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MinerTx {
+    pub version: u64,
+    pub unlock_time: u64,
+    pub inputs: Vec<TxInput>,
+    pub outputs: Vec<TxOutput>,
+    pub extra: String,
+    pub signatures: Vec<String>,
+    pub ringct: RingCt,
+}
+
+impl From<&Transaction> for MinerTx {
+    fn from(tx: &Transaction) -> Self {
+        let rct = &tx.rct_signatures;
+
+        MinerTx {
+            version: tx.prefix.version,
+            unlock_time: match tx.prefix.timelock {
+                Timelock::None => 0,
+                Timelock::Block(height) => height as u64,
+                Timelock::Time(time) => time,
+            },
+            inputs: tx.prefix.inputs.iter().map(TxInput::from).collect(),
+            outputs: tx
+                .prefix
+                .outputs
+                .iter()
+                .map(|output| {
+                    let key = hex::encode(output.key.to_bytes());
+                    TxOutput {
+                        amount: output.amount.unwrap_or(0),
+                        target: match output.view_tag {
+                            None => OutputTarget::ToKey { key },
+                            Some(view_tag) => OutputTarget::ToTaggedKey {
+                                key,
+                                view_tag: hex::encode([view_tag]),
+                            },
+                        },
+                    }
+                })
+                .collect(),
+            extra: hex::encode(&tx.prefix.extra),
+            signatures: vec![],
+            ringct: RingCt {
+                // Miner transactions don't have prunable data so this is always 0 for them.
+                rct_type: match rct.prunable {
+                    RctPrunable::Null => 0,
+                    _ => rct_type_number(rct.rct_type()),
+                },
+                encrypted: vec![],
+                commitments: rct
+                    .base
+                    .commitments
+                    .iter()
+                    .map(|commitment| hex::encode(commitment.compress().to_bytes()))
+                    .collect(),
+                fee: rct.base.fee,
+            },
+        }
+    }
+}
+
+/// Returns monerod's number for a RingCT type.
+fn rct_type_number(rct_type: RctType) -> u8 {
+    match rct_type {
+        RctType::Null => 0,
+        RctType::MlsagAggregate => 1,
+        RctType::MlsagIndividual => 2,
+        RctType::Bulletproofs => 3,
+        RctType::BulletproofsCompactAmount => 4,
+        RctType::Clsag => 5,
+        RctType::BulletproofsPlus => 6,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxInput {
+    Gen {
+        height: u64,
+    },
+    ToKey {
+        amount: u64,
+        key_offsets: Vec<u64>,
+        key_image: String,
+    },
+}
+
+impl From<&Input> for TxInput {
+    fn from(input: &Input) -> Self {
+        match input {
+            Input::Gen(height) => TxInput::Gen { height: *height },
+            Input::ToKey {
+                amount,
+                key_offsets,
+                key_image,
+            } => TxInput::ToKey {
+                amount: amount.unwrap_or(0),
+                key_offsets: key_offsets.clone(),
+                key_image: hex::encode(key_image.compress().to_bytes()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TxOutput {
+    pub amount: u64,
+    #[serde(flatten)]
+    pub target: OutputTarget,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputTarget {
+    ToKey { key: String },
+    ToTaggedKey { key: String, view_tag: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RingCt {
+    #[serde(rename = "type")]
+    pub rct_type: u8,
+    pub encrypted: Vec<String>,
+    pub commitments: Vec<String>,
+    pub fee: u64,
+}
+
+/// A transaction in `json-minimal-txpool_add`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MinimalTxPoolAdd {
+    pub id: String,
+    pub blob_size: usize,
+    pub weight: usize,
+    pub fee: u64,
+}
+
+impl From<&PoolTx> for MinimalTxPoolAdd {
+    fn from(tx: &PoolTx) -> Self {
+        MinimalTxPoolAdd {
+            id: hex::encode(tx.hash),
+            blob_size: tx.tx.serialize().len(),
+            weight: tx.weight,
+            fee: tx.fee,
+        }
+    }
+}
+
+/// Returns the message for this topic and payload.
+pub fn message(topic: &str, payload: &impl Serialize) -> String {
+    format!(
+        "{}:{}",
+        topic,
+        serde_json::to_string(payload).expect("ZMQ payloads can always be serialized")
+    )
+}
+
+/// Formats events into ZMQ messages and queues them for [`run_publisher`], clones of this handle
+/// queue to the same socket.
+#[derive(Debug, Clone)]
+pub struct ZmqPublisher {
+    messages: mpsc::UnboundedSender<String>,
+}
+
+impl ZmqPublisher {
+    /// Returns a publisher and the receiver of its messages, which should be given to
+    /// [`run_publisher`].
+    pub fn new() -> (ZmqPublisher, mpsc::UnboundedReceiver<String>) {
+        let (messages, rx) = mpsc::unbounded();
+        (ZmqPublisher { messages }, rx)
+    }
+
+    /// Publishes the `chain_main` topics for blocks added to the main chain, `first_height` is the
+    /// height of the first block.
+    pub fn chain_main(&self, first_height: u64, blocks: &[Block]) {
+        if blocks.is_empty() {
+            return;
+        }
+
+        self.publish(
+            TOPIC_MINIMAL_CHAIN_MAIN,
+            &MinimalChainMain::new(first_height, blocks),
+        );
+        self.publish(
+            TOPIC_FULL_CHAIN_MAIN,
+            &blocks.iter().map(FullBlock::from).collect::<Vec<_>>(),
+        );
+    }
+
+    /// Publishes `json-minimal-txpool_add` for transactions added to the pool.
+    pub fn txpool_add(&self, txs: &[PoolTx]) {
+        if txs.is_empty() {
+            return;
+        }
+
+        self.publish(
+            TOPIC_MINIMAL_TXPOOL_ADD,
+            &txs.iter().map(MinimalTxPoolAdd::from).collect::<Vec<_>>(),
+        );
+    }
+
+    fn publish(&self, topic: &str, payload: &impl Serialize) {
+        if self
+            .messages
+            .unbounded_send(message(topic, payload))
+            .is_err()
+        {
+            tracing::debug!("ZMQ publisher stopped, dropping {} message", topic);
+        }
+    }
+}
+
+impl TxPoolListener for ZmqPublisher {
+    fn tx_added(&self, tx: &PoolTx) {
+        self.txpool_add(std::slice::from_ref(tx));
+    }
+}
+
+/// Binds a ZMQ PUB socket to `endpoint` and publishes the messages from a [`ZmqPublisher`], this runs
+/// until every clone of the publisher is dropped.
+///
+/// Messages are dropped for subscribers that aren't keeping up, like any ZMQ PUB socket.
+#[cfg(feature = "zmq")]
+pub async fn run_publisher(
+    endpoint: &str,
+    mut messages: mpsc::UnboundedReceiver<String>,
+) -> Result<(), zeromq::ZmqError> {
+    use futures::StreamExt;
+    use zeromq::{Socket, SocketSend};
+
+    let mut socket = zeromq::PubSocket::new();
+    let bound = socket.bind(endpoint).await?;
+    tracing::info!("Publishing ZMQ messages on {}", bound);
+
+    while let Some(message) = messages.next().await {
+        socket.send(message.into()).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde_json::json;
+
+    use monero_consensus::{
+        hardforks::HardFork,
+        test_utils::{dummy_block, dummy_miner_tx},
+    };
+
+    use super::*;
+
+    #[test]
+    fn full_block_json() {
+        let mut block = dummy_block(
+            HardFork::V16,
+            HardFork::V16,
+            [1; 32],
+            dummy_miner_tx(2, Some(3_000_000), vec![]),
+        );
+        block.header.timestamp = 1_700_000_000;
+        block.header.nonce = 7;
+        block.miner_tx.prefix.extra = vec![1, 2];
+        block.txs = vec![[2; 32]];
+
+        assert_eq!(
+            serde_json::to_value(FullBlock::from(&block)).unwrap(),
+            json!({
+                "major_version": 16,
+                "minor_version": 16,
+                "timestamp": 1_700_000_000,
+                "prev_id": hex::encode([1; 32]),
+                "nonce": 7,
+                "miner_tx": {
+                    "version": 2,
+                    "unlock_time": 3_000_060,
+                    "inputs": [{"gen": {"height": 3_000_000}}],
+                    "outputs": [],
+                    "extra": "0102",
+                    "signatures": [],
+                    "ringct": {"type": 0, "encrypted": [], "commitments": [], "fee": 0},
+                },
+                "tx_hashes": [hex::encode([2; 32])],
+            })
+        );
+    }
+
+    #[test]
+    fn output_targets() {
+        let output = |view_tag| TxOutput {
+            amount: 5,
+            target: match view_tag {
+                None => OutputTarget::ToKey { key: "aa".into() },
+                Some(view_tag) => OutputTarget::ToTaggedKey {
+                    key: "aa".into(),
+                    view_tag,
+                },
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(output(None)).unwrap(),
+            json!({"amount": 5, "to_key": {"key": "aa"}})
+        );
+        assert_eq!(
+            serde_json::to_value(output(Some("0f".into()))).unwrap(),
+            json!({"amount": 5, "to_tagged_key": {"key": "aa", "view_tag": "0f"}})
+        );
+    }
+
+    #[test]
+    fn messages_are_prefixed_with_the_topic() {
+        let (publisher, messages) = ZmqPublisher::new();
+
+        publisher.publish(
+            TOPIC_MINIMAL_CHAIN_MAIN,
+            &MinimalChainMain {
+                first_height: 10,
+                first_prev_id: "00".into(),
+                ids: vec!["01".into()],
+            },
+        );
+        // Nothing is published for empty events.
+        publisher.chain_main(11, &[]);
+        publisher.txpool_add(&[]);
+        drop(publisher);
+
+        let messages: Vec<_> = futures::executor::block_on(messages.collect());
+        assert_eq!(
+            messages,
+            vec![
+                r#"json-minimal-chain_main:{"first_height":10,"first_prev_id":"00","ids":["01"]}"#
+            ]
+        );
+    }
+}